@@ -1,11 +1,15 @@
 use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::io::Write as _;
 use std::{
     fs::{self, File},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+#[path = "fn_macros/symbol.rs"]
+mod symbol;
+
 // take an input str and parse it with syn::ExprCall and return the args as strings
 pub fn parse_args(input: &str) -> Vec<String> {
     let pseudo_fn = format!("X{input}");
@@ -51,6 +55,48 @@ fn convert(lisp_name: impl Into<String>, rust_name: &str) -> String {
     lisp_name
 }
 
+/// All rust files under `dir`.
+fn rust_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(rust_files(&path));
+        } else if path.extension().and_then(OsStr::to_str) == Some("rs") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// The names of the symbols used with `sym!(...)` in `contents`.
+fn sym_macro_uses(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents.match_indices("sym!(").filter_map(|(start, _)| {
+        let line_start = contents[..start].rfind('\n').map_or(0, |x| x + 1);
+        let prefix = &contents[line_start..start];
+        let is_ident = prefix.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+        if is_ident || prefix.trim_start().starts_with("//") {
+            return None;
+        }
+        let body = get_substring_between(&contents[start..], "sym!(", ")");
+        Some(body.chars().filter(|c| !c.is_whitespace()).collect())
+    })
+}
+
+/// Return the feature of a `#[cfg(feature = "...")]` or
+/// `#![cfg(feature = "...")]` attribute.
+fn cfg_feature(attr: &str) -> Option<&str> {
+    let attr = attr.trim().strip_prefix('#')?;
+    let attr = attr.strip_prefix('!').unwrap_or(attr);
+    attr.strip_prefix("[cfg(feature = \"")?.strip_suffix("\")]")
+}
+
+/// Cargo sets `CARGO_FEATURE_<NAME>` for each enabled feature.
+fn feature_enabled(feature: &str) -> bool {
+    let var = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+    std::env::var_os(var).is_some()
+}
+
 enum DefvarType {
     Bool,
     Other,
@@ -61,12 +107,12 @@ fn main() {
     let mut all_defvar = Vec::new();
     let mut all_defsym = Vec::new();
 
-    // rerun for all top level files
-    for entry in fs::read_dir("src").unwrap() {
-        let path = entry.unwrap().path();
-        if path.extension().and_then(OsStr::to_str) == Some("rs") {
-            println!("cargo:rerun-if-changed={}", path.display());
-        }
+    // rerun for all files, since any of them can use `sym!`
+    let mut sym_uses = BTreeSet::new();
+    for path in rust_files(Path::new("src")) {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let contents = fs::read_to_string(&path).unwrap();
+        sym_uses.extend(sym_macro_uses(&contents));
     }
 
     for entry in fs::read_dir("src").unwrap() {
@@ -74,6 +120,13 @@ fn main() {
         let path = entry.path();
         if path.is_file() {
             let contents = fs::read_to_string(&path).unwrap();
+            // A file can be disabled with a `#![cfg(feature = "...")]` at the
+            // top. The symbols of its functions are still defined.
+            let file_enabled = contents
+                .lines()
+                .filter(|line| line.starts_with("#!["))
+                .find_map(cfg_feature)
+                .is_none_or(feature_enabled);
             for (start, end) in contents.match_indices("#[defun") {
                 let non_symbol = |c: char| !(c.is_alphanumeric() || c == '_');
                 let name = get_substring_between_predicate(&contents[start..], "fn ", non_symbol);
@@ -92,17 +145,26 @@ fn main() {
                     let import_path = basename.unwrap().replace('/', ":");
                     format!("crate::{import_path}::S{name}")
                 };
-                all_defun.push((struct_name, name.to_string(), lisp_name));
+                // Functions can also be disabled with a
+                // `#[cfg(feature = "...")]` directly before `#[defun]`
+                let attr = contents[..start].lines().last().and_then(cfg_feature);
+                let enabled = file_enabled && attr.is_none_or(feature_enabled);
+                if enabled {
+                    all_defun.push((struct_name, name.to_string(), lisp_name));
+                } else {
+                    let lisp_name = format!("\"{lisp_name}\"");
+                    all_defsym.push((name.to_ascii_uppercase(), Some(lisp_name)));
+                }
             }
-            // process all strings starting with defvar
-            for (start, _) in contents.match_indices("\ndefvar") {
+            // process all strings starting with defvar. The variables of
+            // disabled files are not defined.
+            let defvars = contents.match_indices("\ndefvar").filter(|_| file_enabled);
+            for (start, _) in defvars {
                 let defvar_type = if contents[start..].starts_with("\ndefvar_bool!") {
                     DefvarType::Bool
                 } else if contents[start..].starts_with("\ndefvar!") {
                     DefvarType::Other
-                } else {
-                    continue;
-                };
+                } else {continue};
                 let body = get_substring_between(&contents[start..], "!", ";");
                 let args = parse_args(body);
                 let len = args.len();
@@ -140,12 +202,78 @@ fn main() {
         }
     }
 
+    // Define the symbols used with `sym!` that are not defined yet. A name
+    // that is already defined under another identifier gets an alias.
+    let mut aliases = Vec::new();
+    {
+        let mut idents: HashMap<String, String> = HashMap::new();
+        idents.insert("NIL".into(), "nil".into());
+        idents.insert("TRUE".into(), "t".into());
+        for (ident, name) in &all_defsym {
+            let name = match name {
+                Some(name) => name.trim_matches('"').to_string(),
+                None => map_varname(ident),
+            };
+            idents.insert(ident.clone(), name);
+        }
+        for (ident, name, _, _) in &all_defvar {
+            idents.insert(ident.clone(), name.clone());
+        }
+        for (_, ident, name) in &all_defun {
+            idents.insert(ident.to_ascii_uppercase(), name.clone());
+        }
+        let names: HashMap<_, _> = idents.iter().map(|(k, v)| (v.clone(), k.clone())).collect();
+        for name in sym_uses {
+            let Some(ident) = symbol::ident_for(&name) else { continue };
+            match (idents.get(&ident), names.get(&name)) {
+                (Some(existing), _) if *existing == name => {}
+                (Some(existing), _) => {
+                    panic!("sym!({name}) collides with `{existing}`, which is also sym::{ident}")
+                }
+                (None, Some(other)) => aliases.push((ident.clone(), other.clone())),
+                (None, None) => all_defsym.push((ident.clone(), Some(format!("\"{name}\"")))),
+            }
+            idents.insert(ident, name);
+        }
+    }
+
+    // A variable with the same name as a function, like `buffer-file-name`,
+    // is stored in the symbol of the function, which is made special.
+    let defsym_name = |(ident, name): &(String, Option<String>)| match name {
+        Some(name) => name.trim_matches('"').to_string(),
+        None => map_varname(ident),
+    };
+    let mut function_idents: HashMap<String, String> = HashMap::new();
+    for defsym in &all_defsym {
+        function_idents.insert(defsym_name(defsym), defsym.0.clone());
+    }
+    for (_, ident, name) in &all_defun {
+        function_idents.insert(name.clone(), ident.to_ascii_uppercase());
+    }
+    let mut specials = HashSet::new();
+    let mut own_defvar = Vec::new();
+    for defvar in &all_defvar {
+        match function_idents.get(&defvar.1) {
+            Some(ident) => {
+                specials.insert(defvar.1.clone());
+                if *ident != defvar.0 {
+                    aliases.push((defvar.0.clone(), ident.clone()));
+                }
+            }
+            None => own_defvar.push(defvar),
+        }
+    }
+    let cell = |name: &str| match specials.contains(name) {
+        true => "new_special",
+        false => "new",
+    };
+
     let out_dir = std::env::var("OUT_DIR").unwrap();
     println!("cargo:warning={out_dir}/sym.rs");
     let dest_path = Path::new(&out_dir).join("sym.rs");
     let mut f = File::create(dest_path).unwrap();
 
-    let symbol_len = all_defsym.len() + all_defun.len() + all_defvar.len() + 2;
+    let symbol_len = all_defsym.len() + all_defun.len() + own_defvar.len() + 2;
     writeln!(
         f,
         "
@@ -162,23 +290,22 @@ pub(super) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
     .unwrap();
 
     // write the list of all defsym to the file
-    for (sym, name) in &all_defsym {
-        let sym_name = match name {
-            Some(name) => name.trim_matches('"').to_string(),
-            None => map_varname(sym),
-        };
-        writeln!(f, "    SymbolCell::new(\"{sym_name}\"),").unwrap();
+    for defsym in &all_defsym {
+        let sym_name = defsym_name(defsym);
+        let cell = cell(&sym_name);
+        writeln!(f, "    SymbolCell::{cell}(\"{sym_name}\"),").unwrap();
     }
 
-    for (_, name, _, _) in &all_defvar {
+    for (_, name, _, _) in &own_defvar {
         #[rustfmt::skip]
         writeln!(f, "    SymbolCell::new_special(\"{name}\"),").unwrap();
     }
 
     // write the list of all defun to a file in out_dir
     for (_, _, lisp_name) in &all_defun {
+        let cell = cell(lisp_name);
         #[rustfmt::skip]
-        writeln!(f, "    SymbolCell::new(\"{lisp_name}\"),").unwrap();
+        writeln!(f, "    SymbolCell::{cell}(\"{lisp_name}\"),").unwrap();
     }
 
     // End BUILTIN_SYMBOLS
@@ -188,7 +315,7 @@ pub(super) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
     let all_elements = special
         .iter()
         .chain(all_defsym.iter().map(|x| &x.0))
-        .chain(all_defvar.iter().map(|x| &x.0))
+        .chain(own_defvar.iter().map(|x| &x.0))
         .chain(all_defun.iter().map(|x| &x.1))
         .enumerate();
     for (idx, element) in all_elements {
@@ -196,6 +323,9 @@ pub(super) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
         #[rustfmt::skip]
         writeln!(f, "pub(crate) const {sym_name}: Symbol = Symbol::new_builtin({idx});").unwrap();
     }
+    for (ident, other) in &aliases {
+        writeln!(f, "pub(crate) const {ident}: Symbol = {other};").unwrap();
+    }
 
     // all SubrFn
     let subr_len = all_defun.len();
@@ -240,6 +370,7 @@ lazy_static::lazy_static! {{
         ObjectMap {{
             map,
             block: Block::new_global(),
+            pure_strings: Default::default(),
         }}
     }});
 }}
@@ -261,7 +392,7 @@ use crate::core::object::Object;
     .unwrap();
 
     // write out the value of each defvar
-    for (ident, _, value, ty) in all_defvar {
+    for (ident, _, value, _) in &all_defvar {
         let nil = "Object::NIL";
         let mut value = match value {
             Some(value) => Cow::from(value),
@@ -274,6 +405,10 @@ use crate::core::object::Object;
             value.to_mut().insert_str(len - 1, "; cx");
         }
         writeln!(f, "env.vars.insert(sym::{ident}, cx.add({value}));").unwrap();
+    }
+
+    // register boolean variables once `byte-boolean-vars' is defined
+    for (ident, _, _, ty) in &all_defvar {
         match ty {
             DefvarType::Bool => {
                 writeln!(
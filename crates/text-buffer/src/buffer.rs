@@ -2,6 +2,7 @@
 #![allow(clippy::uninlined_format_args)]
 #![allow(clippy::must_use_candidate)]
 use std::{
+    cell::Cell,
     fmt::{Debug, Display},
     ops::{Bound, RangeBounds},
 };
@@ -9,6 +10,8 @@ use std::{
 use bytecount::num_chars;
 use str_indices::chars;
 
+use crate::lines::LineIndex;
+
 /// A Gap buffer. This represents the text of a buffer, and allows for
 /// efficient insertion and deletion of text.
 #[derive(Default)]
@@ -26,6 +29,13 @@ pub struct Buffer {
     /// The current cursor.
     cursor: Point,
     total_chars: usize,
+    /// The result of the last position conversion, so that converting nearby
+    /// positions doesn't have to count from the gap or the cursor. Unlike the
+    /// other points, the byte is a position in the text rather than an index
+    /// into `data`, so it stays valid when the gap moves.
+    cache: Cell<Point>,
+    /// The positions of the newlines
+    lines: LineIndex,
 }
 
 impl Display for Buffer {
@@ -48,6 +58,8 @@ impl Debug for Buffer {
             .field("gap_chars", &self.gap_chars)
             .field("cursor", &self.cursor)
             .field("total_chars", &self.total_chars)
+            .field("cache", &self.cache.get())
+            .field("lines", &self.lines)
             .finish()
     }
 }
@@ -78,6 +90,8 @@ impl From<&str> for Buffer {
                 char: 0,
             },
             total_chars: chars::count(data),
+            cache: Cell::default(),
+            lines: LineIndex::new(data),
         }
     }
 }
@@ -102,6 +116,8 @@ impl From<String> for Buffer {
                 char: 0,
             },
             total_chars: chars::count(&data),
+            cache: Cell::default(),
+            lines: LineIndex::new(&data),
         }
     }
 }
@@ -117,33 +133,54 @@ impl Buffer {
         Self::default()
     }
 
+    /// The size of the gap when the buffer is grown to hold `len` bytes. The
+    /// gap is proportional to the text so that a series of insertions only
+    /// reallocates a logarithmic number of times.
+    fn new_gap_len(len: usize) -> usize {
+        (len / 8).max(Self::GAP_SIZE)
+    }
+
+    /// Reallocate the buffer with `slice` inserted at the cursor. The gap is
+    /// placed after the new text, so it does not need to be moved first.
     fn grow(&mut self, slice: &str) {
-        let new_capacity = {
-            let pre_gap = self.gap_start;
-            let post_gap = self.data.len() - self.gap_end;
-            pre_gap + slice.len() + Self::GAP_SIZE + post_gap
-        };
+        let len = self.len() + slice.len();
+        let gap_len = Self::new_gap_len(len);
         let new_storage = {
-            let mut buffer = Vec::with_capacity(new_capacity);
-            // pre-gap
-            buffer.extend_from_slice(&self.data[..self.gap_start]);
+            let mut buffer = Vec::with_capacity(len + gap_len);
+            let cursor = self.cursor.byte;
+            // text before the cursor
+            if cursor < self.gap_start {
+                buffer.extend_from_slice(&self.data[..cursor]);
+            } else {
+                buffer.extend_from_slice(&self.data[..self.gap_start]);
+                buffer.extend_from_slice(&self.data[self.gap_end..cursor]);
+            }
             // new text
             buffer.extend_from_slice(slice.as_bytes());
+            let gap_start = buffer.len();
             // gap
-            buffer.resize(buffer.len() + Self::GAP_SIZE, 0);
-            // post-gap
-            buffer.extend_from_slice(&self.data[self.gap_end..]);
+            buffer.resize(gap_start + gap_len, 0);
+            // text after the cursor
+            if cursor < self.gap_start {
+                buffer.extend_from_slice(&self.data[cursor..self.gap_start]);
+                buffer.extend_from_slice(&self.data[self.gap_end..]);
+            } else {
+                buffer.extend_from_slice(&self.data[cursor..]);
+            }
+            self.gap_start = gap_start;
             buffer.into_boxed_slice()
         };
-        assert_eq!(new_storage.len(), new_capacity);
+        assert_eq!(new_storage.len(), len + gap_len);
         self.data = new_storage;
-        self.gap_start += slice.len();
-        self.gap_end = self.gap_start + Self::GAP_SIZE;
-        self.cursor.byte = self.gap_end;
+        self.gap_end = self.gap_start + gap_len;
         let num_chars = chars::count(slice);
-        self.gap_chars += num_chars;
-        self.cursor.char = self.gap_chars;
+        self.gap_chars = self.cursor.char + num_chars;
+        self.cursor = Point {
+            byte: self.gap_end,
+            char: self.gap_chars,
+        };
         self.total_chars += num_chars;
+        self.invalidate_cache(self.gap_start - slice.len());
     }
 
     pub fn insert_char(&mut self, chr: char) {
@@ -152,15 +189,15 @@ impl Buffer {
     }
 
     pub fn insert(&mut self, slice: &str) {
-        // if gap is not at cursor, move it there
-        if self.gap_chars != self.cursor.char {
-            // TODO: we don't need to recalculate the position
-            self.move_gap(self.cursor.char);
-        }
+        let pos = self.raw_to_byte(self.cursor.byte);
+        self.lines.insert(pos, slice, self.len());
         if self.gap_len() < slice.len() {
-            // TODO: grow the gap and move the cursor in one go
             self.grow(slice);
         } else {
+            // if gap is not at cursor, move it there
+            if self.gap_chars != self.cursor.char {
+                self.move_gap_to_raw(self.cursor.byte);
+            }
             let new_slice = &mut self.data[self.gap_start..(self.gap_start + slice.len())];
             new_slice.copy_from_slice(slice.as_bytes());
             self.gap_start += slice.len();
@@ -168,9 +205,22 @@ impl Buffer {
             self.gap_chars += num_chars;
             self.cursor.char += num_chars;
             self.total_chars += num_chars;
+            self.invalidate_cache(self.gap_start - slice.len());
         }
     }
 
+    /// Insert `slice` at character position `pos`, leaving the cursor after
+    /// it.
+    pub fn insert_at(&mut self, pos: usize, slice: &str) {
+        self.set_cursor(pos);
+        self.insert(slice);
+    }
+
+    /// Insert `slice` at byte position `pos`, leaving the cursor after it.
+    pub fn insert_at_byte(&mut self, pos: usize, slice: &str) {
+        self.insert_at(self.byte_to_char(pos), slice);
+    }
+
     pub fn delete_backwards(&mut self, size: usize) {
         let size = size.min(self.cursor.char);
         self.delete_region(self.cursor.char - size, self.cursor.char);
@@ -180,13 +230,38 @@ impl Buffer {
         self.delete_region(self.cursor.char, self.cursor.char + size);
     }
 
+    /// Delete all the text. The storage is kept, so the buffer can be reused
+    /// without reallocating.
+    pub fn clear(&mut self) {
+        self.gap_start = 0;
+        self.gap_end = self.data.len();
+        self.gap_chars = 0;
+        self.cursor = Point::default();
+        self.total_chars = 0;
+        self.cache.take();
+        self.lines = LineIndex::default();
+    }
+
     pub fn delete_region(&mut self, beg: usize, end: usize) {
         let (mut beg, mut end) = (beg, end);
         if beg > end {
             (beg, end) = (end, beg);
         }
-        let end = self.char_to_byte(end.min(self.total_chars));
-        let beg = self.char_to_byte(beg.min(self.total_chars));
+        let end = self.char_to_raw(end.min(self.total_chars));
+        let beg = self.char_to_raw(beg.min(self.total_chars));
+        if end != beg {
+            self.delete_byte_region(beg, end);
+        }
+    }
+
+    /// Delete the text between byte positions `beg` and `end`.
+    pub fn delete_byte_range(&mut self, beg: usize, end: usize) {
+        let (mut beg, mut end) = (beg, end);
+        if beg > end {
+            (beg, end) = (end, beg);
+        }
+        let end = self.byte_to_raw(end.min(self.len()));
+        let beg = self.byte_to_raw(beg.min(self.len()));
         if end != beg {
             self.delete_byte_region(beg, end);
         }
@@ -198,6 +273,9 @@ impl Buffer {
         assert!(end <= self.data.len(), "end out of bounds");
         self.assert_char_boundary(beg);
         self.assert_char_boundary(end);
+        self.invalidate_cache(self.raw_to_byte(beg));
+        self.lines
+            .delete(self.raw_to_byte(beg), self.raw_to_byte(end), self.len());
         if end < self.gap_start {
             // delete before gap
             //
@@ -297,6 +375,14 @@ impl Buffer {
         // update cursor chars
     }
 
+    /// Forget the cached conversion point if it is after `pos`, where the text
+    /// changed.
+    fn invalidate_cache(&self, pos: usize) {
+        if self.cache.get().byte > pos {
+            self.cache.take();
+        }
+    }
+
     fn update_cursor_chars(&mut self, beg: usize, end: usize, size: usize) {
         if self.cursor.byte > beg {
             if self.cursor.byte > end {
@@ -334,7 +420,10 @@ impl Buffer {
     }
 
     fn move_gap(&mut self, pos: usize) {
-        let pos = self.char_to_byte(pos);
+        self.move_gap_to_raw(self.char_to_raw(pos));
+    }
+
+    fn move_gap_to_raw(&mut self, pos: usize) {
         assert!(pos <= self.data.len(), "attempt to move gap out of bounds");
         self.assert_char_boundary(pos);
         if pos < self.gap_start {
@@ -371,17 +460,52 @@ impl Buffer {
 
     pub fn set_cursor(&mut self, pos: usize) {
         let pos = pos.min(self.total_chars);
-        let byte_pos = self.char_to_byte(pos);
+        let byte_pos = self.char_to_raw(pos);
         self.cursor = Point {
             byte: byte_pos,
             char: pos,
         };
     }
 
+    /// The character position of the cursor.
+    pub const fn cursor(&self) -> usize {
+        self.cursor.char
+    }
+
+    /// The text between character positions `beg` and `end`.
+    pub fn substring(&self, beg: usize, end: usize) -> String {
+        let (before, after) = self.slice(beg, end);
+        let mut string = String::with_capacity(before.len() + after.len());
+        string.push_str(before);
+        string.push_str(after);
+        string
+    }
+
+    /// The text between character positions `beg` and `end` without copying
+    /// it. The text is returned in two parts, because it can span the gap.
+    pub fn slice(&self, beg: usize, end: usize) -> (&str, &str) {
+        let end = end.min(self.total_chars);
+        let beg = self.char_to_raw(beg.min(end));
+        let end = self.char_to_raw(end);
+        if beg < self.gap_start && end >= self.gap_end {
+            (
+                self.to_str(beg..self.gap_start),
+                self.to_str(self.gap_end..end),
+            )
+        } else {
+            (self.to_str(beg..end), "")
+        }
+    }
+
     pub const fn len(&self) -> usize {
         self.data.len() - self.gap_len()
     }
 
+    /// The number of bytes the buffer can hold without reallocating.
+    pub const fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
     pub const fn len_chars(&self) -> usize {
         self.total_chars
     }
@@ -394,7 +518,106 @@ impl Buffer {
         self.gap_end - self.gap_start
     }
 
-    fn char_to_byte(&self, pos: usize) -> usize {
+    /// Convert a character position to a byte position.
+    pub fn char_to_byte(&self, pos: usize) -> usize {
+        self.raw_to_byte(self.char_to_raw(pos.min(self.total_chars)))
+    }
+
+    /// Convert a byte position to a character position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` is not on a character boundary.
+    pub fn byte_to_char(&self, pos: usize) -> usize {
+        let pos = pos.min(self.len());
+        self.assert_char_boundary(self.byte_to_raw(pos));
+        // count from the closest known point
+        let anchors = [
+            Point { byte: 0, char: 0 },
+            Point {
+                byte: self.gap_start,
+                char: self.gap_chars,
+            },
+            Point {
+                byte: self.raw_to_byte(self.cursor.byte),
+                char: self.cursor.char,
+            },
+            self.cache.get(),
+            Point {
+                byte: self.len(),
+                char: self.total_chars,
+            },
+        ];
+        let anchor = anchors
+            .into_iter()
+            .min_by_key(|x| x.byte.abs_diff(pos))
+            .unwrap();
+        let char = if pos < anchor.byte {
+            anchor.char - self.count_chars(pos, anchor.byte)
+        } else {
+            anchor.char + self.count_chars(anchor.byte, pos)
+        };
+        self.cache.set(Point { byte: pos, char });
+        char
+    }
+
+    /// The number of newlines before character position `pos`, which is the
+    /// line it is on counting from 0.
+    pub fn line_at(&self, pos: usize) -> usize {
+        self.lines.count(self.char_to_byte(pos), self.len())
+    }
+
+    /// The character position of newline `n`, counting from 0.
+    pub fn newline(&self, n: usize) -> Option<usize> {
+        let pos = self.lines.get(n, self.len())?;
+        Some(self.byte_to_char(pos))
+    }
+
+    /// The number of characters between the byte positions `beg` and `end`.
+    fn count_chars(&self, beg: usize, end: usize) -> usize {
+        if end <= self.gap_start {
+            num_chars(&self.data[beg..end])
+        } else if beg >= self.gap_start {
+            num_chars(&self.data[beg + self.gap_len()..end + self.gap_len()])
+        } else {
+            num_chars(&self.data[beg..self.gap_start])
+                + num_chars(&self.data[self.gap_end..end + self.gap_len()])
+        }
+    }
+
+    /// The largest byte position that is not greater than `pos` and is on a
+    /// character boundary.
+    pub fn floor_char_boundary(&self, pos: usize) -> usize {
+        let mut pos = pos.min(self.len());
+        while !self
+            .data
+            .get(self.byte_to_raw(pos))
+            .is_none_or(|x| Self::is_char_boundary(*x))
+        {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// Convert a byte position in the text to an index into `data`.
+    const fn byte_to_raw(&self, pos: usize) -> usize {
+        if pos < self.gap_start {
+            pos
+        } else {
+            pos + self.gap_len()
+        }
+    }
+
+    /// Convert an index into `data` to a byte position in the text.
+    const fn raw_to_byte(&self, pos: usize) -> usize {
+        if pos < self.gap_end {
+            pos
+        } else {
+            pos - self.gap_len()
+        }
+    }
+
+    fn char_to_raw(&self, pos: usize) -> usize {
         if pos == 0 {
             return if self.gap_start == 0 { self.gap_end } else { 0 };
         }
@@ -407,11 +630,11 @@ impl Buffer {
             let end = (self.gap_end, self.gap_chars);
             let total = (self.data.len(), self.total_chars);
             let cursor = (self.cursor.byte, self.cursor.char);
-            if self.cursor.char < self.gap_chars {
-                [(0, 0), cursor, start, end, total]
-            } else {
-                [(0, 0), start, end, cursor, total]
-            }
+            let cache = self.cache.get();
+            let cache = (self.byte_to_raw(cache.byte), cache.char);
+            let mut positions = [(0, 0), start, end, cursor, cache, total];
+            positions.sort_unstable();
+            positions
         };
 
         // find which positions window the char position falls into
@@ -427,14 +650,19 @@ impl Buffer {
         self.assert_char_boundary(*end_byte);
 
         let num_chars = end_char - beg_char;
-        if end_byte - beg_byte == num_chars {
+        let raw = if end_byte - beg_byte == num_chars {
             // the slice is ascii text, so we can just index into it
             beg_byte + (pos - beg_char)
         } else {
             let string = self.to_str(*beg_byte..*end_byte);
             let byte_idx = chars::to_byte_idx(string, pos - beg_char);
             beg_byte + byte_idx
-        }
+        };
+        self.cache.set(Point {
+            byte: self.raw_to_byte(raw),
+            char: pos,
+        });
+        raw
     }
 
     fn to_str(&self, range: impl std::slice::SliceIndex<[u8], Output = [u8]>) -> &str {
@@ -500,6 +728,32 @@ mod test {
         assert_eq!(buffer.to_string(), "");
     }
 
+    #[test]
+    fn clear() {
+        let mut buffer = Buffer::from("hello buffer");
+        buffer.set_cursor(5);
+        buffer.insert(" world");
+        let capacity = buffer.capacity();
+        buffer.clear();
+        assert_eq!(buffer.to_string(), "");
+        assert_eq!(buffer.len_chars(), 0);
+        assert_eq!(buffer.capacity(), capacity);
+        buffer.insert("reused");
+        assert_eq!(buffer.to_string(), "reused");
+        assert_eq!(buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn slice() {
+        let mut buffer = Buffer::from("hello world");
+        buffer.set_cursor(5);
+        buffer.insert(",");
+        assert_eq!(buffer.slice(0, 12), ("hello,", " world"));
+        assert_eq!(buffer.slice(1, 4), ("ell", ""));
+        assert_eq!(buffer.slice(7, 20), ("world", ""));
+        assert_eq!(buffer.substring(3, 9), "lo, wo");
+    }
+
     #[test]
     fn insert() {
         let string = "hello buffer";
@@ -573,6 +827,19 @@ mod test {
         assert_eq!(buffer.to_string(), "hlo rld");
     }
 
+    #[test]
+    fn test_substring() {
+        let mut buffer = Buffer::from("world");
+        buffer.insert("hello ");
+        assert_eq!(buffer.substring(0, 11), "hello world");
+        assert_eq!(buffer.substring(4, 8), "o wo");
+        assert_eq!(buffer.substring(6, 6), "");
+        assert_eq!(buffer.substring(7, 100), "orld");
+        buffer.set_cursor(0);
+        buffer.insert("ñ");
+        assert_eq!(buffer.substring(0, 3), "ñhe");
+    }
+
     #[test]
     fn test_delete_nothing() {
         let mut buffer = Buffer::from("world");
@@ -650,6 +917,133 @@ mod test {
         assert_eq!(buffer.to_string(), "hello world");
     }
 
+    #[test]
+    fn insert_at() {
+        let mut buffer = Buffer::from("hello world");
+        buffer.insert_at(5, ",");
+        assert_eq!(buffer.to_string(), "hello, world");
+        buffer.insert_at(0, "Θ ");
+        assert_eq!(buffer.to_string(), "Θ hello, world");
+        buffer.insert_at(100, "!");
+        assert_eq!(buffer.to_string(), "Θ hello, world!");
+        // byte 3 is after "Θ "
+        buffer.insert_at_byte(3, "oh ");
+        assert_eq!(buffer.to_string(), "Θ oh hello, world!");
+    }
+
+    #[test]
+    fn byte_positions() {
+        let mut buffer = Buffer::from("aΘbΘc");
+        buffer.set_cursor(2);
+        buffer.insert("x");
+        assert_eq!(buffer.to_string(), "aΘxbΘc");
+        for (chr, byte) in [(0, 0), (1, 1), (2, 3), (3, 4), (4, 5), (5, 7), (6, 8)] {
+            assert_eq!(buffer.char_to_byte(chr), byte);
+            assert_eq!(buffer.byte_to_char(byte), chr);
+        }
+        buffer.delete_byte_range(7, 1);
+        assert_eq!(buffer.to_string(), "ac");
+        buffer.delete_byte_range(0, 100);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn position_cache() {
+        let mut buffer = Buffer::from("aΘbΘc");
+        let mut string = buffer.to_string();
+        let check = |buffer: &Buffer, string: &str| {
+            let bytes: Vec<_> = string.char_indices().map(|x| x.0).collect();
+            for (chr, &byte) in bytes.iter().enumerate() {
+                assert_eq!(buffer.char_to_byte(chr), byte, "{buffer:?}");
+                assert_eq!(buffer.byte_to_char(byte), chr, "{buffer:?}");
+            }
+            // convert backwards so that the cache is ahead of the position
+            for (chr, &byte) in bytes.iter().enumerate().rev() {
+                assert_eq!(buffer.byte_to_char(byte), chr, "{buffer:?}");
+                assert_eq!(buffer.char_to_byte(chr), byte, "{buffer:?}");
+            }
+        };
+        check(&buffer, &string);
+        for (pos, text) in [(2, "ΘΘ"), (0, "x"), (8, "end"), (4, "yΘ")] {
+            // leave the cache after the edit
+            buffer.char_to_byte(buffer.len_chars() - 1);
+            buffer.insert_at(pos, text);
+            let byte = string.char_indices().nth(pos).map_or(string.len(), |x| x.0);
+            string.insert_str(byte, text);
+            check(&buffer, &string);
+        }
+        buffer.char_to_byte(buffer.len_chars() - 1);
+        buffer.delete_region(1, 4);
+        string.replace_range(1..6, "");
+        check(&buffer, &string);
+        assert_eq!(buffer.to_string(), string);
+    }
+
+    #[test]
+    fn lines() {
+        fn check(buffer: &Buffer, string: &str) {
+            let newlines: Vec<_> = string
+                .chars()
+                .enumerate()
+                .filter(|x| x.1 == '\n')
+                .map(|x| x.0)
+                .collect();
+            let found: Vec<_> = (0..).map_while(|n| buffer.newline(n)).collect();
+            assert_eq!(found, newlines, "{string:?}");
+            for (pos, _) in string.chars().enumerate() {
+                let expect = newlines.partition_point(|&x| x < pos);
+                assert_eq!(buffer.line_at(pos), expect, "{string:?} {pos}");
+            }
+        }
+        let mut buffer = Buffer::from("Θ\nab\n");
+        let mut string = String::from("Θ\nab\n");
+        for (pos, text) in [(1, "x\nΘ\n"), (0, "\n"), (9, "long\nline\ntext"), (3, "y")] {
+            buffer.insert_at(pos, text);
+            let byte = string.char_indices().nth(pos).map_or(string.len(), |x| x.0);
+            string.insert_str(byte, text);
+            check(&buffer, &string);
+        }
+        buffer.delete_region(2, 6);
+        let beg = string.char_indices().nth(2).unwrap().0;
+        let end = string.char_indices().nth(6).unwrap().0;
+        string.replace_range(beg..end, "");
+        check(&buffer, &string);
+        buffer.clear();
+        assert_eq!(buffer.newline(0), None);
+    }
+
+    #[test]
+    fn floor_char_boundary() {
+        let buffer = Buffer::from("aΘb");
+        let floors: Vec<_> = (0..6).map(|x| buffer.floor_char_boundary(x)).collect();
+        assert_eq!(floors, [0, 1, 1, 3, 4, 4]);
+    }
+
+    #[test]
+    #[should_panic = "not on utf8 boundary"]
+    fn byte_not_on_boundary() {
+        let buffer = Buffer::from("Θ");
+        buffer.byte_to_char(1);
+    }
+
+    #[test]
+    fn grow_gap() {
+        let mut buffer = Buffer::from("world");
+        buffer.set_cursor(2);
+        // the gap is grown at the cursor without moving it first
+        buffer.insert("hello there ");
+        assert_eq!(buffer.gap_start, 14);
+        assert_eq!(buffer.gap_chars, 14);
+        assert_eq!(buffer.to_string(), "wohello there rld");
+        // the gap grows with the size of the text
+        let text = "x".repeat(100);
+        buffer.insert(&text);
+        assert_eq!(buffer.gap_len(), 117 / 8);
+        assert_eq!(buffer.len(), 117);
+        buffer.insert("y");
+        assert_eq!(buffer.byte_to_char(buffer.len()), 118);
+    }
+
     #[test]
     fn cursor() {
         let string = "world";
@@ -657,5 +1051,8 @@ mod test {
         let mut buffer = Buffer::from(string);
         buffer.insert(new_string);
         assert_eq!(buffer.gap_chars, new_string.len());
+        assert_eq!(buffer.cursor(), 3);
+        buffer.set_cursor(100);
+        assert_eq!(buffer.cursor(), 8);
     }
 }
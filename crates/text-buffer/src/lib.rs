@@ -1,3 +1,4 @@
 mod buffer;
+mod lines;
 
 pub use buffer::*;
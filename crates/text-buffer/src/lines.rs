@@ -0,0 +1,113 @@
+use memchr::memchr_iter;
+
+/// An index of the newlines in the text, so that lines can be found without
+/// scanning the text. Like the text itself, it has a gap: the newlines before
+/// the gap are stored as byte positions, and the ones after it as distances
+/// from the end of the text. Edits at the gap only touch the newlines they
+/// insert or delete, and the gap moves to an edit by moving the newlines in
+/// between. The gap is independent of the gap in the text.
+#[derive(Debug, Default)]
+pub(crate) struct LineIndex {
+    /// Positions of the newlines before the gap in ascending order
+    before: Vec<usize>,
+    /// Distances from the end of the text to the newlines after the gap in
+    /// ascending order, so the newline closest to the gap is last
+    after: Vec<usize>,
+}
+
+impl LineIndex {
+    pub(crate) fn new(text: &str) -> Self {
+        let before = memchr_iter(b'\n', text.as_bytes()).collect();
+        Self {
+            before,
+            after: Vec::new(),
+        }
+    }
+
+    /// Move the gap to byte position `pos` of text that is `len` bytes long.
+    fn move_gap(&mut self, pos: usize, len: usize) {
+        while let Some(&newline) = self.before.last() {
+            if newline < pos {
+                break;
+            }
+            self.before.pop();
+            self.after.push(len - newline);
+        }
+        while let Some(&dist) = self.after.last() {
+            if len - dist >= pos {
+                break;
+            }
+            self.after.pop();
+            self.before.push(len - dist);
+        }
+    }
+
+    /// Record that `text` was inserted at byte position `pos` of text that
+    /// was `len` bytes long.
+    pub(crate) fn insert(&mut self, pos: usize, text: &str, len: usize) {
+        self.move_gap(pos, len);
+        let newlines = memchr_iter(b'\n', text.as_bytes()).map(|x| pos + x);
+        self.before.extend(newlines);
+    }
+
+    /// Record that the bytes between `beg` and `end` were deleted from text
+    /// that was `len` bytes long.
+    pub(crate) fn delete(&mut self, beg: usize, end: usize, len: usize) {
+        self.move_gap(beg, len);
+        while self.after.last().is_some_and(|dist| len - dist < end) {
+            self.after.pop();
+        }
+    }
+
+    /// The number of newlines before byte position `pos` of text that is
+    /// `len` bytes long.
+    pub(crate) fn count(&self, pos: usize, len: usize) -> usize {
+        let before = self.before.partition_point(|&x| x < pos);
+        let after = self.after.len() - self.after.partition_point(|&x| x <= len - pos);
+        before + after
+    }
+
+    /// The byte position of newline `n`, counting from 0, in text that is
+    /// `len` bytes long.
+    pub(crate) fn get(&self, n: usize, len: usize) -> Option<usize> {
+        match n.checked_sub(self.before.len()) {
+            None => Some(self.before[n]),
+            Some(idx) if idx < self.after.len() => {
+                Some(len - self.after[self.after.len() - 1 - idx])
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn newlines(index: &LineIndex, len: usize) -> Vec<usize> {
+        (0..).map_while(|n| index.get(n, len)).collect()
+    }
+
+    #[test]
+    fn edits() {
+        let mut text = String::from("a\nbc\n\nd");
+        let mut index = LineIndex::new(&text);
+        assert_eq!(newlines(&index, text.len()), [1, 4, 5]);
+        for (pos, insert) in [(3, "x\ny"), (0, "\n"), (11, "\n\n"), (4, "z")] {
+            index.insert(pos, insert, text.len());
+            text.insert_str(pos, insert);
+            let expect: Vec<_> = text.match_indices('\n').map(|x| x.0).collect();
+            assert_eq!(newlines(&index, text.len()), expect, "{text:?}");
+        }
+        for (beg, end) in [(2, 6), (0, 1), (5, 8)] {
+            index.delete(beg, end, text.len());
+            text.replace_range(beg..end, "");
+            let expect: Vec<_> = text.match_indices('\n').map(|x| x.0).collect();
+            assert_eq!(newlines(&index, text.len()), expect, "{text:?}");
+        }
+        for pos in 0..=text.len() {
+            let expect = text[..pos].matches('\n').count();
+            assert_eq!(index.count(pos, text.len()), expect, "{text:?} {pos}");
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use darling::FromMeta;
-use proc_macro::TokenStream;
+use proc_macro::{TokenStream, TokenTree};
+use quote::{format_ident, quote};
 use syn::parse_macro_input;
 
 mod defun;
+mod symbol;
 mod trace;
 
 #[proc_macro_attribute]
@@ -21,3 +23,26 @@ pub fn trace_derive(stream: TokenStream) -> TokenStream {
     let derived = parse_macro_input!(stream as syn::DeriveInput);
     trace::expand(&derived).into()
 }
+
+/// Expand to the static symbol for a lisp name, like `static_sym!(foo-bar)`.
+/// The symbol itself is defined by build.rs. Use it through `sym!`.
+#[proc_macro]
+pub fn static_sym(ts: TokenStream) -> TokenStream {
+    let name: String = ts
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Punct(punct) => punct.as_char().to_string(),
+            token => token.to_string(),
+        })
+        .collect();
+    match symbol::ident_for(&name) {
+        Some(ident) => {
+            let ident = format_ident!("{ident}");
+            quote!(crate::core::env::sym::#ident).into()
+        }
+        None => {
+            let msg = format!("`{name}` has no static symbol, define it with defsym!");
+            quote!(compile_error!(#msg)).into()
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Mapping from lisp symbol names to the identifiers of their static symbols
+//! in `sym`. This is also included by build.rs so both agree on the names.
+
+/// Return the identifier of the static symbol for `name`. Dashes become
+/// underscores, punctuation is spelled out, and keywords get a `KW_` prefix,
+/// so `let*` is `LET_STAR` and `:test` is `KW_TEST`. Returns `None` if the
+/// name has no identifier.
+pub fn ident_for(name: &str) -> Option<String> {
+    let (prefix, name) = match name.strip_prefix(':') {
+        Some(keyword) => ("KW_", keyword),
+        None => ("", name),
+    };
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => word.push(c.to_ascii_uppercase()),
+            '-' | '_' => words.push(std::mem::take(&mut word)),
+            c => {
+                words.push(std::mem::take(&mut word));
+                words.push(punctuation(c)?.to_owned());
+            }
+        }
+    }
+    words.push(word);
+    words.retain(|x| !x.is_empty());
+    let ident = format!("{prefix}{}", words.join("_"));
+    match ident.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => Some(ident),
+        _ => None,
+    }
+}
+
+fn punctuation(c: char) -> Option<&'static str> {
+    let word = match c {
+        '*' => "STAR",
+        '&' => "AND",
+        '+' => "PLUS",
+        '/' => "SLASH",
+        '=' => "EQ",
+        '<' => "LT",
+        '>' => "GT",
+        '%' => "PERCENT",
+        '!' => "BANG",
+        '?' => "QUESTION",
+        '.' => "DOT",
+        '@' => "AT",
+        '$' => "DOLLAR",
+        '^' => "CARET",
+        '~' => "TILDE",
+        _ => return None,
+    };
+    Some(word)
+}
@@ -143,7 +143,7 @@
 ;; (load "emacs-lisp/map-ynp")
 ;; (load "international/mule")
 ;; (load "international/mule-conf")
-;; (load "env")
+(load "env")
 ;; (load "format")
 ;; (load "bindings")
 ;; (load "window")  ; Needed here for `replace-buffer-in-windows'.
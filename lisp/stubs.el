@@ -12,3 +12,22 @@
 (defun substitute-command-keys (string &optional no-face)
   "stub of function for bootstrapping"
   string)
+
+(defmacro oclosure-define (name &optional docstring &rest slots)
+  "stub of macro for bootstrapping
+NAME is a symbol or (NAME . PROPS), where PROPS can hold (:parent PARENT).
+Each of SLOTS is a symbol or (SLOT . PROPS)."
+  (let* ((props (cdr-safe name))
+         (name (or (car-safe name) name)))
+    (when (and docstring (not (stringp docstring)))
+      (push docstring slots))
+    `(oclosure--define ',name ',(car (cdr (assq :parent props)))
+                       ',(mapcar (lambda (slot) (or (car-safe slot) slot)) slots))))
+
+(defmacro oclosure-lambda (type-and-slots args &rest body)
+  "stub of macro for bootstrapping
+TYPE-AND-SLOTS is (TYPE . SLOTS), where each of SLOTS is (SLOT VALUE)."
+  `(oclosure--make ',(car type-and-slots)
+                   (list ,@(mapcar (lambda (slot) `(cons ',(car slot) ,(car (cdr slot))))
+                                   (cdr type-and-slots)))
+                   (lambda ,args ,@body)))
@@ -233,13 +233,14 @@ value of last one, or nil if there are none.
   (declare (indent 1) (debug t))
   (cons 'if (cons cond (cons nil body))))
 
-(defsubst xor (cond1 cond2)
-  "Return the boolean exclusive-or of COND1 and COND2.
-If only one of the arguments is non-nil, return it; otherwise
-return nil."
-  (declare (pure t) (side-effect-free error-free))
-  (cond ((not cond1) cond2)
-        ((not cond2) cond1)))
+;; RUNE BOOTSTRAP: xor is defined natively in src/fns.rs
+;; (defsubst xor (cond1 cond2)
+;;   "Return the boolean exclusive-or of COND1 and COND2.
+;; If only one of the arguments is non-nil, return it; otherwise
+;; return nil."
+;;   (declare (pure t) (side-effect-free error-free))
+;;   (cond ((not cond1) cond2)
+;;         ((not cond2) cond1)))
 
 (defmacro dolist (spec &rest body)
   "Loop over a list.
@@ -3094,9 +3095,10 @@ See also `locate-user-emacs-file'.")
 
 ;;;; Misc. useful functions.
 
-(defsubst buffer-narrowed-p ()
-  "Return non-nil if the current buffer is narrowed."
-  (/= (- (point-max) (point-min)) (buffer-size)))
+;; RUNE BOOTSTRAP: buffer-narrowed-p is defined natively in src/editfns.rs
+;; (defsubst buffer-narrowed-p ()
+;;   "Return non-nil if the current buffer is narrowed."
+;;   (/= (- (point-max) (point-min)) (buffer-size)))
 
 (defun find-tag-default-bounds ()
   "Determine the boundaries of the default tag, based on text at point.
@@ -3558,10 +3560,11 @@ also `with-temp-buffer'."
   (when (window-live-p (nth 1 state))
     (select-window (nth 1 state) 'norecord)))
 
-(defun generate-new-buffer (name)
-  "Create and return a buffer with a name based on NAME.
-Choose the buffer's name using `generate-new-buffer-name'."
-  (get-buffer-create (generate-new-buffer-name name)))
+;; RUNE BOOTSTRAP: generate-new-buffer is defined natively in src/buffer.rs
+;; (defun generate-new-buffer (name)
+;;   "Create and return a buffer with a name based on NAME.
+;; Choose the buffer's name using `generate-new-buffer-name'."
+;;   (get-buffer-create (generate-new-buffer-name name)))
 
 (defmacro with-selected-window (window &rest body)
   "Execute the forms in BODY with WINDOW as the selected window.
@@ -4095,19 +4098,20 @@ rather than your caller's match data."
 	      ;; as Lisp programs should not copy from save-match-data-internal.
 	      '(set-match-data save-match-data-internal 'evaporate))))
 
-(defun match-string (num &optional string)
-  "Return string of text matched by last search.
-NUM specifies which parenthesized expression in the last regexp.
- Value is nil if NUMth pair didn't match, or there were less than NUM pairs.
-Zero means the entire text matched by the whole regexp or whole string.
-STRING should be given if the last search was by `string-match' on STRING.
-If STRING is nil, the current buffer should be the same buffer
-the search/match was performed in."
-  (declare (side-effect-free t))
-  (if (match-beginning num)
-      (if string
-	  (substring string (match-beginning num) (match-end num))
-	(buffer-substring (match-beginning num) (match-end num)))))
+;; RUNE BOOTSTRAP: match-string is defined natively in src/search.rs
+;; (defun match-string (num &optional string)
+;;   "Return string of text matched by last search.
+;; NUM specifies which parenthesized expression in the last regexp.
+;;  Value is nil if NUMth pair didn't match, or there were less than NUM pairs.
+;; Zero means the entire text matched by the whole regexp or whole string.
+;; STRING should be given if the last search was by `string-match' on STRING.
+;; If STRING is nil, the current buffer should be the same buffer
+;; the search/match was performed in."
+;;   (declare (side-effect-free t))
+;;   (if (match-beginning num)
+;;       (if string
+;; 	  (substring string (match-beginning num) (match-end num))
+;; 	(buffer-substring (match-beginning num) (match-end num)))))
 
 (defun match-string-no-properties (num &optional string)
   "Return string of text matched by last search, without text properties.
@@ -4143,53 +4147,55 @@ meaning as for `replace-match'."
       (replace-match replacement fixedcase literal match subexp))))
 
 
-(defun looking-back (regexp &optional limit greedy)
-  "Return non-nil if text before point matches regular expression REGEXP.
-Like `looking-at' except matches before point, and is slower.
-LIMIT if non-nil speeds up the search by specifying a minimum
-starting position, to avoid checking matches that would start
-before LIMIT.
-
-If GREEDY is non-nil, extend the match backwards as far as
-possible, stopping when a single additional previous character
-cannot be part of a match for REGEXP.  When the match is
-extended, its starting position is allowed to occur before
-LIMIT.
-
-As a general recommendation, try to avoid using `looking-back'
-wherever possible, since it is slow."
-  (declare
-   (advertised-calling-convention (regexp limit &optional greedy) "25.1"))
-  (let ((start (point))
-	(pos
-	 (save-excursion
-	   (and (re-search-backward (concat "\\(?:" regexp "\\)\\=") limit t)
-		(point)))))
-    (if (and greedy pos)
-	(save-restriction
-	  (narrow-to-region (point-min) start)
-	  (while (and (> pos (point-min))
-		      (save-excursion
-			(goto-char pos)
-			(backward-char 1)
-			(looking-at (concat "\\(?:"  regexp "\\)\\'"))))
-	    (setq pos (1- pos)))
-	  (save-excursion
-	    (goto-char pos)
-	    (looking-at (concat "\\(?:"  regexp "\\)\\'")))))
-    (not (null pos))))
-
-(defsubst looking-at-p (regexp)
-  "\
-Same as `looking-at' except this function does not change the match data."
-  (let ((inhibit-changing-match-data t))
-    (looking-at regexp)))
-
-(defsubst string-match-p (regexp string &optional start)
-  "\
-Same as `string-match' except this function does not change the match data."
-  (let ((inhibit-changing-match-data t))
-    (string-match regexp string start)))
+;; RUNE BOOTSTRAP: looking-back and looking-at-p are defined natively in src/search.rs
+;; (defun looking-back (regexp &optional limit greedy)
+;;   "Return non-nil if text before point matches regular expression REGEXP.
+;; Like `looking-at' except matches before point, and is slower.
+;; LIMIT if non-nil speeds up the search by specifying a minimum
+;; starting position, to avoid checking matches that would start
+;; before LIMIT.
+;;
+;; If GREEDY is non-nil, extend the match backwards as far as
+;; possible, stopping when a single additional previous character
+;; cannot be part of a match for REGEXP.  When the match is
+;; extended, its starting position is allowed to occur before
+;; LIMIT.
+;;
+;; As a general recommendation, try to avoid using `looking-back'
+;; wherever possible, since it is slow."
+;;   (declare
+;;    (advertised-calling-convention (regexp limit &optional greedy) "25.1"))
+;;   (let ((start (point))
+;; 	(pos
+;; 	 (save-excursion
+;; 	   (and (re-search-backward (concat "\\(?:" regexp "\\)\\=") limit t)
+;; 		(point)))))
+;;     (if (and greedy pos)
+;; 	(save-restriction
+;; 	  (narrow-to-region (point-min) start)
+;; 	  (while (and (> pos (point-min))
+;; 		      (save-excursion
+;; 			(goto-char pos)
+;; 			(backward-char 1)
+;; 			(looking-at (concat "\\(?:"  regexp "\\)\\'"))))
+;; 	    (setq pos (1- pos)))
+;; 	  (save-excursion
+;; 	    (goto-char pos)
+;; 	    (looking-at (concat "\\(?:"  regexp "\\)\\'")))))
+;;     (not (null pos))))
+;;
+;; (defsubst looking-at-p (regexp)
+;;   "\
+;; Same as `looking-at' except this function does not change the match data."
+;;   (let ((inhibit-changing-match-data t))
+;;     (looking-at regexp)))
+
+;; RUNE BOOTSTRAP: string-match-p is defined natively in src/search.rs
+;; (defsubst string-match-p (regexp string &optional start)
+;;   "\
+;; Same as `string-match' except this function does not change the match data."
+;;   (let ((inhibit-changing-match-data t))
+;;     (string-match regexp string start)))
 
 (defun subregexp-context-p (regexp pos &optional start)
   "Return non-nil if POS is in a normal subregexp context in REGEXP.
@@ -4372,82 +4378,86 @@ Unless optional argument INPLACE is non-nil, return a new string."
 	  (aset newstr i tochar)))
     newstr))
 
-(defun replace-regexp-in-string (regexp rep string &optional
-					fixedcase literal subexp start)
-  "Replace all matches for REGEXP with REP in STRING.
-
-Return a new string containing the replacements.
-
-Optional arguments FIXEDCASE, LITERAL and SUBEXP are like the
-arguments with the same names of function `replace-match'.  If START
-is non-nil, start replacements at that index in STRING, and omit
-the first START characters of STRING from the return value.
-
-REP is either a string used as the NEWTEXT arg of `replace-match' or a
-function.  If it is a function, it is called with the actual text of each
-match, and its value is used as the replacement text.  When REP is called,
-the match data are the result of matching REGEXP against a substring
-of STRING, the same substring that is the actual text of the match which
-is passed to REP as its argument.
-
-To replace only the first match (if any), make REGEXP match up to \\\\='
-and replace a sub-expression, e.g.
-  (replace-regexp-in-string \"\\\\(foo\\\\).*\\\\\\='\" \"bar\" \" foo foo\" nil nil 1)
-    => \" bar foo\""
-
-  ;; To avoid excessive consing from multiple matches in long strings,
-  ;; don't just call `replace-match' continually.  Walk down the
-  ;; string looking for matches of REGEXP and building up a (reversed)
-  ;; list MATCHES.  This comprises segments of STRING that weren't
-  ;; matched interspersed with replacements for segments that were.
-  ;; [For a `large' number of replacements it's more efficient to
-  ;; operate in a temporary buffer; we can't tell from the function's
-  ;; args whether to choose the buffer-based implementation, though it
-  ;; might be reasonable to do so for long enough STRING.]
-  (let ((l (length string))
-	(start (or start 0))
-	matches str mb me)
-    (save-match-data
-      (while (and (< start l) (string-match regexp string start))
-	(setq mb (match-beginning 0)
-	      me (match-end 0))
-	;; If we matched the empty string, make sure we advance by one char
-	(when (= me mb) (setq me (min l (1+ mb))))
-	;; Generate a replacement for the matched substring.
-	;; Operate on only the substring to minimize string consing.
-	;; Set up match data for the substring for replacement;
-	;; presumably this is likely to be faster than munging the
-	;; match data directly in Lisp.
-	(string-match regexp (setq str (substring string mb me)))
-	(setq matches
-	      (cons (replace-match (if (stringp rep)
-				       rep
-				     (funcall rep (match-string 0 str)))
-				   fixedcase literal str subexp)
-		    (cons (substring string start mb) ; unmatched prefix
-			  matches)))
-	(setq start me))
-      ;; Reconstruct a string from the pieces.
-      (setq matches (cons (substring string start l) matches)) ; leftover
-      (apply #'concat (nreverse matches)))))
-
-(defun string-prefix-p (prefix string &optional ignore-case)
-  "Return non-nil if PREFIX is a prefix of STRING.
-If IGNORE-CASE is non-nil, the comparison is done without paying attention
-to case differences."
-  (let ((prefix-length (length prefix)))
-    (if (> prefix-length (length string)) nil
-      (eq t (compare-strings prefix 0 prefix-length string
-			     0 prefix-length ignore-case)))))
-
-(defun string-suffix-p (suffix string  &optional ignore-case)
-  "Return non-nil if SUFFIX is a suffix of STRING.
-If IGNORE-CASE is non-nil, the comparison is done without paying
-attention to case differences."
-  (let ((start-pos (- (length string) (length suffix))))
-    (and (>= start-pos 0)
-         (eq t (compare-strings suffix nil nil
-                                string start-pos nil ignore-case)))))
+;; RUNE BOOTSTRAP: defined natively in src/search.rs
+;; (defun replace-regexp-in-string (regexp rep string &optional
+;; 					fixedcase literal subexp start)
+;;   "Replace all matches for REGEXP with REP in STRING.
+;;
+;; Return a new string containing the replacements.
+;;
+;; Optional arguments FIXEDCASE, LITERAL and SUBEXP are like the
+;; arguments with the same names of function `replace-match'.  If START
+;; is non-nil, start replacements at that index in STRING, and omit
+;; the first START characters of STRING from the return value.
+;;
+;; REP is either a string used as the NEWTEXT arg of `replace-match' or a
+;; function.  If it is a function, it is called with the actual text of each
+;; match, and its value is used as the replacement text.  When REP is called,
+;; the match data are the result of matching REGEXP against a substring
+;; of STRING, the same substring that is the actual text of the match which
+;; is passed to REP as its argument.
+;;
+;; To replace only the first match (if any), make REGEXP match up to \\\\='
+;; and replace a sub-expression, e.g.
+;;   (replace-regexp-in-string \"\\\\(foo\\\\).*\\\\\\='\" \"bar\" \" foo foo\" nil nil 1)
+;;     => \" bar foo\""
+;;
+;;   ;; To avoid excessive consing from multiple matches in long strings,
+;;   ;; don't just call `replace-match' continually.  Walk down the
+;;   ;; string looking for matches of REGEXP and building up a (reversed)
+;;   ;; list MATCHES.  This comprises segments of STRING that weren't
+;;   ;; matched interspersed with replacements for segments that were.
+;;   ;; [For a `large' number of replacements it's more efficient to
+;;   ;; operate in a temporary buffer; we can't tell from the function's
+;;   ;; args whether to choose the buffer-based implementation, though it
+;;   ;; might be reasonable to do so for long enough STRING.]
+;;   (let ((l (length string))
+;; 	(start (or start 0))
+;; 	matches str mb me)
+;;     (save-match-data
+;;       (while (and (< start l) (string-match regexp string start))
+;; 	(setq mb (match-beginning 0)
+;; 	      me (match-end 0))
+;; 	;; If we matched the empty string, make sure we advance by one char
+;; 	(when (= me mb) (setq me (min l (1+ mb))))
+;; 	;; Generate a replacement for the matched substring.
+;; 	;; Operate on only the substring to minimize string consing.
+;; 	;; Set up match data for the substring for replacement;
+;; 	;; presumably this is likely to be faster than munging the
+;; 	;; match data directly in Lisp.
+;; 	(string-match regexp (setq str (substring string mb me)))
+;; 	(setq matches
+;; 	      (cons (replace-match (if (stringp rep)
+;; 				       rep
+;; 				     (funcall rep (match-string 0 str)))
+;; 				   fixedcase literal str subexp)
+;; 		    (cons (substring string start mb) ; unmatched prefix
+;; 			  matches)))
+;; 	(setq start me))
+;;       ;; Reconstruct a string from the pieces.
+;;       (setq matches (cons (substring string start l) matches)) ; leftover
+;;       (apply #'concat (nreverse matches)))))
+;; 
+
+;; RUNE BOOTSTRAP: string-prefix-p and string-suffix-p are defined natively in
+;; src/fns.rs
+;; (defun string-prefix-p (prefix string &optional ignore-case)
+;;   "Return non-nil if PREFIX is a prefix of STRING.
+;; If IGNORE-CASE is non-nil, the comparison is done without paying attention
+;; to case differences."
+;;   (let ((prefix-length (length prefix)))
+;;     (if (> prefix-length (length string)) nil
+;;       (eq t (compare-strings prefix 0 prefix-length string
+;; 			     0 prefix-length ignore-case)))))
+;;
+;; (defun string-suffix-p (suffix string  &optional ignore-case)
+;;   "Return non-nil if SUFFIX is a suffix of STRING.
+;; If IGNORE-CASE is non-nil, the comparison is done without paying
+;; attention to case differences."
+;;   (let ((start-pos (- (length string) (length suffix))))
+;;     (and (>= start-pos 0)
+;;          (eq t (compare-strings suffix nil nil
+;;                                 string start-pos nil ignore-case)))))
 
 (defun bidi-string-mark-left-to-right (str)
   "Return a string that can be safely inserted in left-to-right text.
@@ -1,7 +1,8 @@
-use crate::core::env::{Symbol, SymbolCell};
-use crate::core::gc::Context;
+use crate::core::env::{sym, Env, Symbol, SymbolCell, INTERNED_SYMBOLS};
+use crate::core::error::EvalError;
+use crate::core::gc::{reserve, Context, Rt};
 use crate::core::object::{
-    nil, ByteFn, FnArgs, Gc, GcObj, IntoObject, LispString, LispVec, RecordBuilder,
+    nil, qtrue, ByteFn, FnArgs, Gc, GcObj, IntoObject, LispString, LispVec, RecordBuilder,
 };
 use anyhow::{ensure, Result};
 use fn_macros::defun;
@@ -79,9 +80,51 @@ fn record<'ob>(type_: GcObj<'ob>, slots: &[GcObj<'ob>]) -> RecordBuilder<'ob> {
     RecordBuilder(record)
 }
 
+/// Make a copy of `obj` in pure (read-only) storage. Strings with identical
+/// contents share a single copy. Only done while `purify-flag` is non-nil.
 #[defun]
-fn purecopy(obj: GcObj) -> GcObj {
-    obj
+fn purecopy<'ob>(obj: GcObj<'ob>, env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    match env.vars.get(sym::PURIFY_FLAG) {
+        Some(flag) if flag.bind(cx) != nil() => INTERNED_SYMBOLS.lock().unwrap().purecopy(obj, cx),
+        _ => obj,
+    }
+}
+
+/// Reclaim the storage of the objects that are no longer referenced.
+#[defun]
+fn garbage_collect(cx: &mut Context) -> bool {
+    cx.garbage_collect(true);
+    false
+}
+
+/// Signal `memory-full` if an allocation needed the emergency reserve or the
+/// live objects of `cx` are over the limits it was created with. The variable
+/// `memory-full` is t until the reserve can be refilled. This is called at the
+/// same safe points as the collector, and before every builtin is called.
+pub(crate) fn check_memory_limits(env: &mut Rt<Env>, cx: &mut Context) -> Result<(), EvalError> {
+    let exhausted = reserve::take_exhausted();
+    let mut exceeded = cx.exceeded_limit();
+    // what the caches hold can be recomputed, so drop it before giving up
+    if exceeded.is_some() && env.clear_caches(cx) {
+        cx.garbage_collect(true);
+        exceeded = cx.exceeded_limit();
+    }
+    let full = cx.memory_full() || !reserve::refill_reserve();
+    if env
+        .vars
+        .get(sym::MEMORY_FULL)
+        .is_none_or(|x| x.bind(cx).nil() == full)
+    {
+        env.vars
+            .insert(sym::MEMORY_FULL, if full { qtrue() } else { nil() });
+    }
+    let message = match exceeded {
+        Some(message) => message,
+        None if exhausted => "Memory exhausted",
+        None => return Ok(()),
+    };
+    let data = list![message; cx];
+    Err(EvalError::signal(sym::MEMORY_FULL.into(), data, env))
 }
 
 #[defun]
@@ -89,3 +132,107 @@ fn make_symbol<'ob>(name: &str, cx: &'ob Context) -> Gc<Symbol<'ob>> {
     let sym = SymbolCell::new_uninterned(name);
     sym.into_obj(cx)
 }
+
+/// Register a cache called NAME that holds up to LIMIT entries. Its entries
+/// are rooted like variables, and are removed by `clear-caches`.
+#[defun]
+fn define_cache<'ob>(name: Symbol<'ob>, limit: usize, env: &mut Rt<Env>) -> Symbol<'ob> {
+    env.define_cache(name, limit);
+    name
+}
+
+/// The value of KEY in the cache called NAME, or nil if it is not there.
+#[defun]
+fn cache_get<'ob>(name: Symbol, key: GcObj, env: &mut Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    env.cache_get(name, key, cx).unwrap_or_else(nil)
+}
+
+/// Set the value of KEY in the cache called NAME to VALUE.
+#[defun]
+fn cache_put<'ob>(
+    name: Symbol,
+    key: GcObj,
+    value: GcObj<'ob>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    ensure!(env.cache_put(name, key, value), "No cache named {name}");
+    Ok(value)
+}
+
+/// Remove the entries of all caches. They are also removed when the live
+/// objects are over the memory limits.
+#[defun]
+fn clear_caches(env: &mut Rt<Env>, cx: &Context) -> bool {
+    env.clear_caches(cx);
+    false
+}
+
+defvar!(PURIFY_FLAG, true);
+defvar!(MEMORY_FULL, false);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Limits, RootSet};
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_memory_limits() {
+        let roots = &RootSet::default();
+        let limits = Limits {
+            max_conses: Some(2000),
+            max_bytes: None,
+        };
+        let cx = &mut Context::with_limits(roots, limits);
+        root!(env, Env::default(), cx);
+        // garbage does not count against the limit
+        let form = "(let ((f #'(lambda (x) (cons x nil))) (i 0))
+                      (while (< i 3000) (funcall f i) (setq i (1+ i)))
+                      i)";
+        assert_eq!(check(form, env, cx), 3000);
+        assert_eq!(check("memory-full", env, cx), nil());
+
+        // handlers can still allocate once the limit is hit
+        let form = "(let ((f #'(lambda (x) (cons 1 x))) (x nil))
+                      (condition-case err
+                          (while t (setq x (funcall f x)))
+                        (error (list (car err) memory-full (funcall f nil)))))";
+        assert_eq!(check(form, env, cx).to_string(), "(memory-full t (1))");
+        // the reserve is refilled once the objects are freed
+        assert_eq!(check("memory-full", env, cx), nil());
+
+        // allocating in a loop is caught without any closure being called
+        let form = "(let ((x nil))
+                      (condition-case err
+                          (while t (setq x (cons 1 x)))
+                        (error (list (car err) memory-full))))";
+        assert_eq!(check(form, env, cx).to_string(), "(memory-full t)");
+        assert_eq!(check("memory-full", env, cx), nil());
+    }
+
+    #[test]
+    fn test_caches() {
+        let roots = &RootSet::default();
+        let limits = Limits {
+            max_conses: Some(2000),
+            max_bytes: None,
+        };
+        let cx = &mut Context::with_limits(roots, limits);
+        root!(env, Env::default(), cx);
+        check("(define-cache 'test-cache 4)", env, cx);
+        check("(cache-put 'test-cache \"key\" 1)", env, cx);
+        assert_eq!(check("(cache-get 'test-cache \"key\")", env, cx), 1);
+        check("(clear-caches)", env, cx);
+        assert_eq!(check("(cache-get 'test-cache \"key\")", env, cx), nil());
+
+        // the caches are emptied before the limits are enforced
+        let build = "(let ((f #'(lambda (x) (cons 1 x))) (i 0) (x nil))
+                       (while (< i 1500) (setq x (funcall f x) i (1+ i)))
+                       x)";
+        check(&format!("(cache-put 'test-cache 'list {build})"), env, cx);
+        assert_eq!(check(&format!("(length {build})"), env, cx), 1500);
+        assert_eq!(check("memory-full", env, cx), nil());
+        assert_eq!(check("(cache-get 'test-cache 'list)", env, cx), nil());
+    }
+}
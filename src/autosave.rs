@@ -0,0 +1,354 @@
+//! Auto-saving, which periodically writes the text of modified buffers to
+//! their auto-save files so it can be recovered if the process dies. An
+//! auto-save happens after `auto-save-interval` input events or changes, and
+//! when input is idle for `auto-save-timeout` seconds.
+#![cfg(feature = "buffers")]
+use crate::buffer::{
+    buffer_var, generate_new_buffer, get_current_buffer, live_buffers, set_buffer_var,
+    set_current_buffer,
+};
+use crate::core::{
+    env::{sym, Env, Symbol},
+    gc::{Context, Rt},
+    object::{Buffer, Gc, GcObj, LispString, Object},
+};
+use crate::editfns::message;
+use crate::fileio::{expand, expand_file_name, file_io_error, file_modtime, visited_file};
+use crate::root;
+use anyhow::{ensure, Result};
+use fn_macros::defun;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use std::{fs, io};
+
+/// Input events since the last auto-save.
+static INPUT_EVENTS: AtomicUsize = AtomicUsize::new(0);
+/// Buffer changes since the last auto-save.
+static CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// The auto-save file of the file `file`, `#NAME#` in its directory.
+fn auto_save_name(file: &str) -> String {
+    let start = file.rfind('/').map_or(0, |x| x + 1);
+    format!("{}#{}#", &file[..start], &file[start..])
+}
+
+/// The auto-save file of `buffer`, or `None` if it is not auto-saved.
+fn auto_save_file(buffer: &'static Buffer, env: &mut Rt<Env>, cx: &Context) -> Option<String> {
+    match buffer_var(buffer, sym::BUFFER_AUTO_SAVE_FILE_NAME, env, cx).untag() {
+        Object::String(name) => <&str>::try_from(name).ok().map(str::to_owned),
+        _ => None,
+    }
+}
+
+/// Return a file name to auto-save the current buffer in. A buffer visiting
+/// a file is saved in `#NAME#` next to it. Other buffers are saved in
+/// `default-directory`, with the characters of the buffer name that are not
+/// safe in file names hex-encoded, and the process id added so the file
+/// names of different processes are distinct.
+#[defun]
+fn make_auto_save_file_name(env: &mut Rt<Env>, cx: &Context) -> Result<String> {
+    let buffer = get_current_buffer(env);
+    if let Some(file) = visited_file(buffer, env, cx) {
+        return Ok(auto_save_name(&file));
+    }
+    let mut name = String::new();
+    for c in buffer.lock()?.name().chars() {
+        match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '.' | '~' | '+' | '-' => name.push(c),
+            c => _ = write!(name, "%{:02X}", c as u32),
+        }
+    }
+    let pid = std::process::id();
+    expand_file_name(&format!("#{name}#{pid}#"), None, env, cx)
+}
+
+/// Write the text of each buffer that changed since it was last auto-saved
+/// to its auto-save file, after running `auto-save-hook`. Only buffers with
+/// a non-nil `buffer-auto-save-file-name` are saved. If NO-MESSAGE is
+/// non-nil, nothing is shown unless a save fails. If CURRENT-ONLY is
+/// non-nil, only the current buffer is saved. If `auto-save-list-file-name`
+/// is non-nil, the name of each visited file and its auto-save file is
+/// written to it, one per line.
+#[defun]
+pub(crate) fn do_auto_save(
+    no_message: Option<()>,
+    current_only: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    INPUT_EVENTS.store(0, Ordering::Relaxed);
+    CHANGES.store(0, Ordering::Relaxed);
+    let hook: GcObj = sym::AUTO_SAVE_HOOK.into();
+    root!(hook, cx);
+    crate::eval::run_hook_with_args(hook, &[], env, cx)?;
+    let buffers = match current_only {
+        Some(()) => vec![get_current_buffer(env)],
+        None => live_buffers(),
+    };
+    let mut list = String::new();
+    let mut saved = false;
+    for buffer in buffers {
+        let Some(file) = auto_save_file(buffer, env, cx) else {continue};
+        let visited = visited_file(buffer, env, cx).unwrap_or_default();
+        _ = write!(list, "{visited}\n{file}\n");
+        let (name, text) = {
+            let data = buffer.lock()?;
+            if !data.needs_auto_save() {
+                continue;
+            }
+            (
+                data.name().to_owned(),
+                data.text.substring(0, data.text.len_chars()),
+            )
+        };
+        if !saved && no_message.is_none() {
+            message("Auto-saving...", &[])?;
+        }
+        saved = true;
+        match fs::write(&file, text) {
+            Ok(()) => buffer.lock()?.set_auto_saved(),
+            Err(e) => {
+                message("Auto-saving %s: %s", &[cx.add(name), cx.add(e.to_string())])?;
+            }
+        }
+    }
+    if let Some(Object::String(list_file)) = env
+        .vars
+        .get(sym::AUTO_SAVE_LIST_FILE_NAME)
+        .map(|x| x.bind(cx).untag())
+    {
+        let list_file: &str = list_file.try_into()?;
+        if let Err(e) = fs::write(list_file, list) {
+            message(
+                "Writing %s: %s",
+                &[cx.add(list_file), cx.add(e.to_string())],
+            )?;
+        }
+    }
+    if saved && no_message.is_none() {
+        message("Auto-saving...done", &[])?;
+    }
+    Ok(false)
+}
+
+/// The value of the number variable `var` if it is positive.
+fn positive_var(var: Symbol, env: &Rt<Env>, cx: &Context) -> Option<f64> {
+    let value = match env.vars.get(var)?.bind(cx).untag() {
+        Object::Int(x) => x as f64,
+        Object::Float(x) => **x,
+        _ => return None,
+    };
+    (value > 0.0).then_some(value)
+}
+
+/// Add `count` to `counter`, and auto-save if it reached
+/// `auto-save-interval`.
+fn count_events(
+    counter: &AtomicUsize,
+    count: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let total = counter.fetch_add(count, Ordering::Relaxed) + count;
+    match positive_var(sym::AUTO_SAVE_INTERVAL, env, cx) {
+        Some(interval) if total as f64 >= interval => {
+            do_auto_save(None, None, env, cx)?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Count `events` input events toward the next auto-save.
+pub(crate) fn record_input(events: usize, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    count_events(&INPUT_EVENTS, events, env, cx)
+}
+
+/// Count a buffer change toward the next auto-save.
+pub(crate) fn record_change(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    count_events(&CHANGES, 1, env, cx)
+}
+
+/// How long input has to be idle before auto-saving, or `None` if
+/// `auto-save-timeout` disables it.
+pub(crate) fn idle_timeout(env: &Rt<Env>, cx: &Context) -> Option<Duration> {
+    positive_var(sym::AUTO_SAVE_TIMEOUT, env, cx).map(Duration::from_secs_f64)
+}
+
+/// Auto-save after input was idle for [`idle_timeout`], if anything changed
+/// since the last auto-save.
+pub(crate) fn auto_save_idle(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let events = INPUT_EVENTS.load(Ordering::Relaxed) + CHANGES.load(Ordering::Relaxed);
+    if events > 0 {
+        do_auto_save(None, None, env, cx)?;
+    }
+    Ok(())
+}
+
+/// Delete the auto-save file of `buffer` if `delete-auto-save-files` is
+/// non-nil and it was auto-saved since it was last unmodified, or `force`
+/// is true. A buffer that auto-saves to its visited file keeps it.
+pub(crate) fn delete_auto_save_file(
+    buffer: &'static Buffer,
+    force: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let enabled = env.vars.get(sym::DELETE_AUTO_SAVE_FILES);
+    if enabled.is_none_or(|x| x.bind(cx).nil()) || !(force || buffer.lock()?.recent_auto_save()) {
+        return Ok(());
+    }
+    let Some(file) = auto_save_file(buffer, env, cx) else {return Ok(())};
+    if visited_file(buffer, env, cx).as_ref() == Some(&file) {
+        return Ok(());
+    }
+    match fs::remove_file(&file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(file_io_error(&e, "Deleting auto-save file", &file, env, cx))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Delete the auto-save file of the current buffer if
+/// `delete-auto-save-files` is non-nil and the buffer was auto-saved since
+/// it was last unmodified. If FORCE is non-nil, it is deleted even if the
+/// buffer was not auto-saved since.
+#[defun]
+fn delete_auto_save_file_if_necessary(
+    force: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    delete_auto_save_file(get_current_buffer(env), force.is_some(), env, cx)?;
+    Ok(false)
+}
+
+/// Mark the current buffer as auto-saved as it is now.
+#[defun]
+fn set_buffer_auto_saved(env: &mut Rt<Env>) -> Result<bool> {
+    get_current_buffer(env).lock()?.set_auto_saved();
+    Ok(false)
+}
+
+/// Return t if the current buffer was auto-saved since it was last
+/// unmodified.
+#[defun]
+fn recent_auto_save_p(env: &mut Rt<Env>) -> Result<bool> {
+    Ok(get_current_buffer(env).lock()?.recent_auto_save())
+}
+
+/// Visit FILE with the text of its auto-save file, which has to be newer
+/// than FILE. The buffer is left modified so that saving it replaces FILE
+/// with the recovered text.
+#[defun]
+fn recover_file(
+    file: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<&'static Buffer> {
+    let file = expand(file, env, cx)?;
+    let auto_save = auto_save_name(&file);
+    let modified = |x: &str| fs::metadata(x).and_then(|x| x.modified());
+    let current = match (modified(&auto_save), modified(&file)) {
+        (Ok(auto_save), Ok(file)) => auto_save > file,
+        (Ok(_), Err(_)) => true,
+        (Err(_), _) => false,
+    };
+    ensure!(current, "Auto-save file {auto_save} not current");
+    let text = match fs::read(&auto_save) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => return Err(file_io_error(&e, "Opening input file", &auto_save, env, cx)),
+    };
+    let visiting = live_buffers()
+        .into_iter()
+        .find(|x| visited_file(x, env, cx).as_ref() == Some(&file));
+    let buffer = match visiting {
+        Some(buffer) => buffer,
+        None => generate_new_buffer(&file[file.rfind('/').map_or(0, |x| x + 1)..], None),
+    };
+    set_current_buffer(buffer, env, cx);
+    set_buffer_var(buffer, sym::BUFFER_FILE_NAME, cx.add(file.as_str()), env);
+    set_buffer_var(
+        buffer,
+        sym::BUFFER_AUTO_SAVE_FILE_NAME,
+        cx.add(auto_save),
+        env,
+    );
+    let len = {
+        let mut data = buffer.lock()?;
+        data.widen();
+        data.set_modtime(file_modtime(&file));
+        data.text.len_chars()
+    };
+    crate::buffer::delete_text(0, len, env, cx)?;
+    crate::buffer::insert_text(&text, env, cx)?;
+    let mut data = buffer.lock()?;
+    data.goto_char(0);
+    data.set_modified(true);
+    data.set_auto_saved();
+    Ok(buffer)
+}
+
+defvar!(BUFFER_AUTO_SAVE_FILE_NAME);
+defvar!(AUTO_SAVE_INTERVAL, 300);
+defvar!(AUTO_SAVE_TIMEOUT, 30);
+defvar!(AUTO_SAVE_HOOK);
+defvar!(AUTO_SAVE_LIST_FILE_NAME);
+defvar!(DELETE_AUTO_SAVE_FILES, true);
+
+#[cfg(test)]
+mod test {
+    use crate::core::gc::{Context, RootSet};
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_auto_save() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, crate::core::env::Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-autosave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).map(|x| x.to_string())
+        };
+        let saved = eval(&format!(
+            r#"(progn
+                 (setq delete-auto-save-files t auto-save-interval 300)
+                 (set-buffer (get-buffer-create "rune-autosave"))
+                 (setq buffer-file-name "{dir}/text")
+                 (setq buffer-auto-save-file-name (make-auto-save-file-name))
+                 (insert "saved")
+                 (write-region nil nil buffer-file-name nil t)
+                 (insert " edit")
+                 (do-auto-save t)
+                 (list (file-name-nondirectory buffer-auto-save-file-name)
+                       (recent-auto-save-p)
+                       (file-exists-p "{dir}/#text#")
+                       (progn (write-region nil nil buffer-file-name nil t)
+                              (file-exists-p "{dir}/#text#"))
+                       (progn (setq auto-save-interval 2)
+                              (insert "!") (insert "!") (insert "!")
+                              (recent-auto-save-p))))"#
+        ));
+        // make the auto-save file newer than the file it was saved after
+        let file = std::fs::File::options()
+            .write(true)
+            .open(format!("{dir}/text"));
+        file.unwrap().set_modified(std::time::UNIX_EPOCH).unwrap();
+        let recovered = eval(&format!(
+            r#"(progn
+                 (setq auto-save-interval 300)
+                 (kill-buffer)
+                 (set-buffer (recover-file "{dir}/text"))
+                 (list (buffer-string) (buffer-modified-p)))"#
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(saved.unwrap(), "(\"#text#\" t t nil t)");
+        assert_eq!(recovered.unwrap(), "(\"saved edit!\" t)");
+    }
+}
@@ -0,0 +1,265 @@
+//! Base64 encoding, in the standard alphabet of RFC 4648 and in its
+//! URL-safe variant. Only bytes can be encoded: a unibyte string holds
+//! them, but multibyte text has to be encoded first, unless it is all
+//! ASCII. Like in Emacs, any other character is an error instead of being
+//! taken for a byte.
+#[cfg(feature = "buffers")]
+use crate::coding::{region_text, replace_region};
+#[cfg(feature = "buffers")]
+use crate::core::{env::Env, gc::Rt};
+use crate::core::{
+    gc::Context,
+    object::{GcObj, LispString},
+};
+use anyhow::{bail, Result};
+use bstr::ByteSlice;
+use fn_macros::defun;
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The length of the lines of encoded text, unless line breaks are turned
+/// off.
+const LINE_LENGTH: usize = 76;
+
+/// The bytes of the multibyte `text`, or an error if it is not ASCII.
+fn text_bytes(text: &str) -> Result<Vec<u8>> {
+    match text.is_ascii() {
+        true => Ok(text.as_bytes().to_vec()),
+        false => bail!("Multibyte character in data for base64 encoding"),
+    }
+}
+
+fn string_bytes(string: &LispString) -> Result<Vec<u8>> {
+    match string.is_multibyte() {
+        true => text_bytes(string.to_str()?),
+        false => Ok(string.to_vec()),
+    }
+}
+
+/// Encode `bytes` with the URL-safe alphabet if `url` is set. The encoding is
+/// padded with `=` to a multiple of four characters if `pad` is set, and
+/// broken into lines if `line_break` is set.
+fn encode(bytes: &[u8], url: bool, pad: bool, line_break: bool) -> String {
+    let alphabet = if url { URL } else { STANDARD };
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    let mut column = 0;
+    for chunk in bytes.chunks(3) {
+        if line_break && column == LINE_LENGTH {
+            out.push('\n');
+            column = 0;
+        }
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &x)| acc | u32::from(x) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(char::from(
+                alphabet[(group >> (18 - 6 * i) & 0x3f) as usize],
+            ));
+        }
+        if pad {
+            out.extend(std::iter::repeat_n('=', 3 - chunk.len()));
+        }
+        column += 4;
+    }
+    out
+}
+
+/// Decode `text`, which is in the URL-safe alphabet if `url` is set.
+/// Whitespace is ignored, and so are other characters that are not in the
+/// alphabet if `ignore_invalid` is set. The padding can only be left out of
+/// the URL-safe encoding.
+fn decode(text: &[u8], url: bool, ignore_invalid: bool) -> Result<Vec<u8>> {
+    let alphabet = if url { URL } else { STANDARD };
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut group, mut len) = (0u32, 0);
+    let mut rest = text.iter();
+    for &byte in rest.by_ref() {
+        if byte == b'=' {
+            break;
+        }
+        match alphabet.iter().position(|&x| x == byte) {
+            Some(value) => {
+                group = group << 6 | value as u32;
+                len += 1;
+                if len == 4 {
+                    out.extend_from_slice(&group.to_be_bytes()[1..]);
+                    (group, len) = (0, 0);
+                }
+            }
+            None if byte.is_ascii_whitespace() || ignore_invalid => {}
+            None => bail!("Invalid base64 data"),
+        }
+    }
+    let mut padding = usize::from(rest.len() < text.len());
+    for &byte in rest {
+        match byte {
+            b'=' => padding += 1,
+            x if x.is_ascii_whitespace() || ignore_invalid => {}
+            _ => bail!("Invalid base64 data"),
+        }
+    }
+    let padded = len + padding >= 4 || url;
+    match len {
+        2 if padded => out.push((group >> 4) as u8),
+        3 if padded => out.extend_from_slice(&(group >> 2).to_be_bytes()[2..]),
+        0 => {}
+        _ => bail!("Invalid base64 data"),
+    }
+    Ok(out)
+}
+
+/// Base64-encode STRING and return the result. The lines of the result are
+/// broken every 76 characters, unless NO-LINE-BREAK is non-nil. STRING can
+/// only hold bytes.
+#[defun]
+fn base64_encode_string(string: &LispString, no_line_break: Option<()>) -> Result<String> {
+    let bytes = string_bytes(string)?;
+    Ok(encode(&bytes, false, true, no_line_break.is_none()))
+}
+
+/// Base64url-encode STRING and return the result. The result is not padded
+/// with `=` if NO-PAD is non-nil. STRING can only hold bytes.
+#[defun]
+fn base64url_encode_string(string: &LispString, no_pad: Option<()>) -> Result<String> {
+    let bytes = string_bytes(string)?;
+    Ok(encode(&bytes, true, no_pad.is_none(), false))
+}
+
+/// Base64-decode STRING and return the result as a unibyte string. STRING is
+/// in the base64url alphabet if BASE64URL is non-nil, and then its padding
+/// is optional. Whitespace is ignored, and so are other characters that are
+/// not in the alphabet if IGNORE-INVALID is non-nil.
+#[defun]
+fn base64_decode_string<'ob>(
+    string: &LispString,
+    base64url: Option<()>,
+    ignore_invalid: Option<()>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let bytes = decode(string, base64url.is_some(), ignore_invalid.is_some())?;
+    Ok(cx.add(bytes))
+}
+
+/// Base64-encode the region between START and END, replacing it with the
+/// encoding, and return its length. The lines of the encoding are broken
+/// every 76 characters, unless NO-LINE-BREAK is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+fn base64_encode_region<'ob>(
+    start: usize,
+    end: usize,
+    no_line_break: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end, text) = region_text(start, end, env, cx)?;
+    let encoded = encode(&text_bytes(&text)?, false, true, no_line_break.is_none());
+    replace_region((beg, end), &encoded, None, env, cx)
+}
+
+/// Base64url-encode the region between START and END, replacing it with the
+/// encoding, and return its length. The encoding is not padded with `=` if
+/// NO-PAD is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+fn base64url_encode_region<'ob>(
+    start: usize,
+    end: usize,
+    no_pad: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end, text) = region_text(start, end, env, cx)?;
+    let encoded = encode(&text_bytes(&text)?, true, no_pad.is_none(), false);
+    replace_region((beg, end), &encoded, None, env, cx)
+}
+
+/// Base64-decode the region between START and END, replacing it with the
+/// decoded bytes as the characters with the same code, and return their
+/// number. BASE64URL and IGNORE-INVALID are as in `base64-decode-string`.
+#[cfg(feature = "buffers")]
+#[defun]
+fn base64_decode_region<'ob>(
+    start: usize,
+    end: usize,
+    base64url: Option<()>,
+    ignore_invalid: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end, text) = region_text(start, end, env, cx)?;
+    let bytes = decode(
+        text.as_bytes(),
+        base64url.is_some(),
+        ignore_invalid.is_some(),
+    )?;
+    let decoded: String = bytes.into_iter().map(char::from).collect();
+    replace_region((beg, end), &decoded, None, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b"", false, true, true), "");
+        assert_eq!(encode(b"f", false, true, true), "Zg==");
+        assert_eq!(encode(b"fo", false, true, true), "Zm8=");
+        assert_eq!(encode(b"foo", false, true, true), "Zm9v");
+        assert_eq!(encode(b"foob", true, false, true), "Zm9vYg");
+        assert_eq!(encode(&[0xfb, 0xff], false, true, true), "+/8=");
+        assert_eq!(encode(&[0xfb, 0xff], true, true, true), "-_8=");
+        let long = encode(&[0; 60], false, true, true);
+        assert_eq!(long.lines().map(str::len).collect::<Vec<_>>(), [76, 4]);
+        assert!(!encode(&[0; 60], false, true, false).contains('\n'));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"Zm9vYg==", false, false).unwrap(), b"foob");
+        assert_eq!(decode(b"Zm9v\nYmE=", false, false).unwrap(), b"fooba");
+        assert!(decode(b"Zm9vYg", false, false).is_err());
+        assert_eq!(decode(b"Zm9vYg", true, false).unwrap(), b"foob");
+        assert_eq!(decode(b"-_8", true, false).unwrap(), [0xfb, 0xff]);
+        assert!(decode(b"-_8=", false, false).is_err());
+        assert!(decode(b"Zm9v!", false, false).is_err());
+        assert_eq!(decode(b"Zm!9v", false, true).unwrap(), b"foo");
+        assert!(decode(b"Z", false, false).is_err());
+        assert!(decode(b"Zm9vYg==x", false, false).is_err());
+    }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_base64_region() {
+        use crate::core::{env::Env, gc::RootSet};
+        use crate::{interpreter, reader, root};
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(progn
+                      (set-buffer (get-buffer-create \"base64\"))
+                      (insert \"<foo!>\")
+                      (list (base64-encode-region 2 6)
+                            (buffer-string)
+                            (point)
+                            (base64-decode-region 2 10)
+                            (equal (buffer-string) \"<foo!>\")
+                            (base64url-encode-region 2 6 t)
+                            (buffer-string)
+                            (condition-case nil (base64-decode-region 1 3) (error 'invalid))
+                            (base64-encode-string (unibyte-string 255))
+                            (condition-case nil (base64-encode-string \"é\") (error 'multibyte))
+                            (condition-case nil (base64-encode-string \"Ā\") (error 'multibyte))
+                            (progn (insert \"ÿ\")
+                                   (condition-case nil (base64-encode-region 1 (point-max)) (error 'multibyte)))
+                            (equal (base64-decode-string \"_w\" t) (unibyte-string 255))))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let expect = "(8 \"<Zm9vIQ==>\" 11 4 t 6 \"<Zm9vIQ>\" invalid \"/w==\" multibyte multibyte multibyte t)";
+        assert_eq!(result.to_string(), expect);
+    }
+}
@@ -0,0 +1,120 @@
+//! Benchmarking functions.
+use crate::core::env::Env;
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{Function, Gc, GcObj, Object};
+use crate::root;
+use anyhow::Result;
+use fn_macros::defun;
+use std::time::{Duration, Instant};
+
+/// How many times a benchmark is run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Repetitions {
+    Count(i64),
+    /// Repeat until at least this many seconds have passed
+    Seconds(f64),
+}
+
+impl TryFrom<GcObj<'_>> for Repetitions {
+    type Error = TypeError;
+
+    fn try_from(obj: GcObj) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            Object::NIL => Ok(Repetitions::Count(1)),
+            Object::Int(x) => Ok(Repetitions::Count(x)),
+            Object::Float(x) => Ok(Repetitions::Seconds(**x)),
+            _ => Err(TypeError::new(Type::Number, obj)),
+        }
+    }
+}
+
+/// Run `body` according to `repetitions` and return
+/// `(ELAPSED GC-COUNT GC-ELAPSED)`. When repetitions are given in seconds,
+/// the number of runs is added to the front of the list.
+pub(crate) fn run<'ob, E>(
+    repetitions: Repetitions,
+    cx: &'ob mut Context,
+    mut body: impl FnMut(&mut Context) -> Result<(), E>,
+) -> Result<GcObj<'ob>, E> {
+    let gc_count = cx.gc_count();
+    let gc_time = cx.gc_time();
+    let start = Instant::now();
+    let runs = match repetitions {
+        Repetitions::Count(count) => {
+            for _ in 0..count {
+                body(cx)?;
+            }
+            None
+        }
+        Repetitions::Seconds(secs) => {
+            let limit = Duration::from_secs_f64(secs.max(0.0));
+            let mut runs: i64 = 0;
+            while runs == 0 || start.elapsed() < limit {
+                body(cx)?;
+                runs += 1;
+            }
+            Some(runs)
+        }
+    };
+    let elapsed = start.elapsed().as_secs_f64();
+    let gc_count = (cx.gc_count() - gc_count) as i64;
+    let gc_time = cx.gc_time().saturating_sub(gc_time).as_secs_f64();
+    let results = list![elapsed, gc_count, gc_time; cx];
+    Ok(match runs {
+        Some(runs) => cons!(runs, results; cx),
+        None => results,
+    })
+}
+
+#[defun]
+fn benchmark_call<'ob>(
+    func: &Rt<Gc<Function>>,
+    repetitions: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let repetitions = match repetitions {
+        Some(x) => x.bind(cx).try_into()?,
+        None => Repetitions::Count(1),
+    };
+    run(repetitions, cx, |cx| {
+        root!(args, Vec::new(), cx);
+        func.call(args, env, cx, None)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader};
+
+    fn benchmark<'ob>(form: &str, cx: &'ob mut Context) -> Vec<GcObj<'ob>> {
+        root!(env, Env::default(), cx);
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = rebind!(interpreter::eval(obj, None, env, cx).unwrap(), cx);
+        result.as_list().unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_benchmark_run() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let result = benchmark("(benchmark-run 3 (funcall #'(lambda () (cons 1 2))))", cx);
+        assert_eq!(result.len(), 3);
+        assert!(matches!(result[0].untag(), Object::Float(x) if **x >= 0.0));
+        // the collector always runs in tests when a closure is called
+        assert!(matches!(result[1].untag(), Object::Int(x) if x >= 3));
+        assert!(matches!(result[2].untag(), Object::Float(_)));
+
+        let result = benchmark("(benchmark-run (+ 1 2))", cx);
+        assert_eq!(result.len(), 3);
+
+        let result = benchmark("(benchmark-call #'(lambda () (+ 1 2)) 0.001)", cx);
+        assert_eq!(result.len(), 4);
+        assert!(matches!(result[0].untag(), Object::Int(x) if x >= 1));
+    }
+}
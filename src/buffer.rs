@@ -1,14 +1,1832 @@
-use crate::core::object::GcObj;
+#![cfg(feature = "buffers")]
+use crate::core::env::{sym, Env, Symbol};
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, qtrue, Buffer, Function, Gc, GcObj, Object, Overlay, TagType};
+use crate::fns::slice_into_list;
+use crate::root;
+use crate::textprop::InsertProps;
+use anyhow::{anyhow, bail, ensure, Result};
 use fn_macros::defun;
+use lazy_static::lazy_static;
+use std::cmp::Reverse;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// All live buffers with their names, in the order of `buffer-list`.
+    /// `*scratch*` always exists at startup.
+    static ref BUFFERS: Mutex<Vec<(String, &'static Buffer)>> =
+        Mutex::new(vec![(SCRATCH.to_owned(), Buffer::create(SCRATCH))]);
+}
+
+const SCRATCH: &str = "*scratch*";
+
+/// Variables that have a separate value in every buffer.
+const PER_BUFFER_VARS: [Symbol<'static>; 5] = [
+    sym::BUFFER_UNDO_LIST,
+    sym::TAB_WIDTH,
+    sym::BUFFER_FILE_NAME,
+    sym::BUFFER_AUTO_SAVE_FILE_NAME,
+    sym::BUFFER_FILE_CODING_SYSTEM,
+];
+
+/// Whether VAR has a separate value in every buffer.
+pub(crate) fn is_per_buffer_var(var: Symbol) -> bool {
+    PER_BUFFER_VARS.contains(&var)
+}
+
+/// The value of the per-buffer variable VAR in a buffer that has not been
+/// current yet. Undo is disabled in buffers whose names start with a space.
+fn initial_buffer_var<'ob>(buffer: &Buffer, var: Symbol) -> GcObj<'ob> {
+    let internal = buffer.lock().is_ok_and(|x| x.name().starts_with(' '));
+    match var {
+        sym::BUFFER_UNDO_LIST if internal => qtrue(),
+        sym::TAB_WIDTH => 8.into(),
+        sym::BUFFER_FILE_CODING_SYSTEM => sym::UTF_8_UNIX.into(),
+        _ => nil(),
+    }
+}
+
+/// The buffer whose value of the per-buffer variable VAR is used by BUFFER.
+/// Indirect buffers share their undo list with their base buffer.
+fn var_owner(buffer: &'static Buffer, var: Symbol) -> &'static Buffer {
+    match var {
+        sym::BUFFER_UNDO_LIST => buffer.text_owner(),
+        _ => buffer,
+    }
+}
+
+/// The value of the per-buffer variable VAR in BUFFER.
+pub(crate) fn buffer_var<'ob>(
+    buffer: &'static Buffer,
+    var: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let owner = var_owner(buffer, var);
+    if var_owner(get_current_buffer(env), var) == owner {
+        return env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+    }
+    let Some(vars) = env.buffer_vars.get(owner.tag()) else {return initial_buffer_var(owner, var)};
+    let value = vars.iter().find(|x| x.0 == var).map(|x| x.1.bind(cx));
+    value.unwrap_or_else(|| initial_buffer_var(owner, var))
+}
+
+/// Set the per-buffer variable VAR in BUFFER to VALUE.
+pub(crate) fn set_buffer_var(
+    buffer: &'static Buffer,
+    var: Symbol,
+    value: GcObj,
+    env: &mut Rt<Env>,
+) {
+    let owner = var_owner(buffer, var);
+    if var_owner(get_current_buffer(env), var) == owner {
+        env.vars.insert(var, value);
+    } else {
+        save_buffer_var(owner, var, value, env);
+    }
+}
+
+/// Save the value of VAR for OWNER while it is not current.
+fn save_buffer_var(owner: &'static Buffer, var: Symbol, value: GcObj, env: &mut Rt<Env>) {
+    match env.buffer_vars.get_mut(owner.tag()) {
+        Some(vars) => match vars.iter_mut().find(|x| x.0 == var) {
+            Some(x) => x.1.set(value),
+            None => vars.push((var, value)),
+        },
+        None => {
+            env.buffer_vars.insert(owner.tag(), vec![(var, value)]);
+        }
+    }
+}
+
+/// Make BUFFER the current buffer. The per-buffer variables of the previous
+/// buffer are saved and those of BUFFER are swapped in.
+pub(crate) fn set_current_buffer(buffer: &'static Buffer, env: &mut Rt<Env>, cx: &Context) {
+    let old = get_current_buffer(env);
+    if old == buffer {
+        return;
+    }
+    for var in PER_BUFFER_VARS {
+        let (old_owner, owner) = (var_owner(old, var), var_owner(buffer, var));
+        if old_owner == owner {
+            continue;
+        }
+        let saved = env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+        let value = buffer_var(buffer, var, env, cx);
+        save_buffer_var(old_owner, var, saved, env);
+        if let Some(vars) = env.buffer_vars.get_mut(owner.tag()) {
+            if let Some(idx) = vars.iter().position(|x| x.0 == var) {
+                vars.swap_remove(idx);
+            }
+        }
+        env.vars.insert(var, value);
+    }
+    env.current_buffer = Some(buffer);
+}
+
+/// Return the current buffer, selecting `*scratch*` if there is none.
+pub(crate) fn get_current_buffer(env: &mut Rt<Env>) -> &'static Buffer {
+    if let Some(buffer) = env.current_buffer {
+        return buffer;
+    }
+    let buffer = get_buffer_create_internal(SCRATCH);
+    env.current_buffer = Some(buffer);
+    buffer
+}
+
+/// Make BUFFER current again at the end of a `save-current-buffer`. If it was
+/// killed the current buffer is kept instead.
+pub(crate) fn restore_current_buffer(buffer: &'static Buffer, env: &mut Rt<Env>, cx: &Context) {
+    if buffer.is_live() {
+        set_current_buffer(buffer, env, cx);
+    } else if !get_current_buffer(env).is_live() {
+        set_current_buffer(other_buffer(Some(buffer), None), env, cx);
+    }
+}
+
+fn get_buffer_create_internal(name: &str) -> &'static Buffer {
+    let mut buffers = BUFFERS.lock().unwrap();
+    if let Some((_, buffer)) = buffers.iter().find(|x| x.0 == name) {
+        return buffer;
+    }
+    let buffer = Buffer::create(name);
+    buffers.push((name.to_owned(), buffer));
+    buffer
+}
+
+/// All live buffers, in the order of `buffer-list`.
+pub(crate) fn live_buffers() -> Vec<&'static Buffer> {
+    BUFFERS.lock().unwrap().iter().map(|x| x.1).collect()
+}
+
+fn get_buffer_internal(name: &str) -> Option<&'static Buffer> {
+    let buffers = BUFFERS.lock().unwrap();
+    buffers.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// Resolve BUFFER-OR-NAME to a live buffer.
+pub(crate) fn get_live_buffer(buffer_or_name: GcObj) -> Result<&'static Buffer> {
+    match buffer_or_name.untag() {
+        Object::Buffer(buffer) => {
+            ensure!(buffer.is_live(), "Selecting deleted buffer");
+            Ok(buffer)
+        }
+        Object::String(name) => {
+            let name: &str = name.try_into()?;
+            get_buffer_internal(name).ok_or_else(|| anyhow!("No such buffer {name}"))
+        }
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// A name based on `name` that no buffer has, or `ignore`.
+fn unique_buffer_name(buffers: &[(String, &Buffer)], name: &str, ignore: Option<&str>) -> String {
+    let taken = |name: &str| ignore != Some(name) && buffers.iter().any(|x| x.0 == name);
+    if !taken(name) {
+        return name.to_owned();
+    }
+    let mut i = 2;
+    loop {
+        let candidate = format!("{name}<{i}>");
+        if !taken(&candidate) {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+#[defun]
+pub(crate) fn get_buffer_create(
+    buffer_or_name: GcObj,
+    _inhibit_buffer_hooks: Option<()>,
+) -> Result<&'static Buffer> {
+    match buffer_or_name.untag() {
+        Object::Buffer(buffer) => Ok(buffer),
+        _ => {
+            let name: &str = buffer_or_name.try_into()?;
+            ensure!(
+                !name.is_empty(),
+                "Empty string for buffer name is not allowed"
+            );
+            Ok(get_buffer_create_internal(name))
+        }
+    }
+}
+
+/// Return a string based on NAME that is not the name of any buffer, by
+/// appending `<2>`, `<3>`, and so on. NAME itself is used if it is free or
+/// equal to IGNORE.
+#[defun]
+fn generate_new_buffer_name(name: &str, ignore: Option<&str>) -> String {
+    unique_buffer_name(&BUFFERS.lock().unwrap(), name, ignore)
+}
+
+/// Create and return a buffer with a name based on NAME that is not already
+/// in use. See `generate-new-buffer-name`.
+#[defun]
+pub(crate) fn generate_new_buffer(
+    name: &str,
+    _inhibit_buffer_hooks: Option<()>,
+) -> &'static Buffer {
+    let mut buffers = BUFFERS.lock().unwrap();
+    let name = unique_buffer_name(&buffers, name, None);
+    let buffer = Buffer::create(&name);
+    buffers.push((name, buffer));
+    buffer
+}
+
+#[defun]
+fn get_buffer(buffer_or_name: GcObj) -> Result<GcObj> {
+    match buffer_or_name.untag() {
+        Object::Buffer(_) => Ok(buffer_or_name),
+        _ => Ok(
+            get_buffer_internal(buffer_or_name.try_into()?).map_or_else(nil, |x| x.tag().into())
+        ),
+    }
+}
+
+#[defun]
+fn current_buffer(env: &mut Rt<Env>) -> &'static Buffer {
+    get_current_buffer(env)
+}
+
+#[defun]
+fn buffer_name<'ob>(
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    // killed buffers have no name
+    buffer.lock().map_or_else(|_| nil(), |x| cx.add(x.name()))
+}
+
+/// Return the name of the file BUFFER is visiting, or nil if it is not
+/// visiting a file. BUFFER defaults to the current buffer.
+#[defun]
+fn buffer_file_name<'ob>(
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    buffer_var(buffer, sym::BUFFER_FILE_NAME, env, cx)
+}
+
+#[defun]
+pub(crate) fn set_buffer(
+    buffer_or_name: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Buffer> {
+    let buffer = get_live_buffer(buffer_or_name)?;
+    buffer.lock()?;
+    set_current_buffer(buffer, env, cx);
+    Ok(buffer)
+}
+
+/// Create an indirect buffer named NAME that shares the text of BASE-BUFFER.
+/// Edits in either buffer are visible in both, but each has its own point,
+/// markers, overlays, and narrowing. If BASE-BUFFER is itself indirect, its
+/// base buffer is used instead. If CLONE is non-nil, the mark and overlays of
+/// the base buffer are copied to the new buffer.
+#[defun]
+fn make_indirect_buffer(
+    base_buffer: GcObj,
+    name: &str,
+    clone: Option<()>,
+    _inhibit_buffer_hooks: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Buffer> {
+    let base = get_live_buffer(base_buffer)?.text_owner();
+    ensure!(
+        !name.is_empty(),
+        "Empty string for buffer name is not allowed"
+    );
+    let mut buffers = BUFFERS.lock().unwrap();
+    ensure!(
+        buffers.iter().all(|x| x.0 != name),
+        "Buffer name `{name}' is in use"
+    );
+    let buffer = Buffer::create_indirect(base, name, clone.is_some())?;
+    buffers.push((name.to_owned(), buffer));
+    drop(buffers);
+    if clone.is_some() {
+        let overlays: Vec<_> = base.lock()?.overlays().collect();
+        for (overlay, start, end) in overlays {
+            let copy = Overlay::create(overlay.front_advance, overlay.rear_advance, cx);
+            buffer.lock()?.add_overlay(copy, start, end);
+            copy.set_buffer(Some(buffer));
+            let props: Vec<_> = match env.overlay_props.get(overlay.tag()) {
+                Some(plist) => plist.iter().map(|x| (x.0.bind(cx), x.1.bind(cx))).collect(),
+                None => Vec::new(),
+            };
+            for (prop, value) in props {
+                env.set_overlay_prop(copy, prop, value);
+            }
+        }
+    }
+    Ok(buffer)
+}
+
+/// Return t if OBJECT is a buffer that has not been killed.
+#[defun]
+fn buffer_live_p(object: GcObj) -> bool {
+    matches!(object.untag(), Object::Buffer(buffer) if buffer.is_live())
+}
+
+/// Return a list of all live buffers. Buried buffers are at the end.
+#[defun]
+fn buffer_list<'ob>(_frame: Option<GcObj>, cx: &'ob Context) -> GcObj<'ob> {
+    let buffers: Vec<GcObj> = BUFFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|x| x.1.tag().into())
+        .collect();
+    slice_into_list(&buffers, None, cx)
+}
+
+/// Move BUFFER to the end of the buffer list.
+#[defun]
+fn bury_buffer_internal(buffer: &'static Buffer) -> bool {
+    let mut buffers = BUFFERS.lock().unwrap();
+    if let Some(idx) = buffers.iter().position(|x| x.1 == buffer) {
+        let entry = buffers.remove(idx);
+        buffers.push(entry);
+    }
+    false
+}
+
+/// Put BUFFER-OR-NAME, or the current buffer, at the end of the buffer list
+/// so that it is the least likely to be chosen by `other-buffer`.
+#[defun]
+fn bury_buffer(buffer_or_name: Option<GcObj>, env: &mut Rt<Env>) -> Result<bool> {
+    let buffer = match buffer_or_name {
+        Some(x) => get_live_buffer(x)?,
+        None => get_current_buffer(env),
+    };
+    Ok(bury_buffer_internal(buffer))
+}
+
+/// Return the most recent buffer in the buffer list other than BUFFER, whose
+/// name does not start with a space. `*scratch*` is created if there is none.
+#[defun]
+fn other_buffer(buffer: Option<&'static Buffer>, _visible_ok: Option<()>) -> &'static Buffer {
+    let other = BUFFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|x| Some(x.1) != buffer && !x.0.starts_with(' '))
+        .map(|x| x.1);
+    other.unwrap_or_else(|| get_buffer_create_internal(SCRATCH))
+}
+
+/// Change the name of the current buffer to NEWNAME. If another buffer has
+/// that name, it is an error unless UNIQUE is non-nil, in which case a free
+/// name is generated from NEWNAME. Return the new name.
+#[defun]
+fn rename_buffer<'ob>(
+    newname: &str,
+    unique: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    ensure!(
+        !newname.is_empty(),
+        "Empty string is invalid as a buffer name"
+    );
+    let buffer = get_current_buffer(env);
+    let mut buffers = BUFFERS.lock().unwrap();
+    let Some(idx) = buffers.iter().position(|x| x.1 == buffer) else {bail!("Selecting deleted buffer")};
+    let name = match buffers.iter().find(|x| x.0 == newname) {
+        Some(x) if x.1 != buffer => {
+            ensure!(unique.is_some(), "Buffer name `{newname}' is in use");
+            let current = buffers[idx].0.clone();
+            unique_buffer_name(&buffers, newname, Some(&current))
+        }
+        _ => newname.to_owned(),
+    };
+    buffer.lock()?.set_name(&name);
+    buffers[idx].0.clone_from(&name);
+    Ok(cx.add(name))
+}
+
+/// Kill BUFFER-OR-NAME, or the current buffer. The functions in
+/// `kill-buffer-query-functions` are called first with the buffer current,
+/// and if any returns nil, the buffer is not killed. Then `kill-buffer-hook`
+/// is run. Killing a base buffer also kills its indirect buffers. If the
+/// buffer was current, `other-buffer` becomes current. Return t if the buffer
+/// was killed.
+#[defun]
+fn kill_buffer(
+    buffer_or_name: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = match buffer_or_name.map(|x| x.get(cx)) {
+        None => get_current_buffer(env),
+        Some(Object::Buffer(buffer)) => buffer,
+        Some(Object::String(name)) => {
+            let name: &str = name.try_into()?;
+            get_buffer_internal(name).ok_or_else(|| anyhow!("No such buffer {name}"))?
+        }
+        Some(x) => bail!(TypeError::new(Type::String, x)),
+    };
+    kill_buffer_internal(buffer, env, cx)
+}
+
+fn kill_buffer_internal(
+    buffer: &'static Buffer,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    if !buffer.is_live() {
+        return Ok(false);
+    }
+    if !run_kill_hooks(buffer, env, cx)? || !buffer.is_live() {
+        return Ok(false);
+    }
+    crate::filelock::unlock_buffer_file(buffer, env, cx)?;
+    if buffer.base().is_none() {
+        let indirect: Vec<_> = BUFFERS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.1.base() == Some(buffer))
+            .map(|x| x.1)
+            .collect();
+        for indirect in indirect {
+            kill_buffer_internal(indirect, env, cx)?;
+        }
+    }
+    if get_current_buffer(env) == buffer {
+        set_current_buffer(other_buffer(Some(buffer), None), env, cx);
+    }
+    BUFFERS.lock().unwrap().retain(|x| x.1 != buffer);
+    for overlay in buffer.lock()?.kill() {
+        overlay.set_buffer(None);
+        env.overlay_props.remove(overlay.tag());
+    }
+    env.buffer_vars.remove(buffer.tag());
+    env.syntax_tables.remove(buffer.tag());
+    env.case_tables.remove(buffer.tag());
+    env.category_tables.remove(buffer.tag());
+    if buffer.base().is_none() {
+        env.text_props.remove(buffer.tag());
+    }
+    Ok(true)
+}
+
+/// Run `kill-buffer-query-functions` and then `kill-buffer-hook` with BUFFER
+/// current. Returns false if a query function refused the kill.
+fn run_kill_hooks(buffer: &'static Buffer, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let current = get_current_buffer(env);
+    set_current_buffer(buffer, env, cx);
+    let result = run_kill_hooks_in_current(env, cx);
+    if current.is_live() {
+        set_current_buffer(current, env, cx);
+    }
+    result
+}
+
+fn run_kill_hooks_in_current(env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    root!(args, Vec::new(), cx);
+    let hook: GcObj = sym::KILL_BUFFER_QUERY_FUNCTIONS.into();
+    root!(hook, cx);
+    if !crate::eval::run_hook_with_args_until_failure(hook, args, env, cx)? {
+        return Ok(false);
+    }
+    let hook: GcObj = sym::KILL_BUFFER_HOOK.into();
+    root!(hook, cx);
+    crate::eval::run_hook_with_args(hook, args, env, cx)?;
+    Ok(true)
+}
+
+/// Return the base buffer of BUFFER if it is an indirect buffer, or nil
+/// otherwise.
+#[defun]
+fn buffer_base_buffer<'ob>(buffer: Option<&'static Buffer>, env: &mut Rt<Env>) -> GcObj<'ob> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    buffer.base().map_or_else(nil, |x| x.tag().into())
+}
+
+/// Return t if BUFFER has been modified since it was last marked unmodified.
+#[defun]
+fn buffer_modified_p(buffer: Option<&'static Buffer>, env: &mut Rt<Env>) -> Result<bool> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    Ok(buffer.lock()?.is_modified())
+}
+
+/// Mark the current buffer as modified if FLAG is non-nil, or as unmodified
+/// otherwise.
+#[defun]
+fn set_buffer_modified_p<'ob>(
+    flag: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    restore_buffer_modified_p(flag, env, cx)
+}
+
+/// Like `set-buffer-modified-p`, but without updating the display. The
+/// visited file is locked when the buffer becomes modified and unlocked when
+/// it becomes unmodified.
+#[defun]
+fn restore_buffer_modified_p<'ob>(
+    flag: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let buffer = get_current_buffer(env);
+    let modified = !flag.bind(cx).nil();
+    match modified {
+        true => crate::filelock::lock_buffer_file(buffer, env, cx)?,
+        false => crate::filelock::unlock_buffer_file(buffer, env, cx)?,
+    }
+    buffer.lock()?.set_modified(modified);
+    Ok(flag.bind(cx))
+}
+
+/// Return the tick counter of BUFFER, which is incremented by every change to
+/// its text or text properties.
+#[defun]
+fn buffer_modified_tick(buffer: Option<&'static Buffer>, env: &mut Rt<Env>) -> Result<usize> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    Ok(buffer.lock()?.modified_tick())
+}
+
+/// Return the value of `buffer-modified-tick` at the last change to the text
+/// of BUFFER. Changes to text properties are not counted.
+#[defun]
+fn buffer_chars_modified_tick(buffer: Option<&'static Buffer>, env: &mut Rt<Env>) -> Result<usize> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    Ok(buffer.lock()?.chars_modified_tick())
+}
+
+/// Insert `text` at point in the current buffer, running the change hooks and
+/// overlay hooks around the change.
+pub(crate) fn insert_text(text: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    insert_text_with(text, InsertProps::None, env, cx)
+}
+
+/// Insert `text` like [`insert_text`], with text properties from `props`.
+pub(crate) fn insert_text_with(
+    text: &str,
+    props: InsertProps,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    insert_text_at_point(text, props, false, env, cx)
+}
+
+/// Insert `text` like [`insert_text_with`], but before the markers at point,
+/// so that they all end up after the text.
+pub(crate) fn insert_text_before_markers(
+    text: &str,
+    props: InsertProps,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    insert_text_at_point(text, props, true, env, cx)
+}
+
+fn insert_text_at_point(
+    text: &str,
+    props: InsertProps,
+    before_markers: bool,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    let point = buffer.lock()?.point();
+    signal_before_change(buffer, point, point, env, cx)?;
+    let hooks = overlay_hooks(buffer, point, point, true, env, cx)?;
+    root!(hooks, move(hooks), cx);
+    run_overlay_hooks(hooks, false, (point, point), None, env, cx)?;
+    // the hooks can move point
+    let point = buffer.lock()?.point();
+    let end = point + text.chars().count();
+    crate::undo::record_insert(buffer, point, end - point, env, cx)?;
+    if before_markers {
+        buffer.lock()?.insert_before_markers(text);
+    } else {
+        buffer.lock()?.insert(text);
+    }
+    crate::textprop::text_inserted(buffer, point, end - point, props, env, cx)?;
+    signal_after_change(point, end, 0, env, cx)?;
+    run_overlay_hooks(hooks, true, (point, end), Some(0), env, cx)
+}
+
+/// Delete the text between `beg` and `end` in the current buffer, running the
+/// change hooks and overlay hooks around the change. Overlays that become
+/// empty are deleted if they have the `evaporate` property.
+pub(crate) fn delete_text(
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    signal_before_change(buffer, beg, end, env, cx)?;
+    let hooks = overlay_hooks(buffer, beg, end, false, env, cx)?;
+    root!(hooks, move(hooks), cx);
+    run_overlay_hooks(hooks, false, (beg, end), None, env, cx)?;
+    let text = buffer.lock()?.text.substring(beg, end);
+    crate::undo::record_delete(buffer, beg, &text, env, cx)?;
+    buffer.lock()?.delete_region(beg, end);
+    crate::textprop::text_deleted(buffer, beg, end, env, cx);
+    let empty: Vec<_> = buffer
+        .lock()?
+        .overlays()
+        .filter(|x| x.1 == beg && x.2 == beg)
+        .map(|x| x.0)
+        .collect();
+    for overlay in empty {
+        if !overlay_prop(overlay, sym::EVAPORATE, env, cx).nil() {
+            delete_overlay(overlay, env);
+        }
+    }
+    signal_after_change(beg, beg, end - beg, env, cx)?;
+    run_overlay_hooks(hooks, true, (beg, beg), Some(end - beg), env, cx)
+}
+
+/// Swap the text between `beg1` and `end1` in the current buffer with the
+/// text between `beg2` and `end2`, where the first region is before the
+/// second, running the change hooks and overlay hooks around the change.
+pub(crate) fn transpose_text(
+    (beg1, end1): (usize, usize),
+    (beg2, end2): (usize, usize),
+    leave_markers: bool,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    signal_before_change(buffer, beg1, end2, env, cx)?;
+    let hooks = overlay_hooks(buffer, beg1, end2, false, env, cx)?;
+    root!(hooks, move(hooks), cx);
+    run_overlay_hooks(hooks, false, (beg1, end2), None, env, cx)?;
+    let text = buffer.lock()?.text.substring(beg1, end2);
+    crate::undo::record_delete(buffer, beg1, &text, env, cx)?;
+    crate::undo::record_insert(buffer, beg1, end2 - beg1, env, cx)?;
+    let regions = ((beg1, end1), (beg2, end2));
+    buffer
+        .lock()?
+        .transpose_regions(regions.0, regions.1, leave_markers);
+    crate::textprop::text_transposed(buffer, regions.0, regions.1, env, cx);
+    signal_after_change(beg1, end2, end2 - beg1, env, cx)?;
+    run_overlay_hooks(hooks, true, (beg1, end2), Some(end2 - beg1), env, cx)
+}
+
+/// Replace the characters from `beg` in the current buffer with `text`, which
+/// is as long as the text it replaces, running the change hooks and overlay
+/// hooks around the change. Markers and text properties are kept.
+pub(crate) fn replace_text(
+    beg: usize,
+    text: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    let end = beg + text.chars().count();
+    signal_before_change(buffer, beg, end, env, cx)?;
+    let hooks = overlay_hooks(buffer, beg, end, false, env, cx)?;
+    root!(hooks, move(hooks), cx);
+    run_overlay_hooks(hooks, false, (beg, end), None, env, cx)?;
+    let old = buffer.lock()?.text.substring(beg, end);
+    crate::undo::record_delete(buffer, beg, &old, env, cx)?;
+    crate::undo::record_insert(buffer, beg, end - beg, env, cx)?;
+    buffer.lock()?.replace_chars(beg, text);
+    signal_after_change(beg, end, end - beg, env, cx)?;
+    run_overlay_hooks(hooks, true, (beg, end), Some(end - beg), env, cx)
+}
+
+fn modification_hooks_inhibited(env: &Rt<Env>, cx: &Context) -> bool {
+    let inhibit = env.vars.get(sym::INHIBIT_MODIFICATION_HOOKS);
+    inhibit.is_some_and(|x| !x.bind(cx).nil())
+}
+
+/// Count the change for auto-saving, lock the file BUFFER visits and run
+/// `first-change-hook` if BUFFER is unmodified, and then run
+/// `before-change-functions` with the bounds of the text about to change.
+fn signal_before_change(
+    buffer: &'static Buffer,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    crate::autosave::record_change(env, cx)?;
+    crate::filelock::lock_buffer_file(buffer, env, cx)?;
+    if modification_hooks_inhibited(env, cx) {
+        return Ok(());
+    }
+    if !buffer.lock()?.is_modified() {
+        run_change_hook(sym::FIRST_CHANGE_HOOK, Vec::new(), env, cx)?;
+    }
+    let args = vec![(beg + 1).into(), (end + 1).into()];
+    run_change_hook(sym::BEFORE_CHANGE_FUNCTIONS, args, env, cx)
+}
+
+/// Run `after-change-functions` with the bounds of the changed text and the
+/// length of the text it replaced.
+fn signal_after_change(
+    beg: usize,
+    end: usize,
+    old_len: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if modification_hooks_inhibited(env, cx) {
+        return Ok(());
+    }
+    if combining_after_changes(env, cx)? {
+        let buffer = get_current_buffer(env);
+        let pending = *COMBINED_CHANGE.lock().unwrap();
+        if pending.is_some_and(|x| x.buffer != buffer) {
+            combine_after_change_execute(env, cx)?;
+        }
+        let tail = buffer.lock()?.text.len_chars() - end;
+        let change = (end - beg) as isize - old_len as isize;
+        let mut combined = COMBINED_CHANGE.lock().unwrap();
+        *combined = Some(match *combined {
+            Some(x) => CombinedChange {
+                beg: x.beg.min(beg),
+                tail: x.tail.min(tail),
+                change: x.change + change,
+                ..x
+            },
+            None => CombinedChange {
+                buffer,
+                beg,
+                tail,
+                change,
+            },
+        });
+        return Ok(());
+    }
+    let args = vec![(beg + 1).into(), (end + 1).into(), old_len.into()];
+    run_change_hook(sym::AFTER_CHANGE_FUNCTIONS, args, env, cx)
+}
+
+/// The changes whose `after-change-functions` were deferred by
+/// `combine-after-change-calls`, as one change covering all of them.
+#[derive(Clone, Copy)]
+struct CombinedChange {
+    buffer: &'static Buffer,
+    /// The start of the changed text
+    beg: usize,
+    /// The length of the unchanged text after the changed text
+    tail: usize,
+    /// The number of characters inserted minus the number deleted
+    change: isize,
+}
+
+static COMBINED_CHANGE: Mutex<Option<CombinedChange>> = Mutex::new(None);
+
+/// Whether `after-change-functions` should be deferred. Changes are only
+/// combined when `before-change-functions` would not see the changes
+/// separately, and when the buffer has no overlays whose hooks would.
+fn combining_after_changes(env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let is_set = |var| env.vars.get(var).is_some_and(|x| !x.bind(cx).nil());
+    if !is_set(sym::COMBINE_AFTER_CHANGE_CALLS) || is_set(sym::BEFORE_CHANGE_FUNCTIONS) {
+        return Ok(false);
+    }
+    Ok(get_current_buffer(env).lock()?.overlays().next().is_none())
+}
+
+/// Run `after-change-functions` once for the changes made by the body of
+/// `combine-after-change-calls`, with a region covering all of them.
+#[defun]
+pub(crate) fn combine_after_change_execute(env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let Some(change) = COMBINED_CHANGE.lock().unwrap().take() else {return Ok(false)};
+    let Ok(len) = change.buffer.lock().map(|x| x.text.len_chars()) else {return Ok(false)};
+    let (beg, end) = (change.beg, len - change.tail);
+    let old_len = (end - beg) as isize - change.change;
+    let depth = env.binding_depth();
+    env.bind_current_buffer();
+    set_current_buffer(change.buffer, env, cx);
+    env.varbind(sym::COMBINE_AFTER_CHANGE_CALLS, nil(), cx);
+    let result = signal_after_change(beg, end, old_len as usize, env, cx);
+    env.unbind_to(depth, cx);
+    result.map(|()| false)
+}
+
+/// Run HOOK with ARGS. Modification hooks are inhibited while it runs.
+fn run_change_hook(
+    hook: Symbol<'static>,
+    args: Vec<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let hook: GcObj = hook.into();
+    root!(hook, cx);
+    root!(args, move(args), cx);
+    env.varbind(sym::INHIBIT_MODIFICATION_HOOKS, qtrue(), cx);
+    let result = crate::eval::run_hook_with_args(hook, args, env, cx).map(|_| ());
+    env.unbind(1, cx);
+    result
+}
+
+/// Collect the overlay hooks to run for a change from `beg` to `end`, paired
+/// with their overlays. An insertion runs `insert-in-front-hooks` and
+/// `insert-behind-hooks` of the overlays it touches and `modification-hooks`
+/// of the ones it is inside. A deletion runs `modification-hooks` of the
+/// overlays it overlaps. Hooks of nested overlays run from the outermost in,
+/// in the order of [`overlay_precedence`].
+fn overlay_hooks<'ob>(
+    buffer: &'static Buffer,
+    beg: usize,
+    end: usize,
+    insertion: bool,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Vec<(GcObj<'ob>, GcObj<'ob>)>> {
+    if modification_hooks_inhibited(env, cx) {
+        return Ok(Vec::new());
+    }
+    let mut overlays: Vec<_> = buffer.lock()?.overlays().collect();
+    overlays.sort_by_cached_key(|x| overlay_precedence(*x, env, cx));
+    let mut hooks = Vec::new();
+    for (overlay, start, end_pos) in overlays {
+        let mut props = Vec::new();
+        if insertion {
+            if beg == start {
+                props.push(sym::INSERT_IN_FRONT_HOOKS);
+            }
+            if beg == end_pos {
+                props.push(sym::INSERT_BEHIND_HOOKS);
+            }
+            if start < beg && beg < end_pos {
+                props.push(sym::MODIFICATION_HOOKS);
+            }
+        } else if beg < end_pos && end > start {
+            props.push(sym::MODIFICATION_HOOKS);
+        }
+        for prop in props {
+            for hook in overlay_prop(overlay, prop, env, cx).as_list()? {
+                hooks.push((cx.add(overlay), hook?));
+            }
+        }
+    }
+    Ok(hooks)
+}
+
+/// Call each hook with its overlay as `(OVERLAY AFTER BEG END)`, and with the
+/// length of the replaced text after the change. Modification hooks are
+/// inhibited while they run.
+fn run_overlay_hooks(
+    hooks: &Rt<Vec<(GcObj<'static>, GcObj<'static>)>>,
+    after: bool,
+    (beg, end): (usize, usize),
+    old_len: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    env.varbind(sym::INHIBIT_MODIFICATION_HOOKS, qtrue(), cx);
+    let result = call_overlay_hooks(hooks, after, (beg, end), old_len, env, cx);
+    env.unbind(1, cx);
+    result
+}
+
+fn call_overlay_hooks(
+    hooks: &Rt<Vec<(GcObj<'static>, GcObj<'static>)>>,
+    after: bool,
+    (beg, end): (usize, usize),
+    old_len: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    for hook in hooks.iter() {
+        let after = if after { qtrue() } else { nil() };
+        let mut args = vec![hook.0.bind(cx), after, (beg + 1).into(), (end + 1).into()];
+        if let Some(len) = old_len {
+            args.push(len.into());
+        }
+        root!(args, move(args), cx);
+        let func: &Rt<Gc<Function>> = Rt::try_into(&hook.1)?;
+        func.call(args, env, cx, None)?;
+    }
+    Ok(())
+}
+
+/// The precedence of an overlay, lowest first. Overlays with a higher
+/// `priority` take precedence. With equal priorities, an overlay nested inside
+/// another takes precedence over it.
+fn overlay_precedence(
+    (overlay, start, end): (&'static Overlay, usize, usize),
+    env: &Rt<Env>,
+    cx: &Context,
+) -> (i64, i64, usize, Reverse<usize>) {
+    let int = |x: GcObj| match x.untag() {
+        Object::Int(x) => x,
+        _ => 0,
+    };
+    // the priority is either PRIMARY or (PRIMARY . SECONDARY)
+    let (primary, secondary) = match overlay_prop(overlay, sym::PRIORITY, env, cx).untag() {
+        Object::Int(x) => (x, 0),
+        Object::Cons(cons) => (int(cons.car()), int(cons.cdr())),
+        _ => (0, 0),
+    };
+    (primary, secondary, start, Reverse(end))
+}
+
+/// Get the value of PROP in OVERLAY, falling back to the properties of its
+/// `category` symbol.
+fn overlay_prop<'ob>(
+    overlay: &'static Overlay,
+    prop: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let Some(plist) = env.overlay_props.get(overlay.tag()) else {return nil()};
+    let lookup = |prop: Symbol| plist.iter().find(|x| x.0 == prop).map(|x| x.1.bind(cx));
+    if let Some(value) = lookup(prop) {
+        return value;
+    }
+    match lookup(sym::CATEGORY).map(Gc::untag) {
+        Some(Object::Symbol(category)) => crate::data::get(category, prop, env, cx),
+        _ => nil(),
+    }
+}
+
+/// The buffer and bounds of OVERLAY, or `None` if it was deleted.
+fn overlay_bounds(overlay: &Overlay) -> Option<(&'static Buffer, usize, usize)> {
+    let buffer = overlay.buffer()?;
+    let (start, end) = buffer.lock().ok()?.overlay_bounds(overlay)?;
+    Some((buffer, start, end))
+}
+
+/// Take OVERLAY out of its buffer, keeping its properties.
+fn detach_overlay(overlay: &Overlay) {
+    if let Some(buffer) = overlay.buffer() {
+        if let Ok(mut data) = buffer.lock() {
+            data.remove_overlay(overlay);
+        }
+        overlay.set_buffer(None);
+    }
+}
+
+/// Delete OVERLAY from its buffer and drop its properties, so that it is
+/// freed once it can't be reached.
+fn delete_overlay(overlay: &'static Overlay, env: &mut Rt<Env>) {
+    detach_overlay(overlay);
+    env.overlay_props.remove(overlay.tag());
+}
+
+/// Delete OVERLAY if it is empty and has the `evaporate` property.
+fn evaporate_overlay(overlay: &'static Overlay, env: &mut Rt<Env>, cx: &Context) {
+    let empty = overlay_bounds(overlay).is_some_and(|x| x.1 == x.2);
+    if empty && !overlay_prop(overlay, sym::EVAPORATE, env, cx).nil() {
+        delete_overlay(overlay, env);
+    }
+}
+
+#[defun]
+fn overlayp(object: GcObj) -> bool {
+    matches!(object.untag(), Object::Overlay(_))
+}
+
+/// Create an overlay from BEG to END in BUFFER. If FRONT-ADVANCE is non-nil,
+/// text inserted at the start is excluded from the overlay. If REAR-ADVANCE
+/// is non-nil, text inserted at the end is included in it.
+#[defun]
+fn make_overlay(
+    beg: usize,
+    end: usize,
+    buffer: Option<&'static Buffer>,
+    front_advance: Option<()>,
+    rear_advance: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Overlay> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    let overlay = Overlay::create(front_advance.is_some(), rear_advance.is_some(), cx);
+    buffer
+        .lock()?
+        .add_overlay(overlay, beg.saturating_sub(1), end.saturating_sub(1));
+    overlay.set_buffer(Some(buffer));
+    Ok(overlay)
+}
+
+#[defun]
+fn overlay_start<'ob>(overlay: &'static Overlay) -> GcObj<'ob> {
+    overlay_bounds(overlay).map_or_else(nil, |x| (x.1 + 1).into())
+}
 
 #[defun]
-fn set_buffer(buffer_or_name: GcObj) -> GcObj {
-    // TODO: implement
-    buffer_or_name
+fn overlay_end<'ob>(overlay: &'static Overlay) -> GcObj<'ob> {
+    overlay_bounds(overlay).map_or_else(nil, |x| (x.2 + 1).into())
 }
 
 #[defun]
-fn set_buffer_modified_p(flag: GcObj) -> GcObj {
-    // TODO: implement
-    flag
+fn overlay_buffer<'ob>(overlay: &'static Overlay) -> GcObj<'ob> {
+    overlay.buffer().map_or_else(nil, |x| x.tag().into())
+}
+
+/// Delete OVERLAY from its buffer and drop its properties. It can be added
+/// back with `move-overlay`.
+#[defun(name = "delete-overlay")]
+fn delete_overlay_lisp(overlay: &'static Overlay, env: &mut Rt<Env>) -> bool {
+    delete_overlay(overlay, env);
+    false
+}
+
+/// Set the bounds of OVERLAY to BEG and END, and move it to BUFFER if it is
+/// non-nil.
+#[defun]
+fn move_overlay(
+    overlay: &'static Overlay,
+    beg: usize,
+    end: usize,
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Overlay> {
+    let current = overlay.buffer();
+    let buffer = buffer
+        .or(current)
+        .unwrap_or_else(|| get_current_buffer(env));
+    let (beg, end) = (beg.saturating_sub(1), end.saturating_sub(1));
+    if current == Some(buffer) {
+        let mut data = buffer.lock()?;
+        let len = data.text.len_chars();
+        data.move_overlay(overlay, beg.min(len), end.min(len));
+    } else {
+        detach_overlay(overlay);
+        buffer.lock()?.add_overlay(overlay, beg, end);
+        overlay.set_buffer(Some(buffer));
+    }
+    evaporate_overlay(overlay, env, cx);
+    Ok(overlay)
+}
+
+#[defun]
+fn overlay_get<'ob>(
+    overlay: &'static Overlay,
+    prop: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    overlay_prop(overlay, prop, env, cx)
+}
+
+#[defun]
+fn overlay_put<'ob>(
+    overlay: &'static Overlay,
+    prop: Symbol,
+    value: GcObj<'ob>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> GcObj<'ob> {
+    env.set_overlay_prop(overlay, prop, value);
+    if prop == sym::EVAPORATE {
+        evaporate_overlay(overlay, env, cx);
+    }
+    value
+}
+
+#[defun]
+fn overlay_properties<'ob>(
+    overlay: &'static Overlay,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let Some(plist) = env.overlay_props.get(overlay.tag()) else {return nil()};
+    let plist: Vec<GcObj> = plist
+        .iter()
+        .flat_map(|x| [x.0.bind(cx).into(), x.1.bind(cx)])
+        .collect();
+    slice_into_list(&plist, None, cx)
+}
+
+/// Return the overlays that contain the character at POS. If SORTED is
+/// non-nil, the overlays are in decreasing order of precedence.
+#[defun]
+fn overlays_at<'ob>(
+    pos: usize,
+    sorted: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let pos = pos.saturating_sub(1);
+    let mut overlays: Vec<_> = get_current_buffer(env)
+        .lock()?
+        .overlays()
+        .filter(|x| x.1 <= pos && pos < x.2)
+        .collect();
+    if sorted.is_some() {
+        overlays.sort_by_cached_key(|x| Reverse(overlay_precedence(*x, env, cx)));
+    }
+    let overlays: Vec<GcObj> = overlays.into_iter().map(|x| cx.add(x.0)).collect();
+    Ok(slice_into_list(&overlays, None, cx))
+}
+
+/// Return the overlays that overlap the region from BEG to END. Empty
+/// overlays are included if they are at BEG, inside the region, or at END when
+/// END is the end of the accessible portion of the buffer.
+#[defun]
+fn overlays_in<'ob>(
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end) = (
+        beg.min(end).saturating_sub(1),
+        beg.max(end).saturating_sub(1),
+    );
+    let data = get_current_buffer(env).lock()?;
+    let zv = data.zv();
+    let overlays: Vec<GcObj> = data
+        .overlays()
+        .filter(|&(_, start, stop)| {
+            if start == stop {
+                (beg..end).contains(&start) || (start == end && end >= zv)
+            } else {
+                start < end && stop > beg
+            }
+        })
+        .map(|x| cx.add(x.0))
+        .collect();
+    Ok(slice_into_list(&overlays, None, cx))
+}
+
+defsym!(PRIORITY);
+defsym!(CATEGORY);
+defsym!(EVAPORATE);
+defsym!(MODIFICATION_HOOKS);
+defsym!(INSERT_IN_FRONT_HOOKS);
+defsym!(INSERT_BEHIND_HOOKS);
+defvar!(INHIBIT_MODIFICATION_HOOKS);
+defvar!(INHIBIT_READ_ONLY);
+defvar!(KILL_BUFFER_QUERY_FUNCTIONS);
+defvar!(KILL_BUFFER_HOOK);
+defvar!(BEFORE_CHANGE_FUNCTIONS);
+defvar!(AFTER_CHANGE_FUNCTIONS);
+defvar!(COMBINE_AFTER_CHANGE_CALLS);
+defvar!(FIRST_CHANGE_HOOK);
+defvar!(CASE_FOLD_SEARCH, true);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_point_and_mark() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"point-test\"))", env, cx);
+        assert_eq!(check("(buffer-name)", env, cx), "point-test");
+        get_current_buffer(env)
+            .lock()
+            .unwrap()
+            .insert("hello world");
+        assert_eq!(check("(point)", env, cx), 12);
+        assert_eq!(check("(point-min)", env, cx), 1);
+        assert_eq!(check("(point-max)", env, cx), 12);
+        assert_eq!(check("(goto-char 100)", env, cx), 100);
+        assert_eq!(check("(point)", env, cx), 12);
+        assert_eq!(check("(mark)", env, cx), nil());
+        check("(progn (goto-char 3) (push-mark 9 t))", env, cx);
+        assert_eq!(
+            check("(list (mark) (region-beginning) (region-end))", env, cx).to_string(),
+            "(9 3 9)"
+        );
+        check("(progn (push-mark nil t) (pop-mark))", env, cx);
+        assert_eq!(check("(mark)", env, cx), 9);
+        check("(set-mark nil)", env, cx);
+        assert_eq!(check("(mark)", env, cx), nil());
+    }
+
+    #[test]
+    fn test_save_excursion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"excursion-test\"))",
+            env,
+            cx,
+        );
+        let buffer = get_current_buffer(env);
+        buffer.lock().unwrap().insert("hello world");
+        check("(goto-char 7)", env, cx);
+        let form =
+            "(save-excursion (set-buffer (get-buffer-create \"excursion-other\")) (goto-char 1))";
+        check(form, env, cx);
+        assert_eq!(
+            check("(list (buffer-name) (point))", env, cx).to_string(),
+            "(\"excursion-test\" 7)"
+        );
+        // point is restored on error
+        let form =
+            "(condition-case nil (save-excursion (goto-char 1) (error \"fail\")) (error (point)))";
+        assert_eq!(check(form, env, cx), 7);
+    }
+
+    #[test]
+    fn test_save_current_buffer() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"scb-outer\"))", env, cx);
+        let form =
+            "(save-current-buffer (set-buffer (get-buffer-create \"scb-inner\")) (buffer-name))";
+        assert_eq!(check(form, env, cx), "scb-inner");
+        assert_eq!(check("(buffer-name)", env, cx), "scb-outer");
+        // restored on error
+        let form = "(condition-case nil
+                      (save-current-buffer (set-buffer \"scb-inner\") (error \"fail\"))
+                      (error (buffer-name)))";
+        assert_eq!(check(form, env, cx), "scb-outer");
+        // a killed buffer is not restored
+        let form = "(save-current-buffer (set-buffer \"scb-inner\") (kill-buffer \"scb-outer\"))";
+        check(form, env, cx);
+        assert_eq!(check("(buffer-name)", env, cx), "scb-inner");
+        let form = "(save-current-buffer
+                      (set-buffer (get-buffer-create \"scb-third\"))
+                      (kill-buffer \"scb-inner\")
+                      (kill-buffer))";
+        check(form, env, cx);
+        assert_eq!(check("(buffer-live-p (current-buffer))", env, cx), qtrue());
+        let form = "(progn (setq b (get-buffer-create \"scb-killed\")) (kill-buffer b)
+                      (condition-case nil (set-buffer b) (error 'dead)))";
+        assert_eq!(check(form, env, cx).to_string(), "dead");
+    }
+
+    #[test]
+    fn test_narrowing() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"narrow-test\"))", env, cx);
+        get_current_buffer(env)
+            .lock()
+            .unwrap()
+            .insert("hello world");
+        check("(narrow-to-region 9 3)", env, cx);
+        assert_eq!(
+            check(
+                "(list (point-min) (point-max) (point) (buffer-narrowed-p) (buffer-size))",
+                env,
+                cx
+            )
+            .to_string(),
+            "(3 9 9 t 11)"
+        );
+        let form = "(let ((x nil))
+                      (condition-case nil
+                          (handler-bind ((args-out-of-range #'(lambda (e) (setq x e))))
+                            (narrow-to-region 1 20))
+                        (error x)))";
+        assert_eq!(check(form, env, cx).to_string(), "(args-out-of-range 1 20)");
+        let form = "(progn (save-restriction (widen) (goto-char 1) (point-max)))";
+        assert_eq!(check(form, env, cx), 12);
+        assert_eq!(
+            check("(list (point-min) (point-max) (point))", env, cx).to_string(),
+            "(3 9 3)"
+        );
+        check("(widen)", env, cx);
+        assert_eq!(check("(buffer-narrowed-p)", env, cx), nil());
+
+        check("(internal--labeled-narrow-to-region 2 6 'test)", env, cx);
+        check("(progn (widen) (narrow-to-region 1 12))", env, cx);
+        assert_eq!(
+            check("(list (point-min) (point-max))", env, cx).to_string(),
+            "(2 6)"
+        );
+        check("(internal--labeled-widen 'test)", env, cx);
+        assert_eq!(check("(buffer-narrowed-p)", env, cx), nil());
+    }
+
+    #[test]
+    fn test_compare_and_hash() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(setq case-fold-search t)", env, cx);
+        check("(set-buffer (get-buffer-create \"cmp-b\"))", env, cx);
+        check("(insert \"hello there\")", env, cx);
+        check("(set-buffer (get-buffer-create \"cmp-a\"))", env, cx);
+        check("(insert \"Hello World\")", env, cx);
+        let compare = |args: &str, env: &mut Rt<Env>, cx: &mut Context| {
+            let form = format!("(compare-buffer-substrings {args})");
+            check(&form, env, cx).to_string()
+        };
+        assert_eq!(compare("nil nil nil \"cmp-b\" nil nil", env, cx), "7");
+        assert_eq!(compare("nil 1 6 \"cmp-b\" 1 6", env, cx), "0");
+        assert_eq!(compare("nil 1 6 \"cmp-b\" 1 3", env, cx), "3");
+        assert_eq!(compare("\"cmp-b\" 1 3 nil 1 6", env, cx), "-3");
+        assert_eq!(compare("nil 2 4 nil 1 3", env, cx), "-1");
+        check("(setq case-fold-search nil)", env, cx);
+        assert_eq!(compare("nil nil nil \"cmp-b\" nil nil", env, cx), "-1");
+
+        // the gap is in the middle of the text
+        check("(progn (goto-char 3) (insert \"XY\"))", env, cx);
+        let form = "(equal (secure-hash 'sha256 (current-buffer))
+                           (secure-hash 'sha256 \"HeXYllo World\"))";
+        assert_eq!(check(form, env, cx), sym::TRUE);
+        let form = "(equal (secure-hash 'md5 (current-buffer) 2 6)
+                           (secure-hash 'md5 \"HeXYllo World\" 1 5))";
+        assert_eq!(check(form, env, cx), sym::TRUE);
+        let form = "(equal (secure-hash 'md5 \"xxeXYl\" 2)
+                           (secure-hash 'md5 \"eXYlxx\" 0 -2))";
+        assert_eq!(check(form, env, cx), sym::TRUE);
+        let hash = check("(secure-hash 'sha1 \"abc\" nil nil t)", env, cx);
+        let expect = crate::digest::Algorithm::Sha1.digest([b"abc".as_slice()]);
+        assert!(matches!(hash.untag(), Object::String(x) if **x == *expect));
+        check("(narrow-to-region 1 3)", env, cx);
+        let form = "(equal (buffer-hash) (secure-hash 'sha1 \"HeXYllo World\"))";
+        assert_eq!(check(form, env, cx), sym::TRUE);
+    }
+
+    #[test]
+    fn test_byte_positions() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"bytes-test\"))", env, cx);
+        check("(insert \"aΘ\\nbΘ\")", env, cx);
+        let form = "(list (position-bytes 1) (position-bytes 3) (position-bytes 6)
+                          (position-bytes 7) (position-bytes 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 4 8 nil nil)");
+        let form = "(list (byte-to-position 2) (byte-to-position 3) (byte-to-position 4)
+                          (byte-to-position 8) (byte-to-position 9))";
+        assert_eq!(check(form, env, cx).to_string(), "(2 2 3 6 nil)");
+        let form = "(list (bufferpos-to-filepos 4) (bufferpos-to-filepos 5 'exact 'utf-8-dos)
+                          (bufferpos-to-filepos 5 nil 'iso-latin-1-unix))";
+        assert_eq!(check(form, env, cx).to_string(), "(4 6 4)");
+    }
+
+    #[test]
+    fn test_lines() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"lines-test\"))", env, cx);
+        check("(insert \"one\\ntwo\\n\\nfour\")", env, cx);
+        let form = "(list (forward-line -1) (point) (forward-line -5) (point)
+                          (forward-line 2) (point) (forward-line 3) (point) (forward-line 0) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(0 9 -3 1 0 9 1 14 0 10)");
+        let form =
+            "(list (count-lines 1 14) (count-lines 1 10) (count-lines 2 2) (count-lines 5 9))";
+        assert_eq!(check(form, env, cx).to_string(), "(4 3 0 1)");
+        check("(narrow-to-region 5 14)", env, cx);
+        let form =
+            "(list (line-number-at-pos 5) (line-number-at-pos 14) (line-number-at-pos 14 t))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 3 4)");
+        check("(goto-line 2 nil t)", env, cx);
+        assert_eq!(check("(point)", env, cx), 9);
+        check("(goto-line 2)", env, cx);
+        assert_eq!(
+            check("(list (point) (point-min))", env, cx).to_string(),
+            "(5 1)"
+        );
+    }
+
+    #[test]
+    fn test_overlays() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"overlay-test\"))", env, cx);
+        check("(insert \"hello world\")", env, cx);
+        check("(setq outer (make-overlay 1 12))", env, cx);
+        check("(setq inner (make-overlay 3 6 nil t nil))", env, cx);
+        check("(setq top (make-overlay 4 8))", env, cx);
+        assert_eq!(check("(overlayp inner)", env, cx), qtrue());
+        assert_eq!(
+            check("(list (overlay-start inner) (overlay-end inner))", env, cx).to_string(),
+            "(3 6)"
+        );
+        check("(overlay-put outer 'name 'outer)", env, cx);
+        check("(overlay-put inner 'name 'inner)", env, cx);
+        check("(overlay-put top 'name 'top)", env, cx);
+        // nested overlays take precedence unless the priority is higher
+        let form = "(mapcar #'(lambda (o) (overlay-get o 'name)) (overlays-at 5 t))";
+        assert_eq!(check(form, env, cx).to_string(), "(top inner outer)");
+        check("(overlay-put outer 'priority 10)", env, cx);
+        assert_eq!(check(form, env, cx).to_string(), "(outer top inner)");
+
+        // text inserted at the start of INNER is excluded
+        check("(progn (goto-char 3) (insert \"ab\"))", env, cx);
+        assert_eq!(
+            check("(list (overlay-start inner) (overlay-end inner))", env, cx).to_string(),
+            "(5 8)"
+        );
+
+        check("(overlay-put inner 'evaporate t)", env, cx);
+        check("(delete-region 4 9)", env, cx);
+        assert_eq!(check("(overlay-buffer inner)", env, cx), nil());
+        assert_eq!(check("(overlay-start inner)", env, cx), nil());
+        check("(move-overlay inner 2 3)", env, cx);
+        assert_eq!(check("(overlay-start inner)", env, cx), 2);
+        check("(overlay-put top 'face 'bold)", env, cx);
+        check("(delete-overlay top)", env, cx);
+        assert_eq!(check("(length (overlays-in 1 (point-max)))", env, cx), 2);
+        // a deleted overlay keeps nothing alive
+        assert_eq!(check("(overlay-properties top)", env, cx), nil());
+    }
+
+    #[test]
+    fn test_indirect_buffer() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"indirect-base\"))",
+            env,
+            cx,
+        );
+        check("(insert \"hello world\")", env, cx);
+        check("(put-text-property 1 6 'face 'bold)", env, cx);
+        check("(overlay-put (make-overlay 1 3) 'name 'base)", env, cx);
+        check("(setq base (current-buffer))", env, cx);
+        check(
+            "(setq ind (make-indirect-buffer base \"indirect\"))",
+            env,
+            cx,
+        );
+        check(
+            "(setq clone (make-indirect-buffer ind \"indirect-clone\" t))",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check("(eq (buffer-base-buffer clone) base)", env, cx),
+            qtrue()
+        );
+        assert_eq!(check("(buffer-base-buffer base)", env, cx), nil());
+
+        // edits are shared but point and narrowing are not
+        check("(set-buffer ind)", env, cx);
+        assert_eq!(check("(point)", env, cx), 12);
+        check("(progn (goto-char 1) (insert \"ab\"))", env, cx);
+        assert_eq!(check("(get-text-property 3 'face)", env, cx), sym!(bold));
+        check("(narrow-to-region 1 3)", env, cx);
+        assert_eq!(check("(point-max)", env, cx), 3);
+        check("(set-buffer base)", env, cx);
+        assert_eq!(
+            check("(list (point) (point-max))", env, cx).to_string(),
+            "(14 14)"
+        );
+        let text = get_current_buffer(env).lock().unwrap().text.to_string();
+        assert_eq!(text, "abhello world");
+        check("(set-buffer ind)", env, cx);
+        check("(widen)", env, cx);
+        check("(delete-region 1 3)", env, cx);
+        check("(set-buffer base)", env, cx);
+        assert_eq!(check("(point)", env, cx), 12);
+
+        // overlays are only copied to clones
+        assert_eq!(check("(length (overlays-in 1 12))", env, cx), 1);
+        check("(set-buffer ind)", env, cx);
+        assert_eq!(check("(overlays-in 1 12)", env, cx), nil());
+        check("(set-buffer clone)", env, cx);
+        let form = "(mapcar #'(lambda (o) (overlay-get o 'name)) (overlays-in 1 12))";
+        assert_eq!(check(form, env, cx).to_string(), "(base)");
+    }
+
+    #[test]
+    fn test_change_hooks() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"change-hooks\"))", env, cx);
+        check("(setq calls nil)", env, cx);
+        let form = "(progn
+                      (setq first-change-hook (list #'(lambda () (setq calls (cons 'first calls)))))
+                      (setq before-change-functions
+                            (list #'(lambda (beg end) (setq calls (cons (list 'before beg end) calls)))))
+                      (setq after-change-functions
+                            (list #'(lambda (beg end len) (setq calls (cons (list 'after beg end len) calls))))))";
+        check(form, env, cx);
+        assert_eq!(check("(buffer-modified-p)", env, cx), nil());
+        check("(insert \"hello\")", env, cx);
+        check("(delete-region 2 4)", env, cx);
+        assert_eq!(
+            check("(reverse calls)", env, cx).to_string(),
+            "(first (before 1 1) (after 1 6 0) (before 2 4) (after 2 2 2))"
+        );
+        assert_eq!(check("(buffer-modified-p)", env, cx), qtrue());
+
+        check("(setq calls nil)", env, cx);
+        check("(set-buffer-modified-p nil)", env, cx);
+        check(
+            "(let ((inhibit-modification-hooks t)) (insert \"x\"))",
+            env,
+            cx,
+        );
+        assert_eq!(check("calls", env, cx), nil());
+        assert_eq!(check("(buffer-modified-p)", env, cx), qtrue());
+
+        // property changes only count in the modified tick
+        let tick = "(list (buffer-modified-tick) (buffer-chars-modified-tick))";
+        let before = check(tick, env, cx).to_string();
+        check("(put-text-property 1 2 'face 'bold)", env, cx);
+        let after = check(tick, env, cx).to_string();
+        assert_ne!(before, after);
+        assert_eq!(check("(buffer-chars-modified-tick)", env, cx), 4);
+    }
+
+    #[test]
+    fn test_combine_after_change_calls() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"combine-test\"))", env, cx);
+        check("(insert \"hello world\")", env, cx);
+        check("(setq calls nil)", env, cx);
+        let form = "(setq after-change-functions
+                          (list #'(lambda (beg end len) (setq calls (cons (list beg end len) calls)))))";
+        check(form, env, cx);
+        let form = "(combine-after-change-calls
+                      (goto-char 1)
+                      (insert \"ab\")
+                      (delete-region 8 10)
+                      (goto-char (point-max))
+                      (insert \"!\")
+                      calls)";
+        assert_eq!(check(form, env, cx), nil());
+        assert_eq!(check("calls", env, cx).to_string(), "((1 13 11))");
+        // changes are not combined when before-change-functions would see them
+        check(
+            "(setq calls nil before-change-functions (list #'(lambda (beg end))))",
+            env,
+            cx,
+        );
+        check(
+            "(combine-after-change-calls (insert \"x\") (insert \"y\"))",
+            env,
+            cx,
+        );
+        assert_eq!(check("calls", env, cx).to_string(), "((14 15 0) (13 14 0))");
+    }
+
+    #[test]
+    fn test_columns() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(setq indent-tabs-mode t)", env, cx);
+        check("(set-buffer (get-buffer-create \"columns-test\"))", env, cx);
+        check("(insert \"a\tb中c\")", env, cx);
+        let form = "(list (current-column) (move-to-column 5) (point) (move-to-column 10) (point)
+                          (char-width ?中) (char-width #x301))";
+        assert_eq!(check(form, env, cx).to_string(), "(12 8 3 11 5 2 0)");
+        // a tab spanning the column is converted to spaces
+        let form = "(list (move-to-column 5 t) (point) (current-column))";
+        assert_eq!(check(form, env, cx).to_string(), "(5 6 5)");
+        check("(goto-char (point-max))", env, cx);
+        assert_eq!(check("(move-to-column 20 t)", env, cx), 20);
+        let text = "(buffer-substring (point-min) (point-max))";
+        assert_eq!(check(text, env, cx), "a    \tb中c\t    ");
+        check("(insert \"\n\t  x\")", env, cx);
+        assert_eq!(check("(current-indentation)", env, cx), 10);
+        // tab-width is local to each buffer
+        check("(setq tab-width 4)", env, cx);
+        assert_eq!(check("(current-indentation)", env, cx), 6);
+        check("(get-buffer-create \"columns-other\")", env, cx);
+        let form = "(list (progn (set-buffer \"columns-other\") tab-width)
+                          (progn (set-buffer \"columns-test\") tab-width))";
+        assert_eq!(check(form, env, cx).to_string(), "(8 4)");
+        check("(setq indent-tabs-mode nil)", env, cx);
+        check("(indent-to 9 2)", env, cx);
+        assert_eq!(check("(current-column)", env, cx), 9);
+    }
+
+    #[test]
+    fn test_silent_modifications() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"silent-test\"))", env, cx);
+        check("(insert \"hello\")", env, cx);
+        check("(set-buffer-modified-p nil)", env, cx);
+        check("(setq calls nil)", env, cx);
+        let form = "(setq after-change-functions
+                          (list #'(lambda (beg end len) (setq calls (cons beg calls)))))";
+        check(form, env, cx);
+        let state = "(list (buffer-modified-p) (buffer-modified-tick)
+                           (buffer-chars-modified-tick) buffer-undo-list calls)";
+        let before = check(state, env, cx).to_string();
+        let form = "(with-silent-modifications
+                      (put-text-property 1 3 'face 'bold)
+                      (list buffer-undo-list inhibit-modification-hooks))";
+        assert_eq!(check(form, env, cx).to_string(), "(t t)");
+        assert_eq!(check(state, env, cx).to_string(), before);
+        assert_eq!(check("(get-text-property 1 'face)", env, cx), sym!(bold));
+
+        // the undo list is restored in the buffer it was bound in
+        check("(get-buffer-create \"silent-other\")", env, cx);
+        let form = "(condition-case nil
+                        (with-silent-modifications
+                          (set-buffer \"silent-other\")
+                          (error \"exit\"))
+                      (error (list (buffer-name) buffer-undo-list)))";
+        assert_eq!(check(form, env, cx).to_string(), "(\"silent-other\" nil)");
+        check("(set-buffer \"silent-test\")", env, cx);
+        assert_eq!(check(state, env, cx).to_string(), before);
+    }
+
+    #[test]
+    fn test_deletion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let text = |env: &mut Rt<Env>| get_current_buffer(env).lock().unwrap().text.to_string();
+        check("(set-buffer (get-buffer-create \"deletion\"))", env, cx);
+        check("(insert \"hello world\")", env, cx);
+        check("(goto-char 6)", env, cx);
+        check("(delete-char 1)", env, cx);
+        assert_eq!(text(env), "helloworld");
+        check("(delete-char -2)", env, cx);
+        assert_eq!(text(env), "helworld");
+        assert_eq!(check("(point)", env, cx), 4);
+        let obj = reader::read("(delete-char 10)", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+        assert_eq!(check("(delete-and-extract-region 4 1)", env, cx), "hel");
+        assert_eq!(text(env), "world");
+
+        check("(narrow-to-region 2 3)", env, cx);
+        check("(erase-buffer)", env, cx);
+        assert_eq!(text(env), "");
+        assert_eq!(check("(point-max)", env, cx), 1);
+
+        check("(insert \"one two three\")", env, cx);
+        check("(put-text-property 9 14 'face 'bold)", env, cx);
+        check("(set-mark 10)", env, cx);
+        check("(goto-char 6)", env, cx);
+        check("(transpose-regions 9 14 1 4)", env, cx);
+        assert_eq!(text(env), "three two one");
+        assert_eq!(check("(mark)", env, cx), 2);
+        assert_eq!(check("(point)", env, cx), 6);
+        assert_eq!(check("(get-text-property 1 'face)", env, cx), sym!(bold));
+        assert!(check("(get-text-property 6 'face)", env, cx).nil());
+        check("(transpose-regions 1 6 11 14 t)", env, cx);
+        assert_eq!(text(env), "one two three");
+        assert_eq!(check("(mark)", env, cx), 2);
+        let obj = reader::read("(transpose-regions 1 5 3 6)", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_buffer_lifecycle() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(setq a (get-buffer-create \"lifecycle\"))", env, cx);
+        check("(setq b (generate-new-buffer \"lifecycle\"))", env, cx);
+        assert_eq!(check("(buffer-name b)", env, cx), "lifecycle<2>");
+        assert_eq!(
+            check("(generate-new-buffer-name \"lifecycle\")", env, cx),
+            "lifecycle<3>"
+        );
+        assert_eq!(
+            check(
+                "(generate-new-buffer-name \"lifecycle\" \"lifecycle\")",
+                env,
+                cx
+            ),
+            "lifecycle"
+        );
+        let before = "(< (length (memq b (buffer-list))) (length (memq a (buffer-list))))";
+        assert_eq!(check(before, env, cx), qtrue());
+        check("(bury-buffer a)", env, cx);
+        assert_eq!(check(before, env, cx), nil());
+
+        // renaming
+        check("(set-buffer b)", env, cx);
+        assert_eq!(
+            check("(rename-buffer \"lifecycle-b\")", env, cx),
+            "lifecycle-b"
+        );
+        assert_eq!(
+            check("(eq (get-buffer \"lifecycle-b\") b)", env, cx),
+            qtrue()
+        );
+        assert_eq!(check("(get-buffer \"lifecycle<2>\")", env, cx), nil());
+        let obj = reader::read("(rename-buffer \"lifecycle\")", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+        assert_eq!(
+            check("(rename-buffer \"lifecycle\" t)", env, cx),
+            "lifecycle<2>"
+        );
+
+        // killing
+        check("(setq calls nil)", env, cx);
+        check(
+            "(setq kill-buffer-query-functions (list #'(lambda () (null (eq (current-buffer) a)))))",
+            env,
+            cx,
+        );
+        check(
+            "(setq kill-buffer-hook (list #'(lambda () (setq calls (cons (buffer-name) calls)))))",
+            env,
+            cx,
+        );
+        assert_eq!(check("(kill-buffer a)", env, cx), nil());
+        assert_eq!(check("(buffer-live-p a)", env, cx), qtrue());
+        check(
+            "(setq c (make-indirect-buffer b \"lifecycle-indirect\"))",
+            env,
+            cx,
+        );
+        assert_eq!(check("(kill-buffer)", env, cx), qtrue());
+        assert_eq!(
+            check("calls", env, cx).to_string(),
+            "(\"lifecycle-indirect\" \"lifecycle<2>\")"
+        );
+        assert_eq!(
+            check("(list (buffer-live-p b) (buffer-live-p c))", env, cx).to_string(),
+            "(nil nil)"
+        );
+        assert_eq!(check("(memq b (buffer-list))", env, cx), nil());
+        assert_eq!(check("(buffer-name b)", env, cx), nil());
+        assert_eq!(check("(eq (current-buffer) b)", env, cx), nil());
+        assert_eq!(check("(kill-buffer b)", env, cx), nil());
+        let obj = reader::read("(set-buffer b)", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_overlay_hooks() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"overlay-hooks\"))",
+            env,
+            cx,
+        );
+        check("(insert \"hello world\")", env, cx);
+        check("(setq calls nil)", env, cx);
+        let form = "(defalias 'record
+                       #'(lambda (name)
+                           #'(lambda (&rest args)
+                               (setq calls (cons (cons name (cdr args)) calls)))))";
+        check(form, env, cx);
+        check("(setq outer (make-overlay 1 10))", env, cx);
+        check(
+            "(overlay-put outer 'modification-hooks (list (record 'outer)))",
+            env,
+            cx,
+        );
+        check("(setq inner (make-overlay 3 6))", env, cx);
+        check(
+            "(overlay-put inner 'modification-hooks (list (record 'inner)))",
+            env,
+            cx,
+        );
+        check(
+            "(overlay-put inner 'insert-in-front-hooks (list (record 'front)))",
+            env,
+            cx,
+        );
+        check(
+            "(overlay-put inner 'insert-behind-hooks (list (record 'behind)))",
+            env,
+            cx,
+        );
+
+        check("(progn (goto-char 3) (insert \"x\"))", env, cx);
+        assert_eq!(
+            check("(reverse calls)", env, cx).to_string(),
+            "((outer nil 3 3) (front nil 3 3) (outer t 3 4 0) (front t 3 4 0))"
+        );
+        check("(setq calls nil)", env, cx);
+        check("(progn (goto-char 7) (insert \"y\"))", env, cx);
+        assert_eq!(
+            check("(reverse calls)", env, cx).to_string(),
+            "((outer nil 7 7) (behind nil 7 7) (outer t 7 8 0) (behind t 7 8 0))"
+        );
+        check("(setq calls nil)", env, cx);
+        check("(delete-region 5 6)", env, cx);
+        assert_eq!(
+            check("(reverse calls)", env, cx).to_string(),
+            "((outer nil 5 6) (inner nil 5 6) (outer t 5 5 1) (inner t 5 5 1))"
+        );
+        // hooks don't run while modification hooks are inhibited
+        check("(setq calls nil)", env, cx);
+        check(
+            "(let ((inhibit-modification-hooks t)) (insert \"z\"))",
+            env,
+            cx,
+        );
+        assert_eq!(check("calls", env, cx), nil());
+    }
 }
@@ -209,6 +209,9 @@ struct Handler<'ob> {
     jump_code: u16,
     #[no_trace]
     stack_size: usize,
+    /// The length of the handler stack of the environment before this
+    #[no_trace]
+    depth: usize,
     condition: GcObj<'ob>,
 }
 
@@ -217,6 +220,7 @@ impl<'ob> IntoRoot<Handler<'static>> for Handler<'ob> {
         Handler {
             jump_code: self.jump_code,
             stack_size: self.stack_size,
+            depth: self.depth,
             condition: self.condition.into_root(),
         }
     }
@@ -264,9 +268,7 @@ impl<'brw, 'ob> Routine<'brw> {
     fn varbind(&mut self, idx: u16, env: &mut Rt<Env>, cx: &'ob Context) {
         let value = self.stack.pop(cx);
         let symbol = self.frame.get_const(idx as usize, cx);
-        let Object::Symbol(sym) = symbol.untag() else {
-            unreachable!("Varbind was not a symbol: {:?}", symbol)
-        };
+        let Object::Symbol(sym) = symbol.untag() else {unreachable!("Varbind was not a symbol: {:?}", symbol)};
         env.varbind(sym, value, cx);
     }
 
@@ -319,7 +321,7 @@ impl<'brw, 'ob> Routine<'brw> {
             x => unreachable!("Expected symbol for call found {:?}", x),
         };
 
-        let Some(func) = sym.follow_indirect(cx) else {bail_err!("Void Function: {sym}")};
+        let Some(func) = sym.follow_indirect(cx) else {return Err(crate::missing::void_function(sym, env, cx))};
         let slice = &self.stack[..arg_cnt];
         let args = Rt::bind_slice(slice, cx).to_vec();
         let name = sym.name().to_owned();
@@ -329,14 +331,14 @@ impl<'brw, 'ob> Routine<'brw> {
         self.stack.remove_top(arg_cnt);
         self.stack[0].set(result);
         cx.garbage_collect(false);
-        Ok(())
+        crate::alloc::check_memory_limits(env, cx)
     }
 
     fn run(&mut self, env: &mut Rt<Env>, cx: &'ob mut Context) -> EvalResult<'ob> {
         'main: loop {
             let err = match self.execute_bytecode(env, cx) {
                 Ok(x) => return Ok(rebind!(x, cx)),
-                Err(e) => e,
+                Err(e) => crate::interpreter::run_handlers(e, env, cx),
             };
 
             // we will fix this once we can handle different error types
@@ -368,6 +370,7 @@ impl<'brw, 'ob> Routine<'brw> {
                     // full errors are implemented
                     cons!(sym::ERROR, format!("{err}"); cx)
                 };
+                env.handler_stack.truncate(handler.depth);
                 self.stack.truncate(handler.stack_size);
                 self.stack.push(error);
                 self.frame.pc.goto(handler.jump_code);
@@ -381,6 +384,8 @@ impl<'brw, 'ob> Routine<'brw> {
     /// The main bytecode execution loop.
     fn execute_bytecode(&mut self, env: &mut Rt<Env>, cx: &'ob mut Context) -> EvalResult<'ob> {
         use crate::{alloc, arith, data, fns};
+        #[cfg(feature = "buffers")]
+        use crate::{buffer, editfns};
         use opcode::OpCode as op;
         loop {
             let op = match self.frame.pc.next().try_into() {
@@ -492,6 +497,7 @@ impl<'brw, 'ob> Routine<'brw> {
                 }
                 op::PopHandler => {
                     self.handlers.pop();
+                    env.handler_stack.pop();
                 }
                 op::PushCondtionCase => {
                     // pop before getting stack size
@@ -499,9 +505,11 @@ impl<'brw, 'ob> Routine<'brw> {
                     let handler = Handler {
                         jump_code: self.frame.pc.arg2(),
                         stack_size: self.stack.len(),
+                        depth: env.handler_stack.len(),
                         condition,
                     };
                     self.handlers.push(handler);
+                    env.handler_stack.push((condition, nil()));
                 }
                 op::PushCatch => todo!("PushCatch bytecode"),
                 op::Nth => {
@@ -678,11 +686,18 @@ impl<'brw, 'ob> Routine<'brw> {
                     let args = &[top.bind_as(cx)?, arg1.try_into()?];
                     top.set(cx.add(arith::mul(args)));
                 }
-                op::Point => todo!("Point bytecode"),
-                op::GotoChar => todo!("GotoChar bytecode"),
+                #[cfg(feature = "buffers")]
+                op::Point => self.stack.push(cx.add(editfns::point(env)?)),
+                #[cfg(feature = "buffers")]
+                op::GotoChar => {
+                    let top = self.stack.top();
+                    top.set(cx.add(editfns::goto_char(top.bind_as(cx)?, env)?));
+                }
                 op::Insert => todo!("Insert bytecode"),
-                op::PointMax => todo!("PointMax bytecode"),
-                op::PointMin => todo!("PointMin bytecode"),
+                #[cfg(feature = "buffers")]
+                op::PointMax => self.stack.push(cx.add(editfns::point_max(env)?)),
+                #[cfg(feature = "buffers")]
+                op::PointMin => self.stack.push(cx.add(editfns::point_min(env)?)),
                 op::CharAfter => todo!("CharAfter bytecode"),
                 op::FollowingChar => todo!("FollowingChar bytecode"),
                 op::PrecedingChar => todo!("PrecedingChar bytecode"),
@@ -692,9 +707,15 @@ impl<'brw, 'ob> Routine<'brw> {
                 op::EndOfBufferP => todo!("EndOfBufferP bytecode"),
                 op::BeginningOfLineP => todo!("BeginningOfLineP bytecode"),
                 op::BeginningOfBufferP => todo!("BeginningOfBufferP bytecode"),
-                op::CurrentBuffer => todo!("CurrentBuffer bytecode"),
-                op::SetBuffer => todo!("SetBuffer bytecode"),
-                op::SaveCurrentBuffer1 => todo!("SaveCurrentBuffer1 bytecode"),
+                #[cfg(feature = "buffers")]
+                op::CurrentBuffer => self.stack.push(cx.add(buffer::get_current_buffer(env))),
+                #[cfg(feature = "buffers")]
+                op::SetBuffer => {
+                    let top = self.stack.top();
+                    top.set(cx.add(buffer::set_buffer(top.bind(cx), env, cx)?));
+                }
+                #[cfg(feature = "buffers")]
+                op::SaveCurrentBuffer1 => env.bind_current_buffer(),
                 op::ForwardChar => todo!("ForwardChar bytecode"),
                 op::ForwardWord => todo!("ForwardWord bytecode"),
                 op::SkipCharsForward => todo!("SkipCharsForward bytecode"),
@@ -703,8 +724,26 @@ impl<'brw, 'ob> Routine<'brw> {
                 op::CharSyntax => todo!("CharSyntax bytecode"),
                 op::BufferSubstring => todo!("BufferSubstring bytecode"),
                 op::DeleteRegion => todo!("DeleteRegion bytecode"),
-                op::NarrowToRegion => todo!("NarrowToRegion bytecode"),
-                op::Widen => todo!("Widen bytecode"),
+                #[cfg(feature = "buffers")]
+                op::NarrowToRegion => {
+                    let end = self.stack.pop(cx);
+                    let top = self.stack.top();
+                    let result =
+                        editfns::narrow_to_region(top.bind_as(cx)?, end.try_into()?, env, cx)?;
+                    top.set(cx.add(result));
+                }
+                #[cfg(feature = "buffers")]
+                op::Widen => self.stack.push(cx.add(editfns::widen(env)?)),
+                #[cfg(not(feature = "buffers"))]
+                op::Point
+                | op::GotoChar
+                | op::PointMax
+                | op::PointMin
+                | op::CurrentBuffer
+                | op::SetBuffer
+                | op::SaveCurrentBuffer1
+                | op::NarrowToRegion
+                | op::Widen => bail_err!("Buffers are not enabled in this build"),
                 op::EndOfLine => todo!("EndOfLine bytecode"),
                 op::ConstantN2 => {
                     let idx = self.frame.pc.arg2();
@@ -774,9 +813,19 @@ impl<'brw, 'ob> Routine<'brw> {
                     let top = self.stack[0].bind(cx);
                     self.stack.push(top);
                 }
-                op::SaveExcursion => todo!("SaveExcursion bytecode"),
-                op::SaveRestriction => todo!("SaveRestriction bytecode"),
-                op::UnwindProtect => todo!("UnwindProtect bytecode"),
+                op::SaveExcursion => {
+                    return Err(crate::missing::void_function(sym::SAVE_EXCURSION, env, cx))
+                }
+                op::SaveRestriction => {
+                    return Err(crate::missing::void_function(
+                        sym::SAVE_RESTRICTION,
+                        env,
+                        cx,
+                    ))
+                }
+                op::UnwindProtect => {
+                    return Err(crate::missing::void_function(sym::UNWIND_PROTECT, env, cx))
+                }
                 op::SetMarker => todo!("SetMarker bytecode"),
                 op::MatchBeginning => todo!("MatchBeginning bytecode"),
                 op::MatchEnd => todo!("MatchEnd bytecode"),
@@ -976,7 +1025,11 @@ pub(crate) fn call<'ob>(
         handlers,
     };
     rout.prepare_lisp_args(func.bind(cx), arg_cnt, name, cx)?;
-    rout.run(env, cx)
+    let depth = env.handler_stack.len();
+    let result = rout.run(env, cx);
+    // the condition cases of the function end with it
+    env.handler_stack.truncate(depth);
+    result
 }
 
 #[allow(clippy::enum_glob_use)]
@@ -1138,6 +1191,25 @@ mod test {
         check_bytecode!(bytecode, [1, 2], 3, cx);
     }
 
+    #[test]
+    #[cfg(feature = "buffers")]
+    fn test_bytecode_buffer() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        lazy_static::initialize(&crate::core::env::INTERNED_SYMBOLS);
+        // (lambda (x) (goto-char x) (+ (point) (point-min) (point-max)))
+        make_bytecode!(
+            bytecode,
+            257,
+            [StackRef0, GotoChar, Discard, Point, PointMin, Plus, PointMax, Plus, Return],
+            [],
+            cx
+        );
+        // the default buffer is empty
+        check_bytecode!(bytecode, [5], 3, cx);
+    }
+
     #[test]
     fn test_bytecode_variables() {
         use OpCode::*;
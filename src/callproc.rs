@@ -0,0 +1,712 @@
+//! The environment of subprocesses and the search for the programs they
+//! run, and synchronous subprocesses. Subprocesses get the variables in
+//! `process-environment` rather than those of this process, and programs
+//! are looked for in `exec-path`.
+#[cfg(feature = "processes")]
+use crate::buffer::{get_buffer_create, get_current_buffer, restore_current_buffer};
+#[cfg(feature = "processes")]
+use crate::coding::{coding_system_var, set_last_coding_system_used, CodingSystem};
+#[cfg(feature = "processes")]
+use crate::core::object::Buffer;
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Rt},
+    object::{nil, GcObj, Object},
+};
+use crate::fileio::expand_file_name;
+#[cfg(feature = "processes")]
+use crate::fileio::{call_handler, file_io_error, file_name_handler, HandlerArg};
+use crate::fns::slice_into_list;
+#[cfg(feature = "processes")]
+use crate::root;
+#[cfg(feature = "processes")]
+use anyhow::bail;
+use anyhow::{ensure, Result};
+use fn_macros::defun;
+#[cfg(feature = "processes")]
+use std::fs::File;
+#[cfg(feature = "processes")]
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+#[cfg(feature = "processes")]
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::Command;
+#[cfg(feature = "processes")]
+use std::process::Stdio;
+
+/// The environment of this process as `VARIABLE=VALUE` strings, the initial
+/// value of `process-environment`.
+pub(crate) fn initial_environment<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    let vars: Vec<GcObj> = std::env::vars_os()
+        .map(|(var, value)| {
+            let entry = format!("{}={}", var.to_string_lossy(), value.to_string_lossy());
+            cx.add(entry)
+        })
+        .collect();
+    slice_into_list(&vars, None, cx)
+}
+
+/// The directories in `PATH`, the initial value of `exec-path`. An empty
+/// entry means the current directory.
+pub(crate) fn initial_exec_path<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<GcObj> = std::env::split_paths(&path)
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => cx.add("."),
+            false => cx.add(dir.to_string_lossy().into_owned()),
+        })
+        .collect();
+    slice_into_list(&dirs, None, cx)
+}
+
+/// An entry for a variable in `process-environment`.
+enum Entry {
+    Set(String),
+    /// An entry without a value, which unsets the variable.
+    Unset,
+}
+
+/// The first entry for `var` in the list of environment strings `list`.
+fn lookup(var: &str, list: GcObj) -> Result<Option<Entry>> {
+    for entry in list.as_list()? {
+        let Object::String(entry) = entry?.untag() else {continue};
+        let entry: &str = entry.try_into()?;
+        match entry.strip_prefix(var) {
+            Some("") => return Ok(Some(Entry::Unset)),
+            Some(value) => {
+                if let Some(value) = value.strip_prefix('=') {
+                    return Ok(Some(Entry::Set(value.to_owned())));
+                }
+            }
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+fn process_environment<'ob>(env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    env.vars
+        .get(sym::PROCESS_ENVIRONMENT)
+        .map_or_else(nil, |x| x.bind(cx))
+}
+
+/// Return the value of environment variable VARIABLE in
+/// `process-environment`, or nil if it is not set. If ENV is a list, it is
+/// searched instead, and t is returned for an entry that unsets VARIABLE.
+#[defun]
+fn getenv_internal<'ob>(
+    variable: &str,
+    env: Option<GcObj<'ob>>,
+    vars: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (list, negative) = match env {
+        Some(list) if matches!(list.untag(), Object::Cons(_) | Object::NIL) => (list, true.into()),
+        _ => (process_environment(vars, cx), nil()),
+    };
+    Ok(match lookup(variable, list)? {
+        Some(Entry::Set(value)) => cx.add(value),
+        Some(Entry::Unset) => negative,
+        None => nil(),
+    })
+}
+
+/// Return the value of environment variable VARIABLE in
+/// `process-environment`, or nil if it is not set.
+#[defun]
+fn getenv<'ob>(
+    variable: &str,
+    _frame: Option<GcObj>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    Ok(match lookup(variable, process_environment(env, cx))? {
+        Some(Entry::Set(value)) => cx.add(value),
+        _ => nil(),
+    })
+}
+
+/// Set environment variable VARIABLE to VALUE in `process-environment`, or
+/// unset it if VALUE is nil, and return VALUE. The environment of this
+/// process is not changed, only that of subprocesses, except that TZ sets
+/// the local time zone. If SUBSTITUTE-ENV-VARS is non-nil, the variables in
+/// VALUE are substituted first, see `substitute-env-vars`.
+#[defun]
+fn setenv<'ob>(
+    variable: &str,
+    value: Option<&str>,
+    substitute_env_vars: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    ensure!(
+        !variable.contains('='),
+        "Environment variable name `{variable}' contains `='"
+    );
+    let value = match (value, substitute_env_vars) {
+        (Some(value), Some(())) => Some(substitute(value, false, env, cx)?),
+        (value, _) => value.map(str::to_owned),
+    };
+    let entry = match &value {
+        Some(value) => cx.add(format!("{variable}={value}")),
+        None => cx.add(variable),
+    };
+    if variable == "TZ" {
+        crate::timefns::set_local_rule(value.as_deref());
+    }
+    let list = process_environment(env, cx);
+    if let Object::Cons(cons) = list.untag() {
+        for tail in cons.conses() {
+            let tail = tail?;
+            let Object::String(current) = tail.car().untag() else {continue};
+            let current: &str = current.try_into()?;
+            if current
+                .strip_prefix(variable)
+                .is_some_and(|x| x.is_empty() || x.starts_with('='))
+            {
+                tail.set_car(entry)?;
+                return Ok(value.map_or_else(nil, |x| cx.add(x)));
+            }
+        }
+    }
+    env.vars
+        .insert(sym::PROCESS_ENVIRONMENT, cons!(entry, list; cx));
+    Ok(value.map_or_else(nil, |x| cx.add(x)))
+}
+
+/// `string` with the environment variables in it replaced by their values.
+fn substitute(string: &str, keep_undefined: bool, env: &Rt<Env>, cx: &Context) -> Result<String> {
+    let list = process_environment(env, cx);
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut result = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else if let Some(after) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        let value = match name {
+            "" => None,
+            name => match lookup(name, list)? {
+                Some(Entry::Set(value)) => Some(value),
+                _ => None,
+            },
+        };
+        match value {
+            Some(value) => result.push_str(&value),
+            None if keep_undefined || name.is_empty() => {
+                result.push_str(&rest[start..=start + len]);
+            }
+            None => {}
+        }
+        rest = &after[len..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Return STRING with each `$VAR` or `${VAR}` replaced by the value of
+/// environment variable VAR, and each `$$` by `$`. An undefined variable is
+/// replaced by the empty string, unless WHEN-UNDEFINED is non-nil, which
+/// keeps the reference to it.
+#[defun]
+fn substitute_env_vars(
+    string: &str,
+    when_undefined: Option<()>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    substitute(string, when_undefined.is_some(), env, cx)
+}
+
+/// The environment of a subprocess: the first entry of each variable in
+/// `process-environment` that has a value.
+pub(crate) fn child_environment(env: &Rt<Env>, cx: &Context) -> Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, Option<String>)> = Vec::new();
+    for entry in process_environment(env, cx).as_list()? {
+        let Object::String(entry) = entry?.untag() else {continue};
+        let entry: &str = entry.try_into()?;
+        let (var, value) = match entry.split_once('=') {
+            Some((var, value)) => (var, Some(value.to_owned())),
+            None => (entry, None),
+        };
+        if !vars.iter().any(|x| x.0 == var) {
+            vars.push((var.to_owned(), value));
+        }
+    }
+    Ok(vars
+        .into_iter()
+        .filter_map(|(var, value)| Some((var, value?)))
+        .collect())
+}
+
+/// Whether `file` is a file that can be executed.
+fn is_executable(file: &Path) -> bool {
+    let metadata = std::fs::metadata(file);
+    metadata.is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+}
+
+/// The absolute name of the executable for `program`. A name with a
+/// directory is taken relative to `default-directory`, and other names are
+/// looked for in each directory of `exec-path`, with each of
+/// `exec-suffixes`. A nil directory in `exec-path` is `default-directory`.
+pub(crate) fn find_program(program: &str, env: &Rt<Env>, cx: &Context) -> Result<Option<String>> {
+    if program.contains('/') {
+        let file = expand_file_name(program, None, env, cx)?;
+        return Ok(is_executable(Path::new(&file)).then_some(file));
+    }
+    let var = |var, env: &Rt<Env>| env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+    let mut suffixes = Vec::new();
+    for suffix in var(sym::EXEC_SUFFIXES, env).as_list()? {
+        if let Object::String(suffix) = suffix?.untag() {
+            suffixes.push(<&str>::try_from(suffix)?.to_owned());
+        }
+    }
+    if suffixes.is_empty() {
+        suffixes.push(String::new());
+    }
+    for dir in var(sym::EXEC_PATH, env).as_list()? {
+        let dir = match dir?.untag() {
+            Object::String(dir) => <&str>::try_from(dir)?.to_owned(),
+            Object::NIL => ".".to_owned(),
+            _ => continue,
+        };
+        let dir = expand_file_name(&dir, None, env, cx)?;
+        for suffix in &suffixes {
+            let file = Path::new(&dir).join(format!("{program}{suffix}"));
+            if is_executable(&file) {
+                return Ok(Some(file.to_string_lossy().into_owned()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Search for COMMAND in `exec-path` and return its absolute file name, or
+/// nil if it is not found. See `exec-suffixes`.
+#[defun]
+fn executable_find<'ob>(
+    command: &str,
+    _remote: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    Ok(find_program(command, env, cx)?.map_or_else(nil, |x| cx.add(x)))
+}
+
+/// Give `command` the environment of a subprocess and run it in
+/// `default-directory`.
+#[cfg_attr(not(feature = "processes"), allow(dead_code))]
+pub(crate) fn configure_command(command: &mut Command, env: &Rt<Env>, cx: &Context) -> Result<()> {
+    command.env_clear().envs(child_environment(env, cx)?);
+    let dir = expand_file_name(".", None, env, cx)?;
+    if Path::new(&dir).is_dir() {
+        command.current_dir(dir);
+    }
+    Ok(())
+}
+
+/// Where output of a synchronous process goes.
+#[cfg(feature = "processes")]
+enum Output {
+    Discard,
+    /// Discard the output and don't wait for the process to exit
+    NoWait,
+    /// Insert the output at point
+    Buffer(&'static Buffer),
+    File(String),
+}
+
+/// Where the output and the error output of a synchronous process go, from
+/// the DESTINATION argument of `call-process`. The error output is `None`
+/// if it is mixed with the output.
+#[cfg(feature = "processes")]
+fn process_destination(
+    destination: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(Output, Option<Output>)> {
+    let file = |name: GcObj, env: &mut Rt<Env>| -> Result<Output> {
+        let name: &str = name.try_into()?;
+        Ok(Output::File(expand_file_name(name, None, env, cx)?))
+    };
+    let (real, stderr) = match destination.untag() {
+        Object::Cons(cons) => {
+            let mut elts = cons.elements();
+            let car = elts.next().unwrap()?;
+            let cadr = elts.next().transpose()?.unwrap_or_else(nil);
+            if car == sym::KW_FILE {
+                return Ok((file(cadr, env)?, None));
+            }
+            let stderr = match cadr.untag() {
+                Object::NIL => Some(Output::Discard),
+                Object::TRUE => None,
+                _ => Some(file(cadr, env)?),
+            };
+            (car, stderr)
+        }
+        _ => (destination, None),
+    };
+    let output = match real.untag() {
+        Object::NIL => Output::Discard,
+        Object::TRUE => Output::Buffer(get_current_buffer(env)),
+        Object::Int(0) => Output::NoWait,
+        _ => Output::Buffer(get_buffer_create(real, None)?),
+    };
+    Ok((output, stderr))
+}
+
+/// The file `name` created for output of a process.
+#[cfg(feature = "processes")]
+fn create_output(name: &str, env: &mut Rt<Env>, cx: &Context) -> Result<File> {
+    File::create(name).map_err(|e| file_io_error(&e, "Opening process output file", name, env, cx))
+}
+
+/// The strings in `args`.
+#[cfg(feature = "processes")]
+fn string_args(args: &[Rt<GcObj>], cx: &Context) -> Result<Vec<String>> {
+    args.iter()
+        .map(|x| Ok(<&str>::try_from(x.bind(cx))?.to_owned()))
+        .collect()
+}
+
+/// Run `program` with `args` and wait for it to exit, sending its output to
+/// `destination` as described for `call-process`. Its input is `input` if
+/// given, or else the file `infile`, or else nothing. Returns its exit code,
+/// or the description of the signal that killed it, or nil if it was not
+/// waited for.
+#[cfg(feature = "processes")]
+fn run_process<'ob>(
+    program: &str,
+    infile: Option<&str>,
+    input: Option<Vec<u8>>,
+    destination: Option<&Rt<GcObj>>,
+    args: &[String],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let destination = destination.map_or_else(nil, |x| x.bind(cx));
+    let (output, error_output) = process_destination(destination, env, cx)?;
+    let Some(file) = find_program(program, env, cx)? else {
+        let err = io::Error::from_raw_os_error(2);
+        return Err(file_io_error(
+            &err,
+            "Searching for program",
+            program,
+            env,
+            cx,
+        ));
+    };
+    let mut cmd = Command::new(&file);
+    cmd.args(args);
+    configure_command(&mut cmd, env, cx)?;
+    match (&input, infile) {
+        (Some(_), _) => cmd.stdin(Stdio::piped()),
+        (None, Some(infile)) => {
+            let infile = expand_file_name(infile, None, env, cx)?;
+            match File::open(&infile) {
+                Ok(infile) => cmd.stdin(infile),
+                Err(e) => {
+                    return Err(file_io_error(
+                        &e,
+                        "Opening process input file",
+                        &infile,
+                        env,
+                        cx,
+                    ))
+                }
+            }
+        }
+        (None, None) => cmd.stdin(Stdio::null()),
+    };
+    // the error output goes where the output goes unless it is redirected
+    let mixed = error_output.is_none();
+    let mut reader = None;
+    let (stdout, mixed): (Stdio, Option<Stdio>) = match &output {
+        Output::Buffer(_) => {
+            let (pipe, writer) = io::pipe()?;
+            reader = Some(pipe);
+            let mixed = mixed.then(|| writer.try_clone()).transpose()?;
+            (writer.into(), mixed.map(Into::into))
+        }
+        Output::File(name) => {
+            let file = create_output(name, env, cx)?;
+            let mixed = mixed.then(|| file.try_clone()).transpose()?;
+            (file.into(), mixed.map(Into::into))
+        }
+        Output::Discard | Output::NoWait => (Stdio::null(), None),
+    };
+    let stderr = match error_output {
+        Some(Output::File(name)) => create_output(&name, env, cx)?.into(),
+        _ => mixed.unwrap_or_else(Stdio::null),
+    };
+    cmd.stdout(stdout).stderr(stderr);
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(file_io_error(&e, "Spawning child process", &file, env, cx)),
+    };
+    // the pipes the child writes to must only be open in the child, so
+    // that reading them ends when it exits
+    drop(cmd);
+    if matches!(output, Output::NoWait) {
+        std::thread::spawn(move || child.wait());
+        return Ok(nil());
+    }
+    // the input is written on another thread, so that a child that writes
+    // a lot before reading all of it can't block
+    let writer = input.zip(child.stdin.take()).map(|(input, mut stdin)| {
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        })
+    });
+    let mut bytes = Vec::new();
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut bytes)?;
+    }
+    let status = child.wait()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if let Output::Buffer(buffer) = output {
+        let coding = coding_system_var(sym::CODING_SYSTEM_FOR_READ, env, cx)
+            .unwrap_or_else(|| CodingSystem::get("undecided").unwrap());
+        let (coding, text) = coding.decode(&bytes);
+        set_last_coding_system_used(coding, env, cx);
+        let old = get_current_buffer(env);
+        crate::buffer::set_current_buffer(buffer, env, cx);
+        let result = crate::buffer::insert_text(&text, env, cx);
+        restore_current_buffer(old, env, cx);
+        result?;
+    }
+    match (status.code(), status.signal()) {
+        (Some(code), _) => Ok(i64::from(code).into()),
+        (None, Some(signal)) => {
+            let description = crate::sysdep::signal_description(signal, status.core_dumped());
+            Ok(cx.add(description))
+        }
+        (None, None) => bail!("Process exited without a status"),
+    }
+}
+
+/// Call PROGRAM with ARGS synchronously in a subprocess, and return its exit
+/// code, or a description of the signal that killed it. Its input comes from
+/// the file INFILE, or from nothing if INFILE is nil.
+///
+/// DESTINATION says where the output goes: t means to insert it at point in
+/// the current buffer, a buffer or buffer name to insert it at point in that
+/// buffer, nil to discard it, and 0 to discard it and return nil right away
+/// instead of waiting for PROGRAM to exit. (:file FILE) writes it to FILE.
+/// DESTINATION can also be (REAL-DESTINATION STDERR-FILE) to treat the error
+/// output apart, where STDERR-FILE is nil to discard it, t to mix it with
+/// the output, or the name of a file to write it to. Otherwise the error
+/// output is mixed with the output.
+///
+/// DISPLAY is ignored, since there is no display to update.
+#[cfg(feature = "processes")]
+#[defun]
+fn call_process<'ob>(
+    program: &Rt<GcObj>,
+    infile: Option<&Rt<GcObj>>,
+    destination: Option<&Rt<GcObj>>,
+    _display: Option<&Rt<GcObj>>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let program = <&str>::try_from(program.bind(cx))?.to_owned();
+    let infile = match infile.map(|x| x.bind(cx)) {
+        Some(x) if !x.nil() => Some(<&str>::try_from(x)?.to_owned()),
+        _ => None,
+    };
+    let args = string_args(args, cx)?;
+    run_process(
+        &program,
+        infile.as_deref(),
+        None,
+        destination,
+        &args,
+        env,
+        cx,
+    )
+}
+
+/// Send the text from START to END to PROGRAM, called with ARGS synchronously
+/// in a subprocess, and return its exit code, or a description of the
+/// signal that killed it. START can be a string to send instead, and if it
+/// is nil the whole buffer is sent. The text is deleted before PROGRAM runs
+/// if DELETE is non-nil. DESTINATION and DISPLAY are as in `call-process`.
+#[cfg(feature = "processes")]
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn call_process_region<'ob>(
+    start: &Rt<GcObj>,
+    end: &Rt<GcObj>,
+    program: &Rt<GcObj>,
+    delete: Option<()>,
+    destination: Option<&Rt<GcObj>>,
+    _display: Option<&Rt<GcObj>>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let program = <&str>::try_from(program.bind(cx))?.to_owned();
+    let args = string_args(args, cx)?;
+    let start = start.bind(cx);
+    let (text, region) = match start.untag() {
+        Object::String(string) => (<&str>::try_from(string)?.to_owned(), None),
+        Object::NIL => {
+            let buffer = get_current_buffer(env);
+            let data = buffer.lock()?;
+            let len = data.text.len_chars();
+            (data.text.substring(0, len), Some((0, len)))
+        }
+        _ => {
+            let start: usize = start.try_into()?;
+            let end: usize = end.bind(cx).try_into()?;
+            let (beg, end, text) = crate::coding::region_text(start, end, env, cx)?;
+            (text, Some((beg, end)))
+        }
+    };
+    let coding = coding_system_var(sym::CODING_SYSTEM_FOR_WRITE, env, cx)
+        .unwrap_or_else(|| CodingSystem::get("utf-8-unix").unwrap());
+    let input = coding.encode(&text);
+    if let (Some((beg, end)), Some(())) = (region, delete) {
+        crate::buffer::delete_text(beg, end, env, cx)?;
+    }
+    run_process(&program, None, Some(input), destination, &args, env, cx)
+}
+
+/// Like `call-process`, but run PROGRAM with the file name handler of
+/// `default-directory` if it has one, so that it can run on a remote host.
+/// INFILE is relative to `default-directory`.
+#[cfg(feature = "processes")]
+#[defun]
+fn process_file<'ob>(
+    program: &Rt<GcObj>,
+    infile: Option<&Rt<GcObj>>,
+    buffer: Option<&Rt<GcObj>>,
+    display: Option<&Rt<GcObj>>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let directory = match env
+        .vars
+        .get(sym::DEFAULT_DIRECTORY)
+        .map(|x| x.bind(cx).untag())
+    {
+        Some(Object::String(dir)) => <&str>::try_from(dir)?.to_owned(),
+        _ => String::new(),
+    };
+    let handler = file_name_handler(&directory, sym::PROCESS_FILE, env, cx)?;
+    if !handler.nil() {
+        root!(handler, cx);
+        let mut handler_args = vec![
+            HandlerArg::Obj(Some(program)),
+            HandlerArg::Obj(infile),
+            HandlerArg::Obj(buffer),
+            HandlerArg::Obj(display),
+        ];
+        handler_args.extend(args.iter().map(|x| HandlerArg::Obj(Some(x))));
+        return call_handler(handler, sym::PROCESS_FILE, &handler_args, env, cx);
+    }
+    call_process(program, infile, buffer, display, args, env, cx)
+}
+
+defsym!(KW_FILE);
+
+defvar!(
+    PROCESS_ENVIRONMENT,
+    crate::callproc::initial_environment(cx)
+);
+defvar!(
+    INITIAL_ENVIRONMENT,
+    crate::callproc::initial_environment(cx)
+);
+defvar!(EXEC_PATH, crate::callproc::initial_exec_path(cx));
+defvar!(EXEC_SUFFIXES);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_environment() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn
+            (setq process-environment (list "A=1" "B" "B=2" "AB=3"))
+            (setq exec-path '("/nonexistent" "/bin" "/usr/bin"))
+            (list (getenv "A") (getenv "B") (getenv "C") (getenv-internal "B" '("B"))
+                  (setenv "A" "x") (setenv "B" "$A-${AB}-$C-$$" t) (setenv "D" nil)
+                  process-environment
+                  (substitute-env-vars "$C$A" t)
+                  (file-name-nondirectory (executable-find "sh"))
+                  (executable-find "rune-no-such-program")))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(\"1\" nil nil t \"x\" \"x-3--$\" nil (\"D\" \"A=x\" \"B=x-3--$\" \"B=2\" \"AB=3\") \
+             \"$Cx\" \"sh\" nil)"
+        );
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo \"$A,$B,${D-unset},$HOME\""]);
+        configure_command(&mut command, env, cx).unwrap();
+        let output = command.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "x,x-3--$,unset,\n");
+    }
+
+    #[cfg(feature = "processes")]
+    #[test]
+    fn test_call_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let file = std::env::temp_dir().join(format!("rune-callproc-{}", std::process::id()));
+        let file = file.to_string_lossy().into_owned();
+        let form = format!(
+            r#"(progn
+                 (set-buffer (get-buffer-create "call-process"))
+                 (insert "<>")
+                 (goto-char 2)
+                 (list (call-process "/bin/sh" nil t nil "-c" "echo out; echo err >&2; exit 2")
+                       (call-process "/bin/sh" nil '(t nil) nil "-c" "echo out2; echo err2 >&2")
+                       (buffer-string) (point)
+                       (call-process "/bin/sh" nil nil nil "-c" "kill -9 $$")
+                       (call-process "/bin/sh" nil 0 nil "-c" "echo lost")
+                       (call-process "/bin/sh" nil '(:file "{file}") nil "-c" "echo saved")
+                       (progn (erase-buffer) (call-process "/bin/cat" "{file}" t))
+                       (progn (insert "abc") (call-process-region 7 10 "/bin/sh" t t nil "-c" "tr a-z A-Z"))
+                       (call-process-region "xyz" nil "/bin/cat" nil t)
+                       (process-file "/bin/sh" nil t nil "-c" "echo $0")
+                       (buffer-string)
+                       (condition-case nil (call-process "/nonexistent") (error 'missing))))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap().to_string();
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(
+            result,
+            "(2 0 \"<out\nerr\nout2\n>\" 15 \"Killed\" nil 0 0 0 0 0 \
+             \"saved\nABCxyz/bin/sh\n\" missing)"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Case conversion of characters, strings and regions of the buffer, using
+//! the case table of the current buffer. A word starts at a word
+//! constituent that follows a character that is not one.
+#[cfg(feature = "buffers")]
+use crate::buffer::get_current_buffer;
+use crate::casetab::{current_case, CaseTable};
+use crate::core::env::Env;
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{GcObj, Object};
+#[cfg(feature = "buffers")]
+use crate::editfns::validate_region;
+use anyhow::Result;
+use fn_macros::defun;
+
+/// How the case of text is changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseOp {
+    Up,
+    Down,
+    /// The first character of each word titlecased and the rest downcased
+    Capitalize,
+    /// The first character of each word titlecased and the rest unchanged
+    UpInitials,
+}
+
+/// The form of `chr` that starts a word. This is its uppercase form in
+/// `case`, except for the few characters with a different titlecase form.
+fn titlecase(chr: char, case: &CaseTable) -> char {
+    match crate::unidata::titlecase(chr.into()).and_then(char::from_u32) {
+        Some(title) => title,
+        None => case.upcase(chr),
+    }
+}
+
+/// Whether a character is a word constituent in the current syntax table.
+#[cfg(feature = "buffers")]
+fn word_syntax(env: &mut Rt<Env>, cx: &Context) -> impl Fn(char) -> bool {
+    let syntax = crate::syntax::current_syntax(env, cx);
+    move |chr| syntax.is_word(chr)
+}
+
+/// Without buffers there are no syntax tables, so words are made of
+/// letters and digits.
+#[cfg(not(feature = "buffers"))]
+fn word_syntax(_: &mut Rt<Env>, _: &Context) -> impl Fn(char) -> bool {
+    char::is_alphanumeric
+}
+
+/// `text` with its case changed by `op`.
+fn casify(op: CaseOp, text: &str, case: &CaseTable, is_word: impl Fn(char) -> bool) -> String {
+    let mut in_word = false;
+    let mut result = String::with_capacity(text.len());
+    for chr in text.chars() {
+        result.push(match op {
+            CaseOp::Up => case.upcase(chr),
+            CaseOp::Down => case.downcase(chr),
+            CaseOp::Capitalize if in_word => case.downcase(chr),
+            CaseOp::Capitalize | CaseOp::UpInitials if !in_word => titlecase(chr, case),
+            CaseOp::Capitalize | CaseOp::UpInitials => chr,
+        });
+        in_word = is_word(chr);
+    }
+    result
+}
+
+/// Change the case of OBJECT, a character or a string. A string is copied.
+fn casify_object<'ob>(
+    op: CaseOp,
+    object: GcObj<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let case = current_case(env, cx);
+    match object.untag() {
+        Object::Int(chr) => {
+            let Some(chr) = u32::try_from(chr).ok().and_then(char::from_u32) else {return Ok(object)};
+            let chr = match op {
+                CaseOp::Up => case.upcase(chr),
+                CaseOp::Down => case.downcase(chr),
+                CaseOp::Capitalize | CaseOp::UpInitials => titlecase(chr, &case),
+            };
+            Ok(i64::from(u32::from(chr)).into())
+        }
+        Object::String(string) => {
+            let string: &str = string.try_into()?;
+            let is_word = word_syntax(env, cx);
+            Ok(cx.add(casify(op, string, &case, is_word)))
+        }
+        _ => Err(TypeError::new(Type::String, object).into()),
+    }
+}
+
+/// Convert OBJ to upper case. OBJ is a character or a string; a string is
+/// returned as a new string.
+#[defun]
+fn upcase<'ob>(obj: GcObj<'ob>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    casify_object(CaseOp::Up, obj, env, cx)
+}
+
+/// Convert OBJ to lower case. OBJ is a character or a string; a string is
+/// returned as a new string.
+#[defun]
+fn downcase<'ob>(obj: GcObj<'ob>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    casify_object(CaseOp::Down, obj, env, cx)
+}
+
+/// Convert OBJ to capitalized form, where each word starts with an upper
+/// case character and the rest of it is lower case. A character is
+/// converted to the form that starts a word.
+#[defun]
+fn capitalize<'ob>(obj: GcObj<'ob>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    casify_object(CaseOp::Capitalize, obj, env, cx)
+}
+
+/// Convert the initial of each word in OBJ to upper case, leaving the rest
+/// of it alone. A character is converted to the form that starts a word.
+#[defun]
+fn upcase_initials<'ob>(
+    obj: GcObj<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    casify_object(CaseOp::UpInitials, obj, env, cx)
+}
+
+/// Change the case of the text between BEG and END in the current buffer.
+/// Only the part of the text that changes is replaced, so markers and text
+/// properties are kept.
+#[cfg(feature = "buffers")]
+fn casify_region(
+    op: CaseOp,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let bounds = {
+        let data = buffer.lock()?;
+        (data.begv(), data.zv())
+    };
+    let (beg, end) = validate_region(beg, end, bounds, env, cx)?;
+    let text = buffer.lock()?.text.substring(beg, end);
+    let case = current_case(env, cx);
+    let new = casify(op, &text, &case, word_syntax(env, cx));
+    let changed = |(_, (x, y)): &(usize, (char, char))| x != y;
+    let pairs = || text.chars().zip(new.chars()).enumerate();
+    let Some((first, _)) = pairs().find(changed) else {return Ok(false)};
+    let last = pairs().filter(changed).last().map_or(first, |x| x.0);
+    let replacement: String = new.chars().skip(first).take(last + 1 - first).collect();
+    crate::buffer::replace_text(beg + first, &replacement, env, cx)?;
+    Ok(false)
+}
+
+/// Convert the region to upper case. In programs, it takes the arguments
+/// BEG and END. REGION-NONCONTIGUOUS-P is accepted for compatibility and
+/// ignored.
+#[cfg(feature = "buffers")]
+#[defun]
+fn upcase_region(
+    beg: usize,
+    end: usize,
+    region_noncontiguous_p: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let _ = region_noncontiguous_p;
+    casify_region(CaseOp::Up, beg, end, env, cx)
+}
+
+/// Convert the region to lower case. In programs, it takes the arguments
+/// BEG and END. REGION-NONCONTIGUOUS-P is accepted for compatibility and
+/// ignored.
+#[cfg(feature = "buffers")]
+#[defun]
+fn downcase_region(
+    beg: usize,
+    end: usize,
+    region_noncontiguous_p: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let _ = region_noncontiguous_p;
+    casify_region(CaseOp::Down, beg, end, env, cx)
+}
+
+/// Capitalize the words in the region. In programs, it takes the arguments
+/// BEG and END. REGION-NONCONTIGUOUS-P is accepted for compatibility and
+/// ignored.
+#[cfg(feature = "buffers")]
+#[defun]
+fn capitalize_region(
+    beg: usize,
+    end: usize,
+    region_noncontiguous_p: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let _ = region_noncontiguous_p;
+    casify_region(CaseOp::Capitalize, beg, end, env, cx)
+}
+
+/// Upcase the initial of each word in the region. In programs, it takes
+/// the arguments BEG and END.
+#[cfg(feature = "buffers")]
+#[defun]
+fn upcase_initials_region(
+    beg: usize,
+    end: usize,
+    region_noncontiguous_p: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let _ = region_noncontiguous_p;
+    casify_region(CaseOp::UpInitials, beg, end, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_casify() {
+        let case = CaseTable::default();
+        let is_word = char::is_alphanumeric;
+        let text = "hello WORLD, éa-bc";
+        assert_eq!(
+            casify(CaseOp::Up, text, &case, is_word),
+            "HELLO WORLD, ÉA-BC"
+        );
+        assert_eq!(casify(CaseOp::Down, "ÀB ß", &case, is_word), "àb ß");
+        assert_eq!(
+            casify(CaseOp::Capitalize, text, &case, is_word),
+            "Hello World, Éa-Bc"
+        );
+        assert_eq!(
+            casify(CaseOp::UpInitials, "hello wORLD", &case, is_word),
+            "Hello WORLD"
+        );
+        // digraphs start a word in titlecase
+        assert_eq!(
+            casify(CaseOp::Capitalize, "ǆa ǅB ǄC", &case, is_word),
+            "ǅa ǅb ǅc"
+        );
+        assert_eq!(casify(CaseOp::UpInitials, "ǆa", &case, is_word), "ǅa");
+        assert_eq!(casify(CaseOp::Up, "ǆa", &case, is_word), "ǄA");
+        // multi-character mappings are not applied
+        assert_eq!(casify(CaseOp::Up, "straße", &case, is_word), "STRAßE");
+    }
+
+    #[test]
+    fn test_case_functions() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(list (upcase ?a) (downcase ?A) (capitalize ?b) (upcase-initials ?c)
+                          (upcase \"foo bar\") (downcase \"FOO\")
+                          (capitalize \"foo bAR\") (upcase-initials \"foo bAR\"))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(65 97 66 67 \"FOO BAR\" \"foo\" \"Foo Bar\" \"Foo BAR\")"
+        );
+        let form = "(list (capitalize \"ǆa\") (capitalize ?ǆ) (upcase-initials ?ǆ) (upcase ?ǆ))";
+        assert_eq!(check(form, env, cx).to_string(), "(\"ǅa\" 453 453 452)");
+        let obj = reader::read("(upcase 'a)", cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+    }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_case_region() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"case\"))", env, cx);
+        check("(insert \"hello world again\")", env, cx);
+        let form = "(list (progn (upcase-region 1 6) (buffer-string))
+                          (progn (capitalize-region 7 18) (buffer-string))
+                          (progn (downcase-region 18 1) (buffer-string))
+                          (progn (upcase-initials-region 1 12) (buffer-string))
+                          (point))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(\"HELLO world again\" \"HELLO World Again\" \"hello world again\" \
+              \"Hello World again\" 18)"
+        );
+        // a case table of the buffer
+        let form = "(let ((table (make-char-table 'case-table)))
+                      (aset table ?X ?o)
+                      (set-case-table table)
+                      (list (downcase \"XY\") (eq (current-case-table) table)))";
+        assert_eq!(check(form, env, cx).to_string(), "(\"oy\" t)");
+    }
+}
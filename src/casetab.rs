@@ -0,0 +1,224 @@
+//! Case tables, which say how characters are converted between upper and
+//! lower case. A case table is a char-table that maps each character to its
+//! lowercase form. Its first extra slot is the table of uppercase forms, or
+//! nil to compute it from the lowercase forms. Characters that the table
+//! does not map use the simple case mappings of Unicode.
+#[cfg(feature = "buffers")]
+use crate::buffer::get_current_buffer;
+use crate::core::env::{sym, Env};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, CharMap, CharTable, CharTableData, Gc, GcObj, Object, TagType};
+use anyhow::{bail, Result};
+use fn_macros::defun;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The number of extra slots of a case table: the up, canonicalize and
+/// equivalences tables.
+pub(crate) const CASE_TABLE_EXTRA_SLOTS: usize = 3;
+
+/// The lowercase form of `chr` in Unicode, if it is a single character.
+fn simple_downcase(chr: char) -> char {
+    let mut lower = chr.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => chr,
+    }
+}
+
+/// The uppercase form of `chr` in Unicode, if it is a single character.
+fn simple_upcase(chr: char) -> char {
+    let mut upper = chr.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(upper), None) => upper,
+        _ => chr,
+    }
+}
+
+/// The characters that `table` maps to characters.
+fn char_entries(table: &CharTable) -> CharMap<char> {
+    let mut map = CharMap::default();
+    for (from, to, value) in table.borrow().entries.iter() {
+        if let Object::Int(chr) = value.untag() {
+            if let Some(chr) = u32::try_from(chr).ok().and_then(char::from_u32) {
+                map.set_range(from, to, chr);
+            }
+        }
+    }
+    map
+}
+
+/// The case conversions of a case table. This is a copy that does not refer
+/// to the heap, so it can be kept between calls.
+#[derive(Debug, Default)]
+pub(crate) struct CaseTable {
+    down: CharMap<char>,
+    up: CharMap<char>,
+}
+
+impl CaseTable {
+    fn new(table: &CharTable) -> Self {
+        let down = char_entries(table);
+        let up = match table.borrow().extras.first().map(|x| x.untag()) {
+            Some(Object::CharTable(up)) => char_entries(up),
+            // the inverse of the lowercase forms
+            _ => {
+                let mut up = CharMap::default();
+                for (from, to, chr) in down.iter() {
+                    let upper = char::from_u32(from);
+                    if from == to && upper.is_some_and(|x| x != *chr) {
+                        up.set((*chr).into(), upper.unwrap());
+                    }
+                }
+                up
+            }
+        };
+        Self { down, up }
+    }
+
+    pub(crate) fn downcase(&self, chr: char) -> char {
+        match self.down.get(chr.into()) {
+            Some(lower) => *lower,
+            None => simple_downcase(chr),
+        }
+    }
+
+    pub(crate) fn upcase(&self, chr: char) -> char {
+        match self.up.get(chr.into()) {
+            Some(upper) => *upper,
+            None => simple_upcase(chr),
+        }
+    }
+}
+
+/// The address of a case table and the number of char-table changes when a
+/// copy of it was made.
+type CaseKey = (usize, usize);
+
+thread_local! {
+    /// The copy of the case table that was last used.
+    static CASE_CACHE: RefCell<Option<(CaseKey, Rc<CaseTable>)>> = const { RefCell::new(None) };
+}
+
+/// The standard case table, which is created the first time it is needed.
+#[defun]
+pub(crate) fn standard_case_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    if let Some(table) = env.standard_case_table.as_ref() {
+        return table.bind(cx).untag();
+    }
+    let mut data = CharTableData::new(sym::CASE_TABLE.into(), nil(), CASE_TABLE_EXTRA_SLOTS);
+    for chr in 0..=0x7F_u8 {
+        data.entries
+            .set(chr.into(), i64::from(chr.to_ascii_lowercase()).into());
+    }
+    let table: Gc<&CharTable> = cx.add_as(data);
+    env.standard_case_table.set(table);
+    table.untag()
+}
+
+/// Return the case table of the current buffer.
+#[defun]
+pub(crate) fn current_case_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    #[cfg(feature = "buffers")]
+    {
+        let buffer = get_current_buffer(env);
+        if let Some(table) = env.case_tables.get(buffer.tag()) {
+            return table.bind(cx).untag();
+        }
+    }
+    standard_case_table(env, cx)
+}
+
+/// The case conversions of the current buffer, as a copy of its case table
+/// that is only made again when a char-table changes.
+pub(crate) fn current_case(env: &mut Rt<Env>, cx: &Context) -> Rc<CaseTable> {
+    let table = current_case_table(env, cx);
+    let key = (std::ptr::from_ref(table) as usize, CharTable::changes());
+    CASE_CACHE.with_borrow_mut(|cache| match cache {
+        Some((cached, case)) if *cached == key => case.clone(),
+        _ => {
+            let case = Rc::new(CaseTable::new(table));
+            *cache = Some((key, case.clone()));
+            case
+        }
+    })
+}
+
+/// Return t if OBJECT is a case table: a char-table with subtype
+/// `case-table` whose extra slots are char-tables or nil.
+#[defun]
+fn case_table_p(object: GcObj) -> bool {
+    match object.untag() {
+        Object::CharTable(table) => {
+            let data = table.borrow();
+            let is_table = |x: &GcObj| matches!(x.untag(), Object::NIL | Object::CharTable(_));
+            data.subtype == sym::CASE_TABLE
+                && data.extras.len() == CASE_TABLE_EXTRA_SLOTS
+                && data.extras.iter().all(is_table)
+        }
+        _ => false,
+    }
+}
+
+fn check_case_table(table: GcObj) -> Result<&CharTable> {
+    match table.untag() {
+        Object::CharTable(x) if case_table_p(table) => Ok(x),
+        _ => bail!("Wrong type argument: case-table-p, {table}"),
+    }
+}
+
+/// Make TABLE the case table of the current buffer, and return it.
+#[cfg(feature = "buffers")]
+#[defun]
+fn set_case_table<'ob>(table: GcObj<'ob>, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let case = check_case_table(table)?;
+    let buffer = get_current_buffer(env);
+    env.case_tables.insert(buffer.tag(), case.tag());
+    Ok(table)
+}
+
+/// Make TABLE the standard case table, which new buffers use, and return
+/// it.
+#[defun]
+fn set_standard_case_table<'ob>(table: GcObj<'ob>, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let case = check_case_table(table)?;
+    env.standard_case_table.set(case.tag());
+    Ok(table)
+}
+
+defsym!(CASE_TABLE);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_case_table() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(list (case-table-p (standard-case-table))
+                          (eq (current-case-table) (standard-case-table))
+                          (aref (standard-case-table) ?A) (aref (standard-case-table) ?a)
+                          (case-table-p (make-char-table 'case-table))
+                          (case-table-p (make-char-table 'syntax-table)))";
+        assert_eq!(check(form, env, cx).to_string(), "(t t 97 97 t nil)");
+        let case = current_case(env, cx);
+        assert_eq!(case.upcase('a'), 'A');
+        assert_eq!(case.downcase('É'), 'é');
+        assert_eq!(case.upcase('ß'), 'ß');
+        // the up table is the inverse of the down table unless it is set
+        check("(setq table (make-char-table 'case-table))", env, cx);
+        check("(aset table ?x ?y)", env, cx);
+        let table = check("table", env, cx);
+        let Object::CharTable(table) = table.untag() else {unreachable!()};
+        let case = CaseTable::new(table);
+        assert_eq!((case.downcase('x'), case.upcase('y')), ('y', 'x'));
+        assert_eq!((case.downcase('Q'), case.upcase('q')), ('q', 'Q'));
+        let form = "(condition-case err (set-standard-case-table 1) (error err))";
+        assert!(check(form, env, cx).to_string().contains("case-table-p"));
+    }
+}
@@ -0,0 +1,502 @@
+#![cfg(feature = "buffers")]
+//! Category tables, which give each character a set of categories. A
+//! category is a printing ASCII character, such as `a` for ASCII or `C` for
+//! Han, and is matched by `\\cC` in a regexp. Category tables are
+//! char-tables whose values are category sets. There are no bool-vectors, so
+//! a category set is a vector of 128 elements that are t for the categories
+//! in the set. The first extra slot of a table is a vector of the docstrings
+//! of the categories defined in it.
+use crate::buffer::get_current_buffer;
+use crate::chartab::{char_code, char_range};
+use crate::core::env::{sym, Env};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{
+    nil, qtrue, CharMap, CharTable, CharTableData, Gc, GcObj, ObjCell, Object, TagType,
+};
+use anyhow::{bail, Result};
+use fn_macros::defun;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The number of characters that can be categories, from space to `~`.
+const CATEGORY_COUNT: usize = 95;
+
+/// Characters from FROM to TO inclusive.
+type CharRanges = &'static [(u32, u32)];
+
+const HAN: CharRanges = &[
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xF900, 0xFAFF),
+    (0x20000, 0x2FA1F),
+];
+const HIRAGANA: CharRanges = &[(0x3040, 0x309F)];
+const KATAKANA: CharRanges = &[(0x30A0, 0x30FF), (0x31F0, 0x31FF)];
+const HANGUL: CharRanges = &[(0x1100, 0x11FF), (0x3130, 0x318F), (0xAC00, 0xD7AF)];
+const CJK_PUNCTUATION: CharRanges = &[(0x3000, 0x303F)];
+
+/// The categories of the standard table, as `characters.el` defines them
+/// for the scripts that regexps and filling most often look for.
+const STANDARD_CATEGORIES: &[(u8, &str, &[CharRanges])] = &[
+    (
+        b'a',
+        "ASCII\nASCII graphic characters 32-126 (ISO646 IRV:1983[4/0])",
+        &[&[(0x20, 0x7E)]],
+    ),
+    (
+        b'l',
+        "Latin",
+        &[&[(0x20, 0x7E), (0xA0, 0x24F), (0x1E00, 0x1EFF)]],
+    ),
+    (b'g', "Greek", &[&[(0x370, 0x3FF), (0x1F00, 0x1FFF)]]),
+    (b'y', "Cyrillic", &[&[(0x400, 0x52F)]]),
+    (
+        b'^',
+        "Combining\nCombining diacritic or mark",
+        &[&[(0x300, 0x36F)]],
+    ),
+    (
+        b'C',
+        "2-byte han\nChinese (Han) characters of 2-byte character sets",
+        &[HAN],
+    ),
+    (b'c', "Chinese", &[HAN, CJK_PUNCTUATION]),
+    (
+        b'j',
+        "Japanese",
+        &[HAN, HIRAGANA, KATAKANA, CJK_PUNCTUATION],
+    ),
+    (b'h', "Korean", &[HANGUL]),
+    (
+        b'H',
+        "2-byte Hiragana\nJapanese Hiragana characters of 2-byte character sets",
+        &[HIRAGANA],
+    ),
+    (
+        b'K',
+        "2-byte Katakana\nJapanese Katakana characters of 2-byte character sets",
+        &[KATAKANA],
+    ),
+    (
+        b'|',
+        "line breakable\nWhile filling, we can break a line at this character.",
+        &[HAN, HIRAGANA, KATAKANA, HANGUL, CJK_PUNCTUATION],
+    ),
+];
+
+/// The category `chr`, or an error if it is not one.
+fn category(chr: i64) -> Result<u8> {
+    match u8::try_from(chr) {
+        Ok(chr @ 0x20..=0x7E) => Ok(chr),
+        _ => bail!("Wrong type argument: categoryp, {chr}"),
+    }
+}
+
+/// The categories in the category set `set` as bits.
+fn set_mask(set: GcObj) -> Option<u128> {
+    let Object::Vec(set) = set.untag() else {return None};
+    let bits = set.iter().take(128).enumerate();
+    Some(bits.fold(0, |mask, (idx, x)| match x.get().nil() {
+        true => mask,
+        false => mask | 1 << idx,
+    }))
+}
+
+fn make_set<'ob>(mask: u128, cx: &'ob Context) -> GcObj<'ob> {
+    let set: Vec<GcObj> = (0..128)
+        .map(|idx| if mask >> idx & 1 == 1 { qtrue() } else { nil() })
+        .collect();
+    cx.add(set)
+}
+
+/// The categories of every character, taken from a category table and its
+/// parents. This is a copy that does not refer to the heap, so it can be
+/// kept in compiled regexps.
+#[derive(Debug, Default)]
+pub(crate) struct CategoryTable {
+    /// The entries and default value of the table and each of its ancestors
+    levels: Vec<(CharMap<Option<u128>>, Option<u128>)>,
+}
+
+impl CategoryTable {
+    fn new(mut table: &CharTable) -> Self {
+        let mut levels = Vec::new();
+        loop {
+            let data = table.borrow();
+            let entries = data.entries.map(|x| set_mask(*x));
+            levels.push((entries, set_mask(data.default)));
+            match table.parent() {
+                Some(parent) => table = parent,
+                None => return Self { levels },
+            }
+        }
+    }
+
+    /// Whether `chr` has the category `category`.
+    pub(crate) fn has(&self, chr: char, category: u8) -> bool {
+        let mask = self
+            .levels
+            .iter()
+            .find_map(|(entries, default)| entries.get(chr.into()).copied().flatten().or(*default));
+        mask.is_some_and(|x| x >> category & 1 == 1)
+    }
+}
+
+/// The address of a category table and the number of char-table changes
+/// when a copy of it was made.
+type CategoryKey = (usize, usize);
+
+thread_local! {
+    /// The copy of the category table that was last used.
+    static CATEGORY_CACHE: RefCell<Option<(CategoryKey, Rc<CategoryTable>)>> = const { RefCell::new(None) };
+}
+
+/// The runs of characters from `from` to `to` whose category set changes
+/// when the category `category` is added, or removed if `reset` is true,
+/// along with their new sets as bits.
+fn modified_runs(
+    data: &CharTableData,
+    (from, to): (u32, u32),
+    category: u8,
+    reset: bool,
+) -> Vec<(u32, u32, u128)> {
+    let default = set_mask(data.default).unwrap_or(0);
+    let bit = 1 << category;
+    let runs = data.entries.runs(from, to).into_iter();
+    runs.filter_map(|(beg, end, x)| {
+        let mask = x.and_then(|x| set_mask(*x)).unwrap_or(default);
+        let new = if reset { mask & !bit } else { mask | bit };
+        (new != mask).then_some((beg, end, new))
+    })
+    .collect()
+}
+
+/// Add the category `category` to the characters from `from` to `to`, or
+/// remove it if `reset` is true. Runs of characters that shared a set get a
+/// new set, so sets are never changed.
+fn modify_entry<'ob>(
+    data: &mut CharTableData<'ob>,
+    range: (u32, u32),
+    category: u8,
+    reset: bool,
+    cx: &'ob Context,
+) {
+    for (beg, end, mask) in modified_runs(data, range, category, reset) {
+        data.entries.set_range(beg, end, make_set(mask, cx));
+    }
+}
+
+/// A category table with no categories.
+fn new_table<'ob>(cx: &'ob Context) -> CharTableData<'ob> {
+    let mut data = CharTableData::new(sym::CATEGORY_TABLE.into(), make_set(0, cx), 2);
+    data.extras[0] = cx.add(vec![nil(); CATEGORY_COUNT]);
+    data
+}
+
+/// The standard category table, which is created the first time it is
+/// needed.
+#[defun]
+pub(crate) fn standard_category_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    if let Some(table) = env.standard_category_table.as_ref() {
+        return table.bind(cx).untag();
+    }
+    let mut data = new_table(cx);
+    let mut docstrings = vec![nil(); CATEGORY_COUNT];
+    for (category, docstring, ranges) in STANDARD_CATEGORIES {
+        docstrings[usize::from(*category - b' ')] = cx.add(*docstring);
+        for range in ranges.iter().copied().flatten() {
+            modify_entry(&mut data, *range, *category, false, cx);
+        }
+    }
+    data.extras[0] = cx.add(docstrings);
+    let table: Gc<&CharTable> = cx.add_as(data);
+    env.standard_category_table.set(table);
+    table.untag()
+}
+
+/// Return the category table of the current buffer.
+#[defun]
+pub(crate) fn category_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    let buffer = get_current_buffer(env);
+    match env.category_tables.get(buffer.tag()) {
+        Some(table) => table.bind(cx).untag(),
+        None => standard_category_table(env, cx),
+    }
+}
+
+/// The categories of the current buffer, as a copy of its category table
+/// that is only made again when a char-table changes.
+pub(crate) fn current_categories(env: &mut Rt<Env>, cx: &Context) -> Rc<CategoryTable> {
+    let table = category_table(env, cx);
+    let key = (std::ptr::from_ref(table) as usize, CharTable::changes());
+    CATEGORY_CACHE.with_borrow_mut(|cache| match cache {
+        Some((cached, categories)) if *cached == key => categories.clone(),
+        _ => {
+            let categories = Rc::new(CategoryTable::new(table));
+            *cache = Some((key, categories.clone()));
+            categories
+        }
+    })
+}
+
+#[defun]
+fn category_table_p(object: GcObj) -> bool {
+    match object.untag() {
+        Object::CharTable(table) => table.borrow().subtype == sym::CATEGORY_TABLE,
+        _ => false,
+    }
+}
+
+/// TABLE as a category table, or the table of the current buffer if it is
+/// nil.
+fn table_arg<'ob>(
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'ob CharTable> {
+    match table {
+        None => Ok(category_table(env, cx)),
+        Some(table) => match table.untag() {
+            Object::CharTable(x) if category_table_p(table) => Ok(x),
+            _ => bail!("Wrong type argument: category-table-p, {table}"),
+        },
+    }
+}
+
+/// The docstring of CATEGORY in `table`, which is nil if it is not defined.
+fn docstring(table: &CharTable, category: u8) -> GcObj<'_> {
+    match table.borrow().extras.first().map(|x| x.untag()) {
+        Some(Object::Vec(docstrings)) => docstrings
+            .get(usize::from(category - b' '))
+            .map_or_else(nil, ObjCell::get),
+        _ => nil(),
+    }
+}
+
+/// Make the current buffer use TABLE as its category table, and return it.
+#[defun]
+fn set_category_table<'ob>(
+    table: GcObj<'ob>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<GcObj<'ob>> {
+    let category = table_arg(Some(table), env, cx)?;
+    let buffer = get_current_buffer(env);
+    env.category_tables.insert(buffer.tag(), category.tag());
+    Ok(table)
+}
+
+/// Return a category table with no categories.
+#[defun]
+fn make_category_table<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    cx.add(new_table(cx))
+}
+
+/// Return a copy of TABLE, or of the standard category table if it is nil.
+#[defun]
+fn copy_category_table<'ob>(
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let table = match table {
+        Some(table) => table_arg(Some(table), env, cx)?,
+        None => standard_category_table(env, cx),
+    };
+    let data = table.borrow();
+    let mut extras = data.extras.clone();
+    if let Some(Object::Vec(docstrings)) = extras.first().map(|x| x.untag()) {
+        extras[0] = cx.add(docstrings.clone_vec());
+    }
+    let copy = CharTableData {
+        subtype: data.subtype,
+        parent: data.parent,
+        default: data.default,
+        extras,
+        entries: data.entries.clone(),
+    };
+    Ok(cx.add(copy))
+}
+
+/// Return a category set with the categories in the string CATEGORIES.
+#[defun]
+fn make_category_set<'ob>(categories: &str, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let mut mask = 0;
+    for chr in categories.chars() {
+        mask |= 1 << category(u32::from(chr).into())?;
+    }
+    Ok(make_set(mask, cx))
+}
+
+/// Define CATEGORY as a category with DOCSTRING in TABLE, or the category
+/// table of the current buffer if it is nil.
+#[defun]
+fn define_category<'ob>(
+    category: i64,
+    docstring: GcObj<'ob>,
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<bool> {
+    let category = self::category(category)?;
+    let _: &str = docstring.try_into()?;
+    let table = table_arg(table, env, cx)?;
+    if !self::docstring(table, category).nil() {
+        bail!("Category `{}' is already defined", char::from(category));
+    }
+    let mut data = table.borrow_mut();
+    let Some(slot) = data.extras.first_mut() else {bail!("Invalid category table: {table}")};
+    if !matches!(slot.untag(), Object::Vec(_)) {
+        *slot = cx.add(vec![nil(); CATEGORY_COUNT]);
+    }
+    let Object::Vec(docstrings) = slot.untag() else {unreachable!()};
+    docstrings.try_mut()?[usize::from(category - b' ')].set(docstring);
+    Ok(false)
+}
+
+/// Return the docstring of CATEGORY in TABLE, or the category table of the
+/// current buffer if it is nil.
+#[defun]
+fn category_docstring<'ob>(
+    category: i64,
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let category = self::category(category)?;
+    Ok(docstring(table_arg(table, env, cx)?, category))
+}
+
+/// Return a category that is not defined in TABLE, or the category table
+/// of the current buffer if it is nil. Return nil if every category is.
+#[defun]
+fn get_unused_category<'ob>(
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let table = table_arg(table, env, cx)?;
+    let unused = (b' '..=b'~').find(|x| docstring(table, *x).nil());
+    Ok(unused.map_or_else(nil, |x| i64::from(x).into()))
+}
+
+/// Return the category set of CHAR in the category table of the current
+/// buffer.
+#[defun]
+fn char_category_set<'ob>(char: i64, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let chr = char_code(char)?;
+    let set = category_table(env, cx).get(chr);
+    Ok(if set.nil() { make_set(0, cx) } else { set })
+}
+
+/// Return a string of the categories in CATEGORY-SET.
+#[defun]
+fn category_set_mnemonics(category_set: GcObj) -> Result<String> {
+    let Some(mask) = set_mask(category_set) else {bail!("Wrong type argument: categorysetp, {category_set}")};
+    let categories = (b' '..=b'~').filter(|x| mask >> x & 1 == 1);
+    Ok(categories.map(char::from).collect())
+}
+
+/// Add CATEGORY to the category set of CHARACTER in TABLE, or the category
+/// table of the current buffer if it is nil. CHARACTER can be a cons
+/// (FROM . TO) for the characters from FROM to TO inclusive. If RESET is
+/// non-nil, CATEGORY is removed instead.
+#[defun]
+fn modify_category_entry(
+    character: GcObj,
+    category: i64,
+    table: Option<GcObj>,
+    reset: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let range = char_range(character)?;
+    let category = self::category(category)?;
+    let table = table_arg(table, env, cx)?;
+    if docstring(table, category).nil() {
+        bail!("Undefined category: {}", char::from(category));
+    }
+    // The new sets are made before the table is borrowed mutably, because
+    // allocating can trace the table under `gc_stress`
+    let runs = modified_runs(&table.borrow(), range, category, reset.is_some());
+    let runs: Vec<_> = runs
+        .into_iter()
+        .map(|(beg, end, mask)| (beg, end, make_set(mask, cx)))
+        .collect();
+    let mut data = table.borrow_mut();
+    for (beg, end, set) in runs {
+        data.entries.set_range(beg, end, set);
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_category_table() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(list (category-set-mnemonics (char-category-set ?a))
+                          (category-set-mnemonics (char-category-set 20013))
+                          (category-set-mnemonics (char-category-set 12354))
+                          (category-set-mnemonics (char-category-set 10))
+                          (category-docstring ?l) (aref (char-category-set ?b) ?a)
+                          (category-table-p (category-table))
+                          (category-set-mnemonics (make-category-set \"zab\")))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(\"al\" \"Ccj|\" \"Hj|\" \"\" \"Latin\" t t \"abz\")"
+        );
+        check("(set-buffer (get-buffer-create \"category\"))", env, cx);
+        check("(set-category-table (copy-category-table))", env, cx);
+        let form = "(let ((cat (get-unused-category)))
+                      (define-category cat \"Test\")
+                      (modify-category-entry '(?b . ?d) cat)
+                      (modify-category-entry ?c cat nil t)
+                      (modify-category-entry ?b ?a nil t)
+                      (list cat (category-docstring cat)
+                            (category-set-mnemonics (char-category-set ?b))
+                            (category-set-mnemonics (char-category-set ?c))
+                            (category-set-mnemonics (char-category-set ?d))
+                            (category-set-mnemonics
+                             (aref (standard-category-table) ?b))))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(32 \"Test\" \" l\" \"al\" \" al\" \"al\")"
+        );
+        if cfg!(feature = "regex") {
+            let form = "(list (string-match \"\\\\cj+\" \"abc 日本語\") (match-end 0)
+                              (string-match \"\\\\Ca\" \"ab日\") (string-match \"\\\\c \" \"ab c\"))";
+            assert_eq!(check(form, env, cx).to_string(), "(4 7 1 1)");
+        }
+        let form = "(condition-case err (define-category ?a \"again\") (error err))";
+        assert!(check(form, env, cx).to_string().contains("already defined"));
+        let form = "(condition-case err (modify-category-entry ?a ?Q) (error err))";
+        assert!(check(form, env, cx)
+            .to_string()
+            .contains("Undefined category"));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let mut data = new_table(cx);
+        modify_entry(&mut data, (10, 20), b'x', false, cx);
+        modify_entry(&mut data, (15, 30), b'y', false, cx);
+        modify_entry(&mut data, (12, 12), b'x', true, cx);
+        let mask = |chr: u32| data.entries.get(chr).and_then(|x| set_mask(*x));
+        let (x, y) = (1 << b'x', 1 << b'y');
+        assert_eq!(mask(9), None);
+        assert_eq!(mask(11), Some(x));
+        assert_eq!(mask(12), Some(0));
+        assert_eq!(mask(16), Some(x | y));
+        assert_eq!(mask(25), Some(y));
+    }
+}
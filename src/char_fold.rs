@@ -0,0 +1,249 @@
+#![cfg(feature = "regex")]
+//! Character folding for searches. A character matches the characters
+//! whose decomposition is that character followed by combining marks, so
+//! searching for `a` finds `á` and `ａ`, and a sequence of characters
+//! matches the characters that decompose to it, like `ﬁ` for "fi". The
+//! table is built from the decompositions of the Unicode database the
+//! first time it is needed.
+use crate::core::env::{sym, Env};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, CharTable, CharTableData, Gc, GcObj};
+use crate::fns::slice_into_list;
+use crate::hashmap::HashMap;
+use crate::search::regexp_quote;
+use crate::ucs_normalize::{normalize, Form};
+use crate::unidata::{combining_class, decomposable_chars, general_category};
+use fn_macros::defun;
+use lazy_static::lazy_static;
+
+/// Characters that match a character although they do not decompose to
+/// it: the quotation marks that match the ASCII ones.
+const DEFAULT_INCLUDE: &[(char, &str)] =
+    &[('"', "“”„‟«»〝〞〟❝❞"), ('\'', "‘’‚‛‹›❛❜"), ('`', "‘‛❛")];
+
+/// A regexp longer than this is not used, as it would overflow the stack
+/// of the matcher; the string is searched for literally instead.
+const MAX_REGEXP_LENGTH: usize = 5000;
+
+/// Sequences of characters after their first character, each with the
+/// characters that match it.
+type Sequences = Vec<(Vec<char>, Vec<char>)>;
+
+/// The characters that match each character or sequence of characters
+/// besides itself.
+#[derive(Debug, Default)]
+struct FoldTable {
+    chars: HashMap<char, Vec<char>>,
+    /// Sequences of two or more characters, by their first character, with
+    /// the rest of the sequence
+    multi: HashMap<char, Sequences>,
+    /// The character that each character in `chars` matches
+    bases: HashMap<char, char>,
+}
+
+fn is_mark(chr: char) -> bool {
+    let code = u32::from(chr);
+    combining_class(code) != 0 || general_category(code).starts_with('M')
+}
+
+impl FoldTable {
+    fn new() -> Self {
+        let mut table = Self::default();
+        for chr in decomposable_chars().filter_map(char::from_u32) {
+            let decomposed = normalize(&chr.to_string(), Form::Nfkd);
+            let base: Vec<char> = decomposed.chars().filter(|x| !is_mark(*x)).collect();
+            match base[..] {
+                [] => {}
+                [single] if single == chr => {}
+                [single] => table.add(single, chr),
+                [first, ref rest @ ..] => {
+                    let entries = table.multi.entry(first).or_default();
+                    match entries.iter_mut().find(|x| x.0 == rest) {
+                        Some(entry) => entry.1.push(chr),
+                        None => entries.push((rest.to_vec(), vec![chr])),
+                    }
+                }
+            }
+        }
+        for (base, chars) in DEFAULT_INCLUDE {
+            for chr in chars.chars() {
+                table.add(*base, chr);
+            }
+        }
+        for chars in table.chars.values_mut() {
+            chars.sort_unstable();
+            chars.dedup();
+        }
+        table
+    }
+
+    fn add(&mut self, base: char, chr: char) {
+        self.chars.entry(base).or_default().push(chr);
+        self.bases.insert(chr, base);
+    }
+
+    /// The characters that `chr` matches, starting with itself. If
+    /// `symmetric` is true, a character that matches another also matches
+    /// everything that the other one does.
+    fn class(&self, chr: char, symmetric: bool) -> Vec<char> {
+        let base = match self.bases.get(&chr) {
+            Some(base) if symmetric => *base,
+            _ => chr,
+        };
+        let mut class = vec![chr];
+        if base != chr {
+            class.push(base);
+        }
+        let others = self.chars.get(&base).into_iter().flatten();
+        class.extend(others.filter(|x| **x != chr));
+        class
+    }
+
+    fn to_regexp(&self, chars: &[char], lax: bool, symmetric: bool) -> String {
+        // the regexps of the suffixes of `chars`, from the longest
+        let mut suffixes = vec![String::new(); chars.len() + 1];
+        for (idx, chr) in chars.iter().enumerate().rev() {
+            let mut regexp = alternatives(&self.class(*chr, symmetric));
+            if lax && *chr == ' ' {
+                regexp.push('+');
+            }
+            regexp.push_str(&suffixes[idx + 1]);
+            let rest = &chars[idx + 1..];
+            let multi = self.multi.get(chr).into_iter().flatten();
+            let mut branches: Vec<String> = multi
+                .filter(|x| rest.starts_with(&x.0))
+                .map(|(seq, chars)| alternatives(chars) + &suffixes[idx + 1 + seq.len()])
+                .collect();
+            if !branches.is_empty() {
+                branches.push(regexp);
+                regexp = format!("\\(?:{}\\)", branches.join("\\|"));
+            }
+            suffixes[idx] = regexp;
+        }
+        std::mem::take(&mut suffixes[0])
+    }
+}
+
+/// A regexp that matches any one of `chars`.
+fn alternatives(chars: &[char]) -> String {
+    if let [chr] = chars {
+        return regexp_quote(&chr.to_string());
+    }
+    // `]` has to come first in a bracket expression, `-` last and `^`
+    // anywhere but first
+    let mut set = String::from("[");
+    if chars.contains(&']') {
+        set.push(']');
+    }
+    set.extend(chars.iter().filter(|x| !"]^-".contains(**x)));
+    for special in ['^', '-'] {
+        if chars.contains(&special) {
+            set.push(special);
+        }
+    }
+    set.push(']');
+    set
+}
+
+lazy_static! {
+    static ref FOLD_TABLE: FoldTable = FoldTable::new();
+}
+
+/// Return a regexp that matches STRING with its characters folded: each
+/// character also matches the characters that decompose to it, like `á`
+/// for `a`. If `char-fold-symmetric` is non-nil, `á` also matches `a`. If
+/// LAX is non-nil, a space matches any number of spaces. FROM is the index
+/// of the character of STRING to start at.
+#[defun]
+fn char_fold_to_regexp(
+    string: &str,
+    lax: Option<()>,
+    from: Option<usize>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> String {
+    let symmetric = env.vars.get(sym::CHAR_FOLD_SYMMETRIC);
+    let symmetric = symmetric.is_some_and(|x| !x.bind(cx).nil());
+    let chars: Vec<char> = string.chars().skip(from.unwrap_or(0)).collect();
+    let regexp = FOLD_TABLE.to_regexp(&chars, lax.is_some(), symmetric);
+    if regexp.len() > MAX_REGEXP_LENGTH {
+        return regexp_quote(&chars.iter().collect::<String>());
+    }
+    regexp
+}
+
+/// Return a new char-fold table. It is a char-table that maps each
+/// character to a regexp of the characters it matches. Its extra slot is a
+/// char-table that maps the first character of each sequence of
+/// characters that other characters match to an alist of the rest of the
+/// sequence and a regexp of those characters.
+#[defun]
+fn char_fold_make_table<'ob>(cx: &'ob Context) -> Gc<&'ob CharTable> {
+    let table = &*FOLD_TABLE;
+    let mut multi = CharTableData::new(sym::CHAR_FOLD_TABLE.into(), nil(), 0);
+    for (first, entries) in &table.multi {
+        let alist: Vec<GcObj> = entries
+            .iter()
+            .map(|(rest, chars)| {
+                let rest: String = rest.iter().collect();
+                cons!(rest, alternatives(chars); cx)
+            })
+            .collect();
+        multi
+            .entries
+            .set((*first).into(), slice_into_list(&alist, None, cx));
+    }
+    let multi: Gc<&CharTable> = cx.add_as(multi);
+    let mut data = CharTableData::new(sym::CHAR_FOLD_TABLE.into(), nil(), 1);
+    data.extras[0] = multi.into();
+    for base in table.chars.keys() {
+        let regexp = alternatives(&table.class(*base, false));
+        data.entries.set((*base).into(), cx.add(regexp));
+    }
+    cx.add_as(data)
+}
+
+defsym!(CHAR_FOLD_TABLE);
+defvar!(CHAR_FOLD_SYMMETRIC);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_char_fold() {
+        let table = &*FOLD_TABLE;
+        let regexp = |string: &str| {
+            let chars: Vec<char> = string.chars().collect();
+            table.to_regexp(&chars, false, false)
+        };
+        assert!(table.class('a', false).contains(&'á'));
+        assert!(table.class('a', false).contains(&'ａ'));
+        assert_eq!(table.class('á', false), ['á']);
+        assert!(table.class('á', true).contains(&'à'));
+        assert_eq!(regexp("."), "[.․﹒．]");
+        assert!(regexp("a").starts_with("[aª"));
+        assert!(regexp("fi").starts_with("\\(?:ﬁ\\|["));
+        assert_eq!(alternatives(&['-', 'x', ']', '^']), "[]x^-]");
+    }
+
+    #[test]
+    fn test_char_fold_search() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(list (string-match (char-fold-to-regexp \"cafe\") \"un café\")
+                          (string-match (char-fold-to-regexp \"file\") \"a ﬁle\")
+                          (string-match (char-fold-to-regexp \"\\\"x\\\"\") \"“x”\")
+                          (string-match (char-fold-to-regexp \"é\") \"e\")
+                          (string-match (char-fold-to-regexp \"a b\" t) \"a   b\")
+                          (string-match (char-fold-to-regexp \"xcafe\" nil 1) \"café\")
+                          (char-table-p (char-fold-make-table)))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(result.to_string(), "(3 2 0 nil 0 0 t)");
+    }
+}
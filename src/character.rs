@@ -1,5 +1,8 @@
-use crate::core::object::Gc;
-use anyhow::Result;
+use crate::core::env::{sym, Env};
+use crate::core::error::EvalError;
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, Gc, GcObj};
+use anyhow::{bail, Result};
 use fn_macros::defun;
 
 #[defun]
@@ -7,3 +10,187 @@ fn unibyte_string(bytes: &[Gc<i64>]) -> Result<Vec<u8>> {
     let unibyte: Result<Vec<u8>, _> = bytes.iter().map(|x| u8::try_from(x.untag())).collect();
     Ok(unibyte?)
 }
+
+pub(crate) fn int_to_char(chr: i64) -> Result<char> {
+    match u32::try_from(chr).ok().and_then(char::from_u32) {
+        Some(chr) => Ok(chr),
+        None => bail!("Invalid character: {chr}"),
+    }
+}
+
+/// Characters that take no columns, like combining marks and zero-width
+/// spaces.
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036F),
+    (0x0483, 0x0489),
+    (0x0591, 0x05BD),
+    (0x0610, 0x061A),
+    (0x064B, 0x065F),
+    (0x200B, 0x200F),
+    (0x202A, 0x202E),
+    (0x2060, 0x2064),
+    (0x20D0, 0x20FF),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+    (0xFEFF, 0xFEFF),
+];
+
+/// Characters that take two columns: the East Asian wide and fullwidth
+/// characters and emoji.
+const DOUBLE_WIDTH: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x2329, 0x232A),
+    (0x23E9, 0x23EC),
+    (0x23F0, 0x23F0),
+    (0x23F3, 0x23F3),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267F, 0x267F),
+    (0x2693, 0x2693),
+    (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x26CE, 0x26CE),
+    (0x26D4, 0x26D4),
+    (0x26EA, 0x26EA),
+    (0x26F2, 0x26F3),
+    (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA),
+    (0x26FD, 0x26FD),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x274E, 0x274E),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27B0, 0x27B0),
+    (0x27BF, 0x27BF),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B50),
+    (0x2B55, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x16FE0, 0x16FE4),
+    (0x17000, 0x18AFF),
+    (0x1B000, 0x1B2FF),
+    (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF),
+    (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F64F),
+    (0x1F680, 0x1F6FF),
+    (0x1F900, 0x1F9FF),
+    (0x20000, 0x2FFFD),
+    (0x30000, 0x3FFFD),
+];
+
+fn in_table(table: &[(u32, u32)], chr: char) -> bool {
+    let code = u32::from(chr);
+    let idx = table.partition_point(|x| x.1 < code);
+    table.get(idx).is_some_and(|x| x.0 <= code)
+}
+
+/// The number of columns `chr` takes when displayed. Control characters are
+/// displayed as `^C`, so they take two.
+pub(crate) fn char_columns(chr: char) -> usize {
+    if chr.is_ascii_control() {
+        2
+    } else if in_table(ZERO_WIDTH, chr) {
+        0
+    } else if in_table(DOUBLE_WIDTH, chr) {
+        2
+    } else {
+        1
+    }
+}
+
+/// The number of columns CHAR takes when displayed.
+#[defun]
+fn char_width(char: i64) -> Result<usize> {
+    Ok(char_columns(int_to_char(char)?))
+}
+
+/// The width in pixels of a column. Without a graphical frame, every column
+/// is one canonical character wide.
+const CANONICAL_CHAR_WIDTH: usize = 1;
+
+/// The number of columns STRING takes when displayed, from character FROM
+/// to TO. Negative positions count from the end of STRING.
+#[defun]
+fn string_width(
+    string: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let len = string.chars().count() as i64;
+    let resolve = |pos: i64| if pos < 0 { pos + len } else { pos };
+    let (beg, end) = (resolve(from.unwrap_or(0)), resolve(to.unwrap_or(len)));
+    if !(0 <= beg && beg <= end && end <= len) {
+        let arg = |pos: Option<i64>| pos.map_or_else(nil, GcObj::from);
+        let data = list![string, arg(from), arg(to); cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    }
+    let chars = string.chars().skip(beg as usize).take((end - beg) as usize);
+    Ok(chars.map(char_columns).sum())
+}
+
+/// The width of STRING in pixels, which is its width in columns times the
+/// width of a canonical character.
+#[defun]
+fn string_pixel_width(string: &str, _buffer: Option<()>) -> usize {
+    let width: usize = string.chars().map(char_columns).sum();
+    width * CANONICAL_CHAR_WIDTH
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_string_width() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        assert_eq!(string_width("abc", None, None, env, cx).unwrap(), 3);
+        assert_eq!(string_width("a日本", None, None, env, cx).unwrap(), 5);
+        assert_eq!(
+            string_width("a日本", Some(1), Some(-1), env, cx).unwrap(),
+            2
+        );
+        assert_eq!(string_width("e\u{301}", None, None, env, cx).unwrap(), 1);
+        let form = "(list (condition-case err (string-width \"abc\" 2 1) (error err))
+                          (condition-case err (string-width \"abc\" -4) (error err)))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "((args-out-of-range \"abc\" 2 1) (args-out-of-range \"abc\" -4 nil))"
+        );
+        for string in ["", "a", "a日", "a日\u{301}b"] {
+            let width = string_width(string, None, None, env, cx).unwrap();
+            assert_eq!(
+                string_pixel_width(string, None),
+                width * CANONICAL_CHAR_WIDTH
+            );
+        }
+    }
+}
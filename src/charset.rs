@@ -0,0 +1,203 @@
+//! Character sets. Every character is a Unicode code point or, above the
+//! Unicode range, one of the characters of Emacs, whose last 128 are the
+//! raw bytes of the `eight-bit` charset. Only the charsets that map code
+//! points directly to those characters are defined.
+use crate::core::env::intern;
+use crate::core::gc::Context;
+use crate::core::object::{nil, Gc, GcObj, Object};
+use anyhow::{bail, Result};
+use fn_macros::defun;
+
+/// The largest character.
+pub(crate) const MAX_CHAR: u32 = 0x3F_FFFF;
+
+/// A charset whose code points from `min` to `max` are the characters
+/// `offset` above them.
+#[derive(Debug)]
+struct Charset {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    offset: u32,
+}
+
+impl Charset {
+    fn encode(&self, chr: u32) -> Option<u32> {
+        let code = chr.checked_sub(self.offset)?;
+        (self.min <= code && code <= self.max).then_some(code)
+    }
+
+    fn decode(&self, code: u32) -> Option<u32> {
+        (self.min <= code && code <= self.max).then_some(code + self.offset)
+    }
+}
+
+/// The charsets, in order of priority.
+const CHARSETS: &[Charset] = &[
+    Charset {
+        name: "unicode",
+        min: 0,
+        max: 0x10_FFFF,
+        offset: 0,
+    },
+    Charset {
+        name: "ascii",
+        min: 0,
+        max: 0x7F,
+        offset: 0,
+    },
+    Charset {
+        name: "iso-8859-1",
+        min: 0,
+        max: 0xFF,
+        offset: 0,
+    },
+    Charset {
+        name: "unicode-bmp",
+        min: 0,
+        max: 0xFFFF,
+        offset: 0,
+    },
+    Charset {
+        name: "emacs",
+        min: 0,
+        max: 0x3F_FF7F,
+        offset: 0,
+    },
+    Charset {
+        name: "eight-bit",
+        min: 0x80,
+        max: 0xFF,
+        offset: 0x3F_FF00,
+    },
+];
+
+fn find_charset(object: GcObj) -> Option<&'static Charset> {
+    match object.untag() {
+        Object::Symbol(sym) => CHARSETS.iter().find(|x| x.name == sym.name()),
+        _ => None,
+    }
+}
+
+fn check_charset(object: GcObj) -> Result<&'static Charset> {
+    match find_charset(object) {
+        Some(charset) => Ok(charset),
+        None => bail!("Wrong type argument: charsetp, {object}"),
+    }
+}
+
+pub(crate) fn check_char(chr: i64) -> Result<u32> {
+    match u32::try_from(chr) {
+        Ok(chr) if chr <= MAX_CHAR => Ok(chr),
+        _ => bail!("Wrong type argument: characterp, {chr}"),
+    }
+}
+
+/// Return t if OBJECT is a charset.
+#[defun]
+fn charsetp(object: GcObj) -> bool {
+    find_charset(object).is_some()
+}
+
+/// Return the charset of highest priority that contains CH. An ASCII
+/// character is in `ascii` and a raw byte in `eight-bit`. If RESTRICTION is
+/// a list of charsets, only they are considered, and nil is returned if
+/// none contains CH.
+#[defun]
+fn char_charset<'ob>(ch: i64, restriction: Option<GcObj>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let chr = check_char(ch)?;
+    let charset = match restriction.map(Gc::untag) {
+        Some(Object::Cons(list)) => {
+            let mut charset = None;
+            for elem in list.elements() {
+                match find_charset(elem?) {
+                    Some(x) if x.encode(chr).is_some() => {
+                        charset = Some(x);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            charset
+        }
+        _ if chr < 0x80 => Some(&CHARSETS[1]),
+        _ if chr > CHARSETS[4].max => Some(&CHARSETS[5]),
+        _ => CHARSETS.iter().find(|x| x.encode(chr).is_some()),
+    };
+    match charset {
+        Some(charset) => Ok(intern(charset.name, cx).into()),
+        None => Ok(nil()),
+    }
+}
+
+/// Return the code point of CH in CHARSET, or nil if CHARSET does not
+/// contain it.
+#[defun]
+fn encode_char(ch: i64, charset: GcObj) -> Result<GcObj> {
+    let chr = check_char(ch)?;
+    let code = check_charset(charset)?.encode(chr);
+    Ok(code.map_or_else(nil, |x| i64::from(x).into()))
+}
+
+/// Return the character of CODE-POINT in CHARSET, or nil if it is not a
+/// code point of CHARSET.
+#[defun]
+fn decode_char(charset: GcObj, code_point: i64) -> Result<GcObj> {
+    let charset = check_charset(charset)?;
+    let chr = u32::try_from(code_point)
+        .ok()
+        .and_then(|x| charset.decode(x));
+    Ok(chr.map_or_else(nil, |x| i64::from(x).into()))
+}
+
+/// Return the list of charsets in order of priority. If HIGHESTP is
+/// non-nil, only the charset of highest priority is returned.
+#[defun]
+fn charset_priority_list<'ob>(highestp: Option<()>, cx: &'ob Context) -> GcObj<'ob> {
+    let count = if highestp.is_some() {
+        1
+    } else {
+        CHARSETS.len()
+    };
+    let names: Vec<GcObj> = CHARSETS[..count]
+        .iter()
+        .map(|x| intern(x.name, cx).into())
+        .collect();
+    crate::fns::slice_into_list(&names, None, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_charsets() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let name = |chr: u32, restriction: Option<GcObj>| {
+            char_charset(chr.into(), restriction, cx)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(name('a'.into(), None), "ascii");
+        assert_eq!(name('é'.into(), None), "unicode");
+        assert_eq!(name(0x3F_FFA0, None), "eight-bit");
+        assert_eq!(name(0x20_0000, None), "emacs");
+        let latin = crate::list![intern("iso-8859-1", cx); cx];
+        assert_eq!(name('é'.into(), Some(latin)), "iso-8859-1");
+        assert_eq!(name('中'.into(), Some(latin)), "nil");
+        let eight_bit = intern("eight-bit", cx).into();
+        assert!(charsetp(eight_bit));
+        assert_eq!(encode_char(0x3F_FFA0, eight_bit).unwrap(), 0xA0);
+        assert_eq!(decode_char(eight_bit, 0xA0).unwrap(), 0x3F_FFA0);
+        assert_eq!(decode_char(eight_bit, 0x20).unwrap(), nil());
+        let ascii = intern("ascii", cx).into();
+        assert_eq!(
+            encode_char(i64::from(u32::from('é')), ascii).unwrap(),
+            nil()
+        );
+        assert!(encode_char(1, intern("latin-1", cx).into()).is_err());
+        assert_eq!(charset_priority_list(Some(()), cx).to_string(), "(unicode)");
+    }
+}
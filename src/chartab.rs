@@ -0,0 +1,193 @@
+//! Char-tables, which map every character to a value.
+use crate::core::env::{sym, Env, Symbol};
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, CharTable, CharTableData, GcObj, Object};
+use anyhow::{bail, Result};
+use fn_macros::defun;
+
+/// The largest character code.
+pub(crate) const MAX_CHAR: u32 = 0x3F_FFFF;
+
+/// The character code `chr`, or an error if it is not a character.
+pub(crate) fn char_code(chr: i64) -> Result<u32> {
+    match u32::try_from(chr) {
+        Ok(chr) if chr <= MAX_CHAR => Ok(chr),
+        _ => bail!("Invalid character: {chr}"),
+    }
+}
+
+/// The characters from FROM to TO, given as a character or a cons of them.
+pub(crate) fn char_range(range: GcObj) -> Result<(u32, u32)> {
+    match range.untag() {
+        Object::Int(chr) => Ok((char_code(chr)?, char_code(chr)?)),
+        Object::Cons(cons) => {
+            let from = char_code(cons.car().try_into()?)?;
+            let to = char_code(cons.cdr().try_into()?)?;
+            Ok((from, to))
+        }
+        _ => Err(TypeError::new(Type::Int, range).into()),
+    }
+}
+
+/// The number of extra slots of the char-tables whose purpose Emacs defines
+/// the `char-table-extra-slots` property for.
+fn default_extra_slots(purpose: Symbol) -> usize {
+    match purpose {
+        sym::CASE_TABLE => crate::casetab::CASE_TABLE_EXTRA_SLOTS,
+        // the docstrings of the categories and a version
+        sym::CATEGORY_TABLE => 2,
+        // the table of sequences of characters
+        sym::CHAR_FOLD_TABLE => 1,
+        _ => 0,
+    }
+}
+
+/// Return a new char-table with subtype PURPOSE, whose elements are INIT.
+/// The number of extra slots is the `char-table-extra-slots` property of
+/// PURPOSE, or the number Emacs gives the tables it defines.
+#[defun]
+fn make_char_table<'ob>(
+    purpose: Symbol<'ob>,
+    init: Option<GcObj<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let extras = match crate::data::get(purpose, sym::CHAR_TABLE_EXTRA_SLOTS, env, cx).untag() {
+        Object::Int(n @ 0..=10) => n as usize,
+        Object::NIL => default_extra_slots(purpose),
+        x => bail!("Invalid number of extra slots: {x}"),
+    };
+    let table = CharTableData::new(purpose.into(), init.unwrap_or_else(nil), extras);
+    Ok(cx.add(table))
+}
+
+#[defun]
+fn char_table_p(object: GcObj) -> bool {
+    matches!(object.untag(), Object::CharTable(_))
+}
+
+#[defun]
+fn char_table_subtype(char_table: &CharTable) -> GcObj {
+    char_table.borrow().subtype
+}
+
+#[defun]
+fn char_table_parent(char_table: &CharTable) -> GcObj {
+    char_table.borrow().parent
+}
+
+/// Set the parent of CHAR-TABLE to PARENT, which is a char-table or nil.
+#[defun]
+fn set_char_table_parent<'ob>(
+    char_table: &'ob CharTable,
+    parent: Option<&'ob CharTable>,
+) -> Result<GcObj<'ob>> {
+    let mut ancestor = parent;
+    while let Some(table) = ancestor {
+        if table == char_table {
+            bail!("Attempt to make a chartable be its own parent");
+        }
+        ancestor = table.parent();
+    }
+    let parent = parent.map_or_else(nil, Into::into);
+    char_table.borrow_mut().parent = parent;
+    Ok(parent)
+}
+
+#[defun]
+fn char_table_extra_slot(char_table: &CharTable, n: usize) -> Result<GcObj> {
+    match char_table.borrow().extras.get(n) {
+        Some(value) => Ok(*value),
+        None => bail!("Args out of range: {char_table}, {n}"),
+    }
+}
+
+#[defun]
+fn set_char_table_extra_slot<'ob>(
+    char_table: &'ob CharTable,
+    n: usize,
+    value: GcObj<'ob>,
+) -> Result<GcObj<'ob>> {
+    match char_table.borrow_mut().extras.get_mut(n) {
+        Some(slot) => *slot = value,
+        None => bail!("Args out of range: {char_table}, {n}"),
+    }
+    Ok(value)
+}
+
+/// Return the value of CHAR-TABLE for RANGE. RANGE is nil for the default
+/// value, a character, or a cons (FROM . TO) to get the value of FROM.
+#[defun]
+fn char_table_range<'ob>(char_table: &'ob CharTable, range: GcObj) -> Result<GcObj<'ob>> {
+    if range.nil() {
+        return Ok(char_table.borrow().default);
+    }
+    let (from, _) = char_range(range)?;
+    Ok(char_table.get(from))
+}
+
+/// Set the value of CHAR-TABLE for RANGE to VALUE. RANGE is nil for the
+/// default value, t for every character, a character, or a cons (FROM . TO)
+/// for the characters from FROM to TO inclusive.
+#[defun]
+fn set_char_table_range<'ob>(
+    char_table: &'ob CharTable,
+    range: GcObj,
+    value: GcObj<'ob>,
+) -> Result<GcObj<'ob>> {
+    let mut data = char_table.borrow_mut();
+    match range.untag() {
+        Object::NIL => data.default = value,
+        Object::TRUE => data.entries.set_range(0, MAX_CHAR, value),
+        _ => {
+            let (from, to) = char_range(range)?;
+            data.entries.set_range(from, to, value);
+        }
+    }
+    Ok(value)
+}
+
+defsym!(CHAR_TABLE_EXTRA_SLOTS);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_char_table() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(put 'test-table 'char-table-extra-slots 2)", env, cx);
+        check("(setq table (make-char-table 'test-table 'init))", env, cx);
+        check("(setq parent (make-char-table 'test-table))", env, cx);
+        let form = "(list (char-table-p table) (char-table-subtype table)
+                          (aref table ?a) (aset table ?a 'a) (aref table ?a)
+                          (set-char-table-range table '(?b . ?z) 'b) (aref table ?a)
+                          (aref table ?c) (char-table-range table '(?y . ?z))
+                          (set-char-table-extra-slot table 1 'x)
+                          (char-table-extra-slot table 1) (type-of table))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(t test-table init a a b a b b x x char-table)"
+        );
+        // nil values are looked up in the parent
+        let form = "(progn (set-char-table-parent table parent)
+                           (set-char-table-range parent t 'p)
+                           (aset table ?a nil)
+                           (set-char-table-range table nil nil)
+                           (list (aref table ?a) (aref table ?c) (aref table ?A)
+                                 (char-table-range table nil)
+                                 (eq (char-table-parent table) parent)))";
+        assert_eq!(check(form, env, cx).to_string(), "(p b p nil t)");
+        let obj = reader::read("(set-char-table-parent parent table)", cx)
+            .unwrap()
+            .0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+    }
+}
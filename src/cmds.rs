@@ -0,0 +1,116 @@
+#![cfg(feature = "buffers")]
+//! Line movement and line numbers. Lines are found with the newline index of
+//! the buffer text, so none of these scan the text.
+use crate::buffer::{get_current_buffer, set_buffer};
+use crate::core::env::{sym, Env};
+use crate::core::error::EvalError;
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{BufferData, GcObj};
+use crate::editfns::validate_region;
+use anyhow::Result;
+use fn_macros::defun;
+
+/// Move point to the start of the line `n` lines away. Returns the number of
+/// lines that could not be moved, which is negative when moving backwards.
+fn forward_lines(data: &mut BufferData, n: i64) -> i64 {
+    let (point, begv, zv) = (data.point(), data.begv(), data.zv());
+    let text = &data.text;
+    let line = text.line_at(point);
+    let (pos, mut shortage) = if n <= 0 {
+        // the newline that ends the line before the target line
+        let count = n.unsigned_abs() as usize + 1;
+        let newline = line.checked_sub(count).and_then(|x| text.newline(x));
+        match newline.filter(|&x| x >= begv) {
+            Some(newline) => (newline + 1, 0),
+            None => (begv, count - (line - text.line_at(begv))),
+        }
+    } else {
+        let count = n as usize;
+        match text.newline(line + count - 1).filter(|&x| x < zv) {
+            Some(newline) => (newline + 1, 0),
+            None => (zv, count - (text.line_at(zv) - line)),
+        }
+    };
+    // a partial line at the end of the buffer counts as a line moved over
+    let ends_in_newline = || zv > 0 && text.line_at(zv) > text.line_at(zv - 1);
+    if shortage > 0 && (n <= 0 || (zv > begv && pos != point && !ends_in_newline())) {
+        shortage -= 1;
+    }
+    data.goto_char(pos);
+    let shortage = shortage as i64;
+    if n <= 0 {
+        -shortage
+    } else {
+        shortage
+    }
+}
+
+/// Move N lines forward, or backward if N is negative, to the start of the
+/// line. Returns the count of lines left to move.
+#[defun]
+pub(crate) fn forward_line(n: Option<i64>, env: &mut Rt<Env>) -> Result<i64> {
+    let mut data = get_current_buffer(env).lock()?;
+    Ok(forward_lines(&mut data, n.unwrap_or(1)))
+}
+
+/// The line number of POSITION, or point, counting from the start of the
+/// accessible portion. If ABSOLUTE is non-nil, count from the start of the
+/// buffer instead.
+#[defun]
+fn line_number_at_pos(
+    position: Option<usize>,
+    absolute: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let data = get_current_buffer(env).lock()?;
+    let (begv, zv) = (data.begv(), data.zv());
+    let pos = position.unwrap_or_else(|| data.point() + 1);
+    if !(begv + 1..=zv + 1).contains(&pos) {
+        let data = list![pos as i64, begv as i64 + 1, zv as i64 + 1; cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    }
+    let start = if absolute.is_some() { 0 } else { begv };
+    Ok(data.text.line_at(pos - 1) - data.text.line_at(start) + 1)
+}
+
+/// The number of lines between START and END. A line that does not end in a
+/// newline is counted if it is not empty.
+#[defun]
+fn count_lines(
+    start: usize,
+    end: usize,
+    _ignore_invisible_lines: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let data = get_current_buffer(env).lock()?;
+    let (start, end) = validate_region(start, end, (0, data.text.len_chars()), env, cx)?;
+    let lines = data.text.line_at(end) - data.text.line_at(start);
+    let partial = end > start && data.text.line_at(end - 1) == data.text.line_at(end);
+    Ok(lines + usize::from(partial))
+}
+
+/// Go to the start of line LINE in BUFFER, or the current buffer, counting
+/// from 1. The buffer is widened first, unless RELATIVE is non-nil, in which
+/// case LINE counts from the start of the accessible portion.
+#[defun]
+fn goto_line(
+    line: i64,
+    buffer: Option<GcObj>,
+    relative: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    if let Some(buffer) = buffer {
+        set_buffer(buffer, env, cx)?;
+    }
+    let mut data = get_current_buffer(env).lock()?;
+    if relative.is_none() {
+        data.widen();
+    }
+    let begv = data.begv();
+    data.goto_char(begv);
+    forward_lines(&mut data, line - 1);
+    Ok(false)
+}
@@ -0,0 +1,1250 @@
+//! Coding systems, which convert between the bytes of files and processes
+//! and the characters of strings and buffers. A coding system is named by a
+//! symbol like `utf-8` or `latin-1`. Buffers cannot hold raw bytes, so a
+//! byte that cannot be decoded becomes the character with the same code,
+//! the way `insert` treats unibyte strings, and encoding with `raw-text`
+//! turns those characters back into their bytes. The coding system of text
+//! read with `undecided` is detected from its byte order mark or its bytes.
+//! Each coding system also has `-unix`, `-dos` and `-mac` variants that end
+//! lines with LF, CRLF and CR, and without one the line ends are detected
+//! when decoding and are LF when encoding.
+use crate::core::env::{sym, Env, Symbol};
+use crate::core::error::{EvalError, Type, TypeError};
+use crate::core::gc::{Context, Rt};
+#[cfg(feature = "buffers")]
+use crate::core::object::Gc;
+use crate::core::object::{GcObj, Object};
+use crate::fns::slice_into_list;
+use anyhow::{bail, Result};
+use bstr::ByteSlice;
+use fn_macros::defun;
+use std::sync::Mutex;
+
+/// How a coding system converts text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Utf8,
+    Utf16 {
+        big_endian: bool,
+    },
+    Utf32 {
+        big_endian: bool,
+    },
+    Latin1,
+    Ascii,
+    /// Bytes are passed through unchanged
+    RawText,
+    /// The coding is detected from the text, and is UTF-8 when encoding
+    Undecided,
+}
+
+/// How a coding system ends lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Eol {
+    /// The line ends are detected when decoding, and are LF when encoding
+    Undecided,
+    Unix,
+    Dos,
+    Mac,
+}
+
+impl Eol {
+    const VARIANTS: [(Self, &'static str); 3] =
+        [(Self::Unix, "unix"), (Self::Dos, "dos"), (Self::Mac, "mac")];
+
+    /// The line ends of `text`: CRLF if every LF follows a CR, CR if there
+    /// are only CRs, and LF otherwise.
+    fn detect(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let mut newlines = memchr::memchr_iter(b'\n', bytes).peekable();
+        if newlines.peek().is_none() {
+            return match bytes.contains(&b'\r') {
+                true => Self::Mac,
+                false => Self::Undecided,
+            };
+        }
+        match newlines.all(|x| x > 0 && bytes[x - 1] == b'\r') {
+            true => Self::Dos,
+            false => Self::Unix,
+        }
+    }
+}
+
+/// A coding system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CodingSystem {
+    pub(crate) name: &'static str,
+    /// The coding system that this one is an alias of, or its own name
+    pub(crate) base: &'static str,
+    pub(crate) kind: Kind,
+    /// Whether the text starts with a byte order mark, which is removed when
+    /// decoding and added when encoding
+    pub(crate) bom: bool,
+    pub(crate) eol: Eol,
+}
+
+const fn coding(name: &'static str, base: &'static str, kind: Kind, bom: bool) -> CodingSystem {
+    CodingSystem {
+        name,
+        base,
+        kind,
+        bom,
+        eol: Eol::Undecided,
+    }
+}
+
+const UTF16_BE: Kind = Kind::Utf16 { big_endian: true };
+const UTF16_LE: Kind = Kind::Utf16 { big_endian: false };
+const UTF32_BE: Kind = Kind::Utf32 { big_endian: true };
+const UTF32_LE: Kind = Kind::Utf32 { big_endian: false };
+
+/// The coding systems, each followed by its aliases.
+const CODING_SYSTEMS: &[CodingSystem] = &[
+    coding("utf-8", "utf-8", Kind::Utf8, false),
+    coding("mule-utf-8", "utf-8", Kind::Utf8, false),
+    coding(
+        "utf-8-with-signature",
+        "utf-8-with-signature",
+        Kind::Utf8,
+        true,
+    ),
+    coding("utf-8-emacs", "utf-8-emacs", Kind::Utf8, false),
+    coding("emacs-internal", "utf-8-emacs", Kind::Utf8, false),
+    coding("prefer-utf-8", "prefer-utf-8", Kind::Undecided, false),
+    coding("utf-16", "utf-16", UTF16_BE, true),
+    coding("utf-16be", "utf-16be", UTF16_BE, false),
+    coding("utf-16le", "utf-16le", UTF16_LE, false),
+    coding(
+        "utf-16be-with-signature",
+        "utf-16be-with-signature",
+        UTF16_BE,
+        true,
+    ),
+    coding(
+        "utf-16le-with-signature",
+        "utf-16le-with-signature",
+        UTF16_LE,
+        true,
+    ),
+    coding("utf-32", "utf-32", UTF32_BE, true),
+    coding("utf-32be", "utf-32be", UTF32_BE, false),
+    coding("utf-32le", "utf-32le", UTF32_LE, false),
+    coding("iso-latin-1", "iso-latin-1", Kind::Latin1, false),
+    coding("iso-8859-1", "iso-latin-1", Kind::Latin1, false),
+    coding("latin-1", "iso-latin-1", Kind::Latin1, false),
+    coding("us-ascii", "us-ascii", Kind::Ascii, false),
+    coding("iso-safe", "us-ascii", Kind::Ascii, false),
+    coding("raw-text", "raw-text", Kind::RawText, false),
+    CodingSystem {
+        eol: Eol::Unix,
+        ..coding("no-conversion", "no-conversion", Kind::RawText, false)
+    },
+    CodingSystem {
+        eol: Eol::Unix,
+        ..coding("binary", "no-conversion", Kind::RawText, false)
+    },
+    coding("undecided", "undecided", Kind::Undecided, false),
+];
+
+/// The byte order marks that start text, with the coding systems they
+/// are detected as. The UTF-32 mark in little-endian order starts with the
+/// UTF-16 one, so it has to come first.
+const BOMS: &[(&[u8], &str)] = &[
+    (b"\xEF\xBB\xBF", "utf-8-with-signature"),
+    (b"\xFF\xFE\0\0", "utf-32"),
+    (b"\0\0\xFE\xFF", "utf-32"),
+    (b"\xFE\xFF", "utf-16be-with-signature"),
+    (b"\xFF\xFE", "utf-16le-with-signature"),
+];
+
+/// The coding systems that text is detected as, from the most preferred.
+/// `iso-latin-1` accepts any text, so the ones after it are only detected
+/// if they are preferred with `prefer-coding-system`.
+const DEFAULT_PRIORITY: &[&str] = &[
+    "utf-8",
+    "iso-latin-1",
+    "utf-16le",
+    "utf-16be",
+    "raw-text",
+    "no-conversion",
+];
+
+/// The coding systems that `prefer-coding-system` and
+/// `set-coding-system-priority` put before the default ones.
+static PREFERRED: Mutex<Vec<CodingSystem>> = Mutex::new(Vec::new());
+
+/// The character that replaces the characters a coding system cannot
+/// encode.
+const DEFAULT_CHAR: u8 = b'?';
+
+impl CodingSystem {
+    /// The coding system called `name`, if there is one. A name can end in
+    /// `-unix`, `-dos` or `-mac` to give the line ends.
+    pub(crate) fn get(name: &str) -> Option<Self> {
+        if let Some(coding) = CODING_SYSTEMS.iter().find(|x| x.name == name) {
+            return Some(*coding);
+        }
+        let (name, suffix) = name.rsplit_once('-')?;
+        let (eol, _) = Eol::VARIANTS.into_iter().find(|x| x.1 == suffix)?;
+        let coding = Self::get(name).filter(|x| x.has_eol_variants())?;
+        Some(Self { eol, ..coding })
+    }
+
+    /// Whether the coding system has variants for each kind of line end.
+    /// `no-conversion` always uses LF.
+    fn has_eol_variants(self) -> bool {
+        CODING_SYSTEMS
+            .iter()
+            .any(|x| x.name == self.name && x.eol == Eol::Undecided)
+    }
+
+    /// The name of the coding system, with the suffix of its line ends.
+    pub(crate) fn full_name(self) -> String {
+        match Eol::VARIANTS.iter().find(|x| x.0 == self.eol) {
+            Some((_, suffix)) if self.has_eol_variants() => format!("{}-{suffix}", self.name),
+            _ => self.name.to_owned(),
+        }
+    }
+
+    pub(crate) fn symbol<'ob>(self, cx: &'ob Context) -> Symbol<'ob> {
+        crate::core::env::intern(&self.full_name(), cx)
+    }
+
+    /// The coding system named by `coding`, or an error if it is not one.
+    pub(crate) fn from_symbol(coding: Symbol, env: &mut Rt<Env>, cx: &Context) -> Result<Self> {
+        match Self::get(coding.name()) {
+            Some(coding) => Ok(coding),
+            None => {
+                let data = list![coding; cx];
+                Err(EvalError::signal(sym::CODING_SYSTEM_ERROR.into(), data, env).into())
+            }
+        }
+    }
+
+    /// Like [`CodingSystem::from_symbol`], but nil is `no-conversion`.
+    fn from_symbol_or_nil(coding: Symbol, env: &mut Rt<Env>, cx: &Context) -> Result<Self> {
+        match coding == sym::NIL {
+            true => Ok(Self::get("no-conversion").unwrap()),
+            false => Self::from_symbol(coding, env, cx),
+        }
+    }
+
+    /// The type of the coding system, as returned by `coding-system-type`.
+    fn type_name(self) -> &'static str {
+        match self.kind {
+            Kind::Utf8 => "utf-8",
+            Kind::Utf16 { .. } => "utf-16",
+            Kind::Utf32 { .. } => "utf-32",
+            Kind::Latin1 | Kind::Ascii => "charset",
+            Kind::RawText => "raw-text",
+            Kind::Undecided => "undecided",
+        }
+    }
+
+    /// Whether `bytes` can be text in this coding system. A coding system
+    /// with a byte order mark only accepts text that starts with one.
+    fn accepts(self, bytes: &[u8]) -> bool {
+        if self.bom
+            && !BOMS
+                .iter()
+                .any(|x| x.1 == self.name && bytes.starts_with(x.0))
+        {
+            return false;
+        }
+        match self.kind {
+            Kind::Utf8 => std::str::from_utf8(bytes).is_ok(),
+            Kind::Utf16 { big_endian } => {
+                bytes.len().is_multiple_of(2)
+                    && !decode_utf16(bytes, big_endian).contains('\u{FFFD}')
+            }
+            Kind::Utf32 { big_endian } => {
+                bytes.len().is_multiple_of(4)
+                    && !decode_utf32(bytes, big_endian).contains('\u{FFFD}')
+            }
+            Kind::Ascii => bytes.is_ascii(),
+            Kind::Latin1 | Kind::RawText => true,
+            Kind::Undecided => false,
+        }
+    }
+
+    /// Decode `bytes` into text, and return it with the coding system that
+    /// decoded it: this one, with the coding system the bytes are detected
+    /// as if this is `undecided` and with the line ends of the text if they
+    /// are not given.
+    pub(crate) fn decode(self, bytes: &[u8]) -> (Self, String) {
+        let mut coding = match self.kind {
+            Kind::Undecided => {
+                let detected = detect(bytes)[0];
+                match self.eol {
+                    Eol::Undecided => detected,
+                    eol if detected.has_eol_variants() => Self { eol, ..detected },
+                    _ => detected,
+                }
+            }
+            _ => self,
+        };
+        let text = coding.decode_chars(bytes);
+        if coding.eol == Eol::Undecided {
+            coding.eol = Eol::detect(&text);
+        }
+        let text = match coding.eol {
+            Eol::Dos => text.replace("\r\n", "\n"),
+            Eol::Mac => text.replace('\r', "\n"),
+            Eol::Unix | Eol::Undecided => text,
+        };
+        (coding, text)
+    }
+
+    /// Decode `bytes` into text, leaving the line ends as they are.
+    fn decode_chars(self, bytes: &[u8]) -> String {
+        match self.kind {
+            // the text is ASCII
+            Kind::Undecided => decode_utf8(bytes),
+            Kind::Utf8 => {
+                let bytes = match self.bom {
+                    true => bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes),
+                    false => bytes,
+                };
+                decode_utf8(bytes)
+            }
+            Kind::Utf16 { big_endian } => {
+                let (bytes, big_endian) = match bytes {
+                    [0xFE, 0xFF, rest @ ..] if self.bom => (rest, true),
+                    [0xFF, 0xFE, rest @ ..] if self.bom => (rest, false),
+                    _ => (bytes, big_endian),
+                };
+                decode_utf16(bytes, big_endian)
+            }
+            Kind::Utf32 { big_endian } => {
+                let (bytes, big_endian) = match bytes {
+                    [0, 0, 0xFE, 0xFF, rest @ ..] if self.bom => (rest, true),
+                    [0xFF, 0xFE, 0, 0, rest @ ..] if self.bom => (rest, false),
+                    _ => (bytes, big_endian),
+                };
+                decode_utf32(bytes, big_endian)
+            }
+            Kind::Latin1 | Kind::Ascii | Kind::RawText => {
+                bytes.iter().copied().map(char::from).collect()
+            }
+        }
+    }
+
+    /// Whether encoding text leaves it as its UTF-8 bytes.
+    pub(crate) fn keeps_utf8(self) -> bool {
+        matches!(self.kind, Kind::Utf8 | Kind::Undecided)
+            && !self.bom
+            && matches!(self.eol, Eol::Unix | Eol::Undecided)
+    }
+
+    /// Encode `text` into bytes.
+    pub(crate) fn encode(self, text: &str) -> Vec<u8> {
+        match self.eol {
+            Eol::Dos => self.encode_chars(&text.replace('\n', "\r\n")),
+            Eol::Mac => self.encode_chars(&text.replace('\n', "\r")),
+            Eol::Unix | Eol::Undecided => self.encode_chars(text),
+        }
+    }
+
+    /// Encode `text` into bytes, leaving the line ends as they are.
+    fn encode_chars(self, text: &str) -> Vec<u8> {
+        match self.kind {
+            Kind::Utf8 | Kind::Undecided => {
+                let bom: &[u8] = if self.bom { b"\xEF\xBB\xBF" } else { b"" };
+                [bom, text.as_bytes()].concat()
+            }
+            Kind::Utf16 { big_endian } => {
+                let bom = self.bom.then_some(0xFEFF);
+                let units = bom.into_iter().chain(text.encode_utf16());
+                let bytes = |x: u16| match big_endian {
+                    true => x.to_be_bytes(),
+                    false => x.to_le_bytes(),
+                };
+                units.flat_map(bytes).collect()
+            }
+            Kind::Utf32 { big_endian } => {
+                let bom = self.bom.then_some(0xFEFF);
+                let chars = bom.into_iter().chain(text.chars().map(u32::from));
+                let bytes = |x: u32| match big_endian {
+                    true => x.to_be_bytes(),
+                    false => x.to_le_bytes(),
+                };
+                chars.flat_map(bytes).collect()
+            }
+            Kind::Latin1 => text
+                .chars()
+                .map(|x| u8::try_from(x).unwrap_or(DEFAULT_CHAR))
+                .collect(),
+            Kind::Ascii => text
+                .chars()
+                .map(|x| if x.is_ascii() { x as u8 } else { DEFAULT_CHAR })
+                .collect(),
+            Kind::RawText => raw_bytes(text),
+        }
+    }
+}
+
+/// Decode UTF-8, turning each byte that is not part of a valid sequence
+/// into the character with the same code.
+fn decode_utf8(mut bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return text;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                // SAFETY: `from_utf8` checked that this part is valid.
+                text.push_str(unsafe { std::str::from_utf8_unchecked(valid) });
+                let invalid = e.error_len().unwrap_or(rest.len());
+                text.extend(rest[..invalid].iter().copied().map(char::from));
+                bytes = &rest[invalid..];
+            }
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> String {
+    let (pairs, rest) = bytes.as_chunks::<2>();
+    let units = pairs.iter().map(|x| match big_endian {
+        true => u16::from_be_bytes(*x),
+        false => u16::from_le_bytes(*x),
+    });
+    let mut text: String = char::decode_utf16(units)
+        .map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    // an odd byte at the end is kept as a raw byte
+    text.extend(rest.iter().copied().map(char::from));
+    text
+}
+
+fn decode_utf32(bytes: &[u8], big_endian: bool) -> String {
+    let (units, rest) = bytes.as_chunks::<4>();
+    let mut text: String = units
+        .iter()
+        .map(|x| match big_endian {
+            true => u32::from_be_bytes(*x),
+            false => u32::from_le_bytes(*x),
+        })
+        .map(|x| char::from_u32(x).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    text.extend(rest.iter().copied().map(char::from));
+    text
+}
+
+/// The coding systems in the order they are detected in.
+fn priority_list() -> Vec<CodingSystem> {
+    let mut list = PREFERRED.lock().unwrap().clone();
+    for name in DEFAULT_PRIORITY {
+        let coding = CodingSystem::get(name).unwrap();
+        if !list.iter().any(|x| x.base == coding.base) {
+            list.push(coding);
+        }
+    }
+    list
+}
+
+/// The most preferred coding system, which text is encoded in when no
+/// other coding system is given.
+pub(crate) fn preferred_coding_system() -> CodingSystem {
+    priority_list()[0]
+}
+
+/// The coding systems that `bytes` can be text in, from the most likely.
+/// Text that starts with a byte order mark is in the coding system of the
+/// mark, and ASCII text is only `undecided`, since most coding systems
+/// decode it the same way.
+pub(crate) fn detect(bytes: &[u8]) -> Vec<CodingSystem> {
+    let mut detected = Vec::new();
+    if let Some((_, name)) = BOMS.iter().find(|x| bytes.starts_with(x.0)) {
+        detected.push(CodingSystem::get(name).unwrap());
+    } else if bytes.is_ascii() {
+        return vec![CodingSystem::get("undecided").unwrap()];
+    }
+    for coding in priority_list() {
+        if coding.accepts(bytes) && !detected.contains(&coding) {
+            detected.push(coding);
+        }
+    }
+    detected
+}
+
+/// The value of the `coding:` cookie of a file, either in a `-*-` line at
+/// its start, which may follow a `#!` line, or in the local variables list
+/// at its end. Cookies that do not name a coding system are ignored.
+pub(crate) fn coding_cookie(bytes: &[u8]) -> Option<CodingSystem> {
+    let mut lines = bytes.lines();
+    let line = match lines.next() {
+        Some(line) if line.starts_with(b"#!") => lines.next(),
+        line => line,
+    };
+    let props = line.and_then(|line| {
+        let start = line.find("-*-")? + 3;
+        let end = line[start..].find("-*-")?;
+        Some(&line[start..start + end])
+    });
+    if let Some(coding) = props.and_then(cookie_value) {
+        return Some(coding);
+    }
+    // the local variables list is in the last 3000 bytes
+    let tail = &bytes[bytes.len().saturating_sub(3000)..];
+    let start = tail.rfind("Local Variables:")?;
+    tail[start..]
+        .lines()
+        .take_while(|x| x.find("End:").is_none())
+        .find_map(cookie_value)
+}
+
+/// The coding system named after `coding:` in `text`.
+fn cookie_value(text: &[u8]) -> Option<CodingSystem> {
+    let mut offset = 0;
+    while let Some(idx) = text[offset..].find("coding:") {
+        let start = offset + idx;
+        offset = start + "coding:".len();
+        if start > 0 && !matches!(text[start - 1], b' ' | b'\t' | b';') {
+            continue;
+        }
+        let value = text[offset..].trim_start();
+        let end = value.find_byteset(b" \t;").unwrap_or(value.len());
+        return CodingSystem::get(value[..end].to_str().ok()?);
+    }
+    None
+}
+
+/// The bytes of `text`, where the characters below 256 stand for the raw
+/// bytes with their code and other characters for their UTF-8 bytes.
+pub(crate) fn raw_bytes(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for chr in text.chars() {
+        match u8::try_from(chr) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => bytes.extend_from_slice(chr.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
+/// The coding system in `var`, if it is set to one.
+pub(crate) fn coding_system_var(var: Symbol, env: &Rt<Env>, cx: &Context) -> Option<CodingSystem> {
+    match env.vars.get(var)?.get(cx) {
+        Object::Symbol(coding) => CodingSystem::get(coding.name()),
+        _ => None,
+    }
+}
+
+/// Decode `bytes` read from a file, with `coding-system-for-read` if it is
+/// set, the coding system of the `coding:` cookie of the file if it has one,
+/// or else the coding system that they are detected as.
+pub(crate) fn decode_file_contents(bytes: &[u8], env: &mut Rt<Env>, cx: &Context) -> String {
+    let coding = coding_system_var(sym::CODING_SYSTEM_FOR_READ, env, cx)
+        .or_else(|| coding_cookie(bytes))
+        .unwrap_or_else(|| CodingSystem::get("undecided").unwrap());
+    let (coding, text) = coding.decode(bytes);
+    set_last_coding_system_used(coding, env, cx);
+    text
+}
+
+/// The coding system to write files with: `coding-system-for-write`, or
+/// else `buffer-file-coding-system`.
+#[cfg(feature = "buffers")]
+pub(crate) fn coding_system_for_write(env: &Rt<Env>, cx: &Context) -> CodingSystem {
+    coding_system_var(sym::CODING_SYSTEM_FOR_WRITE, env, cx)
+        .or_else(|| coding_system_var(sym::BUFFER_FILE_CODING_SYSTEM, env, cx))
+        .unwrap_or_else(|| CodingSystem::get("utf-8-unix").unwrap())
+}
+
+pub(crate) fn set_last_coding_system_used(coding: CodingSystem, env: &mut Rt<Env>, cx: &Context) {
+    let name: GcObj = coding.symbol(cx).into();
+    env.vars.insert(sym::LAST_CODING_SYSTEM_USED, name);
+}
+
+/// Return t if OBJECT is a coding system. nil is the coding system that
+/// does no conversion.
+#[defun]
+fn coding_system_p(object: GcObj) -> bool {
+    match object.untag() {
+        Object::Symbol(coding) => coding == sym::NIL || CodingSystem::get(coding.name()).is_some(),
+        _ => false,
+    }
+}
+
+/// Return CODING-SYSTEM if it is a coding system, and otherwise signal
+/// `coding-system-error`.
+#[defun]
+fn check_coding_system<'ob>(
+    coding_system: Symbol<'ob>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Symbol<'ob>> {
+    if coding_system != sym::NIL {
+        CodingSystem::from_symbol(coding_system, env, cx)?;
+    }
+    Ok(coding_system)
+}
+
+/// Return the list of coding systems. If BASE-ONLY is non-nil, aliases and
+/// the variants for each kind of line end are left out.
+#[defun]
+fn coding_system_list<'ob>(base_only: Option<()>, cx: &'ob Context) -> GcObj<'ob> {
+    let mut names: Vec<GcObj> = Vec::new();
+    for coding in CODING_SYSTEMS {
+        if base_only.is_some() && coding.name != coding.base {
+            continue;
+        }
+        names.push(coding.symbol(cx).into());
+        if base_only.is_none() && coding.has_eol_variants() {
+            for (eol, _) in Eol::VARIANTS {
+                names.push(CodingSystem { eol, ..*coding }.symbol(cx).into());
+            }
+        }
+    }
+    slice_into_list(&names, None, cx)
+}
+
+/// Return the coding system that CODING-SYSTEM is an alias or a line end
+/// variant of, or CODING-SYSTEM itself.
+#[defun]
+fn coding_system_base<'ob>(
+    coding_system: Symbol<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    if coding_system == sym::NIL {
+        return Ok(sym::NO_CONVERSION);
+    }
+    let coding = CodingSystem::from_symbol(coding_system, env, cx)?;
+    Ok(crate::core::env::intern(coding.base, cx))
+}
+
+/// Return the aliases of CODING-SYSTEM, starting with the coding system it
+/// is an alias of, all with the line ends of CODING-SYSTEM.
+#[defun]
+fn coding_system_aliases<'ob>(
+    coding_system: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let coding = CodingSystem::from_symbol(coding_system, env, cx)?;
+    let names: Vec<GcObj> = CODING_SYSTEMS
+        .iter()
+        .filter(|x| x.base == coding.base)
+        .map(|x| {
+            CodingSystem {
+                eol: coding.eol,
+                ..*x
+            }
+            .symbol(cx)
+            .into()
+        })
+        .collect();
+    Ok(slice_into_list(&names, None, cx))
+}
+
+/// Return how CODING-SYSTEM ends lines: 0 for LF, 1 for CRLF and 2 for CR.
+/// If the line ends are detected, return a vector of the variants of
+/// CODING-SYSTEM for each of them instead.
+#[defun]
+fn coding_system_eol_type<'ob>(
+    coding_system: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let coding = CodingSystem::from_symbol_or_nil(coding_system, env, cx)?;
+    Ok(match coding.eol {
+        Eol::Undecided => {
+            let variants: Vec<GcObj> = Eol::VARIANTS
+                .iter()
+                .map(|x| CodingSystem { eol: x.0, ..coding }.symbol(cx).into())
+                .collect();
+            cx.add(variants)
+        }
+        Eol::Unix => 0.into(),
+        Eol::Dos => 1.into(),
+        Eol::Mac => 2.into(),
+    })
+}
+
+/// Return the variant of CODING-SYSTEM that ends lines as EOL-TYPE says:
+/// `unix` or 0 for LF, `dos` or 1 for CRLF, and `mac` or 2 for CR. If
+/// EOL-TYPE is nil, return the variant that detects the line ends.
+#[defun]
+fn coding_system_change_eol_conversion<'ob>(
+    coding_system: Symbol<'ob>,
+    eol_type: GcObj,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    let coding = CodingSystem::from_symbol_or_nil(coding_system, env, cx)?;
+    let eol = match eol_type.untag() {
+        Object::Symbol(x) if x == sym::NIL => Eol::Undecided,
+        Object::Symbol(x) => match Eol::VARIANTS.iter().find(|y| y.1 == x.name()) {
+            Some((eol, _)) => *eol,
+            None => bail!("Invalid EOL type: {x}"),
+        },
+        Object::Int(x @ 0..=2) => Eol::VARIANTS[x as usize].0,
+        _ => bail!(TypeError::new(Type::Symbol, eol_type)),
+    };
+    if !coding.has_eol_variants() {
+        return Ok(coding_system);
+    }
+    Ok(CodingSystem { eol, ..coding }.symbol(cx))
+}
+
+/// Return the type of CODING-SYSTEM: `utf-8`, `utf-16`, `charset`,
+/// `raw-text` or `undecided`.
+#[defun]
+fn coding_system_type<'ob>(
+    coding_system: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    let coding = CodingSystem::from_symbol_or_nil(coding_system, env, cx)?;
+    Ok(crate::core::env::intern(coding.type_name(), cx))
+}
+
+/// The bytes of a string to decode. A multibyte string is decoded from its
+/// UTF-8 bytes.
+fn string_bytes(string: GcObj<'_>) -> Result<&[u8]> {
+    match string.untag() {
+        Object::String(string) => Ok(string.as_ref()),
+        x => bail!(TypeError::new(Type::String, x)),
+    }
+}
+
+/// Decode STRING, whose bytes are text in CODING-SYSTEM, and return the
+/// decoded text. If CODING-SYSTEM is nil, STRING is returned unchanged.
+/// Bytes that are not valid in CODING-SYSTEM become the characters with
+/// the same code. NOCOPY is ignored, and if BUFFER is non-nil the text is
+/// inserted in BUFFER after point and its length is returned instead.
+#[defun]
+fn decode_coding_string<'ob>(
+    string: GcObj<'ob>,
+    coding_system: Symbol,
+    _nocopy: Option<()>,
+    buffer: Option<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let bytes = string_bytes(string)?;
+    if coding_system == sym::NIL {
+        return Ok(string);
+    }
+    let coding = CodingSystem::from_symbol(coding_system, env, cx)?;
+    let (coding, text) = coding.decode(bytes);
+    set_last_coding_system_used(coding, env, cx);
+    match buffer {
+        Some(buffer) => insert_after_point(buffer, &text),
+        None => Ok(cx.add(text)),
+    }
+}
+
+/// Encode STRING into the bytes of its text in CODING-SYSTEM, and return
+/// them as a unibyte string. A unibyte STRING is already encoded, and is
+/// returned unchanged, as it is if CODING-SYSTEM is nil. Characters that
+/// CODING-SYSTEM cannot represent are encoded as `?`. NOCOPY is ignored,
+/// and if BUFFER is non-nil the bytes are inserted in BUFFER after point
+/// and their number is returned instead.
+#[defun]
+fn encode_coding_string<'ob>(
+    string: GcObj<'ob>,
+    coding_system: Symbol,
+    _nocopy: Option<()>,
+    buffer: Option<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let Object::String(lisp_string) = string.untag() else {bail!(TypeError::new(Type::String, string))};
+    if coding_system == sym::NIL || !lisp_string.is_multibyte() {
+        return Ok(string);
+    }
+    let coding = CodingSystem::from_symbol(coding_system, env, cx)?;
+    let bytes = coding.encode(lisp_string.try_into()?);
+    set_last_coding_system_used(coding, env, cx);
+    match buffer {
+        Some(buffer) => insert_after_point(
+            buffer,
+            &bytes.iter().copied().map(char::from).collect::<String>(),
+        ),
+        None => Ok(cx.add(bytes)),
+    }
+}
+
+/// Insert `text` in `buffer` after point, and return its length.
+#[cfg(feature = "buffers")]
+fn insert_after_point<'ob>(buffer: GcObj, text: &str) -> Result<GcObj<'ob>> {
+    let mut data = crate::buffer::get_live_buffer(buffer)?.lock()?;
+    let point = data.point();
+    data.insert(text);
+    data.goto_char(point);
+    Ok(text.chars().count().into())
+}
+
+#[cfg(not(feature = "buffers"))]
+fn insert_after_point<'ob>(_: GcObj, _: &str) -> Result<GcObj<'ob>> {
+    bail!("Buffers are not supported")
+}
+
+/// The bounds of the region from START to END in the current buffer, and
+/// its text.
+#[cfg(feature = "buffers")]
+pub(crate) fn region_text(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(usize, usize, String)> {
+    let buffer = crate::buffer::get_current_buffer(env);
+    let bounds = {
+        let data = buffer.lock()?;
+        (data.begv(), data.zv())
+    };
+    let (beg, end) = crate::editfns::validate_region(start, end, bounds, env, cx)?;
+    let text = buffer.lock()?.text.substring(beg, end);
+    Ok((beg, end, text))
+}
+
+/// Replace the text from `beg` to `end` in the current buffer with `new`,
+/// or return `new` as a string if DESTINATION is t, or insert it in
+/// DESTINATION if it is a buffer. Returns the length of `new` otherwise.
+#[cfg(feature = "buffers")]
+pub(crate) fn replace_region<'ob>(
+    (beg, end): (usize, usize),
+    new: &str,
+    destination: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    match destination.map(|x| x.bind(cx)) {
+        Some(dest) if dest == sym::TRUE => return Ok(cx.add(new)),
+        Some(dest) => return insert_after_point(dest, new),
+        None => {}
+    }
+    let buffer = crate::buffer::get_current_buffer(env);
+    let point = buffer.lock()?.point();
+    crate::buffer::delete_text(beg, end, env, cx)?;
+    buffer.lock()?.goto_char(beg);
+    crate::buffer::insert_text(new, env, cx)?;
+    let (old_len, new_len) = (end - beg, new.chars().count());
+    let point = match point {
+        x if x >= end => x + new_len - old_len,
+        x if x > beg => beg,
+        x => x,
+    };
+    buffer.lock()?.goto_char(point);
+    Ok(new_len.into())
+}
+
+/// Decode the text from START to END in the current buffer, whose
+/// characters below 256 are raw bytes in CODING-SYSTEM, and replace it with
+/// the decoded text. If DESTINATION is t, the text is returned as a string
+/// instead, and if it is a buffer, it is inserted there after point.
+/// Otherwise the length of the decoded text is returned.
+#[cfg(feature = "buffers")]
+#[defun]
+fn decode_coding_region<'ob>(
+    start: usize,
+    end: usize,
+    coding_system: &Rt<Gc<Symbol>>,
+    destination: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let coding = CodingSystem::from_symbol_or_nil(coding_system.get(cx), env, cx)?;
+    let (beg, end, text) = region_text(start, end, env, cx)?;
+    let (coding, text) = coding.decode(&raw_bytes(&text));
+    set_last_coding_system_used(coding, env, cx);
+    replace_region((beg, end), &text, destination, env, cx)
+}
+
+/// Encode the text from START to END in the current buffer with
+/// CODING-SYSTEM, and replace it with its bytes as the characters with the
+/// same code. If DESTINATION is t, the bytes are returned as a unibyte
+/// string instead, and if it is a buffer, they are inserted there after
+/// point. Otherwise the number of bytes is returned.
+#[cfg(feature = "buffers")]
+#[defun]
+fn encode_coding_region<'ob>(
+    start: usize,
+    end: usize,
+    coding_system: &Rt<Gc<Symbol>>,
+    destination: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let coding = CodingSystem::from_symbol_or_nil(coding_system.get(cx), env, cx)?;
+    set_last_coding_system_used(coding, env, cx);
+    let (beg, end, text) = region_text(start, end, env, cx)?;
+    let bytes = coding.encode(&text);
+    if destination.is_some_and(|x| x.bind(cx) == sym::TRUE) {
+        return Ok(cx.add(bytes));
+    }
+    let new: String = bytes.into_iter().map(char::from).collect();
+    replace_region((beg, end), &new, destination, env, cx)
+}
+
+/// The coding systems in `codings`, or the first of them if `highest` is
+/// true.
+fn coding_list<'ob>(codings: &[CodingSystem], highest: bool, cx: &'ob Context) -> GcObj<'ob> {
+    let mut names = codings.iter().map(|x| x.symbol(cx).into());
+    match highest {
+        true => names.next().unwrap(),
+        false => slice_into_list(&names.collect::<Vec<GcObj>>(), None, cx),
+    }
+}
+
+/// The coding systems that `bytes` can be text in, with the line ends that
+/// each of them detects.
+fn detected_list<'ob>(bytes: &[u8], highest: bool, cx: &'ob Context) -> GcObj<'ob> {
+    let detected: Vec<_> = detect(bytes)
+        .into_iter()
+        .map(|x| x.decode(bytes).0)
+        .collect();
+    coding_list(&detected, highest, cx)
+}
+
+/// Return the list of coding systems that the bytes of STRING can be
+/// text in, from the most likely. Text that starts with a byte order mark
+/// is detected as the coding system of the mark, and ASCII text as
+/// `undecided`. Each coding system ends lines the way STRING does. If
+/// HIGHEST is non-nil, only the most likely one is returned.
+#[defun]
+fn detect_coding_string<'ob>(
+    string: GcObj,
+    highest: Option<()>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    Ok(detected_list(string_bytes(string)?, highest.is_some(), cx))
+}
+
+/// Return the list of coding systems that the text from START to END in
+/// the current buffer can be in, like `detect-coding-string`. Its
+/// characters below 256 are taken as raw bytes.
+#[cfg(feature = "buffers")]
+#[defun]
+fn detect_coding_region<'ob>(
+    start: usize,
+    end: usize,
+    highest: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (_, _, text) = region_text(start, end, env, cx)?;
+    Ok(detected_list(&raw_bytes(&text), highest.is_some(), cx))
+}
+
+/// Give CODING-SYSTEM the highest priority when detecting the coding
+/// system of text.
+#[defun]
+fn prefer_coding_system(coding_system: Symbol, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let coding = CodingSystem::from_symbol(coding_system, env, cx)?;
+    let mut preferred = PREFERRED.lock().unwrap();
+    preferred.retain(|x| x.base != coding.base);
+    preferred.insert(0, coding);
+    Ok(false)
+}
+
+/// Give CODING-SYSTEMS the highest priorities when detecting the coding
+/// system of text, in the order they are given.
+#[defun]
+fn set_coding_system_priority(
+    coding_systems: &[GcObj],
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let mut codings = Vec::new();
+    for coding in coding_systems {
+        let coding: Symbol = (*coding).try_into()?;
+        codings.push(CodingSystem::from_symbol(coding, env, cx)?);
+    }
+    let mut preferred = PREFERRED.lock().unwrap();
+    preferred.retain(|x| !codings.iter().any(|y| x.base == y.base));
+    preferred.splice(0..0, codings);
+    Ok(false)
+}
+
+/// Return the list of coding systems in the order they are detected in, or
+/// only the first one if HIGHESTP is non-nil.
+#[defun]
+fn coding_system_priority_list<'ob>(highestp: Option<()>, cx: &'ob Context) -> GcObj<'ob> {
+    coding_list(&priority_list(), highestp.is_some(), cx)
+}
+
+/// Set the coding system that the current buffer is saved with to
+/// CODING-SYSTEM, and mark the buffer modified unless NOMODIFY is non-nil.
+/// If CODING-SYSTEM detects its text conversion or line ends, those of the
+/// current coding system are kept, so the file keeps its line ends, unless
+/// FORCE is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+fn set_buffer_file_coding_system(
+    coding_system: Symbol,
+    force: Option<()>,
+    nomodify: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let mut coding = CodingSystem::from_symbol_or_nil(coding_system, env, cx)?;
+    let current = coding_system_var(sym::BUFFER_FILE_CODING_SYSTEM, env, cx);
+    if let (Some(current), None) = (current, force) {
+        if coding.kind == Kind::Undecided {
+            coding = CodingSystem {
+                eol: coding.eol,
+                ..current
+            };
+        }
+        if coding.eol == Eol::Undecided && coding.has_eol_variants() {
+            coding.eol = current.eol;
+        }
+    }
+    let name: GcObj = coding.symbol(cx).into();
+    env.vars.insert(sym::BUFFER_FILE_CODING_SYSTEM, name);
+    if nomodify.is_none() {
+        crate::buffer::get_current_buffer(env)
+            .lock()?
+            .set_modified(true);
+    }
+    Ok(false)
+}
+
+defsym!(CODING_SYSTEM_ERROR);
+defsym!(NO_CONVERSION);
+defvar!(CODING_SYSTEM_FOR_READ);
+defvar!(CODING_SYSTEM_FOR_WRITE);
+defvar!(LAST_CODING_SYSTEM_USED);
+defvar!(BUFFER_FILE_CODING_SYSTEM, sym::UTF_8_UNIX);
+defsym!(UTF_8_UNIX);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_coding() {
+        let get = |name| CodingSystem::get(name).unwrap();
+        assert_eq!(get("utf-8").decode(b"caf\xC3\xA9").1, "café");
+        assert_eq!(get("utf-8").decode(b"a\xE9b\xC3").1, "a\u{E9}b\u{C3}");
+        assert_eq!(get("utf-8-with-signature").decode(b"\xEF\xBB\xBFx").1, "x");
+        assert_eq!(get("utf-8").decode(b"\xEF\xBB\xBFx").1, "\u{FEFF}x");
+        assert_eq!(get("latin-1").decode(b"caf\xE9").1, "café");
+        assert_eq!(get("latin-1").encode("café €"), b"caf\xE9 ?");
+        assert_eq!(get("us-ascii").encode("café"), b"caf?");
+        assert_eq!(get("utf-16").encode("a😀"), b"\xFE\xFF\0a\xD8\x3D\xDE\x00");
+        assert_eq!(get("utf-16le").encode("a"), b"a\0");
+        assert_eq!(get("utf-16").decode(b"\xFF\xFEa\0b\0").1, "ab");
+        assert_eq!(get("utf-16be").decode(b"\xD8\x3D\xDE\x00\0a").1, "😀a");
+        assert_eq!(get("binary").encode("\u{E9}é"), b"\xE9\xE9");
+        assert_eq!(get("binary").encode("€"), "€".as_bytes());
+        assert_eq!(get("binary").decode(b"\xE9").1, "\u{E9}");
+        assert_eq!(get("iso-8859-1").base, "iso-latin-1");
+        assert!(CodingSystem::get("utf-9").is_none());
+    }
+
+    #[test]
+    fn test_detect() {
+        let names = |bytes: &[u8]| -> Vec<&str> { detect(bytes).iter().map(|x| x.name).collect() };
+        assert_eq!(names(b"abc"), ["undecided"]);
+        assert_eq!(names("café".as_bytes())[..2], ["utf-8", "iso-latin-1"]);
+        assert_eq!(
+            names(b"caf\xE9!"),
+            ["iso-latin-1", "raw-text", "no-conversion"]
+        );
+        assert_eq!(names(b"\xEF\xBB\xBFx")[0], "utf-8-with-signature");
+        assert_eq!(names(b"\xFF\xFEa\0")[0], "utf-16le-with-signature");
+        assert_eq!(names(b"\xFF\xFE\0\0a\0\0\0")[0], "utf-32");
+        let undecided = CodingSystem::get("undecided").unwrap();
+        assert_eq!(undecided.decode(b"\xFE\xFF\0a").1, "a");
+        assert_eq!(undecided.decode(b"\xFF\xFE\0\0a\0\0\0").1, "a");
+        assert_eq!(undecided.decode(b"caf\xE9").1, "café");
+        assert_eq!(names(b"\xE9\0")[..2], ["iso-latin-1", "utf-16le"]);
+        let latin1 = CodingSystem::get("latin-1").unwrap();
+        PREFERRED.lock().unwrap().push(latin1);
+        assert_eq!(names("é".as_bytes())[..3], ["latin-1", "utf-8", "utf-16le"]);
+        PREFERRED.lock().unwrap().clear();
+    }
+
+    #[test]
+    fn test_eol() {
+        let get = |name| CodingSystem::get(name).unwrap();
+        assert_eq!(get("utf-8-dos").eol, Eol::Dos);
+        assert_eq!(get("latin-1-mac").full_name(), "latin-1-mac");
+        assert_eq!(get("binary").full_name(), "binary");
+        assert!(CodingSystem::get("binary-dos").is_none());
+        assert!(CodingSystem::get("utf-8-cpm").is_none());
+        let decode = |name, bytes: &[u8]| {
+            let (coding, text) = get(name).decode(bytes);
+            (coding.full_name(), text)
+        };
+        assert_eq!(
+            decode("utf-8", b"a\r\nb\r\n"),
+            ("utf-8-dos".into(), "a\nb\n".into())
+        );
+        assert_eq!(
+            decode("utf-8", b"a\r\nb\n"),
+            ("utf-8-unix".into(), "a\r\nb\n".into())
+        );
+        assert_eq!(
+            decode("latin-1", b"a\rb"),
+            ("latin-1-mac".into(), "a\nb".into())
+        );
+        assert_eq!(
+            decode("undecided", b"ab"),
+            ("undecided".into(), "ab".into())
+        );
+        assert_eq!(
+            decode("undecided-dos", b"\xE9\r\n"),
+            ("iso-latin-1-dos".into(), "é\n".into())
+        );
+        assert_eq!(
+            decode("utf-8-unix", b"a\r\n"),
+            ("utf-8-unix".into(), "a\r\n".into())
+        );
+        assert_eq!(
+            decode("utf-16le", b"a\0\r\0\n\0"),
+            ("utf-16le-dos".into(), "a\n".into())
+        );
+        assert_eq!(
+            decode("binary", b"a\r\n"),
+            ("binary".into(), "a\r\n".into())
+        );
+        assert_eq!(get("utf-8-dos").encode("a\nb"), b"a\r\nb");
+        assert_eq!(get("utf-16be-mac").encode("\n"), b"\0\r");
+        assert_eq!(get("utf-8").encode("a\n"), b"a\n");
+    }
+
+    #[test]
+    fn test_coding_cookie() {
+        let cookie = |text: &str| coding_cookie(text.as_bytes()).map(|x| x.name);
+        assert_eq!(cookie(";; -*- coding: latin-1 -*-\n"), Some("latin-1"));
+        assert_eq!(
+            cookie(";; -*- mode: text; coding:utf-16le; -*-"),
+            Some("utf-16le")
+        );
+        assert_eq!(
+            cookie("#!/bin/sh\n# -*- coding: binary -*-\n"),
+            Some("binary")
+        );
+        assert_eq!(cookie("x\n;; -*- coding: latin-1 -*-\n"), None);
+        assert_eq!(cookie(";; -*- precoding: latin-1 -*-\n"), None);
+        assert_eq!(cookie(";; -*- coding: latin-9 -*-\n"), None);
+        let vars = "x\n;; Local Variables:\n;; fill-column: 70\n;; coding: us-ascii\n;; End:\n";
+        assert_eq!(cookie(vars), Some("us-ascii"));
+        let ended = "x\n;; Local Variables:\n;; End:\n;; coding: us-ascii\n";
+        assert_eq!(cookie(ended), None);
+    }
+
+    #[test]
+    fn test_coding_functions() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let cases = [
+            (
+                "(decode-coding-string (unibyte-string #x63 #xc3 #xa9) 'utf-8)",
+                "\"cé\"",
+            ),
+            (
+                "(list (encode-coding-string \"é\" 'latin-1) last-coding-system-used)",
+                "(\"[233]\" latin-1)",
+            ),
+            ("(length (encode-coding-string \"é\" 'utf-16))", "4"),
+            ("(coding-system-p 'latin-1)", "t"),
+            ("(coding-system-p 'latin-1-dos)", "t"),
+            ("(aref (coding-system-eol-type 'utf-8) 1)", "utf-8-dos"),
+            ("(coding-system-eol-type 'latin-1-dos)", "1"),
+            ("(coding-system-eol-type 'binary)", "0"),
+            (
+                "(coding-system-change-eol-conversion 'utf-8 'dos)",
+                "utf-8-dos",
+            ),
+            (
+                "(coding-system-change-eol-conversion 'utf-8-mac nil)",
+                "utf-8",
+            ),
+            (
+                "(coding-system-change-eol-conversion 'latin-1 0)",
+                "latin-1-unix",
+            ),
+            ("(coding-system-base 'iso-8859-1-dos)", "iso-latin-1"),
+            ("(detect-coding-string \"a\r\nb\" t)", "undecided-dos"),
+            (
+                "(encode-coding-string \"a\nb\" 'us-ascii-dos)",
+                "\"[97, 13, 10, 98]\"",
+            ),
+            (
+                "(detect-coding-string (unibyte-string #xfe #xff 0 ?a) t)",
+                "utf-16be-with-signature",
+            ),
+            ("(detect-coding-string \"abc\" t)", "undecided"),
+            ("(detect-coding-string \"abc\")", "(undecided)"),
+            ("(detect-coding-string \"a\r\nb\")", "(undecided-dos)"),
+            (
+                "(list (decode-coding-string (unibyte-string #x63 #xe9) 'undecided)
+                       last-coding-system-used)",
+                "(\"cé\" iso-latin-1)",
+            ),
+            (
+                "(memq 'no-conversion (coding-system-priority-list))",
+                "(no-conversion)",
+            ),
+            ("(coding-system-p 'latin-2)", "nil"),
+            ("(coding-system-base 'binary)", "no-conversion"),
+            ("(coding-system-type 'us-ascii)", "charset"),
+            (
+                "(coding-system-aliases 'latin-1)",
+                "(iso-latin-1 iso-8859-1 latin-1)",
+            ),
+            ("(memq 'latin-1 (coding-system-list t))", "nil"),
+            (
+                "(condition-case err (check-coding-system 'foo) (error err))",
+                "(coding-system-error foo)",
+            ),
+        ];
+        for (form, expect) in cases {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            let result = interpreter::eval(obj, None, env, cx).unwrap();
+            assert_eq!(result.to_string(), expect, "{form}");
+        }
+    }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_coding_region() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(progn
+                      (set-buffer (get-buffer-create \"coding\"))
+                      (insert \"x\" (unibyte-string #xc3 #xa9) \"y\")
+                      (list (decode-coding-region 2 4 'utf-8)
+                            (buffer-string)
+                            (point)
+                            (encode-coding-region 1 3 'utf-16be)
+                            (decode-coding-region 1 5 'utf-16be t)
+                            (length (buffer-string))))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(result.to_string(), "(1 \"xéy\" 4 4 \"xé\" 5)");
+        let form = "(list buffer-file-coding-system
+                          (progn (set-buffer-file-coding-system 'latin-1-dos t)
+                                 buffer-file-coding-system)
+                          (progn (set-buffer-file-coding-system 'utf-16le)
+                                 buffer-file-coding-system)
+                          (progn (set-buffer-file-coding-system 'undecided-mac)
+                                 buffer-file-coding-system)
+                          (progn (set-buffer-file-coding-system 'utf-8 t)
+                                 buffer-file-coding-system)
+                          (buffer-modified-p))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(utf-8-unix latin-1-dos utf-16le-dos utf-16le-mac utf-8 t)"
+        );
+    }
+}
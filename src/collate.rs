@@ -0,0 +1,187 @@
+//! Collation of strings. There is no table for each locale: the locale only
+//! chooses between comparing by code point, in the C and POSIX locales, and
+//! a single ordering for every other locale that approximates the default
+//! collation of glibc and the Unicode Collation Algorithm. That ordering
+//! compares strings first by their letters and digits without accents or
+//! case, then by accents, then by case with lowercase first, and last by
+//! the spaces and punctuation that the first levels ignore. Orderings that
+//! are specific to a language, like Swedish sorting "å" after "z", are not
+//! supported.
+use crate::ucs_normalize::{normalize, Form};
+use crate::unidata::{combining_class, decimal_digit_value, general_category};
+use fn_macros::defun;
+use std::cmp::Ordering;
+
+/// The weights of a string at each level of comparison.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SortKey {
+    /// The group of each letter, digit or symbol and its weight in the group
+    primary: Vec<(u8, u32)>,
+    /// For each letter, digit or symbol a zero followed by its accents
+    secondary: Vec<u32>,
+    /// For each letter, digit or symbol whether it is uppercase
+    tertiary: Vec<bool>,
+    /// The spaces and punctuation
+    quaternary: Vec<u32>,
+}
+
+const SYMBOL_GROUP: u8 = 1;
+const DIGIT_GROUP: u8 = 2;
+const LETTER_GROUP: u8 = 3;
+
+fn lowercase(chr: char) -> char {
+    let mut lower = chr.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => chr,
+    }
+}
+
+impl SortKey {
+    fn new(string: &str) -> Self {
+        let mut key = Self::default();
+        // compatibility variants like ligatures sort as what they stand for
+        for chr in normalize(string, Form::Nfkd).chars() {
+            let code = u32::from(chr);
+            let category = general_category(code);
+            match category.as_bytes()[0] {
+                // control and format characters are ignored
+                b'C' => {}
+                b'Z' | b'P' => key.quaternary.push(code),
+                _ if combining_class(code) != 0 || category.starts_with('M') => {
+                    key.secondary.push(code);
+                }
+                first => {
+                    let primary = match first {
+                        b'N' => match decimal_digit_value(code) {
+                            Some(digit) => (DIGIT_GROUP, digit),
+                            None => (DIGIT_GROUP, 10 + code),
+                        },
+                        b'S' => (SYMBOL_GROUP, code),
+                        _ => (LETTER_GROUP, lowercase(chr).into()),
+                    };
+                    key.primary.push(primary);
+                    key.secondary.push(0);
+                    key.tertiary.push(lowercase(chr) != chr);
+                }
+            }
+        }
+        key
+    }
+
+    fn compare(&self, other: &Self, ignore_case: bool) -> Ordering {
+        let tertiary = || match ignore_case {
+            true => Ordering::Equal,
+            false => self.tertiary.cmp(&other.tertiary),
+        };
+        self.primary
+            .cmp(&other.primary)
+            .then_with(|| self.secondary.cmp(&other.secondary))
+            .then_with(tertiary)
+            .then_with(|| self.quaternary.cmp(&other.quaternary))
+    }
+}
+
+/// Whether `locale`, or the locale of the environment if it is None, sorts
+/// strings by code point.
+fn code_point_locale(locale: Option<&str>) -> bool {
+    let from_env = || {
+        let vars = ["LC_ALL", "LC_COLLATE", "LANG"].into_iter();
+        vars.filter_map(|x| std::env::var(x).ok())
+            .find(|x| !x.is_empty())
+    };
+    match locale.map(str::to_owned).or_else(from_env) {
+        Some(locale) => {
+            let name = locale.split(['.', '@']).next().unwrap_or_default();
+            matches!(name, "" | "C" | "POSIX")
+        }
+        None => true,
+    }
+}
+
+fn collate(s1: &str, s2: &str, locale: Option<&str>, ignore_case: bool) -> Ordering {
+    if code_point_locale(locale) {
+        return match ignore_case {
+            true => s1.chars().map(lowercase).cmp(s2.chars().map(lowercase)),
+            false => s1.cmp(s2),
+        };
+    }
+    SortKey::new(s1).compare(&SortKey::new(s2), ignore_case)
+}
+
+/// Return t if S1 sorts before S2 in the collation order of LOCALE, a
+/// locale name like "en_US.UTF-8", or of the locale of the environment if
+/// it is nil. In the C and POSIX locales, this is like `string-lessp`.
+/// Every other locale uses the same ordering, where accents and then case
+/// only matter when the letters are equal, and spaces and punctuation only
+/// when the strings are otherwise equal. If IGNORE-CASE is non-nil, case
+/// is ignored.
+#[defun]
+fn string_collate_lessp(s1: &str, s2: &str, locale: Option<&str>, ignore_case: Option<()>) -> bool {
+    collate(s1, s2, locale, ignore_case.is_some()) == Ordering::Less
+}
+
+/// Return t if S1 and S2 are equal in the collation order of LOCALE, a
+/// locale name like "en_US.UTF-8", or of the locale of the environment if
+/// it is nil. In the C and POSIX locales, this is like `string-equal`. In
+/// every other locale, strings that differ only in how their characters
+/// are composed are equal. If IGNORE-CASE is non-nil, case is ignored.
+#[defun]
+fn string_collate_equalp(
+    s1: &str,
+    s2: &str,
+    locale: Option<&str>,
+    ignore_case: Option<()>,
+) -> bool {
+    collate(s1, s2, locale, ignore_case.is_some()) == Ordering::Equal
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collate() {
+        let lessp = |s1, s2| string_collate_lessp(s1, s2, Some("en_US.UTF-8"), None);
+        assert!(lessp("apple", "Banana"));
+        assert!(!lessp("Banana", "apple"));
+        assert!(lessp("a", "A"));
+        assert!(lessp("resume", "résumé"));
+        assert!(lessp("résumé", "rose"));
+        assert!(lessp("file10", "file2"));
+        assert!(string_collate_lessp("B", "a", Some("POSIX"), None));
+        assert!(!string_collate_lessp("B", "a", Some("POSIX"), Some(())));
+        // spaces and punctuation only break ties
+        let mut files = ["1.2", "12", "1 2", "1.1", "11", "1 1"];
+        files.sort_by(|a, b| collate(a, b, Some("en_US.UTF-8"), false));
+        assert_eq!(files, ["11", "1 1", "1.1", "12", "1 2", "1.2"]);
+        let equalp = |s1, s2, ignore_case: bool| {
+            string_collate_equalp(s1, s2, Some("en_US.UTF-8"), ignore_case.then_some(()))
+        };
+        assert!(equalp("e\u{301}", "é", false));
+        assert!(!equalp("abc", "ABC", false));
+        assert!(equalp("abc", "ABC", true));
+        assert!(equalp("ﬁle", "file", false));
+        assert!(!equalp("abc", "abd", true));
+        assert!(equalp("Éclair", "éclair", true));
+        assert!(!equalp("eclair", "éclair", true));
+    }
+
+    #[test]
+    fn test_collate_order() {
+        // letters first, then accents, then case with lowercase first
+        let sorted = ["eclair", "Eclair", "éclair", "Éclair", "ecole", "Ecole"];
+        let sort = |locale| {
+            let mut words = ["ecole", "Éclair", "Eclair", "éclair", "eclair", "Ecole"];
+            words.sort_by(|a, b| collate(a, b, Some(locale), false));
+            words
+        };
+        assert_eq!(sort("en_US.UTF-8"), sorted);
+        // the locale doesn't change the ordering
+        assert_eq!(sort("sv_SE.UTF-8"), sorted);
+        assert_eq!(
+            sort("C"),
+            ["Eclair", "Ecole", "eclair", "ecole", "Éclair", "éclair"]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! A data-driven conformance suite for the core of the interpreter.
+//!
+//! The suite is a file of Lisp data with one list per area:
+//!
+//! ```lisp
+//! (numbers
+//!  ((+ 1 2) "3")
+//!  ((car 1) :error wrong-type-argument)
+//!  ((/ 5 0) :error arith-error :known-failure))
+//! ```
+//!
+//! A case is a form and the printed value it should return in GNU Emacs, or
+//! `:error` and the error symbol it should signal. Cases that rune does not
+//! pass yet are marked `:known-failure`, so the report shows how far along
+//! each area is while any other failure is caught as a regression. A known
+//! failure that starts passing is reported too, so its mark can be removed.
+//! Cases that need a Cargo feature are marked `:feature FEATURE`, and are
+//! skipped when rune is built without it.
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Rt},
+    object::{GcObj, Object},
+};
+use crate::{interpreter, reader, root};
+use anyhow::{anyhow, bail, Result};
+use std::fmt::{self, Display};
+use std::panic::{self, AssertUnwindSafe};
+
+/// What evaluating a form did.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The printed value
+    Value(String),
+    /// The symbol of the error that was signaled
+    Error(String),
+    /// The interpreter panicked
+    Panic,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Value(value) => write!(f, "=> {value}"),
+            Outcome::Error(symbol) => write!(f, "signals {symbol}"),
+            Outcome::Panic => write!(f, "panics"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Failure {
+    form: String,
+    expected: Outcome,
+    actual: Outcome,
+}
+
+/// The results of the cases in one area.
+#[derive(Debug, Default)]
+pub(crate) struct Area {
+    name: String,
+    total: usize,
+    passed: usize,
+    known_failures: usize,
+    /// Cases that need a feature rune was built without
+    skipped: usize,
+    /// Cases that fail without being marked as known failures
+    regressions: Vec<Failure>,
+    /// Known failures that pass
+    fixed: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    areas: Vec<Area>,
+}
+
+impl Report {
+    /// Whether every case gave the result the suite expects of rune.
+    pub(crate) fn is_ok(&self) -> bool {
+        self.areas
+            .iter()
+            .all(|x| x.regressions.is_empty() && x.fixed.is_empty())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.areas.iter().map(|x| x.name.len()).max().unwrap_or(0);
+        let (mut passed, mut total) = (0, 0);
+        for area in &self.areas {
+            let percent = area.passed * 100 / area.total.max(1);
+            let name = &area.name;
+            write!(
+                f,
+                "{name:width$}  {:>4}/{:<4} {percent:>3}%  ({} known failures",
+                area.passed, area.total, area.known_failures
+            )?;
+            match area.skipped {
+                0 => writeln!(f, ")")?,
+                skipped => writeln!(f, ", {skipped} skipped)")?,
+            }
+            passed += area.passed;
+            total += area.total;
+        }
+        writeln!(f, "{:width$}  {passed:>4}/{total:<4}", "total")?;
+        for area in &self.areas {
+            for failure in &area.regressions {
+                let Failure {
+                    form,
+                    expected,
+                    actual,
+                } = failure;
+                writeln!(
+                    f,
+                    "FAIL {}: {form} {actual}, expected {expected}",
+                    area.name
+                )?;
+            }
+            for form in &area.fixed {
+                writeln!(
+                    f,
+                    "FIXED {}: {form} passes but is marked :known-failure",
+                    area.name
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Evaluate `form` in a fresh environment. Errors that are not signals are
+/// reported as `error`, as `condition-case` sees them.
+fn evaluate(form: &Rt<GcObj>, cx: &mut Context) -> Outcome {
+    root!(env, Env::default(), cx);
+    crate::core::env::init_variables(cx, env);
+    let wrapped = list![
+        sym::CONDITION_CASE,
+        sym::ERR,
+        list![sym::LIST, list![sym::QUOTE, sym::OK; cx], form.bind(cx); cx],
+        list![sym::ERROR, list![sym::CAR, sym::ERR; cx]; cx];
+        cx
+    ];
+    root!(wrapped, cx);
+    match interpreter::eval(wrapped, None, env, cx) {
+        Ok(value) => match value.as_list().map(|mut x| (x.next(), x.next())) {
+            Ok((Some(Ok(tag)), Some(Ok(value)))) if tag == sym::OK => {
+                Outcome::Value(value.to_string())
+            }
+            _ => Outcome::Error(value.to_string()),
+        },
+        // a throw with no catch, which condition-case does not handle
+        Err(_) => Outcome::Error(sym::NO_CATCH.to_string()),
+    }
+}
+
+/// Whether rune was built with the Cargo feature `name`.
+fn has_feature(name: &str) -> Result<bool> {
+    Ok(match name {
+        "buffers" => cfg!(feature = "buffers"),
+        "regex" => cfg!(feature = "regex"),
+        "processes" => cfg!(feature = "processes"),
+        "network" => cfg!(feature = "network"),
+        "tls" => cfg!(feature = "tls"),
+        "json" => cfg!(feature = "json"),
+        "treesit" => cfg!(feature = "treesit"),
+        "terminal" => cfg!(feature = "terminal"),
+        _ => bail!("Unknown feature: {name}"),
+    })
+}
+
+/// A case of the suite.
+struct Case<'ob> {
+    form: GcObj<'ob>,
+    expected: Outcome,
+    known_failure: bool,
+    /// Whether rune has the features the case needs
+    supported: bool,
+}
+
+/// Parse a case, with the marks that follow its expected outcome.
+fn parse_case(case: GcObj) -> Result<Case> {
+    let mut elems = case.as_list()?;
+    let (Some(form), Some(expected)) = (elems.next(), elems.next()) else {bail!("Invalid case: {case}")};
+    let expected = match expected?.untag() {
+        Object::String(value) => {
+            let value: &str = value.try_into()?;
+            Outcome::Value(value.to_owned())
+        }
+        Object::Symbol(sym::KW_ERROR) => match elems.next() {
+            Some(symbol) => Outcome::Error(symbol?.to_string()),
+            None => bail!("Missing error symbol: {case}"),
+        },
+        _ => bail!("Invalid expected result: {case}"),
+    };
+    let (mut known_failure, mut supported) = (false, true);
+    while let Some(mark) = elems.next().transpose()? {
+        match mark.untag() {
+            Object::Symbol(sym::KW_KNOWN_FAILURE) => known_failure = true,
+            Object::Symbol(sym::KW_FEATURE) => match elems.next().transpose()? {
+                Some(feature) => supported &= has_feature(&feature.to_string())?,
+                None => bail!("Missing feature: {case}"),
+            },
+            _ => bail!("Invalid case: {case}"),
+        }
+    }
+    Ok(Case {
+        form: form?,
+        expected,
+        known_failure,
+        supported,
+    })
+}
+
+/// Run every case in `suite`.
+pub(crate) fn run(suite: &str, cx: &mut Context) -> Result<Report> {
+    let mut report = Report::default();
+    let mut pos = 0;
+    loop {
+        let area = match reader::read(&suite[pos..], cx) {
+            Ok((area, len)) => {
+                pos += len;
+                area
+            }
+            Err(reader::Error::EmptyStream) => return Ok(report),
+            Err(e) => return Err(anyhow!(e)),
+        };
+        root!(area, cx);
+        let mut elems = area.bind(cx).as_list()?;
+        let Some(name) = elems.next() else {bail!("Empty area")};
+        let mut result = Area {
+            name: name?.to_string(),
+            ..Area::default()
+        };
+        let count = elems.count();
+        for idx in 0..count {
+            // cases are fetched again after each evaluation, which can
+            // collect garbage
+            let case = area.bind(cx).as_list()?.nth(idx + 1).unwrap()?;
+            let Case {
+                form,
+                expected,
+                known_failure,
+                supported,
+            } = parse_case(case)?;
+            if !supported {
+                result.skipped += 1;
+                continue;
+            }
+            let form_text = form.to_string();
+            root!(form, cx);
+            // a panic only fails this case, since the roots and the
+            // environment of the case are dropped as it unwinds
+            let actual = panic::catch_unwind(AssertUnwindSafe(|| evaluate(form, cx)))
+                .unwrap_or(Outcome::Panic);
+            result.total += 1;
+            match (actual == expected, known_failure) {
+                (true, false) => result.passed += 1,
+                (true, true) => {
+                    result.passed += 1;
+                    result.fixed.push(form_text);
+                }
+                (false, true) => result.known_failures += 1,
+                (false, false) => result.regressions.push(Failure {
+                    form: form_text,
+                    expected,
+                    actual,
+                }),
+            }
+        }
+        report.areas.push(result);
+    }
+}
+
+defsym!(OK);
+defsym!(ERR);
+defsym!(KW_ERROR);
+defsym!(KW_KNOWN_FAILURE);
+defsym!(KW_FEATURE);
+defsym!(NO_CATCH);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_report() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let suite = r#"
+            ;; comments are skipped
+            (basics
+             ((+ 1 2) "3")
+             ((car 1) :error error)
+             ((signal 'wrong-type-argument '(1)) :error wrong-type-argument)
+             ((+ 1 1) "3" :known-failure)
+             ((car '(1)) "1" :feature regex)
+             ((car 1) "1" :known-failure :feature regex))
+            (regressions
+             ((list 1 "a") "(1 a)")
+             ((+ 1 1) "2" :known-failure))
+        "#;
+        let report = run(suite, cx).unwrap();
+        assert!(!report.is_ok());
+        let text = report.to_string();
+        if cfg!(feature = "regex") {
+            assert!(text.contains("basics          4/6     66%  (2 known failures)"));
+        } else {
+            assert!(text.contains("basics          3/4     75%  (1 known failures, 2 skipped)"));
+        }
+        assert!(text.contains("FAIL regressions: (list 1 \"a\") => (1 \"a\"), expected => (1 a)"));
+        assert!(text.contains("FIXED regressions: (+ 1 1)"));
+        assert!(run("(basics ((+ 1 1)))", cx).is_err());
+        assert!(run("(basics ((+ 1 1) \"2\" :feature none))", cx).is_err());
+    }
+}
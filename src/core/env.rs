@@ -1,24 +1,124 @@
 #![allow(unstable_name_collisions)]
-use super::gc::{Block, Context, Rt};
-use super::object::{CloneIn, Function, Gc, GcObj};
-use crate::hashmap::HashMap;
+use super::gc::{Block, Context, IntoRoot, Rt, Trace};
+#[cfg(feature = "buffers")]
+use super::object::TagType;
+use super::object::{
+    Buffer, CharTable, CloneIn, Function, Gc, GcObj, Overlay, Process, RawObj, WithLifetime,
+};
+use crate::hashmap::{HashMap, HashSet};
+use crate::intervals::IntervalTree;
 use anyhow::{anyhow, Result};
 use fn_macros::Trace;
 use std::sync::Mutex;
 
+mod cache;
+mod pure;
 mod symbol;
+pub(crate) use cache::*;
 pub(crate) use symbol::*;
 
+/// The properties of an interval of text.
+pub(crate) type TextProps<'ob> = Vec<(Symbol<'ob>, GcObj<'ob>)>;
+
 #[derive(Debug, Default, Trace)]
 pub(crate) struct Env {
     pub(crate) vars: HashMap<Symbol<'static>, GcObj<'static>>,
     pub(crate) props: HashMap<Symbol<'static>, Vec<(Symbol<'static>, GcObj<'static>)>>,
+    /// The properties of each overlay, which are dropped when it is deleted so
+    /// that the overlay can be freed
+    pub(crate) overlay_props: HashMap<Gc<&'static Overlay>, Vec<(Symbol<'static>, GcObj<'static>)>>,
+    /// The functions and property list of each process. Processes are not
+    /// owned by a context, so these are rooted here
+    pub(crate) process_props: HashMap<Gc<&'static Process>, Vec<(Symbol<'static>, GcObj<'static>)>>,
+    /// Text properties of each buffer that has any, rooted here for the same
+    /// reason
+    pub(crate) text_props: HashMap<Gc<&'static Buffer>, IntervalTree<TextProps<'static>>>,
+    /// Values of the per-buffer variables of buffers that are not current.
+    /// The values for the current buffer are in `vars`.
+    pub(crate) buffer_vars: HashMap<Gc<&'static Buffer>, Vec<(Symbol<'static>, GcObj<'static>)>>,
+    /// The syntax table of each buffer that does not use the standard one
+    pub(crate) syntax_tables: HashMap<Gc<&'static Buffer>, Gc<&'static CharTable>>,
+    /// The standard syntax table, once it is first needed
+    pub(crate) standard_syntax_table: Option<Gc<&'static CharTable>>,
+    /// The case table of each buffer that does not use the standard one
+    pub(crate) case_tables: HashMap<Gc<&'static Buffer>, Gc<&'static CharTable>>,
+    /// The standard case table, once it is first needed
+    pub(crate) standard_case_table: Option<Gc<&'static CharTable>>,
+    /// The category table of each buffer that does not use the standard one
+    pub(crate) category_tables: HashMap<Gc<&'static Buffer>, Gc<&'static CharTable>>,
+    /// The standard category table, once it is first needed
+    pub(crate) standard_category_table: Option<Gc<&'static CharTable>>,
     pub(crate) catch_stack: Vec<GcObj<'static>>,
+    /// Active `handler-bind` handlers as (CONDITIONS . HANDLER), innermost
+    /// last. The clauses of `condition-case` are here with a nil HANDLER, so
+    /// that the handlers outside of one are not run for the errors it catches.
+    pub(crate) handler_stack: Vec<(GcObj<'static>, GcObj<'static>)>,
     exception: (GcObj<'static>, GcObj<'static>),
     #[no_trace]
     exception_id: u32,
-    binding_stack: Vec<(Symbol<'static>, Option<GcObj<'static>>)>,
+    binding_stack: Vec<Specbind<'static>>,
+    /// The start and end of each group of the last match
     pub(crate) match_data: GcObj<'static>,
+    /// The buffer the last match was in, or t if it was in a string
+    pub(crate) match_object: GcObj<'static>,
+    /// Caches registered with [`Rt<Env>::define_cache`]
+    caches: HashMap<Symbol<'static>, Cache<'static>>,
+    #[no_trace]
+    pub(crate) current_buffer: Option<&'static Buffer>,
+    /// The state of `random`
+    #[no_trace]
+    pub(crate) random: crate::fns::Random,
+    /// How many times each missing primitive was used, for
+    /// `rune-missing-primitives`
+    #[no_trace]
+    pub(crate) missing: HashMap<crate::missing::Gap, usize>,
+}
+
+/// An entry on the dynamic binding stack, undone by [`Rt<Env>::unbind`].
+#[derive(Debug)]
+pub(crate) enum Specbind<'ob> {
+    /// A bound variable and its previous value
+    Var(Symbol<'ob>, Option<GcObj<'ob>>),
+    /// The buffer to make current again
+    #[cfg(feature = "buffers")]
+    CurrentBuffer(&'static Buffer),
+    /// A per-buffer variable bound in a buffer, and its previous value there.
+    /// The value is restored in that buffer even if another buffer is
+    /// current when the binding is undone.
+    #[cfg(feature = "buffers")]
+    BufferVar(&'static Buffer, Symbol<'ob>, GcObj<'ob>),
+}
+
+impl Trace for Specbind<'_> {
+    fn trace(&self, stack: &mut Vec<RawObj>) {
+        match self {
+            Specbind::Var(sym, value) => {
+                sym.trace(stack);
+                value.trace(stack);
+            }
+            #[cfg(feature = "buffers")]
+            Specbind::CurrentBuffer(_) => {}
+            #[cfg(feature = "buffers")]
+            Specbind::BufferVar(_, sym, value) => {
+                sym.trace(stack);
+                value.trace(stack);
+            }
+        }
+    }
+}
+
+impl IntoRoot<Specbind<'static>> for Specbind<'_> {
+    unsafe fn into_root(self) -> Specbind<'static> {
+        self.with_lifetime()
+    }
+}
+
+impl<'old, 'new> WithLifetime<'new> for Specbind<'old> {
+    type Out = Specbind<'new>;
+
+    unsafe fn with_lifetime(self) -> Self::Out {
+        std::mem::transmute::<Specbind<'old>, Specbind<'new>>(self)
+    }
 }
 
 impl Rt<Env> {
@@ -43,6 +143,25 @@ impl Rt<Env> {
         }
     }
 
+    #[cfg(feature = "buffers")]
+    pub(crate) fn set_overlay_prop(
+        &mut self,
+        overlay: &'static Overlay,
+        propname: Symbol,
+        value: GcObj,
+    ) {
+        let overlay = overlay.tag();
+        match self.overlay_props.get_mut(overlay) {
+            Some(plist) => match plist.iter_mut().find(|x| x.0 == propname) {
+                Some(x) => x.1.set(value),
+                None => plist.push((propname, value)),
+            },
+            None => {
+                self.overlay_props.insert(overlay, vec![(propname, value)]);
+            }
+        }
+    }
+
     pub(in crate::core) fn set_exception(&mut self, tag: GcObj, data: GcObj) -> u32 {
         self.exception.0.set(tag);
         self.exception.1.set(data);
@@ -58,31 +177,78 @@ impl Rt<Env> {
     }
 
     pub(crate) fn varbind(&mut self, var: Symbol, value: GcObj, cx: &Context) {
+        #[cfg(feature = "buffers")]
+        if crate::buffer::is_per_buffer_var(var) {
+            let buffer = crate::buffer::get_current_buffer(self);
+            let prev_value = crate::buffer::buffer_var(buffer, var, self, cx);
+            self.binding_stack
+                .push(Specbind::BufferVar(buffer, var, prev_value));
+            self.vars.insert(var, value);
+            return;
+        }
         let prev_value = self.vars.get(var).map(|x| x.bind(cx));
-        self.binding_stack.push((var, prev_value));
+        self.binding_stack.push(Specbind::Var(var, prev_value));
         self.vars.insert(var, value);
     }
 
+    /// Save the current buffer so that the matching `unbind` makes it current
+    /// again.
+    #[cfg(feature = "buffers")]
+    pub(crate) fn bind_current_buffer(&mut self) {
+        let buffer = crate::buffer::get_current_buffer(self);
+        self.binding_stack.push(Specbind::CurrentBuffer(buffer));
+    }
+
     pub(crate) fn unbind(&mut self, count: u16, cx: &Context) {
         for _ in 0..count {
             match self.binding_stack.bind_mut(cx).pop() {
-                Some((sym, val)) => match val {
+                Some(Specbind::Var(sym, val)) => match val {
                     Some(val) => self.vars.insert(sym, val),
                     None => self.vars.remove(sym),
                 },
+                #[cfg(feature = "buffers")]
+                Some(Specbind::CurrentBuffer(buffer)) => {
+                    crate::buffer::restore_current_buffer(buffer, self, cx);
+                }
+                #[cfg(feature = "buffers")]
+                Some(Specbind::BufferVar(buffer, sym, val)) => {
+                    // the binding ends with a killed buffer
+                    if buffer.is_live() {
+                        crate::buffer::set_buffer_var(buffer, sym, val, self);
+                    }
+                }
                 None => panic!("Binding stack was empty"),
             }
         }
     }
 
-    pub(crate) fn defvar(&mut self, var: Symbol, value: GcObj) -> Result<()> {
+    /// The number of entries on the binding stack.
+    pub(crate) fn binding_depth(&self) -> usize {
+        self.binding_stack.len()
+    }
+
+    /// Undo bindings until the stack is back to DEPTH. This also undoes any
+    /// bindings left behind by forms that exited with an error.
+    pub(crate) fn unbind_to(&mut self, depth: usize, cx: &Context) {
+        let count = self.binding_stack.len().saturating_sub(depth);
+        self.unbind(count as u16, cx);
+    }
+
+    pub(crate) fn defvar<'ob>(
+        &mut self,
+        var: Symbol,
+        value: GcObj<'ob>,
+        cx: &'ob Context,
+    ) -> Result<()> {
         self.set_var(var, value)?;
         var.make_special();
         // If this variable was unbound previously in the binding stack,
         // we will bind it to the new value
-        for binding in self.binding_stack.iter_mut() {
-            if binding.0 == var && binding.1.is_none() {
-                binding.1.set(value);
+        for binding in self.binding_stack.bind_mut(cx).iter_mut() {
+            if let Specbind::Var(sym, prev @ None) = binding {
+                if *sym == var {
+                    *prev = Some(value);
+                }
             }
         }
         Ok(())
@@ -92,6 +258,7 @@ impl Rt<Env> {
 pub(crate) struct ObjectMap {
     map: SymbolMap,
     block: Block<true>,
+    pure_strings: HashSet<pure::PureString>,
 }
 
 /// Box is marked as unique. However we are freely sharing the pointer to this
@@ -204,6 +371,11 @@ impl ObjectMap {
     pub(crate) fn get(&self, name: &str) -> Option<Symbol> {
         self.map.get(name)
     }
+
+    /// Iterate over every interned symbol.
+    pub(crate) fn symbols(&self) -> impl Iterator<Item = Symbol<'_>> {
+        self.map.map.values().map(|x| Symbol::new(x.as_ref()))
+    }
 }
 
 // This file includes all symbol definitions. Generated by build.rs
@@ -240,6 +412,32 @@ mod test {
         intern("foo", cx);
     }
 
+    #[test]
+    fn static_symbols() {
+        assert_eq!(sym!(let*), sym::LET_STAR);
+        assert_eq!(sym!(&optional), sym::AND_OPTIONAL);
+        assert_eq!(sym!(:test), sym::KW_TEST);
+        assert_eq!(sym!(t), sym::TRUE);
+        assert_eq!(sym!(+), sym::ADD);
+        assert_eq!(
+            sym!(inhibit - modification - hooks).name(),
+            "inhibit-modification-hooks"
+        );
+        // symbols only used through `sym!` are defined too
+        assert_eq!(sym!(not).name(), "not");
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(intern("not", cx), sym!(not));
+    }
+
+    #[test]
+    fn builtin_symbols_are_unique() {
+        let mut names = HashSet::default();
+        for sym in &sym::BUILTIN_SYMBOLS {
+            assert!(names.insert(sym.name()), "{} is defined twice", sym.name());
+        }
+    }
+
     #[test]
     fn symbol_func() {
         let roots = &RootSet::default();
@@ -0,0 +1,146 @@
+//! Named caches of objects.
+//!
+//! A static cannot hold objects, because the collector does not see it. Caches
+//! are kept in the [`Env`] instead, so their entries are rooted like any other
+//! variable. Each cache has a size limit, and all of them are emptied by
+//! `clear-caches` or when the live objects are over the limits of the
+//! [`Context`].
+use super::{Env, Symbol};
+use crate::core::gc::{Context, IntoRoot, Rt};
+use crate::core::object::{GcObj, WithLifetime};
+use fn_macros::Trace;
+
+/// A map from keys to values, compared with `equal`. Once it holds `limit`
+/// entries, adding another evicts the least recently used one.
+#[derive(Debug, Trace)]
+pub(crate) struct Cache<'ob> {
+    #[no_trace]
+    limit: usize,
+    /// Entries from the least to the most recently used
+    entries: Vec<(GcObj<'ob>, GcObj<'ob>)>,
+}
+
+impl IntoRoot<Cache<'static>> for Cache<'_> {
+    unsafe fn into_root(self) -> Cache<'static> {
+        self.with_lifetime()
+    }
+}
+
+impl<'old, 'new> WithLifetime<'new> for Cache<'old> {
+    type Out = Cache<'new>;
+
+    unsafe fn with_lifetime(self) -> Self::Out {
+        std::mem::transmute::<Cache<'old>, Cache<'new>>(self)
+    }
+}
+
+impl Rt<Cache<'static>> {
+    /// Make entry `idx` the most recently used.
+    fn touch(&mut self, idx: usize) {
+        self.entries[idx..].rotate_left(1);
+    }
+
+    fn evict(&mut self) {
+        let len = self.entries.len();
+        let excess = len.saturating_sub(self.limit);
+        self.entries.rotate_left(excess);
+        self.entries.truncate(len - excess);
+    }
+}
+
+impl Rt<Env> {
+    /// Register a cache called `name` that holds up to `limit` entries. If
+    /// it already exists, only its limit changes.
+    pub(crate) fn define_cache(&mut self, name: Symbol, limit: usize) {
+        match self.caches.get_mut(name) {
+            Some(cache) => {
+                cache.limit = limit;
+                cache.evict();
+            }
+            None => {
+                let cache = Cache {
+                    limit,
+                    entries: Vec::new(),
+                };
+                self.caches.insert(name, cache);
+            }
+        }
+    }
+
+    /// The value of `key` in the cache called `name`, if it is there.
+    pub(crate) fn cache_get<'ob>(
+        &mut self,
+        name: Symbol,
+        key: GcObj,
+        cx: &'ob Context,
+    ) -> Option<GcObj<'ob>> {
+        let cache = self.caches.get_mut(name)?;
+        let idx = cache.entries.iter().position(|x| x.0 == key)?;
+        cache.touch(idx);
+        Some(cache.entries.last()?.1.bind(cx))
+    }
+
+    /// Set the value of `key` in the cache called `name`. Returns false if
+    /// there is no such cache.
+    pub(crate) fn cache_put(&mut self, name: Symbol, key: GcObj, value: GcObj) -> bool {
+        let Some(cache) = self.caches.get_mut(name) else {return false};
+        match cache.entries.iter().position(|x| x.0 == key) {
+            Some(idx) => {
+                cache.touch(idx);
+                let last = cache.entries.len() - 1;
+                cache.entries[last].1.set(value);
+            }
+            None => {
+                cache.entries.push((key, value));
+                cache.evict();
+            }
+        }
+        true
+    }
+
+    /// Remove the entries of every cache. Returns false if they were all
+    /// empty already.
+    pub(crate) fn clear_caches(&mut self, cx: &Context) -> bool {
+        let names: Vec<_> = self.caches.keys().map(|x| x.bind(cx)).collect();
+        let mut cleared = false;
+        for name in names {
+            if let Some(cache) = self.caches.get_mut(name) {
+                cleared |= !cache.entries.is_empty();
+                cache.entries.clear();
+            }
+        }
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::sym;
+    use crate::core::gc::RootSet;
+    use crate::root;
+
+    #[test]
+    fn eviction() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let name = sym::NIL;
+        assert!(!env.cache_put(name, 1.into(), 1.into()));
+        env.define_cache(name, 2);
+        let key = cx.add("a");
+        env.cache_put(name, key, 10.into());
+        env.cache_put(name, 2.into(), 20.into());
+        // `a` becomes the most recently used, so 2 is evicted
+        assert_eq!(env.cache_get(name, cx.add("a"), cx), Some(10.into()));
+        env.cache_put(name, 3.into(), 30.into());
+        assert_eq!(env.cache_get(name, 2.into(), cx), None);
+        env.cache_put(name, 3.into(), 31.into());
+        assert_eq!(env.cache_get(name, 3.into(), cx), Some(31.into()));
+        env.define_cache(name, 1);
+        assert_eq!(env.cache_get(name, key, cx), None);
+        assert!(env.clear_caches(cx));
+        assert_eq!(env.cache_get(name, 3.into(), cx), None);
+        assert!(!env.clear_caches(cx));
+    }
+}
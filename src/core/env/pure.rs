@@ -0,0 +1,154 @@
+//! Pure space.
+//!
+//! Pure objects are allocated in the global block of the [`ObjectMap`] and
+//! are read-only. They are marked when they are created and never unmarked,
+//! so the garbage collector does not trace through them. Strings with the
+//! same contents share a single copy.
+use super::ObjectMap;
+use crate::core::cons::Cons;
+use crate::core::gc::{Block, Context};
+use crate::core::object::{
+    CloneIn, GcObj, IntoObject, LispString, Object, RecordBuilder, WithLifetime,
+};
+use crate::hashmap::HashSet;
+use bstr::BStr;
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+
+/// A string in pure space, compared by its contents.
+pub(super) struct PureString(&'static LispString);
+
+impl PartialEq for PureString {
+    fn eq(&self, other: &Self) -> bool {
+        **self.0 == **other.0
+    }
+}
+
+impl Eq for PureString {}
+
+impl Hash for PureString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self.0).hash(state);
+    }
+}
+
+impl Borrow<BStr> for PureString {
+    fn borrow(&self) -> &BStr {
+        self.0
+    }
+}
+
+impl ObjectMap {
+    /// Copy `obj` into pure space. Objects that are already pure are returned
+    /// as is.
+    pub(crate) fn purecopy<'ob>(&mut self, obj: GcObj, cx: &'ob Context) -> GcObj<'ob> {
+        let copy = pure_copy(obj, &self.block, &mut self.pure_strings);
+        self.block.uninterned_symbol_map.clear();
+        let stack = &mut vec![copy.into_raw()];
+        while let Some(raw) = stack.pop() {
+            let obj = unsafe { GcObj::from_raw(raw) };
+            if !obj.is_marked() {
+                obj.trace_mark(stack);
+            }
+        }
+        cx.bind(copy)
+    }
+}
+
+// The only mutable part of a pure string is its mark bit, which is not hashed
+#[allow(clippy::mutable_key_type)]
+fn pure_copy<'a>(
+    obj: GcObj,
+    block: &'a Block<true>,
+    strings: &mut HashSet<PureString>,
+) -> GcObj<'a> {
+    // Pure objects stay marked. This is also true of objects that are never
+    // collected, like interned symbols and integers.
+    if obj.is_marked() {
+        return unsafe { obj.with_lifetime() };
+    }
+    match obj.untag() {
+        Object::String(string) => {
+            if let Some(pure) = strings.get::<BStr>(string) {
+                if pure.0.is_multibyte() == string.is_multibyte() {
+                    return pure.0.into();
+                }
+            }
+            let copy = string.clone_in(block);
+            strings.insert(PureString(unsafe { copy.untag().with_lifetime() }));
+            copy.into()
+        }
+        Object::Float(float) => (**float).into_obj(block).into(),
+        Object::Cons(_) => {
+            // iterate over the list to avoid recursing on the cdr
+            let mut elements = Vec::new();
+            let mut tail = obj;
+            while let Object::Cons(cons) = tail.untag() {
+                if tail.is_marked() {
+                    break;
+                }
+                elements.push(pure_copy(cons.car(), block, strings));
+                tail = cons.cdr();
+            }
+            let mut list = pure_copy(tail, block, strings);
+            for element in elements.into_iter().rev() {
+                list = unsafe { Cons::new(element, list).into_obj(block).into() };
+            }
+            list
+        }
+        Object::Vec(vec) => {
+            let vec: Vec<_> = vec
+                .iter()
+                .map(|x| pure_copy(x.get(), block, strings))
+                .collect();
+            vec.into_obj(block).into()
+        }
+        Object::Record(record) => {
+            let vec = record
+                .iter()
+                .map(|x| pure_copy(x.get(), block, strings))
+                .collect();
+            RecordBuilder(vec).into_obj(block).into()
+        }
+        _ => obj.clone_in(block),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::env::INTERNED_SYMBOLS;
+    use crate::core::gc::{Context, RootSet};
+    use crate::core::object::Object;
+    use crate::reader;
+    use crate::root;
+
+    #[test]
+    fn test_purecopy() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let obj = reader::read("(\"pure\" [1 \"pure\" 1.5] . \"pure\")", cx)
+            .unwrap()
+            .0;
+        let expect = obj.to_string();
+        let pure = INTERNED_SYMBOLS.lock().unwrap().purecopy(obj, cx);
+        assert_eq!(pure, obj);
+        root!(pure, cx);
+        cx.garbage_collect(true);
+        let pure = pure.bind(cx);
+        assert!(pure.is_marked());
+        assert_eq!(pure.to_string(), expect);
+
+        let Object::Cons(cons) = pure.untag() else {unreachable!()};
+        assert!(cons.set_car(cx.add(1)).is_err());
+        let Object::Vec(vec) = cons.cdr().as_cons().car().untag() else {unreachable!()};
+        assert!(vec.try_mut().is_err());
+        // identical strings share storage
+        let string = cons.car();
+        assert!(string.ptr_eq(vec[1].get()));
+        assert!(string.ptr_eq(cons.cdr().as_cons().cdr()));
+
+        // copying a pure object is a no-op
+        let copy = INTERNED_SYMBOLS.lock().unwrap().purecopy(pure, cx);
+        assert!(copy.ptr_eq(pure));
+    }
+}
@@ -10,6 +10,8 @@ use super::{
 pub(crate) struct EvalError {
     backtrace: Vec<String>,
     pub(crate) error: ErrorType,
+    /// `handler-bind` handlers have already been run for this error
+    pub(crate) handled: bool,
 }
 
 #[derive(Debug)]
@@ -41,6 +43,7 @@ impl EvalError {
         Self {
             backtrace: Vec::new(),
             error: ErrorType::Err(error),
+            handled: false,
         }
     }
 
@@ -48,6 +51,7 @@ impl EvalError {
         Self {
             backtrace: Vec::new(),
             error: ErrorType::Signal(env.set_exception(error_symbol, data)),
+            handled: false,
         }
     }
 
@@ -55,6 +59,7 @@ impl EvalError {
         Self {
             backtrace: Vec::new(),
             error: ErrorType::Throw(env.set_exception(tag, data)),
+            handled: false,
         }
     }
 
@@ -67,12 +72,24 @@ impl EvalError {
         Self {
             backtrace: vec![format!("{name} {display}")],
             error: ErrorType::Err(error),
+            handled: false,
         }
     }
 
     pub(crate) fn add_trace(mut self, name: &str, args: &[Rt<GcObj>]) -> Self {
-        let display = display_slice(args);
-        self.backtrace.push(format!("{name} {display}"));
+        // `throw` checks that there is a catch for it, so the frames it
+        // unwinds are never shown, and a throw allocates nothing on the way
+        if !matches!(self.error, ErrorType::Throw(_)) {
+            let display = display_slice(args);
+            self.backtrace.push(format!("{name} {display}"));
+        }
+        self
+    }
+
+    /// Record where the error happened, such as the form that was being
+    /// loaded.
+    pub(crate) fn add_context(mut self, context: String) -> Self {
+        self.backtrace.push(context);
         self
     }
 }
@@ -167,6 +184,7 @@ pub(crate) enum Type {
     Vec,
     Record,
     HashTable,
+    CharTable,
     Sequence,
     String,
     Symbol,
@@ -175,6 +193,11 @@ pub(crate) enum Type {
     Number,
     List,
     Buffer,
+    #[cfg_attr(not(feature = "buffers"), allow(dead_code))]
+    BufferOrString,
+    Overlay,
+    Marker,
+    Process,
 }
 
 /// Error provided if object was the wrong type
@@ -212,3 +235,22 @@ impl TypeError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::core::object::nil;
+    use crate::root;
+
+    #[test]
+    fn test_throw_trace() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let err = EvalError::throw(nil(), nil(), env).add_trace("f", &[]);
+        assert_eq!(err.backtrace, Vec::<String>::new());
+        let err = EvalError::signal(nil(), nil(), env).add_trace("f", &[]);
+        assert_eq!(err.backtrace.len(), 1);
+    }
+}
@@ -3,6 +3,7 @@ mod trace;
 #[macro_use]
 mod context;
 mod alloc;
+pub(crate) mod reserve;
 pub(in crate::core) use alloc::*;
 pub(crate) use context::*;
 pub(crate) use root::*;
@@ -1,7 +1,9 @@
 use super::Block;
 use crate::core::cons::Cons;
 use crate::core::env::SymbolCell;
-use crate::core::object::{ByteFn, LispFloat, LispHashTable, LispString, LispVec};
+use crate::core::object::{
+    ByteFn, CharTable, LispFloat, LispHashTable, LispString, LispVec, Overlay,
+};
 use std::fmt::Debug;
 
 /// The owner of an object allocation. No references to
@@ -12,9 +14,29 @@ pub(super) enum OwnedObject {
     Cons(Box<Cons>),
     Vec(Box<LispVec>),
     HashTable(Box<LispHashTable>),
+    CharTable(Box<CharTable>),
     String(Box<LispString>),
     Symbol(Box<SymbolCell>),
     ByteFn(Box<ByteFn>),
+    Overlay(Box<Overlay>),
+}
+
+impl OwnedObject {
+    /// The approximate number of bytes used by this object.
+    pub(super) fn size(&self) -> usize {
+        use std::mem::size_of;
+        match self {
+            OwnedObject::Float(_) => size_of::<LispFloat>(),
+            OwnedObject::Cons(_) => size_of::<Cons>(),
+            OwnedObject::Vec(x) => size_of::<LispVec>() + x.len() * size_of::<usize>(),
+            OwnedObject::HashTable(_) => size_of::<LispHashTable>(),
+            OwnedObject::CharTable(_) => size_of::<CharTable>(),
+            OwnedObject::String(x) => size_of::<LispString>() + x.len(),
+            OwnedObject::Symbol(_) => size_of::<SymbolCell>(),
+            OwnedObject::ByteFn(_) => size_of::<ByteFn>(),
+            OwnedObject::Overlay(_) => size_of::<Overlay>(),
+        }
+    }
 }
 
 pub(in crate::core) trait AllocObject
@@ -29,7 +51,7 @@ impl AllocObject for f64 {
     type Output = LispFloat;
     fn alloc_obj<const C: bool>(self, block: &Block<C>) -> *const Self::Output {
         let mut objects = block.objects.borrow_mut();
-        Block::<C>::register(
+        block.register(
             &mut objects,
             OwnedObject::Float(Box::new(LispFloat::new(self))),
         );
@@ -45,7 +67,7 @@ impl AllocObject for Cons {
         if CONST {
             self.mark_const();
         }
-        Block::<CONST>::register(&mut objects, OwnedObject::Cons(Box::new(self)));
+        block.register(&mut objects, OwnedObject::Cons(Box::new(self)));
         let Some(OwnedObject::Cons(x)) = objects.last() else {unreachable!()};
         x.as_ref()
     }
@@ -55,7 +77,7 @@ impl AllocObject for SymbolCell {
     type Output = SymbolCell;
     fn alloc_obj<const CONST: bool>(self, block: &Block<CONST>) -> *const Self::Output {
         let mut objects = block.objects.borrow_mut();
-        Block::<CONST>::register(&mut objects, OwnedObject::Symbol(Box::new(self)));
+        block.register(&mut objects, OwnedObject::Symbol(Box::new(self)));
         let Some(OwnedObject::Symbol(x)) = objects.last() else {unreachable!()};
         x.as_ref()
     }
@@ -64,9 +86,12 @@ impl AllocObject for SymbolCell {
 impl AllocObject for LispString {
     type Output = Self;
 
-    fn alloc_obj<const C: bool>(self, block: &Block<C>) -> *const Self::Output {
+    fn alloc_obj<const CONST: bool>(mut self, block: &Block<CONST>) -> *const Self::Output {
         let mut objects = block.objects.borrow_mut();
-        Block::<C>::register(&mut objects, OwnedObject::String(Box::new(self)));
+        if CONST {
+            self.make_const();
+        }
+        block.register(&mut objects, OwnedObject::String(Box::new(self)));
         let Some(OwnedObject::String(x)) = objects.last_mut() else {unreachable!()};
         x.as_ref()
     }
@@ -77,12 +102,22 @@ impl AllocObject for ByteFn {
     fn alloc_obj<const C: bool>(self, block: &Block<C>) -> *const Self::Output {
         let mut objects = block.objects.borrow_mut();
         let boxed = Box::new(self);
-        Block::<C>::register(&mut objects, OwnedObject::ByteFn(boxed));
+        block.register(&mut objects, OwnedObject::ByteFn(boxed));
         let Some(OwnedObject::ByteFn(x)) = objects.last() else {unreachable!()};
         x.as_ref()
     }
 }
 
+impl AllocObject for Overlay {
+    type Output = Overlay;
+    fn alloc_obj<const C: bool>(self, block: &Block<C>) -> *const Self::Output {
+        let mut objects = block.objects.borrow_mut();
+        block.register(&mut objects, OwnedObject::Overlay(Box::new(self)));
+        let Some(OwnedObject::Overlay(x)) = objects.last() else {unreachable!()};
+        x.as_ref()
+    }
+}
+
 impl AllocObject for LispVec {
     type Output = LispVec;
 
@@ -91,7 +126,7 @@ impl AllocObject for LispVec {
         if CONST {
             self.make_const();
         }
-        Block::<CONST>::register(&mut objects, OwnedObject::Vec(Box::new(self)));
+        block.register(&mut objects, OwnedObject::Vec(Box::new(self)));
         let Some(OwnedObject::Vec(x)) = objects.last() else {unreachable!()};
         x.as_ref()
     }
@@ -105,8 +140,19 @@ impl AllocObject for LispHashTable {
         if CONST {
             self.make_const();
         }
-        Block::<CONST>::register(&mut objects, OwnedObject::HashTable(Box::new(self)));
+        block.register(&mut objects, OwnedObject::HashTable(Box::new(self)));
         let Some(OwnedObject::HashTable(x)) = objects.last() else {unreachable!()};
         x.as_ref()
     }
 }
+
+impl AllocObject for CharTable {
+    type Output = Self;
+
+    fn alloc_obj<const C: bool>(self, block: &Block<C>) -> *const Self::Output {
+        let mut objects = block.objects.borrow_mut();
+        block.register(&mut objects, OwnedObject::CharTable(Box::new(self)));
+        let Some(OwnedObject::CharTable(x)) = objects.last() else {unreachable!()};
+        x.as_ref()
+    }
+}
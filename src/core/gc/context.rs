@@ -5,7 +5,14 @@ use crate::core::object::{Gc, GcObj, IntoObject, WithLifetime};
 use std::cell::{Cell, RefCell};
 use std::fmt::Debug;
 use std::ops::Deref;
+use std::ptr::NonNull;
 use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+/// Collect on every allocation and at every safe point, so that objects that
+/// are not rooted are freed as soon as possible. Enabled with the `gc_stress`
+/// feature.
+pub(crate) const GC_STRESS: bool = cfg!(feature = "gc_stress");
 
 /// A global store of all gc roots. This struct should be passed to the [Context]
 /// when it is created.
@@ -20,6 +27,39 @@ pub(crate) struct RootSet {
 pub(crate) struct Block<const CONST: bool> {
     pub(super) objects: RefCell<Vec<OwnedObject>>,
     pub(in crate::core) uninterned_symbol_map: UninternedSymbolMap,
+    /// Number of conses allocated in this block
+    cons_count: Cell<usize>,
+    /// Approximate number of bytes allocated in this block
+    byte_count: Cell<usize>,
+    /// The roots of the [Context] that owns this block. Only set with the
+    /// `gc_stress` feature, so that allocations can collect.
+    stress_roots: StressRoots,
+    /// Number of objects that were live at the last collection. The objects
+    /// allocated since then may be held without a root until the next safe
+    /// point, so a collection on allocation keeps them.
+    collected_len: Cell<usize>,
+}
+
+#[derive(Default)]
+struct StressRoots(Cell<Option<NonNull<RootSet>>>);
+
+// SAFETY: A block is only sent to another thread before a context owns it,
+// when this is unset.
+unsafe impl Send for StressRoots {}
+
+/// The headroom above each limit of a [Context] once it has been exceeded, as
+/// a fraction of the limit.
+const LIMIT_RESERVE: usize = 8;
+
+/// Caps on the live objects of a [Context]. They are checked at safe points,
+/// after a collection, so they bound what is reachable rather than what has
+/// been allocated.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Limits {
+    /// The maximum number of conses
+    pub(crate) max_conses: Option<usize>,
+    /// The approximate maximum number of bytes used by all objects
+    pub(crate) max_bytes: Option<usize>,
 }
 
 /// Owns all allocations and creates objects. All objects have
@@ -29,13 +69,21 @@ pub(crate) struct Context<'rt> {
     pub(crate) block: Block<false>,
     root_set: &'rt RootSet,
     prev_obj_count: usize,
+    /// Number of garbage collections run by this context
+    gc_count: usize,
+    /// Total time spent in garbage collection
+    gc_time: Duration,
+    limits: Limits,
+    /// Whether a limit was exceeded and the headroom above it is in use
+    limit_reserve_used: bool,
 }
 
 impl<'rt> Drop for Context<'rt> {
     fn drop(&mut self) {
         self.garbage_collect(true);
+        let objects = self.block.objects.borrow();
         assert!(
-            std::thread::panicking() || self.block.objects.borrow().is_empty(),
+            std::thread::panicking() || objects.iter().all(OwnedObject::is_pinned),
             "Error: Context was dropped while still holding data"
         );
     }
@@ -129,26 +177,94 @@ impl<const CONST: bool> Block<CONST> {
         obj.into_obj(self).into()
     }
 
-    pub(super) fn register(objects: &mut Vec<OwnedObject>, obj: OwnedObject) {
+    pub(super) fn register(&self, objects: &mut Vec<OwnedObject>, obj: OwnedObject) {
+        if matches!(obj, OwnedObject::Cons(_)) {
+            self.cons_count.set(self.cons_count.get() + 1);
+        }
+        self.byte_count.set(self.byte_count.get() + obj.size());
         objects.push(obj);
+        if let Some(roots) = self.stress_roots.0.get() {
+            // SAFETY: The root set outlives the context that owns this block
+            let roots = unsafe { roots.as_ref() };
+            self.collect(objects, roots, self.collected_len.get());
+        }
+    }
+
+    /// Free the objects that are not reachable from `roots`. The objects from
+    /// index `young` on are kept, along with everything they reference.
+    fn collect(&self, objects: &mut Vec<OwnedObject>, roots: &RootSet, young: usize) {
+        let gray_stack = &mut Vec::new();
+        for x in roots.roots.borrow().iter() {
+            // SAFETY: The contact of root structs will ensure that it removes
+            // itself from this list before it drops.
+            unsafe {
+                (**x).trace(gray_stack);
+            }
+        }
+        for x in &objects[young..] {
+            x.trace_mark(gray_stack);
+        }
+        while let Some(raw) = gray_stack.pop() {
+            let obj = unsafe { GcObj::from_raw(raw) };
+            if !obj.is_marked() {
+                obj.trace_mark(gray_stack);
+            }
+        }
+
+        let young_count = objects.len() - young;
+        objects.retain_mut(|x| {
+            let marked = x.is_marked();
+            if marked {
+                x.unmark();
+            }
+            marked || x.is_pinned()
+        });
+        let conses = objects.iter().filter(|x| matches!(x, OwnedObject::Cons(_)));
+        self.cons_count.set(conses.count());
+        self.byte_count
+            .set(objects.iter().map(OwnedObject::size).sum());
+        self.collected_len.set(objects.len() - young_count);
     }
 }
 
 impl<'ob, 'rt> Context<'rt> {
     pub(crate) fn new(roots: &'rt RootSet) -> Self {
+        let block = Block::new_local();
+        if GC_STRESS {
+            block.stress_roots.0.set(Some(NonNull::from(roots)));
+        }
         Context {
-            block: Block::new_local(),
+            block,
             root_set: roots,
             prev_obj_count: 0,
+            gc_count: 0,
+            gc_time: Duration::ZERO,
+            limits: Limits::default(),
+            limit_reserve_used: false,
         }
     }
 
+    /// Create a context whose live objects are capped by `limits`. See
+    /// [`Context::exceeded_limit`].
+    pub(crate) fn with_limits(roots: &'rt RootSet, limits: Limits) -> Self {
+        let mut cx = Self::new(roots);
+        cx.limits = limits;
+        cx
+    }
+
     pub(crate) fn from_block(block: Block<false>, roots: &'rt RootSet) -> Self {
         Block::assert_unique();
+        if GC_STRESS {
+            block.stress_roots.0.set(Some(NonNull::from(roots)));
+        }
         Context {
             block,
             root_set: roots,
             prev_obj_count: 0,
+            gc_count: 0,
+            gc_time: Duration::ZERO,
+            limits: Limits::default(),
+            limit_reserve_used: false,
         }
     }
 
@@ -163,53 +279,111 @@ impl<'ob, 'rt> Context<'rt> {
         self.root_set
     }
 
+    pub(crate) fn gc_count(&self) -> usize {
+        self.gc_count
+    }
+
+    pub(crate) fn gc_time(&self) -> Duration {
+        self.gc_time
+    }
+
+    /// The limit that the live objects are over, if any. With `reserve`,
+    /// each limit is raised by its share of headroom.
+    fn over_limit(&self, reserve: bool) -> Option<&'static str> {
+        let Limits {
+            max_conses,
+            max_bytes,
+        } = self.limits;
+        let raise = |max: usize| {
+            if reserve {
+                max + max / LIMIT_RESERVE
+            } else {
+                max
+            }
+        };
+        if max_conses.is_some_and(|max| self.block.cons_count.get() > raise(max)) {
+            Some("Maximum number of conses exceeded")
+        } else if max_bytes.is_some_and(|max| self.block.byte_count.get() > raise(max)) {
+            Some("Maximum memory size exceeded")
+        } else {
+            None
+        }
+    }
+
+    /// Check the live objects against the limits of this context, collecting
+    /// first if they are over. Returns a description of the limit that is
+    /// still exceeded after the collection. Once a limit is exceeded, the
+    /// headroom above it is released so that error handlers can run, until
+    /// the live objects are back under the limits.
+    pub(crate) fn exceeded_limit(&mut self) -> Option<&'static str> {
+        let reserve = self.limit_reserve_used;
+        if reserve && self.over_limit(false).is_none() {
+            self.limit_reserve_used = false;
+            return None;
+        }
+        self.over_limit(reserve)?;
+        self.garbage_collect(true);
+        if self.over_limit(false).is_none() {
+            self.limit_reserve_used = false;
+            return None;
+        }
+        self.limit_reserve_used = true;
+        self.over_limit(reserve)
+    }
+
+    /// Whether the headroom above the limits of this context is in use.
+    pub(crate) fn memory_full(&self) -> bool {
+        self.limit_reserve_used
+    }
+
     pub(crate) fn garbage_collect(&mut self, force: bool) {
         let mut objects = self.block.objects.borrow_mut();
         if cfg!(not(test))
+            && !GC_STRESS
             && !force
             && (objects.len() < 2000 || objects.len() < (self.prev_obj_count * 2))
         {
             return;
         }
-        let gray_stack = &mut Vec::new();
-        for x in self.root_set.roots.borrow().iter() {
-            // SAFETY: The contact of root structs will ensure that it removes
-            // itself from this list before it drops.
-            unsafe {
-                (**x).trace(gray_stack);
-            }
-        }
-        while let Some(raw) = gray_stack.pop() {
-            let obj = unsafe { GcObj::from_raw(raw) };
-            if !obj.is_marked() {
-                obj.trace_mark(gray_stack);
-            }
-        }
-
-        // let prev = objects.len();
-        objects.retain_mut(|x| {
-            let marked = x.is_marked();
-            if marked {
-                x.unmark();
-            }
-            marked
-        });
-        // let retained = prev - objects.len();
-        // println!("garbage collected: {retained}/{prev}");
+        let start = Instant::now();
+        // At a safe point nothing can be held without a root
+        let len = objects.len();
+        self.block.collect(&mut objects, self.root_set, len);
         self.prev_obj_count = objects.len();
+        self.gc_count += 1;
+        self.gc_time += start.elapsed();
     }
 }
 
 impl OwnedObject {
+    fn trace_mark(&self, stack: &mut Vec<crate::core::object::RawObj>) {
+        match self {
+            OwnedObject::Float(x) => x.mark(),
+            OwnedObject::Cons(x) => x.trace(stack),
+            OwnedObject::Vec(x) => x.trace(stack),
+            OwnedObject::HashTable(x) => x.trace(stack),
+            OwnedObject::CharTable(x) => x.trace(stack),
+            OwnedObject::String(x) => x.trace(stack),
+            OwnedObject::Symbol(x) => {
+                x.trace(stack);
+                x.mark();
+            }
+            OwnedObject::ByteFn(x) => x.trace(stack),
+            OwnedObject::Overlay(x) => x.trace(stack),
+        }
+    }
+
     fn unmark(&self) {
         match self {
             OwnedObject::Float(x) => x.unmark(),
             OwnedObject::Cons(x) => x.unmark(),
             OwnedObject::Vec(x) => x.unmark(),
             OwnedObject::HashTable(x) => x.unmark(),
+            OwnedObject::CharTable(x) => x.unmark(),
             OwnedObject::String(x) => x.unmark(),
             OwnedObject::Symbol(x) => x.unmark(),
             OwnedObject::ByteFn(x) => x.unmark(),
+            OwnedObject::Overlay(x) => x.unmark(),
         }
     }
 
@@ -219,9 +393,20 @@ impl OwnedObject {
             OwnedObject::Cons(x) => x.is_marked(),
             OwnedObject::Vec(x) => x.is_marked(),
             OwnedObject::HashTable(x) => x.is_marked(),
+            OwnedObject::CharTable(x) => x.is_marked(),
             OwnedObject::String(x) => x.is_marked(),
             OwnedObject::Symbol(x) => x.is_marked(),
             OwnedObject::ByteFn(x) => x.is_marked(),
+            OwnedObject::Overlay(x) => x.is_marked(),
+        }
+    }
+
+    /// Whether the object is kept even if it is not reachable. See
+    /// [`Overlay`](crate::core::object::Overlay).
+    fn is_pinned(&self) -> bool {
+        match self {
+            OwnedObject::Overlay(x) => x.is_pinned(),
+            _ => false,
         }
     }
 }
@@ -244,6 +429,13 @@ impl<const CONST: bool> Drop for Block<CONST> {
     // Only one block can exist in a thread at a time. This part of that
     // contract.
     fn drop(&mut self) {
+        // overlays in a buffer or in another block outlive the block
+        for obj in self.objects.get_mut().drain(..) {
+            match obj {
+                OwnedObject::Overlay(x) if x.is_pinned() => _ = Box::leak(x),
+                _ => {}
+            }
+        }
         SINGLETON_CHECK.with(|s| {
             assert!(s.get(), "Context singleton check was overwritten");
             s.set(false);
@@ -322,4 +514,59 @@ mod test {
         vec.push(cons);
         cx.garbage_collect(true);
     }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn collect_overlays() {
+        use crate::core::object::{Buffer, Overlay};
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let count = |cx: &Context| cx.block.objects.borrow().len();
+        let buffer = Buffer::create("collect-overlays");
+        let in_buffer = Overlay::create(false, false, cx);
+        buffer.lock().unwrap().add_overlay(in_buffer, 0, 0);
+        in_buffer.set_buffer(Some(buffer));
+        let _rooted: GcObj = Overlay::create(false, false, cx).into();
+        root!(_rooted, cx);
+        Overlay::create(false, false, cx);
+        cx.garbage_collect(true);
+        // the buffer holds its overlays like a root
+        assert_eq!(count(cx), 2);
+        buffer.lock().unwrap().remove_overlay(in_buffer);
+        in_buffer.set_buffer(None);
+        cx.garbage_collect(true);
+        assert_eq!(count(cx), 1);
+        // the context can be dropped with overlays that are still in a buffer
+        let kept = Overlay::create(false, false, cx);
+        buffer.lock().unwrap().add_overlay(kept, 0, 0);
+        kept.set_buffer(Some(buffer));
+    }
+
+    #[test]
+    fn collect_on_allocation() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        // what the gc_stress feature does for every context
+        cx.block.stress_roots.0.set(Some(NonNull::from(roots)));
+        let count = |cx: &Context| cx.block.objects.borrow().len();
+        let kept = cx.add("kept");
+        root!(kept, cx);
+        {
+            let _dropped = cx.add("dropped");
+            root!(_dropped, cx);
+            cx.garbage_collect(true);
+            assert_eq!(count(cx), 2);
+        }
+        // objects allocated since the last collection can be unrooted, but
+        // the others are freed as soon as they are not
+        let first = cx.add("first");
+        assert_eq!(count(cx), 2);
+        let second = cx.add("second");
+        assert_eq!(count(cx), 3);
+        assert_eq!(first, "first");
+        assert_eq!(second, "second");
+        cx.garbage_collect(true);
+        assert_eq!(count(cx), 1);
+        assert_eq!(kept.bind(cx), "kept");
+    }
 }
@@ -0,0 +1,96 @@
+//! An emergency memory reserve. A block of memory is held back from the
+//! system allocator so that when an allocation fails, the reserve can be
+//! released and the allocation retried instead of aborting. The failure is
+//! recorded and reported as a `memory-full` signal at the next safe point,
+//! which gives error handlers room to run.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// Size of the emergency reserve in bytes
+const RESERVE_SIZE: usize = 1 << 16;
+
+/// The reserve block, or null if it was released
+static RESERVE: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Set when an allocation only succeeded after releasing the reserve
+static EXHAUSTED: AtomicBool = AtomicBool::new(false);
+
+fn reserve_layout() -> Layout {
+    Layout::from_size_align(RESERVE_SIZE, 1).unwrap()
+}
+
+/// Allocate the reserve if it has been released. Returns false if there is
+/// still not enough memory for it.
+pub(crate) fn refill_reserve() -> bool {
+    if !RESERVE.load(Ordering::Acquire).is_null() {
+        return true;
+    }
+    let block = unsafe { System.alloc(reserve_layout()) };
+    if block.is_null() {
+        return false;
+    }
+    if RESERVE
+        .compare_exchange(
+            std::ptr::null_mut(),
+            block,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        // another thread refilled it first
+        unsafe { System.dealloc(block, reserve_layout()) };
+    }
+    true
+}
+
+/// Release the reserve back to the system. Returns false if it was already
+/// released.
+fn release_reserve() -> bool {
+    let block = RESERVE.swap(std::ptr::null_mut(), Ordering::AcqRel);
+    if block.is_null() {
+        return false;
+    }
+    unsafe { System.dealloc(block, reserve_layout()) };
+    EXHAUSTED.store(true, Ordering::Release);
+    true
+}
+
+/// Whether the reserve was released since the last call.
+pub(crate) fn take_exhausted() -> bool {
+    EXHAUSTED.swap(false, Ordering::AcqRel)
+}
+
+/// The global allocator. It forwards to the system allocator and falls back
+/// on the reserve when that fails.
+pub(crate) struct ReserveAllocator;
+
+unsafe impl GlobalAlloc for ReserveAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if ptr.is_null() && release_reserve() {
+            return System.alloc(layout);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if ptr.is_null() && release_reserve() {
+            return System.alloc_zeroed(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if new.is_null() && release_reserve() {
+            return System.realloc(ptr, layout, new_size);
+        }
+        new
+    }
+}
@@ -4,7 +4,9 @@ use super::super::{
 };
 use super::{Block, Context, RootSet, Trace};
 use crate::core::env::Symbol;
-use crate::core::object::{ByteFn, Gc, IntoObject, LispString, Object, Untag, WithLifetime};
+use crate::core::object::{
+    ByteFn, Gc, IntoObject, LispString, LispVec, Object, Untag, WithLifetime,
+};
 use crate::hashmap::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut, Index, IndexMut};
@@ -70,6 +72,12 @@ impl IntoRoot<&'static ByteFn> for &ByteFn {
     }
 }
 
+impl IntoRoot<&'static LispVec> for &LispVec {
+    unsafe fn into_root(self) -> &'static LispVec {
+        self.with_lifetime()
+    }
+}
+
 impl IntoRoot<Symbol<'static>> for Symbol<'_> {
     unsafe fn into_root(self) -> Symbol<'static> {
         self.with_lifetime()
@@ -326,6 +334,14 @@ impl<T> Rt<T> {
         unsafe { &*(slice as *const [Rt<T>] as *const [U]) }
     }
 
+    /// Replace the value with its default, releasing everything it rooted.
+    pub(crate) fn reset(&mut self)
+    where
+        T: Default,
+    {
+        self.inner = T::default();
+    }
+
     /// This functions is very unsafe to call directly. The caller must ensure
     /// that resulting Rt is only exposed through references and that it is
     /// properly rooted.
@@ -345,6 +361,14 @@ impl TryFrom<&Rt<GcObj<'_>>> for usize {
     }
 }
 
+impl TryFrom<&Rt<GcObj<'_>>> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Rt<GcObj>) -> Result<Self, Self::Error> {
+        Ok(value.inner.try_into()?)
+    }
+}
+
 impl<T> Rt<Gc<T>> {
     /// Like `try_into`, but needed to due no specialization
     pub(crate) fn try_into<U, E>(&self) -> Result<&Rt<Gc<U>>, E>
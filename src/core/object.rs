@@ -10,20 +10,28 @@
 //! of the vm.
 
 mod buffer;
+mod chartable;
 mod convert;
 mod float;
 mod func;
 mod hashtable;
+mod marker;
+mod overlay;
+mod process;
 mod string;
 mod tagged;
 mod vector;
 
 #[allow(unused_imports)]
 pub(crate) use buffer::*;
+pub(crate) use chartable::*;
 pub(crate) use convert::*;
 pub(crate) use float::*;
 pub(crate) use func::*;
 pub(crate) use hashtable::*;
+pub(crate) use marker::*;
+pub(crate) use overlay::*;
+pub(crate) use process::*;
 pub(crate) use string::*;
 pub(crate) use tagged::*;
 pub(crate) use vector::*;
@@ -1,22 +1,748 @@
-use super::{Gc, RawObj, TagType, WithLifetime};
+// The buffer type is part of the object model even when the buffer builtins
+// are not built
+#![cfg_attr(not(feature = "buffers"), allow(dead_code))]
+use super::{Gc, Overlay, RawObj, TagType, WithLifetime};
+use crate::core::env::Symbol;
 use crate::core::gc::{GcManaged, GcMark, Trace};
-use std::{fmt::Display, sync::Mutex};
+use anyhow::{ensure, Result};
+use std::fmt::Display;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
 use text_buffer::Buffer as TextBuffer;
 
+mod marker;
+pub(crate) use marker::MarkerId;
+use marker::Markers;
+
+/// Buffers with names that start with this are temporary, like the ones made
+/// by `with-temp-buffer`. Their text storage is reused.
+const TEMP_BUFFER_PREFIX: &str = " *temp*";
+
+/// The most text stores to keep for reuse
+const TEXT_POOL_SIZE: usize = 8;
+
+/// Text stores larger than this are freed instead of reused
+const MAX_POOLED_CAPACITY: usize = 1 << 20;
+
+/// Empty text stores of killed temporary buffers
+struct TextPool(Mutex<Vec<TextBuffer>>);
+
+impl TextPool {
+    const fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// A text store for a new buffer named `name`.
+    fn take(&self, name: &str) -> TextBuffer {
+        if name.starts_with(TEMP_BUFFER_PREFIX) {
+            if let Some(text) = self.0.lock().unwrap().pop() {
+                return text;
+            }
+        }
+        TextBuffer::new()
+    }
+
+    /// Put the text store of the killed buffer `name` in the pool if it is a
+    /// temporary buffer.
+    fn recycle(&self, name: &str, text: &mut TextBuffer) {
+        if !name.starts_with(TEMP_BUFFER_PREFIX) || text.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        let mut pool = self.0.lock().unwrap();
+        if pool.len() < TEXT_POOL_SIZE {
+            let mut text = std::mem::take(text);
+            text.clear();
+            pool.push(text);
+        }
+    }
+}
+
+static TEXT_POOL: TextPool = TextPool::new();
+
+/// The accessible portion of a narrowed buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Restriction {
+    pub(crate) begv: usize,
+    pub(crate) zv: usize,
+}
+
+impl Restriction {
+    /// Text inserted at the end of the restriction is inside it.
+    fn adjust_for_insert(&mut self, pos: usize, len: usize) {
+        if pos < self.begv {
+            self.begv += len;
+        }
+        if pos <= self.zv {
+            self.zv += len;
+        }
+    }
+
+    fn adjust_for_delete(&mut self, beg: usize, end: usize) {
+        self.begv = position_after_delete(self.begv, beg, end);
+        self.zv = position_after_delete(self.zv, beg, end);
+    }
+}
+
+/// Where `pos` is after the text between `beg` and `end` is deleted.
+fn position_after_delete(pos: usize, beg: usize, end: usize) -> usize {
+    if pos >= end {
+        pos - (end - beg)
+    } else {
+        pos.min(beg)
+    }
+}
+
+/// The restrictions of a buffer saved by `save-restriction`.
+#[derive(Debug, Clone)]
+struct SavedRestriction {
+    restriction: Option<Restriction>,
+    labeled: Vec<(Symbol<'static>, Restriction)>,
+}
+
+/// An overlay in a buffer and the markers of its bounds.
+#[derive(Debug)]
+struct OverlayEntry {
+    overlay: &'static Overlay,
+    start: MarkerId,
+    end: MarkerId,
+}
+
+/// The modification time that a buffer recorded for its visited file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Modtime {
+    /// The buffer is not visiting a file, or its time is not known
+    Unknown,
+    /// The file did not exist
+    Missing,
+    Time(SystemTime),
+}
+
+/// The state of a buffer that is not shared with the other buffers that
+/// share its text.
 #[derive(Debug)]
-#[allow(dead_code)]
-struct BufferData {
+struct BufferState {
     name: String,
-    file_name: String,
-    text: TextBuffer,
+    /// The modification time of the visited file when it was last read or
+    /// written
+    modtime: Modtime,
+    point: usize,
+    /// The mark, if it has been set
+    mark: Option<MarkerId>,
+    /// Previous marks, most recent first
+    mark_ring: Vec<MarkerId>,
+    /// The accessible portion of the buffer, or `None` if it is not narrowed
+    restriction: Option<Restriction>,
+    /// Labeled restrictions, innermost last. The buffer can't be widened past
+    /// these without the label.
+    labeled_restrictions: Vec<(Symbol<'static>, Restriction)>,
+    /// Restrictions saved by `save-restriction`, innermost last
+    saved_restrictions: Vec<SavedRestriction>,
+    overlays: Vec<OverlayEntry>,
+}
+
+impl BufferState {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            modtime: Modtime::Unknown,
+            point: 0,
+            mark: None,
+            mark_ring: Vec::new(),
+            restriction: None,
+            labeled_restrictions: Vec::new(),
+            saved_restrictions: Vec::new(),
+            overlays: Vec::new(),
+        }
+    }
+
+    fn restrictions_mut(&mut self) -> impl Iterator<Item = &mut Restriction> {
+        let saved = self.saved_restrictions.iter_mut().flat_map(|x| {
+            let labeled = x.labeled.iter_mut().map(|x| &mut x.1);
+            x.restriction.iter_mut().chain(labeled)
+        });
+        let labeled = self.labeled_restrictions.iter_mut().map(|x| &mut x.1);
+        self.restriction.iter_mut().chain(labeled).chain(saved)
+    }
+}
+
+/// The text of a base buffer and the state of it and each of its indirect
+/// buffers. The methods act on the buffer it was locked through. Positions
+/// are 0-based character offsets.
+#[derive(Debug)]
+pub(crate) struct BufferData {
+    pub(crate) text: TextBuffer,
+    /// The markers of all the buffers that share the text
+    markers: Markers,
+    /// The state of each buffer that shares the text, or `None` if it was
+    /// killed
+    buffers: Vec<Option<BufferState>>,
+    /// Index of the buffer this was locked through
+    current: usize,
+    /// Incremented by every change to the text or its properties
+    modified_tick: usize,
+    /// The value of `modified_tick` at the last change to the text
+    chars_modified_tick: usize,
+    /// The value of `modified_tick` when the buffer was last unmodified
+    save_tick: usize,
+    /// The value of `modified_tick` when the buffer was last auto-saved
+    auto_save_tick: usize,
+    /// Changes are not counted while this is set, so the ticks and the
+    /// modified flag are left alone
+    silent: bool,
+}
+
+impl BufferData {
+    fn state(&self) -> &BufferState {
+        self.buffers[self.current].as_ref().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut BufferState {
+        self.buffers[self.current].as_mut().unwrap()
+    }
+
+    fn states_mut(&mut self) -> impl Iterator<Item = &mut BufferState> {
+        self.buffers.iter_mut().flatten()
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.state().name
+    }
+
+    pub(crate) fn set_name(&mut self, name: &str) {
+        name.clone_into(&mut self.state_mut().name);
+    }
+
+    /// Kill this buffer and free its markers. Returns its overlays, which are
+    /// no longer in any buffer. The text is kept for the other buffers that
+    /// share it, and otherwise the text of a temporary buffer is reused.
+    pub(crate) fn kill(&mut self) -> Vec<&'static Overlay> {
+        let Some(state) = self.buffers[self.current].take() else {return Vec::new()};
+        if self.buffers.iter().all(Option::is_none) {
+            TEXT_POOL.recycle(&state.name, &mut self.text);
+        }
+        let overlay_markers = state.overlays.iter().flat_map(|x| [x.start, x.end]);
+        let markers: Vec<_> = state
+            .mark
+            .into_iter()
+            .chain(state.mark_ring.iter().copied())
+            .chain(overlay_markers)
+            .collect();
+        for marker in markers {
+            self.free_marker(marker);
+        }
+        state.overlays.iter().map(|x| x.overlay).collect()
+    }
+
+    pub(crate) fn point(&self) -> usize {
+        self.state().point
+    }
+
+    /// Start of the accessible portion of the buffer.
+    pub(crate) fn begv(&self) -> usize {
+        self.state().restriction.map_or(0, |x| x.begv)
+    }
+
+    /// End of the accessible portion of the buffer.
+    pub(crate) fn zv(&self) -> usize {
+        self.state()
+            .restriction
+            .map_or_else(|| self.text.len_chars(), |x| x.zv)
+    }
+
+    /// Move point to `pos`, clamped to the accessible portion of the buffer.
+    pub(crate) fn goto_char(&mut self, pos: usize) {
+        self.state_mut().point = pos.clamp(self.begv(), self.zv());
+    }
+
+    /// Insert `text` at point. Markers after point move with the text, as do
+    /// markers at point that advance on insertion. The point of other buffers
+    /// that share the text stays before it.
+    #[allow(dead_code)]
+    pub(crate) fn insert(&mut self, text: &str) {
+        self.insert_at_point(text, false);
+    }
+
+    /// Insert `text` at point like [`insert`](Self::insert), but before all
+    /// the markers at point, so they all move after it. So does the point of
+    /// the other buffers that share the text.
+    pub(crate) fn insert_before_markers(&mut self, text: &str) {
+        self.insert_at_point(text, true);
+    }
+
+    fn insert_at_point(&mut self, text: &str, before_markers: bool) {
+        let point = self.point();
+        let len = text.chars().count();
+        self.chars_modified();
+        self.text.insert_at(point, text);
+        if before_markers {
+            self.markers.insert_before(point, len);
+        } else {
+            self.markers.insert(point, len);
+        }
+        for state in self.states_mut() {
+            if state.point > point || (before_markers && state.point == point) {
+                state.point += len;
+            }
+            for restriction in state.restrictions_mut() {
+                restriction.adjust_for_insert(point, len);
+            }
+        }
+        self.state_mut().point = point + len;
+        // An empty overlay only grows if its end advances
+        self.fix_overlays();
+    }
+
+    /// Make the overlays whose start moved past their end empty at their end.
+    fn fix_overlays(&mut self) {
+        for entry in self.buffers.iter().flatten().flat_map(|x| &x.overlays) {
+            let end = self.markers.position(entry.end);
+            if self.markers.position(entry.start) > end {
+                self.markers.set_position(entry.start, end);
+            }
+        }
+    }
+
+    /// Delete the text between `beg` and `end`. Markers in the deleted region
+    /// move to `beg`.
+    #[allow(dead_code)]
+    pub(crate) fn delete_region(&mut self, beg: usize, end: usize) {
+        let len = self.text.len_chars();
+        let (beg, end) = (beg.min(end).min(len), beg.max(end).min(len));
+        self.chars_modified();
+        self.text.delete_region(beg, end);
+        self.markers.delete(beg, end);
+        for state in self.states_mut() {
+            state.point = position_after_delete(state.point, beg, end);
+            for restriction in state.restrictions_mut() {
+                restriction.adjust_for_delete(beg, end);
+            }
+        }
+    }
+
+    /// Swap the text between `beg1` and `end1` with the text between `beg2`
+    /// and `end2`, where the first region is before the second. Unless
+    /// `leave_markers` is true, markers move with the text they are in. Point
+    /// keeps its position.
+    pub(crate) fn transpose_regions(
+        &mut self,
+        (beg1, end1): (usize, usize),
+        (beg2, end2): (usize, usize),
+        leave_markers: bool,
+    ) {
+        let text = [(beg2, end2), (end1, beg2), (beg1, end1)]
+            .map(|(beg, end)| self.text.substring(beg, end))
+            .concat();
+        self.chars_modified();
+        self.text.delete_region(beg1, end2);
+        self.text.insert_at(beg1, &text);
+        if !leave_markers {
+            self.markers.transpose(beg1, end1, beg2, end2);
+            self.fix_overlays();
+        }
+    }
+
+    /// Replace the characters from `beg` with `text`, which is as long as
+    /// the text it replaces. Markers and point keep their positions.
+    pub(crate) fn replace_chars(&mut self, beg: usize, text: &str) {
+        let end = beg + text.chars().count();
+        self.chars_modified();
+        self.text.delete_region(beg, end);
+        self.text.insert_at(beg, text);
+    }
+
+    /// Count a change to the text properties of the buffer.
+    pub(crate) fn modified(&mut self) {
+        if !self.silent {
+            self.modified_tick += 1;
+        }
+    }
+
+    /// Count a change to the text of the buffer.
+    fn chars_modified(&mut self) {
+        if !self.silent {
+            self.modified_tick += 1;
+            self.chars_modified_tick = self.modified_tick;
+        }
+    }
+
+    /// Stop counting changes to the buffer, or start again if `silent` is
+    /// false. Returns the previous setting.
+    pub(crate) fn set_silent_modifications(&mut self, silent: bool) -> bool {
+        std::mem::replace(&mut self.silent, silent)
+    }
+
+    pub(crate) fn modified_tick(&self) -> usize {
+        self.modified_tick
+    }
+
+    pub(crate) fn chars_modified_tick(&self) -> usize {
+        self.chars_modified_tick
+    }
+
+    /// Whether the buffer has changed since it was last marked unmodified.
+    pub(crate) fn is_modified(&self) -> bool {
+        self.save_tick < self.modified_tick
+    }
+
+    /// Mark the buffer as modified or not. Marking an unmodified buffer as
+    /// modified counts as a change.
+    pub(crate) fn set_modified(&mut self, flag: bool) {
+        if !flag {
+            self.save_tick = self.modified_tick;
+        } else if !self.is_modified() {
+            self.save_tick = self.modified_tick;
+            self.modified();
+        }
+    }
+
+    /// Whether the buffer is modified and has changed since it was last
+    /// auto-saved.
+    pub(crate) fn needs_auto_save(&self) -> bool {
+        self.is_modified() && self.auto_save_tick < self.modified_tick
+    }
+
+    /// Record that the buffer was auto-saved as it is now.
+    pub(crate) fn set_auto_saved(&mut self) {
+        self.auto_save_tick = self.modified_tick;
+    }
+
+    /// Whether the buffer was auto-saved since it was last unmodified.
+    pub(crate) fn recent_auto_save(&self) -> bool {
+        self.save_tick < self.auto_save_tick
+    }
+
+    pub(crate) fn modtime(&self) -> Modtime {
+        self.state().modtime
+    }
+
+    pub(crate) fn set_modtime(&mut self, modtime: Modtime) {
+        self.state_mut().modtime = modtime;
+    }
+
+    /// Restrict the accessible portion of the buffer to `beg` and `end`. If a
+    /// labeled restriction is in effect, the new bounds are clamped to it.
+    pub(crate) fn narrow(&mut self, beg: usize, end: usize) {
+        let len = self.text.len_chars();
+        let (mut beg, mut end) = (beg.min(end).min(len), beg.max(end).min(len));
+        if let Some((_, outer)) = self.state().labeled_restrictions.last() {
+            beg = beg.clamp(outer.begv, outer.zv);
+            end = end.clamp(outer.begv, outer.zv);
+        }
+        self.state_mut().restriction = Some(Restriction { begv: beg, zv: end });
+        self.goto_char(self.point());
+    }
+
+    /// Remove the restriction, up to the innermost labeled restriction.
+    pub(crate) fn widen(&mut self) {
+        let state = self.state_mut();
+        state.restriction = state.labeled_restrictions.last().map(|x| x.1);
+    }
+
+    pub(crate) fn is_narrowed(&self) -> bool {
+        self.begv() != 0 || self.zv() != self.text.len_chars()
+    }
+
+    /// Narrow to `beg` and `end`, and prevent widening past it until
+    /// [`Self::widen_labeled`] is called with the same label.
+    pub(crate) fn narrow_labeled(&mut self, beg: usize, end: usize, label: Symbol<'static>) {
+        self.narrow(beg, end);
+        let restriction = Restriction {
+            begv: self.begv(),
+            zv: self.zv(),
+        };
+        self.state_mut()
+            .labeled_restrictions
+            .push((label, restriction));
+    }
+
+    /// Remove the innermost labeled restriction if it has `label`, then widen.
+    pub(crate) fn widen_labeled(&mut self, label: Symbol) {
+        let labeled = &mut self.state_mut().labeled_restrictions;
+        if labeled.last().is_some_and(|x| x.0 == label) {
+            labeled.pop();
+        }
+        self.widen();
+    }
+
+    /// Remove all labeled restrictions, so that the buffer can be widened.
+    pub(crate) fn remove_labeled_restrictions(&mut self) {
+        self.state_mut().labeled_restrictions.clear();
+    }
+
+    pub(crate) fn save_restriction(&mut self) {
+        let state = self.state_mut();
+        state.saved_restrictions.push(SavedRestriction {
+            restriction: state.restriction,
+            labeled: state.labeled_restrictions.clone(),
+        });
+    }
+
+    /// Restore the restriction saved by the matching call to
+    /// [`Self::save_restriction`]. Point is moved inside of it.
+    pub(crate) fn restore_restriction(&mut self) {
+        let state = self.state_mut();
+        let saved = state
+            .saved_restrictions
+            .pop()
+            .expect("no saved restriction");
+        state.restriction = saved.restriction;
+        state.labeled_restrictions = saved.labeled;
+        self.goto_char(self.point());
+    }
+
+    /// Make a marker at `pos` that stays before text inserted at its
+    /// position.
+    pub(crate) fn make_marker(&mut self, pos: usize) -> MarkerId {
+        self.markers.add(pos.min(self.text.len_chars()), false)
+    }
+
+    pub(crate) fn marker_position(&self, marker: MarkerId) -> usize {
+        self.markers.position(marker)
+    }
+
+    pub(crate) fn set_marker(&mut self, marker: MarkerId, pos: usize) {
+        let pos = pos.min(self.text.len_chars());
+        self.markers.set_position(marker, pos);
+    }
+
+    /// Return true if the marker advances when text is inserted at its
+    /// position.
+    #[allow(dead_code)]
+    pub(crate) fn marker_insertion_type(&self, marker: MarkerId) -> bool {
+        self.markers.insertion_type(marker)
+    }
+
+    pub(crate) fn set_marker_insertion_type(&mut self, marker: MarkerId, advance: bool) {
+        self.markers.set_insertion_type(marker, advance);
+    }
+
+    pub(crate) fn free_marker(&mut self, marker: MarkerId) {
+        self.markers.remove(marker);
+    }
+
+    pub(crate) fn mark(&self) -> Option<usize> {
+        self.state().mark.map(|x| self.marker_position(x))
+    }
+
+    /// Set the mark to `pos`, or unset it if `pos` is `None`.
+    pub(crate) fn set_mark(&mut self, pos: Option<usize>) {
+        match (self.state().mark, pos) {
+            (Some(mark), Some(pos)) => self.set_marker(mark, pos),
+            (None, Some(pos)) => self.state_mut().mark = Some(self.make_marker(pos)),
+            (Some(mark), None) => {
+                self.free_marker(mark);
+                self.state_mut().mark = None;
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Save the current mark on the mark ring, which holds at most `max`
+    /// entries, and set the mark to `pos`.
+    pub(crate) fn push_mark(&mut self, pos: usize, max: usize) {
+        if let Some(mark) = self.state().mark {
+            let saved = self.make_marker(self.marker_position(mark));
+            let ring = &mut self.state_mut().mark_ring;
+            ring.insert(0, saved);
+            for old in ring.split_off(max.min(ring.len())) {
+                self.free_marker(old);
+            }
+        }
+        self.set_mark(Some(pos));
+    }
+
+    /// Set the mark to the most recent entry of the mark ring, and move the
+    /// current mark to the end of the ring.
+    pub(crate) fn pop_mark(&mut self) {
+        if self.state().mark_ring.is_empty() {
+            return;
+        }
+        let top = self.state_mut().mark_ring.remove(0);
+        let pos = self.marker_position(top);
+        match self.state().mark {
+            Some(mark) => {
+                self.set_marker(top, self.marker_position(mark));
+                self.state_mut().mark_ring.push(top);
+            }
+            None => self.free_marker(top),
+        }
+        self.set_mark(Some(pos));
+    }
+
+    /// Add `overlay` to this buffer from `beg` to `end`. The bounds advance
+    /// over inserted text according to the overlay's insertion types.
+    pub(crate) fn add_overlay(&mut self, overlay: &'static Overlay, beg: usize, end: usize) {
+        let len = self.text.len_chars();
+        let (beg, end) = (beg.min(end).min(len), beg.max(end).min(len));
+        let start = self.markers.add(beg, overlay.front_advance);
+        let end = self.markers.add(end, overlay.rear_advance);
+        self.state_mut().overlays.push(OverlayEntry {
+            overlay,
+            start,
+            end,
+        });
+    }
+
+    /// Remove `overlay` from this buffer. Returns false if it was not in this
+    /// buffer.
+    pub(crate) fn remove_overlay(&mut self, overlay: &Overlay) -> bool {
+        let Some(idx) = self
+            .state()
+            .overlays
+            .iter()
+            .position(|x| x.overlay == overlay)
+        else {return false};
+        let entry = self.state_mut().overlays.remove(idx);
+        self.free_marker(entry.start);
+        self.free_marker(entry.end);
+        true
+    }
+
+    /// Move `overlay` in this buffer to `beg` and `end`.
+    pub(crate) fn move_overlay(&mut self, overlay: &Overlay, beg: usize, end: usize) {
+        let (beg, end) = (beg.min(end), beg.max(end));
+        if let Some(entry) = self.state().overlays.iter().find(|x| x.overlay == overlay) {
+            let (start, end_marker) = (entry.start, entry.end);
+            self.set_marker(start, beg);
+            self.set_marker(end_marker, end);
+        }
+    }
+
+    pub(crate) fn overlay_bounds(&self, overlay: &Overlay) -> Option<(usize, usize)> {
+        let entry = self
+            .state()
+            .overlays
+            .iter()
+            .find(|x| x.overlay == overlay)?;
+        Some((
+            self.markers.position(entry.start),
+            self.markers.position(entry.end),
+        ))
+    }
+
+    /// The overlays of this buffer with their bounds, in the order they were
+    /// added.
+    pub(crate) fn overlays(&self) -> impl Iterator<Item = (&'static Overlay, usize, usize)> + '_ {
+        self.state().overlays.iter().map(|x| {
+            let start = self.markers.position(x.start);
+            (x.overlay, start, self.markers.position(x.end))
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn mark_ring(&self) -> impl Iterator<Item = usize> + '_ {
+        self.state()
+            .mark_ring
+            .iter()
+            .map(|x| self.marker_position(*x))
+    }
+}
+
+/// A locked buffer that is still live.
+pub(crate) struct OpenBuffer<'a>(MutexGuard<'a, BufferData>);
+
+impl Deref for OpenBuffer<'_> {
+    type Target = BufferData;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for OpenBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct Buffer {
     gc: GcMark,
-    text_buffer: Mutex<Option<BufferData>>,
+    /// The text and state of this buffer, shared with its base buffer and
+    /// the other indirect buffers of it
+    data: &'static Mutex<BufferData>,
+    /// Index of the state of this buffer in `data`
+    id: usize,
+    /// The buffer that owns the text if this is an indirect buffer
+    base: Option<&'static Buffer>,
+}
+
+impl Buffer {
+    /// Create a new empty buffer. Buffers are never freed, but the text of
+    /// a killed temporary buffer is reused.
+    pub(crate) fn create(name: &str) -> &'static Buffer {
+        let data = BufferData {
+            text: TEXT_POOL.take(name),
+            markers: Markers::default(),
+            buffers: vec![Some(BufferState::new(name))],
+            current: 0,
+            modified_tick: 1,
+            chars_modified_tick: 1,
+            save_tick: 1,
+            auto_save_tick: 1,
+            silent: false,
+        };
+        Box::leak(Box::new(Buffer {
+            gc: GcMark::default(),
+            data: Box::leak(Box::new(Mutex::new(data))),
+            id: 0,
+            base: None,
+        }))
+    }
+
+    /// Create an indirect buffer that shares the text of `base`, or of its
+    /// base buffer if it is indirect itself. It starts with the point and
+    /// restriction of that buffer, and also its mark if `clone` is true.
+    pub(crate) fn create_indirect(
+        base: &'static Buffer,
+        name: &str,
+        clone: bool,
+    ) -> Result<&'static Buffer> {
+        let base = base.text_owner();
+        let mut data = base.lock()?;
+        let mut state = BufferState::new(name);
+        state.point = data.point();
+        state.restriction = data.state().restriction;
+        if let (true, Some(mark)) = (clone, data.mark()) {
+            state.mark = Some(data.make_marker(mark));
+        }
+        data.buffers.push(Some(state));
+        Ok(Box::leak(Box::new(Buffer {
+            gc: GcMark::default(),
+            data: base.data,
+            id: data.buffers.len() - 1,
+            base: Some(base),
+        })))
+    }
+
+    /// The buffer that owns the text if this is an indirect buffer.
+    pub(crate) fn base(&self) -> Option<&'static Buffer> {
+        self.base
+    }
+
+    /// This buffer if it owns its text, or else its base buffer.
+    pub(crate) fn text_owner(&'static self) -> &'static Buffer {
+        self.base.unwrap_or(self)
+    }
+
+    /// Whether the buffer has not been killed.
+    pub(crate) fn is_live(&self) -> bool {
+        self.data.lock().unwrap().buffers[self.id].is_some()
+    }
+
+    /// Lock the buffer for access. Returns an error if the buffer has been
+    /// killed. Buffers that share text also share the lock.
+    pub(crate) fn lock(&self) -> Result<OpenBuffer<'_>> {
+        let mut data = self.data.lock().unwrap();
+        ensure!(data.buffers[self.id].is_some(), "Selecting deleted buffer");
+        data.current = self.id;
+        Ok(OpenBuffer(data))
+    }
 }
 
+unsafe impl Sync for Buffer {}
+
 impl PartialEq for Buffer {
     fn eq(&self, other: &Self) -> bool {
         std::ptr::eq(self, other)
@@ -27,9 +753,9 @@ impl Eq for Buffer {}
 
 impl Display for Buffer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let data = self.text_buffer.lock().unwrap();
-        let name = match data.as_ref() {
-            Some(buf) => &buf.name,
+        let data = self.data.lock().unwrap();
+        let name = match &data.buffers[self.id] {
+            Some(state) => &state.name,
             None => "deleted buffer",
         };
         write!(f, "#<{name}>")
@@ -38,7 +764,8 @@ impl Display for Buffer {
 
 impl Trace for Buffer {
     fn trace(&self, _v: &mut Vec<RawObj>) {
-        todo!()
+        // Buffers are not owned by a context and never hold objects
+        self.mark();
     }
 }
 
@@ -56,3 +783,122 @@ impl<'old, 'new> Buffer {
         unsafe { self.with_lifetime().tag() }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_markers() {
+        let buffer = Buffer::create("markers-test");
+        let mut data = buffer.lock().unwrap();
+        data.insert("hello world");
+        let start = data.make_marker(0);
+        let middle = data.make_marker(6);
+        let end = data.make_marker(11);
+        data.goto_char(5);
+        data.insert(",");
+        assert_eq!(data.text.to_string(), "hello, world");
+        assert_eq!(data.marker_position(start), 0);
+        assert_eq!(data.marker_position(middle), 7);
+        assert_eq!(data.marker_position(end), 12);
+        data.delete_region(8, 1);
+        assert_eq!(data.text.to_string(), "horld");
+        assert_eq!(data.marker_position(start), 0);
+        assert_eq!(data.marker_position(middle), 1);
+        assert_eq!(data.marker_position(end), 5);
+        data.free_marker(middle);
+        assert_eq!(data.make_marker(2), middle);
+
+        data.goto_char(2);
+        data.set_marker_insertion_type(middle, true);
+        data.insert("ab");
+        assert_eq!(data.marker_position(middle), 4);
+        assert!(data.marker_insertion_type(middle));
+    }
+
+    #[test]
+    fn test_mark_ring() {
+        let buffer = Buffer::create("mark-ring-test");
+        let mut data = buffer.lock().unwrap();
+        data.insert("hello world");
+        assert_eq!(data.mark(), None);
+        data.push_mark(1, 2);
+        data.push_mark(2, 2);
+        data.push_mark(3, 2);
+        data.push_mark(4, 2);
+        assert_eq!(data.mark(), Some(4));
+        assert_eq!(data.mark_ring().collect::<Vec<_>>(), vec![3, 2]);
+        data.pop_mark();
+        assert_eq!(data.mark(), Some(3));
+        assert_eq!(data.mark_ring().collect::<Vec<_>>(), vec![2, 4]);
+        data.set_mark(None);
+        assert_eq!(data.mark(), None);
+    }
+
+    #[test]
+    fn test_temp_buffer_text_reuse() {
+        // not the global pool, which other tests use in parallel
+        let pool = TextPool::new();
+        let mut text = pool.take(" *temp*-reuse-test");
+        text.insert("temporary text");
+        let capacity = text.capacity();
+        assert!(capacity > 0);
+        pool.recycle(" *temp*-reuse-test", &mut text);
+        let text = pool.take(" *temp*-reuse-test<2>");
+        assert_eq!(text.to_string(), "");
+        assert_eq!(text.capacity(), capacity);
+        // other buffers get a fresh text store
+        let mut text = pool.take("reuse-test");
+        assert_eq!(text.capacity(), 0);
+        text.insert("text");
+        pool.recycle("reuse-test", &mut text);
+        assert_eq!(pool.take(" *temp*-reuse-test<3>").capacity(), 0);
+    }
+
+    #[test]
+    fn test_narrowing() {
+        let buffer = Buffer::create("narrowing-test");
+        let mut data = buffer.lock().unwrap();
+        data.insert("hello world");
+        data.narrow(8, 2);
+        assert_eq!((data.begv(), data.zv()), (2, 8));
+        assert_eq!(data.point(), 8);
+        assert!(data.is_narrowed());
+        data.goto_char(0);
+        assert_eq!(data.point(), 2);
+
+        // text inserted at the end of the restriction is accessible
+        data.goto_char(8);
+        data.insert("!");
+        assert_eq!((data.begv(), data.zv()), (2, 9));
+        data.delete_region(1, 4);
+        assert_eq!((data.begv(), data.zv()), (1, 6));
+
+        data.save_restriction();
+        data.widen();
+        assert!(!data.is_narrowed());
+        data.goto_char(0);
+        data.insert("a");
+        data.restore_restriction();
+        assert_eq!((data.begv(), data.zv()), (2, 7));
+        assert_eq!(data.point(), 2);
+    }
+
+    #[test]
+    fn test_labeled_restriction() {
+        let buffer = Buffer::create("labeled-restriction-test");
+        let mut data = buffer.lock().unwrap();
+        data.insert("hello world");
+        let label = crate::core::env::sym::NIL;
+        data.narrow_labeled(2, 8, label);
+        data.narrow(0, 5);
+        assert_eq!((data.begv(), data.zv()), (2, 5));
+        data.widen();
+        assert_eq!((data.begv(), data.zv()), (2, 8));
+        data.widen_labeled(crate::core::env::sym::TRUE);
+        assert_eq!((data.begv(), data.zv()), (2, 8));
+        data.widen_labeled(label);
+        assert!(!data.is_narrowed());
+    }
+}
@@ -0,0 +1,377 @@
+//! Marker positions of a buffer.
+//!
+//! Markers are kept sorted by position in chunks of up to [`MAX_CHUNK`]
+//! entries. Each chunk has an offset that applies to every marker in it, so an
+//! edit only updates the markers in the chunk where it happens and the offsets
+//! of the chunks after it.
+
+/// Identifies a marker in a [`Markers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MarkerId(usize);
+
+const MAX_CHUNK: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    /// Position relative to the chunk offset. This can wrap around.
+    pos: usize,
+    /// Whether the marker advances when text is inserted at its position
+    advance: bool,
+    id: usize,
+}
+
+#[derive(Debug, Default)]
+struct Chunk {
+    offset: isize,
+    entries: Vec<Entry>,
+}
+
+impl Chunk {
+    fn pos(&self, entry: &Entry) -> usize {
+        entry.pos.wrapping_add_signed(self.offset)
+    }
+
+    fn set_pos(&mut self, idx: usize, pos: usize) {
+        self.entries[idx].pos = pos.wrapping_add_signed(-self.offset);
+    }
+
+    /// Sort key of an entry. Markers that don't advance come first at the
+    /// same position, so that the markers moved by an insertion are always
+    /// a suffix.
+    fn key(&self, entry: &Entry) -> (usize, bool) {
+        (self.pos(entry), entry.advance)
+    }
+}
+
+/// The markers of a buffer.
+#[derive(Debug, Default)]
+pub(crate) struct Markers {
+    chunks: Vec<Chunk>,
+    /// Index of the chunk holding each marker, indexed by [`MarkerId`]. Free
+    /// slots are `None`.
+    slots: Vec<Option<usize>>,
+}
+
+impl Markers {
+    /// Add a marker at `pos`.
+    pub(crate) fn add(&mut self, pos: usize, advance: bool) -> MarkerId {
+        let id = match self.slots.iter().position(Option::is_none) {
+            Some(id) => id,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.insert_entry(Entry { pos, advance, id });
+        MarkerId(id)
+    }
+
+    pub(crate) fn remove(&mut self, id: MarkerId) {
+        self.remove_entry(id);
+        self.slots[id.0] = None;
+    }
+
+    pub(crate) fn position(&self, id: MarkerId) -> usize {
+        let (chunk, idx) = self.find(id);
+        let chunk = &self.chunks[chunk];
+        chunk.pos(&chunk.entries[idx])
+    }
+
+    pub(crate) fn set_position(&mut self, id: MarkerId, pos: usize) {
+        let advance = self.remove_entry(id);
+        self.insert_entry(Entry {
+            pos,
+            advance,
+            id: id.0,
+        });
+    }
+
+    pub(crate) fn insertion_type(&self, id: MarkerId) -> bool {
+        let (chunk, idx) = self.find(id);
+        self.chunks[chunk].entries[idx].advance
+    }
+
+    /// Set whether the marker advances when text is inserted at its position.
+    pub(crate) fn set_insertion_type(&mut self, id: MarkerId, advance: bool) {
+        let pos = self.position(id);
+        self.remove_entry(id);
+        self.insert_entry(Entry {
+            pos,
+            advance,
+            id: id.0,
+        });
+    }
+
+    /// Update the markers for `len` characters inserted at `pos`. Markers at
+    /// `pos` only move if they advance.
+    pub(crate) fn insert(&mut self, pos: usize, len: usize) {
+        self.shift_from((pos, true), len);
+    }
+
+    /// Update the markers for `len` characters inserted at `pos` before all
+    /// the markers there, so that they all move.
+    pub(crate) fn insert_before(&mut self, pos: usize, len: usize) {
+        self.shift_from((pos, false), len);
+    }
+
+    /// Move the markers from `key` onwards forward by `len`.
+    fn shift_from(&mut self, key: (usize, bool), len: usize) {
+        let (start, idx) = self.lower_bound(key);
+        if let Some(chunk) = self.chunks.get_mut(start) {
+            for entry in &mut chunk.entries[idx..] {
+                entry.pos = entry.pos.wrapping_add(len);
+            }
+        }
+        for chunk in self.chunks.iter_mut().skip(start + 1) {
+            chunk.offset += len as isize;
+        }
+    }
+
+    /// Update the markers for the text between `beg` and `end` being deleted.
+    /// Markers in the deleted text move to `beg`.
+    pub(crate) fn delete(&mut self, beg: usize, end: usize) {
+        let len = end - beg;
+        // markers from `beg` to `end` all end up at `beg`. Rewrite them in
+        // order with the ones that don't advance first.
+        let first = self.lower_bound((beg, false));
+        let last = self.lower_bound((end, true));
+        let run: Vec<_> = self.range(first, last).collect();
+        let (mut run, advancing): (Vec<_>, Vec<_>) = run.into_iter().partition(|x| !x.advance);
+        run.extend(advancing);
+        let mut run = run.into_iter();
+        let (mut chunk, mut idx) = first;
+        while (chunk, idx) < last {
+            let entry = run.next().unwrap();
+            self.chunks[chunk].entries[idx] = entry;
+            self.chunks[chunk].set_pos(idx, beg);
+            self.slots[entry.id] = Some(chunk);
+            idx += 1;
+            if idx == self.chunks[chunk].entries.len() {
+                chunk += 1;
+                idx = 0;
+            }
+        }
+        if let Some(chunk) = self.chunks.get_mut(last.0) {
+            for entry in &mut chunk.entries[last.1..] {
+                entry.pos = entry.pos.wrapping_sub(len);
+            }
+        }
+        for chunk in self.chunks.iter_mut().skip(last.0 + 1) {
+            chunk.offset -= len as isize;
+        }
+    }
+
+    /// Update the markers for the text between `beg1` and `end1` being
+    /// swapped with the text between `beg2` and `end2`, where the first
+    /// region is before the second. Markers move with the text after them.
+    pub(crate) fn transpose(&mut self, beg1: usize, end1: usize, beg2: usize, end2: usize) {
+        let (len1, len2) = (end1 - beg1, end2 - beg2);
+        let first = self.lower_bound((beg1, false));
+        let last = self.lower_bound((end2, false));
+        let moved: Vec<_> = self.range(first, last).collect();
+        for entry in moved {
+            let pos = if entry.pos < end1 {
+                entry.pos + (end2 - end1)
+            } else if entry.pos < beg2 {
+                entry.pos - len1 + len2
+            } else {
+                entry.pos - (beg2 - beg1)
+            };
+            self.set_position(MarkerId(entry.id), pos);
+        }
+    }
+
+    /// Iterate over the positions of all markers in order.
+    #[cfg(test)]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (MarkerId, usize)> + '_ {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.entries.iter().map(|x| (MarkerId(x.id), chunk.pos(x))))
+    }
+
+    /// The entries between two locations, with their positions made absolute.
+    fn range(
+        &self,
+        (start, start_idx): (usize, usize),
+        (end, end_idx): (usize, usize),
+    ) -> impl Iterator<Item = Entry> + '_ {
+        self.chunks[start..end.min(self.chunks.len())]
+            .iter()
+            .chain(self.chunks.get(end))
+            .enumerate()
+            .flat_map(move |(i, chunk)| {
+                let lo = if i == 0 { start_idx } else { 0 };
+                let hi = if start + i == end {
+                    end_idx
+                } else {
+                    chunk.entries.len()
+                };
+                chunk.entries[lo..hi.max(lo)].iter().map(|x| Entry {
+                    pos: chunk.pos(x),
+                    ..*x
+                })
+            })
+    }
+
+    /// The location of the first entry with a key that is not less than
+    /// `key`.
+    fn lower_bound(&self, key: (usize, bool)) -> (usize, usize) {
+        let chunk = self.chunks.partition_point(|chunk| {
+            chunk
+                .entries
+                .last()
+                .is_some_and(|last| chunk.key(last) < key)
+        });
+        match self.chunks.get(chunk) {
+            Some(c) => (chunk, c.entries.partition_point(|x| c.key(x) < key)),
+            None => (chunk, 0),
+        }
+    }
+
+    fn find(&self, id: MarkerId) -> (usize, usize) {
+        let chunk = self.slots[id.0].expect("marker was freed");
+        let idx = self.chunks[chunk]
+            .entries
+            .iter()
+            .position(|x| x.id == id.0)
+            .unwrap();
+        (chunk, idx)
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        if self.chunks.is_empty() {
+            self.chunks.push(Chunk::default());
+        }
+        let (mut chunk, mut idx) = self.lower_bound((entry.pos, entry.advance));
+        if chunk == self.chunks.len() {
+            chunk -= 1;
+            idx = self.chunks[chunk].entries.len();
+        }
+        self.chunks[chunk].entries.insert(idx, entry);
+        self.chunks[chunk].set_pos(idx, entry.pos);
+        self.slots[entry.id] = Some(chunk);
+        if self.chunks[chunk].entries.len() > MAX_CHUNK {
+            let entries = self.chunks[chunk].entries.split_off(MAX_CHUNK / 2);
+            let offset = self.chunks[chunk].offset;
+            self.chunks.insert(chunk + 1, Chunk { offset, entries });
+            self.renumber(chunk + 1);
+        }
+    }
+
+    /// Remove the entry of a marker and return its insertion type.
+    fn remove_entry(&mut self, id: MarkerId) -> bool {
+        let (chunk, idx) = self.find(id);
+        let entry = self.chunks[chunk].entries.remove(idx);
+        if self.chunks[chunk].entries.is_empty() {
+            self.chunks.remove(chunk);
+            self.renumber(chunk);
+        }
+        entry.advance
+    }
+
+    /// Update the slots of the markers in the chunks starting at `start`
+    /// after chunks were added or removed.
+    fn renumber(&mut self, start: usize) {
+        for (i, chunk) in self.chunks.iter().enumerate().skip(start) {
+            for entry in &chunk.entries {
+                self.slots[entry.id] = Some(i);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn positions(markers: &Markers) -> Vec<usize> {
+        markers.iter().map(|x| x.1).collect()
+    }
+
+    #[test]
+    fn insertion_types() {
+        let mut markers = Markers::default();
+        let stay = markers.add(5, false);
+        let advance = markers.add(5, true);
+        let before = markers.add(2, true);
+        let after = markers.add(8, false);
+        markers.insert(5, 3);
+        assert_eq!(markers.position(stay), 5);
+        assert_eq!(markers.position(advance), 8);
+        assert_eq!(markers.position(before), 2);
+        assert_eq!(markers.position(after), 11);
+
+        markers.delete(3, 9);
+        assert_eq!(markers.position(stay), 3);
+        assert_eq!(markers.position(advance), 3);
+        assert_eq!(markers.position(after), 5);
+        // the collapsed markers keep their insertion types
+        markers.insert(3, 1);
+        assert_eq!(markers.position(stay), 3);
+        assert_eq!(markers.position(advance), 4);
+
+        markers.set_insertion_type(stay, true);
+        assert!(markers.insertion_type(stay));
+        markers.insert(3, 1);
+        assert_eq!(markers.position(stay), 4);
+        markers.remove(before);
+        assert_eq!(markers.add(0, false), before);
+
+        // inserting before the markers moves every marker at the position
+        markers.insert_before(0, 2);
+        assert_eq!(markers.position(before), 2);
+        assert_eq!(markers.position(stay), 6);
+        assert_eq!(markers.position(advance), 7);
+    }
+
+    #[test]
+    fn transpose() {
+        let mut markers = Markers::default();
+        let ids: Vec<_> = (0..10).map(|i| markers.add(i, false)).collect();
+        // swap 2..4 with 6..9
+        markers.transpose(2, 4, 6, 9);
+        let moved: Vec<_> = ids.iter().map(|x| markers.position(*x)).collect();
+        assert_eq!(moved, [0, 1, 7, 8, 5, 6, 2, 3, 4, 9]);
+        let sorted = positions(&markers);
+        assert!(sorted.windows(2).all(|x| x[0] <= x[1]));
+    }
+
+    #[test]
+    fn many_markers() {
+        let mut markers = Markers::default();
+        let ids: Vec<_> = (0..1000)
+            .map(|i| markers.add(i * 2, i.is_multiple_of(2)))
+            .collect();
+        markers.insert(1000, 10);
+        markers.delete(100, 200);
+        let expect = |i: usize| {
+            let pos = i * 2;
+            let pos = if pos > 1000 || (pos == 1000 && i.is_multiple_of(2)) {
+                pos + 10
+            } else {
+                pos
+            };
+            if pos >= 200 {
+                pos - 100
+            } else {
+                pos.min(100)
+            }
+        };
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(markers.position(*id), expect(i));
+        }
+        let sorted = positions(&markers);
+        assert!(sorted.windows(2).all(|x| x[0] <= x[1]));
+
+        for id in ids.iter().step_by(2) {
+            markers.set_position(*id, 50);
+        }
+        for id in ids.iter().step_by(3) {
+            markers.remove(*id);
+        }
+        let sorted = positions(&markers);
+        assert!(sorted.windows(2).all(|x| x[0] <= x[1]));
+        assert_eq!(sorted.len(), 1000 - 334);
+        assert_eq!(markers.position(ids[2]), 50);
+    }
+}
@@ -0,0 +1,234 @@
+use super::{nil, CloneIn, Gc, GcObj, IntoObject};
+use crate::core::gc::{GcManaged, GcMark, Trace};
+use crate::hashmap::HashMap;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A map from characters to values. Single characters are kept in a hash
+/// map and ranges in a list, where a later range takes precedence over an
+/// earlier one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CharMap<T> {
+    chars: HashMap<u32, T>,
+    ranges: Vec<(u32, u32, T)>,
+}
+
+impl<T> Default for CharMap<T> {
+    fn default() -> Self {
+        Self {
+            chars: HashMap::default(),
+            ranges: Vec::new(),
+        }
+    }
+}
+
+impl<T> CharMap<T> {
+    pub(crate) fn get(&self, chr: u32) -> Option<&T> {
+        self.chars.get(&chr).or_else(|| {
+            let range = self.ranges.iter().rev().find(|x| x.0 <= chr && chr <= x.1);
+            range.map(|x| &x.2)
+        })
+    }
+
+    pub(crate) fn set(&mut self, chr: u32, value: T) {
+        self.chars.insert(chr, value);
+    }
+
+    /// Set every character from `from` to `to` inclusive to `value`.
+    pub(crate) fn set_range(&mut self, from: u32, to: u32, value: T) {
+        if from == to {
+            return self.set(from, value);
+        }
+        self.chars.retain(|chr, _| !(from..=to).contains(chr));
+        self.ranges.retain(|x| !(from <= x.0 && x.1 <= to));
+        self.ranges.push((from, to, value));
+    }
+
+    /// The ranges and characters that were set, in an order that gives the
+    /// same map when they are set again.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u32, u32, &T)> {
+        let ranges = self.ranges.iter().map(|(from, to, x)| (*from, *to, x));
+        ranges.chain(self.chars.iter().map(|(chr, x)| (*chr, *chr, x)))
+    }
+
+    /// The runs of characters from `from` to `to` inclusive that share a
+    /// value, with that value if they were set.
+    pub(crate) fn runs(&self, from: u32, to: u32) -> Vec<(u32, u32, Option<&T>)> {
+        let mut bounds = vec![from, to + 1];
+        let mut add = |beg: u32, end: u32| {
+            bounds.extend([beg, end + 1].into_iter().filter(|x| from < *x && *x <= to));
+        };
+        for (beg, end, _) in &self.ranges {
+            add(*beg, *end);
+        }
+        for chr in self.chars.keys() {
+            add(*chr, *chr);
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds
+            .windows(2)
+            .map(|x| (x[0], x[1] - 1, self.get(x[0])))
+            .collect()
+    }
+
+    pub(crate) fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> CharMap<U> {
+        CharMap {
+            chars: self.chars.iter().map(|(chr, x)| (*chr, f(x))).collect(),
+            ranges: self
+                .ranges
+                .iter()
+                .map(|(from, to, x)| (*from, *to, f(x)))
+                .collect(),
+        }
+    }
+}
+
+/// The slots of a char-table other than the gc mark.
+#[derive(Debug)]
+pub(crate) struct CharTableData<'ob> {
+    /// The symbol that says what the table is for, such as `syntax-table`
+    pub(crate) subtype: GcObj<'ob>,
+    /// The table consulted for characters whose value is nil, or nil
+    pub(crate) parent: GcObj<'ob>,
+    /// The value of characters that were not set
+    pub(crate) default: GcObj<'ob>,
+    pub(crate) extras: Vec<GcObj<'ob>>,
+    pub(crate) entries: CharMap<GcObj<'ob>>,
+}
+
+impl<'ob> CharTableData<'ob> {
+    pub(crate) fn new(subtype: GcObj<'ob>, init: GcObj<'ob>, extras: usize) -> Self {
+        Self {
+            subtype,
+            parent: nil(),
+            default: init,
+            extras: vec![nil(); extras],
+            entries: CharMap::default(),
+        }
+    }
+}
+
+/// Counts the changes to any char-table, so a value derived from tables can
+/// tell when it is stale.
+static CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// A table that maps every character to a value, with a parent table for
+/// the characters whose value is nil.
+pub(crate) struct CharTable {
+    gc: GcMark,
+    inner: RefCell<CharTableData<'static>>,
+}
+
+impl CharTable {
+    // SAFETY: Since this type does not have an object lifetime, it is only safe
+    // to create an owned version in context of the allocator.
+    pub(in crate::core) unsafe fn new(data: CharTableData) -> Self {
+        CHANGES.fetch_add(1, Ordering::Relaxed);
+        Self {
+            gc: GcMark::default(),
+            inner: RefCell::new(std::mem::transmute::<CharTableData, CharTableData<'static>>(data)),
+        }
+    }
+
+    pub(crate) fn borrow<'a>(&'a self) -> Ref<'a, CharTableData<'a>> {
+        unsafe {
+            std::mem::transmute::<Ref<'a, CharTableData<'static>>, Ref<'a, CharTableData<'a>>>(
+                self.inner.borrow(),
+            )
+        }
+    }
+
+    pub(crate) fn borrow_mut<'a>(&'a self) -> RefMut<'a, CharTableData<'a>> {
+        CHANGES.fetch_add(1, Ordering::Relaxed);
+        unsafe {
+            std::mem::transmute::<RefMut<'a, CharTableData<'static>>, RefMut<'a, CharTableData<'a>>>(
+                self.inner.borrow_mut(),
+            )
+        }
+    }
+
+    /// The value of `chr`, looked up in the parent if it is nil here.
+    pub(crate) fn get(&self, chr: u32) -> GcObj {
+        let data = self.borrow();
+        let value = match data.entries.get(chr) {
+            Some(value) if !value.nil() => *value,
+            _ => data.default,
+        };
+        match data.parent.untag() {
+            super::Object::CharTable(parent) if value.nil() => parent.get(chr),
+            _ => value,
+        }
+    }
+
+    /// The number of char-tables created or borrowed mutably so far.
+    pub(crate) fn changes() -> usize {
+        CHANGES.load(Ordering::Relaxed)
+    }
+
+    /// The parent of the table, if it has one.
+    pub(crate) fn parent(&self) -> Option<&CharTable> {
+        match self.borrow().parent.untag() {
+            super::Object::CharTable(parent) => Some(parent),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for CharTable {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for CharTable {}
+
+impl Debug for CharTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl Display for CharTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.inner.try_borrow() {
+            Ok(data) => write!(f, "#<char-table {}>", data.subtype),
+            Err(_) => write!(f, "#<char-table>"),
+        }
+    }
+}
+
+impl<'new> CloneIn<'new, &'new Self> for CharTable {
+    fn clone_in<const C: bool>(&self, bk: &'new crate::core::gc::Block<C>) -> Gc<&'new Self> {
+        let data = self.borrow();
+        let table = CharTableData {
+            subtype: data.subtype.clone_in(bk),
+            parent: data.parent.clone_in(bk),
+            default: data.default.clone_in(bk),
+            extras: data.extras.iter().map(|x| x.clone_in(bk)).collect(),
+            entries: data.entries.map(|x| x.clone_in(bk)),
+        };
+        table.into_obj(bk)
+    }
+}
+
+impl Trace for CharTable {
+    fn trace(&self, stack: &mut Vec<super::RawObj>) {
+        let data = self.borrow();
+        let slots = [data.subtype, data.parent, data.default];
+        let values = data.entries.iter().map(|x| x.2);
+        for obj in slots.iter().chain(&data.extras).chain(values) {
+            if obj.is_markable() {
+                stack.push(obj.into_raw());
+            }
+        }
+        self.mark();
+    }
+}
+
+impl GcManaged for CharTable {
+    fn get_mark(&self) -> &GcMark {
+        &self.gc
+    }
+}
@@ -4,7 +4,8 @@
 
 use super::{
     super::error::{ArgError, Type, TypeError},
-    nil, qtrue, LispHashTable, LispString, LispVec,
+    nil, qtrue, Buffer, CharTable, LispHashTable, LispString, LispVec, Marker, Overlay,
+    Process,
 };
 use super::{Gc, Object};
 use super::{GcObj, LispFloat};
@@ -32,6 +33,52 @@ impl<'ob> TryFrom<GcObj<'ob>> for Option<&'ob str> {
     }
 }
 
+impl<'ob> TryFrom<GcObj<'ob>> for &'static Buffer {
+    type Error = TypeError;
+    fn try_from(obj: GcObj<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            Object::Buffer(x) => Ok(x),
+            x => Err(TypeError::new(Type::Buffer, x)),
+        }
+    }
+}
+
+impl<'ob> TryFrom<GcObj<'ob>> for &'static Overlay {
+    type Error = TypeError;
+    fn try_from(obj: GcObj<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            Object::Overlay(x) => Ok(x),
+            x => Err(TypeError::new(Type::Overlay, x)),
+        }
+    }
+}
+
+impl<'ob> TryFrom<GcObj<'ob>> for &'static Marker {
+    type Error = TypeError;
+    fn try_from(obj: GcObj<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            Object::Marker(x) => Ok(x),
+            x => Err(TypeError::new(Type::Marker, x)),
+        }
+    }
+}
+
+impl<'ob> TryFrom<GcObj<'ob>> for &'static Process {
+    type Error = TypeError;
+    fn try_from(obj: GcObj<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            Object::Process(x) => Ok(x),
+            x => Err(TypeError::new(Type::Process, x)),
+        }
+    }
+}
+
+/// The position of a marker used as an integer.
+fn marker_position(marker: &Marker) -> anyhow::Result<usize> {
+    let pos = marker.position().map(|x| x.1 + 1);
+    pos.context("Marker does not point anywhere")
+}
+
 impl<'ob> TryFrom<GcObj<'ob>> for usize {
     type Error = anyhow::Error;
     fn try_from(obj: GcObj<'ob>) -> Result<Self, Self::Error> {
@@ -39,6 +86,7 @@ impl<'ob> TryFrom<GcObj<'ob>> for usize {
             Object::Int(x) => x
                 .try_into()
                 .with_context(|| format!("Integer must be positive, but was {x}")),
+            Object::Marker(x) => marker_position(x),
             x => Err(TypeError::new(Type::Int, x).into()),
         }
     }
@@ -64,6 +112,7 @@ impl<'ob> TryFrom<GcObj<'ob>> for Option<usize> {
                 Ok(x) => Ok(Some(x)),
                 Err(e) => Err(e).with_context(|| format!("Integer must be positive, but was {x}")),
             },
+            Object::Marker(x) => marker_position(x).map(Some),
             Object::NIL => Ok(None),
             _ => Err(TypeError::new(Type::Int, obj).into()),
         }
@@ -114,6 +163,7 @@ impl<'ob> From<bool> for GcObj<'ob> {
 define_unbox!(Int, i64);
 define_unbox!(Float, &'ob LispFloat);
 define_unbox!(HashTable, &'ob LispHashTable);
+define_unbox!(CharTable, &'ob CharTable);
 define_unbox!(String, &'ob LispString);
 define_unbox!(Vec, &'ob LispVec);
 define_unbox!(Symbol, Symbol<'ob>);
@@ -234,6 +234,12 @@ impl SubrFn {
                 args.push(nil());
             }
         }
+        if crate::core::gc::GC_STRESS {
+            // Anything kept across calls without being rooted is freed here
+            cx.garbage_collect(true);
+        }
+        // a loop of builtins can allocate without calling any closure
+        crate::alloc::check_memory_limits(env, cx)?;
         (self.subr)(args, env, cx)
     }
 }
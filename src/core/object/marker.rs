@@ -0,0 +1,106 @@
+#![cfg_attr(not(feature = "buffers"), allow(dead_code))]
+use super::{Buffer, Gc, MarkerId, RawObj, TagType, WithLifetime};
+use crate::core::gc::{GcManaged, GcMark, Trace};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+/// A marker object. The position is kept with the other markers of the
+/// buffer, so it moves with the text around it.
+#[derive(Debug)]
+pub(crate) struct Marker {
+    gc: GcMark,
+    place: Mutex<MarkerPlace>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct MarkerPlace {
+    /// The buffer and the marker in it, or `None` if it points nowhere
+    buffer: Option<(&'static Buffer, MarkerId)>,
+    /// Whether the marker advances when text is inserted at its position
+    advance: bool,
+}
+
+impl Marker {
+    /// Create a marker that points nowhere. Like overlays, markers are never
+    /// freed.
+    pub(crate) fn create() -> &'static Marker {
+        Box::leak(Box::new(Marker {
+            gc: GcMark::default(),
+            place: Mutex::new(MarkerPlace::default()),
+        }))
+    }
+
+    /// The buffer of the marker and its id there, or `None` if it points
+    /// nowhere.
+    pub(crate) fn place(&self) -> Option<(&'static Buffer, MarkerId)> {
+        self.place.lock().unwrap().buffer.filter(|x| x.0.is_live())
+    }
+
+    pub(crate) fn set_place(&self, place: Option<(&'static Buffer, MarkerId)>) {
+        self.place.lock().unwrap().buffer = place;
+    }
+
+    pub(crate) fn insertion_type(&self) -> bool {
+        self.place.lock().unwrap().advance
+    }
+
+    pub(crate) fn set_insertion_type(&self, advance: bool) {
+        self.place.lock().unwrap().advance = advance;
+    }
+
+    /// The buffer and 0-based position of the marker, or `None` if it points
+    /// nowhere.
+    pub(crate) fn position(&self) -> Option<(&'static Buffer, usize)> {
+        let (buffer, id) = self.place()?;
+        let pos = buffer.lock().ok()?.marker_position(id);
+        Some((buffer, pos))
+    }
+}
+
+impl PartialEq for Marker {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Marker {}
+
+impl Display for Marker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let advance = if self.insertion_type() {
+            "(moves after insertion) "
+        } else {
+            ""
+        };
+        let place = self.position().and_then(|(buffer, pos)| {
+            let name = buffer.lock().ok()?.name().to_owned();
+            Some((pos, name))
+        });
+        match place {
+            Some((pos, name)) => write!(f, "#<marker {advance}at {} in {name}>", pos + 1),
+            None => write!(f, "#<marker {advance}in no buffer>"),
+        }
+    }
+}
+
+impl Trace for Marker {
+    fn trace(&self, _v: &mut Vec<RawObj>) {
+        // Like buffers, markers are not owned by a context
+        self.mark();
+    }
+}
+
+impl GcManaged for Marker {
+    fn get_mark(&self) -> &GcMark {
+        &self.gc
+    }
+}
+
+impl<'old, 'new> Marker {
+    pub(in crate::core) fn clone_in<const C: bool>(
+        &'old self,
+        _: &'new crate::core::gc::Block<C>,
+    ) -> Gc<&'new Marker> {
+        unsafe { self.with_lifetime().tag() }
+    }
+}
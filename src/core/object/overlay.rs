@@ -0,0 +1,107 @@
+#![cfg_attr(not(feature = "buffers"), allow(dead_code))]
+use super::{Buffer, Gc, RawObj, TagType, WithLifetime};
+use crate::core::gc::{AllocObject, Block, GcManaged, GcMark, Trace};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// An overlay on a region of a buffer. The bounds are kept as markers in the
+/// buffer and the properties are kept in the [`Env`](crate::core::env::Env).
+///
+/// An overlay is owned by the block it was made in. The buffer it is in holds
+/// it like a root, and once it is deleted it is freed when it can't be
+/// reached. An overlay copied to another block is never freed, since its
+/// owner can't see the references of that block.
+#[derive(Debug)]
+pub(crate) struct Overlay {
+    gc: GcMark,
+    /// The buffer of the overlay, or `None` if it was deleted
+    buffer: Mutex<Option<&'static Buffer>>,
+    /// Whether the overlay was copied to another block
+    shared: AtomicBool,
+    /// Whether text inserted at the start is excluded from the overlay
+    pub(crate) front_advance: bool,
+    /// Whether text inserted at the end is included in the overlay
+    pub(crate) rear_advance: bool,
+}
+
+impl Overlay {
+    /// Create a new overlay in `block` that is not in any buffer.
+    pub(crate) fn create<const C: bool>(
+        front_advance: bool,
+        rear_advance: bool,
+        block: &Block<C>,
+    ) -> &'static Overlay {
+        let overlay = Overlay {
+            gc: GcMark::default(),
+            buffer: Mutex::new(None),
+            shared: AtomicBool::new(false),
+            front_advance,
+            rear_advance,
+        };
+        // SAFETY: The block keeps the overlay while it is in a buffer, which
+        // is the only place it is held without a root
+        unsafe { &*overlay.alloc_obj(block) }
+    }
+
+    pub(crate) fn buffer(&self) -> Option<&'static Buffer> {
+        *self.buffer.lock().unwrap()
+    }
+
+    pub(crate) fn set_buffer(&self, buffer: Option<&'static Buffer>) {
+        *self.buffer.lock().unwrap() = buffer;
+    }
+
+    /// Whether the block that owns the overlay has to keep it, even if it is
+    /// not reachable from the roots.
+    pub(crate) fn is_pinned(&self) -> bool {
+        self.shared.load(Ordering::Acquire) || self.buffer().is_some()
+    }
+}
+
+impl PartialEq for Overlay {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Overlay {}
+
+impl Display for Overlay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(buffer) = self.buffer() else {return write!(f, "#<overlay in no buffer>")};
+        let Ok(data) = buffer.lock() else {return write!(f, "#<overlay in no buffer>")};
+        match data.overlay_bounds(self) {
+            Some((start, end)) => write!(
+                f,
+                "#<overlay from {} to {} in {}>",
+                start + 1,
+                end + 1,
+                data.name()
+            ),
+            None => write!(f, "#<overlay in no buffer>"),
+        }
+    }
+}
+
+impl Trace for Overlay {
+    fn trace(&self, _v: &mut Vec<RawObj>) {
+        self.mark();
+    }
+}
+
+impl GcManaged for Overlay {
+    fn get_mark(&self) -> &GcMark {
+        &self.gc
+    }
+}
+
+impl<'old, 'new> Overlay {
+    pub(in crate::core) fn clone_in<const C: bool>(
+        &'old self,
+        _: &'new Block<C>,
+    ) -> Gc<&'new Overlay> {
+        self.shared.store(true, Ordering::Release);
+        unsafe { self.with_lifetime().tag() }
+    }
+}
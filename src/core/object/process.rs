@@ -0,0 +1,188 @@
+#![cfg_attr(not(feature = "processes"), allow(dead_code))]
+use super::{Buffer, Gc, Marker, RawObj, TagType, WithLifetime};
+use crate::coding::CodingSystem;
+use crate::core::gc::{GcManaged, GcMark, Trace};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::File;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::process::Child;
+use std::sync::{Mutex, MutexGuard};
+
+/// The state of a process, as reported by `process-status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub(crate) enum Status {
+    Run,
+    /// A network connection is being made
+    Connect,
+    /// A network connection could not be made, with the error number
+    Failed(i32),
+    /// The process exited with a code
+    Exit(i32),
+    /// The process was killed by a signal, and whether it dumped core
+    Signal(i32, bool),
+    /// The process was stopped by a signal
+    Stop(i32),
+}
+
+/// What a process talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProcessKind {
+    /// A subprocess
+    Real,
+    /// A pipe that is read like the output of a subprocess
+    Pipe,
+    /// A network connection
+    Network,
+    /// A network server that makes a process for each connection
+    Server,
+}
+
+/// A subprocess, or something that is read like one. Its functions and
+/// property list are kept in the
+/// [`Env`](crate::core::env::Env).
+#[derive(Debug)]
+pub(crate) struct Process {
+    gc: GcMark,
+    name: String,
+    /// Where output is inserted in the buffer of the process
+    mark: &'static Marker,
+    data: Mutex<ProcessData>,
+}
+
+/// The state of a process that changes as it runs.
+#[derive(Debug)]
+pub(crate) struct ProcessData {
+    pub(crate) kind: ProcessKind,
+    pub(crate) status: Status,
+    /// Whether the sentinel has yet to be told about the status
+    pub(crate) status_changed: bool,
+    pub(crate) child: Option<Child>,
+    /// The status the child exited with once it was reaped, until all its
+    /// output was read
+    pub(crate) exit: Option<Status>,
+    /// Whether the output of a process that is not a subprocess is held back
+    /// by `stop-process`
+    pub(crate) stopped: bool,
+    /// Whether Emacs would ask before killing the process when it exits
+    pub(crate) query_on_exit: bool,
+    /// Where the input of the process is written
+    pub(crate) stdin: Option<File>,
+    /// The input that is waiting for the process to be ready for it
+    pub(crate) pending_input: VecDeque<u8>,
+    /// The master end of the pseudo-terminal of the process, if it has one
+    pub(crate) pty: Option<File>,
+    /// The file name of the terminal of the process
+    pub(crate) tty_name: Option<String>,
+    pub(crate) buffer: Option<&'static Buffer>,
+    /// The session of an encrypted connection
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<crate::tls::Session>,
+    /// The socket of a datagram process
+    #[cfg_attr(not(feature = "network"), allow(dead_code))]
+    pub(crate) socket: Option<UdpSocket>,
+    /// The socket a server accepts connections on
+    #[cfg_attr(not(feature = "network"), allow(dead_code))]
+    pub(crate) listener: Option<TcpListener>,
+    /// Where the datagrams of a datagram process are sent
+    #[cfg_attr(not(feature = "network"), allow(dead_code))]
+    pub(crate) datagram_address: Option<SocketAddr>,
+    /// The number of output streams that are still open
+    pub(crate) open_streams: usize,
+    pub(crate) decoding: CodingSystem,
+    pub(crate) encoding: CodingSystem,
+    /// The end of the output that is not a whole character yet
+    pub(crate) undecoded: Vec<u8>,
+}
+
+impl ProcessData {
+    pub(crate) fn new(decoding: CodingSystem, encoding: CodingSystem) -> Self {
+        Self {
+            kind: ProcessKind::Real,
+            status: Status::Run,
+            status_changed: false,
+            child: None,
+            exit: None,
+            stopped: false,
+            query_on_exit: true,
+            stdin: None,
+            pending_input: VecDeque::new(),
+            pty: None,
+            tty_name: None,
+            buffer: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            socket: None,
+            listener: None,
+            datagram_address: None,
+            open_streams: 0,
+            decoding,
+            encoding,
+            undecoded: Vec::new(),
+        }
+    }
+}
+
+impl Process {
+    /// Create a process named `name`. Like buffers, processes are never
+    /// freed.
+    pub(crate) fn create(name: String, data: ProcessData) -> &'static Process {
+        Box::leak(Box::new(Process {
+            gc: GcMark::default(),
+            name,
+            mark: Marker::create(),
+            data: Mutex::new(data),
+        }))
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn process_mark(&self) -> &'static Marker {
+        self.mark
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ProcessData> {
+        self.data.lock().unwrap()
+    }
+}
+
+unsafe impl Sync for Process {}
+
+impl PartialEq for Process {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Process {}
+
+impl Display for Process {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#<process {}>", self.name)
+    }
+}
+
+impl Trace for Process {
+    fn trace(&self, _v: &mut Vec<RawObj>) {
+        // Like buffers, processes are not owned by a context
+        self.mark();
+    }
+}
+
+impl GcManaged for Process {
+    fn get_mark(&self) -> &GcMark {
+        &self.gc
+    }
+}
+
+impl<'old, 'new> Process {
+    pub(in crate::core) fn clone_in<const C: bool>(
+        &'old self,
+        _: &'new crate::core::gc::Block<C>,
+    ) -> Gc<&'new Process> {
+        unsafe { self.with_lifetime().tag() }
+    }
+}
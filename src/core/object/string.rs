@@ -1,20 +1,32 @@
-use super::{CloneIn, IntoObject};
-use crate::core::gc::{Block, GcManaged, GcMark};
-use anyhow::Result;
+use super::{CloneIn, IntoObject, RawObj, WithLifetime};
+use crate::core::env::TextProps;
+use crate::core::gc::{Block, GcManaged, GcMark, Trace};
+use crate::intervals::IntervalTree;
+use anyhow::{anyhow, Result};
 use bstr::{BStr, BString, ByteSlice};
-use fn_macros::Trace;
 use std::{
+    cell::{Ref, RefCell},
     fmt::{Debug, Display},
     ops::Deref,
 };
 
-#[derive(PartialEq, Eq, Trace)]
 pub(crate) struct LispString {
     gc: GcMark,
-    #[no_trace]
+    is_const: bool,
     string: StrType,
+    /// The text properties of the string, or `None` if it has never had any.
+    props: RefCell<Option<IntervalTree<TextProps<'static>>>>,
 }
 
+/// Strings are `equal` regardless of their text properties.
+impl PartialEq for LispString {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
+impl Eq for LispString {}
+
 unsafe impl Sync for LispString {}
 
 #[derive(Debug, PartialEq, Eq)]
@@ -38,27 +50,68 @@ impl LispString {
         }
     }
 
+    /// True if this string holds characters rather than raw bytes.
+    pub(crate) fn is_multibyte(&self) -> bool {
+        matches!(self.string, StrType::String(_))
+    }
+
     pub(crate) unsafe fn from_string(value: String) -> Self {
-        Self {
-            gc: GcMark::default(),
-            string: StrType::String(value),
-        }
+        Self::new(StrType::String(value))
     }
 
     pub(crate) unsafe fn from_bstring(value: Vec<u8>) -> Self {
+        Self::new(StrType::BString(BString::from(value)))
+    }
+
+    fn new(string: StrType) -> Self {
         Self {
             gc: GcMark::default(),
-            string: StrType::BString(BString::from(value)),
+            is_const: false,
+            string,
+            props: RefCell::new(None),
+        }
+    }
+
+    pub(in crate::core) fn make_const(&mut self) {
+        self.is_const = true;
+    }
+
+    /// The text properties of the string, or `None` if it has never had any.
+    pub(crate) fn props(&self) -> Ref<'_, Option<IntervalTree<TextProps<'_>>>> {
+        self.props.borrow()
+    }
+
+    /// Replace the text properties of the string.
+    #[cfg_attr(not(feature = "buffers"), allow(dead_code))]
+    pub(crate) fn set_props(&self, props: Option<IntervalTree<TextProps>>) -> Result<()> {
+        if self.is_const {
+            return Err(anyhow!("Attempt to modify constant string"));
         }
+        // SAFETY: The values are traced along with the string, so they live as
+        // long as it does.
+        *self.props.borrow_mut() = props.map(|x| unsafe { x.with_lifetime() });
+        Ok(())
     }
 }
 
 impl<'new> CloneIn<'new, &'new Self> for LispString {
     fn clone_in<const C: bool>(&self, bk: &'new Block<C>) -> super::Gc<&'new Self> {
-        match &self.string {
-            StrType::String(s) => s.clone().into_obj(bk),
-            StrType::BString(s) => s.as_bytes().to_vec().into_obj(bk),
-        }
+        let mut string = Self::new(match &self.string {
+            StrType::String(s) => StrType::String(s.clone()),
+            StrType::BString(s) => StrType::BString(s.clone()),
+        });
+        let props = self.props().as_ref().map(|tree| {
+            tree.map(|props| {
+                let props: TextProps = props
+                    .iter()
+                    .map(|&(name, value)| (name.clone_in(bk).untag(), value.clone_in(bk)))
+                    .collect();
+                // SAFETY: The values were cloned into the same block as the string.
+                unsafe { props.with_lifetime() }
+            })
+        });
+        *string.props.get_mut() = props;
+        string.into_obj(bk)
     }
 }
 
@@ -68,6 +121,15 @@ impl GcManaged for LispString {
     }
 }
 
+impl Trace for LispString {
+    fn trace(&self, stack: &mut Vec<RawObj>) {
+        self.mark();
+        if let Some(props) = self.props().as_ref() {
+            props.trace(stack);
+        }
+    }
+}
+
 impl Deref for LispString {
     type Target = BStr;
 
@@ -79,15 +141,38 @@ impl Deref for LispString {
     }
 }
 
+/// Strings with text properties are printed as `#("text" START END PLIST
+/// ...)`, with an entry for each run of text that has properties.
 impl Display for LispString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let props = self.props();
+        let intervals: Vec<_> = props
+            .iter()
+            .flat_map(|x| x.iter_from(0))
+            .filter(|x| !x.2.is_empty())
+            .collect();
+        if !intervals.is_empty() {
+            write!(f, "#(")?;
+        }
         match &self.string {
-            StrType::String(s) => write!(f, "\"{s}\""),
+            StrType::String(s) => write!(f, "\"{s}\"")?,
             StrType::BString(s) => {
                 let bytes: &[u8] = s.as_ref();
-                write!(f, "\"{bytes:?}\"")
+                write!(f, "\"{bytes:?}\"")?;
+            }
+        }
+        if intervals.is_empty() {
+            return Ok(());
+        }
+        for (start, end, plist) in intervals {
+            write!(f, " {start} {end} (")?;
+            for (i, (name, value)) in plist.iter().enumerate() {
+                let sep = if i == 0 { "" } else { " " };
+                write!(f, "{sep}{name} {value}")?;
             }
+            write!(f, ")")?;
         }
+        write!(f, ")")
     }
 }
 
@@ -6,10 +6,11 @@ use super::{
         error::{Type, TypeError},
         gc::{AllocObject, Block},
     },
-    Buffer,
+    Buffer, Marker, Overlay, Process,
 };
 use super::{
-    ByteFn, HashTable, LispFloat, LispHashTable, LispString, LispVec, Record, RecordBuilder, SubrFn,
+    ByteFn, CharTable, CharTableData, HashTable, LispFloat, LispHashTable, LispString, LispVec,
+    Record, RecordBuilder, SubrFn,
 };
 use crate::core::env::sym;
 use crate::core::gc::{GcManaged, Trace};
@@ -331,6 +332,17 @@ impl<'a> IntoObject for HashTable<'a> {
     }
 }
 
+impl<'a> IntoObject for CharTableData<'a> {
+    type Out<'ob> = &'ob CharTable;
+
+    fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
+        unsafe {
+            let ptr = CharTable::new(self).alloc_obj(block);
+            <&CharTable>::tag_ptr(ptr)
+        }
+    }
+}
+
 mod private {
     use super::{Gc, WithLifetime};
 
@@ -344,9 +356,13 @@ mod private {
         Vec,
         Record,
         HashTable,
+        CharTable,
         SubrFn,
         ByteFn,
         Buffer,
+        Overlay,
+        Marker,
+        Process,
     }
 
     pub(crate) trait TaggedPtr: Copy + for<'a> WithLifetime<'a> {
@@ -396,7 +412,11 @@ impl<'a> TaggedPtr for Object<'a> {
                 Tag::Vec => Object::Vec(<&LispVec>::from_obj_ptr(ptr)),
                 Tag::Record => Object::Record(<&Record>::from_obj_ptr(ptr)),
                 Tag::HashTable => Object::HashTable(<&LispHashTable>::from_obj_ptr(ptr)),
+                Tag::CharTable => Object::CharTable(<&CharTable>::from_obj_ptr(ptr)),
                 Tag::Buffer => Object::Buffer(<&Buffer>::from_obj_ptr(ptr)),
+                Tag::Overlay => Object::Overlay(<&Overlay>::from_obj_ptr(ptr)),
+                Tag::Marker => Object::Marker(<&Marker>::from_obj_ptr(ptr)),
+                Tag::Process => Object::Process(<&Process>::from_obj_ptr(ptr)),
             }
         }
     }
@@ -410,10 +430,14 @@ impl<'a> TaggedPtr for Object<'a> {
             Object::Vec(x) => TaggedPtr::tag(x).into(),
             Object::Record(x) => TaggedPtr::tag(x).into(),
             Object::HashTable(x) => TaggedPtr::tag(x).into(),
+            Object::CharTable(x) => TaggedPtr::tag(x).into(),
             Object::String(x) => TaggedPtr::tag(x).into(),
             Object::ByteFn(x) => TaggedPtr::tag(x).into(),
             Object::SubrFn(x) => TaggedPtr::tag(x).into(),
             Object::Buffer(x) => TaggedPtr::tag(x).into(),
+            Object::Overlay(x) => TaggedPtr::tag(x).into(),
+            Object::Marker(x) => TaggedPtr::tag(x).into(),
+            Object::Process(x) => TaggedPtr::tag(x).into(),
         }
     }
 }
@@ -628,6 +652,18 @@ impl TaggedPtr for &LispHashTable {
     }
 }
 
+impl TaggedPtr for &CharTable {
+    type Ptr = CharTable;
+    const TAG: Tag = Tag::CharTable;
+    unsafe fn from_obj_ptr(ptr: *const u8) -> Self {
+        &*ptr.cast::<Self::Ptr>()
+    }
+
+    fn get_ptr(self) -> *const Self::Ptr {
+        self as *const Self::Ptr
+    }
+}
+
 impl TaggedPtr for &Buffer {
     type Ptr = Buffer;
     const TAG: Tag = Tag::Buffer;
@@ -640,6 +676,42 @@ impl TaggedPtr for &Buffer {
     }
 }
 
+impl TaggedPtr for &Overlay {
+    type Ptr = Overlay;
+    const TAG: Tag = Tag::Overlay;
+    unsafe fn from_obj_ptr(ptr: *const u8) -> Self {
+        &*ptr.cast::<Self::Ptr>()
+    }
+
+    fn get_ptr(self) -> *const Self::Ptr {
+        self as *const Self::Ptr
+    }
+}
+
+impl TaggedPtr for &Marker {
+    type Ptr = Marker;
+    const TAG: Tag = Tag::Marker;
+    unsafe fn from_obj_ptr(ptr: *const u8) -> Self {
+        &*ptr.cast::<Self::Ptr>()
+    }
+
+    fn get_ptr(self) -> *const Self::Ptr {
+        self as *const Self::Ptr
+    }
+}
+
+impl TaggedPtr for &Process {
+    type Ptr = Process;
+    const TAG: Tag = Tag::Process;
+    unsafe fn from_obj_ptr(ptr: *const u8) -> Self {
+        &*ptr.cast::<Self::Ptr>()
+    }
+
+    fn get_ptr(self) -> *const Self::Ptr {
+        self as *const Self::Ptr
+    }
+}
+
 macro_rules! cast_gc {
     ($supertype:ty => $($subtype:ty),+ $(,)?) => {
         $(
@@ -768,12 +840,16 @@ pub(crate) enum Object<'ob> {
     Vec(&'ob LispVec) = Tag::Vec as u8,
     Record(&'ob Record) = Tag::Record as u8,
     HashTable(&'ob LispHashTable) = Tag::HashTable as u8,
+    CharTable(&'ob CharTable) = Tag::CharTable as u8,
     String(&'ob LispString) = Tag::String as u8,
     ByteFn(&'ob ByteFn) = Tag::ByteFn as u8,
     SubrFn(&'static SubrFn) = Tag::SubrFn as u8,
     Buffer(&'static Buffer) = Tag::Buffer as u8,
+    Overlay(&'static Overlay) = Tag::Overlay as u8,
+    Marker(&'static Marker) = Tag::Marker as u8,
+    Process(&'static Process) = Tag::Process as u8,
 }
-cast_gc!(Object<'ob> => Number<'ob>, List<'ob>, Function<'ob>, i64, Symbol<'_>, &LispFloat, &'ob Cons, &'ob LispVec, &'ob Record, &'ob LispHashTable, &'ob LispString, &'ob ByteFn, &'ob SubrFn, &'ob Buffer);
+cast_gc!(Object<'ob> => Number<'ob>, List<'ob>, Function<'ob>, i64, Symbol<'_>, &LispFloat, &'ob Cons, &'ob LispVec, &'ob Record, &'ob LispHashTable, &'ob CharTable, &'ob LispString, &'ob ByteFn, &'ob SubrFn, &'ob Buffer, &'ob Overlay, &'ob Marker, &'ob Process);
 
 impl Object<'_> {
     pub(crate) const NIL: Object<'static> = Object::Symbol(sym::NIL);
@@ -788,9 +864,13 @@ impl Object<'_> {
             Object::Vec(_) => Type::Vec,
             Object::Record(_) => Type::Record,
             Object::HashTable(_) => Type::HashTable,
+            Object::CharTable(_) => Type::CharTable,
             Object::String(_) => Type::String,
             Object::ByteFn(_) | Object::SubrFn(_) => Type::Func,
             Object::Buffer(_) => Type::Buffer,
+            Object::Overlay(_) => Type::Overlay,
+            Object::Marker(_) => Type::Marker,
+            Object::Process(_) => Type::Process,
         }
     }
 }
@@ -1022,7 +1102,11 @@ where
             Object::Vec(x) => x.clone_in(bk).into(),
             Object::Record(x) => x.clone_in(bk).into(),
             Object::HashTable(x) => x.clone_in(bk).into(),
+            Object::CharTable(x) => x.clone_in(bk).into(),
             Object::Buffer(x) => x.clone_in(bk).into(),
+            Object::Overlay(x) => x.clone_in(bk).into(),
+            Object::Marker(x) => x.clone_in(bk).into(),
+            Object::Process(x) => x.clone_in(bk).into(),
         };
         let Ok(x) = Gc::<U>::try_from(obj) else {unreachable!()};
         x
@@ -1122,12 +1206,16 @@ impl fmt::Display for Object<'_> {
             Object::Vec(x) => D::fmt(x, f),
             Object::Record(x) => D::fmt(x, f),
             Object::HashTable(x) => D::fmt(x, f),
+            Object::CharTable(x) => D::fmt(x, f),
             Object::String(x) => D::fmt(x, f),
             Object::Symbol(x) => D::fmt(x, f),
             Object::ByteFn(x) => D::fmt(x, f),
             Object::SubrFn(x) => D::fmt(x, f),
             Object::Float(x) => D::fmt(x, f),
             Object::Buffer(x) => D::fmt(x, f),
+            Object::Overlay(x) => D::fmt(x, f),
+            Object::Marker(x) => D::fmt(x, f),
+            Object::Process(x) => D::fmt(x, f),
         }
     }
 }
@@ -1145,10 +1233,14 @@ impl<'ob> Gc<Object<'ob>> {
             Object::Vec(x) => x.is_marked(),
             Object::Record(x) => x.is_marked(),
             Object::HashTable(x) => x.is_marked(),
+            Object::CharTable(x) => x.is_marked(),
             Object::String(x) => x.is_marked(),
             Object::ByteFn(x) => x.is_marked(),
             Object::Symbol(x) => x.is_marked(),
             Object::Buffer(x) => x.is_marked(),
+            Object::Overlay(x) => x.is_marked(),
+            Object::Marker(x) => x.is_marked(),
+            Object::Process(x) => x.is_marked(),
         }
     }
 
@@ -1156,14 +1248,18 @@ impl<'ob> Gc<Object<'ob>> {
         match self.untag() {
             Object::Int(_) | Object::SubrFn(_) => {}
             Object::Float(x) => x.mark(),
-            Object::String(x) => x.mark(),
+            Object::String(x) => x.trace(stack),
             Object::Vec(vec) => vec.trace(stack),
             Object::Record(x) => x.trace(stack),
             Object::HashTable(x) => x.trace(stack),
+            Object::CharTable(x) => x.trace(stack),
             Object::Cons(x) => x.trace(stack),
             Object::Symbol(x) => x.trace(stack),
             Object::ByteFn(x) => x.trace(stack),
             Object::Buffer(x) => x.trace(stack),
+            Object::Overlay(x) => x.trace(stack),
+            Object::Marker(x) => x.trace(stack),
+            Object::Process(x) => x.trace(stack),
         }
     }
 }
@@ -1,7 +1,8 @@
 use super::{display_slice, CloneIn, GcObj, IntoObject, WithLifetime};
-use crate::core::gc::{GcManaged, GcMark, Trace};
+use crate::core::gc::{GcManaged, GcMark, Rt, Trace};
 use anyhow::{anyhow, Result};
 use std::{cell::Cell, fmt::Debug, fmt::Display, ops::Deref};
+use streaming_iterator::StreamingIterator;
 
 /// A lisp vector. Unlike vectors in other languages this is not resizeable.
 /// This type is represented as slice of [`ObjCell`] which is immutable by
@@ -111,6 +112,42 @@ impl LispVec {
     }
 }
 
+/// Iterates over the elements of a rooted vector without copying it. Each
+/// element is read when the iterator reaches it, so changes to the vector
+/// while iterating are seen.
+pub(crate) struct LispVecStreamIter<'rt> {
+    vector: &'rt Rt<&'static LispVec>,
+    elem: Option<Rt<GcObj<'static>>>,
+    idx: usize,
+}
+
+impl<'rt> Rt<&'static LispVec> {
+    #[allow(clippy::iter_not_returning_iterator)]
+    pub(crate) fn iter(&'rt self) -> LispVecStreamIter<'rt> {
+        LispVecStreamIter {
+            vector: self,
+            elem: None,
+            idx: 0,
+        }
+    }
+}
+
+impl StreamingIterator for LispVecStreamIter<'_> {
+    type Item = Rt<GcObj<'static>>;
+
+    fn advance(&mut self) {
+        unsafe {
+            let obj = self.vector.bind_unchecked().get(self.idx);
+            self.elem = obj.map(|x| Rt::new_unchecked(x.get().with_lifetime()));
+            self.idx += 1;
+        }
+    }
+
+    fn get(&self) -> Option<&Self::Item> {
+        self.elem.as_ref()
+    }
+}
+
 impl Deref for LispVec {
     type Target = [ObjCell];
 
@@ -3,8 +3,10 @@ use crate::core::{
     env::{sym, Env, Symbol, INTERNED_SYMBOLS},
     error::{Type, TypeError},
     gc::{Context, IntoRoot, Rt},
-    object::{nil, Gc, GcObj, List, Number, Object, SubrFn},
+    object::{nil, Function, Gc, GcObj, List, Number, Object, SubrFn},
 };
+use crate::root;
+use crate::declare::Declarations;
 use crate::hashmap::HashSet;
 use anyhow::{anyhow, Result};
 use fn_macros::defun;
@@ -28,13 +30,25 @@ pub(crate) fn fset<'ob>(symbol: Symbol<'ob>, definition: GcObj) -> Result<Symbol
     Ok(symbol)
 }
 
+/// Set the function definition of SYMBOL to DEFINITION, and record it in
+/// `current-load-list` as `(defun . SYMBOL)`. The `declare` form of
+/// DEFINITION is stored in the properties of SYMBOL.
 #[defun]
 pub(crate) fn defalias<'ob>(
     symbol: Symbol<'ob>,
-    definition: GcObj,
+    definition: GcObj<'ob>,
     _docstring: Option<&str>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
 ) -> Result<Symbol<'ob>> {
-    fset(symbol, definition)
+    let decls = Declarations::from_definition(definition)?;
+    fset(symbol, definition)?;
+    if let Some(decls) = decls {
+        decls.apply(symbol, env, cx);
+    }
+    let entry = cons!(sym::DEFUN, symbol; cx);
+    crate::lread::record_load_entry(entry, env, cx);
+    Ok(symbol)
 }
 
 #[defun]
@@ -165,9 +179,8 @@ pub(crate) fn numberp(object: GcObj) -> bool {
 }
 
 #[defun]
-pub(crate) fn markerp(_: GcObj) -> bool {
-    // TODO: implement
-    false
+pub(crate) fn markerp(object: GcObj) -> bool {
+    matches!(object.untag(), Object::Marker(_))
 }
 
 #[defun]
@@ -301,6 +314,11 @@ pub(crate) fn aset<'ob>(array: GcObj<'ob>, idx: usize, newlet: GcObj<'ob>) -> Re
                 Err(anyhow!("index {idx} is out of bounds. Length was {len}"))
             }
         }
+        Object::CharTable(table) => {
+            let chr = crate::chartab::char_code(idx as i64)?;
+            table.borrow_mut().entries.set(chr, newlet);
+            Ok(newlet)
+        }
         x => Err(TypeError::new(Type::Sequence, x).into()),
     }
 }
@@ -333,6 +351,7 @@ pub(crate) fn aref(array: GcObj, idx: usize) -> Result<GcObj> {
             Some(x) => Ok(x),
             None => Err(anyhow!("index {idx} is out of bounds")),
         },
+        Object::CharTable(table) => Ok(table.get(crate::chartab::char_code(idx as i64)?)),
         x => Err(TypeError::new(Type::Sequence, x).into()),
     }
 }
@@ -348,9 +367,13 @@ fn type_of(object: GcObj) -> GcObj {
         Object::Record(x) => x.get(0).expect("record was missing type").get(),
         Object::ByteFn(_) => sym::COMPILED_FUNCTION.into(),
         Object::HashTable(_) => sym::HASH_TABLE.into(),
+        Object::CharTable(_) => sym::CHAR_TABLE.into(),
         Object::String(_) => sym::STRING.into(),
         Object::SubrFn(_) => sym::SUBR.into(),
         Object::Buffer(_) => sym::BUFFER.into(),
+        Object::Overlay(_) => sym::OVERLAY.into(),
+        Object::Marker(_) => sym::MARKER.into(),
+        Object::Process(_) => sym::PROCESS.into(),
     }
 }
 
@@ -365,13 +388,43 @@ pub(crate) fn indirect_function<'ob>(object: GcObj<'ob>, cx: &'ob Context) -> Gc
     }
 }
 
+/// Announce that FEATURE is available, with SUBFEATURES as its
+/// `subfeatures` property. The functions that `eval-after-load` added to
+/// `after-load-alist` for FEATURE are called.
 #[defun]
-pub(crate) fn provide<'ob>(feature: Symbol<'ob>, _subfeatures: Option<&Cons>) -> Symbol<'ob> {
-    let mut features = FEATURES.lock().unwrap();
+pub(crate) fn provide<'ob>(
+    feature: &Rt<Gc<Symbol>>,
+    subfeatures: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Symbol<'ob>> {
+    let feat = feature.get(cx);
     // TODO: SYMBOL - need to trace this
-    let feat = unsafe { feature.into_root() };
-    features.insert(feat);
-    feature
+    FEATURES.lock().unwrap().insert(unsafe { feat.into_root() });
+    if let Some(subfeatures) = subfeatures {
+        put(feat, sym::SUBFEATURES, subfeatures.bind(cx), env);
+    }
+    crate::lread::record_load_entry(cons!(sym::PROVIDE, feat; cx), env, cx);
+    let alist = env
+        .vars
+        .get(sym::AFTER_LOAD_ALIST)
+        .map_or_else(nil, |x| x.bind(cx));
+    let mut funcs = Vec::new();
+    for elt in alist.as_list()? {
+        if let Object::Cons(elt) = elt?.untag() {
+            if elt.car() == feat {
+                funcs = elt.cdr().as_list()?.collect::<Result<_>>()?;
+                break;
+            }
+        }
+    }
+    root!(funcs, move(funcs), cx);
+    for func in funcs.iter() {
+        let func: &Rt<Gc<Function>> = func.try_into()?;
+        root!(args, Vec::new(), cx);
+        func.call(args, env, cx, None)?;
+    }
+    Ok(feature.get(cx))
 }
 
 #[defun]
@@ -438,10 +491,15 @@ mod test {
 }
 
 defsym!(MANY);
+defsym!(SUBFEATURES);
 defsym!(INTEGER);
 defsym!(SYMBOL);
 defsym!(COMPILED_FUNCTION);
 defsym!(HASH_TABLE);
+defsym!(CHAR_TABLE);
 defsym!(BUFFER);
+defsym!(OVERLAY);
+defsym!(MARKER);
+defsym!(PROCESS);
 defsym!(STRING);
 defsym!(SUBR);
@@ -0,0 +1,159 @@
+//! Declarations of functions and macros.
+//!
+//! A definition can start with `(declare SPECS...)`, after the doc string.
+//! When the definition is installed with `defalias`, the specs are stored as
+//! properties of the symbol, which is where the optimizer, the indentation
+//! code and the byte compiler's warnings look for them.
+//!
+//! | spec                     | property               |
+//! |--------------------------|------------------------|
+//! | `(indent N)`             | `lisp-indent-function` |
+//! | `(doc-string N)`         | `doc-string-elt`       |
+//! | `(obsolete NEW WHEN)`    | `byte-obsolete-info`   |
+//! | `(pure VAL)`             | `pure`                 |
+//! | `(side-effect-free VAL)` | `side-effect-free`     |
+//!
+//! Other specs are ignored.
+use crate::core::{
+    env::{sym, Env, Symbol},
+    gc::{Context, Rt},
+    object::{nil, GcObj, Object},
+};
+use anyhow::Result;
+
+defsym!(DECLARE);
+defsym!(INDENT);
+defsym!(DOC_STRING);
+defsym!(OBSOLETE);
+defsym!(LISP_INDENT_FUNCTION);
+defsym!(DOC_STRING_ELT);
+defsym!(BYTE_OBSOLETE_INFO);
+
+/// The specs of a `declare` form.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Declarations<'ob> {
+    pub(crate) indent: Option<GcObj<'ob>>,
+    /// The position of the doc string argument
+    pub(crate) doc_string: Option<GcObj<'ob>>,
+    /// The replacement and the version it was made obsolete in
+    pub(crate) obsolete: Option<(GcObj<'ob>, GcObj<'ob>)>,
+    pub(crate) pure: Option<GcObj<'ob>>,
+    pub(crate) side_effect_free: Option<GcObj<'ob>>,
+}
+
+impl<'ob> Declarations<'ob> {
+    /// Parse the SPECS of `(declare SPECS...)`.
+    pub(crate) fn parse(specs: GcObj<'ob>) -> Result<Self> {
+        let mut decls = Self::default();
+        for spec in specs.as_list()? {
+            let mut elems = spec?.as_list()?;
+            let Some(prop) = elems.next() else { continue };
+            let prop: Symbol = prop?.try_into()?;
+            let mut arg = || elems.next().transpose().map(|x| x.unwrap_or_else(nil));
+            match prop {
+                sym::INDENT => decls.indent = Some(arg()?),
+                sym::DOC_STRING => decls.doc_string = Some(arg()?),
+                sym::OBSOLETE => decls.obsolete = Some((arg()?, arg()?)),
+                sym::PURE => decls.pure = Some(arg()?),
+                sym::SIDE_EFFECT_FREE => decls.side_effect_free = Some(arg()?),
+                _ => {}
+            }
+        }
+        Ok(decls)
+    }
+
+    /// Parse the `declare` form at the start of BODY, if there is one.
+    pub(crate) fn from_body(body: GcObj<'ob>) -> Result<Option<Self>> {
+        let mut forms = body.as_list()?;
+        let mut form = forms.next().transpose()?;
+        // skip the doc string
+        if let Some(Object::String(_)) = form.map(GcObj::untag) {
+            form = forms.next().transpose()?;
+        }
+        match form.map(GcObj::untag) {
+            Some(Object::Cons(decl)) if decl.car() == sym::DECLARE => {
+                Self::parse(decl.cdr()).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse the declarations of DEFINITION, which is a lambda, a closure or a
+    /// macro made from one of those.
+    pub(crate) fn from_definition(definition: GcObj<'ob>) -> Result<Option<Self>> {
+        let Object::Cons(cons) = definition.untag() else {return Ok(None)};
+        // (lambda ARGS . BODY) or (closure ENV ARGS . BODY)
+        let skip = match cons.car() {
+            head if head == sym::MACRO => return Self::from_definition(cons.cdr()),
+            head if head == sym::LAMBDA => 1,
+            head if head == sym::CLOSURE => 2,
+            _ => return Ok(None),
+        };
+        let mut body = cons.cdr();
+        for _ in 0..skip {
+            match body.untag() {
+                Object::Cons(cons) => body = cons.cdr(),
+                _ => return Ok(None),
+            }
+        }
+        Self::from_body(body)
+    }
+
+    /// Store the declarations as properties of NAME.
+    pub(crate) fn apply(&self, name: Symbol, env: &mut Rt<Env>, cx: &'ob Context) {
+        if let Some(indent) = self.indent {
+            env.set_prop(name, sym::LISP_INDENT_FUNCTION, indent);
+        }
+        if let Some(pos) = self.doc_string {
+            env.set_prop(name, sym::DOC_STRING_ELT, pos);
+        }
+        if let Some((new, when)) = self.obsolete {
+            env.set_prop(name, sym::BYTE_OBSOLETE_INFO, list!(new, nil(), when; cx));
+        }
+        if let Some(pure) = self.pure {
+            env.set_prop(name, sym::PURE, pure);
+        }
+        if let Some(side_effect_free) = self.side_effect_free {
+            env.set_prop(name, sym::SIDE_EFFECT_FREE, side_effect_free);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_declare() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(defalias 'declare-test
+                      #'(lambda (x) \"doc\"
+                        (declare (indent 1) (doc-string 3) (obsolete other \"29.1\")
+                                 (pure t) (side-effect-free t) (unknown 1))
+                        x))";
+        check(form, env, cx);
+        let form = "(list (get 'declare-test 'lisp-indent-function)
+                          (get 'declare-test 'doc-string-elt)
+                          (get 'declare-test 'byte-obsolete-info)
+                          (get 'declare-test 'pure)
+                          (get 'declare-test 'side-effect-free))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(1 3 (other nil \"29.1\") t t)"
+        );
+        // the declare form does nothing when called
+        assert_eq!(check("(declare-test 5)", env, cx), 5);
+
+        let form = "(defalias 'declare-macro (cons 'macro #'(lambda (x) (declare (indent 0)) x)))";
+        check(form, env, cx);
+        assert_eq!(
+            check("(get 'declare-macro 'lisp-indent-function)", env, cx),
+            0
+        );
+    }
+}
@@ -0,0 +1,843 @@
+//! Cryptographic hash functions for `secure-hash`, `md5` and `buffer-hash`.
+//! The input is fed in pieces, so the text of a buffer can be hashed where it
+//! is instead of being copied out of the gap buffer first.
+use crate::coding::{coding_system_var, preferred_coding_system, CodingSystem};
+use crate::core::{
+    env::{sym, Env, Symbol},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{GcObj, Object},
+};
+use crate::fns::slice_into_list;
+use anyhow::{bail, Result};
+use bstr::ByteSlice;
+use fn_macros::defun;
+use std::fmt::Write;
+
+/// A hash algorithm supported by `secure-hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    const ALL: [(Algorithm, Symbol<'static>); 6] = [
+        (Algorithm::Md5, sym::MD5),
+        (Algorithm::Sha1, sym::SHA1),
+        (Algorithm::Sha224, sym::SHA224),
+        (Algorithm::Sha256, sym::SHA256),
+        (Algorithm::Sha384, sym::SHA384),
+        (Algorithm::Sha512, sym::SHA512),
+    ];
+
+    fn from_symbol(name: Symbol) -> Option<Self> {
+        Self::ALL.iter().find(|x| x.1 == name).map(|x| x.0)
+    }
+
+    /// The size of the hash in bytes.
+    fn size(self) -> usize {
+        match self {
+            Algorithm::Md5 => 16,
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha224 => 28,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha384 => 48,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    /// Hash the concatenation of `parts`.
+    pub(crate) fn digest<'a>(self, parts: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+        let mut hash = match self {
+            Algorithm::Md5 => run(Md5(MD5_INIT), parts),
+            Algorithm::Sha1 => run(Sha1(SHA1_INIT), parts),
+            Algorithm::Sha224 => run(Sha256(SHA224_INIT), parts),
+            Algorithm::Sha256 => run(Sha256(SHA256_INIT), parts),
+            Algorithm::Sha384 => run(Sha512(SHA384_INIT), parts),
+            Algorithm::Sha512 => run(Sha512(SHA512_INIT), parts),
+        };
+        hash.truncate(self.size());
+        hash
+    }
+}
+
+/// The compression function of a hash, which mixes one block of input into
+/// the state.
+trait Compress {
+    const BLOCK: usize;
+    /// MD5 stores the message length little endian, the SHA family big endian
+    const LITTLE_ENDIAN: bool = false;
+    fn compress(&mut self, block: &[u8]);
+    fn output(&self) -> Vec<u8>;
+}
+
+/// Feed `parts` through the compression function of `state` one block at a
+/// time, and then add the padding and the message length.
+fn run<'a, C: Compress>(mut state: C, parts: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut partial = Vec::with_capacity(C::BLOCK);
+    let mut len: u64 = 0;
+    let mut update = |state: &mut C, mut data: &[u8]| {
+        if !partial.is_empty() {
+            let take = (C::BLOCK - partial.len()).min(data.len());
+            partial.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if partial.len() < C::BLOCK {
+                return;
+            }
+            state.compress(&partial);
+            partial.clear();
+        }
+        let mut blocks = data.chunks_exact(C::BLOCK);
+        for block in &mut blocks {
+            state.compress(block);
+        }
+        partial.extend_from_slice(blocks.remainder());
+    };
+    for part in parts {
+        len += part.len() as u64;
+        update(&mut state, part);
+    }
+    // a 1 bit, zeros up to the length field, and the length in bits
+    let len_size = C::BLOCK / 8;
+    let used = (len as usize + 1) % C::BLOCK;
+    let zeros = (2 * C::BLOCK - len_size - used) % C::BLOCK;
+    let mut padding = vec![0x80];
+    padding.resize(1 + zeros, 0);
+    let bits = u128::from(len) * 8;
+    if C::LITTLE_ENDIAN {
+        padding.extend_from_slice(&bits.to_le_bytes()[..len_size]);
+    } else {
+        padding.extend_from_slice(&bits.to_be_bytes()[16 - len_size..]);
+    }
+    update(&mut state, &padding);
+    state.output()
+}
+
+const MD5_INIT: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+const MD5_K: [u32; 64] = [
+    0xd76a_a478,
+    0xe8c7_b756,
+    0x2420_70db,
+    0xc1bd_ceee,
+    0xf57c_0faf,
+    0x4787_c62a,
+    0xa830_4613,
+    0xfd46_9501,
+    0x6980_98d8,
+    0x8b44_f7af,
+    0xffff_5bb1,
+    0x895c_d7be,
+    0x6b90_1122,
+    0xfd98_7193,
+    0xa679_438e,
+    0x49b4_0821,
+    0xf61e_2562,
+    0xc040_b340,
+    0x265e_5a51,
+    0xe9b6_c7aa,
+    0xd62f_105d,
+    0x0244_1453,
+    0xd8a1_e681,
+    0xe7d3_fbc8,
+    0x21e1_cde6,
+    0xc337_07d6,
+    0xf4d5_0d87,
+    0x455a_14ed,
+    0xa9e3_e905,
+    0xfcef_a3f8,
+    0x676f_02d9,
+    0x8d2a_4c8a,
+    0xfffa_3942,
+    0x8771_f681,
+    0x6d9d_6122,
+    0xfde5_380c,
+    0xa4be_ea44,
+    0x4bde_cfa9,
+    0xf6bb_4b60,
+    0xbebf_bc70,
+    0x289b_7ec6,
+    0xeaa1_27fa,
+    0xd4ef_3085,
+    0x0488_1d05,
+    0xd9d4_d039,
+    0xe6db_99e5,
+    0x1fa2_7cf8,
+    0xc4ac_5665,
+    0xf429_2244,
+    0x432a_ff97,
+    0xab94_23a7,
+    0xfc93_a039,
+    0x655b_59c3,
+    0x8f0c_cc92,
+    0xffef_f47d,
+    0x8584_5dd1,
+    0x6fa8_7e4f,
+    0xfe2c_e6e0,
+    0xa301_4314,
+    0x4e08_11a1,
+    0xf753_7e82,
+    0xbd3a_f235,
+    0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+struct Md5([u32; 4]);
+
+impl Compress for Md5 {
+    const BLOCK: usize = 64;
+    const LITTLE_ENDIAN: bool = true;
+
+    fn compress(&mut self, block: &[u8]) {
+        let words: Vec<u32> = block
+            .as_chunks()
+            .0
+            .iter()
+            .map(|x| u32::from_le_bytes(*x))
+            .collect();
+        let mut state = self.0;
+        for (round, constant) in MD5_K.iter().enumerate() {
+            let [_, b, c, d] = state;
+            let (mix, idx) = match round / 16 {
+                0 => ((b & c) | (!b & d), round),
+                1 => ((d & b) | (!d & c), (5 * round + 1) % 16),
+                2 => (b ^ c ^ d, (3 * round + 5) % 16),
+                _ => (c ^ (b | !d), (7 * round) % 16),
+            };
+            let shift = MD5_SHIFTS[(round / 16) * 4 + round % 4];
+            let sum = state[0]
+                .wrapping_add(mix)
+                .wrapping_add(*constant)
+                .wrapping_add(words[idx]);
+            let new = b.wrapping_add(sum.rotate_left(shift));
+            state.rotate_right(1);
+            state[1] = new;
+        }
+        for (x, y) in self.0.iter_mut().zip(state) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+const SHA1_INIT: [u32; 5] = [
+    0x6745_2301,
+    0xefcd_ab89,
+    0x98ba_dcfe,
+    0x1032_5476,
+    0xc3d2_e1f0,
+];
+
+struct Sha1([u32; 5]);
+
+impl Compress for Sha1 {
+    const BLOCK: usize = 64;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut words = [0; 80];
+        for (word, bytes) in words.iter_mut().zip(block.as_chunks().0) {
+            *word = u32::from_be_bytes(*bytes);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let mut state = self.0;
+        for (round, word) in words.iter().enumerate() {
+            let [_, b, c, d, _] = state;
+            let (mix, constant) = match round / 20 {
+                0 => ((b & c) | (!b & d), 0x5a82_7999),
+                1 => (b ^ c ^ d, 0x6ed9_eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = state[0]
+                .rotate_left(5)
+                .wrapping_add(mix)
+                .wrapping_add(state[4])
+                .wrapping_add(constant)
+                .wrapping_add(*word);
+            state.rotate_right(1);
+            state[2] = state[2].rotate_left(30);
+            state[0] = temp;
+        }
+        for (x, y) in self.0.iter_mut().zip(state) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+const SHA224_INIT: [u32; 8] = [
+    0xc105_9ed8,
+    0x367c_d507,
+    0x3070_dd17,
+    0xf70e_5939,
+    0xffc0_0b31,
+    0x6858_1511,
+    0x64f9_8fa7,
+    0xbefa_4fa4,
+];
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+/// SHA-256, and SHA-224 which only differs in the initial state and the
+/// length of the output
+struct Sha256([u32; 8]);
+
+impl Compress for Sha256 {
+    const BLOCK: usize = 64;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut words = [0u32; 64];
+        for (word, bytes) in words.iter_mut().zip(block.as_chunks().0) {
+            *word = u32::from_be_bytes(*bytes);
+        }
+        for i in 16..64 {
+            let (w15, w2) = (words[i - 15], words[i - 2]);
+            let s0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+            let s1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+            words[i] = words[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(words[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut state = self.0;
+        for (constant, word) in SHA256_K.iter().zip(words) {
+            let (a, e) = (state[0], state[4]);
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & state[5]) ^ (!e & state[6]);
+            let t1 = state[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & state[1]) ^ (a & state[2]) ^ (state[1] & state[2]);
+            state.rotate_right(1);
+            state[4] = state[4].wrapping_add(t1);
+            state[0] = t1.wrapping_add(s0.wrapping_add(maj));
+        }
+        for (x, y) in self.0.iter_mut().zip(state) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+const SHA384_INIT: [u64; 8] = [
+    0xcbbb_9d5d_c105_9ed8,
+    0x629a_292a_367c_d507,
+    0x9159_015a_3070_dd17,
+    0x152f_ecd8_f70e_5939,
+    0x6733_2667_ffc0_0b31,
+    0x8eb4_4a87_6858_1511,
+    0xdb0c_2e0d_64f9_8fa7,
+    0x47b5_481d_befa_4fa4,
+];
+
+const SHA512_INIT: [u64; 8] = [
+    0x6a09_e667_f3bc_c908,
+    0xbb67_ae85_84ca_a73b,
+    0x3c6e_f372_fe94_f82b,
+    0xa54f_f53a_5f1d_36f1,
+    0x510e_527f_ade6_82d1,
+    0x9b05_688c_2b3e_6c1f,
+    0x1f83_d9ab_fb41_bd6b,
+    0x5be0_cd19_137e_2179,
+];
+
+const SHA512_K: [u64; 80] = [
+    0x428a_2f98_d728_ae22,
+    0x7137_4491_23ef_65cd,
+    0xb5c0_fbcf_ec4d_3b2f,
+    0xe9b5_dba5_8189_dbbc,
+    0x3956_c25b_f348_b538,
+    0x59f1_11f1_b605_d019,
+    0x923f_82a4_af19_4f9b,
+    0xab1c_5ed5_da6d_8118,
+    0xd807_aa98_a303_0242,
+    0x1283_5b01_4570_6fbe,
+    0x2431_85be_4ee4_b28c,
+    0x550c_7dc3_d5ff_b4e2,
+    0x72be_5d74_f27b_896f,
+    0x80de_b1fe_3b16_96b1,
+    0x9bdc_06a7_25c7_1235,
+    0xc19b_f174_cf69_2694,
+    0xe49b_69c1_9ef1_4ad2,
+    0xefbe_4786_384f_25e3,
+    0x0fc1_9dc6_8b8c_d5b5,
+    0x240c_a1cc_77ac_9c65,
+    0x2de9_2c6f_592b_0275,
+    0x4a74_84aa_6ea6_e483,
+    0x5cb0_a9dc_bd41_fbd4,
+    0x76f9_88da_8311_53b5,
+    0x983e_5152_ee66_dfab,
+    0xa831_c66d_2db4_3210,
+    0xb003_27c8_98fb_213f,
+    0xbf59_7fc7_beef_0ee4,
+    0xc6e0_0bf3_3da8_8fc2,
+    0xd5a7_9147_930a_a725,
+    0x06ca_6351_e003_826f,
+    0x1429_2967_0a0e_6e70,
+    0x27b7_0a85_46d2_2ffc,
+    0x2e1b_2138_5c26_c926,
+    0x4d2c_6dfc_5ac4_2aed,
+    0x5338_0d13_9d95_b3df,
+    0x650a_7354_8baf_63de,
+    0x766a_0abb_3c77_b2a8,
+    0x81c2_c92e_47ed_aee6,
+    0x9272_2c85_1482_353b,
+    0xa2bf_e8a1_4cf1_0364,
+    0xa81a_664b_bc42_3001,
+    0xc24b_8b70_d0f8_9791,
+    0xc76c_51a3_0654_be30,
+    0xd192_e819_d6ef_5218,
+    0xd699_0624_5565_a910,
+    0xf40e_3585_5771_202a,
+    0x106a_a070_32bb_d1b8,
+    0x19a4_c116_b8d2_d0c8,
+    0x1e37_6c08_5141_ab53,
+    0x2748_774c_df8e_eb99,
+    0x34b0_bcb5_e19b_48a8,
+    0x391c_0cb3_c5c9_5a63,
+    0x4ed8_aa4a_e341_8acb,
+    0x5b9c_ca4f_7763_e373,
+    0x682e_6ff3_d6b2_b8a3,
+    0x748f_82ee_5def_b2fc,
+    0x78a5_636f_4317_2f60,
+    0x84c8_7814_a1f0_ab72,
+    0x8cc7_0208_1a64_39ec,
+    0x90be_fffa_2363_1e28,
+    0xa450_6ceb_de82_bde9,
+    0xbef9_a3f7_b2c6_7915,
+    0xc671_78f2_e372_532b,
+    0xca27_3ece_ea26_619c,
+    0xd186_b8c7_21c0_c207,
+    0xeada_7dd6_cde0_eb1e,
+    0xf57d_4f7f_ee6e_d178,
+    0x06f0_67aa_7217_6fba,
+    0x0a63_7dc5_a2c8_98a6,
+    0x113f_9804_bef9_0dae,
+    0x1b71_0b35_131c_471b,
+    0x28db_77f5_2304_7d84,
+    0x32ca_ab7b_40c7_2493,
+    0x3c9e_be0a_15c9_bebc,
+    0x431d_67c4_9c10_0d4c,
+    0x4cc5_d4be_cb3e_42b6,
+    0x597f_299c_fc65_7e2a,
+    0x5fcb_6fab_3ad6_faec,
+    0x6c44_198c_4a47_5817,
+];
+
+/// SHA-512, and SHA-384 which only differs in the initial state and the
+/// length of the output
+struct Sha512([u64; 8]);
+
+impl Compress for Sha512 {
+    const BLOCK: usize = 128;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut words = [0u64; 80];
+        for (word, bytes) in words.iter_mut().zip(block.as_chunks().0) {
+            *word = u64::from_be_bytes(*bytes);
+        }
+        for i in 16..80 {
+            let (w15, w2) = (words[i - 15], words[i - 2]);
+            let s0 = w15.rotate_right(1) ^ w15.rotate_right(8) ^ (w15 >> 7);
+            let s1 = w2.rotate_right(19) ^ w2.rotate_right(61) ^ (w2 >> 6);
+            words[i] = words[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(words[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut state = self.0;
+        for (constant, word) in SHA512_K.iter().zip(words) {
+            let (a, e) = (state[0], state[4]);
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & state[5]) ^ (!e & state[6]);
+            let t1 = state[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & state[1]) ^ (a & state[2]) ^ (state[1] & state[2]);
+            state.rotate_right(1);
+            state[4] = state[4].wrapping_add(t1);
+            state[0] = t1.wrapping_add(s0.wrapping_add(maj));
+        }
+        for (x, y) in self.0.iter_mut().zip(state) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+fn to_hex(hash: &[u8]) -> String {
+    let mut hex = String::with_capacity(hash.len() * 2);
+    for byte in hash {
+        write!(hex, "{byte:02x}").unwrap();
+    }
+    hex
+}
+
+/// Resolve START and END, which are character indices that can be negative
+/// to count from the end, to a byte range of `string`.
+fn string_range(string: &[u8], start: Option<i64>, end: Option<i64>) -> Result<(usize, usize)> {
+    let offsets: Vec<usize> = string
+        .char_indices()
+        .map(|x| x.0)
+        .chain(std::iter::once(string.len()))
+        .collect();
+    let chars = offsets.len() as i64 - 1;
+    let resolve = |idx: i64| if idx < 0 { idx + chars } else { idx };
+    let (beg, end) = (resolve(start.unwrap_or(0)), resolve(end.unwrap_or(chars)));
+    if !(0 <= beg && beg <= end && end <= chars) {
+        bail!("Args out of range: {start:?}, {end:?}");
+    }
+    Ok((offsets[beg as usize], offsets[end as usize]))
+}
+
+/// Return the list of algorithms that `secure-hash` supports.
+#[defun]
+fn secure_hash_algorithms<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    let names: Vec<GcObj> = Algorithm::ALL.iter().map(|x| x.1.into()).collect();
+    slice_into_list(&names, None, cx)
+}
+
+/// The coding system to encode the text of a string or buffer in before
+/// hashing it: `coding_system`, or else `coding-system-for-write`, or for a
+/// buffer its `buffer-file-coding-system`, or else the most preferred one.
+/// A coding system that does not exist is an error, unless `noerror` is
+/// set, in which case the text is hashed as `raw-text`.
+fn hash_coding(
+    coding_system: Option<Symbol>,
+    noerror: bool,
+    buffer: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<CodingSystem> {
+    if let Some(coding) = coding_system {
+        return match CodingSystem::from_symbol(coding, env, cx) {
+            Err(_) if noerror => Ok(CodingSystem::get("raw-text").unwrap()),
+            coding => coding,
+        };
+    }
+    let coding = coding_system_var(sym::CODING_SYSTEM_FOR_WRITE, env, cx)
+        .or_else(|| match buffer {
+            true => coding_system_var(sym::BUFFER_FILE_CODING_SYSTEM, env, cx),
+            false => None,
+        })
+        .unwrap_or_else(preferred_coding_system);
+    Ok(coding)
+}
+
+/// Hash `parts` of text encoded in `coding`. The text is only copied if the
+/// coding system changes its bytes.
+fn hash_text(algorithm: Algorithm, parts: &[&str], coding: CodingSystem) -> Vec<u8> {
+    match coding.keeps_utf8() {
+        true => algorithm.digest(parts.iter().map(|x| x.as_bytes())),
+        false => algorithm.digest([coding.encode(&parts.concat()).as_slice()]),
+    }
+}
+
+/// The hash of OBJECT, a string or a buffer, with `algorithm`, as described
+/// in `secure-hash` and `md5`.
+#[cfg_attr(not(feature = "buffers"), allow(unused_variables))]
+#[allow(clippy::too_many_arguments)]
+fn hash_object(
+    algorithm: Algorithm,
+    object: GcObj,
+    start: Option<i64>,
+    end: Option<i64>,
+    coding_system: Option<Symbol>,
+    noerror: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Vec<u8>> {
+    match object.untag() {
+        Object::String(string) => {
+            let (beg, end) = string_range(string, start, end)?;
+            let text = &string[beg..end];
+            // a unibyte string is already bytes
+            if !string.is_multibyte() {
+                return Ok(algorithm.digest([text.as_bytes()]));
+            }
+            let coding = hash_coding(coding_system, noerror, false, env, cx)?;
+            Ok(hash_text(algorithm, &[text.to_str()?], coding))
+        }
+        #[cfg(feature = "buffers")]
+        Object::Buffer(buffer) => {
+            let coding = hash_coding(coding_system, noerror, true, env, cx)?;
+            let data = buffer.lock()?;
+            let bounds = (data.begv(), data.zv());
+            let start = start.map_or(bounds.0 + 1, |x| x as usize);
+            let end = end.map_or(bounds.1 + 1, |x| x as usize);
+            let (beg, end) = crate::editfns::validate_region(start, end, bounds, env, cx)?;
+            let (before, after) = data.text.slice(beg, end);
+            Ok(hash_text(algorithm, &[before, after], coding))
+        }
+        _ => bail!(TypeError::new(Type::BufferOrString, object)),
+    }
+}
+
+/// Return the hash of OBJECT, a string or a buffer, with ALGORITHM. For a
+/// string, START and END are character indices, and for a buffer they are
+/// positions that default to the accessible portion. Multibyte text is
+/// hashed as it is encoded by `coding-system-for-write`, or for a buffer its
+/// `buffer-file-coding-system`, or else the most preferred coding system.
+/// The hash is returned as a hex string, or as a unibyte string of its bytes
+/// if BINARY is non-nil.
+#[defun]
+fn secure_hash<'ob>(
+    algorithm: Symbol,
+    object: GcObj,
+    start: Option<i64>,
+    end: Option<i64>,
+    binary: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let Some(algorithm) = Algorithm::from_symbol(algorithm) else {bail!("Invalid algorithm arg: {algorithm}")};
+    let hash = hash_object(algorithm, object, start, end, None, false, env, cx)?;
+    Ok(match binary {
+        Some(()) => cx.add(hash),
+        None => cx.add(to_hex(&hash)),
+    })
+}
+
+/// Return the MD5 hash of OBJECT, a string or a buffer, as a hex string.
+/// START and END select part of OBJECT as in `secure-hash`. Multibyte text
+/// is encoded in CODING-SYSTEM before it is hashed, or when that is nil in
+/// the coding system that `secure-hash` uses. If NOERROR is non-nil, a
+/// CODING-SYSTEM that does not exist is replaced by `raw-text` instead of
+/// signaling an error.
+#[defun]
+fn md5(
+    object: GcObj,
+    start: Option<i64>,
+    end: Option<i64>,
+    coding_system: Option<Symbol>,
+    noerror: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    let hash = hash_object(
+        Algorithm::Md5,
+        object,
+        start,
+        end,
+        coding_system,
+        noerror.is_some(),
+        env,
+        cx,
+    )?;
+    Ok(to_hex(&hash))
+}
+
+/// Return a hash of the whole text of BUFFER-OR-NAME, or the current buffer,
+/// ignoring any narrowing. Buffers with the same text have the same hash.
+#[cfg(feature = "buffers")]
+#[defun]
+fn buffer_hash(buffer_or_name: Option<GcObj>, env: &mut Rt<Env>) -> Result<String> {
+    let buffer = match buffer_or_name {
+        Some(x) => crate::buffer::get_live_buffer(x)?,
+        None => crate::buffer::get_current_buffer(env),
+    };
+    let data = buffer.lock()?;
+    let (before, after) = data.text.slice(0, data.text.len_chars());
+    Ok(to_hex(
+        &Algorithm::Sha1.digest([before.as_bytes(), after.as_bytes()]),
+    ))
+}
+
+defsym!(SHA1);
+defsym!(SHA224);
+defsym!(SHA256);
+defsym!(SHA384);
+defsym!(SHA512);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader, root};
+
+    fn hex(algorithm: Algorithm, parts: &[&str]) -> String {
+        to_hex(&algorithm.digest(parts.iter().map(|x| x.as_bytes())))
+    }
+
+    #[test]
+    fn test_digests() {
+        let cases = [
+            (Algorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (Algorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                Algorithm::Sha224,
+                "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7",
+            ),
+            (
+                Algorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                Algorithm::Sha384,
+                "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7",
+            ),
+            (
+                Algorithm::Sha512,
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+        ];
+        for (algorithm, expect) in cases {
+            assert_eq!(hex(algorithm, &["abc"]), expect, "{algorithm:?}");
+            // the result does not depend on how the input is split
+            assert_eq!(hex(algorithm, &["a", "", "bc"]), expect, "{algorithm:?}");
+        }
+        assert_eq!(hex(Algorithm::Md5, &[]), "d41d8cd98f00b204e9800998ecf8427e");
+        // input that spans several blocks
+        let long = "x".repeat(1000);
+        let (front, back) = long.split_at(333);
+        assert_eq!(
+            hex(Algorithm::Sha256, &[front, back]),
+            hex(Algorithm::Sha256, &[&long])
+        );
+        assert_eq!(
+            hex(Algorithm::Sha1, &[&long]),
+            "c3efa690fa3fdd2e2526853eed670538ea127638"
+        );
+        assert_eq!(
+            hex(Algorithm::Sha512, &[&long]),
+            "ae13575c5d98bfa689617bb19f0f55efdd52b39397fd620bcd1fbc03fda979e6b69bfba24698176eafe766d31c48b70273b03198064323082e04cc4eb9126310"
+        );
+    }
+
+    #[test]
+    fn test_md5() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).map(|x| x.to_string())
+        };
+        let md5 = |x: &str| format!("\"{x}\"");
+        assert_eq!(
+            eval("(md5 \"abc\")").unwrap(),
+            md5("900150983cd24fb0d6963f7d28e17f72")
+        );
+        assert_eq!(
+            eval("(md5 \"abc\" 1)").unwrap(),
+            md5("5360af35bde9ebd8f01f492dc059593c")
+        );
+        assert_eq!(
+            eval("(md5 \"é\")").unwrap(),
+            md5("66ddcd97cfdeabb2f6fb8a999b4bc76f")
+        );
+        let form = "(md5 \"é\" nil nil 'utf-16le)";
+        assert_eq!(eval(form).unwrap(), md5("ed71e8ffd3d8c47c1a2e22c53cd384aa"));
+        let form = "(let ((coding-system-for-write 'iso-latin-1)) (md5 \"é\"))";
+        assert_eq!(eval(form).unwrap(), md5("3406877694691ddd1dfb0aca54681407"));
+        assert!(eval("(md5 \"abc\" nil nil 'rune-no-such-coding)").is_err());
+        let form = "(md5 \"abc\" nil nil 'rune-no-such-coding t)";
+        assert_eq!(eval(form).unwrap(), md5("900150983cd24fb0d6963f7d28e17f72"));
+        let form = "(equal (md5 \"abc\") (secure-hash 'md5 \"abc\"))";
+        assert_eq!(eval(form).unwrap(), "t");
+    }
+}
@@ -0,0 +1,315 @@
+//! Directory listings and file attributes.
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Rt},
+    object::{nil, Gc, GcObj, LispString},
+};
+use crate::fileio::{
+    call_file_handler, expand, file_io_error, file_name, name_matches, HandlerArg,
+};
+use crate::fns::slice_into_list;
+use crate::timefns::lisp_time;
+use anyhow::Result;
+use fn_macros::defun;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The name of the user or group with ID `id` in the database `file`, like
+/// /etc/passwd.
+fn id_name(file: &str, id: u32) -> Option<String> {
+    let contents = fs::read_to_string(file).ok()?;
+    contents.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let found = fields.nth(1)?.parse::<u32>().ok()? == id;
+        found.then(|| name.to_owned())
+    })
+}
+
+/// The user or group ID `id` as a name if `names` is true and it has one, or
+/// otherwise as a number.
+fn lisp_id<'ob>(file: &str, id: u32, names: bool, cx: &'ob Context) -> GcObj<'ob> {
+    match id_name(file, id) {
+        Some(name) if names => cx.add(name),
+        _ => i64::from(id).into(),
+    }
+}
+
+/// The mode of a file in the format of `ls -l`, like "drwxr-xr-x".
+pub(crate) fn mode_string(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+    let kinds = [
+        (file_type.is_dir(), 'd'),
+        (file_type.is_symlink(), 'l'),
+        (file_type.is_block_device(), 'b'),
+        (file_type.is_char_device(), 'c'),
+        (file_type.is_fifo(), 'p'),
+        (file_type.is_socket(), 's'),
+    ];
+    let kind = kinds.iter().find(|x| x.0).map_or('-', |x| x.1);
+    let mode = metadata.permissions().mode();
+    let mut string = String::from(kind);
+    for (shift, special, set_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        string.push(if bits & 4 != 0 { 'r' } else { '-' });
+        string.push(if bits & 2 != 0 { 'w' } else { '-' });
+        string.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => set_char,
+            (false, true) => set_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    string
+}
+
+/// The time a file was last changed, from the seconds and nanoseconds since
+/// the epoch.
+fn change_time(metadata: &Metadata) -> SystemTime {
+    let (secs, nanos) = (metadata.ctime(), metadata.ctime_nsec());
+    let nanos = Duration::from_nanos(nanos.try_into().unwrap_or_default());
+    match u64::try_from(secs) {
+        Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+        Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nanos,
+    }
+}
+
+/// The attribute list of `file`, or nil if it does not exist.
+fn attributes<'ob>(file: &str, names: bool, cx: &'ob Context) -> GcObj<'ob> {
+    let Ok(metadata) = fs::symlink_metadata(file) else {return nil()};
+    let file_type: GcObj = if metadata.is_dir() {
+        sym::TRUE.into()
+    } else if metadata.file_type().is_symlink() {
+        match fs::read_link(file) {
+            Ok(target) => cx.add(target.to_string_lossy().into_owned()),
+            Err(_) => nil(),
+        }
+    } else {
+        nil()
+    };
+    let time =
+        |time: std::io::Result<SystemTime>| time.map_or_else(|_| 0.into(), |x| lisp_time(x, cx));
+    let attrs = [
+        file_type,
+        (metadata.nlink() as i64).into(),
+        lisp_id("/etc/passwd", metadata.uid(), names, cx),
+        lisp_id("/etc/group", metadata.gid(), names, cx),
+        time(metadata.accessed()),
+        time(metadata.modified()),
+        lisp_time(change_time(&metadata), cx),
+        (metadata.size() as i64).into(),
+        cx.add(mode_string(&metadata)),
+        sym::TRUE.into(),
+        (metadata.ino() as i64).into(),
+        (metadata.dev() as i64).into(),
+    ];
+    slice_into_list(&attrs, None, cx)
+}
+
+/// Return a list of the attributes of file FILENAME, or nil if it does not
+/// exist. The attributes are:
+///  0. t for a directory, the target name for a symbolic link, else nil
+///  1. the number of links
+///  2. the user ID of the owner
+///  3. the group ID
+///  4. the time of the last access
+///  5. the time of the last modification
+///  6. the time of the last status change
+///  7. the size in bytes
+///  8. the mode as a string, like "-rw-r--r--"
+///  9. t, for compatibility
+/// 10. the inode number
+/// 11. the device number
+///
+/// The IDs are names if ID-FORMAT is `string` and numbers otherwise. A
+/// symbolic link is not followed.
+#[defun]
+fn file_attributes<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    id_format: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [HandlerArg::File(&file), HandlerArg::Obj(id_format)];
+    if let Some(x) = call_file_handler(sym::FILE_ATTRIBUTES, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let names = id_format.is_some_and(|x| x.bind(cx) == sym::STRING);
+    Ok(attributes(&file, names, cx))
+}
+
+/// The names of the files in DIRECTORY that match MATCH, and the absolute
+/// name of the directory.
+fn list_directory(
+    dir: String,
+    regexp: Option<&str>,
+    nosort: bool,
+    count: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(String, Vec<String>)> {
+    let entries = match fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(e) => return Err(file_io_error(&e, "Opening directory", &dir, env, cx)),
+    };
+    let mut names = vec![".".to_owned(), "..".to_owned()];
+    for entry in entries {
+        let entry = entry.map_err(|e| file_io_error(&e, "Reading directory", &dir, env, cx))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    let mut matching = Vec::new();
+    for name in names {
+        if count.is_some_and(|x| matching.len() >= x) {
+            break;
+        }
+        match regexp {
+            Some(regexp) if !name_matches(regexp, &name, env, cx)? => {}
+            _ => matching.push(name),
+        }
+    }
+    if !nosort {
+        matching.sort_unstable();
+    }
+    Ok((dir, matching))
+}
+
+/// `name` in `dir` if `full` is true, and otherwise `name` itself.
+fn entry_name(dir: &str, name: &str, full: bool) -> String {
+    match full {
+        true if dir.ends_with('/') => format!("{dir}{name}"),
+        true => format!("{dir}/{name}"),
+        false => name.to_owned(),
+    }
+}
+
+/// Return a list of the names of the files in DIRECTORY, including "." and
+/// "..". If FULL is non-nil, the names are absolute. If MATCH is non-nil,
+/// only names that match that regexp are returned. The names are sorted
+/// with `string<` unless NOSORT is non-nil. If COUNT is a number, at most
+/// that many names are returned.
+#[defun]
+fn directory_files<'ob>(
+    directory: &Rt<Gc<&LispString>>,
+    full: Option<&Rt<GcObj>>,
+    regexp: Option<&Rt<Gc<&LispString>>>,
+    nosort: Option<&Rt<GcObj>>,
+    count: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let dir = expand(directory, env, cx)?;
+    let args = [
+        HandlerArg::File(&dir),
+        HandlerArg::Obj(full),
+        HandlerArg::Obj(regexp.map(Rt::use_as)),
+        HandlerArg::Obj(nosort),
+        HandlerArg::Obj(count),
+    ];
+    if let Some(x) = call_file_handler(sym::DIRECTORY_FILES, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let regexp = regexp.map(|x| file_name(x, cx)).transpose()?;
+    let count = count.map(|x| x.bind(cx).try_into()).transpose()?;
+    let (dir, names) = list_directory(dir, regexp.as_deref(), nosort.is_some(), count, env, cx)?;
+    let names: Vec<GcObj> = names
+        .iter()
+        .map(|x| cx.add(entry_name(&dir, x, full.is_some())))
+        .collect();
+    Ok(slice_into_list(&names, None, cx))
+}
+
+/// Return a list of the files in DIRECTORY and their attributes, each as a
+/// cons of the name and the list that `file-attributes` returns with
+/// ID-FORMAT. FULL, MATCH, NOSORT and COUNT are as in `directory-files`.
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn directory_files_and_attributes<'ob>(
+    directory: &Rt<Gc<&LispString>>,
+    full: Option<&Rt<GcObj>>,
+    regexp: Option<&Rt<Gc<&LispString>>>,
+    nosort: Option<&Rt<GcObj>>,
+    id_format: Option<&Rt<GcObj>>,
+    count: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let dir = expand(directory, env, cx)?;
+    let args = [
+        HandlerArg::File(&dir),
+        HandlerArg::Obj(full),
+        HandlerArg::Obj(regexp.map(Rt::use_as)),
+        HandlerArg::Obj(nosort),
+        HandlerArg::Obj(id_format),
+        HandlerArg::Obj(count),
+    ];
+    let operation = sym::DIRECTORY_FILES_AND_ATTRIBUTES;
+    if let Some(x) = call_file_handler(operation, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let regexp = regexp.map(|x| file_name(x, cx)).transpose()?;
+    let count = count.map(|x| x.bind(cx).try_into()).transpose()?;
+    let (dir, names) = list_directory(dir, regexp.as_deref(), nosort.is_some(), count, env, cx)?;
+    let id_names = id_format.is_some_and(|x| x.bind(cx) == sym::STRING);
+    let entries: Vec<GcObj> = names
+        .iter()
+        .map(|name| {
+            let attrs = attributes(&entry_name(&dir, name, true), id_names, cx);
+            cons!(entry_name(&dir, name, full.is_some()), attrs; cx)
+        })
+        .collect();
+    Ok(slice_into_list(&entries, None, cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_directory_files() {
+        use crate::core::gc::RootSet;
+        use crate::{interpreter, reader, root};
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-dired-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "hello").unwrap();
+        fs::write(dir.join("a.el"), "").unwrap();
+        let dir = dir.to_string_lossy();
+        let form = format!(
+            r#"(list (directory-files "{dir}")
+                     (directory-files "{dir}" nil "\\.txt\\'")
+                     (directory-files "{dir}" t "^a")
+                     (length (directory-files "{dir}" nil nil t 2))
+                     (let ((attrs (file-attributes "{dir}/b.txt")))
+                       (list (nth 0 attrs) (nth 7 attrs) (substring (nth 8 attrs) 0 1)))
+                     (car (file-attributes "{dir}/sub"))
+                     (file-attributes "{dir}/missing")
+                     (mapcar #'car (directory-files-and-attributes "{dir}" nil "^b")))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let _ = fs::remove_dir_all(&*dir);
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "((\".\" \"..\" \"a.el\" \"b.txt\" \"sub\") (\"b.txt\") (\"{dir}/a.el\") 2 \
+                 (nil 5 \"-\") t nil (\"b.txt\"))"
+            )
+        );
+    }
+
+    #[test]
+    fn test_mode_string() {
+        let file = std::env::temp_dir().join(format!("rune-mode-{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o4754)).unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        let _ = fs::remove_file(&file);
+        assert_eq!(mode_string(&metadata), "-rwsr-xr--");
+    }
+}
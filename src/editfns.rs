@@ -1,10 +1,23 @@
 use crate::core::object::{GcObj, Object};
+#[cfg(feature = "buffers")]
+use crate::{
+    buffer::{self, get_current_buffer},
+    character::int_to_char,
+    coding::{CodingSystem, Eol, Kind},
+    core::env::{sym, Env, Symbol},
+    core::error::EvalError,
+    core::error::{Type, TypeError},
+    core::gc::{Context, IntoRoot, Rt},
+    core::object::{nil, Buffer, Function, Gc, LispString},
+    root,
+    textprop::{self, InsertProps},
+};
 use anyhow::{bail, ensure, Result};
 use fn_macros::defun;
 use std::{fmt::Write as _, io::Write};
 
 #[defun]
-fn message(format_string: &str, args: &[GcObj]) -> Result<String> {
+pub(crate) fn message(format_string: &str, args: &[GcObj]) -> Result<String> {
     let message = format(format_string, args)?;
     println!("MESSAGE: {message}");
     std::io::stdout().flush()?;
@@ -34,7 +47,7 @@ fn format(string: &str, objects: &[GcObj]) -> Result<String> {
     };
     while let Some(start) = remaining.find(&mut is_format_char) {
         result.push_str(&remaining[..start]);
-        let Some(specifier) = remaining.as_bytes().get(start+1) else {bail!("Format string ends in middle of format specifier")};
+        let Some(specifier) = remaining.as_bytes().get(start + 1) else {bail!("Format string ends in middle of format specifier")};
         // "%%" inserts a single "%" in the output
         if *specifier == b'%' {
             result.push('%');
@@ -57,7 +70,7 @@ fn format(string: &str, objects: &[GcObj]) -> Result<String> {
 }
 
 #[defun]
-fn format_message(string: &str, objects: &[GcObj]) -> Result<String> {
+pub(crate) fn format_message(string: &str, objects: &[GcObj]) -> Result<String> {
     let formatted = format(string, objects)?;
     // TODO: implement support for `text-quoting-style`.
     Ok(formatted
@@ -66,9 +79,729 @@ fn format_message(string: &str, objects: &[GcObj]) -> Result<String> {
         .collect())
 }
 
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn point(env: &mut Rt<Env>) -> Result<usize> {
+    Ok(get_current_buffer(env).lock()?.point() + 1)
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn goto_char(position: usize, env: &mut Rt<Env>) -> Result<usize> {
+    get_current_buffer(env)
+        .lock()?
+        .goto_char(position.saturating_sub(1));
+    Ok(position)
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn point_min(env: &mut Rt<Env>) -> Result<usize> {
+    Ok(get_current_buffer(env).lock()?.begv() + 1)
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn point_max(env: &mut Rt<Env>) -> Result<usize> {
+    Ok(get_current_buffer(env).lock()?.zv() + 1)
+}
+
+/// Return the number of characters in BUFFER, or the current buffer if nil,
+/// including those outside of its restriction.
+#[cfg(feature = "buffers")]
+#[defun]
+fn buffer_size(buffer: Option<GcObj>, env: &mut Rt<Env>) -> Result<usize> {
+    let buffer = match buffer {
+        Some(x) => buffer::get_live_buffer(x)?,
+        None => get_current_buffer(env),
+    };
+    Ok(buffer.lock()?.text.len_chars())
+}
+
+/// Convert the region from `start` to `end` to 0-based positions in order.
+/// Signals `args-out-of-range` if either end is outside of the 0-based bounds
+/// `min` and `max`.
+#[cfg(feature = "buffers")]
+pub(crate) fn validate_region(
+    start: usize,
+    end: usize,
+    (min, max): (usize, usize),
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(usize, usize)> {
+    let in_range = |x: usize| (min + 1..=max + 1).contains(&x);
+    if in_range(start) && in_range(end) {
+        Ok((start.min(end) - 1, start.max(end) - 1))
+    } else {
+        let data = list![start as i64, end as i64; cx];
+        Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into())
+    }
+}
+
+/// Restrict editing in the current buffer to the text between START and END.
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn narrow_to_region(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let mut data = buffer.lock()?;
+    let (start, end) = validate_region(start, end, (0, data.text.len_chars()), env, cx)?;
+    data.narrow(start, end);
+    Ok(false)
+}
+
+/// Remove the restriction from the current buffer, up to the innermost
+/// labeled restriction.
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn widen(env: &mut Rt<Env>) -> Result<bool> {
+    get_current_buffer(env).lock()?.widen();
+    Ok(false)
+}
+
+/// Return non-nil if the current buffer is narrowed.
+#[cfg(feature = "buffers")]
+#[defun]
+fn buffer_narrowed_p(env: &mut Rt<Env>) -> Result<bool> {
+    Ok(get_current_buffer(env).lock()?.is_narrowed())
+}
+
+/// Narrow to START and END, and prevent `widen` from removing the restriction
+/// until `internal--labeled-widen` is called with LABEL.
+#[cfg(feature = "buffers")]
+#[defun]
+#[allow(non_snake_case)]
+fn internal__labeled_narrow_to_region(
+    start: usize,
+    end: usize,
+    label: Symbol,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let mut data = buffer.lock()?;
+    let (start, end) = validate_region(start, end, (0, data.text.len_chars()), env, cx)?;
+    // TODO: SYMBOL - need to trace this
+    data.narrow_labeled(start, end, unsafe { label.into_root() });
+    Ok(false)
+}
+
+/// Remove the innermost labeled restriction if it has LABEL, then widen.
+#[cfg(feature = "buffers")]
+#[defun]
+#[allow(non_snake_case)]
+fn internal__labeled_widen(label: Symbol, env: &mut Rt<Env>) -> Result<bool> {
+    get_current_buffer(env).lock()?.widen_labeled(label);
+    Ok(false)
+}
+
+/// The text of the strings or characters in ARGS. The bytes of unibyte
+/// strings are converted to the characters with the same code, since raw
+/// bytes cannot be stored in a buffer.
+#[cfg(feature = "buffers")]
+fn insert_args(args: &[Rt<GcObj>], cx: &Context) -> Result<String> {
+    let mut text = String::new();
+    for arg in args {
+        match arg.get(cx) {
+            Object::String(string) if !string.is_multibyte() => {
+                text.extend(string.iter().copied().map(char::from));
+            }
+            Object::String(string) => text.push_str(string.try_into()?),
+            Object::Int(chr) => text.push(int_to_char(chr)?),
+            x => bail!(TypeError::new(Type::String, x)),
+        }
+    }
+    Ok(text)
+}
+
+/// Insert the strings or characters in ARGS at point.
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn insert(args: &[Rt<GcObj>], env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let text = insert_args(args, cx)?;
+    buffer::insert_text(&text, env, cx)?;
+    Ok(false)
+}
+
+/// Insert the strings or characters in ARGS at point, inheriting the sticky
+/// text properties of the text around it.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_and_inherit(args: &[Rt<GcObj>], env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let text = insert_args(args, cx)?;
+    buffer::insert_text_with(&text, InsertProps::Inherit, env, cx)?;
+    Ok(false)
+}
+
+/// Insert the strings or characters in ARGS at point, before the markers
+/// at point, so that they all end up after the text.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_before_markers(args: &[Rt<GcObj>], env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let text = insert_args(args, cx)?;
+    buffer::insert_text_before_markers(&text, InsertProps::None, env, cx)?;
+    Ok(false)
+}
+
+/// Insert ARGS like `insert-before-markers`, inheriting the sticky text
+/// properties of the text around it.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_before_markers_and_inherit(
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let text = insert_args(args, cx)?;
+    buffer::insert_text_before_markers(&text, InsertProps::Inherit, env, cx)?;
+    Ok(false)
+}
+
+/// Insert COUNT copies of CHARACTER at point. COUNT defaults to 1, and
+/// nothing is inserted if it is not positive. If INHERIT is non-nil, the
+/// inserted text inherits the sticky properties of the text around it.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_char(
+    character: i64,
+    count: Option<i64>,
+    inherit: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let chr = int_to_char(character)?;
+    let count = usize::try_from(count.unwrap_or(1)).unwrap_or(0);
+    if count > 0 {
+        let text: String = std::iter::repeat_n(chr, count).collect();
+        let props = if inherit.is_some() {
+            InsertProps::Inherit
+        } else {
+            InsertProps::None
+        };
+        buffer::insert_text_with(&text, props, env, cx)?;
+    }
+    Ok(false)
+}
+
+/// Insert the text of BUFFER between START and END at point, along with its
+/// text properties. START and END default to the accessible portion of
+/// BUFFER.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_buffer_substring(
+    buffer: &Rt<GcObj>,
+    start: Option<usize>,
+    end: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let source = buffer::get_live_buffer(buffer.bind(cx))?;
+    let (bounds, start, end) = {
+        let data = source.lock()?;
+        let (begv, zv) = (data.begv(), data.zv());
+        let start = start.unwrap_or(begv + 1);
+        ((begv, zv), start, end.unwrap_or(zv + 1))
+    };
+    let (beg, end) = validate_region(start, end, bounds, env, cx)?;
+    let text = source.lock()?.text.substring(beg, end);
+    buffer::insert_text_with(&text, InsertProps::Copy(source, beg), env, cx)?;
+    Ok(false)
+}
+
+/// Convert the region from START to END of the current buffer to 0-based
+/// positions in order. See `validate_region`.
+#[cfg(feature = "buffers")]
+fn current_region(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(usize, usize)> {
+    let bounds = {
+        let data = get_current_buffer(env).lock()?;
+        (data.begv(), data.zv())
+    };
+    validate_region(start, end, bounds, env, cx)
+}
+
+/// The text between the 0-based positions `beg` and `end` of the current
+/// buffer as a string, with its text properties if PROPS is true.
+#[cfg(feature = "buffers")]
+fn buffer_text<'ob>(
+    beg: usize,
+    end: usize,
+    props: bool,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let buffer = get_current_buffer(env);
+    let string: Gc<&LispString> = cx.add_as(buffer.lock()?.text.substring(beg, end));
+    if props {
+        let props = textprop::buffer_props(buffer, beg, end, env, cx);
+        string.untag().set_props(props)?;
+    }
+    Ok(string.into())
+}
+
+/// Return the text between START and END of the current buffer as a string,
+/// along with its text properties.
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn buffer_substring<'ob>(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end) = current_region(start, end, env, cx)?;
+    buffer_text(beg, end, true, env, cx)
+}
+
+/// Return the text between START and END of the current buffer as a string,
+/// without its text properties.
+#[cfg(feature = "buffers")]
+#[defun]
+fn buffer_substring_no_properties<'ob>(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (beg, end) = current_region(start, end, env, cx)?;
+    buffer_text(beg, end, false, env, cx)
+}
+
+/// Return the accessible portion of the current buffer as a string, along
+/// with its text properties.
+#[cfg(feature = "buffers")]
+#[defun]
+fn buffer_string<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let (beg, end) = {
+        let data = get_current_buffer(env).lock()?;
+        (data.begv(), data.zv())
+    };
+    buffer_text(beg, end, true, env, cx)
+}
+
+/// Return the text between BEG and END of the current buffer, as filtered by
+/// `filter-buffer-substring-function`. If DELETE is non-nil the text is also
+/// deleted. Commands that copy text out of a buffer use this instead of
+/// `buffer-substring` so that modes can change what gets copied.
+#[cfg(feature = "buffers")]
+#[defun]
+fn filter_buffer_substring<'ob>(
+    beg: usize,
+    end: usize,
+    delete: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let func = match env.vars.get(sym::FILTER_BUFFER_SUBSTRING_FUNCTION) {
+        Some(func) => func.bind(cx),
+        None => sym::BUFFER_SUBSTRING__FILTER.into(),
+    };
+    let func: Gc<Function> = func.try_into()?;
+    root!(func, cx);
+    let args: Vec<GcObj> = vec![beg.into(), end.into(), delete.is_some().into()];
+    root!(args, cx);
+    // the filter runs inside an implicit `save-excursion`
+    let buffer = get_current_buffer(env);
+    let marker = {
+        let mut data = buffer.lock()?;
+        let point = data.point();
+        data.make_marker(point)
+    };
+    let restore = |env: &mut Rt<Env>, cx: &Context| {
+        if let Ok(mut data) = buffer.lock() {
+            let point = data.marker_position(marker);
+            data.goto_char(point);
+            data.free_marker(marker);
+            drop(data);
+            buffer::set_current_buffer(buffer, env, cx);
+        }
+    };
+    match func.call(args, env, cx, None) {
+        Ok(x) => {
+            root!(x, cx);
+            restore(env, cx);
+            Ok(x.bind(cx))
+        }
+        Err(e) => {
+            restore(env, cx);
+            Err(e.into())
+        }
+    }
+}
+
+/// The default `filter-buffer-substring-function`. Returns the text between
+/// BEG and END with its properties, deleting it if DELETE is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+#[allow(non_snake_case)]
+fn buffer_substring__filter<'ob>(
+    beg: usize,
+    end: usize,
+    delete: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    match delete {
+        Some(()) => delete_and_extract_region(beg, end, env, cx),
+        None => buffer_substring(beg, end, env, cx),
+    }
+}
+
+/// Delete the text between START and END.
+#[cfg(feature = "buffers")]
+#[defun]
+pub(crate) fn delete_region(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let (start, end) = current_region(start, end, env, cx)?;
+    buffer::delete_text(start, end, env, cx)?;
+    Ok(false)
+}
+
+/// Delete the N characters after point, or before point if N is negative.
+/// If KILLFLAG is non-nil, the text is saved in the kill ring with
+/// `kill-forward-chars`.
+#[cfg(feature = "buffers")]
+#[defun]
+fn delete_char(n: i64, killflag: Option<()>, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    if killflag.is_some() {
+        let func: Gc<Function> = GcObj::from(sym::KILL_FORWARD_CHARS).try_into()?;
+        root!(func, cx);
+        let args: Vec<GcObj> = vec![n.into()];
+        root!(args, cx);
+        func.call(args, env, cx, None)?;
+        return Ok(false);
+    }
+    let (point, begv, zv) = {
+        let data = get_current_buffer(env).lock()?;
+        (data.point(), data.begv(), data.zv())
+    };
+    let count = n.unsigned_abs() as usize;
+    let (beg, end) = if n < 0 {
+        if count > point - begv {
+            return Err(EvalError::signal(sym::BEGINNING_OF_BUFFER.into(), nil(), env).into());
+        }
+        (point - count, point)
+    } else {
+        if count > zv - point {
+            return Err(EvalError::signal(sym::END_OF_BUFFER.into(), nil(), env).into());
+        }
+        (point, point + count)
+    };
+    buffer::delete_text(beg, end, env, cx)?;
+    Ok(false)
+}
+
+/// Delete the text between START and END and return it, along with its text
+/// properties.
+#[cfg(feature = "buffers")]
+#[defun]
+fn delete_and_extract_region<'ob>(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (start, end) = current_region(start, end, env, cx)?;
+    let text = buffer_text(start, end, true, env, cx)?;
+    root!(text, cx);
+    if start < end {
+        buffer::delete_text(start, end, env, cx)?;
+    }
+    Ok(text.bind(cx))
+}
+
+/// Delete the entire contents of the current buffer. Any restriction is
+/// removed, so the buffer is empty afterward.
+#[cfg(feature = "buffers")]
+#[defun]
+fn erase_buffer(env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let len = {
+        let mut data = get_current_buffer(env).lock()?;
+        data.remove_labeled_restrictions();
+        data.widen();
+        data.text.len_chars()
+    };
+    buffer::delete_text(0, len, env, cx)?;
+    Ok(false)
+}
+
+/// Swap the text between STARTR1 and ENDR1 with the text between STARTR2
+/// and ENDR2. The regions may not overlap. Markers move with the text they
+/// are in unless LEAVE-MARKERS is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+fn transpose_regions(
+    startr1: usize,
+    endr1: usize,
+    startr2: usize,
+    endr2: usize,
+    leave_markers: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let bounds = {
+        let data = get_current_buffer(env).lock()?;
+        (data.begv(), data.zv())
+    };
+    let region1 = validate_region(startr1, endr1, bounds, env, cx)?;
+    let region2 = validate_region(startr2, endr2, bounds, env, cx)?;
+    let (region1, region2) = if region2.0 < region1.0 {
+        (region2, region1)
+    } else {
+        (region1, region2)
+    };
+    ensure!(region1.1 <= region2.0, "Transposed regions overlap");
+    buffer::transpose_text(region1, region2, leave_markers.is_some(), env, cx)?;
+    Ok(false)
+}
+
+/// Resolve BUFFER, or the current buffer if nil, and the region from START
+/// to END in it, which defaults to the accessible portion.
+#[cfg(feature = "buffers")]
+fn buffer_region(
+    buffer: Option<GcObj>,
+    start: Option<usize>,
+    end: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(&'static Buffer, (usize, usize))> {
+    let buffer = match buffer {
+        Some(x) => buffer::get_live_buffer(x)?,
+        None => get_current_buffer(env),
+    };
+    let bounds = {
+        let data = buffer.lock()?;
+        (data.begv(), data.zv())
+    };
+    let start = start.unwrap_or(bounds.0 + 1);
+    let end = end.unwrap_or(bounds.1 + 1);
+    Ok((buffer, validate_region(start, end, bounds, env, cx)?))
+}
+
+/// Compare the text between START1 and END1 of BUFFER1 with the text between
+/// START2 and END2 of BUFFER2. A nil buffer is the current buffer and nil
+/// positions are the bounds of the accessible portion. Case is ignored if
+/// `case-fold-search` is non-nil. Returns 0 if the texts are equal, or else
+/// -N if the first differing character is at position N of the first text
+/// and is less than the one in the second text, or N if it is greater. If
+/// one text is a prefix of the other, N is one more than the length of the
+/// shorter text, which is the lesser one.
+#[cfg(feature = "buffers")]
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn compare_buffer_substrings(
+    buffer1: Option<GcObj>,
+    start1: Option<usize>,
+    end1: Option<usize>,
+    buffer2: Option<GcObj>,
+    start2: Option<usize>,
+    end2: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    let (buffer1, region1) = buffer_region(buffer1, start1, end1, env, cx)?;
+    let (buffer2, region2) = buffer_region(buffer2, start2, end2, env, cx)?;
+    let fold_case = env
+        .vars
+        .get(sym::CASE_FOLD_SEARCH)
+        .is_some_and(|x| !x.bind(cx).nil());
+    let fold = |chr: char| {
+        if fold_case {
+            chr.to_lowercase().next().unwrap_or(chr)
+        } else {
+            chr
+        }
+    };
+    // buffers that share text also share the lock
+    let data1 = buffer1.lock()?;
+    let data2 = if buffer1.text_owner() == buffer2.text_owner() {
+        None
+    } else {
+        Some(buffer2.lock()?)
+    };
+    let text1 = data1.text.slice(region1.0, region1.1);
+    let text2 = data2
+        .as_ref()
+        .unwrap_or(&data1)
+        .text
+        .slice(region2.0, region2.1);
+    let mut text1 = text1.0.chars().chain(text1.1.chars()).map(fold);
+    let mut text2 = text2.0.chars().chain(text2.1.chars()).map(fold);
+    let mut pos = 1;
+    loop {
+        match (text1.next(), text2.next()) {
+            (Some(chr1), Some(chr2)) if chr1 == chr2 => pos += 1,
+            (Some(chr1), Some(chr2)) => return Ok(if chr1 < chr2 { -pos } else { pos }),
+            (None, Some(_)) => return Ok(-pos),
+            (Some(_), None) => return Ok(pos),
+            (None, None) => return Ok(0),
+        }
+    }
+}
+
+/// Return the byte position of the character at POSITION in the current
+/// buffer, or nil if POSITION is out of range.
+#[cfg(feature = "buffers")]
+#[defun]
+fn position_bytes<'ob>(position: i64, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let data = get_current_buffer(env).lock()?;
+    let pos = usize::try_from(position).unwrap_or(0);
+    if !(1..=data.text.len_chars() + 1).contains(&pos) {
+        return Ok(nil());
+    }
+    Ok((data.text.char_to_byte(pos - 1) + 1).into())
+}
+
+/// Return the position of the character at byte position BYTEPOS in the
+/// current buffer. If BYTEPOS is in the middle of a multibyte character,
+/// that character's position is returned. Returns nil if BYTEPOS is out of
+/// range.
+#[cfg(feature = "buffers")]
+#[defun]
+fn byte_to_position<'ob>(bytepos: i64, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let data = get_current_buffer(env).lock()?;
+    let pos = usize::try_from(bytepos).unwrap_or(0);
+    if !(1..=data.text.len() + 1).contains(&pos) {
+        return Ok(nil());
+    }
+    let byte = data.text.floor_char_boundary(pos - 1);
+    Ok((data.text.byte_to_char(byte) + 1).into())
+}
+
+/// Return the file offset of POSITION in the current buffer if it was saved
+/// with CODING-SYSTEM, which defaults to `utf-8`. The offset is always exact,
+/// so QUALITY is ignored.
+#[cfg(feature = "buffers")]
+#[defun]
+fn bufferpos_to_filepos(
+    position: usize,
+    _quality: Option<GcObj>,
+    coding_system: Option<Symbol>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let buffer = get_current_buffer(env);
+    let len = buffer.lock()?.text.len_chars();
+    let (pos, _) = validate_region(position, position, (0, len), env, cx)?;
+    let name = coding_system.map(|x| x.name().to_owned());
+    let name = name.as_deref().unwrap_or("utf-8");
+    let Some(coding) = CodingSystem::get(name) else {bail!("Unsupported coding system: {name}")};
+    let data = buffer.lock()?;
+    let offset = match coding.kind {
+        Kind::Utf8 | Kind::Undecided => data.text.char_to_byte(pos) + usize::from(coding.bom) * 3,
+        Kind::Latin1 | Kind::Ascii => pos,
+        Kind::RawText | Kind::Utf16 { .. } | Kind::Utf32 { .. } => {
+            return Ok(coding.encode(&data.text.substring(0, pos)).len());
+        }
+    };
+    if coding.eol != Eol::Dos {
+        return Ok(offset);
+    }
+    // each newline is saved as CRLF
+    let (before, after) = data.text.slice(0, pos);
+    Ok(offset
+        + bytecount::count(before.as_bytes(), b'\n')
+        + bytecount::count(after.as_bytes(), b'\n'))
+}
+
+/// Return the positions of point and mark in order.
+#[cfg(feature = "buffers")]
+fn region(env: &mut Rt<Env>) -> Result<(usize, usize)> {
+    let buffer = get_current_buffer(env).lock()?;
+    let Some(mark) = buffer.mark() else {bail!("The mark is not set now, so there is no region")};
+    let point = buffer.point();
+    Ok((point.min(mark) + 1, point.max(mark) + 1))
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+fn region_beginning(env: &mut Rt<Env>) -> Result<usize> {
+    Ok(region(env)?.0)
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+fn region_end(env: &mut Rt<Env>) -> Result<usize> {
+    Ok(region(env)?.1)
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+fn mark<'ob>(_force: Option<()>, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let mark = get_current_buffer(env).lock()?.mark();
+    Ok(mark.map_or_else(nil, |x| (x + 1).into()))
+}
+
+#[cfg(feature = "buffers")]
+#[defun]
+fn set_mark<'ob>(pos: Option<usize>, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    get_current_buffer(env)
+        .lock()?
+        .set_mark(pos.map(|x| x.saturating_sub(1)));
+    Ok(pos.map_or_else(nil, Into::into))
+}
+
+/// Push the mark onto the mark ring and set it to LOCATION, or point if
+/// LOCATION is nil.
+#[cfg(feature = "buffers")]
+#[defun]
+fn push_mark(
+    location: Option<usize>,
+    nomsg: Option<()>,
+    _activate: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let max = match env.vars.get(sym::MARK_RING_MAX).map(|x| x.get(cx)) {
+        Some(Object::Int(max)) => usize::try_from(max).unwrap_or_default(),
+        _ => 16,
+    };
+    {
+        let mut buffer = get_current_buffer(env).lock()?;
+        let pos = location.map_or_else(|| buffer.point(), |x| x.saturating_sub(1));
+        buffer.push_mark(pos, max);
+    }
+    if nomsg.is_none() {
+        message("Mark set", &[])?;
+    }
+    Ok(false)
+}
+
+/// Set the mark to the most recent entry of the mark ring, and rotate the
+/// old mark to the end of the ring.
+#[cfg(feature = "buffers")]
+#[defun]
+fn pop_mark(env: &mut Rt<Env>) -> Result<bool> {
+    get_current_buffer(env).lock()?.pop_mark();
+    Ok(false)
+}
+
+defvar!(MARK_RING_MAX, 16);
+defvar!(
+    FILTER_BUFFER_SUBSTRING_FUNCTION,
+    sym::BUFFER_SUBSTRING__FILTER
+);
+
+defsym!(KILL_FORWARD_CHARS);
+defsym!(BEGINNING_OF_BUFFER);
+defsym!(END_OF_BUFFER);
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::core::env::sym;
 
     #[test]
     fn test_format() {
@@ -80,7 +813,7 @@ mod test {
             &format("foo-%s %s", &[3.into(), 4.into()]).unwrap(),
             "foo-3 4"
         );
-        let sym = crate::core::env::sym::FUNCTION.into();
+        let sym = sym::FUNCTION.into();
         assert_eq!(&format("%s", &[sym]).unwrap(), "function");
 
         assert!(&format("%s", &[]).is_err());
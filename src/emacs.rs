@@ -1,12 +1,33 @@
+use crate::core::env::Env;
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{GcObj, Object};
 use fn_macros::defun;
+use std::io::Write;
 
+/// Exit the interpreter after running `kill-emacs-hook` and killing the
+/// processes. ARG is the exit status if it is an integer, and otherwise the
+/// status is 0. RESTART is ignored.
 #[defun]
-fn kill_emacs() -> bool {
-    false
+fn kill_emacs(
+    arg: Option<&Rt<GcObj>>,
+    restart: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> bool {
+    let _ = restart;
+    let code = match arg.map(|x| x.bind(cx).untag()) {
+        Some(Object::Int(code)) => i32::try_from(code).unwrap_or(i32::MAX),
+        _ => 0,
+    };
+    if let Err(e) = crate::runtime::clean_up(env, cx) {
+        println!("Error: {e}");
+    }
+    _ = std::io::stdout().flush();
+    std::process::exit(code)
 }
 
 defvar!(EMACS_VERSION, "27.1");
-defvar!(SYSTEM_TYPE, "darwin");
+defvar!(SYSTEM_TYPE, crate::sysdep::system_type(cx));
 defvar!(DUMP_MODE);
 defvar!(COMMAND_LINE_ARGS, list![""]);
 defvar!(DEFAULT_DIRECTORY, "");
@@ -1,7 +1,8 @@
+use crate::core::cons::Cons;
 use crate::core::env::{sym, Env, Symbol};
 use crate::core::error::{EvalError, Type, TypeError};
 use crate::core::gc::Rt;
-use crate::core::object::{nil, LispString, Object};
+use crate::core::object::{nil, LispString, ObjCell, Object};
 use crate::core::{
     gc::{Context, IntoRoot},
     object::{Function, Gc, GcObj},
@@ -25,9 +26,15 @@ pub(crate) fn apply<'ob>(
             let end = len - 1;
             let last = &arguments[end];
             let mut args: Vec<_> = arguments[..end].iter().map(|x| x.bind(cx)).collect();
-            for element in last.bind(cx).as_list()? {
-                let e = cx.bind(element?);
-                args.push(e);
+            match last.bind(cx).untag() {
+                // spread vectors directly instead of converting them to a list
+                Object::Vec(vec) => args.extend(vec.iter().map(ObjCell::get)),
+                _ => {
+                    for element in last.bind(cx).as_list()? {
+                        let e = cx.bind(element?);
+                        args.push(e);
+                    }
+                }
             }
             args
         }
@@ -84,6 +91,82 @@ fn run_hooks<'ob>(
     Ok(nil())
 }
 
+/// Call the functions in HOOK with ARGS until `stop` is true for the value of
+/// one, and return that value. The value of HOOK is either a single function
+/// or a list of them, where `t` is skipped.
+fn run_hook_until<'ob>(
+    hook: &Rt<GcObj>,
+    args: &[Rt<GcObj>],
+    stop: impl Fn(GcObj) -> bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Option<GcObj<'ob>>> {
+    let value = match hook.get(cx) {
+        Object::Symbol(sym) => match env.vars.get(sym) {
+            Some(value) => value.bind(cx),
+            None => return Ok(None),
+        },
+        x => bail!(TypeError::new(Type::Symbol, x)),
+    };
+    let funcs = match value.untag() {
+        Object::NIL => return Ok(None),
+        Object::Cons(cons) if cons.car() != sym::LAMBDA => {
+            let funcs: Vec<_> = cons.elements().collect::<Result<_>>()?;
+            funcs.into_iter().filter(|x| *x != sym::TRUE).collect()
+        }
+        _ => vec![value],
+    };
+    root!(funcs, move(funcs), cx);
+    for func in funcs.iter() {
+        let func: &Rt<Gc<Function>> = func.try_into()?;
+        let args = Rt::bind_slice(args, cx).to_vec();
+        root!(args, move(args), cx);
+        let result = func.call(args, env, cx, None)?;
+        if stop(result) {
+            return Ok(Some(rebind!(result, cx)));
+        }
+    }
+    Ok(None)
+}
+
+/// Call each function in HOOK with ARGS. The value of HOOK is either a single
+/// function or a list of them, where `t` is skipped.
+#[defun]
+pub(crate) fn run_hook_with_args<'ob>(
+    hook: &Rt<GcObj>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    run_hook_until(hook, args, |_| false, env, cx)?;
+    Ok(nil())
+}
+
+/// Call each function in HOOK with ARGS until one returns nil. Return nil if
+/// one did, or t otherwise.
+#[defun]
+pub(crate) fn run_hook_with_args_until_failure(
+    hook: &Rt<GcObj>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    Ok(run_hook_until(hook, args, |x| x.nil(), env, cx)?.is_none())
+}
+
+/// Call each function in HOOK with ARGS until one returns non-nil, and return
+/// that value. Return nil if none did.
+#[defun]
+fn run_hook_with_args_until_success<'ob>(
+    hook: &Rt<GcObj>,
+    args: &[Rt<GcObj>],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let value = run_hook_until(hook, args, |x| !x.nil(), env, cx)?;
+    Ok(value.unwrap_or_else(nil))
+}
+
 #[defun]
 pub(crate) fn autoload_do_load<'ob>(
     fundef: &Rt<GcObj>,
@@ -113,7 +196,7 @@ pub(crate) fn autoload_do_load<'ob>(
                 "autoload arguments are not yet implemented"
             );
             root!(file, cx);
-            crate::lread::load(file, None, None, cx, env)?;
+            crate::lread::load(file, None, None, None, None, cx, env)?;
             match funname {
                 Some(func) => match func.get(cx).func(cx) {
                     Some(x) => Ok(x.into()),
@@ -143,6 +226,95 @@ fn autoload<'ob>(
     }
 }
 
+/// Return t if FUNCTION can be called interactively. Strings and vectors are
+/// keyboard macros and count as commands.
+#[defun]
+pub(crate) fn commandp(
+    function: GcObj,
+    _for_call_interactively: Option<GcObj>,
+    cx: &Context,
+) -> bool {
+    let function = match function.untag() {
+        Object::Symbol(sym) => match sym.follow_indirect(cx) {
+            Some(func) => func.into(),
+            None => return false,
+        },
+        _ => function,
+    };
+    match function.untag() {
+        Object::String(_) | Object::Vec(_) => true,
+        // (autoload FILE DOC INTERACTIVE TYPE)
+        Object::Cons(cons) if cons.car() == sym::AUTOLOAD => cons
+            .elements()
+            .nth(3)
+            .is_some_and(|x| x.is_ok_and(|x| !x.nil())),
+        Object::Cons(cons) => interactive_spec(cons).is_some(),
+        // TODO: byte-code functions don't keep their interactive spec yet
+        _ => false,
+    }
+}
+
+/// The `(interactive ...)` form at the start of the body of the lambda or
+/// closure `function`, after its docstring and declarations.
+fn interactive_spec(function: &Cons) -> Option<&Cons> {
+    let mut elems = function.elements().map_while(Result::ok);
+    let car = elems.next()?;
+    // skip the environment of a closure and the arguments
+    let skip = match car {
+        x if x == sym::LAMBDA => 1,
+        x if x == sym::CLOSURE => 2,
+        _ => return None,
+    };
+    let mut body = elems.skip(skip).skip_while(|x| {
+        let is_decl = matches!(x.untag(), Object::Cons(c) if c.car() == sym!(declare));
+        matches!(x.untag(), Object::String(_)) || is_decl
+    });
+    match body.next()?.untag() {
+        Object::Cons(form) if form.car() == sym::INTERACTIVE => Some(form),
+        _ => None,
+    }
+}
+
+/// The lambda or closure that `function` is, or that the symbol `function`
+/// is defined as.
+fn function_cons<'ob>(function: GcObj<'ob>, cx: &'ob Context) -> Option<&'ob Cons> {
+    let function = match function.untag() {
+        Object::Symbol(sym) => sym.follow_indirect(cx)?.into(),
+        _ => function,
+    };
+    match function.untag() {
+        Object::Cons(cons) => Some(cons),
+        _ => None,
+    }
+}
+
+/// Return the interactive form of FUNCTION, or nil if it isn't a command. An
+/// oclosure with no form of its own gets one from `oclosure-interactive-form`
+/// when that is defined.
+#[defun]
+fn interactive_form<'ob>(
+    function: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let is_oclosure = function_cons(function.bind(cx), cx).is_some_and(|x| {
+        interactive_spec(x).is_none() && crate::oclosure::closure_type(x).is_some()
+    });
+    if is_oclosure {
+        let Some(generic) = sym::OCLOSURE_INTERACTIVE_FORM.follow_indirect(cx) else {return Ok(nil())};
+        let func: GcObj = function_cons(function.bind(cx), cx).unwrap().into();
+        root!(args, move(vec![func]), cx);
+        root!(generic, cx);
+        return Ok(generic.call(args, env, cx, Some("oclosure-interactive-form"))?);
+    }
+    let Some(spec) = function_cons(function.bind(cx), cx).and_then(interactive_spec) else {return Ok(nil())};
+    // (interactive SPEC MODES...) is reduced to (interactive SPEC)
+    Ok(match spec.cdr().untag() {
+        Object::Cons(args) if !args.cdr().nil() => list![sym::INTERACTIVE, args.car(); cx],
+        _ => spec.into(),
+    })
+}
+
 #[defun]
 pub(crate) fn macroexpand<'ob>(
     form: &Rt<GcObj>,
@@ -241,7 +413,20 @@ defsym!(AND_REST, "&rest");
 defsym!(LAMBDA);
 defsym!(CLOSURE);
 defsym!(CONDITION_CASE);
+defsym!(HANDLER_BIND);
+defsym!(IGNORE_ERRORS);
+defsym!(IGNORE_ERROR);
+defsym!(WITH_DEMOTED_ERRORS);
+defsym!(CL_ASSERT);
+defsym!(CL_ASSERTION_FAILED);
+defsym!(BENCHMARK_RUN);
+defsym!(BENCHMARK_RUN_COMPILED);
 defsym!(UNWIND_PROTECT);
+defsym!(SAVE_EXCURSION);
+defsym!(SAVE_CURRENT_BUFFER);
+defsym!(SAVE_RESTRICTION);
+defsym!(WITH_SILENT_MODIFICATIONS);
+defsym!(ATOMIC_CHANGE_GROUP);
 defsym!(WHILE);
 defsym!(INLINE);
 defsym!(PROGN);
@@ -259,6 +444,9 @@ defsym!(INTERACTIVE);
 defsym!(CATCH);
 defsym!(THROW);
 defsym!(ERROR);
+defsym!(ERROR_CONDITIONS);
+defsym!(ARGS_OUT_OF_RANGE);
 defsym!(DEBUG);
 
 defvar!(DEBUG_ON_ERROR, false);
+defvar!(DEBUGGER);
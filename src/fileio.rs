@@ -1,44 +1,1584 @@
 use crate::core::{
-    env::{sym, Env},
+    env::{sym, Env, Symbol},
+    error::EvalError,
     gc::{Context, Rt},
-    object::Object,
+    object::{nil, Function, Gc, GcObj, LispString, Object},
+};
+use crate::fns::slice_into_list;
+use crate::root;
+#[cfg(feature = "buffers")]
+use crate::timefns::{decode_system_time, lisp_time};
+#[cfg(feature = "buffers")]
+use crate::{
+    buffer::{buffer_var, get_current_buffer, set_buffer_var},
+    coding::{coding_system_for_write, decode_file_contents, set_last_coding_system_used},
+    core::object::{Buffer, Modtime},
 };
 use anyhow::Result;
 use fn_macros::defun;
-use std::path::Path;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, path::Path};
+#[cfg(feature = "buffers")]
+use std::{
+    fs::OpenOptions,
+    io::{Seek, SeekFrom, Write},
+};
 
-#[defun]
+/// Whether `chr` separates the components of a file name.
+fn is_dir_sep(chr: char) -> bool {
+    chr == '/' || (cfg!(windows) && chr == '\\')
+}
+
+/// Split the drive letter of the file name `name`, like "c:", from the rest
+/// of it.
+#[cfg(windows)]
+fn split_drive(name: &str) -> (&str, &str) {
+    match name.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => name.split_at(2),
+        _ => ("", name),
+    }
+}
+
+#[cfg(not(windows))]
+fn split_drive(name: &str) -> (&str, &str) {
+    ("", name)
+}
+
+/// Whether the file name `name` starts at the root of its drive.
+fn is_absolute(name: &str) -> bool {
+    split_drive(name).1.starts_with(is_dir_sep)
+}
+
+/// The home directory of the user named `user`, from /etc/passwd.
+fn user_home(user: &str) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() > 5 && fields[0] == user).then(|| fields[5].to_owned())
+    })
+}
+
+/// `name` with a leading `~` or `~user` replaced by that home directory, or
+/// `None` if it does not start with one that exists.
+fn expand_home(name: &str) -> Option<String> {
+    let rest = name.strip_prefix('~')?;
+    let (user, rest) = rest.split_at(rest.find(is_dir_sep).unwrap_or(rest.len()));
+    let home = match user {
+        "" => std::env::var("HOME").ok()?,
+        user => user_home(user)?,
+    };
+    Some(format!("{home}{rest}"))
+}
+
+/// The absolute file name `name` without repeated slashes and `.`
+/// components, and with each `..` component removed along with the one
+/// before it. It ends with a slash if `trailing_slash` is true.
+fn canonicalize(name: &str, trailing_slash: bool) -> String {
+    let (drive, path) = split_drive(name);
+    let mut parts = Vec::new();
+    for part in path.split(is_dir_sep) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut name = format!("{drive}/{}", parts.join("/"));
+    if trailing_slash && !parts.is_empty() {
+        name.push('/');
+    }
+    name
+}
+
+/// Convert file name `name` to an absolute name, relative to
+/// `default_directory` or else `default-directory`. A leading `~` is the
+/// home directory, and a relative default directory is relative to the
+/// working directory of the process.
 pub(crate) fn expand_file_name(
     name: &str,
     default_directory: Option<&str>,
     env: &Rt<Env>,
     cx: &Context,
 ) -> Result<String> {
-    // TODO: this needs to be tested to ensure it has the same behavior as GNU
-    // Emacs. It doesn't do any normalization for one thing.
-    if Path::new(name).is_absolute() {
-        Ok(name.to_owned())
-    } else if let Some(dir) = default_directory {
-        let path = Path::new(dir);
-        Ok(path.join(name).to_string_lossy().to_string())
-    } else {
-        let dir = env.vars.get(sym::DEFAULT_DIRECTORY).unwrap();
-        match dir.get(cx) {
-            Object::String(s) => {
-                let name: &str = s.try_into()?;
-                let path = Path::new(name);
-                Ok(path.join(name).to_string_lossy().to_string())
+    let name = expand_home(name).unwrap_or_else(|| name.to_owned());
+    let trailing_slash = name.ends_with(is_dir_sep);
+    if is_absolute(&name) {
+        return Ok(canonicalize(&name, trailing_slash));
+    }
+    let dir = match default_directory {
+        Some(dir) => dir.to_owned(),
+        None => match env.vars.get(sym::DEFAULT_DIRECTORY).map(|x| x.get(cx)) {
+            Some(Object::String(dir)) => <&str>::try_from(dir)?.to_owned(),
+            _ => "/".to_owned(),
+        },
+    };
+    let mut dir = expand_home(&dir).unwrap_or(dir);
+    if !is_absolute(&dir) {
+        let cwd = std::env::current_dir()?;
+        dir = format!("{}/{dir}", cwd.to_string_lossy());
+    }
+    // a name like "c:foo" is relative to that drive and not the directory
+    let (drive, rest) = split_drive(&name);
+    if !drive.is_empty() && split_drive(&dir).0 != drive {
+        dir = format!("{drive}/");
+    }
+    Ok(canonicalize(&format!("{dir}/{rest}"), trailing_slash))
+}
+
+/// The text of the file name `name`.
+pub(crate) fn file_name(name: &Rt<Gc<&LispString>>, cx: &Context) -> Result<String> {
+    let name: &str = name.get(cx).try_into()?;
+    Ok(name.to_owned())
+}
+
+/// The absolute name of the file name `name`.
+pub(crate) fn expand(name: &Rt<Gc<&LispString>>, env: &Rt<Env>, cx: &Context) -> Result<String> {
+    expand_file_name(&file_name(name, cx)?, None, env, cx)
+}
+
+/// Whether `name` matches the regexp `regexp`, with case significant.
+#[cfg(feature = "regex")]
+pub(crate) fn name_matches(
+    regexp: &str,
+    name: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let re = crate::search::compile_regexp_with(regexp, false, env, cx)?;
+    Ok(re.search_forward(name.into(), 0, None)?.is_some())
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn name_matches(_: &str, _: &str, _: &mut Rt<Env>, _: &Context) -> Result<bool> {
+    anyhow::bail!("Regexps are not supported")
+}
+
+/// Whether the list `list` contains `elt`.
+fn memq(elt: GcObj, list: GcObj) -> bool {
+    list.as_list()
+        .is_ok_and(|mut x| x.any(|x| x.is_ok_and(|x| x == elt)))
+}
+
+/// The handler in `file-name-handler-alist` for `operation` on `file`: of
+/// the handlers whose regexp matches it, the one whose match starts last.
+/// A handler symbol with an `operations` property only handles those. The
+/// handlers in `inhibit-file-name-handlers` are skipped while `operation`
+/// is `inhibit-file-name-operation`.
+pub(crate) fn file_name_handler<'ob>(
+    file: &str,
+    operation: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let var = |var, env: &Rt<Env>| env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+    let alist = var(sym::FILE_NAME_HANDLER_ALIST, env);
+    let inhibited = match var(sym::INHIBIT_FILE_NAME_OPERATION, env) == operation {
+        true => var(sym::INHIBIT_FILE_NAME_HANDLERS, env),
+        false => nil(),
+    };
+    let mut found = (nil(), None);
+    for elt in alist.as_list()? {
+        let Object::Cons(elt) = elt?.untag() else {continue};
+        let (Object::String(regexp), handler) = (elt.car().untag(), elt.cdr()) else {continue};
+        let operations = match handler.untag() {
+            Object::Symbol(handler) => crate::data::get(handler, sym::OPERATIONS, env, cx),
+            _ => nil(),
+        };
+        if memq(handler, inhibited) || !(operations.nil() || memq(operation.into(), operations)) {
+            continue;
+        }
+        let regexp: &str = regexp.try_into()?;
+        let Some(pos) = handler_match(regexp, file, env, cx)? else {continue};
+        if found.1.is_none_or(|x| pos > x) {
+            found = (handler, Some(pos));
+        }
+    }
+    Ok(found.0)
+}
+
+/// Where the regexp `regexp` first matches `file`.
+#[cfg(feature = "regex")]
+fn handler_match(
+    regexp: &str,
+    file: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Option<usize>> {
+    let re = crate::search::compile_regexp_with(regexp, false, env, cx)?;
+    Ok(re
+        .search_forward(file.into(), 0, None)?
+        .and_then(|x| x.get(0))
+        .map(|x| x.0))
+}
+
+#[cfg(not(feature = "regex"))]
+fn handler_match(_: &str, _: &str, _: &mut Rt<Env>, _: &Context) -> Result<Option<usize>> {
+    Ok(None)
+}
+
+/// An argument of a file operation to pass on to a file name handler.
+pub(crate) enum HandlerArg<'a> {
+    /// A file name, which a handler is looked for
+    File(&'a str),
+    Obj(Option<&'a Rt<GcObj<'a>>>),
+}
+
+/// Call the handler in `file-name-handler-alist` for `operation` on the
+/// first file name in `args` that has one, with `operation` and `args`, and
+/// return its value. Returns `None` if there is no handler.
+pub(crate) fn call_file_handler<'ob>(
+    operation: Symbol,
+    args: &[HandlerArg],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Option<GcObj<'ob>>> {
+    let mut handler = nil();
+    for arg in args {
+        if let HandlerArg::File(file) = arg {
+            handler = file_name_handler(file, operation, env, cx)?;
+            if !handler.nil() {
+                break;
             }
-            _ => unreachable!("`default-directory' should be a string"),
         }
     }
+    if handler.nil() {
+        return Ok(None);
+    }
+    root!(handler, cx);
+    call_handler(handler, operation, args, env, cx).map(Some)
+}
+
+/// Call the file name handler `handler` with `operation` and `args`.
+pub(crate) fn call_handler<'ob>(
+    handler: &Rt<GcObj>,
+    operation: Symbol,
+    args: &[HandlerArg],
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let mut call = vec![handler.bind(cx), operation.into()];
+    call.extend(args.iter().map(|arg| match arg {
+        HandlerArg::File(file) => cx.add(*file),
+        HandlerArg::Obj(obj) => obj.map_or_else(nil, |x| x.bind(cx)),
+    }));
+    root!(call, move(call), cx);
+    let handler = &call[0];
+    let handler: &Rt<Gc<Function>> = handler.try_into()?;
+    crate::eval::funcall(handler, &call[1..], env, cx)
+}
+
+/// Return the handler in `file-name-handler-alist` for OPERATION on
+/// FILENAME, or nil if it has none. Of the handlers whose regexp matches
+/// FILENAME, the one whose match starts last is used.
+#[defun]
+fn find_file_name_handler<'ob>(
+    filename: &str,
+    operation: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    file_name_handler(filename, operation, env, cx)
+}
+
+/// Convert filename NAME to an absolute name, relative to DEFAULT-DIRECTORY
+/// or else `default-directory`.
+#[defun(name = "expand-file-name")]
+fn expand_file_name_lisp<'ob>(
+    name: &Rt<Gc<&LispString>>,
+    default_directory: Option<&Rt<Gc<&LispString>>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let name_str = file_name(name, cx)?;
+    let dir = default_directory.map(|x| file_name(x, cx)).transpose()?;
+    let mut args = vec![HandlerArg::File(&name_str)];
+    args.extend(dir.as_deref().map(HandlerArg::File));
+    if let Some(x) = call_file_handler(sym!(expand - file - name), &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(cx.add(expand_file_name(&name_str, dir.as_deref(), env, cx)?))
+}
+
+/// Return t if file FILENAME exists. A symbolic link exists if its target
+/// does.
+#[defun]
+fn file_exists_p<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    if let Some(x) = call_file_handler(sym::FILE_EXISTS_P, &[HandlerArg::File(&file)], env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(Path::new(&file).exists().into())
+}
+
+/// Return t if file FILENAME exists and can be read.
+#[defun]
+fn file_readable_p<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [HandlerArg::File(&file)];
+    if let Some(x) = call_file_handler(sym::FILE_READABLE_P, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(match Path::new(&file).is_dir() {
+        true => fs::read_dir(&file).is_ok(),
+        false => fs::File::open(&file).is_ok(),
+    }
+    .into())
+}
+
+/// Return the target of FILENAME if it is a symbolic link, and otherwise
+/// nil. The target is not expanded.
+#[defun]
+fn file_symlink_p<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [HandlerArg::File(&file)];
+    if let Some(x) = call_file_handler(sym::FILE_SYMLINK_P, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(match fs::read_link(file) {
+        Ok(target) => cx.add(target.to_string_lossy().into_owned()),
+        Err(_) => nil(),
+    })
+}
+
+/// Return t if file FILE1 was modified more recently than FILE2. A file
+/// that does not exist is older than any file that does.
+#[defun]
+fn file_newer_than_file_p<'ob>(
+    file1: &Rt<Gc<&LispString>>,
+    file2: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (file1, file2) = (expand(file1, env, cx)?, expand(file2, env, cx)?);
+    let args = [HandlerArg::File(&file1), HandlerArg::File(&file2)];
+    if let Some(x) = call_file_handler(sym::FILE_NEWER_THAN_FILE_P, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let modified = |file: &str| fs::metadata(file).and_then(|x| x.modified()).ok();
+    Ok(match (modified(&file1), modified(&file2)) {
+        (Some(time1), Some(time2)) => time1 > time2,
+        (time1, _) => time1.is_some(),
+    }
+    .into())
+}
+
+/// Return t if FILENAME is the name of a directory.
+#[defun]
+fn file_directory_p<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    if file_name(filename, cx)?.is_empty() {
+        return Ok(true.into());
+    }
+    let file = expand(filename, env, cx)?;
+    let args = [HandlerArg::File(&file)];
+    if let Some(x) = call_file_handler(sym::FILE_DIRECTORY_P, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(Path::new(&file).is_dir().into())
+}
+
+/// Return the directory part of FILENAME, up to and including the last slash,
+/// or nil if it has none.
+#[defun]
+fn file_name_directory<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let filename = file_name(filename, cx)?;
+    let args = [HandlerArg::File(&filename)];
+    if let Some(x) = call_file_handler(sym::FILE_NAME_DIRECTORY, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(match directory_len(&filename) {
+        Some(len) => cx.add(&filename[..len]),
+        None => nil(),
+    })
+}
+
+/// Return FILENAME without its directory part.
+#[defun]
+fn file_name_nondirectory<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let filename = file_name(filename, cx)?;
+    let args = [HandlerArg::File(&filename)];
+    if let Some(x) = call_file_handler(sym::FILE_NAME_NONDIRECTORY, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let start = directory_len(&filename).unwrap_or(0);
+    Ok(cx.add(&filename[start..]))
+}
+
+/// The length of the directory part of `name`, up to and including its last
+/// slash or its drive letter, or `None` if it has none.
+fn directory_len(name: &str) -> Option<usize> {
+    match name.rfind(is_dir_sep) {
+        Some(idx) => Some(idx + 1),
+        None => Some(split_drive(name).0.len()).filter(|x| *x > 0),
+    }
+}
+
+/// The file name `file` without a backup version suffix, like `~` or `.~1~`.
+fn sans_versions(file: &str) -> &str {
+    let Some(rest) = file.strip_suffix('~') else {return file};
+    let version = |x: &str| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_alphanumeric() || "-:#@^._".contains(c))
+    };
+    match rest.rfind(".~") {
+        Some(idx) if version(&rest[idx + 2..]) => &file[..idx],
+        _ => rest,
+    }
+}
+
+/// Where the extension of `file`, which has no directory part, starts: at
+/// its last period, unless that is its first character.
+fn extension_start(file: &str) -> Option<usize> {
+    file.rfind('.').filter(|x| *x > 0)
+}
+
+/// Return t if FILENAME is absolute: if it starts with a slash, or with `~`
+/// or `~USER` for a user that exists.
+#[defun]
+fn file_name_absolute_p(filename: &str) -> bool {
+    match filename.starts_with('~') {
+        true => expand_home(filename).is_some(),
+        false => is_absolute(filename),
+    }
+}
+
+/// Return the extension of FILENAME, the part of its nondirectory part
+/// after the last period, ignoring a backup version suffix. A period at the
+/// start of the name does not start an extension. If PERIOD is non-nil, the
+/// extension includes the period, and it is "" if there is none; otherwise
+/// it is nil then.
+#[defun]
+fn file_name_extension<'ob>(filename: &str, period: Option<()>, cx: &'ob Context) -> GcObj<'ob> {
+    let file = sans_versions(&filename[directory_len(filename).unwrap_or(0)..]);
+    match extension_start(file) {
+        Some(idx) if period.is_some() => cx.add(&file[idx..]),
+        Some(idx) => cx.add(&file[idx + 1..]),
+        None if period.is_some() => cx.add(""),
+        None => nil(),
+    }
+}
+
+/// Return FILENAME without its extension, and without a backup version
+/// suffix if it has an extension.
+#[defun]
+fn file_name_sans_extension(filename: &str) -> String {
+    let start = directory_len(filename).unwrap_or(0);
+    match extension_start(sans_versions(&filename[start..])) {
+        Some(idx) => filename[..start + idx].to_owned(),
+        None => filename.to_owned(),
+    }
+}
+
+/// The file name `name` as the name of a directory, ending with a slash.
+pub(crate) fn as_directory(name: &str) -> String {
+    match name {
+        "" => "./".to_owned(),
+        name if name.ends_with(is_dir_sep) => name.to_owned(),
+        name => format!("{name}/"),
+    }
+}
+
+/// Return FILE as the name of a directory, by adding a slash to the end if
+/// it does not have one. The empty name is "./".
+#[defun]
+fn file_name_as_directory<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = file_name(file, cx)?;
+    let args = [HandlerArg::File(&file)];
+    if let Some(x) = call_file_handler(sym::FILE_NAME_AS_DIRECTORY, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(cx.add(as_directory(&file)))
+}
+
+/// The directory name `name` as the name of a file, without trailing
+/// slashes. The root directory keeps its slash, and "//" is kept as is.
+fn directory_as_file(name: &str) -> String {
+    let (drive, path) = split_drive(name);
+    let trimmed = match path.trim_end_matches(is_dir_sep) {
+        "" if path == "//" => path,
+        "" => &path[..path.len().min(1)],
+        trimmed => trimmed,
+    };
+    format!("{drive}{trimmed}")
+}
+
+/// Return DIRECTORY as the name of a file, by removing the slashes at the
+/// end of it. The root directory is left alone.
+#[defun]
+fn directory_file_name<'ob>(
+    directory: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let directory = file_name(directory, cx)?;
+    let args = [HandlerArg::File(&directory)];
+    if let Some(x) = call_file_handler(sym::DIRECTORY_FILE_NAME, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok(cx.add(directory_as_file(&directory)))
+}
+
+/// Return FILENAME relative to DIRECTORY, or `default-directory` if that is
+/// nil. The name goes up through ".." components as needed. If the two are
+/// on different drives or are handled by different file name handlers,
+/// FILENAME is returned expanded.
+#[defun]
+fn file_relative_name(
+    filename: &str,
+    directory: Option<&str>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    let filename = expand_file_name(filename, None, env, cx)?;
+    let mut directory = as_directory(&match directory {
+        Some(dir) => expand_file_name(dir, None, env, cx)?,
+        None => expand_file_name("", None, env, cx)?,
+    });
+    let remote = sym!(file - remote - p);
+    let handlers = (
+        file_name_handler(&filename, remote, env, cx)?,
+        file_name_handler(&directory, remote, env, cx)?,
+    );
+    if handlers.0 != handlers.1 || split_drive(&filename).0 != split_drive(&directory).0 {
+        return Ok(filename);
+    }
+    let filename_dir = as_directory(&filename);
+    let mut ancestor = ".".to_owned();
+    while !(filename_dir.starts_with(&directory) || filename.starts_with(&directory)) {
+        let parent = directory_len(&directory[..directory.len() - 1]).unwrap_or(0);
+        directory.truncate(parent);
+        ancestor = match ancestor.as_str() {
+            "." => "..".to_owned(),
+            _ => format!("../{ancestor}"),
+        };
+    }
+    match filename.strip_prefix(&directory) {
+        Some(rest) if ancestor == "." && !rest.is_empty() => Ok(rest.to_owned()),
+        Some(rest) => Ok(as_directory(&ancestor) + rest),
+        None => Ok(ancestor),
+    }
+}
+
+/// Return FILENAME with the home directory at its start replaced by `~`.
+#[defun]
+fn abbreviate_file_name<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let filename = file_name(filename, cx)?;
+    let args = [HandlerArg::File(&filename)];
+    if let Some(x) = call_file_handler(sym::ABBREVIATE_FILE_NAME, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let home = expand_home("~").map(|x| directory_as_file(&x));
+    let abbreviated = home.filter(|x| x != "/" && !x.is_empty()).and_then(|home| {
+        let rest = filename.strip_prefix(&home)?;
+        (rest.is_empty() || rest.starts_with(is_dir_sep)).then(|| format!("~{rest}"))
+    });
+    Ok(cx.add(abbreviated.unwrap_or(filename)))
+}
+
+/// Join DIRECTORY and COMPONENTS into a file name, adding a slash between
+/// them where there is none. Components that are nil or empty are skipped.
+#[defun]
+fn file_name_concat(directory: Option<&str>, components: &[GcObj]) -> Result<String> {
+    let mut name = directory.unwrap_or_default().to_owned();
+    for component in components.iter().filter(|x| !x.nil()) {
+        let component: &str = (*component).try_into()?;
+        if component.is_empty() {
+            continue;
+        }
+        if !name.is_empty() && !name.ends_with(is_dir_sep) {
+            name.push('/');
+        }
+        name.push_str(component);
+    }
+    Ok(name)
 }
 
+/// Split FILENAME into a list of its components. An absolute file name
+/// starts with an empty string and a directory name ends with one.
 #[defun]
-fn file_directory_p(filename: &str) -> bool {
+fn file_name_split<'ob>(filename: &str, cx: &'ob Context) -> GcObj<'ob> {
     if filename.is_empty() {
-        true
+        return nil();
+    }
+    let parts: Vec<GcObj> = filename.split('/').map(|x| cx.add(x)).collect();
+    slice_into_list(&parts, None, cx)
+}
+
+/// Create the directory DIRECTORY. If PARENTS is non-nil, its missing
+/// parent directories are created too, and it is not an error if it exists
+/// already; t is returned then.
+#[defun]
+fn make_directory<'ob>(
+    directory: &Rt<Gc<&LispString>>,
+    parents: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let dir = expand(directory, env, cx)?;
+    let args = [HandlerArg::File(&dir), HandlerArg::Obj(parents)];
+    if let Some(x) = call_file_handler(sym::MAKE_DIRECTORY, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let created = match parents {
+        Some(_) if Path::new(&dir).is_dir() => return Ok(true.into()),
+        Some(_) => fs::create_dir_all(&dir),
+        None => fs::create_dir(&dir),
+    };
+    match created {
+        Ok(()) => Ok(nil()),
+        Err(e) => Err(file_io_error(&e, "Creating directory", &dir, env, cx)),
+    }
+}
+
+/// `len` random letters and digits.
+fn random_chars(len: usize) -> String {
+    const CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    // the keys of a new RandomState are random, and the count and the time
+    // keep two names asked for at once apart
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(CALLS.fetch_add(1, Ordering::Relaxed));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    hasher.write_u128(now.map_or(0, |x| x.as_nanos()));
+    hasher.write_u32(std::process::id());
+    let mut bits = hasher.finish();
+    (0..len)
+        .map(|_| {
+            let chr = CHARS[(bits % CHARS.len() as u64) as usize];
+            bits /= CHARS.len() as u64;
+            chr as char
+        })
+        .collect()
+}
+
+/// The default value of `temporary-file-directory`, from `TMPDIR`.
+pub(crate) fn default_temp_directory() -> String {
+    as_directory(&std::env::temp_dir().to_string_lossy())
+}
+
+/// Return PREFIX followed by six random characters, as the name of a file
+/// that probably does not exist. The file may be created by someone else
+/// before it is used, so `make-temp-file` should be used instead to create
+/// temporary files securely.
+#[defun]
+fn make_temp_name(prefix: &str) -> String {
+    format!("{prefix}{}", random_chars(6))
+}
+
+/// Create a new file, or directory if DIR-FLAG is non-nil, named PREFIX
+/// expanded, followed by random characters and SUFFIX, and return its name.
+/// A file is created readable and writable only by the user and contains
+/// TEXT if it is a string, and a directory is accessible only by the user.
+/// The name is picked again if a file with it exists, and since it is
+/// created only if it does not exist, no other file is ever used.
+#[defun]
+fn make_temp_file_internal(
+    prefix: &str,
+    dir_flag: Option<()>,
+    suffix: Option<&str>,
+    text: Option<&str>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+    let prefix = expand_file_name(prefix, None, env, cx)?;
+    let mut error = None;
+    // a name is only taken again if another process raced us to it
+    for _ in 0..100 {
+        let name = format!("{prefix}{}{}", random_chars(6), suffix.unwrap_or_default());
+        let created = match dir_flag {
+            Some(()) => fs::DirBuilder::new().mode(0o700).create(&name),
+            // the file is only written once it was created by us
+            None => fs::File::options()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&name)
+                .and_then(|_| fs::write(&name, text.unwrap_or_default())),
+        };
+        match created {
+            Ok(()) => return Ok(name),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => error = Some(e),
+            Err(e) => {
+                return Err(file_io_error(
+                    &e,
+                    "Creating file with prefix",
+                    &prefix,
+                    env,
+                    cx,
+                ))
+            }
+        }
+    }
+    let error = error.expect("a name should have been tried");
+    Err(file_io_error(
+        &error,
+        "Creating file with prefix",
+        &prefix,
+        env,
+        cx,
+    ))
+}
+
+/// Create a temporary file, or directory if DIR-FLAG is non-nil, and return
+/// its name. The name starts with PREFIX expanded relative to
+/// `temporary-file-directory` and ends with SUFFIX, with random characters
+/// between them. If TEXT is a string, it is written to the file. See
+/// `make-temp-file-internal`.
+#[defun]
+fn make_temp_file(
+    prefix: &str,
+    dir_flag: Option<GcObj>,
+    suffix: Option<&str>,
+    text: Option<&str>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    let dir = match env
+        .vars
+        .get(sym::TEMPORARY_FILE_DIRECTORY)
+        .map(|x| x.bind(cx).untag())
+    {
+        Some(Object::String(dir)) => <&str>::try_from(dir)?.to_owned(),
+        _ => default_temp_directory(),
+    };
+    let prefix = match prefix {
+        "" | "." | ".." => format!("{}{prefix}", as_directory(&dir)),
+        prefix => expand_file_name(prefix, Some(&dir), env, cx)?,
+    };
+    let dir_flag = dir_flag.filter(|x| !x.nil()).map(|_| ());
+    make_temp_file_internal(&prefix, dir_flag, suffix, text, env, cx)
+}
+
+/// Create a temporary file like `make-temp-file`, but on the same host as
+/// `default-directory` if it is remote, when its file name handler handles
+/// `make-nearby-temp-file`. An absolute PREFIX is always used as it is.
+#[defun]
+fn make_nearby_temp_file<'ob>(
+    prefix: &Rt<Gc<&LispString>>,
+    dir_flag: Option<&Rt<GcObj>>,
+    suffix: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let prefix = file_name(prefix, cx)?;
+    let directory = match env
+        .vars
+        .get(sym::DEFAULT_DIRECTORY)
+        .map(|x| x.bind(cx).untag())
+    {
+        Some(Object::String(dir)) => <&str>::try_from(dir)?.to_owned(),
+        _ => String::new(),
+    };
+    let operation = sym::MAKE_NEARBY_TEMP_FILE;
+    let handler = file_name_handler(&directory, operation, env, cx)?;
+    if !handler.nil() && !is_absolute(&prefix) {
+        root!(handler, cx);
+        let args = [
+            HandlerArg::File(&prefix),
+            HandlerArg::Obj(dir_flag),
+            HandlerArg::Obj(suffix),
+        ];
+        return call_handler(handler, operation, &args, env, cx);
+    }
+    let dir_flag = dir_flag.map(|x| x.bind(cx));
+    let suffix = match suffix.map(|x| x.bind(cx).untag()) {
+        Some(Object::String(suffix)) => Some(<&str>::try_from(suffix)?.to_owned()),
+        _ => None,
+    };
+    let name = make_temp_file(&prefix, dir_flag, suffix.as_deref(), None, env, cx)?;
+    Ok(cx.add(name))
+}
+
+/// Delete the directory DIRECTORY, which has to be empty unless RECURSIVE is
+/// non-nil. A symbolic link to a directory is deleted itself. TRASH is
+/// ignored, as there is no trash.
+#[defun]
+fn delete_directory<'ob>(
+    directory: &Rt<Gc<&LispString>>,
+    recursive: Option<&Rt<GcObj>>,
+    trash: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let dir = expand(directory, env, cx)?;
+    let args = [
+        HandlerArg::File(&dir),
+        HandlerArg::Obj(recursive),
+        HandlerArg::Obj(trash),
+    ];
+    if let Some(x) = call_file_handler(sym::DELETE_DIRECTORY, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let is_link = fs::symlink_metadata(&dir).is_ok_and(|x| x.file_type().is_symlink());
+    let deleted = match recursive {
+        _ if is_link => fs::remove_file(&dir),
+        Some(_) => fs::remove_dir_all(&dir),
+        None => fs::remove_dir(&dir),
+    };
+    match deleted {
+        Ok(()) => Ok(nil()),
+        Err(e) => Err(file_io_error(&e, "Removing directory", &dir, env, cx)),
+    }
+}
+
+/// The target of renaming or copying `file` to the absolute file name
+/// `newname`: a file of the same name in `newname` if it is a directory
+/// name, which ends in a slash. It is an error to `action` if the target
+/// exists, unless `ok_if_exists` is true.
+fn target_name(
+    action: &str,
+    file: &str,
+    newname: &str,
+    ok_if_exists: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    let mut target = newname.to_owned();
+    if target.ends_with('/') {
+        let name = file
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        target.push_str(name);
+    }
+    if !ok_if_exists && fs::symlink_metadata(&target).is_ok() {
+        let err = io::Error::from(io::ErrorKind::AlreadyExists);
+        return Err(file_io_error(&err, action, &target, env, cx));
+    }
+    Ok(target)
+}
+
+/// Rename FILE to NEWNAME, or move it into NEWNAME if that is a directory
+/// name. Signal `file-already-exists` if NEWNAME exists, unless
+/// OK-IF-ALREADY-EXISTS is non-nil. A file is copied and deleted if it can't
+/// be renamed across file systems.
+#[defun]
+fn rename_file<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    newname: &Rt<Gc<&LispString>>,
+    ok_if_already_exists: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (source, newname) = (expand(file, env, cx)?, expand(newname, env, cx)?);
+    let args = [
+        HandlerArg::File(&source),
+        HandlerArg::File(&newname),
+        HandlerArg::Obj(ok_if_already_exists),
+    ];
+    if let Some(x) = call_file_handler(sym::RENAME_FILE, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let ok_if_exists = ok_if_already_exists.is_some();
+    let target = target_name("Renaming", &source, &newname, ok_if_exists, env, cx)?;
+    let renamed = fs::rename(&source, &target).or_else(|e| match e.kind() {
+        io::ErrorKind::CrossesDevices if !Path::new(&source).is_dir() => {
+            copy_with_times(&source, &target, true)?;
+            fs::remove_file(&source)
+        }
+        _ => Err(e),
+    });
+    match renamed {
+        Ok(()) => Ok(nil()),
+        Err(e) => Err(file_io_error(&e, "Renaming", &source, env, cx)),
+    }
+}
+
+/// Copy `source` to `target` with its permissions, and its access and
+/// modification times if `keep_time` is true.
+fn copy_with_times(source: &str, target: &str, keep_time: bool) -> io::Result<()> {
+    fs::copy(source, target)?;
+    if keep_time {
+        let metadata = fs::metadata(source)?;
+        let times = fs::FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        fs::File::options()
+            .write(true)
+            .open(target)?
+            .set_times(times)?;
+    }
+    Ok(())
+}
+
+/// Copy FILE to NEWNAME, or into NEWNAME if that is a directory name, with
+/// its permissions. Signal `file-already-exists` if NEWNAME exists, unless
+/// OK-IF-ALREADY-EXISTS is non-nil. If KEEP-TIME is non-nil, the copy gets
+/// the modification time of FILE. The owner of the copy is always the
+/// current user, so PRESERVE-UID-GID has no effect, and the permissions are
+/// always kept, whatever PRESERVE-PERMISSIONS is.
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn copy_file<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    newname: &Rt<Gc<&LispString>>,
+    ok_if_already_exists: Option<&Rt<GcObj>>,
+    keep_time: Option<&Rt<GcObj>>,
+    preserve_uid_gid: Option<&Rt<GcObj>>,
+    preserve_permissions: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let (source, newname) = (expand(file, env, cx)?, expand(newname, env, cx)?);
+    let args = [
+        HandlerArg::File(&source),
+        HandlerArg::File(&newname),
+        HandlerArg::Obj(ok_if_already_exists),
+        HandlerArg::Obj(keep_time),
+        HandlerArg::Obj(preserve_uid_gid),
+        HandlerArg::Obj(preserve_permissions),
+    ];
+    if let Some(x) = call_file_handler(sym::COPY_FILE, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let ok_if_exists = ok_if_already_exists.is_some();
+    let target = target_name("Copying file", &source, &newname, ok_if_exists, env, cx)?;
+    match copy_with_times(&source, &target, keep_time.is_some()) {
+        Ok(()) => Ok(nil()),
+        Err(e) => Err(file_io_error(&e, "Copying file", &source, env, cx)),
+    }
+}
+
+/// The error for failing to do `action` on `file`: `file-missing` if it does
+/// not exist, `file-already-exists` if it should not, `permission-denied` if
+/// access to it was denied, and otherwise `file-error`, which the others are
+/// kinds of.
+pub(crate) fn file_io_error(
+    err: &io::Error,
+    action: &str,
+    file: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> anyhow::Error {
+    let error = match err.kind() {
+        io::ErrorKind::NotFound => sym::FILE_MISSING,
+        io::ErrorKind::AlreadyExists => sym::FILE_ALREADY_EXISTS,
+        io::ErrorKind::PermissionDenied => sym::PERMISSION_DENIED,
+        _ => sym::FILE_ERROR,
+    };
+    let conditions = list![error, sym::FILE_ERROR, sym::ERROR; cx];
+    crate::data::put(error, sym::ERROR_CONDITIONS, conditions, env);
+    // drop the "(os error N)" suffix
+    let reason = err.to_string();
+    let reason = reason.split(" (os error").next().unwrap_or_default();
+    let data = list![action, reason, file; cx];
+    EvalError::signal(error.into(), data, env).into()
+}
+
+/// The modification time of `file`.
+#[cfg(feature = "buffers")]
+pub(crate) fn file_modtime(file: &str) -> Modtime {
+    match fs::metadata(file).and_then(|x| x.modified()) {
+        Ok(time) => Modtime::Time(time),
+        Err(_) => Modtime::Missing,
+    }
+}
+
+/// The name of the file `buffer` is visiting.
+#[cfg(feature = "buffers")]
+pub(crate) fn visited_file(
+    buffer: &'static Buffer,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Option<String> {
+    match buffer_var(buffer, sym::BUFFER_FILE_NAME, env, cx).untag() {
+        Object::String(name) => <&str>::try_from(name).ok().map(str::to_owned),
+        _ => None,
+    }
+}
+
+/// Record that the current buffer is visiting `file`, as it is now, and is
+/// unmodified.
+#[cfg(feature = "buffers")]
+fn visit_file(file: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    crate::filelock::unlock_buffer_file(buffer, env, cx)?;
+    set_buffer_var(buffer, sym::BUFFER_FILE_NAME, cx.add(file), env);
+    let mut data = buffer.lock()?;
+    data.set_modtime(file_modtime(file));
+    data.set_modified(false);
+    Ok(())
+}
+
+/// Replace the accessible portion of the current buffer with `text`,
+/// leaving the text they have in common at the start and the end alone.
+/// Returns the number of characters inserted.
+#[cfg(feature = "buffers")]
+fn replace_accessible(text: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<usize> {
+    let buffer = get_current_buffer(env);
+    let (begv, zv, point, old) = {
+        let data = buffer.lock()?;
+        (
+            data.begv(),
+            data.zv(),
+            data.point(),
+            data.text.substring(data.begv(), data.zv()),
+        )
+    };
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = text.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(x, y)| x == y).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old.iter().rev().zip(new.iter().rev());
+    let suffix = suffix.take(max_suffix).take_while(|(x, y)| x == y).count();
+    let (beg, end) = (begv + prefix, zv - suffix);
+    let inserted: String = new[prefix..new.len() - suffix].iter().collect();
+    if beg == end && inserted.is_empty() {
+        return Ok(0);
+    }
+    crate::buffer::delete_text(beg, end, env, cx)?;
+    buffer.lock()?.goto_char(beg);
+    crate::buffer::insert_text(&inserted, env, cx)?;
+    let len = inserted.chars().count();
+    let point = match point {
+        x if x >= end => x + len - (end - beg),
+        x if x > beg => beg,
+        x => x,
+    };
+    buffer.lock()?.goto_char(point);
+    Ok(len)
+}
+
+/// Insert the contents of file FILENAME after point, decoded with
+/// `coding-system-for-read` or the coding system detected for it, and
+/// return a list of its absolute name and the number of characters
+/// inserted. BEG and END are byte offsets of the part of the file to read.
+/// If VISIT is non-nil, the buffer visits the file and is marked
+/// unmodified; the file name is set even if the file does not exist. If
+/// REPLACE is non-nil, the accessible portion of the buffer is replaced
+/// instead, keeping the text at its start and end that does not change.
+#[cfg(feature = "buffers")]
+#[defun]
+fn insert_file_contents<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    visit: Option<&Rt<GcObj>>,
+    beg: Option<&Rt<GcObj>>,
+    end: Option<&Rt<GcObj>>,
+    replace: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [
+        HandlerArg::File(&file),
+        HandlerArg::Obj(visit),
+        HandlerArg::Obj(beg),
+        HandlerArg::Obj(end),
+        HandlerArg::Obj(replace),
+    ];
+    if let Some(x) = call_file_handler(sym::INSERT_FILE_CONTENTS, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let beg: Option<usize> = beg.map(TryInto::try_into).transpose()?;
+    let end: Option<usize> = end.map(TryInto::try_into).transpose()?;
+    if visit.is_some() {
+        anyhow::ensure!(
+            beg.is_none() && end.is_none(),
+            "Attempt to visit less than an entire file"
+        );
+        visit_file(&file, env, cx)?;
+    }
+    let bytes = match fs::read(&file) {
+        Ok(x) => x,
+        Err(e) => return Err(file_io_error(&e, "Opening input file", &file, env, cx)),
+    };
+    let end = end.unwrap_or(bytes.len()).min(bytes.len());
+    let beg = beg.unwrap_or(0).min(end);
+    let text = decode_file_contents(&bytes[beg..end], env, cx);
+    let inserted = if replace.is_some() {
+        replace_accessible(&text, env, cx)?
     } else {
-        Path::new(filename).is_dir()
+        let point = get_current_buffer(env).lock()?.point();
+        crate::buffer::insert_text(&text, env, cx)?;
+        get_current_buffer(env).lock()?.goto_char(point);
+        text.chars().count()
+    };
+    if visit.is_some() {
+        let coding = env
+            .vars
+            .get(sym::LAST_CODING_SYSTEM_USED)
+            .map_or_else(nil, |x| x.bind(cx));
+        env.vars.insert(sym::BUFFER_FILE_CODING_SYSTEM, coding);
+        get_current_buffer(env).lock()?.set_modified(false);
+    }
+    Ok(list![file, inserted as i64; cx])
+}
+
+/// Write the text from START to END of the current buffer to file FILENAME,
+/// encoded with `coding-system-for-write` or `buffer-file-coding-system`.
+/// If START is nil, the whole buffer is written, and if it is a string, the
+/// string is written instead. If APPEND is non-nil, the text is added to
+/// the end of the file, and if it is an integer, it is written at that byte
+/// offset. If VISIT is t, the buffer visits FILENAME afterwards and is
+/// marked unmodified, and if it is a string, the buffer visits that file
+/// instead. If MUSTBENEW is non-nil, signal `file-already-exists` if
+/// FILENAME exists. The file is synced to disk unless
+/// `write-region-inhibit-fsync` is non-nil.
+#[cfg(feature = "buffers")]
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn write_region<'ob>(
+    start: &Rt<GcObj>,
+    end: &Rt<GcObj>,
+    filename: &Rt<Gc<&LispString>>,
+    append: Option<&Rt<GcObj>>,
+    visit: Option<&Rt<GcObj>>,
+    lockname: Option<&Rt<GcObj>>,
+    mustbenew: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [
+        HandlerArg::Obj(Some(start)),
+        HandlerArg::Obj(Some(end)),
+        HandlerArg::File(&file),
+        HandlerArg::Obj(append),
+        HandlerArg::Obj(visit),
+        HandlerArg::Obj(lockname),
+        HandlerArg::Obj(mustbenew),
+    ];
+    if let Some(x) = call_file_handler(sym::WRITE_REGION, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let (start, end) = (start.bind(cx), end.bind(cx));
+    let text = match start.untag() {
+        Object::String(string) => <&str>::try_from(string)?.to_owned(),
+        Object::Symbol(sym::NIL) => {
+            let buffer = get_current_buffer(env).lock()?;
+            buffer.text.substring(0, buffer.text.len_chars())
+        }
+        _ => {
+            let buffer = get_current_buffer(env);
+            let bounds = {
+                let data = buffer.lock()?;
+                (data.begv(), data.zv())
+            };
+            let (start, end) = (start.try_into()?, end.try_into()?);
+            let (beg, end) = crate::editfns::validate_region(start, end, bounds, env, cx)?;
+            buffer.lock()?.text.substring(beg, end)
+        }
+    };
+    let coding = coding_system_for_write(env, cx);
+    set_last_coding_system_used(coding, env, cx);
+    let bytes = coding.encode(&text);
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    let offset = match append.map(|x| x.bind(cx).untag()) {
+        Some(Object::Int(offset)) => Some(u64::try_from(offset)?),
+        Some(_) => {
+            options.append(true);
+            None
+        }
+        None => {
+            options.truncate(true);
+            None
+        }
+    };
+    match mustbenew {
+        Some(_) => options.create_new(true),
+        None => options.create(true),
+    };
+    let inhibit_fsync = env.vars.get(sym::WRITE_REGION_INHIBIT_FSYNC);
+    let fsync = inhibit_fsync.is_none_or(|x| x.bind(cx).nil());
+    let written = options.open(&file).and_then(|mut out| {
+        if let Some(offset) = offset {
+            out.seek(SeekFrom::Start(offset))?;
+        }
+        out.write_all(&bytes)?;
+        if fsync {
+            out.sync_all()?;
+        }
+        Ok(())
+    });
+    if let Err(e) = written {
+        return Err(file_io_error(&e, "Opening output file", &file, env, cx));
+    }
+
+    let buffer = get_current_buffer(env);
+    let recent_auto_save = buffer.lock()?.recent_auto_save();
+    match visit.map(|x| x.bind(cx).untag()) {
+        Some(Object::Symbol(sym::TRUE)) => visit_file(&file, env, cx)?,
+        Some(Object::String(name)) => {
+            let name = expand_file_name(name.try_into()?, None, env, cx)?;
+            visit_file(&name, env, cx)?;
+            // the buffer records the time of the file that was written
+            buffer.lock()?.set_modtime(file_modtime(&file));
+        }
+        _ => return Ok(nil()),
+    }
+    // the auto-save file is obsolete once the buffer is saved
+    crate::autosave::delete_auto_save_file(buffer, recent_auto_save, env, cx)?;
+    Ok(nil())
+}
+
+/// Return the modification time that the current buffer recorded for its
+/// visited file: 0 if it is not known and -1 if the file did not exist.
+#[cfg(feature = "buffers")]
+#[defun]
+fn visited_file_modtime<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    Ok(match get_current_buffer(env).lock()?.modtime() {
+        Modtime::Unknown => 0.into(),
+        Modtime::Missing => (-1).into(),
+        Modtime::Time(time) => lisp_time(time, cx),
+    })
+}
+
+/// Update the modification time that the current buffer recorded for its
+/// visited file to the file's current time, or to TIME-FLAG if it is
+/// non-nil. A TIME-FLAG of 0 means the time is not known and -1 means the
+/// file does not exist.
+#[cfg(feature = "buffers")]
+#[defun]
+fn set_visited_file_modtime<'ob>(
+    time_flag: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let buffer = get_current_buffer(env);
+    let modtime = match time_flag.map(|x| x.bind(cx)) {
+        Some(flag) => match flag.untag() {
+            Object::Int(0) => Modtime::Unknown,
+            Object::Int(-1) => Modtime::Missing,
+            _ => match decode_system_time(flag) {
+                Some(time) => Modtime::Time(time),
+                None => anyhow::bail!("Invalid time specification"),
+            },
+        },
+        None => match visited_file(buffer, env, cx) {
+            Some(file) => {
+                let args = [HandlerArg::File(&file), HandlerArg::Obj(None)];
+                let operation = sym::SET_VISITED_FILE_MODTIME;
+                if let Some(x) = call_file_handler(operation, &args, env, cx)? {
+                    return Ok(rebind!(x, cx));
+                }
+                file_modtime(&file)
+            }
+            None => Modtime::Unknown,
+        },
+    };
+    buffer.lock()?.set_modtime(modtime);
+    Ok(nil())
+}
+
+/// Forget the modification time that the current buffer recorded for its
+/// visited file, so that the next save does not check it.
+#[cfg(feature = "buffers")]
+#[defun]
+fn clear_visited_file_modtime(env: &mut Rt<Env>) -> Result<bool> {
+    get_current_buffer(env)
+        .lock()?
+        .set_modtime(Modtime::Unknown);
+    Ok(false)
+}
+
+/// Return t if the visited file of BUF still has the modification time that
+/// BUF recorded for it, or if BUF is not visiting a file or did not record
+/// a time. BUF defaults to the current buffer.
+#[cfg(feature = "buffers")]
+#[defun]
+fn verify_visited_file_modtime<'ob>(
+    buf: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let buffer: &'static Buffer = match buf {
+        Some(buf) => buf.bind(cx).try_into()?,
+        None => get_current_buffer(env),
+    };
+    let recorded = buffer.lock()?.modtime();
+    let Some(file) = visited_file(buffer, env, cx) else {return Ok(true.into())};
+    let args = [HandlerArg::File(&file), HandlerArg::Obj(buf)];
+    if let Some(x) = call_file_handler(sym::VERIFY_VISITED_FILE_MODTIME, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    Ok((recorded == Modtime::Unknown || file_modtime(&file) == recorded).into())
+}
+
+defsym!(FILE_ERROR);
+defsym!(FILE_MISSING);
+defsym!(FILE_ALREADY_EXISTS);
+defsym!(PERMISSION_DENIED);
+defsym!(OPERATIONS);
+defvar!(FILE_NAME_HANDLER_ALIST);
+defvar!(INHIBIT_FILE_NAME_HANDLERS);
+defvar!(INHIBIT_FILE_NAME_OPERATION);
+defvar!(BUFFER_FILE_NAME);
+defvar!(WRITE_REGION_INHIBIT_FSYNC);
+defvar!(
+    TEMPORARY_FILE_DIRECTORY,
+    crate::fileio::default_temp_directory()
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::timefns::{decode_system_time, lisp_time};
+    use crate::{interpreter, reader, root};
+    use std::time::Duration;
+
+    #[test]
+    fn test_lisp_time() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let time = UNIX_EPOCH + Duration::new(70_000, 1_234_567);
+        let lisp = lisp_time(time, cx);
+        assert_eq!(lisp.to_string(), "(1 4464 1234 567000)");
+        assert_eq!(decode_system_time(lisp), Some(time));
+        let before = UNIX_EPOCH - Duration::new(1, 500_000_000);
+        assert_eq!(decode_system_time(lisp_time(before, cx)), Some(before));
+    }
+
+    #[test]
+    fn test_file_operations() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-fileops-{}", std::process::id()));
+        let dir = dir.to_string_lossy();
+        let form = format!(
+            r#"(list (make-directory "{dir}/a/b" t)
+                     (make-directory "{dir}/a/b" t)
+                     (condition-case err (make-directory "{dir}/a") (error (car err)))
+                     (file-exists-p "{dir}/a/b")
+                     (file-readable-p "{dir}/a/b")
+                     (file-readable-p "{dir}/x"))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(result.to_string(), "(nil t file-already-exists t t nil)");
+        let file = format!("{dir}/a/file");
+        fs::write(&file, "text").unwrap();
+        std::os::unix::fs::symlink("file", format!("{dir}/a/link")).unwrap();
+        let form = format!(
+            r#"(list (file-symlink-p "{dir}/a/link")
+                     (file-symlink-p "{file}")
+                     (progn (copy-file "{file}" "{dir}/a/b/" nil t)
+                            (file-newer-than-file-p "{dir}/a/b/file" "{file}"))
+                     (condition-case err (rename-file "{file}" "{dir}/a/b/file")
+                       (error (car err)))
+                     (progn (rename-file "{file}" "{dir}/a/b/file" t)
+                            (list (file-exists-p "{file}") (file-readable-p "{dir}/a/b/file")))
+                     (file-newer-than-file-p "{dir}/a/b/file" "{file}")
+                     (condition-case err (delete-directory "{dir}/a")
+                       (error (car err)))
+                     (delete-directory "{dir}" t)
+                     (file-exists-p "{dir}"))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(\"file\" nil nil file-already-exists (nil t) t file-error nil nil)"
+        );
+    }
+
+    #[test]
+    fn test_file_names() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let home = std::env::var("HOME").unwrap();
+        let form = format!(
+            r#"(list (expand-file-name "a/./b//c/../d/" "/x/y")
+                     (expand-file-name "../.." "/x/y/")
+                     (expand-file-name "" "/x/y/")
+                     (expand-file-name "~/a")
+                     (file-name-directory "/a/b.c") (file-name-nondirectory "/a/b.c")
+                     (file-name-extension "a/b.tar.gz") (file-name-extension "a/.emacs")
+                     (file-name-extension "b" t) (file-name-extension "b.el~" t)
+                     (file-name-sans-extension "a.b/c.el.~1~") (file-name-sans-extension ".x")
+                     (file-name-as-directory "/a") (file-name-as-directory "")
+                     (directory-file-name "/a/b//") (directory-file-name "///")
+                     (file-relative-name "/a/b/c" "/a/x/") (file-relative-name "/a/b" "/a/b")
+                     (file-relative-name "/a/b/" "/a/b/") (file-relative-name "/a/b/c" "/a")
+                     (abbreviate-file-name "{home}/f") (abbreviate-file-name "{home}x")
+                     (file-name-concat "a" nil "b/" "" "c")
+                     (file-name-absolute-p "~/a") (file-name-absolute-p "a"))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "(\"/x/y/a/b/d/\" \"/\" \"/x/y\" \"{home}/a\" \"/a/\" \"b.c\" \"gz\" nil \"\" \
+                 \".el\" \"a.b/c\" \".x\" \"/a/\" \"./\" \"/a/b\" \"/\" \"../b/c\" \".\" \
+                 \"./\" \"b/c\" \"~/f\" \"{home}x\" \"a/b/c\" t nil)"
+            )
+        );
+    }
+
+    #[test]
+    fn test_temp_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-tempfiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_string_lossy();
+        let form = format!(
+            r#"(progn
+                 (setq temporary-file-directory "{dir}")
+                 (list (make-temp-file "file" nil ".txt" "text")
+                       (make-temp-file "dir" t)
+                       (make-nearby-temp-file "{dir}/near")
+                       (make-temp-name "name")))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let names: Vec<String> = result
+            .as_list()
+            .unwrap()
+            .map(|x| <&str>::try_from(x.unwrap()).unwrap().to_owned())
+            .collect();
+        let mode = |name: &str| fs::metadata(name).unwrap().permissions().mode() & 0o777;
+        let contents = fs::read_to_string(&names[0]);
+        let modes = [mode(&names[0]), mode(&names[1]), mode(&names[2])];
+        let _ = fs::remove_dir_all(&*dir);
+        assert!(names[0].starts_with(&format!("{dir}/file")));
+        assert_eq!(Path::new(&names[0]).extension(), Some("txt".as_ref()));
+        assert_eq!(names[0].len(), format!("{dir}/file.txt").len() + 6);
+        assert_eq!(contents.unwrap(), "text");
+        assert!(names[1].starts_with(&format!("{dir}/dir")));
+        assert!(names[2].starts_with(&format!("{dir}/near")));
+        assert_eq!(modes, [0o600, 0o700, 0o600]);
+        assert!(names[3].starts_with("name") && names[3].len() == 10);
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn test_file_name_handlers() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn
+            (defalias 'magic-handler #'(lambda (operation &rest args) (cons operation args)))
+            (defalias 'other-handler #'(lambda (_operation &rest _args) 'other))
+            (put 'other-handler 'operations '(file-exists-p))
+            (setq file-name-handler-alist
+                  '(("\\`/magic:" . magic-handler) ("\\.gz\\'" . other-handler)))
+            (list (file-exists-p "/magic:foo")
+                  (file-directory-p "/magic:foo")
+                  (copy-file "/tmp/x" "/magic:y" t)
+                  (file-attributes "/magic:foo" 'string)
+                  (file-exists-p "/magic:foo.gz")
+                  (file-readable-p "/magic:foo.gz")
+                  (find-file-name-handler "/magic:a" 'load)
+                  (find-file-name-handler "/plain" 'load)
+                  (let ((inhibit-file-name-handlers '(magic-handler))
+                        (inhibit-file-name-operation 'file-exists-p))
+                    (list (file-exists-p "/magic:foo")
+                          (file-readable-p "/magic:foo")))
+                  (let ((default-directory "/magic:dir/"))
+                    (make-nearby-temp-file "x" t))))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "((file-exists-p \"/magic:foo\") (file-directory-p \"/magic:foo\") \
+             (copy-file \"/tmp/x\" \"/magic:y\" t nil nil nil) \
+             (file-attributes \"/magic:foo\" string) other \
+             (file-readable-p \"/magic:foo.gz\") magic-handler nil \
+             (nil (file-readable-p \"/magic:foo\")) \
+             (make-nearby-temp-file \"x\" t nil))"
+        );
+    }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_file_io() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("rune-fileio-{}.txt", std::process::id()));
+        let file = file.to_string_lossy();
+        let form = format!(
+            r#"(progn
+                 (set-buffer (get-buffer-create "fileio"))
+                 (insert "abcdef")
+                 (write-region 2 5 "{file}")
+                 (write-region "xyz" nil "{file}" t)
+                 (write-region "B" nil "{file}" 0)
+                 (erase-buffer)
+                 (list (insert-file-contents "{file}" t)
+                       (buffer-string)
+                       (point)
+                       buffer-file-name
+                       (buffer-modified-p)
+                       (verify-visited-file-modtime)
+                       (progn (set-visited-file-modtime '(0 0))
+                              (verify-visited-file-modtime))
+                       (progn (insert "-")
+                              (insert-file-contents "{file}" nil 1 3)
+                              (buffer-string))
+                       (progn (goto-char 3)
+                              (insert-file-contents "{file}" nil nil nil t))
+                       (list (buffer-string) (point))
+                       (condition-case err
+                           (write-region "" nil "{file}" nil nil nil 'excl)
+                         (error (car err)))
+                       (condition-case err
+                           (insert-file-contents "{file}.missing")
+                         (error (car err)))))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let _ = fs::remove_file(&*file);
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "((\"{file}\" 6) \"Bcdxyz\" 1 \"{file}\" nil t nil \"-cdBcdxyz\" (\"{file}\" 0) \
+                 (\"Bcdxyz\" 1) file-already-exists file-missing)"
+            )
+        );
     }
 }
@@ -0,0 +1,371 @@
+//! Lock files, which record that a file is being edited. The lock of a file
+//! is a symbolic link in the same directory named `.#` followed by the name
+//! of the file, whose target is `USER@HOST.PID` of the process that holds
+//! it. A buffer visiting a file locks it when it is first modified, and
+//! unlocks it when it is saved, marked unmodified, or killed.
+use crate::core::{
+    env::{sym, Env},
+    error::EvalError,
+    gc::{Context, Rt},
+    object::{nil, Function, Gc, GcObj, LispString},
+};
+use crate::fileio::{call_file_handler, expand, file_io_error, HandlerArg};
+use crate::root;
+use crate::sysdep::{host_name, login_name};
+#[cfg(feature = "buffers")]
+use crate::{buffer::get_current_buffer, core::object::Buffer, fileio::visited_file};
+use anyhow::Result;
+use fn_macros::defun;
+use std::fmt::Display;
+use std::{fs, io, path::Path};
+
+/// The name of the lock file of `file`.
+fn lock_file_name(file: &str) -> String {
+    let start = file.rfind('/').map_or(0, |x| x + 1);
+    format!("{}.#{}", &file[..start], &file[start..])
+}
+
+/// The process that holds a lock.
+#[derive(Debug, PartialEq)]
+struct LockOwner {
+    user: String,
+    host: String,
+    pid: u32,
+}
+
+impl LockOwner {
+    fn current() -> Self {
+        Self {
+            user: login_name(),
+            host: host_name(),
+            pid: std::process::id(),
+        }
+    }
+
+    /// Parse the target of a lock, `USER@HOST.PID`, which may be followed by
+    /// `:BOOT-TIME`.
+    fn parse(info: &str) -> Option<Self> {
+        let info = info.split(':').next()?;
+        let (user, rest) = info.split_once('@')?;
+        let (host, pid) = rest.rsplit_once('.')?;
+        Some(Self {
+            user: user.to_owned(),
+            host: host.to_owned(),
+            pid: pid.parse().ok()?,
+        })
+    }
+
+    /// Whether the process is still running. Only processes on this host
+    /// can be checked, and the others are assumed to be.
+    fn is_alive(&self) -> bool {
+        self.host != host_name() || Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+}
+
+/// The lock target of the owner, `USER@HOST.PID`.
+impl Display for LockOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}.{}", self.user, self.host, self.pid)
+    }
+}
+
+enum LockState {
+    Unlocked,
+    Ours,
+    Other(LockOwner),
+}
+
+/// Who holds the lock file `lock`. A lock of a process on this host that has
+/// exited is removed.
+fn lock_state(lock: &str) -> io::Result<LockState> {
+    let info = match fs::read_link(lock) {
+        Ok(target) => target.to_string_lossy().into_owned(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LockState::Unlocked),
+        // a lock that is not a link holds the same text as its contents
+        Err(_) => match fs::read_to_string(lock) {
+            Ok(info) => info,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LockState::Unlocked),
+            Err(e) => return Err(e),
+        },
+    };
+    let Some(owner) = LockOwner::parse(&info) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid lock file",
+        ));
+    };
+    if owner == LockOwner::current() {
+        Ok(LockState::Ours)
+    } else if owner.is_alive() {
+        Ok(LockState::Other(owner))
+    } else {
+        fs::remove_file(lock)?;
+        Ok(LockState::Unlocked)
+    }
+}
+
+/// Ask `ask-user-about-lock` whether to take the lock of `file` from
+/// `owner`. It returns non-nil to take it and nil to edit `file` without
+/// locking it, or signals `file-locked` to leave it alone. If it is not
+/// defined, `file-locked` is signaled.
+fn ask_user_about_lock(
+    file: &str,
+    owner: &LockOwner,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let owner = format!("{}@{} (pid {})", owner.user, owner.host, owner.pid);
+    if !sym::ASK_USER_ABOUT_LOCK.has_func() {
+        let conditions = list![sym::FILE_LOCKED, sym::FILE_ERROR, sym::ERROR; cx];
+        crate::data::put(sym::FILE_LOCKED, sym::ERROR_CONDITIONS, conditions, env);
+        let data = list![file, owner; cx];
+        return Err(EvalError::signal(sym::FILE_LOCKED.into(), data, env).into());
+    }
+    let func: Gc<Function> = sym::ASK_USER_ABOUT_LOCK.into();
+    root!(func, cx);
+    let args = vec![cx.add(file), cx.add(owner)];
+    root!(args, move(args), cx);
+    Ok(!func.call(args, env, cx, None)?.nil())
+}
+
+/// Lock the absolute file name `file` for this process, unless
+/// `create-lockfiles` is nil.
+pub(crate) fn lock(file: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let create = env.vars.get(sym::CREATE_LOCKFILES);
+    if create.is_none_or(|x| x.bind(cx).nil()) {
+        return Ok(());
+    }
+    let lock = lock_file_name(file);
+    let target = LockOwner::current().to_string();
+    loop {
+        match std::os::unix::fs::symlink(&target, &lock) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(file_io_error(&e, "Locking file", file, env, cx)),
+        }
+        match lock_state(&lock).map_err(|e| file_io_error(&e, "Locking file", file, env, cx))? {
+            LockState::Unlocked => {}
+            LockState::Ours => return Ok(()),
+            LockState::Other(owner) => {
+                if !ask_user_about_lock(file, &owner, env, cx)? {
+                    return Ok(());
+                }
+                if let Err(e) = fs::remove_file(&lock) {
+                    return Err(file_io_error(&e, "Stealing lock", file, env, cx));
+                }
+            }
+        }
+    }
+}
+
+/// Remove the lock of the absolute file name `file` if this process holds
+/// it.
+pub(crate) fn unlock(file: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let lock = lock_file_name(file);
+    let result = match lock_state(&lock) {
+        Ok(LockState::Ours) => fs::remove_file(&lock),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(file_io_error(&e, "Unlocking file", file, env, cx))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Lock FILE for editing by this process. If another process holds the
+/// lock, `ask-user-about-lock` decides whether to take it. Nothing is done
+/// if `create-lockfiles` is nil.
+#[defun]
+fn lock_file<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(file, env, cx)?;
+    if let Some(x) = call_file_handler(sym::LOCK_FILE, &[HandlerArg::File(&file)], env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    lock(&file, env, cx)?;
+    Ok(nil())
+}
+
+/// Remove the lock of FILE if this process holds it.
+#[defun]
+fn unlock_file<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(file, env, cx)?;
+    if let Some(x) = call_file_handler(sym::UNLOCK_FILE, &[HandlerArg::File(&file)], env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    unlock(&file, env, cx)?;
+    Ok(nil())
+}
+
+/// Return nil if FILENAME is not locked, t if this process holds its lock,
+/// or else the name of the user who does.
+#[defun]
+fn file_locked_p<'ob>(
+    filename: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(filename, env, cx)?;
+    let args = [HandlerArg::File(&file)];
+    if let Some(x) = call_file_handler(sym::FILE_LOCKED_P, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    match lock_state(&lock_file_name(&file)) {
+        Ok(LockState::Unlocked) => Ok(nil()),
+        Ok(LockState::Ours) => Ok(true.into()),
+        Ok(LockState::Other(owner)) => Ok(cx.add(owner.user)),
+        Err(e) => Err(file_io_error(&e, "Testing file lock", &file, env, cx)),
+    }
+}
+
+/// Lock the file `buffer` visits, if it is about to be modified for the
+/// first time since it was last unmodified.
+#[cfg(feature = "buffers")]
+pub(crate) fn lock_buffer_file(
+    buffer: &'static Buffer,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if buffer.lock()?.is_modified() {
+        return Ok(());
+    }
+    match visited_file(buffer, env, cx) {
+        Some(file) => lock(&file, env, cx),
+        None => Ok(()),
+    }
+}
+
+/// Unlock the file `buffer` visits, if it is modified and so holds the lock.
+#[cfg(feature = "buffers")]
+pub(crate) fn unlock_buffer_file(
+    buffer: &'static Buffer,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    if !buffer.lock()?.is_modified() {
+        return Ok(());
+    }
+    match visited_file(buffer, env, cx) {
+        Some(file) => unlock(&file, env, cx),
+        None => Ok(()),
+    }
+}
+
+/// Lock FILE, or the file the current buffer visits, if the buffer is
+/// modified.
+#[cfg(feature = "buffers")]
+#[defun]
+fn lock_buffer(
+    file: Option<&Rt<Gc<&LispString>>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let file = match file {
+        Some(file) => Some(expand(file, env, cx)?),
+        None => visited_file(buffer, env, cx),
+    };
+    if let Some(file) = file {
+        if buffer.lock()?.is_modified() {
+            lock(&file, env, cx)?;
+        }
+    }
+    Ok(false)
+}
+
+/// Unlock the file the current buffer visits, if the buffer is modified.
+#[cfg(feature = "buffers")]
+#[defun]
+fn unlock_buffer(env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    unlock_buffer_file(get_current_buffer(env), env, cx)?;
+    Ok(false)
+}
+
+defsym!(FILE_LOCKED);
+defsym!(ASK_USER_ABOUT_LOCK);
+defvar!(CREATE_LOCKFILES, true);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader};
+
+    #[test]
+    fn test_lock_owner() {
+        let owner = LockOwner::parse("me@host.name.42:1700000000").unwrap();
+        assert_eq!(owner.to_string(), "me@host.name.42");
+        assert!(LockOwner::parse("me@host").is_none());
+        assert_eq!(lock_file_name("/a/b.txt"), "/a/.#b.txt");
+    }
+
+    #[test]
+    fn test_file_locks() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-filelock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let host = host_name();
+        // pid 1 is always running, and this one is out of range
+        std::os::unix::fs::symlink(format!("other@{host}.1"), dir.join(".#held")).unwrap();
+        std::os::unix::fs::symlink(format!("other@{host}.99999999"), dir.join(".#dead")).unwrap();
+        let dir = dir.to_string_lossy();
+        let form = format!(
+            r#"(progn
+                 (setq create-lockfiles t)
+                 (list (progn (lock-file "{dir}/mine") (file-locked-p "{dir}/mine"))
+                       (file-locked-p "{dir}/held")
+                       (file-locked-p "{dir}/dead")
+                       (file-symlink-p "{dir}/.#dead")
+                       (condition-case err (lock-file "{dir}/held") (error (car err)))
+                       (progn (defalias 'ask-user-about-lock #'(lambda (_file _owner) t))
+                              (lock-file "{dir}/held")
+                              (file-locked-p "{dir}/held"))
+                       (progn (unlock-file "{dir}/mine") (file-locked-p "{dir}/mine"))))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx);
+        let _ = fs::remove_dir_all(&*dir);
+        assert_eq!(
+            result.unwrap().to_string(),
+            "(t \"other\" nil nil file-locked t nil)"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "buffers")]
+    fn test_buffer_locks() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let file = std::env::temp_dir().join(format!("rune-buflock-{}", std::process::id()));
+        let file = file.to_string_lossy();
+        let form = format!(
+            r#"(progn
+                 (setq create-lockfiles t)
+                 (set-buffer (get-buffer-create "rune-buflock"))
+                 (setq buffer-file-name "{file}")
+                 (list (file-locked-p "{file}")
+                       (progn (insert "a") (file-locked-p "{file}"))
+                       (progn (set-buffer-modified-p nil) (file-locked-p "{file}"))
+                       (progn (set-buffer-modified-p t) (file-locked-p "{file}"))
+                       (progn (kill-buffer) (file-locked-p "{file}"))))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(result.to_string(), "(nil t nil t nil)");
+    }
+}
@@ -0,0 +1,550 @@
+//! File change notification. A watch reports changes to a file or to the
+//! files in a directory by calling its callback from the event loop with the
+//! watch descriptor, the action, and the file names, as described in
+//! `file-notify-add-watch`. Files are watched with inotify, which is only
+//! available on Linux.
+use crate::core::{
+    env::{sym, Env, Symbol},
+    error::EvalError,
+    gc::{Context, Rt},
+    object::{nil, Function, Gc, GcObj, LispString, Object},
+};
+use crate::fileio::{call_file_handler, expand, HandlerArg};
+use crate::fns::slice_into_list;
+use crate::root;
+use anyhow::{ensure, Result};
+use fn_macros::defun;
+use std::fs::File;
+use std::{io, path::Path, sync::Mutex};
+
+const MODIFY: u32 = 0x2;
+const ATTRIB: u32 = 0x4;
+const MOVED_FROM: u32 = 0x40;
+const MOVED_TO: u32 = 0x80;
+const CREATE: u32 = 0x100;
+const DELETE: u32 = 0x200;
+const DELETE_SELF: u32 = 0x400;
+const MOVE_SELF: u32 = 0x800;
+/// The watch was removed, because it was asked to or its file is gone.
+const IGNORED: u32 = 0x8000;
+/// The events every watch asks for. Watches of the same directory share one
+/// inotify watch, so each filters them by its own flags.
+const WATCHED: u32 =
+    MODIFY | ATTRIB | MOVED_FROM | MOVED_TO | CREATE | DELETE | DELETE_SELF | MOVE_SELF;
+
+/// An event read from inotify.
+struct RawEvent {
+    wd: i32,
+    mask: u32,
+    /// Pairs the two halves of a rename
+    cookie: u32,
+    /// The name of the file in the watched directory, or empty for the
+    /// directory itself
+    name: String,
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use super::RawEvent;
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::raw::{c_char, c_int};
+
+    extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+        fn inotify_rm_watch(fd: c_int, wd: c_int) -> c_int;
+    }
+
+    const NONBLOCK: c_int = 0o4000;
+    const CLOEXEC: c_int = 0o2_000_000;
+    /// The size of `struct inotify_event` without the name.
+    const HEADER: usize = 16;
+
+    pub(super) fn init() -> io::Result<File> {
+        let fd = unsafe { inotify_init1(NONBLOCK | CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    pub(super) fn add_watch(inotify: &File, path: &str, mask: u32) -> io::Result<i32> {
+        let path = CString::new(path)?;
+        match unsafe { inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), mask) } {
+            wd if wd < 0 => Err(io::Error::last_os_error()),
+            wd => Ok(wd),
+        }
+    }
+
+    pub(super) fn rm_watch(inotify: &File, wd: i32) -> io::Result<()> {
+        match unsafe { inotify_rm_watch(inotify.as_raw_fd(), wd) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Read the events that are ready without blocking.
+    pub(super) fn read_events(mut inotify: &File) -> io::Result<Vec<RawEvent>> {
+        let mut events = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let len = match inotify.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(events),
+                Err(e) => return Err(e),
+            };
+            let mut rest = &buf[..len];
+            while rest.len() >= HEADER {
+                let field = |i: usize| rest[i * 4..i * 4 + 4].try_into().unwrap();
+                let name_len = u32::from_ne_bytes(field(3)) as usize;
+                let name = &rest[HEADER..HEADER + name_len];
+                // the name is padded with nul bytes
+                let name = name.split(|&x| x == 0).next().unwrap_or_default();
+                events.push(RawEvent {
+                    wd: i32::from_ne_bytes(field(0)),
+                    mask: u32::from_ne_bytes(field(1)),
+                    cookie: u32::from_ne_bytes(field(2)),
+                    name: String::from_utf8_lossy(name).into_owned(),
+                });
+                rest = &rest[HEADER + name_len..];
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod backend {
+    use super::RawEvent;
+    use std::fs::File;
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "No file notification backend")
+    }
+
+    pub(super) fn init() -> io::Result<File> {
+        Err(unsupported())
+    }
+
+    pub(super) fn add_watch(_: &File, _: &str, _: u32) -> io::Result<i32> {
+        Err(unsupported())
+    }
+
+    pub(super) fn rm_watch(_: &File, _: i32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn read_events(_: &File) -> io::Result<Vec<RawEvent>> {
+        Ok(Vec::new())
+    }
+}
+
+/// A watch made by `file-notify-add-watch`.
+struct Watch {
+    descriptor: i64,
+    wd: i32,
+    /// The watched directory, or the directory of the watched file
+    dir: String,
+    /// The name of the watched file in `dir`, or `None` if `dir` is watched
+    file: Option<String>,
+    change: bool,
+    attribute_change: bool,
+}
+
+impl Watch {
+    /// The absolute name of `name` in the watched directory.
+    fn path(&self, name: &str) -> String {
+        match name {
+            "" => self.dir.clone(),
+            name => format!("{}/{name}", self.dir.trim_end_matches('/')),
+        }
+    }
+
+    /// Whether events about `name` in the watched directory are reported.
+    fn reports(&self, name: &str) -> bool {
+        self.file.as_ref().is_none_or(|x| x.as_str() == name)
+    }
+}
+
+struct Watcher {
+    inotify: Option<File>,
+    watches: Vec<Watch>,
+    next_descriptor: i64,
+}
+
+static WATCHER: Mutex<Watcher> = Mutex::new(Watcher {
+    inotify: None,
+    watches: Vec::new(),
+    next_descriptor: 1,
+});
+
+/// A change to report to the callback of a watch.
+struct Event {
+    descriptor: i64,
+    action: Symbol<'static>,
+    file: String,
+    /// The new name of a renamed file
+    file1: Option<String>,
+}
+
+/// Translate `raw` into the events of each watch, and forget the watches
+/// that stopped. A rename within the watched directories is reported once,
+/// as `renamed`, and otherwise as `deleted` or `created`.
+fn translate(raw: &[RawEvent], watcher: &mut Watcher) -> Vec<Event> {
+    let mut events = Vec::new();
+    for (idx, event) in raw.iter().enumerate() {
+        let is_move = |x: &RawEvent, mask| x.mask & mask != 0 && x.cookie == event.cookie;
+        if event.mask & MOVED_TO != 0 && raw[..idx].iter().any(|x| is_move(x, MOVED_FROM)) {
+            continue;
+        }
+        let moved_to = match event.mask & MOVED_FROM {
+            0 => None,
+            _ => raw[idx + 1..].iter().find(|x| is_move(x, MOVED_TO)),
+        };
+        let moved_to = moved_to.and_then(|to| {
+            let watch = watcher.watches.iter().find(|x| x.wd == to.wd)?;
+            Some((watch.path(&to.name), to.name.as_str()))
+        });
+        let mut stopped = Vec::new();
+        for watch in watcher.watches.iter().filter(|x| x.wd == event.wd) {
+            let mut report = |action, file1: Option<String>| {
+                events.push(Event {
+                    descriptor: watch.descriptor,
+                    action,
+                    file: watch.path(&event.name),
+                    file1,
+                });
+            };
+            if event.mask & IGNORED != 0 {
+                report(sym::STOPPED, None);
+                stopped.push(watch.descriptor);
+                continue;
+            }
+            let reported =
+                watch.reports(&event.name) || moved_to.as_ref().is_some_and(|x| watch.reports(x.1));
+            if !reported || (event.name.is_empty() && watch.file.is_some()) {
+                continue;
+            }
+            if watch.change {
+                if let Some((to, _)) = &moved_to {
+                    report(sym::RENAMED, Some(to.clone()));
+                } else if event.mask & (CREATE | MOVED_TO) != 0 {
+                    report(sym::CREATED, None);
+                } else if event.mask & (DELETE | MOVED_FROM | DELETE_SELF | MOVE_SELF) != 0 {
+                    report(sym::DELETED, None);
+                } else if event.mask & MODIFY != 0 {
+                    report(sym::CHANGED, None);
+                }
+            }
+            if watch.attribute_change && event.mask & ATTRIB != 0 {
+                report(sym::ATTRIBUTE_CHANGED, None);
+            }
+            // a watched file that is gone stops its watch
+            if watch.file.is_some() && event.mask & DELETE != 0 {
+                report(sym::STOPPED, None);
+                stopped.push(watch.descriptor);
+            }
+        }
+        watcher.watches.retain(|x| !stopped.contains(&x.descriptor));
+    }
+    events
+}
+
+/// Signal `file-notify-error` for the failure `err` to watch `file`.
+fn notify_error(err: &io::Error, file: &str, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+    let conditions = list![sym::FILE_NOTIFY_ERROR, sym::FILE_ERROR, sym::ERROR; cx];
+    crate::data::put(
+        sym::FILE_NOTIFY_ERROR,
+        sym::ERROR_CONDITIONS,
+        conditions,
+        env,
+    );
+    let reason = err.to_string();
+    let reason = reason.split(" (os error").next().unwrap_or_default();
+    let data = list!["Cannot watch file", reason, file; cx];
+    EvalError::signal(sym::FILE_NOTIFY_ERROR.into(), data, env).into()
+}
+
+/// The callbacks of the watches, by descriptor.
+fn descriptors<'ob>(env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    env.vars
+        .get(sym::FILE_NOTIFY_DESCRIPTORS)
+        .map_or_else(nil, |x| x.bind(cx))
+}
+
+/// The callback of the watch `descriptor`.
+fn callback<'ob>(descriptor: i64, env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    for entry in descriptors(env, cx).as_list()? {
+        if let Object::Cons(entry) = entry?.untag() {
+            if matches!(entry.car().untag(), Object::Int(x) if x == descriptor) {
+                return Ok(entry.cdr());
+            }
+        }
+    }
+    Ok(nil())
+}
+
+/// Forget the callback of the watch `descriptor`.
+fn remove_descriptor(descriptor: i64, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let mut kept = Vec::new();
+    for entry in descriptors(env, cx).as_list()? {
+        let entry = entry?;
+        match entry.untag() {
+            Object::Cons(cons) if matches!(cons.car().untag(), Object::Int(x) if x == descriptor) =>
+                {}
+            _ => kept.push(entry),
+        }
+    }
+    let list = slice_into_list(&kept, None, cx);
+    env.vars.insert(sym::FILE_NOTIFY_DESCRIPTORS, list);
+    Ok(())
+}
+
+/// Whether any watch is active, so the event loop has to look for events.
+pub(crate) fn is_watching() -> bool {
+    !WATCHER.lock().unwrap().watches.is_empty()
+}
+
+/// Read the pending file notifications and pass each to
+/// `file-notify-handle-event`.
+pub(crate) fn dispatch_events(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let events = {
+        let mut watcher = WATCHER.lock().unwrap();
+        let Some(inotify) = &watcher.inotify else {return Ok(())};
+        let raw = backend::read_events(inotify)?;
+        translate(&raw, &mut watcher)
+    };
+    for event in events {
+        let callback = callback(event.descriptor, env, cx)?;
+        let mut data = vec![
+            event.descriptor.into(),
+            event.action.into(),
+            cx.add(event.file),
+        ];
+        data.extend(event.file1.map(|x| cx.add(x)));
+        let data = slice_into_list(&data, None, cx);
+        if event.action == sym::STOPPED {
+            remove_descriptor(event.descriptor, env, cx)?;
+        }
+        let event = list![sym::FILE_NOTIFY, data, callback; cx];
+        root!(event, cx);
+        file_notify_handle_event(event, env, cx)?;
+    }
+    Ok(())
+}
+
+/// Handle the file notification EVENT, which has the form
+/// `(file-notify EVENT-DATA CALLBACK)`, by calling CALLBACK with EVENT-DATA.
+#[defun]
+fn file_notify_handle_event<'ob>(
+    event: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let parts = event.bind(cx).as_list()?.collect::<Result<Vec<_>, _>>()?;
+    ensure!(
+        parts.len() == 3 && matches!(parts[0].untag(), Object::Symbol(sym::FILE_NOTIFY)),
+        "Not a valid file-notify event"
+    );
+    let call = vec![parts[2], parts[1]];
+    root!(call, move(call), cx);
+    let callback = &call[0];
+    let callback: &Rt<Gc<Function>> = callback.try_into()?;
+    crate::eval::funcall(callback, &call[1..], env, cx)
+}
+
+/// Watch FILE for changes and return a descriptor of the watch. If FILE is
+/// a directory, the files in it are watched too. FLAGS is a list of the
+/// kinds of changes to report: `change` for changes to the contents or the
+/// name of a file, and `attribute-change` for changes to its attributes.
+///
+/// CALLBACK is called from the event loop with a list of the descriptor,
+/// the action, and the absolute name of the file, followed by its new name
+/// if it was renamed. The action is one of `created`, `deleted`, `changed`,
+/// `renamed`, `attribute-changed`, or `stopped`, which is the last event of
+/// a watch, once it was removed or FILE is gone.
+#[defun]
+fn file_notify_add_watch<'ob>(
+    file: &Rt<Gc<&LispString>>,
+    flags: &Rt<GcObj>,
+    callback: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let file = expand(file, env, cx)?;
+    let args = [
+        HandlerArg::File(&file),
+        HandlerArg::Obj(Some(flags)),
+        HandlerArg::Obj(Some(callback)),
+    ];
+    if let Some(x) = call_file_handler(sym::FILE_NOTIFY_ADD_WATCH, &args, env, cx)? {
+        return Ok(rebind!(x, cx));
+    }
+    let mut change = false;
+    let mut attribute_change = false;
+    for flag in flags.bind(cx).as_list()? {
+        match flag?.untag() {
+            Object::Symbol(sym::CHANGE) => change = true,
+            Object::Symbol(sym::ATTRIBUTE_CHANGE) => attribute_change = true,
+            _ => {}
+        }
+    }
+    // a file is watched through its directory, so it is still watched when
+    // it is replaced by a new file
+    let path = Path::new(&file);
+    let (dir, name) = match path.is_dir() {
+        true => (file.trim_end_matches('/').to_owned(), None),
+        false => {
+            let dir = path
+                .parent()
+                .map_or_else(|| "/".into(), |x| x.to_string_lossy());
+            let name = path.file_name().map(|x| x.to_string_lossy().into_owned());
+            (dir.into_owned(), name)
+        }
+    };
+    let dir = if dir.is_empty() { "/".to_owned() } else { dir };
+    let descriptor = {
+        let mut watcher = WATCHER.lock().unwrap();
+        let watched = match &watcher.inotify {
+            Some(inotify) => Ok(inotify),
+            None => backend::init().map(|x| &*watcher.inotify.insert(x)),
+        };
+        let added = watched.and_then(|inotify| match (name.is_none() || path.exists(), inotify) {
+            (true, inotify) => backend::add_watch(inotify, &dir, WATCHED),
+            (false, _) => Err(io::ErrorKind::NotFound.into()),
+        });
+        let wd = match added {
+            Ok(wd) => wd,
+            Err(e) => {
+                drop(watcher);
+                return Err(notify_error(&e, &file, env, cx));
+            }
+        };
+        let descriptor = watcher.next_descriptor;
+        watcher.next_descriptor += 1;
+        watcher.watches.push(Watch {
+            descriptor,
+            wd,
+            dir,
+            file: name,
+            change,
+            attribute_change,
+        });
+        descriptor
+    };
+    let entry = cons!(descriptor, callback.bind(cx); cx);
+    let list = cons!(entry, descriptors(env, cx); cx);
+    env.vars.insert(sym::FILE_NOTIFY_DESCRIPTORS, list);
+    Ok(descriptor.into())
+}
+
+/// Remove the watch DESCRIPTOR. Its callback is called with a `stopped`
+/// event.
+#[defun]
+fn file_notify_rm_watch(descriptor: i64, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let watch = {
+        let mut watcher = WATCHER.lock().unwrap();
+        let Some(idx) = watcher
+            .watches
+            .iter()
+            .position(|x| x.descriptor == descriptor)
+        else {return Ok(false)};
+        let watch = watcher.watches.remove(idx);
+        // the inotify watch is shared with the other watches of the directory
+        if !watcher.watches.iter().any(|x| x.wd == watch.wd) {
+            if let Some(inotify) = &watcher.inotify {
+                // the watch is already gone if its directory was removed
+                let _ = backend::rm_watch(inotify, watch.wd);
+            }
+        }
+        watch
+    };
+    let callback = callback(descriptor, env, cx)?;
+    remove_descriptor(descriptor, env, cx)?;
+    let file = watch.path(watch.file.as_deref().unwrap_or_default());
+    let data = list![descriptor, sym::STOPPED, file; cx];
+    let event = list![sym::FILE_NOTIFY, data, callback; cx];
+    root!(event, cx);
+    file_notify_handle_event(event, env, cx)?;
+    Ok(false)
+}
+
+/// Return t if DESCRIPTOR is a watch that is still active.
+#[defun]
+fn file_notify_valid_p(descriptor: GcObj) -> bool {
+    let watcher = WATCHER.lock().unwrap();
+    watcher
+        .watches
+        .iter()
+        .any(|x| matches!(descriptor.untag(), Object::Int(d) if d == x.descriptor))
+}
+
+defsym!(FILE_NOTIFY);
+defsym!(FILE_NOTIFY_ERROR);
+defsym!(CHANGE);
+defsym!(ATTRIBUTE_CHANGE);
+defsym!(CREATED);
+defsym!(DELETED);
+defsym!(CHANGED);
+defsym!(RENAMED);
+defsym!(ATTRIBUTE_CHANGED);
+defsym!(STOPPED);
+defvar!(FILE_NOTIFY_DESCRIPTORS);
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader};
+
+    #[test]
+    fn test_file_notify() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-filenotify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), "").unwrap();
+        let name = dir.to_string_lossy().into_owned();
+        let eval = |form: &str, env: &mut Rt<Env>, cx: &mut Context| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        let form = format!(
+            r#"(progn
+                 (setq dir-events nil file-events nil)
+                 (defalias 'record-dir #'(lambda (e) (setq dir-events (cons (cdr e) dir-events))))
+                 (defalias 'record-file #'(lambda (e) (setq file-events (cons (cdr e) file-events))))
+                 (list (file-notify-add-watch "{name}" '(change) 'record-dir)
+                       (file-notify-add-watch "{name}/a" '(change attribute-change) 'record-file)))"#
+        );
+        let descriptors = eval(&form, env, cx);
+        std::fs::write(dir.join("b"), "text").unwrap();
+        std::fs::write(dir.join("a"), "text").unwrap();
+        std::fs::rename(dir.join("b"), dir.join("c")).unwrap();
+        std::fs::remove_file(dir.join("a")).unwrap();
+        dispatch_events(env, cx).unwrap();
+        let file_valid = eval("(file-notify-valid-p 2)", env, cx);
+        eval("(file-notify-rm-watch 1)", env, cx);
+        let events = eval(
+            "(list (nreverse dir-events) (nreverse file-events) (file-notify-valid-p 1))",
+            env,
+            cx,
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(descriptors, "(1 2)");
+        assert_eq!(file_valid, "nil");
+        let events = events.replace(&name, "DIR");
+        assert_eq!(
+            events,
+            "(((created \"DIR/b\") (changed \"DIR/b\") (changed \"DIR/a\") \
+             (renamed \"DIR/b\" \"DIR/c\") (deleted \"DIR/a\") (stopped \"DIR\")) \
+             ((changed \"DIR/a\") (deleted \"DIR/a\") (stopped \"DIR/a\")) nil)"
+        );
+    }
+}
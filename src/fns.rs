@@ -1,8 +1,8 @@
 use crate::{
     core::{
         cons::Cons,
-        env::{sym, Env, Symbol},
-        error::{Type, TypeError},
+        env::{sym, Env, Symbol, TextProps},
+        error::{EvalError, Type, TypeError},
         gc::{Context, IntoRoot, Rt},
         object::{
             nil, Function, Gc, GcObj, HashTable, IntoObject, LispHashTable, LispString, LispVec,
@@ -10,11 +10,13 @@ use crate::{
         },
     },
     data::aref,
+    intervals::IntervalTree,
 };
 use crate::{root, rooted_iter};
 use anyhow::{bail, ensure, Result};
 use bstr::ByteSlice;
 use fn_macros::defun;
+use std::sync::Mutex;
 use streaming_iterator::StreamingIterator;
 
 #[defun]
@@ -49,14 +51,39 @@ pub(crate) fn eql<'ob>(obj1: GcObj<'ob>, obj2: GcObj<'ob>) -> bool {
     }
 }
 
+/// Like `equal`, but strings must also have the same text properties, with
+/// `equal` values.
 #[defun]
 fn equal_including_properties<'ob>(o1: GcObj<'ob>, o2: GcObj<'ob>) -> bool {
-    // TODO: implement text properties
-    equal(o1, o2)
+    match (o1.untag(), o2.untag()) {
+        (Object::String(s1), Object::String(s2)) => s1 == s2 && same_props(s1, s2),
+        _ => equal(o1, o2),
+    }
+}
+
+/// Whether S1 and S2 have the same text properties on the same runs of text.
+fn same_props(s1: &LispString, s2: &LispString) -> bool {
+    fn runs<'a, 'ob>(
+        props: Option<&'a IntervalTree<TextProps<'ob>>>,
+    ) -> Vec<(usize, usize, &'a TextProps<'ob>)> {
+        let intervals = props.iter().flat_map(|x| x.iter_from(0));
+        intervals.filter(|x| !x.2.is_empty()).collect()
+    }
+    let (props1, props2) = (s1.props(), s2.props());
+    let (runs1, runs2) = (runs(props1.as_ref()), runs(props2.as_ref()));
+    runs1.len() == runs2.len()
+        && runs1.iter().zip(&runs2).all(|(a, b)| {
+            a.0 == b.0
+                && a.1 == b.1
+                && a.2.len() == b.2.len()
+                && a.2
+                    .iter()
+                    .all(|x| b.2.iter().any(|y| x.0 == y.0 && equal(x.1, y.1)))
+        })
 }
 
 #[defun]
-fn plist_get<'ob>(plist: Gc<List<'ob>>, prop: GcObj<'ob>) -> Result<GcObj<'ob>> {
+pub(crate) fn plist_get<'ob>(plist: Gc<List<'ob>>, prop: GcObj<'ob>) -> Result<GcObj<'ob>> {
     // TODO: this function should never fail. Need to implement safe iterator
     let iter = plist.elements().zip(plist.elements().skip(1));
 
@@ -68,6 +95,71 @@ fn plist_get<'ob>(plist: Gc<List<'ob>>, prop: GcObj<'ob>) -> Result<GcObj<'ob>>
     Ok(nil())
 }
 
+/// Return non-nil if OBJECT is a proper list with an even number of elements.
+#[defun]
+fn plistp(object: GcObj) -> bool {
+    let Ok(list) = Gc::<List>::try_from(object) else {return false};
+    let mut len = 0;
+    for tail in list.conses() {
+        if tail.is_err() {
+            return false;
+        }
+        len += 1;
+    }
+    len % 2 == 0
+}
+
+/// Return OBJECT if it is a list, otherwise a list containing it.
+#[defun]
+fn ensure_list<'ob>(object: GcObj<'ob>, cx: &'ob Context) -> GcObj<'ob> {
+    match object.untag() {
+        Object::Cons(_) | Object::NIL => object,
+        _ => list![object; cx],
+    }
+}
+
+/// Ignore ARGUMENTS and return t.
+#[defun]
+fn always(_arguments: &[GcObj]) -> bool {
+    true
+}
+
+/// Return the one of COND1 and COND2 that is non-nil if the other is nil,
+/// and nil otherwise.
+#[defun]
+fn xor<'ob>(cond1: GcObj<'ob>, cond2: GcObj<'ob>) -> GcObj<'ob> {
+    match (cond1.nil(), cond2.nil()) {
+        (true, _) => cond2,
+        (false, true) => cond1,
+        (false, false) => nil(),
+    }
+}
+
+/// Return the chain of symbols that FUNC is an alias for, or nil if FUNC is
+/// not a symbol aliased to another symbol. Signal an error if the chain is
+/// cyclic unless NOERROR is non-nil.
+#[defun]
+fn function_alias_p<'ob>(
+    func: GcObj<'ob>,
+    noerror: Option<GcObj>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let Object::Symbol(mut symbol) = func.untag() else {return Ok(nil())};
+    let mut chain: Vec<GcObj> = Vec::new();
+    while let Some(Function::Symbol(next)) = symbol.func(cx).map(Gc::untag) {
+        if chain.contains(&next.into()) {
+            ensure!(
+                noerror.is_some_and(|x| !x.nil()),
+                "Cyclic function indirection: {func}"
+            );
+            break;
+        }
+        chain.push(next.into());
+        symbol = next;
+    }
+    Ok(slice_into_list(&chain, None, cx))
+}
+
 #[defun]
 pub(crate) fn prin1_to_string(object: GcObj, _noescape: Option<GcObj>) -> String {
     format!("{object}")
@@ -87,6 +179,10 @@ pub(crate) fn mapcar<'ob>(
             rooted_iter!(iter, cons, cx);
             mapcar_internal(iter, function, env, cx)
         }
+        Object::Vec(vec) => {
+            root!(vec, cx);
+            mapcar_internal(vec.iter(), function, env, cx)
+        }
         Object::ByteFn(fun) => {
             root!(fun, cx);
             mapcar_internal(fun.iter(), function, env, cx)
@@ -120,23 +216,41 @@ where
 #[defun]
 pub(crate) fn mapc<'ob>(
     function: &Rt<Gc<Function>>,
-    sequence: &Rt<Gc<List>>,
+    sequence: &Rt<GcObj>,
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
 ) -> Result<GcObj<'ob>> {
-    match sequence.get(cx) {
-        List::Nil => Ok(nil()),
-        List::Cons(cons) => {
-            root!(call_arg, Vec::new(), cx);
+    match sequence.bind(cx).untag() {
+        Object::NIL => {}
+        Object::Cons(cons) => {
             rooted_iter!(elements, cons, cx);
-            while let Some(elem) = elements.next() {
-                call_arg.push(elem);
-                function.call(call_arg, env, cx, None)?;
-                call_arg.clear();
-            }
-            Ok(sequence.bind(cx).into())
+            mapc_internal(elements, function, env, cx)?;
+        }
+        Object::Vec(vec) => {
+            root!(vec, cx);
+            mapc_internal(vec.iter(), function, env, cx)?;
         }
+        _ => bail!(TypeError::new(Type::Sequence, sequence.bind(cx))),
     }
+    Ok(sequence.bind(cx))
+}
+
+fn mapc_internal<T>(
+    mut iter: T,
+    function: &Rt<Gc<Function>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()>
+where
+    T: StreamingIterator<Item = Rt<GcObj<'static>>>,
+{
+    root!(call_arg, Vec::new(), cx);
+    while let Some(elem) = iter.next() {
+        call_arg.push(elem);
+        function.call(call_arg, env, cx, None)?;
+        call_arg.clear();
+    }
+    Ok(())
 }
 
 #[defun]
@@ -361,38 +475,86 @@ pub(crate) fn defvaralias<'ob>(
     new_alias
 }
 
+/// Return t if FEATURE has been provided, and if SUBFEATURE is non-nil, if
+/// it is one of the subfeatures of FEATURE.
 #[defun]
-pub(crate) fn featurep(_feature: Symbol, _subfeature: Option<Symbol>) -> bool {
-    // TODO: implement
-    false
+pub(crate) fn featurep(
+    feature: Symbol,
+    subfeature: Option<GcObj>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let feat = unsafe { feature.into_root() };
+    if !crate::data::FEATURES.lock().unwrap().contains(&feat) {
+        return Ok(false);
+    }
+    match subfeature {
+        Some(sub) => {
+            let subfeatures = crate::data::get(feature, sym::SUBFEATURES, env, cx);
+            Ok(!member(sub, subfeatures.try_into()?)?.nil())
+        }
+        None => Ok(true),
+    }
 }
 
+/// How many times a feature can be required while it is already being
+/// required before that is an error.
+const MAX_REQUIRE_NESTING: usize = 3;
+
+/// The features that are being required, innermost last.
+static REQUIRE_NESTING: Mutex<Vec<Symbol<'static>>> = Mutex::new(Vec::new());
+
+/// Load FEATURE from FILENAME, or from the file named after it, unless it
+/// has been provided already. If NOERROR is non-nil, nil is returned if the
+/// file is not found. It is an error if the file does not provide FEATURE.
 #[defun]
 fn require<'ob>(
     feature: &Rt<Gc<Symbol>>,
     filename: Option<&Rt<Gc<&LispString>>>,
-    noerror: Option<()>,
+    noerror: Option<&Rt<GcObj>>,
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
 ) -> Result<Symbol<'ob>> {
     // TODO: Fix this unsafe into_root
     let feat = unsafe { feature.get(cx).into_root() };
+    if env
+        .vars
+        .get(sym::LOAD_IN_PROGRESS)
+        .is_some_and(|x| !x.bind(cx).nil())
+    {
+        crate::lread::record_load_entry(cons!(sym::REQUIRE, feat; cx), env, cx);
+    }
     if crate::data::FEATURES.lock().unwrap().contains(&feat) {
         return Ok(feature.get(cx));
     }
+    {
+        let mut nesting = REQUIRE_NESTING.lock().unwrap();
+        let count = nesting.iter().filter(|x| **x == feat).count();
+        ensure!(
+            count <= MAX_REQUIRE_NESTING,
+            "Recursive `require' for feature `{feat}'"
+        );
+        nesting.push(feat);
+    }
+    let must_suffix: GcObj = filename.is_none().into();
+    root!(must_suffix, cx);
     let file = match filename {
         Some(file) => file.get(cx).try_into()?,
         None => feature.get(cx).get().name(),
     };
     let file = file.into_obj(cx);
     root!(file, cx);
-    match crate::lread::load(file, None, None, cx, env) {
-        Ok(_) => Ok(feature.get(cx)),
-        Err(e) => match noerror {
-            Some(()) => Ok(sym::NIL),
-            None => Err(e),
-        },
+    let loaded = crate::lread::load(file, noerror, None, None, Some(must_suffix), cx, env);
+    REQUIRE_NESTING.lock().unwrap().pop();
+    if !loaded? {
+        return Ok(sym::NIL);
     }
+    ensure!(
+        crate::data::FEATURES.lock().unwrap().contains(&feat),
+        "Loading file {file} failed to provide feature `{feat}'",
+        file = file.get(cx)
+    );
+    Ok(feature.get(cx))
 }
 
 #[defun]
@@ -474,6 +636,26 @@ pub(crate) fn nthcdr(n: usize, list: Gc<List>) -> Result<Gc<List>> {
     }
 }
 
+/// Return a copy of the first N elements of LIST. If N is zero or negative
+/// return nil, and if LIST is shorter than N return a copy of all of it.
+#[defun]
+fn take<'ob>(n: i64, list: Gc<List<'ob>>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let n = usize::try_from(n).unwrap_or(0);
+    let elements: Vec<_> = list.elements().take(n).collect::<Result<_>>()?;
+    Ok(slice_into_list(&elements, None, cx))
+}
+
+/// Modify LIST to keep only its first N elements and return it. If N is
+/// zero or negative return nil.
+#[defun]
+fn ntake(n: i64, list: Gc<List>) -> Result<GcObj> {
+    let Ok(n @ 1..) = usize::try_from(n) else {return Ok(nil())};
+    if let Some(tail) = list.conses().nth(n - 1) {
+        tail?.set_cdr(nil())?;
+    }
+    Ok(list.into())
+}
+
 #[defun]
 pub(crate) fn elt(sequence: GcObj, n: usize) -> Result<GcObj> {
     match sequence.untag() {
@@ -590,6 +772,204 @@ fn substring(string: &str, from: Option<usize>, to: Option<usize>) -> String {
     new_string.to_owned()
 }
 
+#[defun]
+fn string_equal(s1: &str, s2: &str) -> bool {
+    s1 == s2
+}
+
+/// Return t if STRING1 and STRING2 are equal when case is ignored.
+#[defun]
+fn string_equal_ignore_case(string1: &str, string2: &str) -> bool {
+    string1
+        .chars()
+        .flat_map(char::to_uppercase)
+        .eq(string2.chars().flat_map(char::to_uppercase))
+}
+
+fn chars_equal(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_uppercase().eq(b.to_uppercase()))
+}
+
+/// Return non-nil if PREFIX is a prefix of STRING. If IGNORE-CASE is
+/// non-nil, case is ignored.
+#[defun]
+fn string_prefix_p(prefix: &str, string: &str, ignore_case: Option<()>) -> bool {
+    let mut chars = string.chars();
+    let ignore_case = ignore_case.is_some();
+    prefix
+        .chars()
+        .all(|x| chars.next().is_some_and(|y| chars_equal(x, y, ignore_case)))
+}
+
+/// Return non-nil if SUFFIX is a suffix of STRING. If IGNORE-CASE is
+/// non-nil, case is ignored.
+#[defun]
+fn string_suffix_p(suffix: &str, string: &str, ignore_case: Option<()>) -> bool {
+    let mut chars = string.chars().rev();
+    let ignore_case = ignore_case.is_some();
+    suffix
+        .chars()
+        .rev()
+        .all(|x| chars.next().is_some_and(|y| chars_equal(x, y, ignore_case)))
+}
+
+/// Search for the string NEEDLE in the string HAYSTACK, starting at the
+/// character START-POS, and return the position of the first match, or nil
+/// if there is none. The search is case-sensitive and ignores the match
+/// data.
+#[defun]
+fn string_search<'ob>(needle: &str, haystack: &str, start_pos: Option<i64>) -> Result<GcObj<'ob>> {
+    let len = haystack.chars().count();
+    let start = start_pos.unwrap_or(0);
+    let Some(start) = usize::try_from(start).ok().filter(|x| *x <= len) else {bail!("Args out of range: {start}")};
+    let offset = haystack
+        .char_indices()
+        .nth(start)
+        .map_or(haystack.len(), |x| x.0);
+    let found = haystack[offset..].find(needle);
+    Ok(found.map_or_else(nil, |idx| {
+        (start + haystack[offset..offset + idx].chars().count()).into()
+    }))
+}
+
+/// The Levenshtein distance between the sequences `a` and `b`.
+fn levenshtein<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let cost = if x == y { diagonal } else { diagonal + 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Return the Levenshtein distance between STRING1 and STRING2: the number
+/// of characters that have to be inserted, deleted or substituted to turn
+/// one into the other. If BYTECOMPARE is non-nil, bytes are compared
+/// instead of characters.
+#[defun]
+fn string_distance(string1: &str, string2: &str, bytecompare: Option<()>) -> usize {
+    if bytecompare.is_some() {
+        return levenshtein(string1.as_bytes(), string2.as_bytes());
+    }
+    let chars = |x: &str| x.chars().collect::<Vec<_>>();
+    levenshtein(&chars(string1), &chars(string2))
+}
+
+/// The weight of the byte at `pos` of `s` in a version: digits are compared
+/// as numbers, letters before other bytes, `~` before everything, even the
+/// end of the string.
+fn version_order(s: &[u8], pos: usize) -> i32 {
+    match s.get(pos) {
+        None => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => i32::from(*c),
+        Some(b'~') => -2,
+        Some(c) => i32::from(*c) + 256,
+    }
+}
+
+/// Compare the versions `s1` and `s2` the way Debian compares versions.
+fn version_compare(s1: &[u8], s2: &[u8]) -> i32 {
+    let digit = |s: &[u8], pos: usize| s.get(pos).is_some_and(u8::is_ascii_digit);
+    let (mut pos1, mut pos2) = (0, 0);
+    while pos1 < s1.len() || pos2 < s2.len() {
+        while (pos1 < s1.len() && !digit(s1, pos1)) || (pos2 < s2.len() && !digit(s2, pos2)) {
+            let (c1, c2) = (version_order(s1, pos1), version_order(s2, pos2));
+            if c1 != c2 {
+                return c1 - c2;
+            }
+            pos1 += 1;
+            pos2 += 1;
+        }
+        while s1.get(pos1) == Some(&b'0') {
+            pos1 += 1;
+        }
+        while s2.get(pos2) == Some(&b'0') {
+            pos2 += 1;
+        }
+        let mut first_diff = 0;
+        while digit(s1, pos1) && digit(s2, pos2) {
+            if first_diff == 0 {
+                first_diff = i32::from(s1[pos1]) - i32::from(s2[pos2]);
+            }
+            pos1 += 1;
+            pos2 += 1;
+        }
+        if digit(s1, pos1) {
+            return 1;
+        }
+        if digit(s2, pos2) {
+            return -1;
+        }
+        if first_diff != 0 {
+            return first_diff;
+        }
+    }
+    0
+}
+
+/// The length of file name `s` without its suffixes, which are made of a
+/// `.` followed by a letter or `~` and then letters, digits and `~`.
+fn file_prefix_len(s: &[u8]) -> usize {
+    let suffix_char = |c: &u8| c.is_ascii_alphanumeric() || *c == b'~';
+    let (mut i, mut prefix_len) = (0, 0);
+    while i < s.len() {
+        i += 1;
+        prefix_len = i;
+        while i + 1 < s.len()
+            && s[i] == b'.'
+            && (s[i + 1].is_ascii_alphabetic() || s[i + 1] == b'~')
+        {
+            i += 2;
+            while s.get(i).is_some_and(suffix_char) {
+                i += 1;
+            }
+        }
+    }
+    prefix_len
+}
+
+/// Compare the file names `a` and `b` as versions, like `filevercmp` of
+/// gnulib: "." and ".." come first, then the other names that start with a
+/// dot, and names are compared without their suffixes first.
+fn file_version_compare(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    let special = |s: &[u8]| match s {
+        b"" => 0,
+        b"." => 1,
+        b".." => 2,
+        [b'.', ..] => 3,
+        _ => 4,
+    };
+    let (sa, sb) = (special(a), special(b));
+    if sa != sb || sa < 3 {
+        return sa.cmp(&sb).then_with(|| a.cmp(b));
+    }
+    let (prefix_a, prefix_b) = (file_prefix_len(a), file_prefix_len(b));
+    let mut result = version_compare(&a[..prefix_a], &b[..prefix_b]);
+    if result == 0 {
+        result = version_compare(a, b);
+    }
+    match result {
+        0 => a.cmp(b),
+        _ if result < 0 => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+/// Return non-nil if STRING1 is less than STRING2 as version strings: the
+/// numbers in them are compared by value, so "foo2.png" is less than
+/// "foo12.png". Leading zeros in numbers are ignored.
+#[defun]
+fn string_version_lessp(string1: &str, string2: &str) -> bool {
+    file_version_compare(string1.as_bytes(), string2.as_bytes()).is_lt()
+}
+
 #[defun]
 fn enable_debug() -> bool {
     crate::debug::enable_debug();
@@ -607,12 +987,128 @@ fn disable_debug() -> bool {
     false
 }
 
+/// The state of the splitmix generator behind `random`. Each runtime has its
+/// own, seeded from the operating system when it is created.
+#[derive(Debug)]
+pub(crate) struct Random {
+    state: u64,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self {
+            state: Self::entropy(),
+        }
+    }
+}
+
+impl Random {
+    /// A seed from the system's entropy pool, or from the time and the
+    /// process if that is not available.
+    fn entropy() -> u64 {
+        use std::hash::{BuildHasher, Hasher};
+        use std::io::Read;
+        let mut bytes = [0; 8];
+        let read = std::fs::File::open("/dev/urandom").and_then(|mut x| x.read_exact(&mut bytes));
+        if read.is_ok() {
+            return u64::from_ne_bytes(bytes);
+        }
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+        hasher.write_u128(now.map_or(0, |x| x.as_nanos()));
+        hasher.write_u32(std::process::id());
+        hasher.finish()
+    }
+
+    /// Seed the generator with the bytes of `seed`, so that the same seed
+    /// always gives the same sequence.
+    fn seed(&mut self, seed: &[u8]) {
+        // FNV-1a, which unlike the std hashers is stable across releases
+        self.state = seed.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut x = self.state;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    /// A number in `0..limit`, with every value equally likely.
+    fn below(&mut self, limit: u64) -> u64 {
+        // reject the top values that would make the low ones more likely
+        let zone = u64::MAX - u64::MAX % limit;
+        loop {
+            let x = self.next();
+            if x < zone {
+                return x % limit;
+            }
+        }
+    }
+
+    /// Any fixnum, negative or not.
+    fn fixnum(&mut self) -> i64 {
+        (self.next() as i64) << 8 >> 8
+    }
+}
+
+/// Return a pseudo-random integer. With positive integer LIMIT, return an
+/// integer in the interval [0,LIMIT). With argument t, first seed the
+/// generator from the system's entropy pool. With a string argument, first
+/// seed it from the string's contents, so the numbers that follow are always
+/// the same. Other values of LIMIT are ignored and any fixnum can be returned.
+#[defun]
+fn random(limit: Option<GcObj>, env: &mut Rt<Env>, cx: &Context) -> Result<i64> {
+    match limit.map(GcObj::untag) {
+        Some(Object::Int(limit)) if limit <= 0 => {
+            let data = list![limit; cx];
+            Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into())
+        }
+        Some(Object::Int(limit)) => Ok(env.random.below(limit as u64) as i64),
+        Some(Object::TRUE) => {
+            env.random.state = Random::entropy();
+            Ok(env.random.fixnum())
+        }
+        Some(Object::String(seed)) => {
+            env.random.seed(seed.as_bytes());
+            Ok(env.random.fixnum())
+        }
+        _ => Ok(env.random.fixnum()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::core::{gc::RootSet, object::qtrue};
 
     use super::*;
 
+    #[test]
+    fn test_random() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = crate::reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            crate::interpreter::eval(obj, None, env, cx).map(|x| x.to_string())
+        };
+        let first = eval("(list (random \"seed\") (random) (random 1000))").unwrap();
+        let again = eval("(list (random \"seed\") (random) (random 1000))").unwrap();
+        assert_eq!(first, again);
+        for _ in 0..100 {
+            let x: i64 = eval("(random 7)").unwrap().parse().unwrap();
+            assert!((0..7).contains(&x));
+        }
+        assert_eq!(eval("(random 1)").unwrap(), "0");
+        assert_eq!(eval("(integerp (random t))").unwrap(), "t");
+        assert!(eval("(random 0)").is_err());
+        assert!(eval("(random -5)").is_err());
+    }
+
     #[test]
     fn test_delq() {
         let roots = &RootSet::default();
@@ -728,6 +1224,67 @@ mod test {
         maphash(func, table, env, cx).unwrap();
     }
 
+    #[test]
+    fn test_map_vector() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str, cx: &mut Context| {
+            let obj = crate::reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            crate::interpreter::eval(obj, None, env, cx)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(eval("(mapcar #'1+ [1 2 3])", cx), "(2 3 4)");
+        assert_eq!(eval("(mapcar #'1+ [])", cx), "nil");
+        assert_eq!(eval("(aref (mapc #'1+ [1 2]) 1)", cx), "2");
+        assert_eq!(eval("(apply #'+ [1 2 3])", cx), "6");
+        assert_eq!(eval("(apply #'list 1 [2 3])", cx), "(1 2 3)");
+        assert_eq!(eval("(apply #'list 1 '(2 3))", cx), "(1 2 3)");
+    }
+
+    #[test]
+    fn test_recent_primitives() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str, cx: &mut Context| {
+            let obj = crate::reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            crate::interpreter::eval(obj, None, env, cx)
+                .unwrap()
+                .to_string()
+        };
+        let mut check = |form: &str, expect: &str| assert_eq!(eval(form, cx), expect);
+        check("(take 2 '(1 2 3))", "(1 2)");
+        check("(take 5 '(1 2))", "(1 2)");
+        check("(take -1 '(1 2))", "nil");
+        check("(let ((x (list 1 2 3))) (ntake 2 x) x)", "(1 2)");
+        check("(ntake 0 (list 1 2))", "nil");
+        check("(ntake 3 (list 1 2))", "(1 2)");
+        check("(plistp '(a 1 b 2))", "t");
+        check("(plistp '(a 1 b))", "nil");
+        check("(plistp '(a . 1))", "nil");
+        check("(plistp nil)", "t");
+        check("(ensure-list 1)", "(1)");
+        check("(ensure-list '(1))", "(1)");
+        check("(always 1 2)", "t");
+        check("(xor nil 2)", "2");
+        check("(xor 1 nil)", "1");
+        check("(xor 1 2)", "nil");
+        check("(string-equal-ignore-case \"Straße\" \"STRASSE\")", "t");
+        check("(string-equal-ignore-case \"a\" \"b\")", "nil");
+        check(
+            "(file-name-split \"/tmp/foo.txt\")",
+            "(\"\" \"tmp\" \"foo.txt\")",
+        );
+        check("(file-name-split \"foo/bar/\")", "(\"foo\" \"bar\" \"\")");
+        check("(progn (defalias 'alias-p-b 'car) (defalias 'alias-p-a 'alias-p-b) (function-alias-p 'alias-p-a))", "(alias-p-b car)");
+        check("(function-alias-p 'car)", "nil");
+        check("(function-alias-p 1)", "nil");
+    }
+
     #[test]
     fn test_copy_alist() {
         let roots = &RootSet::default();
@@ -737,4 +1294,31 @@ mod test {
         let result = copy_alist(list, cx).unwrap();
         assert_eq!(alist, result);
     }
+
+    #[test]
+    fn test_string_functions() {
+        assert!(string_prefix_p("foo", "foobar", None));
+        assert!(!string_prefix_p("FOO", "foobar", None));
+        assert!(string_prefix_p("FOO", "foobar", Some(())));
+        assert!(!string_prefix_p("foobarbaz", "foobar", None));
+        assert!(string_suffix_p("bär", "foobär", None));
+        assert!(string_suffix_p("BÄR", "foobär", Some(())));
+        assert!(!string_suffix_p("foo", "foobar", None));
+        assert_eq!(string_search("é", "aébé", None).unwrap(), 1);
+        assert_eq!(string_search("é", "aébé", Some(2)).unwrap(), 3);
+        assert_eq!(string_search("x", "aébé", Some(4)).unwrap(), nil());
+        assert!(string_search("x", "ab", Some(3)).is_err());
+        assert_eq!(string_distance("kitten", "sitting", None), 3);
+        assert_eq!(string_distance("", "abc", None), 3);
+        assert_eq!(string_distance("ab", "áb", None), 1);
+        assert_eq!(string_distance("ab", "áb", Some(())), 2);
+        assert!(string_version_lessp("foo2.png", "foo12.png"));
+        assert!(!string_version_lessp("foo12.png", "foo2.png"));
+        assert!(string_version_lessp("1.9", "1.10"));
+        assert!(string_version_lessp("abc-1.02", "abc-1.3"));
+        assert!(string_version_lessp("1.0~rc1", "1.0"));
+        assert!(string_version_lessp(".emacs", "a"));
+        assert!(string_version_lessp("a", "b"));
+        assert!(!string_version_lessp("a", "a"));
+    }
 }
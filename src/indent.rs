@@ -0,0 +1,157 @@
+#![cfg(feature = "buffers")]
+//! Columns and indentation. A column is the display width of the text from
+//! the start of the line, where tabs stop at multiples of `tab-width` and wide
+//! characters take two columns.
+use crate::buffer::{delete_text, get_current_buffer, insert_text_with};
+use crate::character::char_columns;
+use crate::core::env::{sym, Env};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{BufferData, GcObj, Object};
+use crate::textprop::InsertProps;
+use anyhow::Result;
+use fn_macros::defun;
+
+/// The value of `tab-width`, or 8 if it is not a sensible width.
+fn tab_width(env: &Rt<Env>, cx: &Context) -> usize {
+    match env.vars.get(sym::TAB_WIDTH).map(|x| x.bind(cx).untag()) {
+        Some(Object::Int(width @ 1..=1000)) => width as usize,
+        _ => 8,
+    }
+}
+
+/// The column after `chr` when it starts at `col`.
+fn advance(col: usize, chr: char, tab_width: usize) -> usize {
+    match chr {
+        '\t' => (col / tab_width + 1) * tab_width,
+        _ => col + char_columns(chr),
+    }
+}
+
+/// The start of the line `pos` is on, within the accessible portion.
+fn line_start(data: &BufferData, pos: usize) -> usize {
+    let line = data.text.line_at(pos);
+    let newline = line.checked_sub(1).and_then(|x| data.text.newline(x));
+    newline.map_or(0, |x| x + 1).max(data.begv())
+}
+
+/// The characters from the start of the line `pos` is on to the end of the
+/// accessible portion.
+fn line_chars(data: &BufferData, pos: usize) -> (usize, impl Iterator<Item = char> + '_) {
+    let start = line_start(data, pos);
+    let (before, after) = data.text.slice(start, data.zv());
+    (start, before.chars().chain(after.chars()))
+}
+
+fn column_at(data: &BufferData, pos: usize, tab_width: usize) -> usize {
+    let (start, chars) = line_chars(data, pos);
+    chars
+        .take(pos - start)
+        .fold(0, |col, chr| advance(col, chr, tab_width))
+}
+
+/// The column of point.
+#[defun]
+fn current_column(env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    let tab_width = tab_width(env, cx);
+    let data = get_current_buffer(env).lock()?;
+    Ok(column_at(&data, data.point(), tab_width))
+}
+
+/// The indentation of the current line, which is the column of its first
+/// character that is not a space or a tab.
+#[defun]
+fn current_indentation(env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    let tab_width = tab_width(env, cx);
+    let data = get_current_buffer(env).lock()?;
+    let (_, chars) = line_chars(&data, data.point());
+    let indent = chars.take_while(|x| matches!(x, ' ' | '\t'));
+    Ok(indent.fold(0, |col, chr| advance(col, chr, tab_width)))
+}
+
+/// Indent from point with tabs and spaces until COLUMN is reached, and at
+/// least MINIMUM columns. Tabs are only used if `indent-tabs-mode` is
+/// non-nil. Returns the column reached.
+#[defun]
+pub(crate) fn indent_to(
+    column: usize,
+    minimum: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let tab_width = tab_width(env, cx);
+    let from = {
+        let data = get_current_buffer(env).lock()?;
+        column_at(&data, data.point(), tab_width)
+    };
+    let target = column.max(from + minimum.unwrap_or(0));
+    if target == from {
+        return Ok(target);
+    }
+    let mut col = from;
+    let mut text = String::new();
+    let use_tabs = env.vars.get(sym::INDENT_TABS_MODE);
+    if use_tabs.is_some_and(|x| !x.bind(cx).nil()) && target / tab_width > from / tab_width {
+        text.extend(std::iter::repeat_n(
+            '\t',
+            target / tab_width - from / tab_width,
+        ));
+        col = target / tab_width * tab_width;
+    }
+    text.extend(std::iter::repeat_n(' ', target - col));
+    insert_text_with(&text, InsertProps::Inherit, env, cx)?;
+    Ok(target)
+}
+
+/// Move point to COLUMN in the current line, or as close to it as the line
+/// allows, and return the column reached. If FORCE is non-nil and a tab
+/// spans COLUMN, it is converted to spaces so that COLUMN can be reached. If
+/// FORCE is t and the line is too short, it is indented to reach COLUMN.
+#[defun]
+fn move_to_column(
+    column: usize,
+    force: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let pad = force.is_some_and(|x| x.bind(cx) == sym::TRUE);
+    let tab_width = tab_width(env, cx);
+    let buffer = get_current_buffer(env);
+    let (mut col, prev_col, pos) = {
+        let mut data = buffer.lock()?;
+        let (start, chars) = line_chars(&data, data.point());
+        let (mut col, mut prev_col, mut pos) = (0, 0, start);
+        for chr in chars {
+            if col >= column || chr == '\n' {
+                break;
+            }
+            prev_col = col;
+            col = advance(col, chr, tab_width);
+            pos += 1;
+        }
+        data.goto_char(pos);
+        (col, prev_col, pos)
+    };
+    let tab_before = || {
+        buffer
+            .lock()
+            .is_ok_and(|x| x.text.slice(pos - 1, pos).0 == "\t")
+    };
+    if force.is_some() && col > column && tab_before() {
+        // replace the tab with spaces up to COLUMN and indentation after it
+        buffer.lock()?.goto_char(pos - 1);
+        let spaces = " ".repeat(column - prev_col);
+        insert_text_with(&spaces, InsertProps::Inherit, env, cx)?;
+        let goal = buffer.lock()?.point();
+        delete_text(goal, goal + 1, env, cx)?;
+        indent_to(col, None, env, cx)?;
+        buffer.lock()?.goto_char(goal);
+        col = column;
+    }
+    if col < column && pad {
+        col = indent_to(column, None, env, cx)?;
+    }
+    Ok(col)
+}
+
+defvar!(TAB_WIDTH, 8);
+defvar!(INDENT_TABS_MODE, true);
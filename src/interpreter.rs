@@ -1,3 +1,4 @@
+use crate::benchmark::Repetitions;
 use crate::core::{
     cons::{Cons, ElemStreamIter},
     env::{sym, Env, Symbol},
@@ -25,6 +26,9 @@ pub(crate) fn eval<'ob>(
     cx: &'ob mut Context,
 ) -> Result<GcObj<'ob>, anyhow::Error> {
     cx.garbage_collect(false);
+    crate::alloc::check_memory_limits(env, cx)?;
+    let form = rebind!(crate::optimize::optimize(form, env, cx), cx);
+    root!(form, cx);
     root!(vars, Vec::new(), cx);
     let mut interpreter = Interpreter { vars, env };
     interpreter.eval_form(form, cx).map_err(Into::into)
@@ -32,13 +36,19 @@ pub(crate) fn eval<'ob>(
 
 impl Interpreter<'_> {
     fn eval_form<'ob>(&mut self, rt: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
-        match rt.get(cx) {
+        let result = match rt.get(cx) {
             Object::Symbol(sym) => self.var_ref(sym, cx),
             Object::Cons(_) => {
                 let x = rt.try_into().unwrap();
                 self.eval_sexp(x, cx)
             }
             _ => Ok(rt.bind(cx)),
+        };
+        // the handlers see the error before the bindings of the form that
+        // signaled it are undone
+        match result {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(run_handlers(e, self.env, cx)),
         }
     }
 
@@ -67,10 +77,32 @@ impl Interpreter<'_> {
                 sym::DEFVAR | sym::DEFCONST => self.defvar(forms, cx),
                 sym::FUNCTION => self.eval_function(forms.bind(cx), cx),
                 sym::INTERACTIVE => Ok(nil()), // TODO: implement
+                // declarations are handled by `defalias`
+                sym::DECLARE => Ok(nil()),
                 sym::CATCH => self.catch(forms, cx),
-                sym::THROW => self.throw(forms.bind(cx), cx),
+                sym::THROW => self.throw(forms, cx),
                 sym::CONDITION_CASE => self.condition_case(forms, cx),
+                sym::HANDLER_BIND => self.handler_bind(forms, cx),
+                sym::IGNORE_ERRORS => self.ignore_errors(forms, false, cx),
+                sym::IGNORE_ERROR => self.ignore_errors(forms, true, cx),
+                sym::WITH_DEMOTED_ERRORS => self.with_demoted_errors(forms, cx),
+                sym::CL_ASSERT => self.cl_assert(forms, cx),
+                sym::BENCHMARK_RUN | sym::BENCHMARK_RUN_COMPILED => self.benchmark_run(forms, cx),
                 sym::UNWIND_PROTECT => self.unwind_protect(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::SAVE_EXCURSION => self.save_excursion(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::SAVE_CURRENT_BUFFER => self.save_current_buffer(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::SAVE_RESTRICTION => self.save_restriction(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::WITH_SILENT_MODIFICATIONS => self.with_silent_modifications(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::ATOMIC_CHANGE_GROUP => self.atomic_change_group(forms, cx),
+                #[cfg(feature = "buffers")]
+                sym::COMBINE_AFTER_CHANGE_CALLS => self.combine_after_change_calls(forms, cx),
+                #[cfg(feature = "regex")]
+                sym::SAVE_MATCH_DATA => self.save_match_data(forms, cx),
                 _ => {
                     root!(sym, cx);
                     self.eval_call(sym, forms, cx)
@@ -83,38 +115,58 @@ impl Interpreter<'_> {
     fn catch<'ob>(&mut self, obj: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
         rooted_iter!(forms, obj, cx);
         let Some(tag) = forms.next() else {bail_err!(ArgError::new(1, 0, "catch"))};
+        let tag = rebind!(self.eval_form(tag, cx)?);
+        // bindings made inside the catch are undone when a throw is caught
+        let (vars_len, depth) = (self.vars.len(), self.env.binding_depth());
         // push this tag on the catch stack
         self.env.catch_stack.push(tag);
         let result = match self.implicit_progn(forms, cx) {
             Ok(x) => Ok(rebind!(x, cx)),
-            Err(e) => {
-                if let ErrorType::Throw(id) = e.error {
-                    if let Some((throw_tag, data)) = self.env.get_exception(id) {
-                        let catch_tag = self.env.catch_stack.last().unwrap();
-                        if catch_tag == throw_tag {
-                            return Ok(data.bind(cx));
-                        }
+            Err(e) => match e.error {
+                ErrorType::Throw(id) => match self.env.get_exception(id) {
+                    Some((throw_tag, data)) if self.is_current_catch(throw_tag.bind(cx), cx) => {
+                        let data = data.bind(cx);
+                        self.vars.truncate(vars_len);
+                        self.env.unbind_to(depth, cx);
+                        Ok(data)
                     }
-                }
-                Err(e)
-            }
+                    _ => Err(e),
+                },
+                _ => Err(e),
+            },
         };
         // pop this tag from the catch stack
         self.env.catch_stack.bind_mut(cx).pop();
         result
     }
 
-    fn throw<'ob>(&mut self, obj: GcObj, cx: &'ob Context) -> EvalResult<'ob> {
-        let mut forms = obj.as_list()?;
-        let len = forms.len() as u16;
+    /// Whether TAG is the tag of the innermost `catch`.
+    fn is_current_catch(&self, tag: GcObj, cx: &Context) -> bool {
+        self.env
+            .catch_stack
+            .last()
+            .is_some_and(|x| x.bind(cx).ptr_eq(tag))
+    }
+
+    fn throw<'ob>(&mut self, obj: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        let len = obj.bind(cx).as_list()?.len() as u16;
         if len != 2 {
             bail_err!(ArgError::new(2, len, "throw"));
         }
-        let tag = forms.next().unwrap()?;
-        let value = forms.next().unwrap()?;
+        rooted_iter!(forms, obj, cx);
+        let tag = rebind!(self.eval_form(forms.next().unwrap(), cx)?);
+        root!(tag, cx);
+        let value = rebind!(self.eval_form(forms.next().unwrap(), cx)?);
+        let tag = tag.bind(cx);
         // Need to check now that there is a catch, because we may have a
         // condition-case along the unwind path
-        if self.env.catch_stack.iter().any(|x| x.bind(cx) == tag) {
+        if self
+            .env
+            .catch_stack
+            .iter()
+            .rev()
+            .any(|x| x.bind(cx).ptr_eq(tag))
+        {
             Err(EvalError::throw(tag, value, self.env))
         } else {
             Err(error!("No catch for {tag}"))
@@ -133,7 +185,8 @@ impl Interpreter<'_> {
             // (defvar x)
             None => nil(),
         };
-        self.env.defvar(name.bind(cx), value)?;
+        self.env.defvar(name.bind(cx), value, cx)?;
+        crate::lread::record_load_entry(name.bind(cx).into(), self.env, cx);
         Ok(value)
     }
 
@@ -143,7 +196,7 @@ impl Interpreter<'_> {
         args: &Rt<GcObj>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
-        let Some(func) = sym.bind(cx).follow_indirect(cx) else {bail_err!("Invalid function: {sym}")};
+        let Some(func) = sym.bind(cx).follow_indirect(cx) else {return Err(crate::missing::void_function(sym.bind(cx), self.env, cx))};
         root!(func, cx);
 
         match func.get(cx) {
@@ -518,12 +571,231 @@ impl Interpreter<'_> {
         }
     }
 
+    /// (save-excursion BODY...)
+    ///
+    /// Point is saved as a marker, so it stays with the surrounding text when
+    /// the buffer is edited.
+    #[cfg(feature = "buffers")]
+    fn save_excursion<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        let buffer = crate::buffer::get_current_buffer(self.env);
+        let marker = {
+            let mut data = buffer.lock()?;
+            let point = data.point();
+            data.make_marker(point)
+        };
+        rooted_iter!(forms, form, cx);
+        match self.implicit_progn(forms, cx) {
+            Ok(x) => {
+                root!(x, cx);
+                self.restore_excursion(buffer, marker, cx);
+                Ok(x.bind(cx))
+            }
+            Err(e) => {
+                self.restore_excursion(buffer, marker, cx);
+                Err(e)
+            }
+        }
+    }
+
+    #[cfg(feature = "buffers")]
+    fn restore_excursion(
+        &mut self,
+        buffer: &'static crate::core::object::Buffer,
+        marker: crate::core::object::MarkerId,
+        cx: &Context,
+    ) {
+        // a killed buffer is not restored
+        if let Ok(mut data) = buffer.lock() {
+            let point = data.marker_position(marker);
+            data.goto_char(point);
+            data.free_marker(marker);
+            drop(data);
+            crate::buffer::set_current_buffer(buffer, self.env, cx);
+        }
+    }
+
+    /// (save-current-buffer BODY...)
+    ///
+    /// If the saved buffer is killed by BODY, the current buffer is kept.
+    #[cfg(feature = "buffers")]
+    fn save_current_buffer<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let depth = self.env.binding_depth();
+        self.env.bind_current_buffer();
+        rooted_iter!(forms, form, cx);
+        match self.implicit_progn(forms, cx) {
+            Ok(x) => {
+                root!(x, cx);
+                self.env.unbind_to(depth, cx);
+                Ok(x.bind(cx))
+            }
+            Err(e) => {
+                self.env.unbind_to(depth, cx);
+                Err(e)
+            }
+        }
+    }
+
+    #[cfg(feature = "buffers")]
+    fn save_restriction<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        let buffer = crate::buffer::get_current_buffer(self.env);
+        buffer.lock()?.save_restriction();
+        rooted_iter!(forms, form, cx);
+        let result = self.implicit_progn(forms, cx);
+        if let Ok(mut data) = buffer.lock() {
+            data.restore_restriction();
+        }
+        result
+    }
+
+    /// (with-silent-modifications BODY...)
+    ///
+    /// Changes made by BODY to the current buffer are not recorded in its undo
+    /// list, do not run the modification hooks and leave its modified flag
+    /// and ticks unchanged. Meant for changes to text properties that are not
+    /// real edits, like highlighting.
+    #[cfg(feature = "buffers")]
+    fn with_silent_modifications<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let buffer = crate::buffer::get_current_buffer(self.env);
+        let was_silent = buffer.lock()?.set_silent_modifications(true);
+        let depth = self.env.binding_depth();
+        self.env.varbind(sym::BUFFER_UNDO_LIST, qtrue(), cx);
+        self.env.varbind(sym::INHIBIT_READ_ONLY, qtrue(), cx);
+        self.env
+            .varbind(sym::INHIBIT_MODIFICATION_HOOKS, qtrue(), cx);
+        rooted_iter!(forms, form, cx);
+        let result = match self.implicit_progn(forms, cx) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(e),
+        };
+        self.env.unbind_to(depth, cx);
+        if let Ok(mut data) = buffer.lock() {
+            data.set_silent_modifications(was_silent);
+        }
+        result
+    }
+
+    /// (atomic-change-group BODY...)
+    ///
+    /// If BODY exits non-locally, all the changes it made to the current
+    /// buffer are undone, so it is either changed completely or not at all.
+    /// Undo information is recorded during BODY even if undo is disabled.
+    #[cfg(feature = "buffers")]
+    fn atomic_change_group<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let (buffer, list) = crate::undo::prepare_change_group(self.env, cx)?;
+        root!(list, cx);
+        rooted_iter!(forms, form, cx);
+        match self.implicit_progn(forms, cx) {
+            Ok(x) => {
+                root!(x, cx);
+                crate::undo::accept_change_group(buffer, list, self.env, cx);
+                Ok(x.bind(cx))
+            }
+            Err(e) => {
+                crate::undo::cancel_change_group(buffer, list, self.env, cx)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// (combine-after-change-calls BODY...)
+    ///
+    /// `after-change-functions` runs once when BODY exits, with a region
+    /// covering all the changes BODY made, instead of after each change.
+    #[cfg(feature = "buffers")]
+    fn combine_after_change_calls<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let depth = self.env.binding_depth();
+        self.env
+            .varbind(sym::COMBINE_AFTER_CHANGE_CALLS, qtrue(), cx);
+        rooted_iter!(forms, form, cx);
+        match self.implicit_progn(forms, cx) {
+            Ok(x) => {
+                root!(x, cx);
+                self.env.unbind_to(depth, cx);
+                crate::buffer::combine_after_change_execute(self.env, cx)?;
+                Ok(x.bind(cx))
+            }
+            Err(e) => {
+                self.env.unbind_to(depth, cx);
+                crate::buffer::combine_after_change_execute(self.env, cx)?;
+                Err(e)
+            }
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    fn save_match_data<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        use crate::search::{match_data, set_match_data};
+        // markers keep the saved positions right when the body edits the buffer
+        let saved = match_data(None, None, None, self.env, cx)?;
+        root!(saved, cx);
+        rooted_iter!(forms, form, cx);
+        let result = match self.implicit_progn(forms, cx) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(e),
+        };
+        let saved: Gc<List> = saved.bind(cx).try_into()?;
+        set_match_data(saved, Some(()), self.env, cx)?;
+        result
+    }
+
+    fn handler_bind<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        rooted_iter!(forms, form, cx);
+        let Some(bindings) = forms.next() else {bail_err!(ArgError::new(1, 0, "handler-bind"))};
+        // (handler-bind ((CONDITIONS HANDLER)...) BODY...)
+        root!(handlers, Vec::new(), cx);
+        rooted_iter!(iter, bindings, cx);
+        while let Some(binding) = iter.next() {
+            let mut elems = binding.bind(cx).as_list()?;
+            let (Some(conditions), Some(handler)) = (elems.next(), elems.next()) else {bail_err!("Invalid handler-bind binding: {binding}")};
+            let (conditions, handler) = (conditions?, handler?);
+            root!(conditions, cx);
+            root!(handler, cx);
+            let handler = rebind!(self.eval_form(handler, cx)?);
+            handlers.push((conditions.bind(cx), handler));
+        }
+        let prev_len = self.env.handler_stack.len();
+        // The first handler in the list is the innermost
+        for x in handlers.bind_ref(cx).iter().rev() {
+            self.env.handler_stack.push(*x);
+        }
+        let result = match self.implicit_progn(forms, cx) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(run_handlers(e, self.env, cx)),
+        };
+        self.env.handler_stack.truncate(prev_len);
+        result
+    }
+
     fn condition_case<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
         rooted_iter!(forms, form, cx);
         let Some(var) = forms.next() else {bail_err!(ArgError::new(2, 0, "condition-case"))};
         root!(var, cx);
         let Some(bodyform) = forms.next() else {bail_err!(ArgError::new(2, 1, "condition-case"))};
-        let err = match self.eval_form(bodyform, cx) {
+        let depth = self.env.handler_stack.len();
+        for clause in form.bind(cx).as_list()?.skip(2) {
+            if let Object::Cons(clause) = clause?.untag() {
+                self.env.handler_stack.push((clause.car(), nil()));
+            }
+        }
+        let result = self.eval_form(bodyform, cx);
+        self.env.handler_stack.truncate(depth);
+        let err = match result {
             Ok(x) => return Ok(rebind!(x, cx)),
             Err(e) => e,
         };
@@ -557,7 +829,7 @@ impl Interpreter<'_> {
                         // full errors are implemented
                         cons!(sym::ERROR, format!("{err}"); cx)
                     };
-                    let binding = list!(var, error; cx).as_cons();
+                    let binding = cons!(var, error; cx).as_cons();
                     self.vars.push(binding);
                     let list: Gc<List> = match cons.cdr().try_into() {
                         Ok(x) => x,
@@ -574,6 +846,180 @@ impl Interpreter<'_> {
         }
         Err(err)
     }
+
+    /// (ignore-errors BODY...) or (ignore-error CONDITION BODY...)
+    ///
+    /// Like `condition-case` without a `debug` condition, the debugger is not
+    /// entered for the errors that are ignored.
+    fn ignore_errors<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        with_condition: bool,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let (condition, body) = match form.get(cx) {
+            _ if !with_condition => (sym::ERROR.into(), form.bind(cx)),
+            Object::Cons(cons) => (cons.car(), cons.cdr()),
+            _ => bail_err!(ArgError::new(1, 0, "ignore-error")),
+        };
+        root!(condition, cx);
+        rooted_iter!(forms, body, cx);
+        let depth = self.env.handler_stack.len();
+        self.env.handler_stack.push((condition.bind(cx), nil()));
+        let result = self.implicit_progn(forms, cx);
+        self.env.handler_stack.truncate(depth);
+        let err = match result {
+            Ok(x) => return Ok(rebind!(x, cx)),
+            Err(e) => e,
+        };
+        match error_object(&err, self.env, cx) {
+            Some(error)
+                if error_matches(condition.bind(cx), error.as_cons().car(), self.env, cx) =>
+            {
+                Ok(nil())
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// (benchmark-run REPETITIONS BODY...)
+    ///
+    /// REPETITIONS is only evaluated if it is a symbol. If it is not a number
+    /// or a symbol it is part of BODY, which is run once. There is no byte
+    /// compiler, so `benchmark-run-compiled` is the same as `benchmark-run`.
+    fn benchmark_run<'ob>(&mut self, form: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        let (repetitions, body) = match form.get(cx) {
+            Object::Cons(cons) => match cons.car().untag() {
+                Object::Int(count) if count >= 0 => (Repetitions::Count(count), cons.cdr()),
+                Object::Symbol(var) if var != sym::NIL => {
+                    (self.var_ref(var, cx)?.try_into()?, cons.cdr())
+                }
+                _ => (Repetitions::Count(1), form.bind(cx)),
+            },
+            _ => (Repetitions::Count(1), nil()),
+        };
+        root!(body, cx);
+        crate::benchmark::run(repetitions, cx, |cx| {
+            self.eval_progn(body, cx)?;
+            Ok(())
+        })
+    }
+
+    /// (with-demoted-errors FORMAT BODY...)
+    ///
+    /// Errors are reported with `message` instead of being signaled, unless
+    /// `debug-on-error` is non-nil.
+    fn with_demoted_errors<'ob>(
+        &mut self,
+        form: &Rt<GcObj>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        // FORMAT is part of BODY if it is not a string
+        let (format, body) = match form.get(cx) {
+            Object::Cons(cons) => match <&str>::try_from(cons.car()) {
+                Ok(format) => (format.to_owned(), cons.cdr()),
+                Err(_) => ("Error: %S".to_owned(), form.bind(cx)),
+            },
+            _ => return Ok(nil()),
+        };
+        rooted_iter!(forms, body, cx);
+        let err = match self.implicit_progn(forms, cx) {
+            Ok(x) => return Ok(rebind!(x, cx)),
+            Err(e) => e,
+        };
+        if debug_on_error(self.env, cx) {
+            return Err(err);
+        }
+        let Some(error) = error_object(&err, self.env, cx) else {return Err(err)};
+        crate::editfns::message(&format, &[error])?;
+        Ok(nil())
+    }
+
+    /// (cl-assert FORM &optional SHOW-ARGS STRING &rest ARGS)
+    ///
+    /// If SHOW-ARGS is non-nil and FORM is a function call, the values of its
+    /// non-constant arguments are included in the error data. The arguments
+    /// are only evaluated once.
+    fn cl_assert<'ob>(&mut self, obj: &Rt<GcObj>, cx: &'ob mut Context) -> EvalResult<'ob> {
+        rooted_iter!(forms, obj, cx);
+        let Some(form) = forms.next() else {bail_err!(ArgError::new(1, 0, "cl-assert"))};
+        root!(form, cx);
+        let show_args = forms.next().is_some_and(|x| !x.bind(cx).nil());
+        root!(sargs, Vec::new(), cx);
+        let func = match form.get(cx) {
+            Object::Cons(cons) if show_args => match cons.car().untag() {
+                Object::Symbol(sym) => sym.follow_indirect(cx).filter(|func| match func.untag() {
+                    Function::Cons(cons) => cons.car() == sym::CLOSURE,
+                    _ => true,
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        let value = match func {
+            Some(func) => {
+                root!(func, cx);
+                let arg_forms = form.bind(cx).as_cons().cdr();
+                rooted_iter!(iter, arg_forms, cx);
+                root!(args, Vec::new(), cx);
+                while let Some(x) = iter.next() {
+                    let is_const = crate::optimize::const_value(x.bind(cx)).is_some();
+                    let result = self.eval_form(x, cx)?;
+                    if !is_const {
+                        sargs.push(result);
+                    }
+                    args.push(result);
+                }
+                let name = form.bind(cx).as_cons().car().to_string();
+                rebind!(func.call(args, self.env, cx, Some(&name))?, cx)
+            }
+            None => rebind!(self.eval_form(form, cx)?),
+        };
+        if !value.nil() {
+            return Ok(nil());
+        }
+        let string = match forms.next() {
+            Some(x) => rebind!(self.eval_form(x, cx)?),
+            None => nil(),
+        };
+        root!(string, cx);
+        root!(args, Vec::new(), cx);
+        while let Some(x) = forms.next() {
+            let result = self.eval_form(x, cx)?;
+            args.push(result);
+        }
+        let sargs_list = crate::fns::slice_into_list(sargs.bind_ref(cx), None, cx);
+        let debugger = match self.env.vars.get(sym::DEBUGGER) {
+            Some(debugger) if debug_on_error(self.env, cx) => debugger.bind(cx),
+            _ => nil(),
+        };
+        if !debugger.nil() {
+            let data = cons!(form.bind(cx), cons!(string.bind(cx), sargs_list; cx); cx);
+            let debugger_args = vec![sym::ERROR.into(), list!(sym::CL_ASSERTION_FAILED, data; cx)];
+            let debugger: Gc<Function> = debugger.try_into()?;
+            root!(debugger, cx);
+            root!(debugger_args, move(debugger_args), cx);
+            debugger.call(debugger_args, self.env, cx, None)?;
+            Ok(nil())
+        } else if string.bind(cx).nil() {
+            let data = cons!(form.bind(cx), sargs_list; cx);
+            Err(EvalError::signal(
+                sym::CL_ASSERTION_FAILED.into(),
+                data,
+                self.env,
+            ))
+        } else {
+            let string: &str = string.bind(cx).try_into()?;
+            let mut format_args = sargs.bind_ref(cx).clone();
+            format_args.extend_from_slice(args.bind_ref(cx));
+            let message = crate::editfns::format_message(string, &format_args)?;
+            Err(EvalError::signal(
+                sym::ERROR.into(),
+                list!(message; cx),
+                self.env,
+            ))
+        }
+    }
 }
 
 impl Rt<Gc<Function<'_>>> {
@@ -583,6 +1029,19 @@ impl Rt<Gc<Function<'_>>> {
         env: &mut Rt<Env>,
         cx: &'ob mut Context,
         name: Option<&str>,
+    ) -> EvalResult<'ob> {
+        match self.call_inner(args, env, cx, name) {
+            Ok(x) => Ok(rebind!(x, cx)),
+            Err(e) => Err(run_handlers(e, env, cx)),
+        }
+    }
+
+    fn call_inner<'ob>(
+        &self,
+        args: &mut Rt<Vec<GcObj<'static>>>,
+        env: &mut Rt<Env>,
+        cx: &'ob mut Context,
+        name: Option<&str>,
     ) -> EvalResult<'ob> {
         let name = name.unwrap_or("lambda");
         let arg_cnt = args.len();
@@ -603,7 +1062,7 @@ impl Rt<Gc<Function<'_>>> {
             Function::Cons(_) => call_closure(self.try_into().unwrap(), args, name, env, cx)
                 .map_err(|e| e.add_trace(name, args)),
             Function::Symbol(sym) => {
-                let Some(func) = sym.follow_indirect(cx) else {bail_err!("Void Function: {sym}")};
+                let Some(func) = sym.follow_indirect(cx) else {return Err(crate::missing::void_function(sym, env, cx))};
                 match func.untag() {
                     Function::Cons(cons) if cons.car() == sym::AUTOLOAD => {
                         // TODO: inifinite loop if autoload does not resolve
@@ -625,6 +1084,82 @@ impl Rt<Gc<Function<'_>>> {
     }
 }
 
+/// Run the `handler-bind` handlers that match `err`, innermost first, until
+/// a `condition-case` that catches it. Handlers are called before the stack
+/// is unwound, and each handler runs with itself and any inner handlers
+/// disabled. If a handler exits non-locally, its error replaces the original
+/// one.
+pub(crate) fn run_handlers(mut err: EvalError, env: &mut Rt<Env>, cx: &mut Context) -> EvalError {
+    if err.handled || env.handler_stack.is_empty() {
+        return err;
+    }
+    err.handled = true;
+    let Some(error) = error_object(&err, env, cx) else {return err};
+    root!(error, cx);
+    let mut idx = env.handler_stack.len();
+    while idx > 0 {
+        idx -= 1;
+        let (conditions, handler) = env.handler_stack.bind_ref(cx)[idx];
+        let error_sym = error.bind(cx).as_cons().car();
+        if !error_matches(conditions, error_sym, env, cx) {
+            continue;
+        }
+        if handler.nil() {
+            break;
+        }
+        let Ok(handler) = Gc::<Function>::try_from(handler) else {continue};
+        root!(handler, cx);
+        let disabled = env.handler_stack.bind_ref(cx)[idx..].to_vec();
+        root!(disabled, move(disabled), cx);
+        env.handler_stack.truncate(idx);
+        root!(args, Vec::new(), cx);
+        args.push(error.bind(cx));
+        let result = handler.call(args, env, cx, None).map(|_| ());
+        for x in disabled.bind_ref(cx) {
+            env.handler_stack.push(*x);
+        }
+        if let Err(e) = result {
+            return e;
+        }
+    }
+    err
+}
+
+/// The error object `(ERROR-SYMBOL . DATA)` of `err`, or `None` if it is a
+/// `throw`.
+fn error_object<'ob>(err: &EvalError, env: &Rt<Env>, cx: &'ob Context) -> Option<GcObj<'ob>> {
+    match err.error {
+        ErrorType::Throw(_) => None,
+        ErrorType::Signal(id) => {
+            let (sym, data) = env.get_exception(id)?;
+            Some(cons!(sym, data; cx))
+        }
+        ErrorType::Err(_) => Some(cons!(sym::ERROR, format!("{err}"); cx)),
+    }
+}
+
+fn debug_on_error(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars
+        .get(sym::DEBUG_ON_ERROR)
+        .is_some_and(|x| !x.bind(cx).nil())
+}
+
+fn error_matches(conditions: GcObj, error_sym: GcObj, env: &Rt<Env>, cx: &Context) -> bool {
+    let Object::Symbol(error_sym) = error_sym.untag() else {return false};
+    let error_conditions = crate::data::get(error_sym, sym::ERROR_CONDITIONS, env, cx);
+    let is_member = |condition: GcObj| {
+        condition == sym::ERROR
+            || condition == error_sym
+            || error_conditions
+                .as_list()
+                .is_ok_and(|mut x| x.any(|x| x.is_ok_and(|x| x == condition)))
+    };
+    match conditions.untag() {
+        Object::Cons(cons) => cons.elements().any(|x| x.is_ok_and(is_member)),
+        _ => is_member(conditions),
+    }
+}
+
 fn call_closure<'ob>(
     closure: &Rt<Gc<&Cons>>,
     args: &Rt<Vec<GcObj>>,
@@ -633,6 +1168,7 @@ fn call_closure<'ob>(
     cx: &'ob mut Context,
 ) -> EvalResult<'ob> {
     cx.garbage_collect(false);
+    crate::alloc::check_memory_limits(env, cx)?;
     let closure: &Cons = closure.get(cx);
     match closure.car().untag() {
         Object::Symbol(sym::CLOSURE) => {
@@ -753,6 +1289,15 @@ fn parse_arg_list(bindings: GcObj) -> AnyResult<(Vec<Symbol>, Vec<Symbol>, Optio
     Ok((required, optional, rest))
 }
 
+/// Read and evaluate `form`, panicking if either fails. Used by the tests of
+/// the modules that define builtins.
+#[cfg(test)]
+pub(crate) fn check<'ob>(form: &str, env: &mut Rt<Env>, cx: &'ob mut Context) -> GcObj<'ob> {
+    let obj = crate::reader::read(form, cx).unwrap().0;
+    root!(obj, cx);
+    eval(obj, None, env, cx).unwrap()
+}
+
 #[cfg(test)]
 mod test {
     use crate::core::{env::intern, gc::RootSet, object::IntoObject};
@@ -1014,6 +1559,149 @@ mod test {
         check_error("(condition-case nil (if) 5 (error 7))", cx);
     }
 
+    #[test]
+    fn test_handler_bind() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(handler-bind () 1 2)", 2, cx);
+        check_interpreter(
+            "(let ((x 0)) (condition-case nil (handler-bind ((error #'(lambda (e) (setq x 1)))) (signal 'error nil)) (error x)))",
+            1,
+            cx,
+        );
+        check_interpreter(
+            "(let ((x 0)) (condition-case nil (handler-bind ((error #'(lambda (e) (setq x (car e))))) (car 1)) (error x)))",
+            sym::ERROR,
+            cx,
+        );
+        check_interpreter(
+            "(let ((x 0)) (condition-case nil (handler-bind ((void-variable #'(lambda (e) (setq x 1)))) (signal 'error nil)) (error x)))",
+            0,
+            cx,
+        );
+        // handlers run before the stack is unwound
+        check_interpreter(
+            "(progn (defvar hb-dyn 1) (let ((x nil)) (condition-case nil (handler-bind ((error #'(lambda (e) (setq x hb-dyn)))) (let ((hb-dyn 2)) (signal 'error nil))) (error x))))",
+            2,
+            cx,
+        );
+        check_interpreter(
+            "(catch 'hb-tag (handler-bind ((error #'(lambda (e) (throw 'hb-tag 5)))) (signal 'error nil)))",
+            5,
+            cx,
+        );
+        check_error(
+            "(handler-bind ((error #'(lambda (e) nil))) (signal 'error nil))",
+            cx,
+        );
+        // errors caught inside do not get to the handlers
+        check_interpreter(
+            "(let ((x 0)) (handler-bind ((error #'(lambda (e) (setq x 1)))) (condition-case nil (signal 'error nil) (error nil))) x)",
+            0,
+            cx,
+        );
+        check_interpreter(
+            "(let ((x 0)) (handler-bind ((error #'(lambda (e) (setq x (1+ x))))) (condition-case nil (signal 'error nil) (error nil)) (ignore-errors (car 1))) x)",
+            0,
+            cx,
+        );
+        // a handler runs once, however many frames the error passes
+        check_interpreter(
+            "(let ((x 0)) (condition-case nil (handler-bind ((error #'(lambda (e) (setq x (1+ x))))) (ignore-error void-variable (ignore-error args-out-of-range (signal 'error nil)))) (error x)))",
+            1,
+            cx,
+        );
+        // nor do the errors of calls that are folded when a form is optimized
+        check_interpreter(
+            "(let ((x 0)) (setq optimize-level 1) (handler-bind ((error #'(lambda (e) (setq x 1)))) (eval '(and nil (+ 1 'a)))) x)",
+            0,
+            cx,
+        );
+        // errors of special forms are seen before their bindings are undone
+        check_interpreter(
+            "(let ((x nil)) (condition-case nil (handler-bind ((error #'(lambda (e) (setq x hb-dyn)))) (let ((hb-dyn 3)) hb-unbound)) (error x)))",
+            3,
+            cx,
+        );
+    }
+
+    #[test]
+    fn test_ignore_errors() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(ignore-errors)", false, cx);
+        check_interpreter("(ignore-errors 1 2)", 2, cx);
+        check_interpreter("(ignore-errors (car 1) 2)", false, cx);
+        check_interpreter("(ignore-errors (signal 'void-variable nil))", false, cx);
+        check_interpreter("(catch 'ie-tag (ignore-errors (throw 'ie-tag 3)))", 3, cx);
+        check_interpreter(
+            "(let ((debug-on-error t)) (ignore-errors (signal 'error nil)))",
+            false,
+            cx,
+        );
+        check_interpreter("(ignore-error error 1)", 1, cx);
+        check_interpreter("(ignore-error error (signal 'error nil))", false, cx);
+        check_interpreter(
+            "(progn (put 'ie-error 'error-conditions '(ie-error error)) (ignore-error (ie-error) (signal 'ie-error nil)))",
+            false,
+            cx,
+        );
+        check_error(
+            "(ignore-error void-variable (signal 'ie-other-error nil))",
+            cx,
+        );
+        check_error("(ignore-error)", cx);
+    }
+
+    #[test]
+    fn test_with_demoted_errors() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(with-demoted-errors \"Error: %S\" 1 2)", 2, cx);
+        check_interpreter("(with-demoted-errors \"Error: %S\" (car 1))", false, cx);
+        check_interpreter("(with-demoted-errors (car 1))", false, cx);
+        check_interpreter("(with-demoted-errors 3)", 3, cx);
+        check_error(
+            "(let ((debug-on-error t)) (with-demoted-errors \"Error: %S\" (car 1)))",
+            cx,
+        );
+    }
+
+    #[test]
+    fn test_cl_assert() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_interpreter("(cl-assert t)", false, cx);
+        check_interpreter("(cl-assert (= 1 1) t)", false, cx);
+        check_interpreter(
+"(let ((e nil)) (ignore-errors (handler-bind ((error #'(lambda (x) (setq e x)))) (cl-assert (= 1 2)))) (equal e '(cl-assertion-failed (= 1 2))))",
+            true,
+            cx,
+        );
+        check_interpreter(
+            "(let ((e nil) (x 1)) (ignore-errors (handler-bind ((error #'(lambda (x) (setq e x)))) (cl-assert (= x 2) t))) (equal e '(cl-assertion-failed (= x 2) 1)))",
+            true,
+            cx,
+        );
+        check_interpreter(
+            "(let ((e nil)) (ignore-errors (handler-bind ((error #'(lambda (x) (setq e x)))) (cl-assert nil nil \"bad %s\" 5))) (equal e '(error \"bad 5\")))",
+            true,
+            cx,
+        );
+        // arguments are only evaluated once
+        check_interpreter(
+            "(let ((x 0)) (condition-case nil (cl-assert (= (setq x (1+ x)) 5) t) (error x)))",
+            1,
+            cx,
+        );
+        check_interpreter(
+            "(let ((called nil)) (let ((debug-on-error t) (debugger #'(lambda (&rest args) (setq called args)))) (cl-assert (= 1 2))) (equal called '(error (cl-assertion-failed ((= 1 2) nil)))))",
+            true,
+            cx,
+        );
+        check_error("(cl-assert nil)", cx);
+    }
+
     #[test]
     fn test_throw_catch() {
         let roots = &RootSet::default();
@@ -1033,5 +1721,35 @@ mod test {
         check_interpreter("(catch 1 (catch 2 (throw 1 3)))", 3, cx);
         check_error("(throw 1 2)", cx);
         check_error("(catch 2 (throw 3 4))", cx);
+        check_interpreter("(let ((x 5)) (catch 'tag (throw 'tag x)))", 5, cx);
+        check_interpreter(
+            "(let ((x 1)) (catch 'tag (let ((x 2)) (throw 'tag nil))) x)",
+            1,
+            cx,
+        );
+        check_error("(catch \"tag\" (throw \"tag\" 1))", cx);
+    }
+
+    #[test]
+    fn test_catch_stack() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        // like a cl-defun, where the body is wrapped in a block
+        let form = "(progn
+                      (defvar catch-test-var nil)
+                      (defalias 'catch-test
+                        #'(lambda (n)
+                            (catch '--cl-block-catch-test--
+                              (let ((catch-test-var n))
+                                (if (> n 5) (throw '--cl-block-catch-test-- catch-test-var))
+                                0))))
+                      (list (catch-test 10) (catch-test 1) catch-test-var))";
+        let obj = crate::reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = eval(obj, None, env, cx).unwrap();
+        assert_eq!(result.to_string(), "(10 0 nil)");
+        assert!(env.catch_stack.is_empty());
+        assert_eq!(env.binding_depth(), 0);
     }
 }
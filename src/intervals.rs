@@ -0,0 +1,417 @@
+//! Intervals of buffer text that share the same value.
+//!
+//! The intervals are kept in a treap ordered by position. Each node is one
+//! interval and stores the length of its subtree, so the interval at a
+//! position can be found in logarithmic time and the tree can be split and
+//! joined at any position. Adjacent intervals with equal values are merged
+//! after every change, so a run of text with the same properties is a single
+//! node.
+#![cfg_attr(not(feature = "buffers"), allow(dead_code))]
+use crate::core::gc::{IntoRoot, Trace};
+use crate::core::object::{RawObj, WithLifetime};
+
+type Link<T> = Option<Box<Node<T>>>;
+
+#[derive(Debug)]
+struct Node<T> {
+    /// Length of the interval
+    len: usize,
+    /// Length of all intervals in this subtree
+    total: usize,
+    /// Heap priority that keeps the tree balanced
+    priority: u32,
+    value: T,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+fn total<T>(link: Option<&Node<T>>) -> usize {
+    link.map_or(0, |x| x.total)
+}
+
+impl<T> Node<T> {
+    fn update(&mut self) {
+        self.total = total(self.left.as_deref()) + self.len + total(self.right.as_deref());
+    }
+}
+
+/// Split `link` into the intervals before `pos` and the ones after it. An
+/// interval that spans `pos` is split in two.
+fn split<T: IntervalValue>(link: Link<T>, pos: usize) -> (Link<T>, Link<T>) {
+    let Some(mut node) = link else {return (None, None)};
+    let left_len = total(node.left.as_deref());
+    if pos <= left_len {
+        let (before, after) = split(node.left.take(), pos);
+        node.left = after;
+        node.update();
+        (before, Some(node))
+    } else if pos >= left_len + node.len {
+        let (before, after) = split(node.right.take(), pos - left_len - node.len);
+        node.right = before;
+        node.update();
+        (Some(node), after)
+    } else {
+        // The second half keeps the priority, which is still at least that of
+        // the right subtree it takes over.
+        let offset = pos - left_len;
+        let mut rest = Box::new(Node {
+            len: node.len - offset,
+            total: 0,
+            priority: node.priority,
+            value: node.value.clone(),
+            left: None,
+            right: node.right.take(),
+        });
+        rest.update();
+        node.len = offset;
+        node.update();
+        (Some(node), Some(rest))
+    }
+}
+
+/// Join two trees where all intervals of `a` come before those of `b`.
+fn merge<T>(a: Link<T>, b: Link<T>) -> Link<T> {
+    match (a, b) {
+        (None, x) | (x, None) => x,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority >= b.priority {
+                a.right = merge(a.right.take(), Some(b));
+                a.update();
+                Some(a)
+            } else {
+                b.left = merge(Some(a), b.left.take());
+                b.update();
+                Some(b)
+            }
+        }
+    }
+}
+
+/// Move the intervals of `link` into `out` in order.
+fn flatten<T>(link: Link<T>, out: &mut Vec<(usize, T)>) {
+    if let Some(node) = link {
+        let node = *node;
+        flatten(node.left, out);
+        out.push((node.len, node.value));
+        flatten(node.right, out);
+    }
+}
+
+/// The value of an interval.
+pub(crate) trait IntervalValue: Clone {
+    /// Whether adjacent intervals with these values can be merged.
+    fn same(&self, other: &Self) -> bool;
+}
+
+/// A partition of text into intervals, each with a value of type `T`.
+#[derive(Debug)]
+pub(crate) struct IntervalTree<T> {
+    root: Link<T>,
+    /// State of the generator for node priorities
+    seed: u32,
+}
+
+impl<T> Default for IntervalTree<T> {
+    fn default() -> Self {
+        Self {
+            root: None,
+            seed: 0x9E37_79B9,
+        }
+    }
+}
+
+impl<T> IntervalTree<T> {
+    /// The value of the interval containing `pos`.
+    pub(crate) fn get(&self, pos: usize) -> Option<&T> {
+        self.iter_from(pos).next().map(|x| x.2)
+    }
+
+    /// Iterate over the intervals starting with the one containing `pos`. Each
+    /// interval is given as its start, end and value.
+    pub(crate) fn iter_from(&self, pos: usize) -> Iter<'_, T> {
+        let mut iter = Iter { stack: Vec::new() };
+        let mut link = &self.root;
+        let mut base = 0;
+        while let Some(node) = link {
+            let start = base + total(node.left.as_deref());
+            if pos < start {
+                iter.stack.push((node, start));
+                link = &node.left;
+            } else if pos < start + node.len {
+                iter.stack.push((node, start));
+                break;
+            } else {
+                base = start + node.len;
+                link = &node.right;
+            }
+        }
+        iter
+    }
+
+    /// A tree with the same intervals as this one, with their values mapped by
+    /// `f`.
+    pub(crate) fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> IntervalTree<U> {
+        fn map<T, U>(link: Option<&Node<T>>, f: &mut impl FnMut(&T) -> U) -> Link<U> {
+            let node = link?;
+            Some(Box::new(Node {
+                len: node.len,
+                total: node.total,
+                priority: node.priority,
+                value: f(&node.value),
+                left: map(node.left.as_deref(), f),
+                right: map(node.right.as_deref(), f),
+            }))
+        }
+        IntervalTree {
+            root: map(self.root.as_deref(), &mut f),
+            seed: self.seed,
+        }
+    }
+}
+
+impl<T: IntervalValue> IntervalTree<T> {
+    /// Create a tree with a single interval of `len` characters.
+    pub(crate) fn new(len: usize, value: T) -> Self {
+        let mut tree = Self::default();
+        tree.join(None, vec![(len, value)], None);
+        tree
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        total(self.root.as_deref())
+    }
+
+    /// Add an interval of `len` characters with `value` at `pos`. An interval
+    /// that spans `pos` is split around it.
+    pub(crate) fn insert(&mut self, pos: usize, len: usize, value: T) {
+        let (before, after) = split(self.root.take(), pos);
+        self.join(before, vec![(len, value)], after);
+    }
+
+    /// Remove the text from `beg` to `end`.
+    pub(crate) fn delete(&mut self, beg: usize, end: usize) {
+        let (before, rest) = split(self.root.take(), beg);
+        let (_, after) = split(rest, end.saturating_sub(beg));
+        self.join(before, Vec::new(), after);
+    }
+
+    /// Replace the value of each interval between `beg` and `end` with the
+    /// result of `f`, where `None` leaves it unchanged. Returns whether any
+    /// value was replaced.
+    pub(crate) fn modify(
+        &mut self,
+        beg: usize,
+        end: usize,
+        mut f: impl FnMut(&T) -> Option<T>,
+    ) -> bool {
+        let (before, rest) = split(self.root.take(), beg);
+        let (middle, after) = split(rest, end.saturating_sub(beg));
+        let mut intervals = Vec::new();
+        flatten(middle, &mut intervals);
+        let mut changed = false;
+        for (_, value) in &mut intervals {
+            if let Some(new) = f(value) {
+                *value = new;
+                changed = true;
+            }
+        }
+        self.join(before, intervals, after);
+        changed
+    }
+
+    /// Set the root to `before`, `middle` and `after` joined in order, merging
+    /// adjacent intervals with equal values.
+    fn join(&mut self, before: Link<T>, middle: Vec<(usize, T)>, after: Link<T>) {
+        // Take the intervals next to the middle so they can be merged with it
+        let before_len = total(before.as_deref());
+        let last_len = Self::last_len(before.as_deref());
+        let (before, last) = split(before, before_len - last_len);
+        let first_len = Self::first_len(after.as_deref());
+        let (first, after) = split(after, first_len);
+
+        let mut intervals = Vec::new();
+        flatten(last, &mut intervals);
+        intervals.extend(middle);
+        flatten(first, &mut intervals);
+        let mut merged: Vec<(usize, T)> = Vec::new();
+        for (len, value) in intervals {
+            match merged.last_mut() {
+                _ if len == 0 => {}
+                Some(prev) if prev.1.same(&value) => prev.0 += len,
+                _ => merged.push((len, value)),
+            }
+        }
+        let mut middle = None;
+        for (len, value) in merged {
+            let node = Box::new(Node {
+                len,
+                total: len,
+                priority: self.next_priority(),
+                value,
+                left: None,
+                right: None,
+            });
+            middle = merge(middle, Some(node));
+        }
+        self.root = merge(merge(before, middle), after);
+    }
+
+    fn first_len(mut link: Option<&Node<T>>) -> usize {
+        let mut len = 0;
+        while let Some(node) = link {
+            len = node.len;
+            link = node.left.as_deref();
+        }
+        len
+    }
+
+    fn last_len(mut link: Option<&Node<T>>) -> usize {
+        let mut len = 0;
+        while let Some(node) = link {
+            len = node.len;
+            link = node.right.as_deref();
+        }
+        len
+    }
+
+    fn next_priority(&mut self) -> u32 {
+        // xorshift32
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+
+    #[cfg(test)]
+    fn depth(&self) -> usize {
+        fn depth<T>(link: Option<&Node<T>>) -> usize {
+            link.map_or(0, |x| {
+                1 + depth(x.left.as_deref()).max(depth(x.right.as_deref()))
+            })
+        }
+        depth(self.root.as_deref())
+    }
+}
+
+pub(crate) struct Iter<'a, T> {
+    /// Nodes that have not been visited, with the start of their interval
+    stack: Vec<(&'a Node<T>, usize)>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, start) = self.stack.pop()?;
+        let end = start + node.len;
+        let mut link = &node.right;
+        while let Some(next) = link {
+            self.stack.push((next, end + total(next.left.as_deref())));
+            link = &next.left;
+        }
+        Some((start, end, &node.value))
+    }
+}
+
+impl<T: Trace> Trace for IntervalTree<T> {
+    fn trace(&self, stack: &mut Vec<RawObj>) {
+        fn trace<T: Trace>(link: Option<&Node<T>>, stack: &mut Vec<RawObj>) {
+            if let Some(node) = link {
+                node.value.trace(stack);
+                trace(node.left.as_deref(), stack);
+                trace(node.right.as_deref(), stack);
+            }
+        }
+        trace(self.root.as_deref(), stack);
+    }
+}
+
+impl<'new, T> WithLifetime<'new> for IntervalTree<T>
+where
+    T: WithLifetime<'new>,
+{
+    type Out = IntervalTree<<T as WithLifetime<'new>>::Out>;
+
+    unsafe fn with_lifetime(self) -> Self::Out {
+        std::mem::transmute(self)
+    }
+}
+
+impl<T, U> IntoRoot<IntervalTree<U>> for IntervalTree<T>
+where
+    T: WithLifetime<'static, Out = U>,
+{
+    unsafe fn into_root(self) -> IntervalTree<U> {
+        self.with_lifetime()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    impl IntervalValue for char {
+        fn same(&self, other: &Self) -> bool {
+            self == other
+        }
+    }
+
+    fn intervals(tree: &IntervalTree<char>) -> Vec<(usize, usize, char)> {
+        tree.iter_from(0)
+            .map(|(beg, end, x)| (beg, end, *x))
+            .collect()
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let mut tree = IntervalTree::new(10, 'a');
+        assert_eq!(tree.len(), 10);
+        assert!(tree.modify(2, 5, |_| Some('b')));
+        assert_eq!(intervals(&tree), [(0, 2, 'a'), (2, 5, 'b'), (5, 10, 'a')]);
+        assert_eq!(tree.get(4), Some(&'b'));
+        assert_eq!(tree.get(10), None);
+        // setting the same value is not a change
+        assert!(!tree.modify(3, 4, |x| (*x != 'b').then_some('b')));
+
+        tree.insert(3, 2, 'c');
+        assert_eq!(
+            intervals(&tree),
+            [
+                (0, 2, 'a'),
+                (2, 3, 'b'),
+                (3, 5, 'c'),
+                (5, 7, 'b'),
+                (7, 12, 'a')
+            ]
+        );
+        tree.insert(5, 1, 'b');
+        assert_eq!(tree.iter_from(6).next(), Some((5, 8, &'b')));
+
+        // deleting the middle interval merges its neighbors
+        tree.delete(3, 5);
+        assert_eq!(intervals(&tree), [(0, 2, 'a'), (2, 6, 'b'), (6, 11, 'a')]);
+        tree.modify(0, 11, |_| Some('a'));
+        assert_eq!(intervals(&tree), [(0, 11, 'a')]);
+        tree.delete(0, 11);
+        assert_eq!(tree.len(), 0);
+        assert_eq!(tree.get(0), None);
+    }
+
+    #[test]
+    fn balanced() {
+        let mut tree = IntervalTree::new(0, 'a');
+        for i in 0..2000 {
+            let value = if i % 2 == 0 { 'a' } else { 'b' };
+            tree.insert(i * 3, 3, value);
+        }
+        assert_eq!(tree.len(), 6000);
+        assert_eq!(tree.iter_from(0).count(), 2000);
+        assert!(tree.depth() < 50, "depth was {}", tree.depth());
+        assert_eq!(tree.iter_from(3001).next(), Some((3000, 3003, &'a')));
+
+        tree.modify(100, 5900, |_| Some('c'));
+        assert_eq!(tree.iter_from(0).count(), 34 + 1 + 34);
+        assert!(tree.depth() < 20, "depth was {}", tree.depth());
+    }
+}
@@ -1,16 +1,21 @@
+use crate::coding::decode_file_contents;
 use crate::core::env::Symbol;
 use crate::core::env::{sym, Env};
-use crate::core::error::{Type, TypeError};
+use crate::core::error::{EvalError, Type, TypeError};
 use crate::core::gc::Context;
 use crate::core::gc::Rt;
-use crate::core::object::{nil, Gc, GcObj, LispString, Object, WithLifetime};
+use crate::core::object::{nil, Buffer, Function, Gc, GcObj, LispString, Object, WithLifetime};
+use crate::fileio::{call_file_handler, expand_file_name, file_io_error, file_name, HandlerArg};
+use crate::fns::{equal, slice_into_list};
+use crate::print::PrintLevel;
 use crate::reader;
 use crate::{interpreter, root};
-use anyhow::{anyhow, Context as _};
+use anyhow::Context as _;
 use anyhow::{bail, ensure, Result};
 use fn_macros::defun;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::time::SystemTime;
 
 fn check_lower_bounds(idx: Option<i64>, len: usize) -> Result<usize> {
     let len = len as i64;
@@ -39,6 +44,7 @@ pub(crate) fn read_from_string<'ob>(
     string: &str,
     start: Option<i64>,
     end: Option<i64>,
+    env: &mut Rt<Env>,
     cx: &'ob Context,
 ) -> Result<GcObj<'ob>> {
     let len = string.len();
@@ -48,6 +54,7 @@ pub(crate) fn read_from_string<'ob>(
     let (obj, new_pos) = match reader::read(&string[start..end], cx) {
         Ok((obj, pos)) => (obj, pos),
         Err(mut e) => {
+            crate::missing::record_read_error(&e, env);
             e.update_pos(start);
             bail!(e);
         }
@@ -55,13 +62,63 @@ pub(crate) fn read_from_string<'ob>(
     Ok(cons!(obj, new_pos as i64; cx))
 }
 
+/// How deeply the form that failed to load is printed when `print-level` is
+/// not set.
+const LOAD_ERROR_PRINT_LEVEL: usize = 3;
+
+/// Add the index, byte offset, and printed form of the top-level form that
+/// signaled ERROR while loading to it.
+fn load_error(
+    error: anyhow::Error,
+    form: GcObj,
+    index: usize,
+    offset: usize,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> anyhow::Error {
+    let error = match error.downcast::<EvalError>() {
+        Ok(e) => e,
+        Err(e) => EvalError::new_error(e),
+    };
+    let level = match env.vars.get(sym::PRINT_LEVEL).map(|x| x.get(cx)) {
+        Some(Object::Int(level)) => usize::try_from(level).unwrap_or(0),
+        _ => LOAD_ERROR_PRINT_LEVEL,
+    };
+    let file = match env.vars.get(sym::LOAD_FILE_NAME).map(|x| x.get(cx)) {
+        Some(Object::String(file)) => format!(" of {file}"),
+        _ => String::new(),
+    };
+    let form = PrintLevel { obj: form, level };
+    error
+        .add_context(format!("In form {index}{file} at byte {offset}: {form}"))
+        .into()
+}
+
 pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>) -> Result<bool> {
+    read_eval_loop(contents, None, None, cx, env)
+}
+
+/// Read and evaluate each form in CONTENTS. If BUFFER is given, CONTENTS is
+/// the text of that buffer starting at the given position, and point is moved
+/// past each form before it is evaluated. If PRINTFLAG is given, the value of
+/// each form is printed to it.
+fn read_eval_loop(
+    contents: &str,
+    buffer: Option<(&'static Buffer, usize)>,
+    printflag: Option<&Rt<GcObj>>,
+    cx: &mut Context,
+    env: &mut Rt<Env>,
+) -> Result<bool> {
     let mut pos = 0;
+    let mut index = 0;
+    let mut chars = 0;
     loop {
+        index += 1;
         let (obj, new_pos) = match reader::read(&contents[pos..], cx) {
             Ok((obj, pos)) => (obj, pos),
             Err(reader::Error::EmptyStream) => return Ok(true),
             Err(mut e) => {
+                crate::missing::record_read_error(&e, env);
                 e.update_pos(pos);
                 bail!(e);
             }
@@ -71,95 +128,356 @@ pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>)
             println!("-----READ START-----\n {content}");
             println!("-----READ END-----");
         }
+        if let Some((buffer, start)) = buffer {
+            chars += contents[pos..pos + new_pos].chars().count();
+            buffer.lock()?.goto_char(start + chars);
+        }
         root!(obj, cx);
-        interpreter::eval(obj, None, env, cx)?;
+        match interpreter::eval(obj, None, env, cx) {
+            Ok(value) => {
+                if let Some(stream) = printflag {
+                    root!(value, cx);
+                    print_value(value, stream, env, cx)?;
+                }
+            }
+            Err(e) => {
+                let offset = pos + reader::skip_whitespace(&contents[pos..]);
+                return Err(load_error(e, obj.bind(cx), index, offset, env, cx));
+            }
+        }
         assert_ne!(new_pos, 0);
         pos += new_pos;
     }
 }
 
-fn file_in_path(file: &str, path: &str) -> Option<PathBuf> {
-    let path = Path::new(path).join(file);
-    if path.exists() {
-        Some(path)
-    } else {
-        let with_ext = path.with_extension("el");
-        with_ext.exists().then_some(with_ext)
+/// Print VALUE to STREAM as with `print`. A STREAM of t echoes the value, a
+/// buffer has it inserted at point, and a function is called with each
+/// character.
+fn print_value(
+    value: &Rt<GcObj>,
+    stream: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let stream = stream.bind(cx);
+    if stream == sym::TRUE {
+        println!("{}", value.bind(cx));
+        return Ok(());
+    }
+    let text = format!("\n{}\n", value.bind(cx));
+    #[cfg(feature = "buffers")]
+    if let Object::Buffer(buffer) = stream.untag() {
+        let current = crate::buffer::get_current_buffer(env);
+        crate::buffer::set_current_buffer(buffer, env, cx);
+        let result = crate::buffer::insert_text(&text, env, cx);
+        crate::buffer::set_current_buffer(current, env, cx);
+        return result;
+    }
+    let func: Gc<Function> = stream.try_into()?;
+    root!(func, cx);
+    for chr in text.chars() {
+        let arg = vec![GcObj::from(chr as i64)];
+        root!(arg, move(arg), cx);
+        func.call(arg, env, cx, None)?;
     }
+    Ok(())
+}
+
+/// Push ENTRY onto `current-load-list`, the definitions made by the file or
+/// buffer being evaluated.
+pub(crate) fn record_load_entry(entry: GcObj, env: &mut Rt<Env>, cx: &Context) {
+    let list = env
+        .vars
+        .get(sym::CURRENT_LOAD_LIST)
+        .map_or_else(nil, |x| x.bind(cx));
+    env.vars
+        .insert(sym::CURRENT_LOAD_LIST, cons!(entry, list; cx));
+}
+
+/// Add an entry for SOURCE to `load-history` with the definitions in
+/// `current-load-list`, replacing any earlier entry for SOURCE.
+fn record_load_history(source: GcObj, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let var = |var, env: &Rt<Env>| env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+    let mut defs: Vec<GcObj> = var(sym::CURRENT_LOAD_LIST, env)
+        .as_list()?
+        .collect::<Result<_>>()?;
+    defs.reverse();
+    let defs = slice_into_list(&defs, None, cx);
+    let mut history = vec![cons!(source, defs; cx)];
+    for entry in var(sym::LOAD_HISTORY, env).as_list()? {
+        let entry = entry?;
+        let same = matches!(entry.untag(), Object::Cons(x) if equal(x.car(), source));
+        if !same {
+            history.push(entry);
+        }
+    }
+    env.vars
+        .insert(sym::LOAD_HISTORY, slice_into_list(&history, None, cx));
+    Ok(())
+}
+
+/// The value of `lexical-binding` set in the `-*-` line at the start of
+/// TEXT, if there is one.
+#[cfg(feature = "buffers")]
+fn lexical_cookie(text: &str) -> Option<bool> {
+    let line = text.lines().next()?;
+    let (_, rest) = line.split_once("-*-")?;
+    let (vars, _) = rest.split_once("-*-")?;
+    vars.split(';').find_map(|var| {
+        let (name, value) = var.split_once(':')?;
+        (name.trim() == "lexical-binding").then(|| value.trim() != "nil")
+    })
+}
+
+/// Evaluate the text from `beg` to `end` of BUFFER, with BUFFER current.
+/// `lexical-binding` is bound to LEXICAL if given, and the definitions are
+/// recorded under SOURCE in `load-history` if it is non-nil. Point in BUFFER
+/// and the current buffer are restored afterward.
+#[cfg(feature = "buffers")]
+fn eval_buffer_text(
+    buffer: &'static Buffer,
+    (beg, end): (usize, usize),
+    printflag: Option<&Rt<GcObj>>,
+    lexical: Option<bool>,
+    source: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    use crate::buffer::{get_current_buffer, set_current_buffer};
+    let printflag = printflag.filter(|x| !x.bind(cx).nil());
+    let source = source.filter(|x| !x.bind(cx).nil());
+    let (contents, marker) = {
+        let mut data = buffer.lock()?;
+        let point = data.point();
+        (data.text.substring(beg, end), data.make_marker(point))
+    };
+    let current = get_current_buffer(env);
+    env.varbind(sym::CURRENT_LOAD_LIST, nil(), cx);
+    if let Some(lexical) = lexical {
+        env.varbind(sym::LEXICAL_BINDING, lexical.into(), cx);
+    }
+    set_current_buffer(buffer, env, cx);
+    let mut result = read_eval_loop(&contents, Some((buffer, beg)), printflag, cx, env).map(|_| ());
+    if let (Ok(()), Some(source)) = (&result, source) {
+        result = record_load_history(source.bind(cx), env, cx);
+    }
+    if let Ok(mut data) = buffer.lock() {
+        let point = data.marker_position(marker);
+        data.goto_char(point);
+        data.free_marker(marker);
+    }
+    if current.is_live() {
+        set_current_buffer(current, env, cx);
+    }
+    env.unbind(if lexical.is_some() { 2 } else { 1 }, cx);
+    result
+}
+
+/// Evaluate the accessible portion of BUFFER as Lisp code, defaulting to the
+/// current buffer. Point is moved past each form before it is evaluated. If
+/// PRINTFLAG is non-nil, the value of each form is printed to it as with
+/// `print`, or echoed if it is t. `lexical-binding` is bound to the value in
+/// the `-*-` line of the text, if it has one. If FILENAME is non-nil, the
+/// definitions made are recorded under it in `load-history`.
+#[cfg(feature = "buffers")]
+#[defun]
+fn eval_buffer(
+    buffer: Option<&Rt<GcObj>>,
+    printflag: Option<&Rt<GcObj>>,
+    filename: Option<&Rt<GcObj>>,
+    _unibyte: Option<()>,
+    _do_allow_print: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = match buffer.map(|x| x.bind(cx)).filter(|x| !x.nil()) {
+        Some(buffer) => crate::buffer::get_live_buffer(buffer)?,
+        None => crate::buffer::get_current_buffer(env),
+    };
+    let (bounds, lexical) = {
+        let data = buffer.lock()?;
+        let bounds = (data.begv(), data.zv());
+        (
+            bounds,
+            lexical_cookie(&data.text.substring(bounds.0, bounds.1)),
+        )
+    };
+    eval_buffer_text(buffer, bounds, printflag, lexical, filename, env, cx)?;
+    Ok(false)
+}
+
+/// Evaluate the text between START and END of the current buffer as Lisp
+/// code. Point is moved past each form before it is evaluated and restored
+/// afterward. If PRINTFLAG is non-nil, the value of each form is printed to
+/// it as with `print`, or echoed if it is t.
+#[cfg(feature = "buffers")]
+#[defun]
+fn eval_region(
+    start: usize,
+    end: usize,
+    printflag: Option<&Rt<GcObj>>,
+    read_function: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    ensure!(
+        read_function.is_none(),
+        "eval-region READ-FUNCTION is not implemented"
+    );
+    let buffer = crate::buffer::get_current_buffer(env);
+    let bounds = {
+        let data = buffer.lock()?;
+        (data.begv(), data.zv())
+    };
+    let region = crate::editfns::validate_region(start, end, bounds, env, cx)?;
+    eval_buffer_text(buffer, region, printflag, None, None, env, cx)?;
+    Ok(false)
 }
 
-fn find_file_in_load_path(file: &str, cx: &Context, env: &Rt<Env>) -> Result<PathBuf> {
-    let load_path = env.vars.get(sym::LOAD_PATH).unwrap();
-    let paths = load_path
-        .bind(cx)
-        .as_list()
-        .context("`load-path' was not a list")?;
-    let mut final_file = None;
-    for path in paths {
-        match path?.untag() {
-            Object::String(path) => {
-                if let Some(x) = file_in_path(file, path.try_into()?) {
-                    final_file = Some(x);
-                    break;
+/// Find FILE to load, in each directory of `load-path` unless it is
+/// absolute. Each name is tried with each of `load-suffixes` unless
+/// NOSUFFIX, and then as is unless MUST-SUFFIX. If `load-prefer-newer` is
+/// non-nil, the newest of the names in a directory is used rather than the
+/// first.
+fn find_load_file(
+    file: &str,
+    nosuffix: bool,
+    must_suffix: bool,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Option<String>> {
+    let var = |var| env.vars.get(var).map_or_else(nil, |x| x.bind(cx));
+    let mut suffixes = Vec::new();
+    if !nosuffix {
+        for suffix in var(sym::LOAD_SUFFIXES).as_list()? {
+            suffixes.push(<&str>::try_from(suffix?)?);
+        }
+    }
+    if !must_suffix {
+        suffixes.push("");
+    }
+    let dirs = match Path::new(file).is_absolute() {
+        true => nil(),
+        false => var(sym::LOAD_PATH),
+    };
+    let dirs: Vec<Option<&str>> = match dirs.nil() {
+        true => vec![None],
+        false => {
+            let dirs = dirs.as_list().context("`load-path' was not a list")?;
+            dirs.map(|dir| match dir?.untag() {
+                Object::NIL => Ok(None),
+                Object::String(dir) => Ok(Some(dir.try_into()?)),
+                x => {
+                    Err(TypeError::new(Type::String, x)).context("Found non-string in `load-path'")
                 }
+            })
+            .collect::<Result<_>>()?
+        }
+    };
+    let prefer_newer = !var(sym::LOAD_PREFER_NEWER).nil();
+    for dir in dirs {
+        let base = expand_file_name(file, dir, env, cx)?;
+        let mut found: Option<(String, SystemTime)> = None;
+        for suffix in &suffixes {
+            let name = format!("{base}{suffix}");
+            let Ok(metadata) = fs::metadata(&name) else {continue};
+            if metadata.is_dir() {
+                continue;
+            }
+            if !prefer_newer {
+                return Ok(Some(name));
             }
-            x => {
-                return Err(TypeError::new(Type::String, x))
-                    .context("Found non-string in `load-path'");
+            let modified = metadata.modified()?;
+            if found.as_ref().is_none_or(|x| modified > x.1) {
+                found = Some((name, modified));
             }
         }
+        if let Some((name, _)) = found {
+            return Ok(Some(name));
+        }
     }
-    final_file.ok_or_else(|| anyhow!("Unable to find file `{file}' in load-path"))
+    Ok(None)
 }
 
+/// Load and evaluate the Lisp file FILE, searching for it in `load-path`
+/// with each of `load-suffixes` and then without a suffix. If NOSUFFIX is
+/// non-nil, no suffixes are tried, and if MUST-SUFFIX is non-nil, FILE is
+/// only used as is if it ends in ".el" or ".elc" or has a directory part.
+/// If NOERROR is non-nil, nil is returned if FILE is not found. Unless
+/// NOMESSAGE is non-nil, a message is printed. The definitions made are
+/// recorded in `load-history`, and `do-after-load-evaluation` is called
+/// with the name of the file afterward.
 #[defun]
 pub(crate) fn load(
     file: &Rt<Gc<&LispString>>,
-    noerror: Option<()>,
-    nomessage: Option<()>,
+    noerror: Option<&Rt<GcObj>>,
+    nomessage: Option<&Rt<GcObj>>,
+    nosuffix: Option<&Rt<GcObj>>,
+    must_suffix: Option<&Rt<GcObj>>,
     cx: &mut Context,
     env: &mut Rt<Env>,
 ) -> Result<bool> {
-    let noerror = noerror.is_some();
-    let nomessage = nomessage.is_some();
-    let file: &str = file.get(cx).try_into()?;
-    let final_file = if Path::new(file).exists() {
-        PathBuf::from(file)
-    } else {
-        match find_file_in_load_path(file, cx, env) {
-            Ok(x) => x,
-            Err(e) => {
-                return match noerror {
-                    true => Ok(false),
-                    false => Err(e),
-                };
-            }
+    let name = file_name(file, cx)?;
+    let args = [
+        HandlerArg::File(&name),
+        HandlerArg::Obj(noerror),
+        HandlerArg::Obj(nomessage),
+        HandlerArg::Obj(nosuffix),
+        HandlerArg::Obj(must_suffix),
+    ];
+    if let Some(x) = call_file_handler(sym::LOAD, &args, env, cx)? {
+        return Ok(!x.nil());
+    }
+    let must_suffix = must_suffix.is_some()
+        && !(matches!(Path::new(&name).extension(), Some(x) if x == "el" || x == "elc")
+            || name.contains('/'));
+    let Some(final_file) = find_load_file(&name, nosuffix.is_some(), must_suffix, env, cx)? else {
+        if noerror.is_some() {
+            return Ok(false);
         }
+        let data = list!["Cannot open load file", "No such file or directory", name.as_str(); cx];
+        return Err(EvalError::signal(sym::FILE_MISSING.into(), data, env).into());
     };
-
-    if !nomessage {
-        println!("Loading {file}...");
+    if nomessage.is_none() {
+        println!("Loading {name}...");
     }
-    let new_load_file = cx.add(final_file.to_string_lossy().to_string());
-    let prev_load_file = match env.vars.get_mut(sym::LOAD_FILE_NAME) {
-        Some(val) => {
-            let prev = val.bind(cx);
-            val.set(new_load_file);
-            prev
+    let bytes = match fs::read(&final_file) {
+        Ok(bytes) => bytes,
+        Err(e) if noerror.is_some() && e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(false);
+        }
+        Err(e) => {
+            return Err(file_io_error(
+                &e,
+                "Opening input file",
+                &final_file,
+                env,
+                cx,
+            ))
         }
-        None => nil(),
-    };
-    root!(prev_load_file, cx);
-    let result = match fs::read_to_string(&final_file)
-        .with_context(|| format!("Couldn't open file {:?}", final_file.as_os_str()))
-    {
-        Ok(content) => load_internal(&content, cx, env),
-        Err(e) => match noerror {
-            true => Ok(false),
-            false => Err(e),
-        },
     };
-    env.vars.insert(sym::LOAD_FILE_NAME, &*prev_load_file);
+    let depth = env.binding_depth();
+    let load_file = cx.add(final_file.as_str());
+    env.varbind(sym::LOAD_FILE_NAME, load_file, cx);
+    env.varbind(sym::LOAD_TRUE_FILE_NAME, load_file, cx);
+    env.varbind(sym::LOAD_IN_PROGRESS, sym::TRUE.into(), cx);
+    env.varbind(sym::CURRENT_LOAD_LIST, nil(), cx);
+    let content = decode_file_contents(&bytes, env, cx);
+    let mut result = load_internal(&content, cx, env);
+    if result.is_ok() {
+        if let Err(e) = record_load_history(cx.add(final_file.as_str()), env, cx) {
+            result = Err(e);
+        }
+    }
+    env.unbind_to(depth, cx);
+    let after_load = sym::DO_AFTER_LOAD_EVALUATION;
+    if result.is_ok() && after_load.has_func() {
+        let func: Gc<Function> = after_load.into();
+        root!(func, cx);
+        let args = vec![cx.add(final_file)];
+        root!(args, move(args), cx);
+        func.call(args, env, cx, None)?;
+    }
     result
 }
 
@@ -194,8 +512,15 @@ defvar!(LEXICAL_BINDING, true);
 defvar!(CURRENT_LOAD_LIST);
 defvar!(LOAD_HISTORY);
 defvar!(LOAD_PATH, list!["lisp"]);
+defvar!(LOAD_SUFFIXES, list![".elc", ".el"]);
+defvar!(LOAD_PREFER_NEWER);
 defvar!(LOAD_FILE_NAME);
+defvar!(LOAD_TRUE_FILE_NAME);
+defvar!(LOAD_IN_PROGRESS);
+defvar!(AFTER_LOAD_ALIST);
+defsym!(DO_AFTER_LOAD_EVALUATION);
 defvar!(BYTE_BOOLEAN_VARS);
+defsym!(DEFUN);
 
 #[cfg(test)]
 mod test {
@@ -217,4 +542,138 @@ mod test {
         let val = interpreter::eval(obj, None, env, cx).unwrap();
         assert_eq!(val, 4.5);
     }
+
+    #[test]
+    fn test_load_error() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let contents = "(setq foo 1)\n  ; comment\n  (car 1 (list 2 (list 3 (list 4))))";
+        let err = load_internal(contents, cx, env).unwrap_err().to_string();
+        let context = "In form 2 at byte 27: (car 1 (list 2 (list 3 ...)))";
+        assert!(err.contains(context), "{err}");
+    }
+
+    #[test]
+    #[cfg(feature = "buffers")]
+    fn test_eval_buffer() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        let check = |form: &str, env: &mut Rt<Env>, cx: &mut Context| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            let value = interpreter::eval(obj, None, env, cx).unwrap();
+            value.to_string()
+        };
+        check("(set-buffer (get-buffer-create \"eval-out\"))", env, cx);
+        check("(set-buffer (get-buffer-create \"eval-src\"))", env, cx);
+        let text = ";; -*- lexical-binding: nil -*-\n\
+                    (setq eval-point (point))\n\
+                    (setq eval-lexical lexical-binding)\n\
+                    (defvar eval-var 1)\n\
+                    (defalias 'eval-fn #'(lambda () eval-var))\n";
+        crate::buffer::insert_text(text, env, cx).unwrap();
+        check("(goto-char 5)", env, cx);
+        check(
+            "(eval-buffer nil (get-buffer \"eval-out\") \"eval.el\")",
+            env,
+            cx,
+        );
+        assert_eq!(check("eval-point", env, cx), "58");
+        assert_eq!(check("eval-lexical", env, cx), "nil");
+        assert_eq!(check("lexical-binding", env, cx), "t");
+        assert_eq!(check("(point)", env, cx), "5");
+        assert_eq!(
+            check("(car load-history)", env, cx),
+            "(\"eval.el\" eval-var (defun . eval-fn))"
+        );
+        check("(set-buffer \"eval-out\")", env, cx);
+        assert_eq!(
+            check("(buffer-string)", env, cx),
+            "\"\n58\n\nnil\n\n1\n\neval-fn\n\""
+        );
+
+        // evaluating again replaces the entry
+        check("(eval-buffer \"eval-src\" nil \"eval.el\")", env, cx);
+        assert_eq!(check("(length load-history)", env, cx), "1");
+
+        check("(set-buffer \"eval-src\")", env, cx);
+        check("(erase-buffer)", env, cx);
+        check(
+            "(insert \"(setq eval-a 1) (setq eval-b (point)) (car 1)\")",
+            env,
+            cx,
+        );
+        check("(narrow-to-region 17 46)", env, cx);
+        check("(eval-region 17 38)", env, cx);
+        assert_eq!(check("eval-b", env, cx), "38");
+        assert_eq!(check("(point)", env, cx), "46");
+        let obj = reader::read("(eval-buffer)", cx).unwrap().0;
+        root!(obj, cx);
+        let err = interpreter::eval(obj, None, env, cx).unwrap_err();
+        assert!(err.to_string().contains("In form 2 at byte 22"), "{err}");
+        assert_eq!(
+            check(
+                "(condition-case nil (eval-region 1 16) (error 'out))",
+                env,
+                cx
+            ),
+            "out"
+        );
+    }
+
+    #[test]
+    fn test_require() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let dir = std::env::temp_dir().join(format!("rune-require-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = [
+            (
+                "rune-feat.el",
+                "(setq feat-loaded load-in-progress) (provide 'rune-feat '(sub))",
+            ),
+            ("rune-none.el", "(setq none-loaded t)"),
+            ("rune-rec.el", "(require 'rune-rec)"),
+            ("rune-plain", "(setq plain-loaded t)"),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let dir = dir.to_string_lossy();
+        let form = format!(
+            r#"(progn
+                 (setq load-path '("{dir}") load-suffixes '(".elc" ".el"))
+                 (defalias 'do-after-load-evaluation #'(lambda (file) (setq after-file file)))
+                 (setq after-load-alist
+                       (list (list 'rune-feat #'(lambda () (setq after-ran feat-loaded)))))
+                 (list (require 'rune-feat) feat-loaded after-ran after-file
+                       (featurep 'rune-feat) (featurep 'rune-feat 'sub)
+                       (featurep 'rune-feat 'other) (featurep 'rune-missing)
+                       (and (member '(provide . rune-feat)
+                                    (cdr (assoc "{dir}/rune-feat.el" load-history)))
+                            t)
+                       (condition-case nil (require 'rune-none) (error none-loaded))
+                       (condition-case nil (require 'rune-rec) (error 'recursive))
+                       (require 'rune-missing nil t)
+                       (load "rune-plain" nil t) plain-loaded
+                       (load "rune-missing" t t)
+                       (condition-case err (load "rune-missing" nil t) (error (car err)))
+                       (load "rune-plain" t t nil t)))"#
+        );
+        let obj = reader::read(&form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx);
+        let _ = fs::remove_dir_all(&*dir);
+        assert_eq!(
+            result.unwrap().to_string(),
+            format!(
+                "(rune-feat t t \"{dir}/rune-feat.el\" t t nil nil t t recursive nil t t nil \
+                 file-missing nil)"
+            )
+        );
+    }
 }
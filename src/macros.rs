@@ -33,6 +33,15 @@ macro_rules! define_unbox {
     };
 }
 
+/// The static symbol for a lisp name, like `sym!(foo-bar)` for `foo-bar` or
+/// `sym!(:test)` for `:test`. Every symbol used this way is defined by
+/// build.rs, so no interning is needed at runtime.
+macro_rules! sym {
+    ($($name:tt)+) => {
+        fn_macros::static_sym!($($name)+)
+    };
+}
+
 // Implementation in build.rs
 macro_rules! defsym {
     ($sym:ident) => {};
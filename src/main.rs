@@ -46,31 +46,77 @@ mod core;
 mod debug;
 mod alloc;
 mod arith;
+mod autosave;
+mod base64;
+mod benchmark;
 mod buffer;
 mod bytecode;
+mod callproc;
+mod casefiddle;
+mod casetab;
+mod category;
+mod char_fold;
 mod character;
+mod charset;
+mod chartab;
+mod cmds;
+mod coding;
+mod collate;
+mod conformance;
 mod data;
+mod declare;
+mod digest;
+mod dired;
 mod editfns;
 mod emacs;
 mod eval;
 mod fileio;
+mod filelock;
+mod filenotify;
 mod floatfns;
 mod fns;
 mod hashmap;
+mod indent;
 mod interpreter;
+mod intervals;
 mod keymap;
 mod lread;
+mod marker;
+mod minibuf;
+mod missing;
+#[cfg(feature = "network")]
+mod network;
+mod oclosure;
+mod optimize;
+mod pdump;
 mod print;
+#[cfg(feature = "processes")]
+mod process;
+mod pty;
 mod reader;
+mod regex;
+mod regexp_opt;
+mod runtime;
 mod search;
+mod syntax;
+mod sysdep;
+mod textprop;
 mod threads;
+mod timefns;
+mod tls;
+mod ucs_normalize;
+mod undo;
+mod unidata;
 
 use crate::core::{
-    env::{intern, Env},
-    gc::{Context, RootSet, Rt},
+    env::Env,
+    gc::{Context, Limits, RootSet, Rt},
 };
+use crate::runtime::Runtime;
 use std::env;
 use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
 
 fn parens_closed(buffer: &str) -> bool {
     let open = buffer.chars().filter(|&x| x == '(').count();
@@ -78,14 +124,111 @@ fn parens_closed(buffer: &str) -> bool {
     open <= close
 }
 
+/// Complete the symbol at the end of `input` and print the candidates. The
+/// completed input is kept so it can be continued on the next line.
+fn complete_input(input: &mut String, env: &Rt<Env>, cx: &Context) {
+    let is_symbol_char = |c: char| !c.is_whitespace() && !"()[]'\"`,;".contains(c);
+    let start = input.trim_end_matches(is_symbol_char).len();
+    let prefix = &input[start..];
+    // complete function names in call position
+    let filter = if input[..start].ends_with('(') {
+        minibuf::SymbolFilter::Function
+    } else {
+        minibuf::SymbolFilter::All
+    };
+    let candidates = minibuf::symbol_completions(prefix, filter, env, cx);
+    if candidates.len() > 1 {
+        println!("{}", candidates.join(" "));
+    }
+    let completed = minibuf::common_prefix(&candidates);
+    if completed.len() > prefix.len() {
+        input.truncate(start);
+        input.push_str(completed);
+    }
+    print!("> {input}");
+}
+
+/// Read the lines of stdin on another thread, so waiting for input can time
+/// out.
+fn read_lines() -> Receiver<String> {
+    let (sender, lines) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = io::stdin();
+        loop {
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) if sender.send(line).is_err() => return,
+                Ok(_) => {}
+            }
+        }
+    });
+    lines
+}
+
+/// How often the event loop looks for file notifications while it waits for
+/// input.
+const FILE_NOTIFY_POLL: Duration = Duration::from_millis(100);
+
+/// Wait for the next line of input, auto-saving once if none arrives within
+/// `auto-save-timeout` seconds, and handling file notifications and the
+/// output of processes meanwhile. Returns `None` at the end of the input.
+fn next_line(lines: &Receiver<String>, env: &mut Rt<Env>, cx: &mut Context) -> Option<String> {
+    #[cfg(feature = "buffers")]
+    let mut auto_save = autosave::idle_timeout(env, cx).map(|x| Instant::now() + x);
+    #[cfg(not(feature = "buffers"))]
+    let auto_save: Option<Instant> = None;
+    loop {
+        if let Err(e) = filenotify::dispatch_events(env, cx) {
+            println!("Error: {e}");
+        }
+        #[cfg(feature = "processes")]
+        process::dispatch_process_events(env, cx);
+        let notify = filenotify::is_watching().then(|| Instant::now() + FILE_NOTIFY_POLL);
+        #[cfg(feature = "processes")]
+        let notify = notify
+            .into_iter()
+            .chain(process::has_processes().then(|| Instant::now() + process::PROCESS_POLL));
+        let Some(deadline) = auto_save.into_iter().chain(notify).min() else {return lines.recv().ok()};
+        match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => return Some(line),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+        #[cfg(feature = "buffers")]
+        if auto_save.is_some_and(|x| x <= Instant::now()) {
+            auto_save = None;
+            if let Err(e) = autosave::auto_save_idle(env, cx) {
+                println!("Error: {e}");
+            }
+        }
+    }
+}
+
 fn repl(env: &mut Rt<Env>, cx: &mut Context) {
     println!("Hello, world!");
     let mut buffer = String::new();
-    let stdin = io::stdin();
+    let lines = read_lines();
+    let mut completing = false;
     loop {
-        print!("> ");
+        if !std::mem::take(&mut completing) {
+            print!("> ");
+        }
         io::stdout().flush().unwrap();
-        stdin.read_line(&mut buffer).unwrap();
+        let Some(line) = next_line(&lines, env, cx) else {return};
+        // every character typed counts toward the next auto-save
+        #[cfg(feature = "buffers")]
+        if let Err(e) = autosave::record_input(line.chars().count(), env, cx) {
+            println!("Error: {e}");
+        }
+        buffer.push_str(&line);
+        // A line ending in a tab asks for completion
+        if buffer.ends_with("\t\n") {
+            buffer.truncate(buffer.len() - 2);
+            complete_input(&mut buffer, env, cx);
+            completing = true;
+            continue;
+        }
         if buffer.trim() == "exit" {
             return;
         }
@@ -98,6 +241,7 @@ fn repl(env: &mut Rt<Env>, cx: &mut Context) {
         let (obj, _) = match reader::read(&buffer, cx) {
             Ok(obj) => obj,
             Err(e) => {
+                missing::record_read_error(&e, env);
                 println!("Error: {e}");
                 buffer.clear();
                 continue;
@@ -113,50 +257,117 @@ fn repl(env: &mut Rt<Env>, cx: &mut Context) {
     }
 }
 
-fn load(env: &mut Rt<Env>, cx: &mut Context) {
+/// Load the vendored standard library in `lisp/`, starting from
+/// `bootstrap.el`.
+fn bootstrap(env: &mut Rt<Env>, cx: &mut Context) -> anyhow::Result<bool> {
     crate::core::env::init_variables(cx, env);
-    crate::data::defalias(
-        intern("not", cx),
-        (crate::core::env::sym::NULL).into(),
-        None,
-    )
-    .expect("null should be defined");
-
-    let buffer = String::from(r#"(load "lisp/bootstrap.el")"#);
-
-    match crate::lread::load_internal(&buffer, cx, env) {
-        Ok(val) => println!("{val}"),
-        Err(e) => println!("Error: {e}"),
+    crate::data::defalias(sym!(not), sym!(null).into(), None, env, cx)
+        .expect("null should be defined");
+
+    let buffer = String::from(r#"(load (expand-file-name "lisp/bootstrap.el"))"#);
+    crate::lread::load_internal(&buffer, cx, env)
+}
+
+/// Returns false if the bootstrap failed.
+fn load(env: &mut Rt<Env>, cx: &mut Context) -> bool {
+    match bootstrap(env, cx) {
+        Ok(val) => {
+            println!("{val}");
+            true
+        }
+        Err(e) => {
+            println!("Error: {e}");
+            false
+        }
+    }
+}
+
+fn load_dump(path: &str, env: &mut Rt<Env>, cx: &Context) {
+    core::env::init_variables(cx, env);
+    if let Err(e) = pdump::load_dump(path, env, cx) {
+        println!("Error: {e}");
     }
 }
 
+/// Run the suite in `tests/conformance.el` and print the report. Returns
+/// false if any case did not give the result the suite expects.
+fn conformance(cx: &mut Context) -> bool {
+    let result = std::fs::read_to_string("tests/conformance.el")
+        .map_err(Into::into)
+        .and_then(|suite| {
+            // panics are reported as failed cases
+            std::panic::set_hook(Box::new(|_| {}));
+            conformance::run(&suite, cx)
+        });
+    _ = std::panic::take_hook();
+    match result {
+        Ok(report) => {
+            print!("{report}");
+            report.is_ok()
+        }
+        Err(e) => {
+            println!("Error: {e}");
+            false
+        }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: core::gc::reserve::ReserveAllocator = core::gc::reserve::ReserveAllocator;
+
 fn main() {
-    let roots = &RootSet::default();
-    let cx = &mut Context::new(roots);
-    root!(env, Env::default(), cx);
+    core::gc::reserve::refill_reserve();
     let mut arg_load = false;
     let mut arg_repl = false;
+    let mut arg_dump = None;
+    let mut arg_conformance = false;
+    let mut limits = Limits::default();
 
     for arg in env::args() {
         match arg.as_str() {
             "--repl" => arg_repl = true,
             "--load" => arg_load = true,
+            "--conformance" => arg_conformance = true,
+            x if x.starts_with("--dump-file=") => {
+                arg_dump = Some(x.trim_start_matches("--dump-file=").to_owned());
+            }
+            x if x.starts_with("--max-conses=") => {
+                limits.max_conses = x.trim_start_matches("--max-conses=").parse().ok();
+            }
+            x if x.starts_with("--max-bytes=") => {
+                limits.max_bytes = x.trim_start_matches("--max-bytes=").parse().ok();
+            }
             x => println!("unknown arg: {x}"),
         }
     }
 
-    if !arg_load && !arg_repl {
+    let roots = &RootSet::default();
+    let mut runtime = Runtime::new(roots, limits);
+    let cx = &mut runtime.cx;
+    root!(env, Env::default(), cx);
+
+    if !arg_load && !arg_repl && arg_dump.is_none() && !arg_conformance {
         arg_load = true;
     }
 
     // Ensure this is always initalized before anything else
     lazy_static::initialize(&crate::core::env::INTERNED_SYMBOLS);
 
-    if arg_load {
-        load(env, cx);
+    if arg_conformance && !conformance(cx) {
+        std::process::exit(1);
+    }
+
+    if let Some(path) = &arg_dump {
+        load_dump(path, env, cx);
+    } else if arg_load && !load(env, cx) && !arg_repl {
+        std::process::exit(1);
     }
 
     if arg_repl {
         repl(env, cx);
     }
+
+    if let Err(e) = runtime.shutdown(env) {
+        println!("Error: {e}");
+    }
 }
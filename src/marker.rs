@@ -0,0 +1,295 @@
+#![cfg(feature = "buffers")]
+//! Lisp markers. A marker either points nowhere or at a position in a
+//! buffer, and moves with the text around it as the buffer is edited. Text
+//! inserted at the position of a marker goes after it, unless the marker's
+//! insertion type is non-nil or the text is inserted with
+//! `insert-before-markers`.
+use crate::buffer::get_current_buffer;
+use crate::core::env::Env;
+use crate::core::gc::Rt;
+use crate::core::object::{nil, Buffer, GcObj, Marker, Object, TagType};
+use anyhow::Result;
+use fn_macros::defun;
+
+/// Point MARKER at the 0-based position `pos` in a buffer, or nowhere if
+/// `place` is `None`. The position is clamped to the buffer, ignoring any
+/// narrowing.
+fn set_marker_place(marker: &Marker, place: Option<(&'static Buffer, usize)>) -> Result<()> {
+    let place = place.filter(|x| x.0.is_live());
+    if let Some((old, id)) = marker.place() {
+        // markers are kept with the text, so buffers that share it can reuse
+        // the same id
+        if let Some((buffer, pos)) = place.filter(|x| x.0.text_owner() == old.text_owner()) {
+            old.lock()?.set_marker(id, pos);
+            marker.set_place(Some((buffer, id)));
+            return Ok(());
+        }
+        old.lock()?.free_marker(id);
+    }
+    let place = match place {
+        Some((buffer, pos)) => {
+            let mut data = buffer.lock()?;
+            let id = data.make_marker(pos);
+            data.set_marker_insertion_type(id, marker.insertion_type());
+            Some((buffer, id))
+        }
+        None => None,
+    };
+    marker.set_place(place);
+    Ok(())
+}
+
+/// Create a marker that points nowhere.
+#[defun]
+fn make_marker() -> &'static Marker {
+    Marker::create()
+}
+
+/// The position of MARKER, or nil if it points nowhere.
+#[defun]
+fn marker_position<'ob>(marker: &'static Marker) -> GcObj<'ob> {
+    marker.position().map_or_else(nil, |x| (x.1 + 1).into())
+}
+
+/// The buffer MARKER points into, or nil if it points nowhere.
+#[defun]
+fn marker_buffer<'ob>(marker: &'static Marker) -> GcObj<'ob> {
+    marker.position().map_or_else(nil, |x| x.0.tag().into())
+}
+
+/// Point MARKER at POSITION in BUFFER, or the current buffer. If POSITION is
+/// nil, MARKER points nowhere.
+#[defun]
+pub(crate) fn set_marker(
+    marker: &'static Marker,
+    position: Option<usize>,
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+) -> Result<&'static Marker> {
+    let buffer = buffer.unwrap_or_else(|| get_current_buffer(env));
+    let place = position.map(|x| (buffer, x.saturating_sub(1)));
+    set_marker_place(marker, place)?;
+    Ok(marker)
+}
+
+#[defun]
+fn move_marker(
+    marker: &'static Marker,
+    position: Option<usize>,
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+) -> Result<&'static Marker> {
+    set_marker(marker, position, buffer, env)
+}
+
+/// A new marker at the position of MARKER, which can be a marker or a
+/// position in the current buffer. If INSERTION-TYPE is non-nil, the new
+/// marker advances when text is inserted at its position.
+#[defun]
+fn copy_marker(
+    marker: Option<GcObj>,
+    insertion_type: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<&'static Marker> {
+    let place = match marker {
+        Some(obj) => match obj.untag() {
+            Object::Marker(marker) => marker.position(),
+            _ => {
+                let pos: usize = obj.try_into()?;
+                Some((get_current_buffer(env), pos.saturating_sub(1)))
+            }
+        },
+        None => None,
+    };
+    let new = Marker::create();
+    new.set_insertion_type(insertion_type.is_some());
+    set_marker_place(new, place)?;
+    Ok(new)
+}
+
+/// Whether MARKER advances when text is inserted at its position.
+#[defun]
+fn marker_insertion_type(marker: &'static Marker) -> bool {
+    marker.insertion_type()
+}
+
+/// Set whether MARKER advances when text is inserted at its position.
+#[defun]
+fn set_marker_insertion_type(marker: &'static Marker, insertion_type: Option<()>) -> Result<bool> {
+    let advance = insertion_type.is_some();
+    marker.set_insertion_type(advance);
+    if let Some((buffer, id)) = marker.place() {
+        buffer.lock()?.set_marker_insertion_type(id, advance);
+    }
+    Ok(advance)
+}
+
+/// A new marker at `pos` in the current buffer.
+fn marker_at(
+    pos: impl FnOnce(&Buffer) -> Result<usize>,
+    env: &mut Rt<Env>,
+) -> Result<&'static Marker> {
+    let buffer = get_current_buffer(env);
+    let marker = Marker::create();
+    set_marker_place(marker, Some((buffer, pos(buffer)?)))?;
+    Ok(marker)
+}
+
+/// A new marker at point.
+#[defun]
+fn point_marker(env: &mut Rt<Env>) -> Result<&'static Marker> {
+    marker_at(|x| Ok(x.lock()?.point()), env)
+}
+
+/// A new marker at the start of the accessible portion of the buffer.
+#[defun]
+fn point_min_marker(env: &mut Rt<Env>) -> Result<&'static Marker> {
+    marker_at(|x| Ok(x.lock()?.begv()), env)
+}
+
+/// A new marker at the end of the accessible portion of the buffer.
+#[defun]
+fn point_max_marker(env: &mut Rt<Env>) -> Result<&'static Marker> {
+    marker_at(|x| Ok(x.lock()?.zv()), env)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_marker_edits() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"marker-edits\"))", env, cx);
+        // each edit starts from "abcdef" with point and a marker at 3
+        let cases = [
+            ("(insert \"XY\")", "nil", 3),
+            ("(insert \"XY\")", "t", 5),
+            ("(insert-before-markers \"XY\")", "nil", 5),
+            ("(insert-before-markers \"XY\")", "t", 5),
+            ("(progn (goto-char 2) (insert \"XY\"))", "nil", 5),
+            ("(progn (goto-char 4) (insert \"XY\"))", "t", 3),
+            ("(delete-region 2 5)", "nil", 2),
+            ("(delete-region 1 2)", "t", 2),
+            ("(delete-region 3 5)", "nil", 3),
+            (
+                "(progn (goto-char 3) (insert \"X\") (delete-char -1))",
+                "t",
+                3,
+            ),
+        ];
+        for (edit, insertion_type, expect) in cases {
+            check(
+                "(progn (erase-buffer) (insert \"abcdef\") (goto-char 3))",
+                env,
+                cx,
+            );
+            check(
+                &format!("(setq m (copy-marker 3 {insertion_type}))"),
+                env,
+                cx,
+            );
+            check(edit, env, cx);
+            let pos = check("(marker-position m)", env, cx);
+            assert_eq!(pos, expect, "{edit} with insertion type {insertion_type}");
+        }
+        // point moves past text inserted before markers
+        check(
+            "(progn (erase-buffer) (insert \"abcdef\") (goto-char 3))",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check("(progn (insert-before-markers \"XY\") (point))", env, cx),
+            5
+        );
+    }
+
+    #[test]
+    fn test_marker_places() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"marker-places\"))",
+            env,
+            cx,
+        );
+        check("(insert \"abcdef\")", env, cx);
+        check("(setq m (make-marker))", env, cx);
+        let form =
+            "(list (markerp m) (marker-position m) (marker-buffer m) (marker-insertion-type m))";
+        assert_eq!(check(form, env, cx).to_string(), "(t nil nil nil)");
+        assert_eq!(
+            check("(format \"%s\" m)", env, cx),
+            "#<marker in no buffer>"
+        );
+        // positions are clamped to the whole buffer, ignoring narrowing
+        check("(narrow-to-region 2 4)", env, cx);
+        let form = "(list (marker-position (set-marker m 100)) (marker-position (set-marker m 0))
+                          (marker-position (set-marker m 6)))";
+        assert_eq!(check(form, env, cx).to_string(), "(7 1 6)");
+        check("(widen)", env, cx);
+        assert_eq!(
+            check("(format \"%s\" m)", env, cx),
+            "#<marker at 6 in marker-places>"
+        );
+        // markers can be used as positions
+        assert_eq!(check("(progn (goto-char m) (point))", env, cx), 6);
+        let form = "(list (marker-position (point-marker)) (marker-position (point-min-marker))
+                          (marker-position (copy-marker m)) (marker-insertion-type (copy-marker m t)))";
+        assert_eq!(check(form, env, cx).to_string(), "(6 1 6 t)");
+        check("(set-marker-insertion-type m t)", env, cx);
+        assert_eq!(
+            check("(format \"%s\" m)", env, cx),
+            "#<marker (moves after insertion) at 6 in marker-places>"
+        );
+        check("(set-marker m nil)", env, cx);
+        assert_eq!(check("(marker-position m)", env, cx), nil());
+        // a marker in a killed buffer points nowhere
+        check(
+            "(set-buffer (get-buffer-create \"marker-killed\"))",
+            env,
+            cx,
+        );
+        check("(insert \"abc\")", env, cx);
+        check("(set-marker m 2)", env, cx);
+        check("(kill-buffer \"marker-killed\")", env, cx);
+        assert_eq!(
+            check("(list (marker-position m) (marker-buffer m))", env, cx).to_string(),
+            "(nil nil)"
+        );
+    }
+
+    #[test]
+    fn test_overlays_and_insert_before_markers() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"marker-overlays\"))",
+            env,
+            cx,
+        );
+        check("(insert \"abcdef\")", env, cx);
+        // text inserted before markers is outside an overlay that starts at
+        // point and inside one that ends there
+        check(
+            "(setq starts (make-overlay 3 5) ends (make-overlay 1 3))",
+            env,
+            cx,
+        );
+        check(
+            "(progn (goto-char 3) (insert-before-markers \"XY\"))",
+            env,
+            cx,
+        );
+        let form = "(list (overlay-start starts) (overlay-end starts) (overlay-start ends) (overlay-end ends))";
+        assert_eq!(check(form, env, cx).to_string(), "(5 7 1 5)");
+    }
+}
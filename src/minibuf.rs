@@ -0,0 +1,273 @@
+//! Completion of symbol names and strings.
+use crate::core::env::{sym, Env, Symbol, INTERNED_SYMBOLS};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, Function, Gc, GcObj, LispString, Object};
+use crate::fns::slice_into_list;
+use crate::root;
+use anyhow::Result;
+use fn_macros::defun;
+
+/// Which symbols are offered as completions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SymbolFilter {
+    All,
+    /// Symbols with a function definition, as with `fboundp`
+    Function,
+    /// Symbols with a value, as with `boundp`
+    Variable,
+    /// Symbols that are interactive commands, as with `commandp`
+    Command,
+}
+
+impl SymbolFilter {
+    /// The filter that is the same as calling the function PREDICATE.
+    fn for_predicate(predicate: Symbol) -> Option<Self> {
+        match predicate {
+            sym::FBOUNDP => Some(Self::Function),
+            sym::BOUNDP => Some(Self::Variable),
+            sym::COMMANDP => Some(Self::Command),
+            _ => None,
+        }
+    }
+
+    fn matches(self, symbol: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+        match self {
+            Self::All => true,
+            Self::Function => symbol.has_func(),
+            Self::Variable => env.vars.get(symbol).is_some(),
+            Self::Command => crate::eval::commandp(symbol.into(), None, cx),
+        }
+    }
+}
+
+/// The names of the interned symbols that start with `prefix` and match
+/// `filter`, sorted. Matching is case-sensitive. This is used by both
+/// `all-completions` and the REPL.
+pub(crate) fn symbol_completions(
+    prefix: &str,
+    filter: SymbolFilter,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Vec<String> {
+    let symbols: Vec<_> = INTERNED_SYMBOLS
+        .lock()
+        .unwrap()
+        .symbols()
+        .filter(|x| x.name().starts_with(prefix))
+        .map(|x| x.name().to_owned())
+        .collect();
+    let mut names: Vec<_> = symbols
+        .into_iter()
+        .filter(|name| {
+            let symbol = crate::core::env::intern(name, cx);
+            filter.matches(symbol, env, cx)
+        })
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// The longest prefix shared by all of `names`.
+pub(crate) fn common_prefix(names: &[impl AsRef<str>]) -> &str {
+    let Some((first, rest)) = names.split_first() else {return ""};
+    let first = first.as_ref();
+    let mut len = first.len();
+    for name in rest {
+        let name = name.as_ref();
+        let mismatch = first
+            .char_indices()
+            .zip(name.chars())
+            .find(|((_, a), b)| a != b);
+        len = match mismatch {
+            Some(((i, _), _)) => len.min(i),
+            None => len.min(name.len()),
+        };
+    }
+    &first[..len]
+}
+
+/// The names in COLLECTION that start with STRING and satisfy PREDICATE.
+fn completions(
+    string: &Rt<Gc<&LispString>>,
+    collection: &Rt<GcObj>,
+    predicate: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<Vec<String>> {
+    let string: &str = string.get(cx).try_into()?;
+    let string = string.to_owned();
+    let predicate = predicate.filter(|x| !x.bind(cx).nil());
+    let mut names = Vec::new();
+    let mut args = Vec::new();
+    let collection = collection.bind(cx);
+    if collection == sym::OBARRAY {
+        // Use the builtin filters for the common predicates
+        let filter = match predicate.map(|x| x.bind(cx).untag()) {
+            None => Some(SymbolFilter::All),
+            Some(Object::Symbol(x)) => SymbolFilter::for_predicate(x),
+            Some(_) => None,
+        };
+        let found = symbol_completions(&string, filter.unwrap_or(SymbolFilter::All), env, cx);
+        if filter.is_some() {
+            return Ok(found);
+        }
+        for name in found {
+            args.push(GcObj::from(crate::core::env::intern(&name, cx)));
+            names.push(name);
+        }
+    } else {
+        for elem in collection.as_list()? {
+            let elem = elem?;
+            let key = match elem.untag() {
+                Object::Cons(cons) => cons.car(),
+                _ => elem,
+            };
+            let name = match key.untag() {
+                Object::String(x) => <&str>::try_from(x).ok().map(ToOwned::to_owned),
+                Object::Symbol(x) => Some(x.name().to_owned()),
+                _ => None,
+            };
+            if let Some(name) = name.filter(|x| x.starts_with(&string)) {
+                names.push(name);
+                args.push(elem);
+            }
+        }
+    }
+    let Some(predicate) = predicate else {return Ok(names)};
+    root!(args, move(args), cx);
+    let func: &Rt<Gc<Function>> = Rt::try_into(predicate)?;
+    let mut kept = Vec::new();
+    for (name, arg) in names.into_iter().zip(args.iter()) {
+        let arg = vec![arg.bind(cx)];
+        root!(arg, move(arg), cx);
+        if !func.call(arg, env, cx, None)?.nil() {
+            kept.push(name);
+        }
+    }
+    Ok(kept)
+}
+
+/// Return the names in COLLECTION that start with STRING, as a list of
+/// strings. COLLECTION is either `obarray` or a list of strings, symbols, or
+/// conses whose car is a string or symbol. If PREDICATE is non-nil, it is
+/// called with each symbol or element and only those it returns non-nil for
+/// are included. Matching is case-sensitive.
+#[defun]
+fn all_completions<'ob>(
+    string: &Rt<Gc<&LispString>>,
+    collection: &Rt<GcObj>,
+    predicate: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let names = completions(string, collection, predicate, env, cx)?;
+    let names: Vec<GcObj> = names.into_iter().map(|x| cx.add(x)).collect();
+    Ok(slice_into_list(&names, None, cx))
+}
+
+/// Return the longest prefix shared by the completions of STRING in
+/// COLLECTION, nil if there are none, or t if STRING is the only one. See
+/// `all-completions` for COLLECTION and PREDICATE.
+#[defun]
+fn try_completion<'ob>(
+    string: &Rt<Gc<&LispString>>,
+    collection: &Rt<GcObj>,
+    predicate: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let names = completions(string, collection, predicate, env, cx)?;
+    if names.is_empty() {
+        return Ok(nil());
+    }
+    let string: &str = string.get(cx).try_into()?;
+    if names.iter().all(|x| x == string) {
+        return Ok(sym::TRUE.into());
+    }
+    Ok(cx.add(common_prefix(&names)))
+}
+
+defvar!(OBARRAY, sym::OBARRAY);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(common_prefix(&[] as &[&str]), "");
+        assert_eq!(common_prefix(&["car"]), "car");
+        assert_eq!(common_prefix(&["car-safe", "car", "carπ"]), "car");
+        assert_eq!(common_prefix(&["πa", "πb"]), "π");
+    }
+
+    #[test]
+    fn test_completion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        let names = symbol_completions("car-", SymbolFilter::Function, env, cx);
+        assert_eq!(names, ["car-safe"]);
+        check("(defvar completion-test-var 1)", env, cx);
+        check("(intern \"completion-test-fn\")", env, cx);
+        let names = symbol_completions("completion-test-", SymbolFilter::All, env, cx);
+        assert_eq!(names, ["completion-test-fn", "completion-test-var"]);
+        let names = symbol_completions("completion-test-", SymbolFilter::Variable, env, cx);
+        assert_eq!(names, ["completion-test-var"]);
+        let names = symbol_completions("Car-", SymbolFilter::All, env, cx);
+        assert_eq!(names, Vec::<String>::new());
+
+        check(
+            "(fset 'completion-test-cmd '(lambda () \"doc\" (interactive) 1))",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check(
+                "(all-completions \"completion-test\" obarray 'commandp)",
+                env,
+                cx
+            )
+            .to_string(),
+            "(\"completion-test-cmd\")"
+        );
+        assert_eq!(
+            check(
+                "(all-completions \"completion-test-\" obarray #'(lambda (x) (boundp x)))",
+                env,
+                cx
+            )
+            .to_string(),
+            "(\"completion-test-var\")"
+        );
+        assert_eq!(
+            check("(try-completion \"completion-t\" obarray)", env, cx),
+            "completion-test-"
+        );
+        let list = "'(\"foo\" \"foobar\" (\"fox\" . 1) bar)";
+        assert_eq!(
+            check(&format!("(all-completions \"fo\" {list})"), env, cx).to_string(),
+            "(\"foo\" \"foobar\" \"fox\")"
+        );
+        assert_eq!(
+            check(&format!("(all-completions \"fo\" {list} 'consp)"), env, cx).to_string(),
+            "(\"fox\")"
+        );
+        assert_eq!(
+            check(&format!("(try-completion \"foob\" {list})"), env, cx),
+            "foobar"
+        );
+        assert_eq!(
+            check(&format!("(try-completion \"foobar\" {list})"), env, cx),
+            sym::TRUE
+        );
+        assert_eq!(
+            check(&format!("(try-completion \"f\" {list})"), env, cx),
+            "fo"
+        );
+        assert!(check(&format!("(try-completion \"x\" {list})"), env, cx).nil());
+    }
+}
@@ -0,0 +1,239 @@
+//! Telemetry for the parts of Emacs that Lisp code uses but that are not
+//! implemented yet. Every call to an undefined function or special form and
+//! every unknown reader syntax is counted in the [`Env`], so the most needed
+//! primitives can be listed with `rune-missing-primitives`. Errors for
+//! undefined functions suggest an implemented alternative when there is one.
+use crate::core::env::{intern, Env, Symbol};
+use crate::core::error::EvalError;
+use crate::core::gc::{Context, Rt};
+use crate::core::object::GcObj;
+use crate::fns::slice_into_list;
+use crate::reader;
+use fn_macros::defun;
+use std::fmt::{Display, Formatter};
+
+/// Implemented functions that can stand in for ones that are not implemented
+/// yet.
+const ALTERNATIVES: &[(&str, &str)] = &[
+    ("setq-default", "set-default"),
+    ("default-value", "symbol-value"),
+    ("make-local-variable", "make-variable-buffer-local"),
+    ("substring-no-properties", "substring"),
+    ("propertize", "put-text-property"),
+    ("char-to-string", "string"),
+    ("string-to-char", "aref"),
+    ("string-to-list", "append"),
+    ("number-to-string", "format"),
+    ("rassoc", "rassq"),
+    ("proper-list-p", "safe-length"),
+    ("forward-char", "goto-char"),
+    ("backward-char", "goto-char"),
+    ("bobp", "point-min"),
+    ("eobp", "point-max"),
+];
+
+/// The special forms of Emacs. The interpreter and the bytecode evaluate the
+/// ones that are implemented without looking up their function, so a call to
+/// one of these that reaches [`void_function`] is not implemented, or not
+/// built.
+const SPECIAL_FORMS: &[&str] = &[
+    "and",
+    "catch",
+    "cond",
+    "condition-case",
+    "defconst",
+    "defvar",
+    "function",
+    "if",
+    "interactive",
+    "let",
+    "let*",
+    "or",
+    "prog1",
+    "prog2",
+    "progn",
+    "quote",
+    "save-current-buffer",
+    "save-excursion",
+    "save-restriction",
+    "setq",
+    "setq-default",
+    "unwind-protect",
+    "while",
+];
+
+/// Something that Lisp code used but is not implemented.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Gap {
+    Function(String),
+    SpecialForm(String),
+    /// A `#` reader macro character
+    ReaderSyntax(char),
+}
+
+impl Gap {
+    fn name(&self) -> String {
+        match self {
+            Gap::Function(name) | Gap::SpecialForm(name) => name.clone(),
+            Gap::ReaderSyntax(chr) => format!("#{chr}"),
+        }
+    }
+}
+
+fn record(gap: Gap, env: &mut Rt<Env>) {
+    *env.missing.entry(gap).or_default() += 1;
+}
+
+/// Record the reader syntax that ERROR is about if it is unknown.
+pub(crate) fn record_read_error(error: &reader::Error, env: &mut Rt<Env>) {
+    if let reader::Error::UnknownMacroCharacter(chr, _) = error {
+        record(Gap::ReaderSyntax(*chr), env);
+    }
+}
+
+/// An implemented function that can be used instead of NAME. Alternatives
+/// that are not built are not suggested.
+fn alternative(name: &str, cx: &Context) -> Option<&'static str> {
+    let (_, alternative) = ALTERNATIVES.iter().find(|x| x.0 == name)?;
+    intern(alternative, cx).has_func().then_some(*alternative)
+}
+
+/// A function or special form was called that is not defined.
+#[derive(Debug, PartialEq)]
+pub(crate) struct VoidFunction {
+    name: String,
+    special_form: bool,
+    alternative: Option<&'static str>,
+}
+
+impl std::error::Error for VoidFunction {}
+
+impl Display for VoidFunction {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Void function: {}", self.name)?;
+        match (self.special_form, self.alternative) {
+            (false, None) => Ok(()),
+            (true, None) => write!(f, " (special form not implemented yet)"),
+            (special_form, Some(alternative)) => {
+                let kind = if special_form { "special form " } else { "" };
+                write!(
+                    f,
+                    " ({kind}not implemented yet, try `{alternative}' instead)"
+                )
+            }
+        }
+    }
+}
+
+impl From<VoidFunction> for EvalError {
+    fn from(e: VoidFunction) -> Self {
+        Self::new_error(e.into())
+    }
+}
+
+/// The error for calling the undefined function or special form NAME. The
+/// call is recorded for `rune-missing-primitives`.
+pub(crate) fn void_function(name: Symbol, env: &mut Rt<Env>, cx: &Context) -> EvalError {
+    let name = name.name().to_owned();
+    let alternative = alternative(&name, cx);
+    let special_form = SPECIAL_FORMS.contains(&name.as_str());
+    let gap = match special_form {
+        true => Gap::SpecialForm(name.clone()),
+        false => Gap::Function(name.clone()),
+    };
+    record(gap, env);
+    VoidFunction {
+        name,
+        special_form,
+        alternative,
+    }
+    .into()
+}
+
+/// Return the functions and special forms that were called without being
+/// defined and the reader syntax that was not understood, most frequent
+/// first. Each element is `(NAME . COUNT)`, where NAME is a symbol for a
+/// function or special form or a string like "#s" for reader syntax.
+#[defun]
+fn rune_missing_primitives<'ob>(env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    let mut gaps: Vec<_> = env
+        .missing
+        .iter()
+        .map(|(gap, count)| (gap.clone(), *count))
+        .collect();
+    gaps.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name().cmp(&b.0.name())));
+    let entries: Vec<GcObj> = gaps
+        .into_iter()
+        .map(|(gap, count)| {
+            let name: GcObj = match &gap {
+                Gap::Function(name) | Gap::SpecialForm(name) => intern(name, cx).into(),
+                Gap::ReaderSyntax(_) => cx.add(gap.name()),
+            };
+            cons!(name, count as i64; cx)
+        })
+        .collect();
+    slice_into_list(&entries, None, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::Env;
+    use crate::core::gc::{RootSet, Rt};
+    use crate::{interpreter, reader, root};
+
+    fn eval_err(form: &str, env: &mut Rt<Env>, cx: &mut Context) -> String {
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        interpreter::eval(obj, None, env, cx)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_void_function() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let err = eval_err("(setq-default x 1)", env, cx);
+        assert!(
+            err.starts_with(
+                "Void function: setq-default (special form not implemented yet, try `set-default' instead)"
+            ),
+            "{err}"
+        );
+        let err = eval_err("(missing-test-fn 1)", env, cx);
+        assert!(err.starts_with("Void function: missing-test-fn\n"), "{err}");
+        eval_err("(funcall 'missing-test-fn)", env, cx);
+        eval_err("(read-from-string \"#q\")", env, cx);
+
+        let report = rune_missing_primitives(env, cx).to_string();
+        assert_eq!(
+            report,
+            "((missing-test-fn . 2) (\"#q\" . 1) (setq-default . 1))"
+        );
+        assert_eq!(
+            env.missing
+                .get(&Gap::SpecialForm("setq-default".to_owned())),
+            Some(&1)
+        );
+        // the counts belong to the environment
+        root!(other, Env::default(), cx);
+        assert_eq!(rune_missing_primitives(other, cx).to_string(), "nil");
+    }
+
+    #[cfg(not(feature = "buffers"))]
+    #[test]
+    fn test_special_form_not_built() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let err = eval_err("(save-excursion 1)", env, cx);
+        assert!(
+            err.starts_with("Void function: save-excursion (special form not implemented yet)"),
+            "{err}"
+        );
+        let report = rune_missing_primitives(env, cx).to_string();
+        assert_eq!(report, "((save-excursion . 1))");
+    }
+}
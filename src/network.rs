@@ -0,0 +1,830 @@
+//! Network processes. A TCP connection is read like the output of a
+//! subprocess, and a server makes a process for each connection it accepts,
+//! with the buffer, filter, sentinel and coding systems of the server. A UDP
+//! socket is a datagram process, whose filter gets each datagram by itself.
+#![cfg(feature = "network")]
+use crate::buffer::get_buffer_create;
+use crate::core::{
+    env::{sym, Env, Symbol},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{nil, GcObj, Object, Process, ProcessData, ProcessKind, Status},
+};
+use crate::fileio::file_io_error;
+use crate::fns::slice_into_list;
+use crate::process::Event;
+use crate::process::{add_process, call_handler, call_sentinel, keyword_arg, process_codings};
+use crate::process::{decode_output, deliver_output, push_event, read_output, slot, start_process};
+use crate::root;
+use anyhow::{anyhow, bail, ensure, Result};
+use fn_macros::defun;
+use std::fs::File;
+use std::io;
+use std::net::UdpSocket;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::OwnedFd;
+
+/// The port of SERVICE: a port number, a string of one, or the name of a
+/// service for `protocol` in `/etc/services`.
+fn service_port(service: GcObj, protocol: &str) -> Result<u16> {
+    let name: &str = match service.untag() {
+        Object::Int(port) => return Ok(u16::try_from(port)?),
+        Object::String(name) => name.try_into()?,
+        _ => bail!(TypeError::new(Type::String, service)),
+    };
+    if let Ok(port) = name.parse() {
+        return Ok(port);
+    }
+    let services = std::fs::read_to_string("/etc/services").unwrap_or_default();
+    services
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split('#').next()?.split_whitespace();
+            let (service, entry) = (fields.next()?, fields.next()?);
+            let (port, proto) = entry.split_once('/')?;
+            if proto != protocol || (service != name && !fields.any(|x| x == name)) {
+                return None;
+            }
+            port.parse().ok()
+        })
+        .ok_or_else(|| anyhow!("Unknown service: {name}"))
+}
+
+/// The addresses of `port` on HOST, a host name or address, or `local` for
+/// the local host. A nil HOST is the local host for a client, and any
+/// address of the local host for a server. FAMILY is `ipv4` or `ipv6` to
+/// only use addresses of that family, or nil for any.
+fn resolve(host: GcObj, port: u16, family: GcObj, server: bool) -> Result<Vec<SocketAddr>> {
+    let ipv6 = match family.untag() {
+        Object::NIL | Object::Symbol(sym::IPV4) => false,
+        Object::Symbol(sym::IPV6) => true,
+        _ => bail!("Unsupported address family: {family}"),
+    };
+    let any = |ip: IpAddr| family.nil() || ip.is_ipv6() == ipv6;
+    let addrs: Vec<SocketAddr> = match host.untag() {
+        Object::NIL if server && ipv6 => vec![(Ipv6Addr::UNSPECIFIED, port).into()],
+        Object::NIL if server => vec![(Ipv4Addr::UNSPECIFIED, port).into()],
+        Object::NIL | Object::Symbol(sym::LOCAL) if ipv6 => {
+            vec![(Ipv6Addr::LOCALHOST, port).into()]
+        }
+        Object::NIL | Object::Symbol(sym::LOCAL) => vec![(Ipv4Addr::LOCALHOST, port).into()],
+        Object::String(name) => {
+            let name: &str = name.try_into()?;
+            match (name, port).to_socket_addrs() {
+                Ok(addrs) => addrs.filter(|x| any(x.ip())).collect(),
+                Err(e) => bail!("{name}/{port} {e}"),
+            }
+        }
+        _ => bail!(TypeError::new(Type::String, host)),
+    };
+    ensure!(!addrs.is_empty(), "{host}/{port} has no {family} address");
+    Ok(addrs)
+}
+
+/// `address` as a vector of the parts of its IP address followed by its
+/// port, the way Lisp sees network addresses.
+fn address_vector<'ob>(address: SocketAddr, cx: &'ob Context) -> GcObj<'ob> {
+    let mut parts: Vec<GcObj> = match address.ip() {
+        IpAddr::V4(ip) => ip.octets().iter().map(|&x| i64::from(x).into()).collect(),
+        IpAddr::V6(ip) => ip.segments().iter().map(|&x| i64::from(x).into()).collect(),
+    };
+    parts.push(i64::from(address.port()).into());
+    cx.add(parts)
+}
+
+/// The network address ADDRESS stands for, a vector of the four bytes of an
+/// IPv4 address or the eight parts of an IPv6 address followed by the port.
+fn vector_address(address: GcObj) -> Result<SocketAddr> {
+    let Object::Vec(vec) = address.untag() else {bail!(TypeError::new(Type::Vec, address))};
+    let parts = vec
+        .iter()
+        .map(|x| usize::try_from(x.get()))
+        .collect::<Result<Vec<_>>>()?;
+    let Some((port, ip)) = parts.split_last() else {bail!("Invalid network address: {address}")};
+    let port = u16::try_from(*port)?;
+    let ip: IpAddr = match ip.len() {
+        4 => {
+            let octets = ip
+                .iter()
+                .map(|&x| u8::try_from(x))
+                .collect::<Result<Vec<_>, _>>()?;
+            <[u8; 4]>::try_from(octets).unwrap().into()
+        }
+        8 => {
+            let segments = ip
+                .iter()
+                .map(|&x| u16::try_from(x))
+                .collect::<Result<Vec<_>, _>>()?;
+            <[u16; 8]>::try_from(segments).unwrap().into()
+        }
+        _ => bail!("Invalid network address: {address}"),
+    };
+    Ok((ip, port).into())
+}
+
+/// ARGS as a property list for `process-contact`, with `port` as the
+/// service, since a server listens on a free port if the service was t.
+fn server_contact<'ob>(args: &[GcObj<'ob>], port: u16, cx: &'ob Context) -> GcObj<'ob> {
+    let mut contact = args.to_vec();
+    let port = i64::from(port).into();
+    match contact.iter().position(|x| *x == sym::KW_SERVICE) {
+        Some(i) if i % 2 == 0 => contact[i + 1] = port,
+        _ => contact.extend::<[GcObj; 2]>([sym::KW_SERVICE.into(), port]),
+    }
+    slice_into_list(&contact, None, cx)
+}
+
+/// A file to write to `stream` through, as the input of a process.
+fn socket_writer(stream: &TcpStream) -> io::Result<File> {
+    Ok(File::from(OwnedFd::from(stream.try_clone()?)))
+}
+
+/// Make the connection `process` was waiting for, or record that it failed.
+/// The sentinel is told either way.
+pub(crate) fn finish_connect(process: &'static Process, result: io::Result<TcpStream>) {
+    match result.and_then(|stream| Ok((socket_writer(&stream)?, stream))) {
+        Ok((writer, stream)) => {
+            read_output(process, stream);
+            let mut data = process.lock();
+            data.stdin = Some(writer);
+            data.status = Status::Run;
+        }
+        Err(e) => process.lock().status = Status::Failed(e.raw_os_error().unwrap_or(0)),
+    }
+    process.lock().status_changed = true;
+}
+
+/// Read the datagrams `socket` receives for `process` on another thread.
+fn read_datagrams(process: &'static Process, socket: UdpSocket) {
+    process.lock().open_streams += 1;
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 65536];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => push_event(Event::Datagram(process, buf[..len].to_vec(), from)),
+                // a datagram that was sent earlier did not arrive
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        push_event(Event::Eof(process));
+    });
+}
+
+/// Give the datagram `bytes` that `process` received from `from` to its
+/// filter. Replies go to where the last datagram came from.
+pub(crate) fn receive_datagram(
+    process: &'static Process,
+    bytes: &[u8],
+    from: SocketAddr,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) {
+    let text = {
+        let mut data = process.lock();
+        data.datagram_address = Some(from);
+        decode_output(&mut data, bytes, true)
+    };
+    deliver_output(process, &text, env, cx);
+}
+
+/// The address of any interface with the port and family of `address`.
+fn unspecified(address: SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, address.port()).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, address.port()).into(),
+    }
+}
+
+/// Make a datagram process for a UDP socket. A server receives datagrams on
+/// `addrs`, and joins the group if they are a multicast address. A client
+/// sends them to the first of `addrs` it can.
+fn datagram_process<'ob>(
+    name: &str,
+    mut data: ProcessData,
+    addrs: &[SocketAddr],
+    args: &[GcObj<'ob>],
+    mut slots: Vec<(Symbol, GcObj<'ob>)>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'static Process> {
+    let server = !keyword_arg(args, sym::KW_SERVER).nil();
+    let group = addrs.iter().copied().find(|x| x.ip().is_multicast());
+    let open = || -> io::Result<UdpSocket> {
+        let socket = match group {
+            Some(group) if server => UdpSocket::bind(unspecified(group))?,
+            _ if server => UdpSocket::bind(addrs)?,
+            _ => {
+                let socket = UdpSocket::bind(unspecified(SocketAddr::new(addrs[0].ip(), 0)))?;
+                socket.connect(addrs)?;
+                socket
+            }
+        };
+        socket.set_broadcast(!keyword_arg(args, sym::KW_BROADCAST).nil())?;
+        match group.map(|x| x.ip()) {
+            Some(IpAddr::V4(ip)) if server => {
+                socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+            }
+            Some(IpAddr::V6(ip)) if server => socket.join_multicast_v6(&ip, 0)?,
+            _ => {}
+        }
+        Ok(socket)
+    };
+    let socket = match open() {
+        Ok(socket) => socket,
+        Err(e) => {
+            let action = if server {
+                "Cannot bind server socket"
+            } else {
+                "make client process failed"
+            };
+            return Err(file_io_error(&e, action, name, env, cx));
+        }
+    };
+    let contact = match server {
+        true => server_contact(args, socket.local_addr()?.port(), cx),
+        false => slice_into_list(args, None, cx),
+    };
+    slots.push((sym::PROCESS_CONTACT, contact));
+    let reader = socket.try_clone()?;
+    data.kind = ProcessKind::Network;
+    data.datagram_address = socket.peer_addr().ok();
+    data.socket = Some(socket);
+    let process = add_process(name, data, &slots, env)?;
+    read_datagrams(process, reader);
+    Ok(process)
+}
+
+/// Make a process for the connection `server` accepted from `peer`. It is
+/// named after the server and the peer, and so is its buffer if the server
+/// has one. The log function of the server and the sentinel of the new
+/// process are told about it.
+pub(crate) fn accept_connection(
+    server: &'static Process,
+    stream: TcpStream,
+    peer: SocketAddr,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let host = peer.ip().to_string();
+    let port = i64::from(peer.port());
+    let name = format!("{} <{host}:{port}>", server.name());
+    let (buffer, decoding, encoding, query_on_exit) = {
+        let data = server.lock();
+        (
+            data.buffer,
+            data.decoding,
+            data.encoding,
+            data.query_on_exit,
+        )
+    };
+    let mut data = ProcessData::new(decoding, encoding);
+    data.kind = ProcessKind::Network;
+    data.query_on_exit = query_on_exit;
+    data.stdin = Some(socket_writer(&stream)?);
+    if let Some(buffer) = buffer {
+        let name = format!("{} <{host}:{port}>", buffer.lock()?.name());
+        data.buffer = Some(get_buffer_create(cx.add(name), None)?);
+    }
+    let contact =
+        list![sym::KW_NAME, name.as_str(), sym::KW_HOST, host.as_str(), sym::KW_SERVICE, port; cx];
+    let mut slots: Vec<_> = [sym::KW_FILTER, sym::KW_SENTINEL, sym::KW_PLIST]
+        .map(|key| (key, slot(server, key, env, cx)))
+        .into();
+    slots.push((sym::PROCESS_CONTACT, contact));
+    let client = start_process(&name, data, stream, &slots, env)?;
+    let log = slot(server, sym::KW_LOG, env, cx);
+    if !log.nil() {
+        root!(log, cx);
+        let message = format!("accept from {host}\n");
+        call_handler(log, &[server, client], &message, "log", env, cx);
+    }
+    call_sentinel(client, &format!("open from {host}\n"), env, cx);
+    Ok(())
+}
+
+/// Make a TCP connection or server and return the process for it. The
+/// arguments are keywords and their values:
+///
+/// :name NAME is the name of the process, which is made unique.
+///
+/// :buffer BUFFER is the buffer or buffer name of the process, which the
+/// output goes to by default.
+///
+/// :host HOST is the name or address of the host to connect to, or `local`
+/// for the local host. A server only accepts connections to HOST, or to any
+/// address of the local host if HOST is nil.
+///
+/// :service SERVICE is the port number or service name to connect to or
+/// listen on. A server listens on a free port if it is t, and
+/// `process-contact` tells which.
+///
+/// :type TYPE is nil or `stream` for a TCP connection, and `datagram` for a
+/// UDP socket. The filter of a datagram process gets each datagram by
+/// itself, and `process-datagram-address` tells where they came from.
+/// A datagram server joins the group if HOST is a multicast address.
+///
+/// :family FAMILY is `ipv4` or `ipv6` to only use addresses of that family.
+///
+/// :coding CODING, :filter FILTER and :sentinel SENTINEL are as in
+/// `make-process`.
+///
+/// :nowait NOWAIT non-nil returns before the connection is made. The status
+/// of the process is `connect` until then, and the sentinel is called with
+/// "open\n" once it is made, or with "failed with code N\n" if it could not
+/// be.
+///
+/// :server SERVER non-nil makes a server, whose status is `listen`. For
+/// each connection it accepts, it makes a process with its buffer, filter,
+/// sentinel and property list, and calls the sentinel of that process with
+/// "open from HOST\n".
+///
+/// :broadcast BROADCAST non-nil lets a datagram process send to broadcast
+/// addresses.
+///
+/// :tls-parameters PARAMS makes a TLS connection, as `gnutls-boot` would
+/// with PARAMS. It is `(gnutls-x509pki . PLIST)`, where PLIST has the
+/// :hostname the certificate has to be for, the :trustfiles of the trusted
+/// authorities, and a :keylist of `(KEY CERT)` files for a client
+/// certificate.
+///
+/// :log LOG is called with the server, the new process and a message when a
+/// server accepts a connection.
+///
+/// :plist PLIST is the property list of the process.
+///
+/// :noquery is as in `make-process`, and the connections a server accepts
+/// get its flag. :stop is accepted and ignored.
+#[defun]
+fn make_network_process<'ob>(
+    args: &[GcObj<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'static Process> {
+    ensure!(
+        args.len().is_multiple_of(2),
+        "Odd number of arguments to make-network-process"
+    );
+    let arg = |key| keyword_arg(args, key);
+    let name: &str = arg(sym::KW_NAME).try_into()?;
+    let datagram = match arg(sym::KW_TYPE).untag() {
+        Object::NIL | Object::Symbol(sym::STREAM) => false,
+        Object::Symbol(sym::DATAGRAM) => true,
+        kind => bail!("Unsupported connection type: {kind}"),
+    };
+    let buffer = match arg(sym::KW_BUFFER) {
+        x if x.nil() => None,
+        x => Some(get_buffer_create(x, None)?),
+    };
+    let server = !arg(sym::KW_SERVER).nil();
+    let tls = arg(sym::KW_TLS_PARAMETERS);
+    ensure!(
+        tls.nil() || !(server || datagram),
+        "TLS is only supported for client stream connections"
+    );
+    ensure!(
+        tls.nil() || cfg!(feature = "tls"),
+        "TLS is not available, since rune is built without the tls feature"
+    );
+    let port = match arg(sym::KW_SERVICE) {
+        x if server && x == sym::TRUE => 0,
+        x => service_port(x, if datagram { "udp" } else { "tcp" })?,
+    };
+    let addrs = resolve(arg(sym::KW_HOST), port, arg(sym::KW_FAMILY), server)?;
+    let (decoding, encoding) = process_codings(arg(sym::KW_CODING), env, cx)?;
+    let mut data = ProcessData::new(decoding, encoding);
+    data.buffer = buffer;
+    data.query_on_exit = arg(sym::KW_NOQUERY).nil();
+    let mut slots: Vec<_> = [sym::KW_FILTER, sym::KW_SENTINEL, sym::KW_PLIST, sym::KW_LOG]
+        .map(|key| (key, arg(key)))
+        .into();
+    if datagram {
+        return datagram_process(name, data, &addrs, args, slots, env, cx);
+    }
+
+    if server {
+        let listener = match TcpListener::bind(&addrs[..]) {
+            Ok(listener) => listener,
+            Err(e) => {
+                return Err(file_io_error(
+                    &e,
+                    "Cannot bind server socket",
+                    name,
+                    env,
+                    cx,
+                ))
+            }
+        };
+        let contact = server_contact(args, listener.local_addr()?.port(), cx);
+        slots.push((sym::PROCESS_CONTACT, contact));
+        data.kind = ProcessKind::Server;
+        // kept to shut the socket down when the server is deleted
+        data.listener = Some(listener.try_clone()?);
+        let process = add_process(name, data, &slots, env)?;
+        std::thread::spawn(move || loop {
+            match listener.accept() {
+                Ok((stream, peer)) => push_event(Event::Accepted(process, stream, peer)),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        });
+        return Ok(process);
+    }
+
+    slots.push((sym::PROCESS_CONTACT, slice_into_list(args, None, cx)));
+    data.kind = ProcessKind::Network;
+    #[cfg(feature = "tls")]
+    if !tls.nil() {
+        let host = match arg(sym::KW_HOST).untag() {
+            Object::String(host) => host.try_into()?,
+            _ => "localhost",
+        };
+        let params = crate::tls::parameters(tls, host)?;
+        return tls_process(name, data, &addrs, &params, &slots, env, cx);
+    }
+    if !arg(sym::KW_NOWAIT).nil() {
+        data.status = Status::Connect;
+        let process = add_process(name, data, &slots, env)?;
+        std::thread::spawn(move || {
+            push_event(Event::Connected(process, TcpStream::connect(&addrs[..])));
+        });
+        return Ok(process);
+    }
+    let stream = match TcpStream::connect(&addrs[..]).and_then(|x| Ok((socket_writer(&x)?, x))) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return Err(file_io_error(
+                &e,
+                "make client process failed",
+                name,
+                env,
+                cx,
+            ))
+        }
+    };
+    data.stdin = Some(stream.0);
+    start_process(name, data, stream.1, &slots, env)
+}
+
+/// Connect to the first of `addrs` that accepts, make a TLS connection over
+/// it with `params`, and make a process named `name` from `data` that reads
+/// the decrypted stream. The handshake is done before the process is made,
+/// so that a certificate that is not valid is an error of the caller.
+#[cfg(feature = "tls")]
+fn tls_process(
+    name: &str,
+    mut data: ProcessData,
+    addrs: &[SocketAddr],
+    params: &crate::tls::Parameters,
+    slots: &[(Symbol, GcObj)],
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Process> {
+    let mut stream = match TcpStream::connect(addrs) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return Err(file_io_error(
+                &e,
+                "make client process failed",
+                name,
+                env,
+                cx,
+            ))
+        }
+    };
+    let session = crate::tls::negotiate(&mut stream, params)?;
+    data.stdin = Some(socket_writer(&stream)?);
+    data.tls = Some(session.clone());
+    start_process(
+        name,
+        data,
+        crate::tls::Reader::new(stream, session),
+        slots,
+        env,
+    )
+}
+
+/// Open a TCP connection to SERVICE on HOST for a process named NAME, whose
+/// output goes to BUFFER, and return the process. PARAMETERS are keywords
+/// and their values:
+///
+/// :type is nil, `network` or `plain` for an ordinary connection, and `tls`
+/// or `ssl` for an encrypted one. The certificate of the server is checked
+/// against those in `gnutls-trustfiles`, and a connection to a server whose
+/// certificate is not valid is an error.
+///
+/// :client-certificate is a list of the files of the key and the
+/// certificate to identify the client with in a TLS connection.
+///
+/// :nowait and :coding are as in `make-network-process`, and the others are
+/// ignored. A TLS connection is made before returning, even with :nowait.
+#[defun]
+fn open_network_stream<'ob>(
+    name: GcObj<'ob>,
+    buffer: GcObj<'ob>,
+    host: GcObj<'ob>,
+    service: GcObj<'ob>,
+    parameters: &[GcObj<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'static Process> {
+    let param = |key| keyword_arg(parameters, key);
+    let tls = match param(sym::KW_TYPE).untag() {
+        Object::NIL | Object::Symbol(sym::NETWORK | sym::PLAIN) => nil(),
+        #[cfg(feature = "tls")]
+        Object::Symbol(sym::TLS | sym::SSL) => {
+            crate::tls::boot_parameters(host, param(sym::KW_CLIENT_CERTIFICATE), env, cx)
+        }
+        #[cfg(not(feature = "tls"))]
+        Object::Symbol(sym::TLS | sym::SSL) => {
+            bail!("TLS is not available, since rune is built without the tls feature")
+        }
+        Object::Symbol(sym::STARTTLS) => bail!("STARTTLS connections are not supported"),
+        kind => bail!("Unsupported connection type: {kind}"),
+    };
+    let args = [
+        (sym::KW_NAME, name),
+        (sym::KW_BUFFER, buffer),
+        (sym::KW_HOST, host),
+        (sym::KW_SERVICE, service),
+        (sym::KW_NOWAIT, param(sym::KW_NOWAIT)),
+        (sym::KW_CODING, param(sym::KW_CODING)),
+        (sym::KW_TLS_PARAMETERS, tls),
+    ];
+    let args: Vec<GcObj> = args
+        .into_iter()
+        .flat_map(|(key, value)| [key.into(), value])
+        .collect();
+    make_network_process(&args, env, cx)
+}
+
+/// Return the address of the peer of the datagram process PROCESS, where
+/// what is sent to it goes and where the last datagram it received came
+/// from. Return nil if PROCESS is not a datagram process or has no peer.
+#[defun]
+fn process_datagram_address<'ob>(process: &'static Process, cx: &'ob Context) -> GcObj<'ob> {
+    let address = process.lock().datagram_address;
+    address.map_or_else(nil, |x| address_vector(x, cx))
+}
+
+/// Make ADDRESS the peer of the datagram process PROCESS, where what is sent
+/// to it goes. Return ADDRESS, or nil if PROCESS is not a datagram process.
+#[defun]
+fn set_process_datagram_address<'ob>(
+    process: &'static Process,
+    address: GcObj<'ob>,
+) -> Result<GcObj<'ob>> {
+    let mut data = process.lock();
+    if data.socket.is_none() {
+        return Ok(nil());
+    }
+    data.datagram_address = Some(vector_address(address)?);
+    Ok(address)
+}
+
+/// Return the list of TLS capabilities, `(gnutls)`, if encrypted network
+/// streams are available, and nil if rune is built without them.
+#[defun]
+fn gnutls_available_p<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    match cfg!(feature = "tls") {
+        true => list![sym::GNUTLS; cx],
+        false => nil(),
+    }
+}
+
+defsym!(KW_TYPE);
+defsym!(KW_FAMILY);
+defsym!(KW_SERVER);
+defsym!(KW_NOWAIT);
+defsym!(KW_LOG);
+defsym!(KW_BROADCAST);
+defsym!(DATAGRAM);
+defsym!(STREAM);
+defsym!(PLAIN);
+defsym!(TLS);
+defsym!(SSL);
+defsym!(STARTTLS);
+defsym!(GNUTLS);
+defsym!(KW_TLS_PARAMETERS);
+defsym!(KW_CLIENT_CERTIFICATE);
+defsym!(IPV4);
+defsym!(IPV6);
+defsym!(LOCAL);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader};
+    use std::io::Write;
+
+    #[test]
+    fn test_network_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        // a client that reads what a server sends until it closes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let greeter = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(b"hello\n").unwrap();
+            }
+        });
+        let form = format!(
+            r#"(progn
+                 (setq statuses nil)
+                 (setq client (make-network-process :name "client" :buffer "net-out"
+                                                    :host "127.0.0.1" :service {port}))
+                 (setq waiting (open-network-stream "waiting" nil 'local "{port}" :nowait t))
+                 (set-process-sentinel waiting #'(lambda (p msg) (setq statuses (cons msg statuses))))
+                 (setq result (list (process-type client) (process-status client) (process-status waiting)
+                                    (process-contact client)))
+                 (while (or (eq (process-status client) 'open) (memq (process-status waiting) '(connect open)))
+                   (accept-process-output nil 0.1))
+                 (set-buffer "net-out")
+                 (append result (list (buffer-string) (process-status client) statuses)))"#
+        );
+        let expect = format!(
+            "(network open connect (\"127.0.0.1\" {port}) \"hello\n\nProcess client connection broken by remote peer\n\" closed (\"connection broken by remote peer\n\" \"open\n\"))"
+        );
+        assert_eq!(eval(&form), expect);
+        greeter.join().unwrap();
+
+        // a server that records what its clients send
+        let form = r#"(progn
+                        (setq received nil opened nil logged nil)
+                        (setq server (make-network-process :name "server" :server t :host 'local :service t
+                                                           :filter #'(lambda (p text) (setq received (cons text received)))
+                                                           :sentinel #'(lambda (p msg) (setq opened (cons msg opened)))
+                                                           :log #'(lambda (s p msg) (setq logged (list (eq s server) (process-type p) msg)))))
+                        (list (process-status server) (process-type server) (integerp (process-contact server :service))))"#;
+        assert_eq!(eval(form), "(listen network t)");
+        let port = eval("(process-contact server :service)");
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{port}")).unwrap();
+        stream.write_all(b"ping").unwrap();
+        drop(stream);
+        let form = r#"(progn
+                        (while (null (and received opened (equal (car opened) "connection broken by remote peer\n")))
+                          (accept-process-output nil 0.1))
+                        (list received (nreverse opened) logged (process-status server)))"#;
+        let expect = "((\"ping\") (\"open from 127.0.0.1\n\" \"connection broken by remote peer\n\") (t network \"accept from 127.0.0.1\n\") listen)";
+        assert_eq!(eval(form), expect);
+
+        // connections that cannot be made
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let form = format!(
+            r#"(progn
+                 (setq failed (make-network-process :name "failed" :host 'local :service {port} :nowait t))
+                 (while (eq (process-status failed) 'connect)
+                   (accept-process-output nil 0.1))
+                 (list (process-status failed)
+                       (condition-case nil (make-network-process :name "refused" :host 'local :service {port})
+                         (error 'refused))))"#
+        );
+        assert_eq!(eval(&form), "(failed refused)");
+    }
+
+    #[test]
+    fn test_datagram_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        let form = r#"(progn
+                        (setq received nil)
+                        (setq server (make-network-process :name "udp" :type 'datagram :server t
+                                                           :host 'local :service t
+                                                           :filter #'(lambda (p text) (setq received (cons text received)))))
+                        (list (process-status server) (process-datagram-address server)))"#;
+        assert_eq!(eval(form), "(open nil)");
+        let port: u16 = eval("(process-contact server :service)").parse().unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_port = peer.local_addr().unwrap().port();
+        peer.send_to(b"one", ("127.0.0.1", port)).unwrap();
+        peer.send_to(b"two", ("127.0.0.1", port)).unwrap();
+        let form = format!(
+            r"(progn
+                 (while (null (cdr received))
+                   (accept-process-output server 0.1))
+                 (list (nreverse received)
+                       (equal (process-datagram-address server) [127 0 0 1 {peer_port}])))"
+        );
+        assert_eq!(eval(&form), "((\"one\" \"two\") t)");
+
+        let form = format!(
+            r#"(progn
+                 (setq client (make-network-process :name "udp-client" :type 'datagram
+                                                    :host "127.0.0.1" :service {peer_port}))
+                 (list (equal (process-datagram-address client) [127 0 0 1 {peer_port}])
+                       (vectorp (set-process-datagram-address client [0 0 0 0 0 0 0 1 9]))
+                       (equal (process-datagram-address client) [0 0 0 0 0 0 0 1 9])
+                       (condition-case nil (set-process-datagram-address client [1 2 3]) (error 'invalid))))"#
+        );
+        assert_eq!(eval(&form), "(t t t invalid)");
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_stream() {
+        use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+        use rustls::server::WebPkiClientVerifier;
+        use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+        use std::io::{BufRead, BufReader};
+        use std::sync::Arc;
+
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        // a server that echoes a line, and says if the client had a certificate
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls");
+        let mut authorities = RootCertStore::empty();
+        authorities
+            .add(CertificateDer::from_pem_file(format!("{dir}/ca.pem")).unwrap())
+            .unwrap();
+        let verifier = WebPkiClientVerifier::builder(Arc::new(authorities))
+            .allow_unauthenticated()
+            .build()
+            .unwrap();
+        let certs = CertificateDer::pem_file_iter(format!("{dir}/server.pem"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file(format!("{dir}/server-key.pem")).unwrap();
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let session = ServerConnection::new(config.clone()).unwrap();
+                let mut tls = StreamOwned::new(session, stream.unwrap());
+                let mut line = String::new();
+                // the clients that reject the certificate end the handshake
+                if BufReader::new(&mut tls).read_line(&mut line).is_err() {
+                    continue;
+                }
+                let cert = if tls.conn.peer_certificates().is_some() {
+                    "with"
+                } else {
+                    "without"
+                };
+                writeln!(tls, "{} {cert} certificate", line.trim_end()).unwrap();
+                tls.conn.send_close_notify();
+                tls.conn.complete_io(&mut tls.sock).unwrap();
+            }
+        });
+        let form = format!(
+            r#"(progn
+                 (setq gnutls-trustfiles '("{dir}/ca.pem") received nil)
+                 (defalias 'receive #'(lambda (p text) (setq received (cons text received))))
+                 (setq with (open-network-stream "with" nil "localhost" {port} :type 'tls
+                                                 :client-certificate '("{dir}/client-key.pem" "{dir}/client.pem")))
+                 (set-process-filter with 'receive)
+                 (process-send-string with "ping\n")
+                 (while (eq (process-status with) 'open)
+                   (accept-process-output nil 0.05))
+                 (setq without (open-network-stream "without" nil "localhost" {port} :type 'tls))
+                 (set-process-filter without 'receive)
+                 (process-send-string without "pong\n")
+                 (process-send-eof without)
+                 (while (eq (process-status without) 'open)
+                   (accept-process-output nil 0.05))
+                 (list (apply #'concat (nreverse received)) (gnutls-available-p)))"#
+        );
+        let expect = "(\"ping with certificate\npong without certificate\n\" (gnutls))";
+        assert_eq!(eval(&form), expect);
+
+        // certificates that are not for the host, or not trusted
+        let form = format!(
+            r#"(list (condition-case err (open-network-stream "mismatch" nil "127.0.0.1" {port} :type 'tls)
+                       (error (string-prefix-p "Certificate validation failed" (cdr err))))
+                     (progn
+                       (setq gnutls-trustfiles nil)
+                       (condition-case err (open-network-stream "untrusted" nil "localhost" {port} :type 'tls)
+                         (error (string-prefix-p "Certificate validation failed" (cdr err))))))"#
+        );
+        assert_eq!(eval(&form), "(t t)");
+    }
+}
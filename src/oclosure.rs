@@ -0,0 +1,325 @@
+//! Open closures (oclosures).
+//!
+//! An oclosure is an interpreted closure whose captured environment starts
+//! with an entry holding its type, followed by its named slots. This is the
+//! layout Emacs uses for interpreted oclosures, and the body of the closure
+//! is left as it is.
+//!
+//! (closure ((:type . TYPE) (slot1 . val1) (slot2 . val2) t) ARGS . BODY)
+//!
+//! `oclosure--define` keeps the parent and slots of a type in the
+//! `oclosure--class` property of its name, with the slots of the parent
+//! first. The accessors it defines check the type of their argument, so the
+//! accessors of a type also work on the types derived from it. The
+//! `oclosure-define` and `oclosure-lambda` macros in stubs.el expand to these
+//! functions. Byte-compiled oclosures are not supported.
+use crate::core::cons::Cons;
+use crate::core::env::{intern, sym, Env, Symbol};
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, GcObj, Object};
+use crate::data::{fset, get, put};
+use anyhow::{anyhow, bail, ensure, Result};
+use fn_macros::defun;
+
+/// Return the type of `closure` if it is an oclosure.
+pub(crate) fn closure_type(closure: &Cons) -> Option<Symbol<'_>> {
+    if closure.car() != sym::CLOSURE {
+        return None;
+    }
+    let env = closure.cdr().as_list().ok()?.next()?.ok()?;
+    let Object::Cons(env) = env.untag() else {return None};
+    match env.car().untag() {
+        Object::Cons(entry) if entry.car() == sym::KW_TYPE => match entry.cdr().untag() {
+            Object::Symbol(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn get_oclosure(obj: GcObj<'_>) -> Result<&Cons> {
+    match obj.untag() {
+        Object::Cons(cons) if closure_type(cons).is_some() => Ok(cons),
+        _ => Err(TypeError::new(Type::Func, obj).into()),
+    }
+}
+
+fn get_slot(oclosure: &Cons, index: usize) -> Result<&Cons> {
+    let env = oclosure.cdr().as_list()?.next().unwrap()?;
+    // skip the type
+    let slot = env.as_list()?.nth(index + 1);
+    match slot.transpose()?.map(GcObj::untag) {
+        Some(Object::Cons(binding)) => Ok(binding),
+        _ => Err(anyhow!("oclosure slot index {index} out of range")),
+    }
+}
+
+/// The slots of the oclosure type `ty`, starting with those of its parent.
+fn type_slots<'ob>(ty: Symbol, env: &Rt<Env>, cx: &'ob Context) -> Result<Vec<Symbol<'ob>>> {
+    let Object::Cons(class) = get(ty, sym::OCLOSURE__CLASS, env, cx).untag() else {bail!("Unknown oclosure type: {ty}")};
+    class.cdr().as_list()?.map(|x| Ok(x?.try_into()?)).collect()
+}
+
+/// Whether `ty` is `ancestor` or derived from it.
+fn is_derived<'ob>(mut ty: Symbol<'ob>, ancestor: Symbol, env: &Rt<Env>, cx: &'ob Context) -> bool {
+    loop {
+        if ty == ancestor {
+            return true;
+        }
+        match get(ty, sym::OCLOSURE__CLASS, env, cx).untag() {
+            Object::Cons(class) => match class.car().untag() {
+                Object::Symbol(parent) if parent != sym::NIL => ty = parent,
+                _ => return false,
+            },
+            _ => return false,
+        }
+    }
+}
+
+#[defun]
+fn oclosure_type(oclosure: GcObj) -> GcObj {
+    match oclosure.untag() {
+        Object::Cons(cons) => closure_type(cons).map_or_else(nil, Into::into),
+        _ => nil(),
+    }
+}
+
+/// Define the oclosure type NAME with the slots of PARENT, unless it is nil,
+/// followed by SLOTS. Each of SLOTS gets an accessor called `NAME--SLOT`.
+#[defun(name = "oclosure--define")]
+fn oclosure_define<'ob>(
+    name: Symbol<'ob>,
+    parent: Symbol<'ob>,
+    slots: GcObj<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    let mut all = if parent == sym::NIL {
+        Vec::new()
+    } else {
+        type_slots(parent, env, cx)?
+    };
+    let inherited = all.len();
+    for slot in slots.as_list()? {
+        all.push(slot?.try_into()?);
+    }
+    let class =
+        crate::fns::slice_into_list(&all.iter().map(|&x| x.into()).collect::<Vec<_>>(), None, cx);
+    put(name, sym::OCLOSURE__CLASS, cons!(parent, class; cx), env);
+    for (index, slot) in all.iter().enumerate().skip(inherited) {
+        // (closure (t) (oclosure) (oclosure--slot oclosure 'NAME INDEX))
+        let arg = sym::OCLOSURE;
+        let ty = list![sym::QUOTE, name; cx];
+        let body = list![sym::OCLOSURE_SLOT, arg, ty, index as i64; cx];
+        let accessor = list![sym::CLOSURE, list![true; cx], list![arg; cx], body; cx];
+        fset(
+            intern(&format!("{}--{}", name.name(), slot.name()), cx),
+            accessor,
+        )?;
+    }
+    Ok(name)
+}
+
+/// Make an oclosure of TYPE that calls FUNCTION, an interpreted closure. The
+/// slots are set from the alist BINDINGS, and the slots it leaves out are
+/// nil.
+#[defun(name = "oclosure--make")]
+fn oclosure_make<'ob>(
+    ty: Symbol<'ob>,
+    bindings: GcObj<'ob>,
+    function: GcObj<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let slots = type_slots(ty, env, cx)?;
+    let closure = match function.untag() {
+        Object::Cons(cons) if cons.car() == sym::CLOSURE => cons,
+        _ => bail!("Not an interpreted closure: {function}"),
+    };
+    let mut values = vec![nil(); slots.len()];
+    for binding in bindings.as_list()? {
+        let binding: &Cons = binding?.try_into()?;
+        let Some(index) = slots.iter().position(|&x| binding.car() == x) else {bail!("Unknown slot {} of oclosure type {ty}", binding.car())};
+        values[index] = binding.cdr();
+    }
+    let mut entries = vec![cons!(sym::KW_TYPE, ty; cx)];
+    for (&slot, &value) in slots.iter().zip(&values) {
+        entries.push(cons!(slot, value; cx));
+    }
+    let Object::Cons(forms) = closure.cdr().untag() else {bail!("Closure missing environment")};
+    let new_env = crate::fns::slice_into_list(&entries, Some(forms.car()), cx);
+    Ok(cons!(sym::CLOSURE, cons!(new_env, forms.cdr(); cx); cx))
+}
+
+/// The value of slot INDEX of OCLOSURE, which has to be of TYPE or of a type
+/// derived from it. The accessors defined by `oclosure--define` call this.
+#[defun(name = "oclosure--slot")]
+fn oclosure_slot<'ob>(
+    oclosure: GcObj<'ob>,
+    ty: Symbol,
+    index: usize,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<GcObj<'ob>> {
+    let cons = get_oclosure(oclosure)?;
+    let actual = closure_type(cons).unwrap();
+    ensure!(
+        is_derived(actual, ty, env, cx),
+        "{oclosure} is not an oclosure of type {ty}"
+    );
+    Ok(get_slot(cons, index)?.cdr())
+}
+
+#[defun(name = "oclosure--get")]
+fn oclosure_get(oclosure: GcObj, index: usize, _mutable: Option<()>) -> Result<GcObj> {
+    let oclosure = get_oclosure(oclosure)?;
+    Ok(get_slot(oclosure, index)?.cdr())
+}
+
+#[defun(name = "oclosure--set")]
+fn oclosure_set<'ob>(value: GcObj<'ob>, oclosure: GcObj, index: usize) -> Result<GcObj<'ob>> {
+    let oclosure = get_oclosure(oclosure)?;
+    get_slot(oclosure, index)?.set_cdr(value)?;
+    Ok(value)
+}
+
+/// Copy `oclosure`, replacing the values of the first slots with `args`.
+#[defun(name = "oclosure--copy")]
+fn oclosure_copy<'ob>(
+    oclosure: GcObj,
+    _mutlist: GcObj,
+    args: &[GcObj<'ob>],
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let oclosure = get_oclosure(oclosure)?;
+    let mut forms = oclosure.cdr().as_list()?;
+    let env = forms.next().unwrap()?;
+    let mut slots = Vec::new();
+    let mut args = args.iter();
+    for binding in env.as_list()? {
+        match binding?.untag() {
+            Object::Cons(binding) if binding.car() == sym::KW_TYPE => slots.push(binding.into()),
+            Object::Cons(binding) => {
+                let value = args.next().copied().unwrap_or_else(|| binding.cdr());
+                slots.push(cons!(binding.car(), value; cx));
+            }
+            Object::TRUE => break,
+            x => bail!("Invalid closure environment member: {x}"),
+        }
+    }
+    if args.next().is_some() {
+        bail!("Too many arguments to oclosure--copy");
+    }
+    let new_env = crate::fns::slice_into_list(&slots, Some(cons!(true; cx)), cx);
+    let rest = oclosure.cdr().as_cons().cdr();
+    Ok(cons!(sym::CLOSURE, cons!(new_env, rest; cx); cx))
+}
+
+defsym!(OCLOSURE);
+defsym!(OCLOSURE__CLASS);
+defsym!(OCLOSURE_INTERACTIVE_FORM);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::{intern, Env};
+    use crate::core::gc::RootSet;
+    use crate::interpreter::{self, check};
+    use crate::{reader, root};
+
+    #[test]
+    fn test_oclosure() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(setq oclosure-test-fn
+                      '(closure ((:type . oclosure-test) (x . 1) (y . 2) t) (z) (+ x y z)))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let oclosure = rebind!(interpreter::eval(obj, None, env, cx).unwrap(), cx);
+        root!(oclosure, cx);
+
+        assert_eq!(check("(funcall oclosure-test-fn 3)", env, cx), 6);
+
+        let oclosure = oclosure.bind(cx);
+        let ty = intern("oclosure-test", cx);
+        assert_eq!(oclosure_type(oclosure), ty);
+        assert_eq!(oclosure_get(oclosure, 1, None).unwrap(), 2);
+        oclosure_set(cx.add(5), oclosure, 0).unwrap();
+        assert_eq!(oclosure_get(oclosure, 0, None).unwrap(), 5);
+        assert!(oclosure_get(oclosure, 2, None).is_err());
+
+        let copy = oclosure_copy(oclosure, nil(), &[cx.add(7)], cx).unwrap();
+        assert_eq!(oclosure_type(copy), ty);
+        assert_eq!(oclosure_get(copy, 0, None).unwrap(), 7);
+        assert_eq!(oclosure_get(copy, 1, None).unwrap(), 2);
+        assert_eq!(oclosure_get(oclosure, 0, None).unwrap(), 5);
+
+        // a symbol at the start of the body is evaluated, not taken as a type
+        let closure = reader::read("(closure (t) () foo 1)", cx).unwrap().0;
+        assert_eq!(oclosure_type(closure), nil());
+        let form = "(condition-case nil (funcall '(closure (t) () oclosure-unbound 1))
+                      (error 'failed))";
+        assert_eq!(check(form, env, cx).to_string(), "failed");
+    }
+
+    #[test]
+    fn test_oclosure_define() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(oclosure--define 'oclosure-base nil '(a b))", env, cx);
+        check(
+            "(oclosure--define 'oclosure-derived 'oclosure-base '(c))",
+            env,
+            cx,
+        );
+        let form = "(setq oclosure-test-fn
+                      (let ((y 4))
+                        (oclosure--make 'oclosure-derived '((a . 1) (c . 3))
+                                        #'(lambda (x) (list a b c x y)))))";
+        check(form, env, cx);
+        let call = check("(funcall oclosure-test-fn 5)", env, cx);
+        assert_eq!(call.to_string(), "(1 nil 3 5 4)");
+        let ty = check("(oclosure-type oclosure-test-fn)", env, cx);
+        assert_eq!(ty.to_string(), "oclosure-derived");
+
+        // the accessors of a type work on the types derived from it
+        assert_eq!(check("(oclosure-base--a oclosure-test-fn)", env, cx), 1);
+        assert_eq!(check("(oclosure-derived--c oclosure-test-fn)", env, cx), 3);
+        let form = "(condition-case nil
+                        (oclosure-derived--c (oclosure--make 'oclosure-base nil #'(lambda ())))
+                      (error 'failed))";
+        assert_eq!(check(form, env, cx).to_string(), "failed");
+        let form = "(condition-case nil
+                        (oclosure--make 'oclosure-base '((c . 1)) #'(lambda ()))
+                      (error 'failed))";
+        assert_eq!(check(form, env, cx).to_string(), "failed");
+    }
+
+    #[test]
+    fn test_interactive_form() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(interactive-form '(lambda (x) \"doc\" (interactive \"p\") x))";
+        assert_eq!(check(form, env, cx).to_string(), "(interactive \"p\")");
+        let form = "(interactive-form '(lambda () (interactive \"p\" foo-mode)))";
+        assert_eq!(check(form, env, cx).to_string(), "(interactive \"p\")");
+        assert_eq!(check("(interactive-form '(lambda (x) x))", env, cx), nil());
+        assert_eq!(check("(interactive-form 'car)", env, cx), nil());
+
+        check("(oclosure--define 'oclosure-command nil '(spec))", env, cx);
+        let form = "(setq oclosure-test-fn
+                      (oclosure--make 'oclosure-command '((spec . \"P\")) #'(lambda ())))";
+        check(form, env, cx);
+        assert_eq!(check("(interactive-form oclosure-test-fn)", env, cx), nil());
+        let form = "(fset 'oclosure-interactive-form
+                          #'(lambda (f) (list 'interactive (oclosure-command--spec f))))";
+        check(form, env, cx);
+        let form = check("(interactive-form oclosure-test-fn)", env, cx);
+        assert_eq!(form.to_string(), "(interactive \"P\")");
+        check("(fset 'oclosure-interactive-form nil)", env, cx);
+    }
+}
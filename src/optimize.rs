@@ -0,0 +1,467 @@
+//! Source level optimizer.
+//!
+//! Forms are simplified before they are evaluated. Calls to pure functions
+//! with constant arguments are folded, dead branches of `if` are removed, and
+//! nested `progn` forms are collapsed. The pass is controlled by
+//! `optimize-level`; a level of 0 disables it.
+//!
+//! Besides the builtin tables below, a function can be declared pure or side
+//! effect free by setting its `pure` or `side-effect-free` property.
+use crate::core::{
+    env::{sym, Env, Symbol},
+    gc::{Context, Rt},
+    object::{nil, Function, Gc, GcObj, Object},
+};
+use crate::{root, rooted_iter};
+use anyhow::Result;
+use streaming_iterator::StreamingIterator;
+
+defvar!(OPTIMIZE_LEVEL, 1);
+defsym!(PURE);
+defsym!(SIDE_EFFECT_FREE);
+
+/// Functions whose result only depends on their arguments. These can be
+/// called at optimization time. Division is left out because dividing by zero
+/// panics.
+const PURE_FUNCTIONS: &[Symbol] = &[
+    sym::ADD,
+    sym::SUB,
+    sym::MUL,
+    sym::ADD_ONE,
+    sym::SUB_ONE,
+    sym::NUM_EQ,
+    sym::NUM_NE,
+    sym::LESS_THAN,
+    sym::LESS_THAN_OR_EQ,
+    sym::GREATER_THAN,
+    sym::GREATER_THAN_OR_EQ,
+    sym::MAX,
+    sym::MIN,
+    sym::LOGIOR,
+    sym::LOGAND,
+    sym::ASH,
+    sym::EQ,
+    sym::EQL,
+    sym::EQUAL,
+    sym::NULL,
+    sym::ATOM,
+    sym::CONSP,
+    sym::LISTP,
+    sym::SYMBOLP,
+    sym::STRINGP,
+    sym::NUMBERP,
+    sym::INTEGERP,
+    sym::FLOATP,
+    sym::VECTORP,
+    sym::IDENTITY,
+    sym::CAR,
+    sym::CDR,
+    sym::CAR_SAFE,
+    sym::CDR_SAFE,
+    sym::NTH,
+    sym::NTHCDR,
+    sym::ELT,
+    sym::AREF,
+    sym::LENGTH,
+    sym::MEMQ,
+    sym::MEMBER,
+    sym::ASSQ,
+    sym::ASSOC,
+    sym::CONCAT,
+    sym::SUBSTRING,
+    sym::STRING_EQUAL,
+    sym::SYMBOL_NAME,
+    sym::STRING_TO_NUMBER,
+];
+
+/// Functions that have no side effects, but whose result is not constant.
+/// Calls to these can be removed if their value is not used.
+const SIDE_EFFECT_FREE_FUNCTIONS: &[Symbol] = &[
+    sym::CONS,
+    sym::LIST,
+    sym::VECTOR,
+    sym::APPEND,
+    sym::REVERSE,
+    sym::VCONCAT,
+    sym::FORMAT,
+    sym::POINT,
+    sym::POINT_MIN,
+    sym::POINT_MAX,
+    sym::CURRENT_BUFFER,
+];
+
+fn is_pure(func: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    PURE_FUNCTIONS.contains(&func) || crate::data::get(func, sym::PURE, env, cx) != nil()
+}
+
+fn is_side_effect_free(func: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    SIDE_EFFECT_FREE_FUNCTIONS.contains(&func)
+        || is_pure(func, env, cx)
+        || crate::data::get(func, sym::SIDE_EFFECT_FREE, env, cx) != nil()
+}
+
+fn optimize_level(env: &Rt<Env>, cx: &Context) -> i64 {
+    match env.vars.get(sym::OPTIMIZE_LEVEL).map(|x| x.get(cx)) {
+        Some(Object::Int(level)) => level,
+        _ => 0,
+    }
+}
+
+/// Optimize `form` according to `optimize-level`. If the form can't be
+/// optimized it is returned unchanged.
+pub(crate) fn optimize<'ob>(
+    form: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> GcObj<'ob> {
+    if optimize_level(env, cx) <= 0 {
+        return form.bind(cx);
+    }
+    match optimize_form(form, env, cx) {
+        Ok(x) => rebind!(x, cx),
+        Err(_) => form.bind(cx),
+    }
+}
+
+/// Return the value of `form` if it is a constant.
+pub(crate) fn const_value(form: GcObj) -> Option<GcObj> {
+    match form.untag() {
+        Object::Symbol(sym) => sym.is_const().then_some(form),
+        Object::Cons(cons) if cons.car() == sym::QUOTE => {
+            let mut forms = cons.cdr().as_list().ok()?;
+            let value = forms.next()?.ok()?;
+            forms.next().is_none().then_some(value)
+        }
+        Object::Cons(_) => None,
+        _ => Some(form),
+    }
+}
+
+/// Turn a value back into a form that evaluates to it.
+fn quote<'ob>(value: GcObj<'ob>, cx: &'ob Context) -> GcObj<'ob> {
+    match value.untag() {
+        Object::Symbol(sym) if !sym.is_const() => list!(sym::QUOTE, value; cx),
+        Object::Cons(_) => list!(sym::QUOTE, value; cx),
+        _ => value,
+    }
+}
+
+fn is_function(func: Symbol, cx: &Context) -> bool {
+    match func.follow_indirect(cx).map(Gc::untag) {
+        Some(Function::SubrFn(_) | Function::ByteFn(_)) => true,
+        Some(Function::Cons(cons)) => cons.car() == sym::CLOSURE,
+        _ => false,
+    }
+}
+
+fn optimize_form<'ob>(
+    form: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let Object::Cons(cons) = form.get(cx) else {return Ok(form.bind(cx))};
+    let Object::Symbol(head) = cons.car().untag() else {return Ok(form.bind(cx))};
+    let args = cons.cdr();
+    root!(args, cx);
+    match head {
+        sym::IF => optimize_if(form, args, env, cx),
+        sym::PROGN => optimize_progn(args, env, cx),
+        sym::AND | sym::OR | sym::WHILE | sym::PROG1 | sym::PROG2 | sym::UNWIND_PROTECT => {
+            let new_args = rebind!(optimize_forms(args, env, cx)?);
+            Ok(rebuild(form, args, new_args, cx))
+        }
+        sym::LET | sym::LET_STAR => {
+            let (bindings, body) = match args.get(cx) {
+                Object::Cons(cons) => (cons.car(), cons.cdr()),
+                _ => return Ok(form.bind(cx)),
+            };
+            root!(bindings, cx);
+            root!(body, cx);
+            let new_body = rebind!(optimize_forms(body, env, cx)?);
+            let new_args = if new_body == body.bind(cx) {
+                args.bind(cx)
+            } else {
+                cons!(bindings.bind(cx), new_body; cx)
+            };
+            Ok(rebuild(form, args, new_args, cx))
+        }
+        // optimize the body of a lambda when it is defined
+        sym::FUNCTION => {
+            let (arglist, body) = match args.get(cx) {
+                Object::Cons(cons) => match cons.car().untag() {
+                    Object::Cons(lambda) if lambda.car() == sym::LAMBDA => {
+                        match lambda.cdr().untag() {
+                            Object::Cons(lambda) => (lambda.car(), lambda.cdr()),
+                            _ => return Ok(form.bind(cx)),
+                        }
+                    }
+                    _ => return Ok(form.bind(cx)),
+                },
+                _ => return Ok(form.bind(cx)),
+            };
+            root!(arglist, cx);
+            root!(body, cx);
+            let new_body = rebind!(optimize_forms(body, env, cx)?);
+            if new_body == body.bind(cx) {
+                return Ok(form.bind(cx));
+            }
+            let lambda = cons!(sym::LAMBDA, cons!(arglist.bind(cx), new_body; cx); cx);
+            Ok(list!(sym::FUNCTION, lambda; cx))
+        }
+        _ if is_function(head, cx) => optimize_call(form, args, env, cx),
+        _ => Ok(form.bind(cx)),
+    }
+}
+
+/// Replace the arguments of `form` if they have changed.
+fn rebuild<'ob>(
+    form: &Rt<GcObj>,
+    args: &Rt<GcObj>,
+    new_args: GcObj<'ob>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    if new_args == args.bind(cx) {
+        form.bind(cx)
+    } else {
+        cons!(form.bind(cx).as_cons().car(), new_args; cx)
+    }
+}
+
+/// Optimize every form in `forms`, returning the original list if nothing
+/// changed.
+fn optimize_forms<'ob>(
+    forms: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let mut changed = false;
+    root!(new_forms, Vec::new(), cx);
+    rooted_iter!(iter, forms, cx);
+    while let Some(form) = iter.next() {
+        let new_form = rebind!(optimize_form(form, env, cx)?);
+        changed |= new_form != form.bind(cx);
+        new_forms.push(new_form);
+    }
+    if changed {
+        Ok(crate::fns::slice_into_list(
+            new_forms.bind_ref(cx),
+            None,
+            cx,
+        ))
+    } else {
+        Ok(forms.bind(cx))
+    }
+}
+
+/// (if COND THEN ELSE...)
+fn optimize_if<'ob>(
+    form: &Rt<GcObj>,
+    args: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let mut forms = args.bind(cx).as_list()?;
+    let (Some(cond), Some(then)) = (forms.next(), forms.next()) else {return Ok(form.bind(cx))};
+    let (cond, then) = (cond?, then?);
+    let else_forms = args.bind(cx).as_cons().cdr().as_cons().cdr();
+    root!(then, cx);
+    root!(else_forms, cx);
+    root!(cond, cx);
+    let new_cond = rebind!(optimize_form(cond, env, cx)?);
+    match const_value(new_cond) {
+        Some(value) if value != nil() => optimize_form(then, env, cx),
+        Some(_) => optimize_progn(else_forms, env, cx),
+        None => {
+            root!(new_cond, cx);
+            let new_then = rebind!(optimize_form(then, env, cx)?);
+            root!(new_then, cx);
+            let new_else = rebind!(optimize_forms(else_forms, env, cx)?);
+            let new_args = cons!(new_cond.bind(cx), cons!(new_then.bind(cx), new_else; cx); cx);
+            Ok(cons!(sym::IF, new_args; cx))
+        }
+    }
+}
+
+/// (progn BODY...)
+fn optimize_progn<'ob>(
+    forms: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    root!(body, Vec::new(), cx);
+    rooted_iter!(iter, forms, cx);
+    while let Some(form) = iter.next() {
+        let new_form = rebind!(optimize_form(form, env, cx)?);
+        match new_form.untag() {
+            // collapse nested progn
+            Object::Cons(cons) if cons.car() == sym::PROGN => {
+                for x in cons.cdr().as_list()? {
+                    body.push(x?);
+                }
+            }
+            _ => body.push(new_form),
+        }
+    }
+    let body = body.bind_ref(cx);
+    // constant forms have no effect unless they are the return value
+    let mut body: Vec<_> = match body.split_last() {
+        Some((last, rest)) => {
+            let mut forms: Vec<_> = rest
+                .iter()
+                .copied()
+                .filter(|x| !is_unused_const(*x, env, cx))
+                .collect();
+            forms.push(*last);
+            forms
+        }
+        None => return Ok(nil()),
+    };
+    if body.len() == 1 {
+        Ok(body.pop().unwrap())
+    } else {
+        Ok(cons!(sym::PROGN, crate::fns::slice_into_list(&body, None, cx); cx))
+    }
+}
+
+/// Return true if `form` can be removed when its value is not used. This is
+/// true of constants and of calls to side effect free functions with constant
+/// arguments.
+fn is_unused_const(form: GcObj, env: &Rt<Env>, cx: &Context) -> bool {
+    match form.untag() {
+        Object::Cons(cons) if cons.car() != sym::QUOTE => match cons.car().untag() {
+            Object::Symbol(func) if is_side_effect_free(func, env, cx) => cons
+                .cdr()
+                .as_list()
+                .is_ok_and(|mut args| args.all(|x| x.ok().and_then(const_value).is_some())),
+            _ => false,
+        },
+        _ => const_value(form).is_some(),
+    }
+}
+
+/// Optimize the arguments of a function call and fold it if the function is
+/// pure and all arguments are constant.
+fn optimize_call<'ob>(
+    form: &Rt<GcObj>,
+    args: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let new_args = rebind!(optimize_forms(args, env, cx)?);
+    let func = form.bind(cx).as_cons().car();
+    let values: Option<Vec<_>> = match func.untag() {
+        Object::Symbol(sym) if is_pure(sym, env, cx) => new_args
+            .as_list()?
+            .map(|x| x.ok().and_then(const_value))
+            .collect(),
+        _ => None,
+    };
+    root!(new_args, cx);
+    if let Some(values) = values {
+        let func: Gc<Function> = func.try_into()?;
+        root!(func, cx);
+        root!(values, move(values), cx);
+        // Errors are left to be signaled at runtime, so the call is caught
+        // like in a `condition-case`, and the `handler-bind` handlers around
+        // it don't see the error
+        let depth = env.handler_stack.len();
+        let catch_all: GcObj = sym::ERROR.into();
+        env.handler_stack.push((catch_all, nil()));
+        let result = func.call(values, env, cx, None);
+        env.handler_stack.truncate(depth);
+        if let Ok(value) = result {
+            let value = rebind!(value, cx);
+            return Ok(quote(value, cx));
+        }
+    }
+    Ok(rebuild(form, args, new_args.bind(cx), cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::reader;
+
+    fn check_optimize(form: &str, expect: &str, cx: &mut Context) {
+        root!(env, Env::default(), cx);
+        println!("Test String: {form}");
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let optimized = optimize_form(obj, env, cx).unwrap();
+        assert_eq!(optimized.to_string(), expect);
+    }
+
+    #[test]
+    fn test_fold() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_optimize("(+ 1 2)", "3", cx);
+        check_optimize("(+ 1 (* 2 3))", "7", cx);
+        check_optimize("(< 1 2)", "t", cx);
+        check_optimize("(+ 1 x)", "(+ 1 x)", cx);
+        check_optimize("(/ 1 0)", "(/ 1 0)", cx);
+        check_optimize("(+ 1 'a)", "(+ 1 (quote a))", cx);
+        check_optimize("(cons (+ 1 2) x)", "(cons 3 x)", cx);
+        check_optimize("'(+ 1 2)", "(quote (+ 1 2))", cx);
+        check_optimize(
+            "(optimize-test-unknown (+ 1 2))",
+            "(optimize-test-unknown (+ 1 2))",
+            cx,
+        );
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_optimize("(if nil 1 (foo) x)", "(progn (foo) x)", cx);
+        check_optimize("(if nil 1)", "nil", cx);
+        check_optimize("(if t 1 2)", "1", cx);
+        check_optimize("(if (< 2 1) 1 x)", "x", cx);
+        check_optimize("(if x (+ 1 1) (+ 2 2) y)", "(if x 2 4 y)", cx);
+        check_optimize("(progn)", "nil", cx);
+        check_optimize("(progn 1 x 2)", "(progn x 2)", cx);
+        check_optimize("(progn (progn x (progn y)) z)", "(progn x y z)", cx);
+        check_optimize("(let ((x 1)) (if t x))", "(let ((x 1)) x)", cx);
+        check_optimize("(progn (length '(1)) (list 1 2) x)", "x", cx);
+        check_optimize("(progn (list y) x)", "(progn (list y) x)", cx);
+    }
+
+    #[test]
+    fn test_fold_builtins() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        check_optimize("(concat \"a\" \"b\")", "\"ab\"", cx);
+        check_optimize("(car '(1 2))", "1", cx);
+        check_optimize("(cdr '(1 2))", "(quote (2))", cx);
+        check_optimize("(car 1)", "(car 1)", cx);
+        check_optimize("(list 1 2)", "(list 1 2)", cx);
+        check_optimize(
+            "(function (lambda (x) (+ x (length \"abc\"))))",
+            "(function (lambda (x) (+ x 3)))",
+            cx,
+        );
+        check_optimize("(function (lambda (x) x))", "(function (lambda (x) x))", cx);
+    }
+
+    #[test]
+    fn test_pure_property() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let obj = reader::read(
+            "(progn (defalias 'optimize-test-pure #'(lambda (x) (* x 2)))
+                    (put 'optimize-test-pure 'pure t))",
+            cx,
+        )
+        .unwrap()
+        .0;
+        root!(obj, cx);
+        crate::interpreter::eval(obj, None, env, cx).unwrap();
+        let obj = reader::read("(optimize-test-pure 4)", cx).unwrap().0;
+        root!(obj, cx);
+        let optimized = optimize_form(obj, env, cx).unwrap();
+        assert_eq!(optimized, 8);
+    }
+}
@@ -0,0 +1,691 @@
+//! Portable dumper.
+//!
+//! The heap reachable from the environment and the obarray is written to an
+//! image file that can be loaded at startup instead of evaluating the
+//! bootstrap lisp. Objects are stored in a table and refer to each other by
+//! index, so the image does not depend on the address it was created at. When
+//! loading, every object is allocated first and the references are fixed up
+//! afterwards, which allows for shared and circular structure.
+use crate::core::{
+    env::{intern, Env, Symbol, SymbolCell, INTERNED_SYMBOLS},
+    gc::{Context, IntoRoot, Rt},
+    object::{
+        nil, ByteFn, CharTableData, FnArgs, Function, GcObj, HashTable, LispVec, Object,
+        RecordBuilder,
+    },
+};
+use crate::data::FEATURES;
+use crate::hashmap::HashMap;
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use fn_macros::defun;
+
+const MAGIC: &[u8; 8] = b"RUNEDMP\0";
+const VERSION: u32 = 1;
+
+/// A heap object with its references replaced by table indices.
+#[derive(Debug, PartialEq)]
+enum Node {
+    Int(i64),
+    Float(f64),
+    Symbol(String),
+    Uninterned(String),
+    Cons(u32, u32),
+    Vec(Vec<u32>),
+    Record(Vec<u32>),
+    String(Vec<u8>, bool),
+    HashTable(Vec<(u32, u32)>),
+    CharTable {
+        subtype: u32,
+        parent: u32,
+        default: u32,
+        extras: Vec<u32>,
+        /// Ranges of characters and their values, in the order they are set
+        entries: Vec<(u32, u32, u32)>,
+    },
+    ByteFn {
+        spec: u64,
+        depth: u64,
+        codes: u32,
+        consts: u32,
+    },
+    Subr(String),
+}
+
+/// The contents of a dump file. All roots refer to entries in `objects`.
+#[derive(Debug, Default, PartialEq)]
+struct Image {
+    objects: Vec<Node>,
+    vars: Vec<(u32, u32)>,
+    specials: Vec<u32>,
+    functions: Vec<(u32, u32)>,
+    props: Vec<(u32, u32, u32)>,
+    features: Vec<u32>,
+}
+
+/// Dump the current heap and obarray to `filename`.
+#[defun]
+fn dump_emacs_portable(
+    filename: &str,
+    _track_referrers: Option<()>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let image = Dumper::new(cx).dump(env)?;
+    let mut bytes = Vec::new();
+    image.write(&mut bytes);
+    std::fs::write(filename, bytes).with_context(|| format!("Failed to write dump {filename}"))?;
+    Ok(false)
+}
+
+/// Load the dump file at `path` into `env` and the obarray.
+pub(crate) fn load_dump(path: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read dump {path}"))?;
+    let image = Image::read(&bytes)?;
+    let objects = image.allocate(cx)?;
+    image.apply(&objects, env)
+}
+
+fn addr<T>(x: &T) -> usize {
+    std::ptr::from_ref(x) as usize
+}
+
+/// A key that is unique for every heap object.
+fn identity(obj: GcObj) -> (u8, usize) {
+    match obj.untag() {
+        Object::Int(x) => (0, x as usize),
+        Object::Float(x) => (1, addr(x)),
+        Object::Symbol(x) => (2, addr(x.get())),
+        Object::Cons(x) => (3, addr(x)),
+        Object::Vec(x) => (4, addr(x)),
+        Object::Record(x) => (5, addr(x)),
+        Object::HashTable(x) => (6, addr(x)),
+        Object::String(x) => (7, addr(x)),
+        Object::ByteFn(x) => (8, addr(x)),
+        Object::SubrFn(x) => (9, addr(x)),
+        Object::Buffer(x) => (10, addr(x)),
+        Object::Overlay(x) => (11, addr(x)),
+        Object::Marker(x) => (12, addr(x)),
+        Object::CharTable(x) => (13, addr(x)),
+        Object::Process(x) => (14, addr(x)),
+    }
+}
+
+struct Dumper<'ob> {
+    ids: HashMap<(u8, usize), u32>,
+    /// Objects in table order. Every object added here still needs to be
+    /// converted to a [`Node`].
+    objects: Vec<GcObj<'ob>>,
+    cx: &'ob Context<'ob>,
+}
+
+impl<'ob> Dumper<'ob> {
+    fn new(cx: &'ob Context<'ob>) -> Self {
+        Self {
+            ids: HashMap::default(),
+            objects: Vec::new(),
+            cx,
+        }
+    }
+
+    fn id(&mut self, obj: GcObj<'ob>) -> u32 {
+        let next = self.objects.len() as u32;
+        *self.ids.entry(identity(obj)).or_insert_with(|| {
+            self.objects.push(obj);
+            next
+        })
+    }
+
+    fn dump(mut self, env: &'ob Rt<Env>) -> Result<Image> {
+        let cx = self.cx;
+        let mut image = Image::default();
+        for (sym, val) in env.vars.iter() {
+            let var = (self.id(sym.bind(cx).into()), self.id(val.bind(cx)));
+            image.vars.push(var);
+        }
+        for (sym, plist) in env.props.iter() {
+            let sym = self.id(sym.bind(cx).into());
+            for (prop, val) in plist.bind_ref(cx) {
+                let prop = (sym, self.id((*prop).into()), self.id(*val));
+                image.props.push(prop);
+            }
+        }
+        {
+            let map = INTERNED_SYMBOLS.lock().unwrap();
+            for sym in map.symbols() {
+                let sym: Symbol<'ob> = cx.bind(sym);
+                if sym.is_special() {
+                    let id = self.id(sym.into());
+                    image.specials.push(id);
+                }
+                let Some(func) = sym.func(cx) else { continue };
+                // builtin functions are already defined when loading
+                if matches!(func.untag(), Function::SubrFn(subr) if subr.name == sym.name()) {
+                    continue;
+                }
+                let function = (self.id(sym.into()), self.id(func.into()));
+                image.functions.push(function);
+            }
+        }
+        for feature in FEATURES.lock().unwrap().iter() {
+            let id = self.id(cx.bind(*feature).into());
+            image.features.push(id);
+        }
+        // Converting an object can add new objects to the end of the table
+        while image.objects.len() < self.objects.len() {
+            let obj = self.objects[image.objects.len()];
+            let node = self.node(obj)?;
+            image.objects.push(node);
+        }
+        Ok(image)
+    }
+
+    fn ids(&mut self, vec: &'ob LispVec) -> Vec<u32> {
+        vec.iter().map(|x| self.id(x.get())).collect()
+    }
+
+    fn node(&mut self, obj: GcObj<'ob>) -> Result<Node> {
+        let node = match obj.untag() {
+            Object::Int(x) => Node::Int(x),
+            Object::Float(x) => Node::Float(**x),
+            Object::Symbol(x) if x.interned() => Node::Symbol(x.name().to_owned()),
+            Object::Symbol(x) => Node::Uninterned(x.name().to_owned()),
+            Object::Cons(x) => Node::Cons(self.id(x.car()), self.id(x.cdr())),
+            Object::Vec(x) => Node::Vec(self.ids(x)),
+            Object::Record(x) => Node::Record(self.ids(x)),
+            Object::String(x) => Node::String(x.to_vec(), x.is_multibyte()),
+            Object::HashTable(x) => {
+                let entries: Vec<_> = x
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (self.cx.bind(*k), self.cx.bind(v.get())))
+                    .collect();
+                let entries = entries.into_iter().map(|(k, v)| (self.id(k), self.id(v)));
+                Node::HashTable(entries.collect())
+            }
+            Object::CharTable(x) => {
+                let data = x.borrow();
+                let entries: Vec<_> = data
+                    .entries
+                    .iter()
+                    .map(|(from, to, x)| (from, to, *x))
+                    .collect();
+                let mut extras = Vec::new();
+                for extra in &data.extras {
+                    extras.push(self.id(*extra));
+                }
+                Node::CharTable {
+                    subtype: self.id(data.subtype),
+                    parent: self.id(data.parent),
+                    default: self.id(data.default),
+                    extras,
+                    entries: entries
+                        .into_iter()
+                        .map(|(from, to, x)| (from, to, self.id(x)))
+                        .collect(),
+                }
+            }
+            Object::ByteFn(x) => Node::ByteFn {
+                spec: x.args.into_arg_spec(),
+                depth: x.depth as u64,
+                codes: self.id(x.codes().into()),
+                consts: self.id(x.constants().into()),
+            },
+            Object::SubrFn(x) => Node::Subr(x.name.to_owned()),
+            Object::Buffer(x) => bail!("Can't dump buffer {x}"),
+            Object::Overlay(x) => bail!("Can't dump overlay {x}"),
+            Object::Marker(x) => bail!("Can't dump marker {x}"),
+            Object::Process(x) => bail!("Can't dump process {x}"),
+        };
+        Ok(node)
+    }
+}
+
+impl Image {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        put_u32(out, VERSION);
+        put_u32(out, self.objects.len() as u32);
+        for node in &self.objects {
+            node.write(out);
+        }
+        put_u32(out, self.vars.len() as u32);
+        for &(sym, val) in &self.vars {
+            put_u32(out, sym);
+            put_u32(out, val);
+        }
+        put_ids(out, &self.specials);
+        put_u32(out, self.functions.len() as u32);
+        for &(sym, func) in &self.functions {
+            put_u32(out, sym);
+            put_u32(out, func);
+        }
+        put_u32(out, self.props.len() as u32);
+        for &(sym, prop, val) in &self.props {
+            put_u32(out, sym);
+            put_u32(out, prop);
+            put_u32(out, val);
+        }
+        put_ids(out, &self.features);
+    }
+
+    fn read(bytes: &[u8]) -> Result<Self> {
+        let mut input = Reader { bytes };
+        ensure!(input.take(MAGIC.len())? == MAGIC, "Not a dump file");
+        let version = input.u32()?;
+        ensure!(version == VERSION, "Unsupported dump version {version}");
+        let mut image = Image::default();
+        for _ in 0..input.u32()? {
+            image.objects.push(Node::read(&mut input)?);
+        }
+        for _ in 0..input.u32()? {
+            image.vars.push((input.u32()?, input.u32()?));
+        }
+        image.specials = input.ids()?;
+        for _ in 0..input.u32()? {
+            image.functions.push((input.u32()?, input.u32()?));
+        }
+        for _ in 0..input.u32()? {
+            image.props.push((input.u32()?, input.u32()?, input.u32()?));
+        }
+        image.features = input.ids()?;
+        ensure!(input.bytes.is_empty(), "Trailing data in dump file");
+        Ok(image)
+    }
+
+    /// Allocate every object in the table. References between objects are
+    /// filled in once all of them exist.
+    fn allocate<'ob>(&self, cx: &'ob Context) -> Result<Vec<GcObj<'ob>>> {
+        let mut objects = Vec::with_capacity(self.objects.len());
+        for node in &self.objects {
+            let obj = match node {
+                Node::Int(x) => cx.add(*x),
+                Node::Float(x) => cx.add(*x),
+                Node::Symbol(name) => intern(name, cx).into(),
+                Node::Uninterned(name) => cx.add(SymbolCell::new_uninterned(name)),
+                Node::Cons(..) => cons!(nil(), nil(); cx),
+                Node::Vec(items) => cx.add(vec![nil(); items.len()]),
+                Node::Record(items) => cx.add(RecordBuilder(vec![nil(); items.len()])),
+                Node::String(bytes, true) => cx.add(String::from_utf8(bytes.clone())?),
+                Node::String(bytes, false) => cx.add(bytes.clone()),
+                Node::HashTable(_) => cx.add(HashTable::default()),
+                Node::CharTable { extras, .. } => {
+                    cx.add(CharTableData::new(nil(), nil(), extras.len()))
+                }
+                // needs the code and constants allocated first
+                Node::ByteFn { .. } => nil(),
+                Node::Subr(name) => match intern(name, cx).func(cx) {
+                    Some(func) if matches!(func.untag(), Function::SubrFn(_)) => func.into(),
+                    _ => bail!("Unknown builtin function {name} in dump"),
+                },
+            };
+            objects.push(obj);
+        }
+        for (idx, node) in self.objects.iter().enumerate() {
+            if let Node::ByteFn {
+                spec,
+                depth,
+                codes,
+                consts,
+            } = *node
+            {
+                let (Object::String(codes), Object::Vec(consts)) = (
+                    get(&objects, codes)?.untag(),
+                    get(&objects, consts)?.untag(),
+                ) else {bail!("Invalid byte code function in dump")};
+                let args = FnArgs::from_arg_spec(spec)?;
+                let func = unsafe { ByteFn::new(codes, consts, args, depth as usize) };
+                objects[idx] = cx.add(func);
+            }
+        }
+        for (node, obj) in self.objects.iter().zip(&objects) {
+            match (node, obj.untag()) {
+                (Node::Cons(car, cdr), Object::Cons(cons)) => {
+                    cons.set_car(get(&objects, *car)?)?;
+                    cons.set_cdr(get(&objects, *cdr)?)?;
+                }
+                (Node::Vec(items), Object::Vec(vec)) => fill(vec, items, &objects)?,
+                (Node::Record(items), Object::Record(record)) => fill(record, items, &objects)?,
+                (Node::HashTable(entries), Object::HashTable(table)) => {
+                    let mut table = table.try_borrow_mut()?;
+                    for &(key, value) in entries {
+                        table.insert(get(&objects, key)?, get(&objects, value)?);
+                    }
+                }
+                (
+                    Node::CharTable {
+                        subtype,
+                        parent,
+                        default,
+                        extras,
+                        entries,
+                    },
+                    Object::CharTable(table),
+                ) => {
+                    let mut data = table.borrow_mut();
+                    data.subtype = get(&objects, *subtype)?;
+                    data.parent = get(&objects, *parent)?;
+                    data.default = get(&objects, *default)?;
+                    for (slot, id) in data.extras.iter_mut().zip(extras) {
+                        *slot = get(&objects, *id)?;
+                    }
+                    for &(from, to, value) in entries {
+                        data.entries.set_range(from, to, get(&objects, value)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Install the roots of the image.
+    fn apply(&self, objects: &[GcObj], env: &mut Rt<Env>) -> Result<()> {
+        let symbol = |id| match get(objects, id)?.untag() {
+            Object::Symbol(sym) => Ok(sym),
+            x => Err(anyhow!("Expected symbol in dump, found {x}")),
+        };
+        for &(sym, val) in &self.vars {
+            env.vars.insert(symbol(sym)?, get(objects, val)?);
+        }
+        for &sym in &self.specials {
+            symbol(sym)?.make_special();
+        }
+        for &(sym, prop, val) in &self.props {
+            env.set_prop(symbol(sym)?, symbol(prop)?, get(objects, val)?);
+        }
+        for &(sym, func) in &self.functions {
+            crate::data::fset(symbol(sym)?, get(objects, func)?)?;
+        }
+        let mut features = FEATURES.lock().unwrap();
+        for &feature in &self.features {
+            features.insert(unsafe { symbol(feature)?.into_root() });
+        }
+        Ok(())
+    }
+}
+
+fn get<'ob>(objects: &[GcObj<'ob>], id: u32) -> Result<GcObj<'ob>> {
+    match objects.get(id as usize) {
+        Some(obj) => Ok(*obj),
+        None => bail!("Invalid object index {id} in dump"),
+    }
+}
+
+fn fill(vec: &LispVec, items: &[u32], objects: &[GcObj]) -> Result<()> {
+    for (cell, id) in vec.try_mut()?.iter().zip(items) {
+        cell.set(get(objects, *id)?);
+    }
+    Ok(())
+}
+
+impl Node {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Node::Int(x) => {
+                out.push(0);
+                put_u64(out, *x as u64);
+            }
+            Node::Float(x) => {
+                out.push(1);
+                put_u64(out, x.to_bits());
+            }
+            Node::Symbol(name) => {
+                out.push(2);
+                put_bytes(out, name.as_bytes());
+            }
+            Node::Uninterned(name) => {
+                out.push(3);
+                put_bytes(out, name.as_bytes());
+            }
+            Node::Cons(car, cdr) => {
+                out.push(4);
+                put_u32(out, *car);
+                put_u32(out, *cdr);
+            }
+            Node::Vec(items) => {
+                out.push(5);
+                put_ids(out, items);
+            }
+            Node::Record(items) => {
+                out.push(6);
+                put_ids(out, items);
+            }
+            Node::String(bytes, multibyte) => {
+                out.push(7);
+                out.push(u8::from(*multibyte));
+                put_bytes(out, bytes);
+            }
+            Node::HashTable(entries) => {
+                out.push(8);
+                put_u32(out, entries.len() as u32);
+                for &(key, value) in entries {
+                    put_u32(out, key);
+                    put_u32(out, value);
+                }
+            }
+            Node::ByteFn {
+                spec,
+                depth,
+                codes,
+                consts,
+            } => {
+                out.push(9);
+                put_u64(out, *spec);
+                put_u64(out, *depth);
+                put_u32(out, *codes);
+                put_u32(out, *consts);
+            }
+            Node::Subr(name) => {
+                out.push(10);
+                put_bytes(out, name.as_bytes());
+            }
+            Node::CharTable {
+                subtype,
+                parent,
+                default,
+                extras,
+                entries,
+            } => {
+                out.push(11);
+                put_u32(out, *subtype);
+                put_u32(out, *parent);
+                put_u32(out, *default);
+                put_ids(out, extras);
+                put_u32(out, entries.len() as u32);
+                for &(from, to, value) in entries {
+                    put_u32(out, from);
+                    put_u32(out, to);
+                    put_u32(out, value);
+                }
+            }
+        }
+    }
+
+    fn read(input: &mut Reader) -> Result<Self> {
+        let node = match input.u8()? {
+            0 => Node::Int(input.u64()? as i64),
+            1 => Node::Float(f64::from_bits(input.u64()?)),
+            2 => Node::Symbol(input.string()?),
+            3 => Node::Uninterned(input.string()?),
+            4 => Node::Cons(input.u32()?, input.u32()?),
+            5 => Node::Vec(input.ids()?),
+            6 => Node::Record(input.ids()?),
+            7 => {
+                let multibyte = input.u8()? != 0;
+                Node::String(input.bytes()?.to_vec(), multibyte)
+            }
+            8 => {
+                let mut entries = Vec::new();
+                for _ in 0..input.u32()? {
+                    entries.push((input.u32()?, input.u32()?));
+                }
+                Node::HashTable(entries)
+            }
+            9 => Node::ByteFn {
+                spec: input.u64()?,
+                depth: input.u64()?,
+                codes: input.u32()?,
+                consts: input.u32()?,
+            },
+            10 => Node::Subr(input.string()?),
+            11 => {
+                let (subtype, parent, default) = (input.u32()?, input.u32()?, input.u32()?);
+                let extras = input.ids()?;
+                let mut entries = Vec::new();
+                for _ in 0..input.u32()? {
+                    entries.push((input.u32()?, input.u32()?, input.u32()?));
+                }
+                Node::CharTable {
+                    subtype,
+                    parent,
+                    default,
+                    extras,
+                    entries,
+                }
+            }
+            tag => bail!("Invalid object tag {tag} in dump"),
+        };
+        Ok(node)
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, x: u32) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, x: u64) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+fn put_ids(out: &mut Vec<u8>, ids: &[u32]) {
+    put_u32(out, ids.len() as u32);
+    for id in ids {
+        put_u32(out, *id);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(len <= self.bytes.len(), "Dump file is truncated");
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(std::str::from_utf8(self.bytes()?)?.to_owned())
+    }
+
+    fn ids(&mut self) -> Result<Vec<u32>> {
+        (0..self.u32()?).map(|_| self.u32()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::core::object::Gc;
+    use crate::{reader, root};
+
+    #[test]
+    fn test_dump_round_trip() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let list_sym = intern("pdump-test-list", cx);
+        let vec_sym = intern("pdump-test-vec", cx);
+        let prop_sym = intern("pdump-test-prop", cx);
+        let fn_sym = intern("pdump-test-fn", cx);
+
+        let list = list!(1, 2; cx);
+        list.as_cons().cdr().as_cons().set_cdr(list).unwrap();
+        let uninterned = cx.add(SymbolCell::new_uninterned("pdump-test-u"));
+        let record = cx.add(RecordBuilder(vec![cx.add(3), uninterned]));
+        let mut table = HashTable::default();
+        table.insert(cx.add("key"), cx.add(4));
+        let table = cx.add(table);
+        let codes = cx.add(vec![192_u8, 135]);
+        let Object::String(codes) = codes.untag() else {unreachable!()};
+        let consts = cx.add(vec![cx.add(1)]);
+        let Object::Vec(consts) = consts.untag() else {unreachable!()};
+        let bytefn = crate::alloc::make_byte_code(257, codes, consts, 1, None, None, &[], cx);
+        let bytefn: GcObj = bytefn.unwrap().into();
+        let vec = vec![
+            cx.add(1.5),
+            cx.add("föo"),
+            uninterned,
+            uninterned,
+            record,
+            table,
+            bytefn,
+        ];
+        let vec = cx.add(vec);
+        env.vars.insert(list_sym, list);
+        env.vars.insert(vec_sym, vec);
+        env.set_prop(list_sym, prop_sym, cx.add(7));
+        let func = reader::read("(closure (t) (x) (+ x 1))", cx).unwrap().0;
+        crate::data::fset(fn_sym, func).unwrap();
+        root!(vec, cx);
+
+        let path = std::env::temp_dir().join(format!("rune-pdump-{}.pdmp", std::process::id()));
+        let path = path.to_str().unwrap();
+        dump_emacs_portable(path, None, env, cx).unwrap();
+        crate::data::fset(fn_sym, nil()).unwrap();
+        root!(new_env, Env::default(), cx);
+        load_dump(path, new_env, cx).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let Object::Cons(list) = new_env.vars.get(list_sym).unwrap().get(cx) else {unreachable!()};
+        assert_eq!(list.car(), 1);
+        let Object::Cons(tail) = list.cdr().untag() else {unreachable!()};
+        assert!(std::ptr::eq(tail.cdr().as_cons(), list));
+        let Object::Vec(new_vec) = new_env.vars.get(vec_sym).unwrap().get(cx) else {unreachable!()};
+        let Object::Vec(old) = vec.get(cx) else {unreachable!()};
+        let old = old.clone_vec();
+        let new = new_vec.clone_vec();
+        assert_eq!(old[..2], new[..2]);
+        // uninterned symbols keep their identity within the dump
+        assert!(new[2].ptr_eq(new[3]));
+        assert_ne!(new[2], old[2]);
+        assert_eq!(new[2].to_string(), "pdump-test-u");
+        let Object::Record(record) = new[4].untag() else {unreachable!()};
+        assert_eq!(record[0].get(), 3);
+        assert!(record[1].get().ptr_eq(new[2]));
+        assert_eq!(old[6], new[6]);
+        let Object::HashTable(new_table) = new[5].untag() else {unreachable!()};
+        let new_table = new_table.borrow();
+        let entries: Vec<_> = new_table.iter().map(|(k, v)| (*k, v.get())).collect();
+        assert_eq!(entries, vec![(cx.add("key"), cx.add(4))]);
+        drop(new_table);
+        assert_eq!(crate::data::get(list_sym, prop_sym, new_env, cx), 7);
+        let func: Gc<Function> = func.try_into().unwrap();
+        assert_eq!(fn_sym.func(cx), Some(func));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::core::object::GcObj;
+use crate::core::object::{GcObj, Object};
 use fn_macros::defun;
+use std::fmt::{self, Display, Write as _};
 
 #[defun]
 fn error_message_string(obj: GcObj) -> String {
@@ -7,6 +8,79 @@ fn error_message_string(obj: GcObj) -> String {
     format!("Error: {obj}")
 }
 
+/// Displays an object with the lists and vectors nested deeper than `level`
+/// abbreviated as `...`, as with `print-level`.
+pub(crate) struct PrintLevel<'ob> {
+    pub(crate) obj: GcObj<'ob>,
+    pub(crate) level: usize,
+}
+
+impl PrintLevel<'_> {
+    fn nested<'a>(&self, obj: GcObj<'a>) -> PrintLevel<'a> {
+        PrintLevel {
+            obj,
+            level: self.level - 1,
+        }
+    }
+}
+
+impl Display for PrintLevel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.obj.untag() {
+            Object::Cons(_) | Object::Vec(_) if self.level == 0 => f.write_str("..."),
+            Object::Cons(mut cons) => {
+                f.write_char('(')?;
+                loop {
+                    write!(f, "{}", self.nested(cons.car()))?;
+                    let cdr = cons.cdr();
+                    match cdr.untag() {
+                        Object::Cons(tail) => {
+                            cons = tail;
+                            f.write_char(' ')?;
+                        }
+                        Object::NIL => break,
+                        _ => {
+                            write!(f, " . {}", self.nested(cdr))?;
+                            break;
+                        }
+                    }
+                }
+                f.write_char(')')
+            }
+            Object::Vec(vec) => {
+                f.write_char('[')?;
+                for (i, elem) in vec.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(' ')?;
+                    }
+                    write!(f, "{}", self.nested(elem.get()))?;
+                }
+                f.write_char(']')
+            }
+            _ => write!(f, "{}", self.obj),
+        }
+    }
+}
+
 defvar!(PRINT_LENGTH);
 defvar!(PRINT_LEVEL);
 defvar_bool!(PRINT_ESCAPE_NEWLINES, false);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::reader;
+
+    #[test]
+    fn test_print_level() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let obj = reader::read("(a (b (c d)) [1 (2)] . e)", cx).unwrap().0;
+        let print = |level| PrintLevel { obj, level }.to_string();
+        assert_eq!(print(0), "...");
+        assert_eq!(print(1), "(a ... ... . e)");
+        assert_eq!(print(2), "(a (b ...) [1 ...] . e)");
+        assert_eq!(print(3), "(a (b (c d)) [1 (2)] . e)");
+    }
+}
@@ -0,0 +1,1823 @@
+//! Asynchronous subprocesses. The output of a process is read on threads of
+//! its own and queued, and the event loop hands it to the filter of the
+//! process, which by default inserts it in the buffer of the process at its
+//! mark. Once a process has exited and all its output was handled, its
+//! sentinel is called with a description of the new status.
+#![cfg(feature = "processes")]
+use crate::buffer::{get_buffer_create, get_current_buffer, get_live_buffer};
+use crate::buffer::{insert_text_before_markers, restore_current_buffer, set_current_buffer};
+use crate::callproc::{configure_command, find_program};
+use crate::coding::{region_text, CodingSystem, Kind};
+use crate::core::{
+    env::{sym, Env, Symbol},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{
+        nil, Buffer, Function, Gc, GcObj, LispString, List, Object, Process, ProcessData,
+        ProcessKind, Status, TagType,
+    },
+};
+use crate::fileio::file_io_error;
+use crate::fns::slice_into_list;
+use crate::root;
+use crate::sysdep::{self, signal_description};
+use crate::textprop::InsertProps;
+use anyhow::{anyhow, bail, ensure, Result};
+use fn_macros::defun;
+use std::fs::File;
+use std::io::{self, Read};
+#[cfg(feature = "network")]
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The processes, oldest first. A process that exited is removed once its
+/// sentinel was called, if `delete-exited-processes` is non-nil.
+static PROCESSES: Mutex<Vec<&'static Process>> = Mutex::new(Vec::new());
+
+/// Something that happened to a process that the event loop has yet to
+/// handle.
+pub(crate) enum Event {
+    Output(&'static Process, Vec<u8>),
+    /// The output of the process was closed
+    Eof(&'static Process),
+    /// A connection was made, or could not be
+    #[cfg(feature = "network")]
+    Connected(&'static Process, io::Result<TcpStream>),
+    /// A server accepted a connection from the address
+    #[cfg(feature = "network")]
+    Accepted(&'static Process, TcpStream, SocketAddr),
+    /// A datagram process received a datagram from the address
+    #[cfg(feature = "network")]
+    Datagram(&'static Process, Vec<u8>, SocketAddr),
+}
+
+impl Event {
+    fn process(&self) -> &'static Process {
+        match self {
+            Event::Output(process, _) | Event::Eof(process) => process,
+            #[cfg(feature = "network")]
+            Event::Connected(process, _)
+            | Event::Accepted(process, ..)
+            | Event::Datagram(process, ..) => process,
+        }
+    }
+}
+
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+/// The events of processes that `stop-process` stopped, until they are
+/// continued.
+static HELD_EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+/// Notified when an event is queued.
+static EVENT_READY: Condvar = Condvar::new();
+
+/// How often the event loop checks whether processes have exited while it
+/// waits for output.
+pub(crate) const PROCESS_POLL: Duration = Duration::from_millis(10);
+
+pub(crate) fn push_event(event: Event) {
+    EVENTS.lock().unwrap().push(event);
+    EVENT_READY.notify_all();
+}
+
+/// Read the output of `process` from `stream` on another thread until it is
+/// closed.
+pub(crate) fn read_output(process: &'static Process, mut stream: impl Read + Send + 'static) {
+    process.lock().open_streams += 1;
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                // a process that was deleted or let go of is no longer read
+                Ok(_) if !PROCESSES.lock().unwrap().contains(&process) => break,
+                Ok(len) => push_event(Event::Output(process, buf[..len].to_vec())),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        push_event(Event::Eof(process));
+    });
+}
+
+/// Wait until an event is queued or `timeout` has passed.
+fn wait_for_event(timeout: Duration) {
+    let events = EVENTS.lock().unwrap();
+    if events.is_empty() {
+        drop(EVENT_READY.wait_timeout(events, timeout).unwrap());
+    }
+}
+
+/// Whether there are processes, so the event loop has to look for their
+/// output.
+pub(crate) fn has_processes() -> bool {
+    !PROCESSES.lock().unwrap().is_empty()
+}
+
+/// The process named `name`.
+fn process_named(name: &str) -> Option<&'static Process> {
+    let processes = PROCESSES.lock().unwrap();
+    processes.iter().copied().find(|x| x.name() == name)
+}
+
+/// The process PROCESS stands for: a process, the name of one, or a buffer
+/// or the name of a buffer with a process. nil stands for the process of the
+/// current buffer.
+fn resolve_process(process: GcObj, env: &mut Rt<Env>) -> Result<&'static Process> {
+    let buffer = match process.untag() {
+        Object::Process(process) => return Ok(process),
+        Object::String(name) => {
+            let name: &str = name.try_into()?;
+            if let Some(process) = process_named(name) {
+                return Ok(process);
+            }
+            get_live_buffer(process).map_err(|_| anyhow!("Process {name} does not exist"))?
+        }
+        Object::Buffer(buffer) => buffer,
+        Object::NIL => get_current_buffer(env),
+        _ => bail!(TypeError::new(Type::Process, process)),
+    };
+    let name = buffer.lock()?.name().to_owned();
+    buffer_process(buffer).ok_or_else(|| anyhow!("Buffer {name} has no process"))
+}
+
+fn buffer_process(buffer: &Buffer) -> Option<&'static Process> {
+    let processes = PROCESSES.lock().unwrap();
+    processes
+        .iter()
+        .copied()
+        .find(|x| x.lock().buffer.is_some_and(|x| x == buffer))
+}
+
+/// The value of `key` among the Lisp values of `process`.
+pub(crate) fn slot<'ob>(
+    process: &'static Process,
+    key: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let Some(slots) = env.process_props.get(process.tag()) else {return nil()};
+    slots
+        .iter()
+        .find(|x| x.0 == key)
+        .map_or_else(nil, |x| x.1.bind(cx))
+}
+
+pub(crate) fn set_slot(process: &'static Process, key: Symbol, value: GcObj, env: &mut Rt<Env>) {
+    let process = process.tag();
+    match env.process_props.get_mut(process) {
+        Some(slots) => match slots.iter_mut().find(|x| x.0 == key) {
+            Some(x) => x.1.set(value),
+            None => slots.push((key, value)),
+        },
+        None => {
+            env.process_props.insert(process, vec![(key, value)]);
+        }
+    }
+}
+
+/// The coding systems to decode the output of a process and to encode its
+/// input with, from the `:coding` argument of `make-process`: a coding
+/// system for both, or a cons of the two. They default to UTF-8.
+pub(crate) fn process_codings(
+    coding: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(CodingSystem, CodingSystem)> {
+    let (decoding, encoding) = match coding.untag() {
+        Object::Cons(cons) => (cons.car(), cons.cdr()),
+        _ => (coding, coding),
+    };
+    let mut get = |coding: GcObj| match coding.untag() {
+        Object::NIL => Ok(CodingSystem::get("utf-8-unix").unwrap()),
+        Object::Symbol(coding) => CodingSystem::from_symbol(coding, env, cx),
+        _ => Err(TypeError::new(Type::Symbol, coding).into()),
+    };
+    Ok((get(decoding)?, get(encoding)?))
+}
+
+/// Decode `bytes` of output of a process, after what was left over from the
+/// last output. The end of a character that is cut off is kept for the next
+/// output, unless `flush` is set.
+pub(crate) fn decode_output(data: &mut ProcessData, bytes: &[u8], flush: bool) -> String {
+    let mut input = std::mem::take(&mut data.undecoded);
+    input.extend_from_slice(bytes);
+    if !flush && matches!(data.decoding.kind, Kind::Utf8 | Kind::Undecided) {
+        if let Err(e) = std::str::from_utf8(&input) {
+            if e.error_len().is_none() {
+                data.undecoded = input.split_off(e.valid_up_to());
+            }
+        }
+    }
+    data.decoding.decode(&input).1
+}
+
+/// The description of `status` of a process of `kind` that is passed to
+/// sentinels.
+fn status_message(kind: ProcessKind, status: Status) -> String {
+    let network = matches!(kind, ProcessKind::Network | ProcessKind::Server);
+    match status {
+        Status::Run if network => "open\n".to_owned(),
+        Status::Run => "run\n".to_owned(),
+        Status::Connect => "connect\n".to_owned(),
+        Status::Failed(code) => format!("failed with code {code}\n"),
+        Status::Exit(0) if network => "deleted\n".to_owned(),
+        Status::Exit(_) if network => "connection broken by remote peer\n".to_owned(),
+        Status::Exit(0) => "finished\n".to_owned(),
+        Status::Exit(code) => format!("exited abnormally with code {code}\n"),
+        Status::Signal(signal, core_dumped) => signal_message(signal, core_dumped),
+        Status::Stop(signal) => signal_message(signal, false),
+    }
+}
+
+/// The description of `signal` that is passed to sentinels: "Killed" is
+/// reported as "killed\n".
+fn signal_message(signal: i32, core_dumped: bool) -> String {
+    let description = signal_description(signal, core_dumped);
+    let mut chars = description.chars();
+    let description: String = chars
+        .next()
+        .into_iter()
+        .flat_map(char::to_lowercase)
+        .chain(chars)
+        .collect();
+    format!("{description}\n")
+}
+
+fn exit_status(status: ExitStatus) -> Status {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => Status::Exit(code),
+        (None, Some(signal)) => Status::Signal(signal, status.core_dumped()),
+        (None, None) => Status::Exit(-1),
+    }
+}
+
+/// Whether `status` is that of a process that runs or is stopped.
+fn is_running(status: Status) -> bool {
+    matches!(status, Status::Run | Status::Stop(_))
+}
+
+/// Whether `process` is running, stopped or connecting, as opposed to
+/// having exited or having been deleted.
+fn is_active(process: &'static Process) -> bool {
+    let status = process.lock().status;
+    is_running(status) || status == Status::Connect
+}
+
+/// Reap the child of `process` as soon as it exits, so that it does not
+/// linger as a zombie, and note whether it was stopped or continued. Once
+/// it has exited and all its output was read, its status changes to how it
+/// exited. A pipe process is done once its output is closed, and so is a
+/// network connection, which the other end broke then.
+fn update_status(process: &'static Process) {
+    let mut data = process.lock();
+    if !is_running(data.status) {
+        return;
+    }
+    let pid = data.child.as_ref().map(std::process::Child::id);
+    if let Some(pid) = pid.filter(|_| data.exit.is_none()) {
+        let status = match sysdep::wait_child(pid, false) {
+            Some(status) if status.continued() => Status::Run,
+            Some(status) => match status.stopped_signal() {
+                Some(signal) => Status::Stop(signal),
+                None => {
+                    data.exit = Some(exit_status(status));
+                    data.status
+                }
+            },
+            None => data.status,
+        };
+        if status != data.status {
+            data.status = status;
+            data.status_changed = true;
+        }
+    }
+    if data.open_streams > 0 || data.kind == ProcessKind::Server {
+        return;
+    }
+    let status = match (pid, data.exit) {
+        (Some(_), Some(exit)) => exit,
+        (Some(_), None) => return,
+        (None, _) if data.kind == ProcessKind::Network => Status::Exit(256),
+        (None, _) => Status::Exit(0),
+    };
+    data.status = status;
+    data.status_changed = true;
+    data.stdin = None;
+}
+
+/// Report an error in the `what` of a process, like its filter, with
+/// `message`. There is no caller to signal it to.
+fn report_error(what: &str, error: &anyhow::Error, cx: &Context) {
+    let args = [cx.add(what), cx.add(error.to_string())];
+    _ = crate::editfns::message("error in process %s: %s", &args);
+}
+
+/// Call FUNCTION with `processes` and `text`, the way filters and sentinels
+/// are called. The current buffer and the match data are kept, and errors
+/// are reported instead of being signaled.
+pub(crate) fn call_handler(
+    function: &Rt<GcObj>,
+    processes: &[&'static Process],
+    text: &str,
+    what: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) {
+    let buffer = get_current_buffer(env);
+    let match_data = env.match_data.bind(cx);
+    root!(match_data, cx);
+    let mut call = vec![function.bind(cx)];
+    call.extend(processes.iter().map(|x| GcObj::from(x.tag())));
+    call.push(cx.add(text));
+    root!(call, move(call), cx);
+    let mut call_function = || -> Result<()> {
+        let function: &Rt<Gc<Function>> = Rt::try_into(&call[0])?;
+        crate::eval::funcall(function, &call[1..], env, cx)?;
+        Ok(())
+    };
+    if let Err(e) = call_function() {
+        report_error(what, &e, cx);
+    }
+    env.match_data.set(match_data.bind(cx));
+    restore_current_buffer(buffer, env, cx);
+}
+
+/// Insert `text` at the end of `buffer` or at `mark` if it points into it,
+/// and move `mark` after the text. Point moves along if it was at `mark`.
+fn insert_at_mark(
+    buffer: &'static Buffer,
+    mark: &'static crate::core::object::Marker,
+    text: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if !buffer.is_live() {
+        return Ok(());
+    }
+    let old = get_current_buffer(env);
+    set_current_buffer(buffer, env, cx);
+    let result = (|| {
+        let pos = mark.position().filter(|x| x.0 == buffer).map(|x| x.1);
+        let (point, pos) = {
+            let mut data = buffer.lock()?;
+            let pos = pos.unwrap_or_else(|| data.text.len_chars());
+            let point = data.point();
+            data.goto_char(pos);
+            (point, pos)
+        };
+        insert_text_before_markers(text, InsertProps::None, env, cx)?;
+        let end = buffer.lock()?.point();
+        crate::marker::set_marker(mark, Some(end + 1), Some(buffer), env)?;
+        // point follows the output if it was at the mark
+        let point = match point {
+            x if x > pos => x + (end - pos),
+            x if x == pos => end,
+            x => x,
+        };
+        buffer.lock()?.goto_char(point);
+        Ok(())
+    })();
+    restore_current_buffer(old, env, cx);
+    result
+}
+
+/// Give `text`, output of `process`, to its filter.
+pub(crate) fn deliver_output(
+    process: &'static Process,
+    text: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) {
+    let filter = slot(process, sym::KW_FILTER, env, cx);
+    match filter.untag() {
+        Object::NIL | Object::Symbol(sym::INTERNAL_DEFAULT_PROCESS_FILTER) => {
+            let buffer = process.lock().buffer;
+            if let Some(buffer) = buffer {
+                if let Err(e) = insert_at_mark(buffer, process.process_mark(), text, env, cx) {
+                    report_error("filter", &e, cx);
+                }
+            }
+        }
+        _ => {
+            root!(filter, cx);
+            call_handler(filter, &[process], text, "filter", env, cx);
+        }
+    }
+}
+
+/// Handle `bytes` of output of `process`.
+fn handle_output(process: &'static Process, bytes: &[u8], env: &mut Rt<Env>, cx: &mut Context) {
+    let text = decode_output(&mut process.lock(), bytes, bytes.is_empty());
+    if !text.is_empty() {
+        deliver_output(process, &text, env, cx);
+    }
+}
+
+/// Call the sentinel of `process` with `message`.
+pub(crate) fn call_sentinel(
+    process: &'static Process,
+    message: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) {
+    let sentinel = slot(process, sym::KW_SENTINEL, env, cx);
+    match sentinel.untag() {
+        Object::NIL | Object::Symbol(sym::INTERNAL_DEFAULT_PROCESS_SENTINEL) => {
+            default_sentinel(process, message, env, cx);
+        }
+        _ => {
+            root!(sentinel, cx);
+            call_handler(sentinel, &[process], message, "sentinel", env, cx);
+        }
+    }
+}
+
+/// Tell the sentinel of `process` about its new status. Once a process has
+/// exited it is forgotten if `delete-exited-processes` is non-nil.
+fn notify_status(process: &'static Process, env: &mut Rt<Env>, cx: &mut Context) {
+    let (kind, status) = {
+        let mut data = process.lock();
+        data.status_changed = false;
+        (data.kind, data.status)
+    };
+    call_sentinel(process, &status_message(kind, status), env, cx);
+    let delete = env
+        .vars
+        .get(sym::DELETE_EXITED_PROCESSES)
+        .is_none_or(|x| !x.bind(cx).nil());
+    if delete && !is_running(status) && status != Status::Connect {
+        PROCESSES.lock().unwrap().retain(|x| *x != process);
+    }
+}
+
+/// Insert a line about the new status of `process` at the end of its buffer.
+fn default_sentinel(process: &'static Process, message: &str, env: &mut Rt<Env>, cx: &mut Context) {
+    let Some(buffer) = process.lock().buffer else {return};
+    if !buffer.is_live() {
+        return;
+    }
+    let text = format!("\nProcess {} {message}", process.name());
+    let end = crate::core::object::Marker::create();
+    if let Err(e) = insert_at_mark(buffer, end, &text, env, cx) {
+        report_error("sentinel", &e, cx);
+    }
+}
+
+/// Handle the output of processes that has been read, and call the
+/// sentinels of the processes that exited. Returns the processes that
+/// produced output.
+pub(crate) fn dispatch_process_events(
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Vec<&'static Process> {
+    let events = std::mem::take(&mut *EVENTS.lock().unwrap());
+    let mut produced = Vec::new();
+    for event in events {
+        let process = event.process();
+        if process.lock().stopped {
+            HELD_EVENTS.lock().unwrap().push(event);
+            continue;
+        }
+        // a deleted process gets no more output
+        if !is_active(process) && !matches!(event, Event::Eof(_)) {
+            continue;
+        }
+        match event {
+            Event::Output(process, bytes) => {
+                handle_output(process, &bytes, env, cx);
+                if !produced.contains(&process) {
+                    produced.push(process);
+                }
+            }
+            Event::Eof(process) => {
+                let closed = {
+                    let mut data = process.lock();
+                    data.open_streams -= 1;
+                    data.open_streams == 0
+                };
+                // the last bytes are decoded even if they are not whole
+                if closed && is_active(process) {
+                    handle_output(process, &[], env, cx);
+                }
+            }
+            #[cfg(feature = "network")]
+            Event::Datagram(process, bytes, from) => {
+                crate::network::receive_datagram(process, &bytes, from, env, cx);
+                if !produced.contains(&process) {
+                    produced.push(process);
+                }
+            }
+            #[cfg(feature = "network")]
+            Event::Connected(process, result) => crate::network::finish_connect(process, result),
+            #[cfg(feature = "network")]
+            Event::Accepted(server, stream, peer) => {
+                if let Err(e) = crate::network::accept_connection(server, stream, peer, env, cx) {
+                    report_error(server.name(), &e, cx);
+                }
+            }
+        }
+    }
+    let processes = PROCESSES.lock().unwrap().clone();
+    for process in processes {
+        update_status(process);
+        if process.lock().status_changed {
+            notify_status(process, env, cx);
+        }
+    }
+    produced
+}
+
+/// `name`, or else the first of `name<1>`, `name<2>` and so on that is not
+/// the name of a process.
+fn unique_name(name: &str) -> String {
+    let mut unique = name.to_owned();
+    let mut i = 1;
+    while process_named(&unique).is_some() {
+        unique = format!("{name}<{i}>");
+        i += 1;
+    }
+    unique
+}
+
+/// Connect the standard streams of the child of `cmd` to a pseudo-terminal
+/// if `use_pty` is set, or else to pipes. The error output goes to `stderr`
+/// if it is given, and is otherwise mixed into the output, so that the two
+/// stay in order. Returns what the output is read from, and the
+/// pseudo-terminal.
+fn connect(
+    cmd: &mut Command,
+    use_pty: bool,
+    stderr: Option<File>,
+) -> io::Result<(Box<dyn Read + Send>, Option<crate::pty::Pty>)> {
+    if use_pty {
+        let pty = crate::pty::open()?;
+        crate::pty::attach(cmd, &pty.terminal)?;
+        match stderr {
+            Some(stderr) => cmd.stderr(stderr),
+            None => cmd.stderr(pty.terminal.try_clone()?),
+        };
+        let reader = pty.master.try_clone()?;
+        return Ok((Box::new(reader), Some(pty)));
+    }
+    let (reader, writer) = io::pipe()?;
+    match stderr {
+        Some(stderr) => cmd.stderr(stderr),
+        None => cmd.stderr(writer.try_clone()?),
+    };
+    cmd.stdin(Stdio::piped()).stdout(writer);
+    Ok((Box::new(reader), None))
+}
+
+/// The value of the keyword `key` in the keyword arguments `args`, or nil.
+pub(crate) fn keyword_arg<'ob>(args: &[GcObj<'ob>], key: Symbol) -> GcObj<'ob> {
+    args.chunks(2)
+        .find(|x| matches!(x[0].untag(), Object::Symbol(s) if s == key))
+        .map_or_else(nil, |x| x[1])
+}
+
+/// Make a process from `data`, named `name` or a variant of it that is
+/// unique, with the Lisp values in `slots`. Its mark starts at the end of
+/// its buffer.
+pub(crate) fn add_process(
+    name: &str,
+    data: ProcessData,
+    slots: &[(Symbol, GcObj)],
+    env: &mut Rt<Env>,
+) -> Result<&'static Process> {
+    let buffer = data.buffer;
+    let process = Process::create(unique_name(name), data);
+    if let Some(buffer) = buffer {
+        let end = buffer.lock()?.text.len_chars();
+        crate::marker::set_marker(process.process_mark(), Some(end + 1), Some(buffer), env)?;
+    }
+    // the processes of an interpreter are the ones it has slots for
+    env.process_props
+        .insert(process.tag(), Vec::<(Symbol, GcObj)>::new());
+    for &(key, value) in slots {
+        set_slot(process, key, value, env);
+    }
+    PROCESSES.lock().unwrap().push(process);
+    Ok(process)
+}
+
+/// Make a process like [`add_process`], and read its output from `reader`.
+pub(crate) fn start_process(
+    name: &str,
+    data: ProcessData,
+    reader: impl Read + Send + 'static,
+    slots: &[(Symbol, GcObj)],
+    env: &mut Rt<Env>,
+) -> Result<&'static Process> {
+    let process = add_process(name, data, slots, env)?;
+    read_output(process, reader);
+    Ok(process)
+}
+
+/// Make a pipe process named `name` whose output goes to `buffer`.
+fn pipe_process(
+    name: &str,
+    buffer: Option<&'static Buffer>,
+    (decoding, encoding): (CodingSystem, CodingSystem),
+    slots: &[(Symbol, GcObj)],
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<&'static Process> {
+    let (reader, writer) = match io::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => return Err(file_io_error(&e, "Creating pipe", name, env, cx)),
+    };
+    let mut data = ProcessData::new(decoding, encoding);
+    data.kind = ProcessKind::Pipe;
+    data.buffer = buffer;
+    data.stdin = Some(File::from(OwnedFd::from(writer)));
+    start_process(name, data, reader, slots, env)
+}
+
+/// Make a pipe process, which reads what is written to a pipe like the
+/// output of a subprocess, such as the :stderr of `make-process`.
+/// Its status is `open` until the pipe is closed, and then `closed`. The
+/// arguments are keywords and their values: :name, :buffer, :coding,
+/// :filter, :sentinel and :noquery are as in `make-process`, and :stop is
+/// accepted and ignored.
+#[defun]
+fn make_pipe_process<'ob>(
+    args: &[GcObj<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'static Process> {
+    ensure!(
+        args.len().is_multiple_of(2),
+        "Odd number of arguments to make-pipe-process"
+    );
+    let arg = |key| keyword_arg(args, key);
+    let name: &str = arg(sym::KW_NAME).try_into()?;
+    let buffer = match arg(sym::KW_BUFFER) {
+        x if x.nil() => None,
+        x => Some(get_buffer_create(x, None)?),
+    };
+    let codings = process_codings(arg(sym::KW_CODING), env, cx)?;
+    let slots = [sym::KW_FILTER, sym::KW_SENTINEL].map(|key| (key, arg(key)));
+    let process = pipe_process(name, buffer, codings, &slots, env, cx)?;
+    process.lock().query_on_exit = arg(sym::KW_NOQUERY).nil();
+    Ok(process)
+}
+
+/// Start a program in a subprocess and return the process object for it.
+/// The arguments are keywords and their values:
+///
+/// :name NAME is the name of the process, which is made unique.
+///
+/// :buffer BUFFER is the buffer or buffer name of the process, which the
+/// output goes to by default.
+///
+/// :command COMMAND is a list of the program and its arguments. The program
+/// is looked for in `exec-path`.
+///
+/// :coding CODING is the coding system to decode the output and encode the
+/// input with, or a cons of the two.
+///
+/// :connection-type TYPE is `pty` to talk to the process through a
+/// pseudo-terminal, or `pipe` to use pipes. If it is nil,
+/// `process-connection-type` says which.
+///
+/// :filter FILTER is called with the process and each piece of output. If
+/// it is nil, the output is inserted in the buffer of the process.
+///
+/// :sentinel SENTINEL is called with the process and a description of its
+/// status when it changes. If it is nil, the description is inserted in
+/// the buffer of the process.
+///
+/// :stderr STDERR is a pipe process that the error output goes to instead
+/// of being mixed with the rest of the output. If it is a buffer or buffer
+/// name, a pipe process named "NAME stderr" is made for that buffer.
+///
+/// :noquery NOQUERY non-nil clears the query-on-exit flag of the process, so
+/// that it is killed when the interpreter shuts down instead of being left
+/// running.
+///
+/// :stop and :file-handler are accepted and ignored.
+#[defun]
+fn make_process<'ob>(
+    args: &[GcObj<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'static Process> {
+    ensure!(
+        args.len().is_multiple_of(2),
+        "Odd number of arguments to make-process"
+    );
+    let arg = |key| keyword_arg(args, key);
+    let name: &str = arg(sym::KW_NAME).try_into()?;
+    let buffer = match arg(sym::KW_BUFFER) {
+        x if x.nil() => None,
+        x => Some(get_buffer_create(x, None)?),
+    };
+    let command = arg(sym::KW_COMMAND);
+    let mut words = Vec::new();
+    for word in command.as_list()? {
+        words.push(<&str>::try_from(word?)?.to_owned());
+    }
+    let Some((program, program_args)) = words.split_first() else {bail!("No program given for make-process")};
+    let codings = process_codings(arg(sym::KW_CODING), env, cx)?;
+
+    let Some(file) = find_program(program, env, cx)? else {
+        let err = io::Error::from_raw_os_error(2);
+        return Err(file_io_error(
+            &err,
+            "Searching for program",
+            program,
+            env,
+            cx,
+        ));
+    };
+    let use_pty = match arg(sym::KW_CONNECTION_TYPE).untag() {
+        Object::NIL => env
+            .vars
+            .get(sym::PROCESS_CONNECTION_TYPE)
+            .is_some_and(|x| !x.bind(cx).nil()),
+        Object::Symbol(sym::PTY) => true,
+        Object::Symbol(sym::PIPE) => false,
+        x => bail!("Invalid connection type: {x}"),
+    };
+    let stderr = match arg(sym::KW_STDERR).untag() {
+        Object::NIL => None,
+        Object::Process(process) => Some(process),
+        _ => {
+            let buffer = get_buffer_create(arg(sym::KW_STDERR), None)?;
+            let name = format!("{name} stderr");
+            let process = pipe_process(&name, Some(buffer), codings, &[], env, cx)?;
+            process.lock().query_on_exit = arg(sym::KW_NOQUERY).nil();
+            Some(process)
+        }
+    };
+    let stderr_pipe = match stderr {
+        Some(process) => {
+            let data = process.lock();
+            match &data.stdin {
+                Some(pipe) if data.kind == ProcessKind::Pipe => Some(pipe.try_clone()?),
+                _ => bail!("Process {} is not an open pipe process", process.name()),
+            }
+        }
+        None => None,
+    };
+    let mut cmd = Command::new(&file);
+    cmd.args(program_args);
+    configure_command(&mut cmd, env, cx)?;
+    // signals go to the whole process group of the child, and signals from
+    // the terminal of this process do not reach it
+    if !use_pty {
+        cmd.process_group(0);
+    }
+    let (reader, pty) = match connect(&mut cmd, use_pty, stderr_pipe) {
+        Ok(x) => x,
+        Err(e) => {
+            let action = if use_pty {
+                "Opening pty"
+            } else {
+                "Creating pipe"
+            };
+            return Err(file_io_error(&e, action, program, env, cx));
+        }
+    };
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(file_io_error(&e, "Spawning child process", &file, env, cx)),
+    };
+    // the pipes and the terminal the child writes to must only be open in
+    // the child, so that reading them ends when it exits
+    drop(cmd);
+    let pty = pty.map(|x| (x.master, x.name));
+    // the pipe process gets to the end of the error output once the child
+    // exits
+    if let Some(stderr) = stderr {
+        stderr.lock().stdin = None;
+    }
+
+    let mut data = ProcessData::new(codings.0, codings.1);
+    data.buffer = buffer;
+    data.query_on_exit = arg(sym::KW_NOQUERY).nil();
+    match pty {
+        Some((master, name)) => {
+            data.stdin = Some(master.try_clone()?);
+            data.pty = Some(master);
+            data.tty_name = Some(name);
+        }
+        None => data.stdin = child.stdin.take().map(|x| File::from(OwnedFd::from(x))),
+    }
+    data.child = Some(child);
+    let slots = [sym::KW_FILTER, sym::KW_SENTINEL, sym::KW_COMMAND].map(|key| (key, arg(key)));
+    start_process(name, data, reader, &slots, env)
+}
+
+/// Return t if OBJECT is a process.
+#[defun]
+fn processp(object: GcObj) -> bool {
+    matches!(object.untag(), Object::Process(_))
+}
+
+/// Return the name of PROCESS, as a string.
+#[defun]
+fn process_name(process: &'static Process) -> String {
+    process.name().to_owned()
+}
+
+/// Return the command that was run to start PROCESS, as a list of the
+/// program and its arguments.
+#[defun]
+fn process_command<'ob>(process: &'static Process, env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    slot(process, sym::KW_COMMAND, env, cx)
+}
+
+/// Return the process id of PROCESS, or nil if it has none.
+#[defun]
+fn process_id<'ob>(process: &'static Process) -> GcObj<'ob> {
+    let data = process.lock();
+    data.child
+        .as_ref()
+        .map_or_else(nil, |x| i64::from(x.id()).into())
+}
+
+/// Return the name of the terminal PROCESS uses, or nil if it talks to it
+/// through pipes. STREAM is ignored, since the terminal is used for all of
+/// its input and output.
+#[defun]
+fn process_tty_name<'ob>(
+    process: &'static Process,
+    _stream: Option<GcObj>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let data = process.lock();
+    data.tty_name.as_deref().map_or_else(nil, |x| cx.add(x))
+}
+
+/// Tell the programs on the terminal of PROCESS that it has HEIGHT lines of
+/// WIDTH characters. Return t if it was done, and nil if PROCESS does not
+/// have a terminal.
+#[defun]
+fn set_process_window_size(process: &'static Process, height: usize, width: usize) -> Result<bool> {
+    let (height, width) = (u16::try_from(height)?, u16::try_from(width)?);
+    let data = process.lock();
+    let Some(master) = &data.pty else {return Ok(false)};
+    crate::pty::set_window_size(master, height, width)?;
+    Ok(true)
+}
+
+/// Return the buffer PROCESS is associated with, or nil.
+#[defun]
+fn process_buffer<'ob>(process: &'static Process) -> GcObj<'ob> {
+    process.lock().buffer.map_or_else(nil, |x| x.tag().into())
+}
+
+/// Make BUFFER the buffer of PROCESS, or leave it without one if BUFFER is
+/// nil.
+#[defun]
+fn set_process_buffer(
+    process: &'static Process,
+    buffer: Option<&'static Buffer>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'static>> {
+    process.lock().buffer = buffer;
+    let mark = process.process_mark();
+    match buffer {
+        Some(buffer) => {
+            let end = buffer.lock()?.text.len_chars();
+            crate::marker::set_marker(mark, Some(end + 1), Some(buffer), env)?;
+        }
+        None => {
+            crate::marker::set_marker(mark, None, None, env)?;
+        }
+    }
+    Ok(buffer.map_or_else(nil, |x| x.tag().into()))
+}
+
+/// Return the marker for where the output of PROCESS is inserted.
+#[defun]
+fn process_mark(process: &'static Process) -> &'static crate::core::object::Marker {
+    process.process_mark()
+}
+
+/// Return the filter function of PROCESS. The default filter is
+/// `internal-default-process-filter`.
+#[defun]
+fn process_filter<'ob>(process: &'static Process, env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    match slot(process, sym::KW_FILTER, env, cx) {
+        x if x.nil() => sym::INTERNAL_DEFAULT_PROCESS_FILTER.into(),
+        x => x,
+    }
+}
+
+/// Give PROCESS the filter function FILTER, which is called with the process
+/// and each piece of its output. If FILTER is nil, the output is inserted in
+/// the buffer of the process.
+#[defun]
+fn set_process_filter<'ob>(
+    process: &'static Process,
+    filter: GcObj<'ob>,
+    env: &mut Rt<Env>,
+) -> GcObj<'ob> {
+    set_slot(process, sym::KW_FILTER, filter, env);
+    filter
+}
+
+/// Return the sentinel of PROCESS. The default sentinel is
+/// `internal-default-process-sentinel`.
+#[defun]
+fn process_sentinel<'ob>(process: &'static Process, env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    match slot(process, sym::KW_SENTINEL, env, cx) {
+        x if x.nil() => sym::INTERNAL_DEFAULT_PROCESS_SENTINEL.into(),
+        x => x,
+    }
+}
+
+/// Give PROCESS the sentinel SENTINEL, which is called with the process and
+/// a description of its status when it changes. If SENTINEL is nil, the
+/// description is inserted in the buffer of the process.
+#[defun]
+fn set_process_sentinel<'ob>(
+    process: &'static Process,
+    sentinel: GcObj<'ob>,
+    env: &mut Rt<Env>,
+) -> GcObj<'ob> {
+    set_slot(process, sym::KW_SENTINEL, sentinel, env);
+    sentinel
+}
+
+/// Return a cons of the coding systems that decode the output of PROCESS
+/// and encode its input.
+#[defun]
+fn process_coding_system<'ob>(process: &'static Process, cx: &'ob Context) -> GcObj<'ob> {
+    let data = process.lock();
+    cons!(data.decoding.symbol(cx), data.encoding.symbol(cx); cx)
+}
+
+/// Decode the output of PROCESS with DECODING and encode its input with
+/// ENCODING. nil stands for UTF-8.
+#[defun]
+fn set_process_coding_system(
+    process: &'static Process,
+    decoding: GcObj,
+    encoding: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let codings = cons!(decoding, encoding; cx);
+    let (decoding, encoding) = process_codings(codings, env, cx)?;
+    let mut data = process.lock();
+    data.decoding = decoding;
+    data.encoding = encoding;
+    Ok(false)
+}
+
+/// Return the property list of PROCESS.
+#[defun]
+fn process_plist<'ob>(process: &'static Process, env: &Rt<Env>, cx: &'ob Context) -> GcObj<'ob> {
+    slot(process, sym::KW_PLIST, env, cx)
+}
+
+/// Replace the property list of PROCESS with PLIST.
+#[defun]
+fn set_process_plist<'ob>(
+    process: &'static Process,
+    plist: GcObj<'ob>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    ensure!(
+        matches!(plist.untag(), Object::Cons(_) | Object::NIL),
+        TypeError::new(Type::List, plist)
+    );
+    set_slot(process, sym::KW_PLIST, plist, env);
+    Ok(plist)
+}
+
+/// Insert TEXT, output of PROCESS, in the buffer of PROCESS at its mark. This
+/// is the default filter.
+#[defun]
+fn internal_default_process_filter(
+    process: &Rt<GcObj>,
+    text: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let process: &'static Process = process.bind(cx).try_into()?;
+    let text: &str = text.bind(cx).try_into()?;
+    let text = text.to_owned();
+    let buffer = process.lock().buffer;
+    if let Some(buffer) = buffer {
+        insert_at_mark(buffer, process.process_mark(), &text, env, cx)?;
+    }
+    Ok(false)
+}
+
+/// Insert a line with the status MESSAGE of PROCESS at the end of its
+/// buffer. This is the default sentinel.
+#[defun]
+fn internal_default_process_sentinel(
+    process: &Rt<GcObj>,
+    message: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let process: &'static Process = process.bind(cx).try_into()?;
+    let message = <&str>::try_from(message.bind(cx))?.to_owned();
+    default_sentinel(process, &message, env, cx);
+    Ok(false)
+}
+
+/// Return the status of PROCESS, which is a process, the name of one, or a
+/// buffer with a process: `run` while it is running, and `exit` or `signal`
+/// once it exited or was killed, or `stop` while it is stopped. A pipe process or network connection is
+/// `open` until it is closed, and then `closed`. A network connection is
+/// `connect` while it is being made and `failed` if it could not be, and a
+/// server is `listen`. Return nil if there is no such process.
+#[defun]
+fn process_status<'ob>(process: GcObj, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let process = match process.untag() {
+        Object::String(name) => match process_named(name.try_into()?) {
+            Some(process) => process,
+            None => return Ok(nil()),
+        },
+        _ => resolve_process(process, env)?,
+    };
+    let data = process.lock();
+    let status = match (data.kind, data.status) {
+        (_, Status::Run | Status::Stop(_)) if data.stopped => sym::STOP,
+        (ProcessKind::Real, Status::Run) => sym::RUN,
+        (ProcessKind::Real, Status::Stop(_)) => sym::STOP,
+        (ProcessKind::Real, Status::Signal(..)) => sym::SIGNAL,
+        (ProcessKind::Real, _) => sym::EXIT,
+        (ProcessKind::Server, Status::Run) => sym::LISTEN,
+        (_, Status::Run) => sym::OPEN,
+        (_, Status::Connect) => sym::CONNECT,
+        (_, Status::Failed(_)) => sym::FAILED,
+        (_, _) => sym::CLOSED,
+    };
+    Ok(status.into())
+}
+
+/// Return the type of PROCESS: `real` for a subprocess, `pipe` for a pipe
+/// process, and `network` for a network connection or server.
+#[defun]
+fn process_type(process: GcObj, env: &mut Rt<Env>) -> Result<Symbol<'static>> {
+    match resolve_process(process, env)?.lock().kind {
+        ProcessKind::Real => Ok(sym::REAL),
+        ProcessKind::Pipe => Ok(sym::PIPE),
+        ProcessKind::Network | ProcessKind::Server => Ok(sym::NETWORK),
+    }
+}
+
+/// Return how PROCESS was contacted. For a network process, that is a list
+/// of its host and service if KEY is nil, the property list of the
+/// arguments that made it if KEY is t, and else the value of KEY in that
+/// list. For other processes, return t. NO-BLOCK is ignored.
+#[defun]
+fn process_contact<'ob>(
+    process: &'static Process,
+    key: Option<GcObj<'ob>>,
+    _no_block: Option<()>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let contact = slot(process, sym::PROCESS_CONTACT, env, cx);
+    if contact.nil() {
+        return Ok(sym::TRUE.into());
+    }
+    let contact: Gc<List> = contact.try_into()?;
+    let get = |key| crate::fns::plist_get(contact, key);
+    Ok(match key {
+        None => list![get(sym::KW_HOST.into())?, get(sym::KW_SERVICE.into())?; cx],
+        Some(key) if key == sym::TRUE => contact.into(),
+        Some(key) => get(key)?,
+    })
+}
+
+/// Return a list of all processes.
+#[defun]
+fn process_list<'ob>(cx: &'ob Context) -> GcObj<'ob> {
+    let processes: Vec<GcObj> = PROCESSES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|x| x.tag().into())
+        .collect();
+    slice_into_list(&processes, None, cx)
+}
+
+/// Return the process named NAME, or nil if there is none. If NAME is a
+/// process, it is returned.
+#[defun]
+fn get_process(name: GcObj) -> Result<GcObj> {
+    match name.untag() {
+        Object::Process(_) => Ok(name),
+        Object::String(name) => {
+            Ok(process_named(name.try_into()?).map_or_else(nil, |x| x.tag().into()))
+        }
+        _ => Err(TypeError::new(Type::String, name).into()),
+    }
+}
+
+/// Return the process of BUFFER, a buffer or the name of one, or nil if it
+/// has none.
+#[defun]
+fn get_buffer_process<'ob>(buffer: GcObj) -> GcObj<'ob> {
+    if buffer.nil() {
+        return nil();
+    }
+    let Ok(buffer) = get_live_buffer(buffer) else {return nil()};
+    buffer_process(buffer).map_or_else(nil, |x| x.tag().into())
+}
+
+/// Queue `bytes` as input to `process`, and write what is queued. While the
+/// process is not ready for more, the output of processes is handled, so
+/// that a process that waits for its output to be read is not waited for
+/// forever. A datagram process sends `bytes` as one datagram.
+fn send_bytes(
+    process: &'static Process,
+    bytes: &[u8],
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let name = process.name();
+    {
+        let mut data = process.lock();
+        ensure!(data.status == Status::Run, "Process {name} not running");
+        if let Some(socket) = &data.socket {
+            let Some(address) = data.datagram_address else {bail!("Process {name} has no datagram address")};
+            socket.send_to(bytes, address)?;
+            return Ok(());
+        }
+        ensure!(
+            data.stdin.is_some(),
+            "Output file descriptor of {name} is closed"
+        );
+        queue_input(&mut data, bytes)?;
+    }
+    loop {
+        {
+            let mut data = process.lock();
+            let data = &mut *data;
+            let Some(stdin) = &data.stdin else {
+                data.pending_input.clear();
+                bail!("Output file descriptor of {name} is closed");
+            };
+            let (queued, _) = data.pending_input.as_slices();
+            if queued.is_empty() {
+                return Ok(());
+            }
+            let len = queued.len().min(sysdep::PIPE_BUF);
+            match sysdep::write_ready(stdin, &queued[..len]) {
+                Ok(written) => {
+                    data.pending_input.drain(..written);
+                    continue;
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => {
+                    data.pending_input.clear();
+                    data.stdin = None;
+                    ensure!(
+                        e.kind() != io::ErrorKind::BrokenPipe,
+                        "Process {name} no longer connected to pipe; closed it"
+                    );
+                    return Err(e.into());
+                }
+            }
+        }
+        dispatch_process_events(env, cx);
+        wait_for_event(PROCESS_POLL);
+    }
+}
+
+/// Queue `bytes` to be written as the input of `data`, encrypted if it is a
+/// TLS connection.
+fn queue_input(data: &mut ProcessData, bytes: &[u8]) -> io::Result<()> {
+    #[cfg(feature = "tls")]
+    if let Some(session) = &data.tls {
+        return crate::tls::encrypt(session, bytes, &mut data.pending_input);
+    }
+    data.pending_input.extend(bytes);
+    Ok(())
+}
+
+/// Send STRING to PROCESS as input, encoded with its coding system for
+/// output. PROCESS is a process, the name of one, or a buffer with one, and
+/// nil stands for the process of the current buffer. The output of
+/// processes is handled while PROCESS is not ready for all of STRING.
+#[defun]
+fn process_send_string(
+    process: &Rt<GcObj>,
+    string: &Rt<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let process = resolve_process(process.bind(cx), env)?;
+    let string = string.get(cx);
+    let bytes = match string.is_multibyte() {
+        true => process.lock().encoding.encode(string.try_into()?),
+        false => string.to_vec(),
+    };
+    send_bytes(process, &bytes, env, cx)?;
+    Ok(false)
+}
+
+/// Send the text of the current buffer between START and END to PROCESS as
+/// input, like `process-send-string`.
+#[defun]
+fn process_send_region(
+    process: &Rt<GcObj>,
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let process = resolve_process(process.bind(cx), env)?;
+    let (_, _, text) = region_text(start, end, env, cx)?;
+    let bytes = process.lock().encoding.encode(&text);
+    send_bytes(process, &bytes, env, cx)?;
+    Ok(false)
+}
+
+/// Make PROCESS read the end of its input, and return it. A process on a
+/// terminal is sent C-d, and the input of other processes is closed, after
+/// what was sent before is written. PROCESS is as in `process-send-string`,
+/// and defaults to the process of the current buffer.
+#[defun]
+fn process_send_eof(
+    process: Option<&Rt<GcObj>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<&'static Process> {
+    let process = resolve_process(process.map_or_else(nil, |x| x.bind(cx)), env)?;
+    if process.lock().pty.is_some() {
+        send_bytes(process, b"\x04", env, cx)?;
+        return Ok(process);
+    }
+    #[cfg(feature = "tls")]
+    let session = process.lock().tls.clone();
+    #[cfg(feature = "tls")]
+    if let Some(session) = session {
+        crate::tls::close_notify(&session);
+        send_bytes(process, &[], env, cx)?;
+    }
+    let mut data = process.lock();
+    ensure!(
+        data.status == Status::Run,
+        "Process {} not running",
+        process.name()
+    );
+    if let Some(stdin) = data.stdin.take() {
+        // the stream is shared with the thread that reads the connection
+        if data.kind == ProcessKind::Network {
+            sysdep::shutdown_output(&stdin);
+        }
+    }
+    Ok(process)
+}
+
+/// Send the signal named `signal` to the process group of the subprocess
+/// `process`, or to the job in the foreground of its terminal if
+/// `current_group` is set and it has one.
+fn signal_subprocess(process: &'static Process, signal: &str, current_group: bool) -> Result<()> {
+    let data = process.lock();
+    let name = process.name();
+    ensure!(
+        data.kind == ProcessKind::Real,
+        "Process {name} is not a subprocess"
+    );
+    let running = matches!(data.status, Status::Run | Status::Stop(_)) && data.exit.is_none();
+    let Some(child) = data.child.as_ref().filter(|_| running) else {bail!("Process {name} is not active")};
+    let group = match &data.pty {
+        Some(master) if current_group => crate::pty::foreground_group(master)?,
+        _ => i32::try_from(child.id())?,
+    };
+    let signal = sysdep::signal_number(signal).unwrap();
+    sysdep::send_signal(-group, signal)?;
+    Ok(())
+}
+
+/// Interrupt PROCESS by sending SIGINT to its process group. PROCESS is a
+/// process, the name of one, or a buffer with one, and nil stands for the
+/// process of the current buffer. If CURRENT-GROUP is non-nil and PROCESS
+/// talks through a terminal, the signal goes to the job in the foreground
+/// of the terminal instead. Return PROCESS.
+#[defun]
+fn interrupt_process<'ob>(
+    process: GcObj<'ob>,
+    current_group: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    signal_subprocess(
+        resolve_process(process, env)?,
+        "INT",
+        current_group.is_some(),
+    )?;
+    Ok(process)
+}
+
+/// Kill PROCESS by sending SIGKILL to it like `interrupt-process`.
+#[defun]
+fn kill_process<'ob>(
+    process: GcObj<'ob>,
+    current_group: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    signal_subprocess(
+        resolve_process(process, env)?,
+        "KILL",
+        current_group.is_some(),
+    )?;
+    Ok(process)
+}
+
+/// Send SIGQUIT to PROCESS like `interrupt-process`.
+#[defun]
+fn quit_process<'ob>(
+    process: GcObj<'ob>,
+    current_group: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    signal_subprocess(
+        resolve_process(process, env)?,
+        "QUIT",
+        current_group.is_some(),
+    )?;
+    Ok(process)
+}
+
+/// Stop PROCESS. A subprocess is sent SIGTSTP like `interrupt-process`, and
+/// the output of other processes is held back until `continue-process`.
+/// Return PROCESS.
+#[defun]
+fn stop_process<'ob>(
+    process: GcObj<'ob>,
+    current_group: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<GcObj<'ob>> {
+    let resolved = resolve_process(process, env)?;
+    if resolved.lock().kind == ProcessKind::Real {
+        signal_subprocess(resolved, "TSTP", current_group.is_some())?;
+    } else {
+        resolved.lock().stopped = true;
+    }
+    Ok(process)
+}
+
+/// Continue PROCESS after `stop-process`. A subprocess is sent SIGCONT like
+/// `interrupt-process`, and its sentinel is told that it runs again.
+/// Return the process.
+#[defun]
+fn continue_process(
+    process: &Rt<GcObj>,
+    current_group: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<&'static Process> {
+    let process = resolve_process(process.bind(cx), env)?;
+    if process.lock().kind == ProcessKind::Real {
+        signal_subprocess(process, "CONT", current_group.is_some())?;
+        let stopped = matches!(process.lock().status, Status::Stop(_));
+        if stopped {
+            process.lock().status = Status::Run;
+            notify_status(process, env, cx);
+        }
+        return Ok(process);
+    }
+    process.lock().stopped = false;
+    let held = std::mem::take(&mut *HELD_EVENTS.lock().unwrap());
+    let (events, rest): (Vec<_>, _) = held.into_iter().partition(|x| x.process() == process);
+    *HELD_EVENTS.lock().unwrap() = rest;
+    // the held events came before the ones that are queued
+    EVENTS.lock().unwrap().splice(0..0, events);
+    EVENT_READY.notify_all();
+    Ok(process)
+}
+
+/// Send the signal SIGCODE to PROCESS, which is a process id, a process, the
+/// name of one, or a buffer with one. SIGCODE is the number of a signal or
+/// its name, in any case and with or without the SIG prefix. Return 0 if
+/// the signal was sent and -1 if it could not be. REMOTE is ignored.
+#[defun]
+fn signal_process(
+    process: GcObj,
+    sigcode: GcObj,
+    _remote: Option<()>,
+    env: &mut Rt<Env>,
+) -> Result<i64> {
+    let pid = match process.untag() {
+        Object::Int(pid) => i32::try_from(pid)?,
+        _ => {
+            let process = resolve_process(process, env)?;
+            let data = process.lock();
+            match &data.child {
+                Some(child) if data.exit.is_none() && is_running(data.status) => {
+                    i32::try_from(child.id())?
+                }
+                _ => bail!("Cannot signal process {}", process.name()),
+            }
+        }
+    };
+    let name: String = match sigcode.untag() {
+        Object::Int(signal) => return Ok(signal_result(pid, i32::try_from(signal)?)),
+        Object::Symbol(name) => name.name().to_owned(),
+        Object::String(name) => <&str>::try_from(name)?.to_owned(),
+        _ => bail!(TypeError::new(Type::Symbol, sigcode)),
+    };
+    let Some(signal) = sysdep::signal_number(&name) else {bail!("Undefined signal name {name}")};
+    Ok(signal_result(pid, signal))
+}
+
+fn signal_result(pid: i32, signal: i32) -> i64 {
+    match sysdep::send_signal(pid, signal) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Kill `process` if it is a subprocess that is still running, and close
+/// its input and its connection, so that no more output comes. Its status
+/// becomes the one it exited with, or else `signal` for a subprocess and
+/// `closed` for other processes.
+fn close_process(process: &'static Process) {
+    let mut data = process.lock();
+    if is_running(data.status) || data.status == Status::Connect {
+        let status = match (data.child.as_ref().map(std::process::Child::id), data.exit) {
+            (Some(_), Some(exit)) => exit,
+            (Some(pid), None) => {
+                let kill = sysdep::signal_number("KILL").unwrap();
+                if let Ok(group) = i32::try_from(pid) {
+                    let _ = sysdep::send_signal(-group, kill);
+                }
+                reap(pid);
+                Status::Signal(kill, false)
+            }
+            (None, _) => Status::Exit(0),
+        };
+        data.status = status;
+        data.status_changed = true;
+    }
+    data.stopped = false;
+    if matches!(data.kind, ProcessKind::Network | ProcessKind::Server) {
+        if let Some(socket) = &data.stdin {
+            sysdep::shutdown_socket(socket);
+        }
+        if let Some(socket) = &data.socket {
+            sysdep::shutdown_socket(socket);
+        }
+        if let Some(listener) = &data.listener {
+            sysdep::shutdown_socket(listener);
+        }
+    }
+    data.stdin = None;
+    data.pty = None;
+    data.socket = None;
+    data.listener = None;
+}
+
+/// Wait for the child `pid` to exit, past the reports of it being stopped
+/// or continued.
+fn reap(pid: u32) {
+    while sysdep::wait_child(pid, true)
+        .is_some_and(|x| x.stopped_signal().is_some() || x.continued())
+    {}
+}
+
+/// Close `process` like [`close_process`], tell its sentinel, and forget it.
+fn remove_process(process: &'static Process, env: &mut Rt<Env>, cx: &mut Context) {
+    close_process(process);
+    if process.lock().status_changed {
+        notify_status(process, env, cx);
+    }
+    PROCESSES.lock().unwrap().retain(|x| *x != process);
+}
+
+/// Delete PROCESS: kill it if it is a running subprocess, and close it
+/// otherwise. Its sentinel is told, and it is forgotten. PROCESS is a
+/// process, the name of one, or a buffer with one, and nil stands for the
+/// process of the current buffer.
+#[defun]
+fn delete_process(process: &Rt<GcObj>, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let process = resolve_process(process.bind(cx), env)?;
+    remove_process(process, env, cx);
+    Ok(false)
+}
+
+/// Delete the processes made in `env` as the interpreter shuts down, once
+/// the output and status changes that came before are handled. Every
+/// subprocess is killed, whatever its query-on-exit flag, so that no child
+/// outlives the interpreter, and the sentinels are told.
+pub(crate) fn shutdown_processes(env: &mut Rt<Env>, cx: &mut Context) {
+    dispatch_process_events(env, cx);
+    let processes: Vec<_> = PROCESSES
+        .lock()
+        .unwrap()
+        .iter()
+        .copied()
+        .filter(|x| env.process_props.get(x.tag()).is_some())
+        .collect();
+    for process in processes {
+        remove_process(process, env, cx);
+    }
+}
+
+/// Return the query-on-exit flag of PROCESS. Emacs asks before exiting
+/// while a process with the flag set is running. There is no one to ask
+/// here, so every process is killed at shutdown whatever its flag.
+#[defun]
+fn process_query_on_exit_flag(process: &'static Process) -> bool {
+    process.lock().query_on_exit
+}
+
+/// Set the query-on-exit flag of PROCESS to FLAG, and return FLAG.
+#[defun]
+fn set_process_query_on_exit_flag<'ob>(process: &'static Process, flag: GcObj<'ob>) -> GcObj<'ob> {
+    process.lock().query_on_exit = !flag.nil();
+    flag
+}
+
+/// Return the exit code of PROCESS, or the number of the signal that killed
+/// or stopped it. A network connection that the other end broke has the
+/// code 256. Return 0 while PROCESS runs.
+#[defun]
+fn process_exit_status(process: GcObj, env: &mut Rt<Env>) -> Result<i64> {
+    Ok(match resolve_process(process, env)?.lock().status {
+        Status::Run | Status::Connect => 0,
+        Status::Exit(code)
+        | Status::Failed(code)
+        | Status::Signal(code, _)
+        | Status::Stop(code) => code.into(),
+    })
+}
+
+/// Wait for output from processes and handle it. If PROCESS is non-nil,
+/// wait until it produces output or exits, and otherwise until any process
+/// produces output. Wait at most SECONDS plus MILLISEC milliseconds if they
+/// are given. Return non-nil if output was received from PROCESS, or from
+/// any process if PROCESS is nil. JUST-THIS-ONE is ignored, since output
+/// of every process is handled.
+#[defun]
+fn accept_process_output(
+    process: &Rt<GcObj>,
+    seconds: Option<&Rt<GcObj>>,
+    millisec: Option<i64>,
+    _just_this_one: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let process = match process.bind(cx) {
+        x if x.nil() => None,
+        x => Some(<&'static Process>::try_from(x)?),
+    };
+    let seconds = match seconds.map(|x| x.bind(cx).untag()) {
+        None => None,
+        Some(Object::Int(x)) => Some(x as f64),
+        Some(Object::Float(x)) => Some(**x),
+        Some(x) => bail!(TypeError::new(Type::Number, x)),
+    };
+    let millis = millisec.map(|x| x as f64 / 1000.0);
+    let timeout = match (seconds, millis) {
+        (None, None) => None,
+        (secs, millis) => {
+            let secs = secs.unwrap_or(0.0) + millis.unwrap_or(0.0);
+            Some(Duration::from_secs_f64(secs.max(0.0)))
+        }
+    };
+    let deadline = timeout.map(|x| Instant::now() + x);
+    loop {
+        let produced = dispatch_process_events(env, cx);
+        match process {
+            Some(process) if produced.contains(&process) => return Ok(true),
+            None if !produced.is_empty() => return Ok(true),
+            Some(process) if !matches!(process.lock().status, Status::Run | Status::Connect) => {
+                return Ok(false);
+            }
+            // nothing can come if there are no processes
+            None if deadline.is_none() && !has_processes() => return Ok(false),
+            _ => {}
+        }
+        let now = Instant::now();
+        if deadline.is_some_and(|x| x <= now) {
+            return Ok(false);
+        }
+        let wait = deadline.map_or(PROCESS_POLL, |x| (x - now).min(PROCESS_POLL));
+        wait_for_event(wait);
+    }
+}
+
+defsym!(KW_NAME);
+defsym!(KW_BUFFER);
+defsym!(KW_COMMAND);
+defsym!(KW_CODING);
+defsym!(KW_FILTER);
+defsym!(KW_SENTINEL);
+defsym!(KW_STDERR);
+defsym!(KW_CONNECTION_TYPE);
+defsym!(PTY);
+defsym!(PIPE);
+defsym!(KW_PLIST);
+defsym!(KW_NOQUERY);
+defsym!(RUN);
+defsym!(EXIT);
+defsym!(REAL);
+defsym!(OPEN);
+defsym!(CLOSED);
+defsym!(NETWORK);
+defsym!(LISTEN);
+defsym!(CONNECT);
+defsym!(FAILED);
+defsym!(STOP);
+defsym!(KW_HOST);
+defsym!(KW_SERVICE);
+defvar_bool!(DELETE_EXITED_PROCESSES, true);
+defvar_bool!(PROCESS_CONNECTION_TYPE, true);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader};
+
+    #[test]
+    fn test_make_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        let form = r#"(progn
+                        (setq output nil statuses nil)
+                        (defalias 'record-output #'(lambda (p text) (setq output (cons text output))))
+                        (defalias 'record-status #'(lambda (p msg) (setq statuses (cons msg statuses))))
+                        (setq filtered (make-process :name "filtered"
+                                                     :command '("/bin/sh" "-c" "echo out; echo err >&2; exit 3")
+                                                     :filter 'record-output
+                                                     :sentinel 'record-status))
+                        (setq buffered (make-process :name "buffered"
+                                                     :buffer "process-out"
+                                                     :stderr "process-err"
+                                                     :command '("/bin/sh" "-c" "echo out; echo err >&2")))
+                        (list (processp filtered) (process-name filtered) (process-status filtered)
+                              (eq (get-process "buffered") buffered)
+                              (eq (get-buffer-process "process-out") buffered)
+                              (process-filter buffered) (process-command buffered)))"#;
+        let expect = r#"(t "filtered" run t t internal-default-process-filter ("/bin/sh" "-c" "echo out; echo err >&2"))"#;
+        assert_eq!(eval(form), expect);
+        let form = r#"(progn
+                        (while (or (eq (process-status filtered) 'run)
+                                   (eq (process-status buffered) 'run)
+                                   (eq (process-status "buffered stderr") 'open))
+                          (accept-process-output nil 0.1))
+                        (list (apply #'concat (nreverse output)) statuses
+                              (progn (set-buffer "process-out") (buffer-string))
+                              (progn (set-buffer "process-err") (buffer-string))
+                              (marker-position (process-mark buffered))
+                              (memq filtered (process-list))
+                              (condition-case nil (make-process :name "x" :command '("/nonexistent")) (error 'missing))))"#;
+        let expect = "(\"out\nerr\n\" (\"exited abnormally with code 3\n\") \"out\n\nProcess buffered finished\n\" \"err\n\nProcess buffered stderr finished\n\" 32 nil missing)";
+        assert_eq!(eval(form), expect);
+    }
+
+    #[test]
+    fn test_pipe_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn
+                        (setq errors nil)
+                        (setq pipe (make-pipe-process :name "errors"
+                                                      :filter #'(lambda (p text) (setq errors (cons text errors)))))
+                        (setq result (list (process-type pipe) (process-status pipe)
+                                           (condition-case nil (make-pipe-process :buffer "x") (error 'unnamed))))
+                        (setq proc (make-process :name "proc" :stderr pipe :connection-type 'pipe
+                                                 :command '("/bin/sh" "-c" "echo oops >&2")))
+                        (while (or (eq (process-status proc) 'run) (eq (process-status pipe) 'open))
+                          (accept-process-output nil 0.1))
+                        (append result (list (process-status pipe) errors
+                                             (condition-case nil (make-process :name "x" :stderr proc :command '("true"))
+                                               (error 'not-pipe)))))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let expect = "(pipe open unnamed closed (\"oops\n\") not-pipe)";
+        assert_eq!(result.to_string(), expect);
+    }
+
+    #[test]
+    fn test_pty_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn
+                        (setq pty (make-process :name "pty" :buffer "pty-out" :connection-type 'pty
+                                                :command '("/bin/sh" "-c" "test -t 0 && test -t 1 && echo tty")))
+                        (setq pipe (make-process :name "pipe" :connection-type 'pipe
+                                                 :command '("/bin/sh" "-c" "test -t 0 || echo pipe")))
+                        (setq result (list (stringp (process-tty-name pty)) (set-process-window-size pty 24 80)
+                                           (process-tty-name pipe) (set-process-window-size pipe 24 80)))
+                        (while (or (eq (process-status pty) 'run) (eq (process-status pipe) 'run))
+                          (accept-process-output nil 0.1))
+                        (set-buffer "pty-out")
+                        (cons (buffer-string) result))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(\"tty\n\nProcess pty finished\n\" t t nil nil)"
+        );
+    }
+    #[test]
+    fn test_process_send() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        // more than a pipe holds, so that the sender has to wait for cat
+        let form = r#"(progn
+                        (setq output nil)
+                        (setq cat (make-process :name "cat" :command '("/bin/cat") :connection-type 'pipe
+                                                :coding 'latin-1
+                                                :filter #'(lambda (p text) (setq output (cons text output)))))
+                        (setq big "x")
+                        (while (< (length big) 200000)
+                          (setq big (concat big big)))
+                        (process-send-string cat big)
+                        (set-buffer (get-buffer-create "send"))
+                        (insert "<é>")
+                        (process-send-region cat 2 3)
+                        (process-send-eof cat)
+                        (while (eq (process-status cat) 'run)
+                          (accept-process-output nil 0.05))
+                        (setq output (apply #'concat (nreverse output)))
+                        (setq tty (make-process :name "tty" :buffer "tty-out" :command '("/bin/cat")
+                                                :connection-type 'pty))
+                        (process-send-string tty "line
+")
+                        (process-send-eof tty)
+                        (while (eq (process-status tty) 'run)
+                          (accept-process-output nil 0.05))
+                        (set-buffer "tty-out")
+                        (list (length output) (aref output 262144) (buffer-string)
+                              (condition-case nil (process-send-string cat "x") (error 'not-running))))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let expect = "(262145 233 \"line\n\nProcess tty finished\n\" not-running)";
+        assert_eq!(result.to_string(), expect);
+    }
+
+    #[test]
+    fn test_signal_process() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn
+                        (setq statuses nil)
+                        (defalias 'wait-while #'(lambda (p status)
+                                                  (while (eq (process-status p) status)
+                                                    (accept-process-output nil 0.05))))
+                        (defalias 'sleeper #'(lambda (name)
+                                               (make-process :name name :connection-type 'pipe
+                                                             :command '("/bin/sleep" "10")
+                                                             :sentinel #'(lambda (p msg) (setq statuses (cons msg statuses))))))
+                        (setq proc (sleeper "interrupted"))
+                        (stop-process proc)
+                        (wait-while proc 'run)
+                        (setq result (list (process-status proc)))
+                        (continue-process proc)
+                        (setq result (cons (process-status proc) result))
+                        (interrupt-process proc)
+                        (wait-while proc 'run)
+                        (setq result (append (list (process-exit-status proc) (process-status proc)) result))
+                        (setq proc (sleeper "terminated"))
+                        (setq result (cons (signal-process proc 'sigterm) result))
+                        (wait-while proc 'run)
+                        (setq result (cons (process-exit-status proc) result))
+                        (setq proc (sleeper "deleted"))
+                        (setq result (cons (condition-case nil (signal-process proc 'sigfoo) (error 'undefined)) result))
+                        (delete-process proc)
+                        (setq result (append (list (memq proc (process-list)) (process-status proc)) result))
+                        (setq pipe (make-pipe-process :name "pipe"))
+                        (stop-process pipe)
+                        (setq result (cons (process-status pipe) result))
+                        (continue-process pipe)
+                        (setq result (cons (process-status pipe) result))
+                        (setq result (cons (condition-case nil (interrupt-process pipe) (error 'not-subprocess)) result))
+                        (delete-process pipe)
+                        (list (nreverse (cons (process-status pipe) result)) (nreverse statuses)))"#;
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = interpreter::eval(obj, None, env, cx).unwrap();
+        let expect =
+            "((stop run signal 2 0 15 undefined signal nil stop open not-subprocess closed) \
+                      (\"stopped\n\" \"run\n\" \"interrupt\n\" \"terminated\n\" \"killed\n\"))";
+        assert_eq!(result.to_string(), expect);
+    }
+}
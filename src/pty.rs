@@ -0,0 +1,222 @@
+//! Pseudo-terminals, for subprocesses that expect to talk to a terminal,
+//! like shells and interpreters. The terminal is set up the way Emacs sets
+//! up the terminals of its subprocesses: input is not echoed back, newlines
+//! are not turned into carriage returns and newlines on output, and C-d
+//! ends the input.
+#![cfg(feature = "processes")]
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::Mutex;
+
+#[cfg(not(target_vendor = "apple"))]
+mod consts {
+    use std::os::raw::c_ulong;
+    pub(super) const O_NOCTTY: i32 = 0o400;
+    pub(super) const TIOCSWINSZ: c_ulong = 0x5414;
+    #[cfg(test)]
+    pub(super) const TIOCGWINSZ: c_ulong = 0x5413;
+    pub(super) const TIOCSCTTY: c_ulong = 0x540e;
+    pub(super) const ECHO: TcFlag = 0o10;
+    pub(super) const ONLCR: TcFlag = 0o4;
+    pub(super) const VEOF: usize = 4;
+    pub(super) const NCCS: usize = 32;
+    pub(super) type TcFlag = u32;
+    pub(super) type Speed = u32;
+}
+
+#[cfg(target_vendor = "apple")]
+mod consts {
+    use std::os::raw::c_ulong;
+    pub(super) const O_NOCTTY: i32 = 0x20000;
+    pub(super) const TIOCSWINSZ: c_ulong = 0x8008_7467;
+    #[cfg(test)]
+    pub(super) const TIOCGWINSZ: c_ulong = 0x4008_7468;
+    pub(super) const TIOCSCTTY: c_ulong = 0x2000_7461;
+    pub(super) const ECHO: TcFlag = 0x8;
+    pub(super) const ONLCR: TcFlag = 0x2;
+    pub(super) const VEOF: usize = 0;
+    pub(super) const NCCS: usize = 20;
+    pub(super) type TcFlag = u64;
+    pub(super) type Speed = u64;
+}
+
+#[cfg(test)]
+use consts::TIOCGWINSZ;
+use consts::{Speed, TcFlag, ECHO, NCCS, ONLCR, O_NOCTTY, TIOCSCTTY, TIOCSWINSZ, VEOF};
+
+const O_RDWR: c_int = 2;
+const F_SETFD: c_int = 2;
+const FD_CLOEXEC: c_int = 1;
+const TCSANOW: c_int = 0;
+
+/// `struct termios` of the C library.
+#[repr(C)]
+#[allow(clippy::struct_field_names)]
+struct Termios {
+    c_iflag: TcFlag,
+    c_oflag: TcFlag,
+    c_cflag: TcFlag,
+    c_lflag: TcFlag,
+    #[cfg(not(target_vendor = "apple"))]
+    c_line: u8,
+    c_cc: [u8; NCCS],
+    c_ispeed: Speed,
+    c_ospeed: Speed,
+}
+
+/// `struct winsize` of the C library.
+#[repr(C)]
+#[allow(clippy::struct_field_names)]
+#[derive(Default)]
+struct WinSize {
+    ws_row: u16,
+    ws_col: u16,
+    ws_xpixel: u16,
+    ws_ypixel: u16,
+}
+
+extern "C" {
+    fn posix_openpt(flags: c_int) -> c_int;
+    fn grantpt(fd: c_int) -> c_int;
+    fn unlockpt(fd: c_int) -> c_int;
+    fn ptsname(fd: c_int) -> *mut c_char;
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    fn setsid() -> c_int;
+    fn tcgetpgrp(fd: c_int) -> c_int;
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        x => Ok(x),
+    }
+}
+
+/// A pseudo-terminal. The subprocess gets the terminal end, and the master
+/// end is read and written to talk to it.
+pub(crate) struct Pty {
+    pub(crate) master: File,
+    pub(crate) terminal: File,
+    /// The file name of the terminal end
+    pub(crate) name: String,
+}
+
+/// Open a new pseudo-terminal.
+pub(crate) fn open() -> io::Result<Pty> {
+    // ptsname returns a static buffer
+    static PTSNAME: Mutex<()> = Mutex::new(());
+    let fd = check(unsafe { posix_openpt(O_RDWR | O_NOCTTY) })?;
+    let master = unsafe { File::from_raw_fd(fd) };
+    check(unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) })?;
+    check(unsafe { grantpt(fd) })?;
+    check(unsafe { unlockpt(fd) })?;
+    let name = {
+        let _guard = PTSNAME.lock().unwrap();
+        let name = unsafe { ptsname(fd) };
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    let terminal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(O_NOCTTY)
+        .open(&name)?;
+    configure(&terminal)?;
+    Ok(Pty {
+        master,
+        terminal,
+        name,
+    })
+}
+
+/// Turn off echoing and the translation of newlines on output, and make C-d
+/// end the input.
+fn configure(terminal: &File) -> io::Result<()> {
+    let mut termios = MaybeUninit::<Termios>::uninit();
+    check(unsafe { tcgetattr(terminal.as_raw_fd(), termios.as_mut_ptr()) })?;
+    let mut termios = unsafe { termios.assume_init() };
+    termios.c_lflag &= !ECHO;
+    termios.c_oflag &= !ONLCR;
+    termios.c_cc[VEOF] = 4;
+    check(unsafe { tcsetattr(terminal.as_raw_fd(), TCSANOW, std::ptr::from_ref(&termios)) })?;
+    Ok(())
+}
+
+/// Give the child of `command` the terminal `terminal` as its standard input
+/// and output, and as the controlling terminal of a session of its own, so
+/// that C-c and the like reach it as signals. The standard error is left
+/// to the caller.
+pub(crate) fn attach(command: &mut Command, terminal: &File) -> io::Result<()> {
+    command
+        .stdin(terminal.try_clone()?)
+        .stdout(terminal.try_clone()?);
+    unsafe {
+        command.pre_exec(|| {
+            check(setsid())?;
+            check(ioctl(0, TIOCSCTTY, 0))?;
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Tell the programs on the terminal of `master` that it has `rows` lines of
+/// `columns` characters.
+pub(crate) fn set_window_size(master: &File, rows: u16, columns: u16) -> io::Result<()> {
+    let size = WinSize {
+        ws_row: rows,
+        ws_col: columns,
+        ..WinSize::default()
+    };
+    check(unsafe { ioctl(master.as_raw_fd(), TIOCSWINSZ, &size) })?;
+    Ok(())
+}
+
+/// The process group of the job in the foreground of the terminal of
+/// `master`.
+pub(crate) fn foreground_group(master: &File) -> io::Result<i32> {
+    check(unsafe { tcgetpgrp(master.as_raw_fd()) })
+}
+
+#[cfg(test)]
+fn window_size(file: &File) -> io::Result<(u16, u16)> {
+    let mut size = WinSize::default();
+    check(unsafe { ioctl(file.as_raw_fd(), TIOCGWINSZ, &mut size) })?;
+    Ok((size.ws_row, size.ws_col))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_pty() {
+        let mut pty = open().unwrap();
+        assert!(pty.name.starts_with("/dev/"));
+        set_window_size(&pty.master, 30, 100).unwrap();
+        assert_eq!(window_size(&pty.terminal).unwrap(), (30, 100));
+        // no echo, and newlines stay newlines
+        pty.terminal.write_all(b"out\n").unwrap();
+        let mut buf = [0; 16];
+        let len = pty.master.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"out\n");
+        pty.master.write_all(b"in\n").unwrap();
+        let len = pty.terminal.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"in\n");
+    }
+}
@@ -477,6 +477,14 @@ impl<'a, 'ob> Reader<'a, 'ob> {
     }
 }
 
+/// The index of the first character in `slice` that is not whitespace or part
+/// of a comment.
+pub(crate) fn skip_whitespace(slice: &str) -> usize {
+    let mut tokens = Tokenizer::new(slice);
+    tokens.skip_till_char();
+    tokens.cur_pos()
+}
+
 /// read a lisp object from `slice`. Return the object and index of next
 /// remaining character in the slice.
 pub(crate) fn read<'ob>(slice: &str, cx: &'ob Context) -> Result<(GcObj<'ob>, usize)> {
@@ -0,0 +1,1060 @@
+#![cfg(feature = "regex")]
+//! A backtracking matcher for Emacs regexps. Patterns are parsed into a tree,
+//! which is compiled to a small program of instructions. The matcher runs the
+//! program against text that may be split in two around the gap of a buffer,
+//! trying alternatives in order and backtracking when they fail, so the first
+//! match found is the same one Emacs finds.
+use crate::casetab::CaseTable;
+use crate::category::CategoryTable;
+use crate::syntax::{CharClass, CharSet, Syntax, SyntaxTable};
+use memchr::memmem;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// The error for a pattern that is not a valid regexp.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct InvalidRegexp(pub(crate) &'static str);
+
+impl std::error::Error for InvalidRegexp {}
+
+impl Display for InvalidRegexp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid regexp: \"{}\"", self.0)
+    }
+}
+
+type Result<T> = std::result::Result<T, InvalidRegexp>;
+
+/// The largest count allowed in `\{\}`.
+const MAX_REPEAT: u32 = 0xFFFF;
+/// The largest program a regexp can compile to.
+const MAX_PROGRAM: usize = 1 << 20;
+/// The most backtracking entries the matcher will keep before giving up.
+const MAX_BACKTRACK: usize = 1 << 24;
+
+/// Text to match against, split in two like the text around the gap of a
+/// buffer. Positions are byte offsets into the two halves joined together.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Text<'a> {
+    before: &'a str,
+    after: &'a str,
+}
+
+impl<'a> Text<'a> {
+    pub(crate) fn new(before: &'a str, after: &'a str) -> Self {
+        Self { before, after }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.before.len() + self.after.len()
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        match pos.checked_sub(self.before.len()) {
+            None => self.before[pos..].chars().next(),
+            Some(pos) => self.after.get(pos..)?.chars().next(),
+        }
+    }
+
+    /// The first occurrence of `needle` that starts at or after `start`.
+    pub(crate) fn find(&self, needle: &str, start: usize) -> Option<usize> {
+        self.find_in(needle, start, self.len(), false)
+    }
+
+    /// The last occurrence of `needle` that starts at or before `start` and
+    /// at or after `bound`.
+    pub(crate) fn rfind(&self, needle: &str, start: usize, bound: usize) -> Option<usize> {
+        let end = start.saturating_add(needle.len()).min(self.len());
+        self.find_in(needle, bound, end, true)
+    }
+
+    /// The first or last occurrence of `needle` within bytes `lo` to `hi`.
+    /// The halves are searched on their own with SIMD, and only the bytes
+    /// next to the split are copied to find an occurrence that spans it.
+    fn find_in(&self, needle: &str, lo: usize, hi: usize, last: bool) -> Option<usize> {
+        if lo > hi {
+            return None;
+        }
+        let needle = needle.as_bytes();
+        let split = self.before.len();
+        let search = |hay: &[u8], offset: usize| {
+            let found = if last {
+                memmem::rfind(hay, needle)
+            } else {
+                memmem::find(hay, needle)
+            };
+            found.map(|x| x + offset)
+        };
+        let before = || {
+            let (lo, hi) = (lo.min(split), hi.min(split));
+            search(&self.before.as_bytes()[lo..hi], lo)
+        };
+        let after = || {
+            let (lo, hi) = (lo.max(split), hi.max(split));
+            search(&self.after.as_bytes()[lo - split..hi - split], lo)
+        };
+        let across = || {
+            if !(lo < split && split < hi) || needle.len() < 2 {
+                return None;
+            }
+            let lo = lo.max(split - (needle.len() - 1).min(split));
+            let hi = hi.min(split + needle.len() - 1);
+            let mut window = self.before.as_bytes()[lo..].to_vec();
+            window.extend_from_slice(&self.after.as_bytes()[..hi - split]);
+            search(&window, lo)
+        };
+        if last {
+            after().or_else(across).or_else(before)
+        } else {
+            before().or_else(across).or_else(after)
+        }
+    }
+
+    /// The number of characters before byte offset `pos`.
+    pub(crate) fn char_count(&self, pos: usize) -> usize {
+        match pos.checked_sub(self.before.len()) {
+            None => self.before[..pos].chars().count(),
+            Some(pos) => self.before.chars().count() + self.after[..pos].chars().count(),
+        }
+    }
+
+    pub(crate) fn char_before(&self, pos: usize) -> Option<char> {
+        match pos.checked_sub(self.before.len()) {
+            Some(pos @ 1..) => self.after[..pos].chars().next_back(),
+            _ => self.before[..pos].chars().next_back(),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Text<'a> {
+    fn from(text: &'a str) -> Self {
+        Self::new(text, "")
+    }
+}
+
+/// Zero-width assertions about the text around a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assert {
+    /// `^`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `` \` ``
+    TextStart,
+    /// `\'`
+    TextEnd,
+    /// `\=`
+    Point,
+    /// `\b`
+    WordBoundary,
+    /// `\B`
+    NotWordBoundary,
+    /// `\<`
+    WordStart,
+    /// `\>`
+    WordEnd,
+    /// `\_<`
+    SymbolStart,
+    /// `\_>`
+    SymbolEnd,
+}
+
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    /// `.`, which matches anything but a newline
+    Any,
+    Set(CharSet),
+    /// `\sC`, or `\SC` when negated
+    Syntax(Syntax, bool),
+    /// `\cC`, or `\CC` when negated
+    Category(u8, bool),
+    Assert(Assert),
+    Backref(usize),
+    /// A group and its number, which shy groups don't have
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The highest group number used so far
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn at(&self, text: &str) -> bool {
+        let mut chars = self.chars[self.pos..].iter();
+        text.chars().all(|x| chars.next() == Some(&x))
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.at(text);
+        if found {
+            self.pos += text.chars().count();
+        }
+        found
+    }
+
+    fn parse(&mut self) -> Result<Node> {
+        let node = self.parse_alt()?;
+        if self.pos < self.chars.len() {
+            // only an unmatched `\)` stops the top level early
+            return Err(InvalidRegexp("Unmatched ) or \\)"));
+        }
+        Ok(node)
+    }
+
+    fn parse_alt(&mut self) -> Result<Node> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat("\\|") {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.pop().unwrap(),
+            _ => Node::Alt(branches),
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node> {
+        let mut items = Vec::new();
+        while let Some(chr) = self.peek() {
+            if self.at("\\|") || self.at("\\)") {
+                break;
+            }
+            self.pos += 1;
+            // operators at the start of a branch are ordinary characters
+            let starts_branch =
+                matches!(items.last(), None | Some(Node::Assert(Assert::LineStart)));
+            let node = match chr {
+                '^' if items.is_empty() => Node::Assert(Assert::LineStart),
+                '$' if self.peek().is_none() || self.at("\\|") || self.at("\\)") => {
+                    Node::Assert(Assert::LineEnd)
+                }
+                '*' | '+' | '?' if !starts_branch => {
+                    let (min, max) = match chr {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    };
+                    let greedy = !self.eat("?");
+                    let node = Box::new(items.pop().unwrap());
+                    items.push(Node::Repeat {
+                        node,
+                        min,
+                        max,
+                        greedy,
+                    });
+                    continue;
+                }
+                '.' => Node::Any,
+                '[' => Node::Set(self.parse_set()?),
+                '\\' => match self.parse_escape()? {
+                    Some(node) => node,
+                    None => {
+                        if starts_branch {
+                            return Err(InvalidRegexp("Invalid preceding regular expression"));
+                        }
+                        let (min, max) = self.parse_interval()?;
+                        let node = Box::new(items.pop().unwrap());
+                        items.push(Node::Repeat {
+                            node,
+                            min,
+                            max,
+                            greedy: true,
+                        });
+                        continue;
+                    }
+                },
+                chr => Node::Char(chr),
+            };
+            items.push(node);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    /// Parse the escape after a backslash. Returns `None` for the start of
+    /// an interval, which applies to the item before it.
+    fn parse_escape(&mut self) -> Result<Option<Node>> {
+        let Some(chr) = self.peek() else {return Err(InvalidRegexp("Trailing backslash"))};
+        self.pos += 1;
+        let node = match chr {
+            '(' => self.parse_group()?,
+            '{' => return Ok(None),
+            '1'..='9' => {
+                let group = chr as usize - '0' as usize;
+                if group > self.groups {
+                    return Err(InvalidRegexp("Invalid back reference"));
+                }
+                Node::Backref(group)
+            }
+            'w' => Node::Syntax(Syntax::Word, false),
+            'W' => Node::Syntax(Syntax::Word, true),
+            's' | 'S' => {
+                let class = self.peek().and_then(Syntax::from_char);
+                let Some(class) = class else {return Err(InvalidRegexp("Invalid syntax designator"))};
+                self.pos += 1;
+                Node::Syntax(class, chr == 'S')
+            }
+            'c' | 'C' => {
+                let category = self.peek().filter(|x| (' '..='~').contains(x));
+                let Some(category) = category else {return Err(InvalidRegexp("Invalid category designator"))};
+                self.pos += 1;
+                Node::Category(category as u8, chr == 'C')
+            }
+            '`' => Node::Assert(Assert::TextStart),
+            '\'' => Node::Assert(Assert::TextEnd),
+            '=' => Node::Assert(Assert::Point),
+            'b' => Node::Assert(Assert::WordBoundary),
+            'B' => Node::Assert(Assert::NotWordBoundary),
+            '<' => Node::Assert(Assert::WordStart),
+            '>' => Node::Assert(Assert::WordEnd),
+            '_' if self.eat("<") => Node::Assert(Assert::SymbolStart),
+            '_' if self.eat(">") => Node::Assert(Assert::SymbolEnd),
+            '_' => return Err(InvalidRegexp("Invalid \\_ construct")),
+            chr => Node::Char(chr),
+        };
+        Ok(Some(node))
+    }
+
+    /// Parse a group after its `\(`, which may be shy (`\(?:`) or explicitly
+    /// numbered (`\(?3:`).
+    fn parse_group(&mut self) -> Result<Node> {
+        let number = if self.eat("?") {
+            let digits = self.parse_number();
+            if !self.eat(":") {
+                return Err(InvalidRegexp("Invalid \\(? construct"));
+            }
+            match digits {
+                None => None,
+                Some(0) => return Err(InvalidRegexp("Invalid \\(? construct")),
+                Some(number) => Some(number as usize),
+            }
+        } else {
+            // implicitly numbered groups come after every earlier group
+            Some(self.groups + 1)
+        };
+        if let Some(number) = number {
+            self.groups = self.groups.max(number);
+        }
+        let node = self.parse_alt()?;
+        if !self.eat("\\)") {
+            return Err(InvalidRegexp("Unmatched ( or \\("));
+        }
+        Ok(Node::Group(number, Box::new(node)))
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let mut number: Option<u32> = None;
+        while let Some(digit) = self.peek().and_then(|x| x.to_digit(10)) {
+            self.pos += 1;
+            let value = number.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+            number = Some(value);
+        }
+        number
+    }
+
+    /// Parse the counts of an interval after its `\{`.
+    fn parse_interval(&mut self) -> Result<(u32, Option<u32>)> {
+        let min = self.parse_number();
+        let max = if self.eat(",") {
+            self.parse_number()
+        } else {
+            Some(min.unwrap_or(0))
+        };
+        if !self.eat("\\}") {
+            return Err(InvalidRegexp("Unmatched \\{"));
+        }
+        let min = min.unwrap_or(0);
+        match max {
+            Some(max) if max < min || max > MAX_REPEAT => {
+                Err(InvalidRegexp("Invalid content of \\{\\}"))
+            }
+            _ if min > MAX_REPEAT => Err(InvalidRegexp("Invalid content of \\{\\}")),
+            max => Ok((min, max)),
+        }
+    }
+
+    /// Parse a bracket expression after its `[`. Backslashes are ordinary
+    /// characters, a `]` right after the opening bracket is part of the set,
+    /// and `[:class:]` names a character class.
+    fn parse_set(&mut self) -> Result<CharSet> {
+        const UNMATCHED: InvalidRegexp = InvalidRegexp("Unmatched [ or [^");
+        let mut set = CharSet {
+            negated: self.eat("^"),
+            ..CharSet::default()
+        };
+        let mut first = true;
+        loop {
+            let chr = self.peek().ok_or(UNMATCHED)?;
+            self.pos += 1;
+            if chr == ']' && !first {
+                return Ok(set);
+            }
+            first = false;
+            if chr == '[' && self.peek() == Some(':') {
+                let rest: String = self.chars[self.pos + 1..].iter().collect();
+                if let Some(len) = rest.find(":]") {
+                    let name = &rest[..len];
+                    let class = CharClass::from_name(name)
+                        .ok_or(InvalidRegexp("Invalid character class name"))?;
+                    set.classes.push(class);
+                    self.pos += 1 + name.chars().count() + 2;
+                    continue;
+                }
+            }
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&x| x != ']');
+            if is_range {
+                let end = self.chars[self.pos + 1];
+                self.pos += 2;
+                // a reversed range is empty
+                if chr <= end {
+                    set.ranges.push((chr, end));
+                }
+            } else {
+                set.ranges.push((chr, chr));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Set(CharSet),
+    Syntax(Syntax, bool),
+    Category(u8, bool),
+    Assert(Assert),
+    Backref(usize),
+    /// Record the position in a capture slot
+    Save(usize),
+    /// Try the first branch, and the second if it fails
+    Split(usize, usize),
+    Jmp(usize),
+    /// Record the position in a register
+    Mark(usize),
+    /// Fail unless the position moved past the one in a register, so loops
+    /// over patterns that match the empty string end
+    Progress(usize),
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    registers: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize> {
+        if self.prog.len() >= MAX_PROGRAM {
+            return Err(InvalidRegexp("Regular expression too big"));
+        }
+        self.prog.push(inst);
+        Ok(self.prog.len() - 1)
+    }
+
+    fn patch(&mut self, split: usize, target: usize) {
+        if let Inst::Split(_, second) | Inst::Jmp(second) = &mut self.prog[split] {
+            *second = target;
+        }
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<()> {
+        match node {
+            Node::Empty => {}
+            Node::Char(chr) => {
+                self.emit(Inst::Char(*chr))?;
+            }
+            Node::Any => {
+                self.emit(Inst::Any)?;
+            }
+            Node::Set(set) => {
+                self.emit(Inst::Set(set.clone()))?;
+            }
+            Node::Syntax(class, negated) => {
+                self.emit(Inst::Syntax(*class, *negated))?;
+            }
+            Node::Category(category, negated) => {
+                self.emit(Inst::Category(*category, *negated))?;
+            }
+            Node::Assert(assert) => {
+                self.emit(Inst::Assert(*assert))?;
+            }
+            Node::Backref(group) => {
+                self.emit(Inst::Backref(*group))?;
+            }
+            Node::Group(Some(group), node) => {
+                self.emit(Inst::Save(group * 2))?;
+                self.compile(node)?;
+                self.emit(Inst::Save(group * 2 + 1))?;
+            }
+            Node::Group(None, node) => self.compile(node)?,
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alt(branches) => {
+                let mut jumps = Vec::new();
+                let (last, rest) = branches.split_last().unwrap();
+                for branch in rest {
+                    let split = self.emit(Inst::Split(self.prog.len() + 1, 0))?;
+                    self.compile(branch)?;
+                    jumps.push(self.emit(Inst::Jmp(0))?);
+                    self.patch(split, self.prog.len());
+                }
+                self.compile(last)?;
+                for jump in jumps {
+                    self.patch(jump, self.prog.len());
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(self.prog.len() + 1, 0))?);
+                            self.compile(node)?;
+                        }
+                        for split in splits {
+                            self.patch(split, self.prog.len());
+                            if !greedy {
+                                self.swap_split(split);
+                            }
+                        }
+                    }
+                    None => {
+                        let register = self.registers;
+                        self.registers += 1;
+                        let split = self.emit(Inst::Split(self.prog.len() + 1, 0))?;
+                        self.emit(Inst::Mark(register))?;
+                        self.compile(node)?;
+                        self.emit(Inst::Progress(register))?;
+                        self.emit(Inst::Jmp(split))?;
+                        self.patch(split, self.prog.len());
+                        if !greedy {
+                            self.swap_split(split);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn swap_split(&mut self, split: usize) {
+        if let Inst::Split(first, second) = &mut self.prog[split] {
+            std::mem::swap(first, second);
+        }
+    }
+}
+
+/// A compiled Emacs regexp.
+#[derive(Debug)]
+pub(crate) struct Regex {
+    prog: Vec<Inst>,
+    /// The number of groups, not counting the whole match
+    groups: usize,
+    registers: usize,
+    case_fold: bool,
+    /// The case conversions used when case is ignored
+    case: Rc<CaseTable>,
+    /// The syntax classes used by `\\w`, `\\s` and the word and symbol
+    /// assertions
+    syntax: Rc<SyntaxTable>,
+    /// The categories used by `\\c`
+    categories: Rc<CategoryTable>,
+}
+
+/// The positions of a match and its groups, as byte offsets into the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Captures(Vec<Option<usize>>);
+
+impl Captures {
+    /// The captures of a match from `beg` to `end` with no groups.
+    pub(crate) fn span(beg: usize, end: usize) -> Self {
+        Self(vec![Some(beg), Some(end)])
+    }
+
+    /// The bounds of group `n`, where group 0 is the whole match, or `None`
+    /// if it did not match.
+    pub(crate) fn get(&self, n: usize) -> Option<(usize, usize)> {
+        Some((
+            self.0.get(n * 2).copied()??,
+            self.0.get(n * 2 + 1).copied()??,
+        ))
+    }
+
+    /// The number of groups, counting the whole match.
+    pub(crate) fn len(&self) -> usize {
+        self.0.len() / 2
+    }
+}
+
+impl Regex {
+    /// Compile the Emacs regexp `pattern`. If `case_fold` is true, case is
+    /// ignored when matching.
+    pub(crate) fn new(pattern: &str, case_fold: bool) -> Result<Self> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let node = parser.parse()?;
+        let mut compiler = Compiler {
+            prog: Vec::new(),
+            registers: 0,
+        };
+        compiler.compile(&node)?;
+        compiler.emit(Inst::Match)?;
+        Ok(Self {
+            prog: compiler.prog,
+            groups: parser.groups,
+            registers: compiler.registers,
+            case_fold,
+            case: Rc::default(),
+            syntax: Rc::default(),
+            categories: Rc::default(),
+        })
+    }
+
+    /// Ignore case with the conversions of `case` instead of the simple case
+    /// mappings of Unicode.
+    pub(crate) fn with_case(self, case: Rc<CaseTable>) -> Self {
+        Self { case, ..self }
+    }
+
+    /// Use the classes of `syntax` instead of the standard syntax table.
+    pub(crate) fn with_syntax(self, syntax: Rc<SyntaxTable>) -> Self {
+        Self { syntax, ..self }
+    }
+
+    /// Use the categories of `categories`, since there are none by default.
+    pub(crate) fn with_categories(self, categories: Rc<CategoryTable>) -> Self {
+        Self { categories, ..self }
+    }
+
+    /// The first match that starts at or after `start`. `point` is the
+    /// position `\=` matches at.
+    pub(crate) fn search_forward(
+        &self,
+        text: Text,
+        start: usize,
+        point: Option<usize>,
+    ) -> anyhow::Result<Option<Captures>> {
+        let mut matcher = Matcher::new(self, text, point);
+        let first = match self.prog.first() {
+            Some(Inst::Char(chr)) => Some(self.fold(*chr)),
+            _ => None,
+        };
+        let mut pos = start;
+        loop {
+            let chr = text.char_at(pos);
+            let skip = first.is_some_and(|first| chr.map(|x| self.fold(x)) != Some(first));
+            if !skip && matcher.run(pos)? {
+                return Ok(Some(matcher.captures()));
+            }
+            match chr {
+                Some(chr) => pos += chr.len_utf8(),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// The match that starts last, at or before `start` and at or after
+    /// `bound`.
+    pub(crate) fn search_backward(
+        &self,
+        text: Text,
+        start: usize,
+        bound: usize,
+        point: Option<usize>,
+    ) -> anyhow::Result<Option<Captures>> {
+        let mut matcher = Matcher::new(self, text, point);
+        let mut pos = start;
+        loop {
+            if matcher.run(pos)? {
+                return Ok(Some(matcher.captures()));
+            }
+            match text.char_before(pos) {
+                Some(chr) if pos > bound => pos -= chr.len_utf8(),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// The match that starts exactly at `pos`.
+    pub(crate) fn match_at(
+        &self,
+        text: Text,
+        pos: usize,
+        point: Option<usize>,
+    ) -> anyhow::Result<Option<Captures>> {
+        let mut matcher = Matcher::new(self, text, point);
+        Ok(matcher.run(pos)?.then(|| matcher.captures()))
+    }
+
+    fn fold(&self, chr: char) -> char {
+        if self.case_fold {
+            self.case.downcase(chr)
+        } else {
+            chr
+        }
+    }
+}
+
+/// An entry on the backtracking stack: an alternative to resume from, or a
+/// value to restore on the way back to one.
+enum Frame {
+    Alt(usize, usize),
+    Slot(usize, Option<usize>),
+    Register(usize, usize),
+}
+
+struct Matcher<'a> {
+    regex: &'a Regex,
+    text: Text<'a>,
+    point: Option<usize>,
+    slots: Vec<Option<usize>>,
+    registers: Vec<usize>,
+    stack: Vec<Frame>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(regex: &'a Regex, text: Text<'a>, point: Option<usize>) -> Self {
+        Self {
+            regex,
+            text,
+            point,
+            slots: vec![None; (regex.groups + 1) * 2],
+            registers: vec![0; regex.registers],
+            stack: Vec::new(),
+        }
+    }
+
+    fn captures(&self) -> Captures {
+        let last = self
+            .slots
+            .chunks(2)
+            .rposition(|x| x[1].is_some())
+            .unwrap_or(0);
+        Captures(self.slots[..(last + 1) * 2].to_vec())
+    }
+
+    fn is_word(&self, chr: Option<char>) -> bool {
+        chr.is_some_and(|x| self.regex.syntax.is_word(x))
+    }
+
+    fn is_symbol(&self, chr: Option<char>) -> bool {
+        let syntax = &self.regex.syntax;
+        chr.is_some_and(|x| matches!(syntax.class(x), Syntax::Word | Syntax::Symbol))
+    }
+
+    fn assert(&self, assert: Assert, pos: usize) -> bool {
+        let text = &self.text;
+        let (before, after) = (text.char_before(pos), text.char_at(pos));
+        match assert {
+            Assert::LineStart => matches!(before, None | Some('\n')),
+            Assert::LineEnd => matches!(after, None | Some('\n')),
+            Assert::TextStart => pos == 0,
+            Assert::TextEnd => pos == text.len(),
+            Assert::Point => self.point == Some(pos),
+            // the edges of the text are always word boundaries
+            Assert::WordBoundary => {
+                before.is_none() || after.is_none() || self.is_word(before) != self.is_word(after)
+            }
+            Assert::NotWordBoundary => {
+                before.is_some() && after.is_some() && self.is_word(before) == self.is_word(after)
+            }
+            Assert::WordStart => !self.is_word(before) && self.is_word(after),
+            Assert::WordEnd => self.is_word(before) && !self.is_word(after),
+            Assert::SymbolStart => !self.is_symbol(before) && self.is_symbol(after),
+            Assert::SymbolEnd => self.is_symbol(before) && !self.is_symbol(after),
+        }
+    }
+
+    /// The position after the text of `group` if it matches at `pos`.
+    fn backref(&self, group: usize, mut pos: usize) -> Option<usize> {
+        let (mut beg, end) = (self.slots[group * 2]?, self.slots[group * 2 + 1]?);
+        while beg < end {
+            let chr = self.text.char_at(beg)?;
+            let other = self.text.char_at(pos)?;
+            if self.regex.fold(chr) != self.regex.fold(other) {
+                return None;
+            }
+            beg += chr.len_utf8();
+            pos += other.len_utf8();
+        }
+        Some(pos)
+    }
+
+    /// Whether the regexp matches at `start`, leaving the groups in the
+    /// slots.
+    fn run(&mut self, start: usize) -> anyhow::Result<bool> {
+        self.slots.fill(None);
+        self.stack.clear();
+        self.slots[0] = Some(start);
+        let regex = self.regex;
+        let (mut pc, mut pos) = (0, start);
+        loop {
+            let next = match &regex.prog[pc] {
+                Inst::Char(expect) => self
+                    .text
+                    .char_at(pos)
+                    .filter(|&x| self.regex.fold(x) == self.regex.fold(*expect))
+                    .map(|x| pos + x.len_utf8()),
+                Inst::Any => self
+                    .text
+                    .char_at(pos)
+                    .filter(|&x| x != '\n')
+                    .map(|x| pos + x.len_utf8()),
+                Inst::Set(set) => self
+                    .text
+                    .char_at(pos)
+                    .filter(|&x| {
+                        let syntax = &self.regex.syntax;
+                        let found = match self.regex.case_fold {
+                            true => {
+                                set.includes(x, syntax)
+                                    || set.includes(self.regex.case.downcase(x), syntax)
+                                    || set.includes(self.regex.case.upcase(x), syntax)
+                            }
+                            false => set.includes(x, syntax),
+                        };
+                        found != set.negated
+                    })
+                    .map(|x| pos + x.len_utf8()),
+                Inst::Syntax(class, negated) => self
+                    .text
+                    .char_at(pos)
+                    .filter(|&x| (self.regex.syntax.class(x) == *class) != *negated)
+                    .map(|x| pos + x.len_utf8()),
+                Inst::Category(category, negated) => self
+                    .text
+                    .char_at(pos)
+                    .filter(|&x| self.regex.categories.has(x, *category) != *negated)
+                    .map(|x| pos + x.len_utf8()),
+                Inst::Assert(assert) => self.assert(*assert, pos).then_some(pos),
+                Inst::Backref(group) => self.backref(*group, pos),
+                Inst::Save(slot) => {
+                    self.stack.push(Frame::Slot(*slot, self.slots[*slot]));
+                    self.slots[*slot] = Some(pos);
+                    Some(pos)
+                }
+                Inst::Split(first, second) => {
+                    if self.stack.len() >= MAX_BACKTRACK {
+                        anyhow::bail!("Stack overflow in regexp matcher");
+                    }
+                    self.stack.push(Frame::Alt(*second, pos));
+                    pc = *first;
+                    continue;
+                }
+                Inst::Jmp(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Inst::Mark(register) => {
+                    self.stack
+                        .push(Frame::Register(*register, self.registers[*register]));
+                    self.registers[*register] = pos;
+                    Some(pos)
+                }
+                Inst::Progress(register) => (pos != self.registers[*register]).then_some(pos),
+                Inst::Match => {
+                    self.slots[1] = Some(pos);
+                    return Ok(true);
+                }
+            };
+            match next {
+                Some(next) => {
+                    pos = next;
+                    pc += 1;
+                }
+                None => loop {
+                    match self.stack.pop() {
+                        Some(Frame::Alt(alt, alt_pos)) => {
+                            (pc, pos) = (alt, alt_pos);
+                            break;
+                        }
+                        Some(Frame::Slot(slot, value)) => self.slots[slot] = value,
+                        Some(Frame::Register(register, value)) => self.registers[register] = value,
+                        None => return Ok(false),
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The text of each group of the first match of `pattern` in `text`.
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let re = Regex::new(pattern, false).unwrap();
+        let captures = re.search_forward(text.into(), 0, None).unwrap()?;
+        let group = |n| captures.get(n).map(|(beg, end)| text[beg..end].to_owned());
+        Some((0..captures.len()).map(group).collect())
+    }
+
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        groups(pattern, text).map(|x| x[0].clone().unwrap())
+    }
+
+    #[test]
+    fn literals_and_operators() {
+        assert_eq!(find("b+c", "abbbcd").as_deref(), Some("bbbc"));
+        assert_eq!(find("ab*?", "abbb").as_deref(), Some("a"));
+        assert_eq!(find("a.c", "a\nc abc").as_deref(), Some("abc"));
+        assert_eq!(find("(a)", "x(a)").as_deref(), Some("(a)"));
+        assert_eq!(find("*a", "b*a").as_deref(), Some("*a"));
+        assert_eq!(find("^*", "*").as_deref(), Some("*"));
+        assert_eq!(find("a$b", "a$b").as_deref(), Some("a$b"));
+        assert_eq!(find("a\\{2,3\\}", "aaaa").as_deref(), Some("aaa"));
+        assert_eq!(find("a\\{2\\}", "aaaa").as_deref(), Some("aa"));
+        assert_eq!(find("xa??", "xaa").as_deref(), Some("x"));
+        assert_eq!(find("xa\\{,2\\}?", "xaa").as_deref(), Some("xaa"));
+        assert_eq!(find("\\(a*\\)*b", "aab").as_deref(), Some("aab"));
+        assert_eq!(find("foo\\|ba[rz]", "a baz").as_deref(), Some("baz"));
+        assert_eq!(find("[]a]+", "x]a]").as_deref(), Some("]a]"));
+        assert_eq!(find("[^[:space:]]+", "  ab c").as_deref(), Some("ab"));
+        assert_eq!(find("[a-]+", "-a-b").as_deref(), Some("-a-"));
+        assert_eq!(find("[\\]+", "a\\\\").as_deref(), Some("\\\\"));
+    }
+
+    #[test]
+    fn groups_and_backrefs() {
+        let expect = |x: &[Option<&str>]| Some(x.iter().map(|x| x.map(String::from)).collect());
+        assert_eq!(
+            groups("\\(a\\)\\(?:b\\)\\(c\\)", "abc"),
+            expect(&[Some("abc"), Some("a"), Some("c")])
+        );
+        assert_eq!(
+            groups("\\(?3:a\\)\\(b\\)", "ab"),
+            expect(&[Some("ab"), None, None, Some("a"), Some("b")])
+        );
+        assert_eq!(
+            groups("\\(x\\)\\|\\(y\\)", "y"),
+            expect(&[Some("y"), None, Some("y")])
+        );
+        assert_eq!(find("\\(a+\\)b\\1", "aabaab aba").as_deref(), Some("aabaa"));
+        assert_eq!(find("\\(?2:.\\)\\2", "abccd").as_deref(), Some("cc"));
+    }
+
+    #[test]
+    fn assertions_and_syntax() {
+        assert_eq!(find("\\<b\\w*", "ab bc").as_deref(), Some("bc"));
+        assert_eq!(find("\\w+\\>", "ab, cd").as_deref(), Some("ab"));
+        assert_eq!(find("\\_<b.\\_>", "a-bc bd").as_deref(), Some("bd"));
+        assert_eq!(find("\\s-+", "a \t b").as_deref(), Some(" \t "));
+        assert_eq!(find("\\S-+", "  ab ").as_deref(), Some("ab"));
+        assert_eq!(find("\\bc", "abc c").as_deref(), Some("c"));
+        assert_eq!(find("^b", "ab\nbc").as_deref(), Some("b"));
+        assert_eq!(find("a$", "ab\nca").as_deref(), Some("a"));
+        assert_eq!(find("\\`a", "ba"), None);
+        assert_eq!(find("a\\'", "ab\na").as_deref(), Some("a"));
+        // no character has a category without a category table
+        assert_eq!(find("\\ca", "b"), None);
+        assert_eq!(find("\\Ca", "b").as_deref(), Some("b"));
+        assert!(Regex::new("\\c", false).is_err());
+        let re = Regex::new("\\=.", false).unwrap();
+        let captures = re.search_forward("abc".into(), 0, Some(2)).unwrap();
+        assert_eq!(captures.unwrap().get(0), Some((2, 3)));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let start = |pattern, text: &str| {
+            let re = Regex::new(pattern, false).unwrap();
+            re.search_forward(text.into(), 0, None)
+                .unwrap()
+                .and_then(|x| x.get(0))
+                .map(|x| x.0)
+        };
+        assert_eq!(start("\\b", ""), Some(0));
+        assert_eq!(start("\\B", ""), None);
+        assert_eq!(start("\\b", " "), Some(0));
+        assert_eq!(start("\\B", " "), None);
+        assert_eq!(start(" \\b", "  "), Some(1));
+        assert_eq!(start("\\B", "  "), Some(1));
+        assert_eq!(start("\\B", "ab"), Some(1));
+        assert_eq!(start("b\\b", "ab"), Some(1));
+    }
+
+    #[test]
+    fn case_fold_and_gap() {
+        let re = Regex::new("b[a-c]+\\(d\\)\\1", true).unwrap();
+        let captures = re.search_forward("aBCad".into(), 0, None).unwrap();
+        assert_eq!(captures, None);
+        let captures = re.search_forward("aBCAdD".into(), 0, None).unwrap();
+        assert_eq!(captures.unwrap().get(0), Some((1, 6)));
+        // matches span the two halves of the text
+        let re = Regex::new("o b\\w+", false).unwrap();
+        let text = Text::new("foo b", "ar baz");
+        let captures = re.search_forward(text, 0, None).unwrap();
+        assert_eq!(captures.unwrap().get(0), Some((2, 7)));
+        let captures = re.search_backward(text, 8, 0, None).unwrap();
+        assert_eq!(captures.unwrap().get(0), Some((2, 7)));
+        assert_eq!(re.match_at(text, 1, None).unwrap(), None);
+    }
+
+    #[test]
+    fn substring_search() {
+        let text = Text::new("abcab", "cabc");
+        assert_eq!(text.find("abc", 0), Some(0));
+        // across the gap, and then after it
+        assert_eq!(text.find("abc", 1), Some(3));
+        assert_eq!(text.find("abc", 4), Some(6));
+        assert_eq!(text.find("abc", 7), None);
+        assert_eq!(text.find("bca", 0), Some(1));
+        assert_eq!(text.find("", 5), Some(5));
+        assert_eq!(text.rfind("abc", 9, 0), Some(6));
+        assert_eq!(text.rfind("abc", 5, 0), Some(3));
+        assert_eq!(text.rfind("abc", 5, 4), None);
+        assert_eq!(text.rfind("ca", 9, 0), Some(5));
+        assert_eq!(text.rfind("bcabc", 9, 0), Some(4));
+        let text = Text::new("aé", "éb");
+        assert_eq!(text.find("éé", 0), Some(1));
+        assert_eq!(text.rfind("é", 6, 0), Some(3));
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |x| Regex::new(x, false).unwrap_err().0;
+        assert_eq!(error("\\(a"), "Unmatched ( or \\(");
+        assert_eq!(error("a\\)"), "Unmatched ) or \\)");
+        assert_eq!(error("[a"), "Unmatched [ or [^");
+        assert_eq!(error("\\1"), "Invalid back reference");
+        assert_eq!(error("a\\{3,2\\}"), "Invalid content of \\{\\}");
+        assert_eq!(error("[[:foo:]]"), "Invalid character class name");
+        assert_eq!(error("\\(?x:a\\)"), "Invalid \\(? construct");
+    }
+}
@@ -0,0 +1,277 @@
+#![cfg(feature = "regex")]
+//! Building regexps that match any of a set of strings.
+//!
+//! The strings are sorted and treated as a trie: a prefix or suffix shared by
+//! all of them is factored out, single characters become a character set, and
+//! the rest is split on the first character. The result matches the longest
+//! string where several could match, like the `regexp-opt` of Emacs.
+use crate::core::{
+    env::sym,
+    object::{Gc, GcObj, List, Object},
+};
+use crate::search::regexp_quote;
+use anyhow::{bail, Result};
+use fn_macros::defun;
+
+/// A regexp that never matches.
+const UNMATCHABLE: &str = "\\`a\\`";
+
+/// Return a regexp that matches any of STRINGS, preferring the longest. If
+/// PAREN is a string, it opens the group around the regexp; if it is `words`
+/// or `symbols`, the strings only match as whole words or symbols; any other
+/// non-nil value makes the regexp a numbered group. With nil, the regexp is
+/// grouped only where needed so a postfix operator applies to all of it. If
+/// KEEP-ORDER is non-nil, the string that matches is the first of STRINGS
+/// that does, instead of the longest.
+#[defun]
+fn regexp_opt(strings: Gc<List>, paren: Option<GcObj>, keep_order: Option<()>) -> Result<String> {
+    let list = strings;
+    let mut strings: Vec<&str> = Vec::new();
+    for string in list.elements() {
+        strings.push(string?.try_into()?);
+    }
+    let open = match paren.map(Gc::untag) {
+        None => None,
+        Some(Object::String(open)) => Some(<&str>::try_from(open)?),
+        Some(_) => Some("\\("),
+    };
+    let regexp = if strings.is_empty() {
+        format!("{}{UNMATCHABLE}\\)", open.unwrap_or("\\(?:"))
+    } else if keep_order.is_some() && !is_prefix_free(&strings) {
+        let alternatives: Vec<_> = strings.iter().map(|x| regexp_quote(x)).collect();
+        format!("{}{}\\)", open.unwrap_or("\\(?:"), alternatives.join("\\|"))
+    } else {
+        strings.sort_unstable();
+        strings.dedup();
+        let group = match open {
+            Some(open) => Group::Open(open),
+            None => Group::Shy,
+        };
+        opt_group(&strings, group, open.is_none())
+    };
+    Ok(match paren.map(Gc::untag) {
+        Some(Object::Symbol(sym::WORDS)) => format!("\\<{regexp}\\>"),
+        Some(Object::Symbol(sym::SYMBOLS)) => format!("\\_<{regexp}\\_>"),
+        _ => regexp,
+    })
+}
+
+/// Whether no string comes after one of its proper prefixes, so that trying
+/// the strings in order finds the same match as trying the longest first.
+fn is_prefix_free(strings: &[&str]) -> bool {
+    strings.iter().enumerate().all(|(idx, string)| {
+        strings[..idx]
+            .iter()
+            .all(|x| x == string || !string.starts_with(x))
+    })
+}
+
+/// How a part of the regexp is grouped.
+#[derive(Debug, Clone, Copy)]
+enum Group<'a> {
+    None,
+    Shy,
+    Open(&'a str),
+}
+
+impl Group<'_> {
+    fn wrap(self, regexp: &str) -> String {
+        match self {
+            Group::None => regexp.to_owned(),
+            Group::Shy => format!("\\(?:{regexp}\\)"),
+            Group::Open(open) => format!("{open}{regexp}\\)"),
+        }
+    }
+}
+
+/// The regexp for the sorted and distinct `strings`. A single character or a
+/// character set needs no group around it if `lax` is true.
+fn opt_group(strings: &[&str], group: Group, lax: bool) -> String {
+    let charset_group = if lax { Group::None } else { group };
+    let (first, rest) = match strings {
+        [] => return String::new(),
+        [string] if string.chars().count() == 1 => {
+            return charset_group.wrap(&regexp_quote(string));
+        }
+        [string] => return group.wrap(&regexp_quote(string)),
+        [first, rest @ ..] => (first, rest),
+    };
+    // the empty string sorts first, and makes the rest optional
+    if first.is_empty() {
+        let rest = opt_group(rest, Group::Shy, true);
+        return charset_group.wrap(&format!("{rest}?"));
+    }
+    if strings.iter().all(|x| x.chars().count() == 1) {
+        let chars: Vec<char> = strings.iter().filter_map(|x| x.chars().next()).collect();
+        return charset_group.wrap(&charset(&chars));
+    }
+    let prefix = common_prefix(first, strings[strings.len() - 1]);
+    if !prefix.is_empty() {
+        let suffixes: Vec<&str> = strings.iter().map(|x| &x[prefix.len()..]).collect();
+        let rest = opt_group(&suffixes, Group::Shy, true);
+        return group.wrap(&format!("{}{rest}", regexp_quote(prefix)));
+    }
+    let suffix = strings
+        .iter()
+        .skip(1)
+        .fold(*first, |x, y| common_suffix(x, y));
+    if !suffix.is_empty() {
+        let mut prefixes: Vec<&str> = strings
+            .iter()
+            .map(|x| &x[..x.len() - suffix.len()])
+            .collect();
+        prefixes.sort_unstable();
+        let rest = opt_group(&prefixes, Group::Shy, true);
+        return group.wrap(&format!("{rest}{}", regexp_quote(suffix)));
+    }
+    // split on the first character, which starts a contiguous run
+    let initial = first.chars().next();
+    let split = strings.partition_point(|x| x.chars().next() == initial);
+    let (half1, half2) = strings.split_at(split);
+    let half1 = opt_group(half1, Group::None, false);
+    let half2 = opt_group(half2, Group::None, false);
+    group.wrap(&format!("{half1}\\|{half2}"))
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or_else(|| a.len().min(b.len()), |((idx, _), _)| idx);
+    &a[..len]
+}
+
+fn common_suffix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len: usize = a
+        .chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    &a[a.len() - len..]
+}
+
+/// A bracket expression for `chars`, using ranges for runs of three or more.
+fn charset(chars: &[char]) -> String {
+    let mut chars = chars.to_vec();
+    chars.sort_unstable();
+    chars.dedup();
+    // `]` must come first, `^` must not, and `-` must be last
+    let special = |x: char| chars.contains(&x).then_some(x);
+    let (bracket, caret, dash) = (special(']'), special('^'), special('-'));
+    let mut set = String::new();
+    let mut runs = chars
+        .iter()
+        .copied()
+        .filter(|x| !"]^-".contains(*x))
+        .peekable();
+    while let Some(start) = runs.next() {
+        let mut end = start;
+        while runs
+            .peek()
+            .is_some_and(|x| Some(*x) == char::from_u32(end as u32 + 1))
+        {
+            end = runs.next().unwrap();
+        }
+        match end as u32 - start as u32 {
+            0 => set.push(start),
+            1 => set.extend([start, end]),
+            _ => set.extend([start, '-', end]),
+        }
+    }
+    let opt = |x: Option<char>| String::from_iter(x);
+    match (bracket, set.is_empty(), caret, dash) {
+        (None, true, None, None) => UNMATCHABLE.to_owned(),
+        (None, true, Some(_), None) => "\\^".to_owned(),
+        // a lone dash comes first, so the caret does not negate the set
+        (None, true, caret, Some(_)) => format!("[-{}]", opt(caret)),
+        _ => format!("[{}{set}{}{}]", opt(bracket), opt(caret), opt(dash)),
+    }
+}
+
+/// Return a regexp that matches any of the characters CHARS.
+#[defun]
+fn regexp_opt_charset(chars: Gc<List>) -> Result<String> {
+    let mut set = Vec::new();
+    for chr in chars.elements() {
+        let chr: i64 = chr?.try_into()?;
+        let Some(chr) = u32::try_from(chr).ok().and_then(char::from_u32) else {bail!("Invalid character: {chr}")};
+        set.push(chr);
+    }
+    Ok(charset(&set))
+}
+
+defsym!(WORDS);
+defsym!(SYMBOLS);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::Env;
+    use crate::core::gc::{Context, RootSet};
+    use crate::interpreter::check;
+    use crate::root;
+
+    fn opt(strings: &[&str]) -> String {
+        let mut strings = strings.to_vec();
+        strings.sort_unstable();
+        opt_group(&strings, Group::Shy, true)
+    }
+
+    #[test]
+    fn test_regexp_opt() {
+        assert_eq!(opt(&["cat", "dog"]), "\\(?:cat\\|dog\\)");
+        assert_eq!(opt(&["a", "b", "c", "x"]), "[a-cx]");
+        assert_eq!(opt(&["foo", "foobar"]), "\\(?:foo\\(?:bar\\)?\\)");
+        assert_eq!(opt(&["ad", "d"]), "\\(?:a?d\\)");
+        assert_eq!(
+            opt(&["if", "else", "elif"]),
+            "\\(?:el\\(?:if\\|se\\)\\|if\\)"
+        );
+        assert_eq!(opt(&["a.b", "a*"]), "\\(?:a\\(?:\\*\\|\\.b\\)\\)");
+        assert_eq!(common_prefix("héllo", "hélp"), "hél");
+        assert_eq!(common_suffix("héllo", "jello"), "llo");
+        assert!(is_prefix_free(&["bar", "ba", "b"]));
+        assert!(!is_prefix_free(&["b", "bar"]));
+    }
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(charset(&['b', 'a']), "[ab]");
+        assert_eq!(charset(&['-', 'a', ']', '^']), "[]a^-]");
+        assert_eq!(charset(&['^']), "\\^");
+        assert_eq!(charset(&['^', '-']), "[-^]");
+        assert_eq!(charset(&[]), UNMATCHABLE);
+        assert_eq!(charset(&['x', 'y', 'z', 'w', '1']), "[1w-z]");
+    }
+
+    #[test]
+    fn test_lisp() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let cases = [
+            ("'(\"foo\" \"bar\") t", "\\(bar\\|foo\\)"),
+            ("'(\"a\" \"b\" \"a\") 'words", "\\<\\([ab]\\)\\>"),
+            ("'(\"x\") 'symbols", "\\_<\\(x\\)\\_>"),
+            ("'(\"ab\" \"cd\") \"\\\\(?1:\"", "\\(?1:ab\\|cd\\)"),
+            ("'(\"b\" \"bar\") nil t", "\\(?:b\\|bar\\)"),
+            ("'(\"bar\" \"b\") nil t", "\\(?:b\\(?:ar\\)?\\)"),
+            ("nil", "\\(?:\\`a\\`\\)"),
+        ];
+        for (args, expect) in cases {
+            let form = format!("(regexp-opt {args})");
+            assert_eq!(check(&form, env, cx), expect, "{form}");
+        }
+        // the longest string matches
+        let form = "(list (string-match (regexp-opt '(\"for\" \"foreach\" \"if\")) \"x foreach\")
+                          (match-end 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(2 9)");
+        assert_eq!(
+            check("(regexp-opt-charset '(?c ?a ?b ?-))", env, cx),
+            "[a-c-]"
+        );
+    }
+}
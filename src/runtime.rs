@@ -0,0 +1,142 @@
+//! Owning the heap of the interpreter and tearing it down.
+//!
+//! Dropping the pieces of the interpreter in the wrong order leaves pointers
+//! into a freed heap, so [`Runtime::shutdown`] does it in a fixed order:
+//!
+//! 1. Wait for the threads started by `go`, which share the buffers.
+//! 2. Run `kill-emacs-hook`, while everything is still alive.
+//! 3. Kill the processes, so that no child is left behind unreaped.
+//! 4. Drop the contents of the [`Env`], so nothing on the heap is rooted.
+//! 5. Free the heap by dropping the [`Context`].
+//!
+//! `kill-emacs` runs the first three steps with [`clean_up`] and then exits.
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Limits, RootSet, Rt},
+    object::GcObj,
+};
+use crate::root;
+use anyhow::Result;
+
+/// The heap of an interpreter. The [`Env`] is rooted on the stack of the
+/// embedder and passed to [`Runtime::shutdown`].
+pub(crate) struct Runtime<'rt> {
+    pub(crate) cx: Context<'rt>,
+}
+
+impl<'rt> Runtime<'rt> {
+    pub(crate) fn new(roots: &'rt RootSet, limits: Limits) -> Self {
+        Self {
+            cx: Context::with_limits(roots, limits),
+        }
+    }
+
+    /// Tear down the interpreter in the order described in the module docs.
+    /// An error from `kill-emacs-hook` does not stop the teardown, and is
+    /// returned once it is done.
+    pub(crate) fn shutdown(mut self, env: &mut Rt<Env>) -> Result<()> {
+        let result = clean_up(env, &mut self.cx);
+        env.reset();
+        // dropping the context collects the heap and checks nothing is left
+        drop(self);
+        result
+    }
+}
+
+/// Wait for the threads, run `kill-emacs-hook` and kill the processes. An
+/// error from the hook is returned once the processes are killed.
+pub(crate) fn clean_up(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    crate::threads::join_threads();
+    let hook: GcObj = sym::KILL_EMACS_HOOK.into();
+    root!(hook, cx);
+    root!(args, Vec::new(), cx);
+    let result = crate::eval::run_hook_with_args(hook, args, env, cx).map(|_| ());
+    #[cfg(feature = "processes")]
+    crate::process::shutdown_processes(env, cx);
+    result
+}
+
+defvar!(KILL_EMACS_HOOK);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::check;
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_shutdown() {
+        let roots = &RootSet::default();
+        {
+            let mut runtime = Runtime::new(roots, Limits::default());
+            let cx = &mut runtime.cx;
+            root!(env, Env::default(), cx);
+            check("(setq kept (cons 1 (list 2 3)))", env, cx);
+            let hook = "(setq kill-emacs-hook
+                          (list #'(lambda ()
+                                  (set-buffer (get-buffer-create \"shutdown\"))
+                                  (insert (format \"%s\" kept)))))";
+            check(hook, env, cx);
+            runtime.shutdown(env).unwrap();
+        }
+        // the hook ran before the heap was freed
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(progn (set-buffer (get-buffer-create \"shutdown\")) (buffer-string))";
+        assert_eq!(check(form, env, cx), "(1 2 3)");
+    }
+
+    #[test]
+    fn test_shutdown_hook_error() {
+        let roots = &RootSet::default();
+        let mut runtime = Runtime::new(roots, Limits::default());
+        let cx = &mut runtime.cx;
+        root!(env, Env::default(), cx);
+        check(
+            "(setq kill-emacs-hook (list #'(lambda () (signal 'error '(\"stuck\")))))",
+            env,
+            cx,
+        );
+        check("(setq kept (make-vector 10 (list 1)))", env, cx);
+        // the heap is still freed, or dropping the context would panic
+        let err = runtime.shutdown(env).unwrap_err();
+        assert!(format!("{err:?}").contains("stuck"));
+    }
+
+    #[cfg(feature = "processes")]
+    #[test]
+    fn test_shutdown_processes() {
+        use crate::core::object::{Process, Status};
+        let roots = &RootSet::default();
+        let (killed, flagged, pid) = {
+            let mut runtime = Runtime::new(roots, Limits::default());
+            let cx = &mut runtime.cx;
+            root!(env, Env::default(), cx);
+            let form = r#"(progn
+                            (get-buffer-create "shutdown-processes")
+                            (setq killed (make-process :name "killed" :command '("/bin/sleep" "10")
+                                                       :connection-type 'pipe :noquery t
+                                                       :sentinel #'(lambda (p msg)
+                                                                     (set-buffer "shutdown-processes")
+                                                                     (insert msg))))
+                            (setq flagged (make-process :name "flagged" :command '("/bin/sleep" "10")
+                                                        :connection-type 'pipe))
+                            (list (process-query-on-exit-flag killed) (process-query-on-exit-flag flagged)
+                                  (set-process-query-on-exit-flag flagged 'yes)))"#;
+            assert_eq!(check(form, env, cx).to_string(), "(nil t yes)");
+            let killed: &'static Process = check("killed", env, cx).try_into().unwrap();
+            let flagged: &'static Process = check("flagged", env, cx).try_into().unwrap();
+            let pid = flagged.lock().child.as_ref().unwrap().id();
+            runtime.shutdown(env).unwrap();
+            (killed, flagged, i32::try_from(pid).unwrap())
+        };
+        assert_eq!(killed.lock().status, Status::Signal(9, false));
+        // the query-on-exit flag doesn't keep a process alive
+        assert_eq!(flagged.lock().status, Status::Signal(9, false));
+        assert!(crate::sysdep::send_signal(pid, 0).is_err());
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = r#"(progn (set-buffer "shutdown-processes") (buffer-string))"#;
+        assert_eq!(check(form, env, cx), "killed\n");
+    }
+}
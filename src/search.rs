@@ -1,115 +1,1289 @@
+#![cfg(feature = "regex")]
+use crate::buffer::get_current_buffer;
+use crate::casetab::{current_case, current_case_table};
+use crate::category::{category_table, current_categories};
 use crate::core::{
-    env::Env,
+    env::{sym, Env},
+    error::{EvalError, Type, TypeError},
     gc::{Context, Rt},
-    object::{nil, Gc, GcObj, List},
+    object::{nil, qtrue, Function, Gc, GcObj, LispString, List, Marker, Object, TagType},
 };
-use anyhow::{ensure, Result};
-use fancy_regex::Regex;
+use crate::editfns::buffer_substring;
+use crate::fns::slice_into_list;
+use crate::marker::set_marker;
+use crate::regex::{Captures, Regex, Text};
+use crate::root;
+use crate::syntax::{current_syntax, SyntaxTable};
+use anyhow::{bail, Result};
 use fn_macros::defun;
+use std::cell::RefCell;
+use std::rc::Rc;
 
+/// The most compiled regexps kept for reuse, as in Emacs.
+const REGEXP_CACHE_SIZE: usize = 20;
+
+/// What a compiled regexp depends on besides its pattern.
+#[derive(Debug, PartialEq, Eq)]
+struct CacheKey {
+    pattern: String,
+    case_fold: bool,
+    /// The address of the copy of the case table, which the regexp keeps
+    /// alive
+    case_table: usize,
+    /// The address of the copy of the syntax table
+    syntax_table: usize,
+    /// The address of the copy of the category table
+    category_table: usize,
+}
+
+thread_local! {
+    /// Recently compiled regexps, from the least to the most recently used.
+    static REGEXP_CACHE: RefCell<Vec<(CacheKey, Rc<Regex>)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn case_fold_search(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars
+        .get(sym::CASE_FOLD_SEARCH)
+        .is_some_and(|x| !x.bind(cx).nil())
+}
+
+/// Compile REGEXP, ignoring case if `case-fold-search` is non-nil. An invalid
+/// regexp signals `invalid-regexp`. Recently used regexps are not compiled
+/// again.
+pub(crate) fn compile_regexp(regexp: &str, env: &mut Rt<Env>, cx: &Context) -> Result<Rc<Regex>> {
+    let case_fold = case_fold_search(env, cx);
+    compile_regexp_with(regexp, case_fold, env, cx)
+}
+
+/// Compile REGEXP like [`compile_regexp`], ignoring case only if `case_fold`
+/// is true.
+pub(crate) fn compile_regexp_with(
+    regexp: &str,
+    case_fold: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Rc<Regex>> {
+    // making a standard table counts as a change to the char-tables, so the
+    // tables are all made before any is copied
+    current_case_table(env, cx);
+    category_table(env, cx);
+    let syntax = current_syntax(env, cx);
+    let case = current_case(env, cx);
+    let categories = current_categories(env, cx);
+    let key = CacheKey {
+        pattern: regexp.to_owned(),
+        case_fold,
+        case_table: Rc::as_ptr(&case) as usize,
+        syntax_table: Rc::as_ptr(&syntax) as usize,
+        category_table: Rc::as_ptr(&categories) as usize,
+    };
+    let cached = REGEXP_CACHE.with_borrow_mut(|cache| {
+        let idx = cache.iter().position(|x| x.0 == key)?;
+        cache[idx..].rotate_left(1);
+        cache.last().map(|x| x.1.clone())
+    });
+    if let Some(re) = cached {
+        return Ok(re);
+    }
+    let re = Regex::new(regexp, case_fold).map_err(|e| {
+        let data = list![e.0; cx];
+        EvalError::signal(sym::INVALID_REGEXP.into(), data, env)
+    })?;
+    let re = Rc::new(
+        re.with_case(case)
+            .with_syntax(syntax)
+            .with_categories(categories),
+    );
+    REGEXP_CACHE.with_borrow_mut(|cache| {
+        if cache.len() == REGEXP_CACHE_SIZE {
+            cache.remove(0);
+        }
+        cache.push((key, re.clone()));
+    });
+    Ok(re)
+}
+
+/// The index of the first match of REGEXP in STRING, starting the search at
+/// START. Sets the match data unless INHIBIT-MODIFY is non-nil.
 #[defun]
 fn string_match<'ob>(
     regexp: &str,
     string: &str,
     start: Option<i64>,
+    inhibit_modify: Option<()>,
     env: &mut Rt<Env>,
     cx: &'ob Context,
 ) -> Result<GcObj<'ob>> {
-    let re = Regex::new(&lisp_regex_to_rust(regexp))?;
-
-    let start = start.unwrap_or(0) as usize;
-    if let Some(matches) = re.captures_iter(&string[start..]).next() {
-        let mut all: Vec<GcObj> = Vec::new();
-        let all_matches = matches?;
-        let mut groups = all_matches.iter();
-        while let Some(Some(group)) = groups.next() {
-            all.push(group.start().into());
-            all.push(group.end().into());
-        }
-        let match_data = crate::fns::slice_into_list(&all, None, cx);
-        env.match_data.set(match_data);
-        Ok(match_data.as_cons().car())
-    } else {
-        Ok(nil())
+    let re = compile_regexp(regexp, env, cx)?;
+    let len = string.chars().count() as i64;
+    let start = match start.unwrap_or(0) {
+        x if x < 0 => x + len,
+        x => x,
+    };
+    if !(0..=len).contains(&start) {
+        let data = list![string, start; cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    }
+    let start = string
+        .char_indices()
+        .nth(start as usize)
+        .map_or(string.len(), |x| x.0);
+    let Some(captures) = re.search_forward(string.into(), start, None)? else {return Ok(nil())};
+    let to_char = |pos: usize| string[..pos].chars().count();
+    let beg = captures.get(0).map(|x| to_char(x.0));
+    if inhibit_modify.is_none() {
+        record_match(&captures, to_char, qtrue(), env, cx);
+    }
+    Ok(beg.into())
+}
+
+/// Like `string-match`, but the match data is not changed.
+#[defun]
+fn string_match_p<'ob>(
+    regexp: &str,
+    string: &str,
+    start: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    string_match(regexp, string, start, Some(()), env, cx)
+}
+
+/// Set the match data to the groups of a match, using `to_pos` to turn byte
+/// offsets into positions. OBJECT is the buffer that was searched, or t for a
+/// string.
+pub(crate) fn record_match(
+    captures: &Captures,
+    to_pos: impl Fn(usize) -> usize,
+    object: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    let mut positions: Vec<GcObj> = Vec::new();
+    for group in 0..captures.len() {
+        let (beg, end) = match captures.get(group) {
+            Some((beg, end)) => (to_pos(beg).into(), to_pos(end).into()),
+            None => (nil(), nil()),
+        };
+        positions.extend([beg, end]);
+    }
+    // groups that did not match at the end are left out
+    while positions.last().is_some_and(|x| *x == nil()) {
+        positions.truncate(positions.len() - 2);
+    }
+    env.match_data.set(slice_into_list(&positions, None, cx));
+    env.match_object.set(object);
+}
+
+/// Position `n` of the match data, counting the start and end of each group.
+fn match_position(n: i64, env: &mut Rt<Env>, cx: &Context) -> Result<Option<usize>> {
+    if n < 0 {
+        let data = list![n; cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    }
+    let data = env.match_data.bind(cx);
+    match data.as_list()?.nth(n as usize) {
+        Some(pos) => Ok(pos?.try_into()?),
+        None => Ok(None),
     }
 }
 
-// Invert the escaping of parens. i.e. \( => ( and ( => \(
-fn lisp_regex_to_rust(regexp: &str) -> String {
-    let mut norm_regex = String::new();
-    let mut chars = regexp.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '(' => norm_regex.push_str("\\("),
-            ')' => norm_regex.push_str("\\)"),
-            '\\' if matches!(chars.peek(), Some('(' | ')')) => {
-                norm_regex.push(chars.next().unwrap());
+/// The match data as a list of the start and end of each group. Unless
+/// INTEGERS is non-nil, positions in a buffer are markers. With INTEGERS,
+/// the buffer that was searched is added at the end. If REUSE is a list, it
+/// is modified to hold the data and returned, and if RESEAT is also non-nil,
+/// the markers in it first point nowhere.
+#[defun]
+pub(crate) fn match_data<'ob>(
+    integers: Option<()>,
+    reuse: Option<GcObj<'ob>>,
+    reseat: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let buffer = match env.match_object.bind(cx).untag() {
+        Object::Buffer(buffer) if buffer.is_live() => Some(buffer),
+        _ => None,
+    };
+    let mut items: Vec<GcObj> = Vec::new();
+    for pos in env.match_data.bind(cx).as_list()? {
+        let pos = pos?;
+        match (buffer, pos.untag()) {
+            (Some(buffer), Object::Int(pos)) if integers.is_none() => {
+                let marker = set_marker(Marker::create(), Some(pos as usize), Some(buffer), env)?;
+                items.push(marker.tag().into());
             }
-            c => norm_regex.push(c),
+            _ => items.push(pos),
         }
     }
-    norm_regex
+    if let (Some(()), Some(buffer)) = (integers, buffer) {
+        items.push(buffer.tag().into());
+    }
+    let Some(reuse) = reuse.filter(|x| matches!(x.untag(), Object::Cons(_))) else {return Ok(slice_into_list(&items, None, cx))};
+    if reseat.is_some() {
+        evaporate_markers(reuse, env)?;
+    }
+    let mut items = items.into_iter();
+    let mut tail = reuse.as_cons();
+    loop {
+        tail.set_car(items.next().unwrap_or_else(nil))?;
+        match tail.cdr().untag() {
+            Object::Cons(next) => tail = next,
+            _ => break,
+        }
+    }
+    let rest: Vec<_> = items.collect();
+    if !rest.is_empty() {
+        tail.set_cdr(slice_into_list(&rest, None, cx))?;
+    }
+    Ok(reuse)
 }
+
+/// Make the markers in LIST point nowhere.
+fn evaporate_markers(list: GcObj, env: &mut Rt<Env>) -> Result<()> {
+    for elem in list.as_list()? {
+        if let Object::Marker(marker) = elem?.untag() {
+            set_marker(marker, None, None, env)?;
+        }
+    }
+    Ok(())
+}
+
+/// Set the match data from LIST, which is in the form returned by
+/// `match-data`. If RESEAT is non-nil, the markers in LIST point nowhere
+/// afterwards.
 #[defun]
-fn match_data<'ob>(
-    integer: Option<()>,
-    reuse: Option<()>,
+pub(crate) fn set_match_data<'ob>(
+    list: Gc<List>,
     reseat: Option<()>,
-    env: &Rt<Env>,
+    env: &mut Rt<Env>,
     cx: &'ob Context,
 ) -> Result<GcObj<'ob>> {
-    ensure!(
-        integer.is_none(),
-        "match-data integer field is not implemented"
-    );
-    ensure!(reuse.is_none(), "match-data reuse field is not implemented");
-    ensure!(
-        reseat.is_none(),
-        "match-data reseat field is not implemented"
-    );
-    Ok(env.match_data.bind(cx))
+    let mut positions: Vec<GcObj> = Vec::new();
+    let mut object: GcObj = qtrue();
+    for elem in list.elements() {
+        let elem = elem?;
+        match elem.untag() {
+            Object::Marker(marker) => match marker.position() {
+                Some((buffer, pos)) => {
+                    object = buffer.tag().into();
+                    positions.push((pos + 1).into());
+                }
+                None => positions.push(0.into()),
+            },
+            Object::Int(_) | Object::NIL => positions.push(elem),
+            Object::Buffer(_) => {
+                object = elem;
+                break;
+            }
+            _ => bail!(TypeError::new(Type::Int, elem)),
+        }
+    }
+    // a start without an end is ignored
+    positions.truncate(positions.len() / 2 * 2);
+    env.match_data.set(slice_into_list(&positions, None, cx));
+    env.match_object.set(object);
+    if reseat.is_some() {
+        evaporate_markers(list.into(), env)?;
+    }
+    Ok(nil())
+}
+
+/// The start of the text matched by group SUBEXP of the last search, or nil
+/// if it did not match. Group 0 is the whole match.
+#[defun]
+fn match_beginning<'ob>(subexp: i64, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    Ok(match_position(subexp.saturating_mul(2), env, cx)?.into())
+}
+
+/// The end of the text matched by group SUBEXP of the last search, or nil if
+/// it did not match.
+#[defun]
+fn match_end<'ob>(subexp: i64, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    Ok(match_position(subexp.saturating_mul(2).saturating_add(1), env, cx)?.into())
+}
+
+/// The text matched by group NUM of the last search, or nil if it did not
+/// match. If the search was in a string, STRING must be that string;
+/// otherwise the text is taken from the current buffer.
+#[defun]
+fn match_string<'ob>(
+    num: i64,
+    string: Option<&str>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let beg = match_position(num.saturating_mul(2), env, cx)?;
+    let end = match_position(num.saturating_mul(2).saturating_add(1), env, cx)?;
+    let (Some(beg), Some(end)) = (beg, end) else {return Ok(nil())};
+    let Some(string) = string else {return buffer_substring(beg, end, env, cx)};
+    if beg > end || end > string.chars().count() {
+        let data = list![string, beg as i64, end as i64; cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    }
+    let text: String = string.chars().skip(beg).take(end - beg).collect();
+    Ok(cx.add(text))
+}
+
+/// How `replace-match` changes the case of the replacement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseAction {
+    NoChange,
+    AllCaps,
+    CapInitial,
+}
+
+/// The case conversion that makes a replacement look like `text`: all caps
+/// if `text` has a word of several letters and no lowercase, capitalized if
+/// each word in it starts with a capital.
+fn case_action(text: &str, syntax: &SyntaxTable) -> CaseAction {
+    let is_word = |x| syntax.is_word(x);
+    let mut multiletter_word = false;
+    let mut lowercase = false;
+    let mut uppercase = false;
+    let mut nonuppercase_initial = false;
+    let mut prev = '\n';
+    for chr in text.chars() {
+        if chr.is_lowercase() {
+            lowercase = true;
+            if is_word(prev) {
+                multiletter_word = true;
+            } else {
+                nonuppercase_initial = true;
+            }
+        } else if chr.is_uppercase() {
+            uppercase = true;
+            if is_word(prev) {
+                multiletter_word = true;
+            }
+        } else if !is_word(prev) && is_word(chr) {
+            // a caseless initial is like a lowercase one
+            nonuppercase_initial = true;
+        }
+        prev = chr;
+    }
+    if !lowercase && multiletter_word {
+        CaseAction::AllCaps
+    } else if !nonuppercase_initial && multiletter_word {
+        CaseAction::CapInitial
+    } else if !nonuppercase_initial && uppercase {
+        CaseAction::AllCaps
+    } else {
+        CaseAction::NoChange
+    }
+}
+
+/// Expand the `\&`, `\N` and `\\` constructs in `newtext`, where `group`
+/// gives the text of each group of the match.
+fn substitute(newtext: &str, group: impl Fn(usize) -> Option<String>) -> Result<String> {
+    let mut text = String::with_capacity(newtext.len());
+    let mut chars = newtext.chars();
+    while let Some(chr) = chars.next() {
+        if chr != '\\' {
+            text.push(chr);
+            continue;
+        }
+        match chars.next() {
+            Some('&') => text.extend(group(0)),
+            Some(digit @ '1'..='9') => text.extend(group(digit as usize - '0' as usize)),
+            Some('\\') => text.push('\\'),
+            _ => bail!("Invalid use of `\\' in replacement text"),
+        }
+    }
+    Ok(text)
+}
+
+/// The text that replaces group `sub` of a match in `text`, as
+/// `replace-match` makes it. `bounds` gives the character offsets in `text` of
+/// each group.
+fn replacement_text(
+    newtext: &str,
+    fixedcase: Option<()>,
+    literal: Option<()>,
+    text: &str,
+    bounds: impl Fn(usize) -> Option<(usize, usize)>,
+    sub: usize,
+    syntax: &SyntaxTable,
+) -> Result<String> {
+    let group_text = |n: usize| {
+        let (beg, end) = bounds(n)?;
+        Some(
+            text.chars()
+                .skip(beg)
+                .take(end.saturating_sub(beg))
+                .collect::<String>(),
+        )
+    };
+    let replacement = match literal {
+        Some(()) => newtext.to_owned(),
+        None => substitute(newtext, group_text)?,
+    };
+    if fixedcase.is_some() {
+        return Ok(replacement);
+    }
+    let matched = group_text(sub).unwrap_or_default();
+    Ok(match case_action(&matched, syntax) {
+        CaseAction::AllCaps => replacement.to_uppercase(),
+        CaseAction::CapInitial => upcase_initials(&replacement, syntax),
+        CaseAction::NoChange => replacement,
+    })
+}
+
+/// Replace the text matched by the last search with NEWTEXT. Unless LITERAL
+/// is non-nil, `\&` in NEWTEXT stands for the matched text, `\N` for the
+/// text of group N, and `\\` for a backslash. Unless FIXEDCASE is non-nil,
+/// the replacement is converted to all caps or capitalized if the matched
+/// text is. If STRING is non-nil, the match was in STRING, and a new string
+/// with the replacement is returned. Otherwise the text is replaced in the
+/// current buffer, point is left after it, and the match data is adjusted.
+/// If SUBEXP is non-nil, only the text of that group is replaced.
+#[defun]
+fn replace_match<'ob>(
+    newtext: &Rt<Gc<&LispString>>,
+    fixedcase: Option<()>,
+    literal: Option<()>,
+    string: Option<&Rt<GcObj>>,
+    subexp: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let newtext: &str = newtext.get(cx).try_into()?;
+    let newtext = newtext.to_owned();
+    let string = match string.map(|x| x.bind(cx)).filter(|x| !x.nil()) {
+        Some(string) => Some(<&str>::try_from(string)?.to_owned()),
+        None => None,
+    };
+    let string = string.as_deref();
+    let mut groups: Vec<Option<usize>> = Vec::new();
+    for pos in env.match_data.bind(cx).as_list()? {
+        groups.push(pos?.try_into()?);
+    }
+    let sub = subexp.unwrap_or(0);
+    let bounds = |n: usize| {
+        Some((
+            groups.get(n * 2).copied()??,
+            groups.get(n * 2 + 1).copied()??,
+        ))
+    };
+    let Some((beg, end)) = usize::try_from(sub).ok().and_then(bounds) else {bail!("replace-match subexpression does not exist: {sub}")};
+    // buffer positions start at 1
+    let base = usize::from(string.is_none());
+    let text = match string {
+        Some(string) => {
+            let len = string.chars().count();
+            let in_range = |n: usize| bounds(n).is_none_or(|(b, e)| b <= e && e <= len);
+            if !(0..groups.len() / 2).all(in_range) {
+                let data = list![beg as i64, end as i64; cx];
+                return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+            }
+            string.to_owned()
+        }
+        None => {
+            let data = get_current_buffer(env).lock()?;
+            let (begv, zv) = (data.begv() + 1, data.zv() + 1);
+            if beg < begv || end > zv || beg > end {
+                drop(data);
+                let data = list![beg as i64, end as i64; cx];
+                return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+            }
+            data.text.to_string()
+        }
+    };
+    // offsets of the groups in `text`
+    let offsets = |n: usize| bounds(n).map(|(beg, end)| (beg - base, end - base));
+    let syntax = current_syntax(env, cx);
+    let replacement = replacement_text(
+        &newtext,
+        fixedcase,
+        literal,
+        &text,
+        offsets,
+        sub as usize,
+        &syntax,
+    )?;
+    if let Some(string) = string {
+        let mut chars = string.chars();
+        let mut result: String = chars.by_ref().take(beg).collect();
+        result.push_str(&replacement);
+        result.extend(chars.skip(end - beg));
+        return Ok(cx.add(result));
+    }
+    let buffer = get_current_buffer(env);
+    buffer.lock()?.goto_char(beg - 1);
+    crate::buffer::delete_text(beg - 1, end - 1, env, cx)?;
+    buffer.lock()?.goto_char(beg - 1);
+    crate::buffer::insert_text(&replacement, env, cx)?;
+    // move the groups after the replaced text by the change in its length
+    let new_end = beg + replacement.chars().count();
+    let adjust = |pos: usize| {
+        if pos >= end {
+            pos - end + new_end
+        } else {
+            pos.min(beg)
+        }
+    };
+    let positions: Vec<GcObj> = groups.iter().map(|x| x.map(adjust).into()).collect();
+    env.match_data.set(slice_into_list(&positions, None, cx));
+    Ok(nil())
+}
+
+/// Replace each match of REGEXP in STRING with REP, and return the new
+/// string. FIXEDCASE, LITERAL and SUBEXP are like the arguments of
+/// `replace-match`. REP is either a string or a function, called with the
+/// text of each match while the match data describes the match within that
+/// text. If START is non-nil, the search starts at that index, and the text
+/// before it is left out of the result. The match data is not changed.
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn replace_regexp_in_string<'ob>(
+    regexp: &Rt<Gc<&LispString>>,
+    rep: &Rt<GcObj>,
+    string: &Rt<Gc<&LispString>>,
+    fixedcase: Option<()>,
+    literal: Option<()>,
+    subexp: Option<i64>,
+    start: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    let re = compile_regexp(regexp.get(cx).try_into()?, env, cx)?;
+    let string: &str = string.get(cx).try_into()?;
+    let string = string.to_owned();
+    let newtext = match rep.bind(cx).untag() {
+        Object::String(rep) => Some(<&str>::try_from(rep)?.to_owned()),
+        _ => None,
+    };
+    let start = start.unwrap_or(0);
+    let Some((start, _)) = usize::try_from(start).ok().and_then(|start| {
+        string
+            .char_indices()
+            .chain([(string.len(), ' ')])
+            .nth(start)
+    }) else {
+        let data = list![string.as_str(), start; cx];
+        return Err(EvalError::signal(sym::ARGS_OUT_OF_RANGE.into(), data, env).into());
+    };
+    let sub = subexp.unwrap_or(0);
+    let Ok(sub) = usize::try_from(sub) else {bail!("replace-match subexpression does not exist: {sub}")};
+    let replace = Replace {
+        newtext,
+        fixedcase,
+        literal,
+        sub,
+    };
+    // a function can clobber the match data, which is restored at the end
+    let saved = (env.match_data.bind(cx), env.match_object.bind(cx));
+    root!(saved, cx);
+    let result = replace.all(&re, rep, &string, start, env, cx);
+    env.match_data.set(saved.bind(cx).0);
+    env.match_object.set(saved.bind(cx).1);
+    Ok(cx.add(result?))
 }
 
+/// The arguments of `replace-regexp-in-string` that apply to each match.
+struct Replace {
+    /// The replacement, or None if it comes from a function
+    newtext: Option<String>,
+    fixedcase: Option<()>,
+    literal: Option<()>,
+    sub: usize,
+}
+
+impl Replace {
+    /// Replace each match of `re` in `string` after byte offset `start`.
+    fn all(
+        &self,
+        re: &Regex,
+        rep: &Rt<GcObj>,
+        string: &str,
+        start: usize,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+    ) -> Result<String> {
+        let mut result = String::new();
+        let mut pos = start;
+        while pos < string.len() {
+            let Some(captures) = re.search_forward(string.into(), pos, None)? else {break};
+            let (beg, end) = captures.get(0).unwrap_or((pos, pos));
+            // an empty match still moves past a character
+            let next = match string[end..].chars().next() {
+                Some(chr) if beg == end => end + chr.len_utf8(),
+                _ => end,
+            };
+            let text = &string[beg..next];
+            let to_char = |pos: usize| string[beg..pos].chars().count();
+            let bounds = |n: usize| captures.get(n).map(|(b, e)| (to_char(b), to_char(e)));
+            let sub = self.sub;
+            let Some((group_beg, group_end)) = bounds(sub) else {bail!("replace-match subexpression does not exist: {sub}")};
+            let newtext = match &self.newtext {
+                Some(newtext) => newtext.clone(),
+                None => {
+                    record_match(&captures, to_char, qtrue(), env, cx);
+                    let arg = vec![cx.add(&string[beg..end])];
+                    root!(arg, move(arg), cx);
+                    let func: &Rt<Gc<Function>> = Rt::try_into(rep)?;
+                    let value = func.call(arg, env, cx, None)?;
+                    <&str>::try_from(value)?.to_owned()
+                }
+            };
+            let syntax = current_syntax(env, cx);
+            let (fixedcase, literal) = (self.fixedcase, self.literal);
+            let replacement =
+                replacement_text(&newtext, fixedcase, literal, text, bounds, sub, &syntax)?;
+            let mut chars = text.chars();
+            result.push_str(&string[pos..beg]);
+            result.extend(chars.by_ref().take(group_beg));
+            result.push_str(&replacement);
+            result.extend(chars.skip(group_end - group_beg));
+            pos = next;
+        }
+        result.push_str(&string[pos.min(string.len())..]);
+        Ok(result)
+    }
+}
+
+/// Upcase the first character of each word in `text`.
+fn upcase_initials(text: &str, syntax: &SyntaxTable) -> String {
+    let mut prev = '\n';
+    let mut result = String::with_capacity(text.len());
+    for chr in text.chars() {
+        if syntax.is_word(prev) {
+            result.push(chr);
+        } else {
+            result.extend(chr.to_uppercase());
+        }
+        prev = chr;
+    }
+    result
+}
+
+/// A regexp that matches STRING literally.
 #[defun]
-fn set_match_data<'ob>(list: Gc<List>, _reseat: Option<()>, env: &mut Rt<Env>) -> GcObj<'ob> {
-    // TODO: add reseat when markers implemented
-    let obj: GcObj = list.into();
-    env.match_data.set(obj);
-    nil()
+pub(crate) fn regexp_quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len());
+    for chr in string.chars() {
+        if "[*.\\?+^$".contains(chr) {
+            quoted.push('\\');
+        }
+        quoted.push(chr);
+    }
+    quoted
+}
+
+/// What a search command looks for.
+enum Pattern {
+    Regexp(Rc<Regex>),
+    /// A string that is the same however case is folded, which is found
+    /// with a substring search instead of the matcher
+    Literal(String),
+}
+
+impl Pattern {
+    /// The pattern that matches STRING literally.
+    fn literal(string: &str, env: &mut Rt<Env>, cx: &Context) -> Result<Self> {
+        let caseless = |chr: char| chr.to_lowercase().eq([chr]) && chr.to_uppercase().eq([chr]);
+        if !case_fold_search(env, cx) || string.chars().all(caseless) {
+            Ok(Self::Literal(string.to_owned()))
+        } else {
+            Ok(Self::Regexp(compile_regexp(
+                &regexp_quote(string),
+                env,
+                cx,
+            )?))
+        }
+    }
+
+    fn search_forward(
+        &self,
+        text: Text,
+        start: usize,
+        point: Option<usize>,
+    ) -> Result<Option<Captures>> {
+        match self {
+            Pattern::Regexp(re) => re.search_forward(text, start, point),
+            Pattern::Literal(string) => {
+                let found = text.find(string, start);
+                Ok(found.map(|x| Captures::span(x, x + string.len())))
+            }
+        }
+    }
+
+    fn search_backward(
+        &self,
+        text: Text,
+        start: usize,
+        bound: usize,
+        point: Option<usize>,
+    ) -> Result<Option<Captures>> {
+        match self {
+            Pattern::Regexp(re) => re.search_backward(text, start, bound, point),
+            Pattern::Literal(string) => {
+                let found = text.rfind(string, start, bound);
+                Ok(found.map(|x| Captures::span(x, x + string.len())))
+            }
+        }
+    }
+}
+
+/// Search the current buffer from point for `re`, `count` times, backwards
+/// if it is negative. A match can not extend past `bound`, nor past the
+/// start of the search going backwards. On success, point moves to the end
+/// of the last match, or to its start going backwards, and the match data is
+/// set. Returns the position the search reached, or the bound if it failed.
+fn search_buffer(
+    re: &Pattern,
+    bound: Option<usize>,
+    count: i64,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<std::result::Result<usize, usize>> {
+    let buffer = get_current_buffer(env);
+    let mut data = buffer.lock()?;
+    let (point, begv, zv) = (data.point(), data.begv(), data.zv());
+    let forward = count >= 0;
+    let bound = match bound.map(|x| x.saturating_sub(1)) {
+        None if forward => zv,
+        None => begv,
+        Some(bound) if forward && bound < point || !forward && bound > point => {
+            bail!("Invalid search bound (wrong side of point)")
+        }
+        Some(bound) => bound.clamp(begv, zv),
+    };
+    // searching zero times succeeds without moving
+    if count == 0 {
+        return Ok(Ok(point + 1));
+    }
+    let offset = |pos| data.text.char_to_byte(pos) - data.text.char_to_byte(begv);
+    let point_byte = Some(offset(point));
+    let mut pos = point;
+    for _ in 0..count.unsigned_abs() {
+        let (before, after) = data.text.slice(begv, if forward { bound } else { pos });
+        let text = Text::new(before, after);
+        let captures = if forward {
+            re.search_forward(text, offset(pos), point_byte)?
+        } else {
+            re.search_backward(text, offset(pos), offset(bound), point_byte)?
+        };
+        let Some(captures) = captures else {return Ok(Err(bound + 1))};
+        let to_pos = |byte| buffer_position(text, begv, byte);
+        let (beg, end) = captures.get(0).expect("a match should have group 0");
+        pos = to_pos(if forward { end } else { beg }) - 1;
+        record_match(&captures, to_pos, buffer.into(), env, cx);
+    }
+    data.goto_char(pos);
+    Ok(Ok(pos + 1))
+}
+
+/// The buffer position of byte offset `byte` in `text`, the part of a
+/// buffer that starts at `begv`.
+fn buffer_position(text: Text, begv: usize, byte: usize) -> usize {
+    begv + text.char_count(byte) + 1
 }
 
+/// Search for `re` as the search commands do, handling a failed search as
+/// NOERROR says.
+fn search_command<'ob>(
+    re: &Pattern,
+    pattern: &str,
+    bound: Option<usize>,
+    noerror: Option<GcObj>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    match search_buffer(re, bound, count.unwrap_or(1), env, cx)? {
+        Ok(pos) => Ok(pos.into()),
+        Err(bound) => match noerror {
+            None => {
+                let data = list![pattern; cx];
+                Err(EvalError::signal(sym::SEARCH_FAILED.into(), data, env).into())
+            }
+            Some(noerror) => {
+                if noerror != qtrue() {
+                    get_current_buffer(env).lock()?.goto_char(bound - 1);
+                }
+                Ok(nil())
+            }
+        },
+    }
+}
+
+/// Search forward from point for STRING, and move point to the end of the
+/// match. The match must end before BOUND. If NOERROR is nil, a failed search
+/// signals `search-failed`; if it is t, it returns nil; otherwise it returns
+/// nil and moves point to BOUND. COUNT searches that many times, and a
+/// negative COUNT searches backward. Case is ignored if `case-fold-search`
+/// is non-nil. Returns point.
 #[defun]
-fn match_beginning<'ob>(subexp: usize, env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
-    env.match_data
-        .bind(cx)
-        .as_list()?
-        .nth(subexp)
-        .unwrap_or_else(|| Ok(nil()))
+fn search_forward<'ob>(
+    string: &str,
+    bound: Option<usize>,
+    noerror: Option<GcObj>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let re = Pattern::literal(string, env, cx)?;
+    search_command(&re, string, bound, noerror, count, env, cx)
 }
 
+/// Search backward from point for STRING, and move point to the start of the
+/// match. The match must start after BOUND and end before point. See
+/// `search-forward` for the other arguments.
 #[defun]
-fn match_end<'ob>(subexp: usize, env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
-    env.match_data
-        .bind(cx)
-        .as_list()?
-        .nth(subexp + 1)
-        .unwrap_or_else(|| Ok(nil()))
+fn search_backward<'ob>(
+    string: &str,
+    bound: Option<usize>,
+    noerror: Option<GcObj>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let re = Pattern::literal(string, env, cx)?;
+    let count = count.unwrap_or(1).checked_neg();
+    search_command(&re, string, bound, noerror, count, env, cx)
 }
 
+/// Like `search-forward`, but REGEXP is a regular expression.
 #[defun]
-fn string_equal(s1: &str, s2: &str) -> bool {
-    s1 == s2
+fn re_search_forward<'ob>(
+    regexp: &str,
+    bound: Option<usize>,
+    noerror: Option<GcObj>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let re = Pattern::Regexp(compile_regexp(regexp, env, cx)?);
+    search_command(&re, regexp, bound, noerror, count, env, cx)
 }
 
+/// Like `search-backward`, but REGEXP is a regular expression. The match
+/// found is the one that starts last.
+#[defun]
+fn re_search_backward<'ob>(
+    regexp: &str,
+    bound: Option<usize>,
+    noerror: Option<GcObj>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let re = Pattern::Regexp(compile_regexp(regexp, env, cx)?);
+    let count = count.unwrap_or(1).checked_neg();
+    search_command(&re, regexp, bound, noerror, count, env, cx)
+}
+
+/// Whether the text after point matches REGEXP. Sets the match data unless
+/// INHIBIT-MODIFY is non-nil.
+#[defun]
+fn looking_at(
+    regexp: &str,
+    inhibit_modify: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let re = compile_regexp(regexp, env, cx)?;
+    let buffer = get_current_buffer(env);
+    let data = buffer.lock()?;
+    let (point, begv, zv) = (data.point(), data.begv(), data.zv());
+    let (before, after) = data.text.slice(begv, zv);
+    let text = Text::new(before, after);
+    let point = data.text.char_to_byte(point) - data.text.char_to_byte(begv);
+    let Some(captures) = re.match_at(text, point, Some(point))? else {return Ok(false)};
+    if inhibit_modify.is_none() {
+        record_match(
+            &captures,
+            |x| buffer_position(text, begv, x),
+            buffer.into(),
+            env,
+            cx,
+        );
+    }
+    Ok(true)
+}
+
+/// Like `looking-at`, but the match data is not changed.
+#[defun]
+fn looking_at_p(regexp: &str, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    looking_at(regexp, Some(()), env, cx)
+}
+
+/// Whether the text before point matches REGEXP, and sets the match data.
+/// The match found is the shortest one, and it starts at or after LIMIT.
+/// If GREEDY is non-nil, the match is extended backward as far as REGEXP
+/// still matches, without regard to LIMIT.
+#[defun]
+fn looking_back(
+    regexp: &str,
+    limit: Option<usize>,
+    greedy: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let re = compile_regexp(&format!("\\(?:{regexp}\\)\\="), env, cx)?;
+    let buffer = get_current_buffer(env);
+    let data = buffer.lock()?;
+    let (point, begv, zv) = (data.point(), data.begv(), data.zv());
+    let limit = match limit.map(|x| x.saturating_sub(1)) {
+        Some(limit) if limit > point => bail!("Invalid search bound (wrong side of point)"),
+        Some(limit) => limit.max(begv),
+        None => begv,
+    };
+    let offset = |pos| data.text.char_to_byte(pos) - data.text.char_to_byte(begv);
+    let (before, after) = data.text.slice(begv, zv);
+    let text = Text::new(before, after);
+    let point_byte = offset(point);
+    let Some(mut captures) =
+        re.search_backward(text, point_byte, offset(limit), Some(point_byte))?
+    else {return Ok(false)};
+    if greedy.is_some() {
+        // the match can only grow within the text before point
+        let anchored = compile_regexp(&format!("\\(?:{regexp}\\)\\'"), env, cx)?;
+        let (before, after) = data.text.slice(begv, point);
+        let text = Text::new(before, after);
+        let (mut beg, _) = captures.get(0).expect("a match should have group 0");
+        while let Some(chr) = text.char_before(beg) {
+            let Some(longer) = anchored.match_at(text, beg - chr.len_utf8(), None)? else {break};
+            beg -= chr.len_utf8();
+            captures = longer;
+        }
+    }
+    record_match(
+        &captures,
+        |x| buffer_position(text, begv, x),
+        buffer.into(),
+        env,
+        cx,
+    );
+    Ok(true)
+}
+
+defsym!(INVALID_REGEXP);
+defsym!(SAVE_MATCH_DATA);
+defsym!(SEARCH_FAILED);
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
 
     #[test]
-    fn lisp_regex() {
-        assert_eq!(lisp_regex_to_rust("foo"), "foo");
-        assert_eq!(lisp_regex_to_rust("\\foo"), "\\foo");
-        assert_eq!(lisp_regex_to_rust("\\(foo\\)"), "(foo)");
-        assert_eq!(lisp_regex_to_rust("(foo)"), "\\(foo\\)");
+    fn test_string_match() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        // positions are in characters
+        let form = "(list (string-match \"b\\\\(c\\\\)?\" \"äbc\") (match-data))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 (1 3 2 3))");
+        let form = "(list (string-match \"\\\\(x\\\\)\\\\|b\" \"abc\" -2) (match-data))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 (1 2))");
+        check("(setq case-fold-search t)", env, cx);
+        let form = "(list (string-match \"B\" \"abc\" nil t) (match-data))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 (1 2))");
+        check("(setq case-fold-search nil)", env, cx);
+        assert_eq!(check("(string-match \"B\" \"abc\")", env, cx), nil());
+        // the start is in characters, and the -p variant keeps the match data
+        let form =
+            "(list (string-match-p \"é\" \"éaé\" 1) (string-match-p \"x\" \"é\") (match-data))";
+        assert_eq!(check(form, env, cx).to_string(), "(2 nil (1 2))");
+        let form = "(condition-case nil (string-match \"a\" \"é\" 2) (error 'out-of-range))";
+        assert_eq!(check(form, env, cx).to_string(), "out-of-range");
+        let form = "(condition-case err (string-match \"\\\\(\" \"\") (error err))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(invalid-regexp \"Unmatched ( or \\(\")"
+        );
+    }
+
+    #[test]
+    fn test_match_data() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(string-match \"\\\\(b\\\\)\\\\(x\\\\)?\\\\(c\\\\)\" \"abcd\")",
+            env,
+            cx,
+        );
+        let form = "(list (match-beginning 0) (match-end 0) (match-beginning 2) (match-end 3)
+                          (match-beginning 5))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 3 nil 3 nil)");
+        let form =
+            "(list (match-string 0 \"abcd\") (match-string 3 \"abcd\") (match-string 2 \"abcd\"))";
+        assert_eq!(check(form, env, cx).to_string(), "(\"bc\" \"c\" nil)");
+        let form = "(condition-case nil (match-beginning -1) (error 'out-of-range))";
+        assert_eq!(check(form, env, cx).to_string(), "out-of-range");
+        // reuse is filled in place, with nil after the data
+        let form = "(let ((l (list 0 0 0 0 0 0 0 0 0))) (eq (match-data nil l) l))";
+        assert_eq!(check(form, env, cx), qtrue());
+        let form = "(match-data nil (list 0 0 0 0 0 0 0 0 0))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(1 3 1 2 nil nil 2 3 nil)"
+        );
+        let form = "(match-data nil (list 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 3 1 2 nil nil 2 3)");
+
+        // positions in a buffer are markers unless INTEGERS is non-nil
+        check("(set-buffer (get-buffer-create \"match-data\"))", env, cx);
+        check("(insert \"hello world\")", env, cx);
+        check("(set-match-data (list 7 12 (current-buffer)))", env, cx);
+        assert_eq!(check("(match-string 0)", env, cx), "world");
+        let form = "(mapcar #'markerp (match-data))";
+        assert_eq!(check(form, env, cx).to_string(), "(t t)");
+        let form = "(match-data t)";
+        assert_eq!(check(form, env, cx).to_string(), "(7 12 #<match-data>)");
+        check("(setq saved (match-data))", env, cx);
+        check("(set-match-data saved t)", env, cx);
+        let form = "(list (match-beginning 0) (marker-position (car saved)))";
+        assert_eq!(check(form, env, cx).to_string(), "(7 nil)");
+
+        // save-match-data restores the data on normal and non-local exits
+        check("(string-match \"o\" \"foo\")", env, cx);
+        let form = "(list (save-match-data (string-match \"x\" \"ax\") (match-beginning 0))
+                          (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(1 1)");
+        let form = "(list (catch 'done (save-match-data (string-match \"x\" \"aax\") (throw 'done 'thrown)))
+                          (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(thrown 1)");
+        let form = "(list (condition-case nil
+                              (save-match-data (string-match \"x\" \"aax\") (error \"fail\"))
+                            (error 'failed))
+                          (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(failed 1)");
+    }
+
+    #[test]
+    fn test_buffer_search() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"search\"))", env, cx);
+        check("(insert \"foo bar foo BAR foo\")", env, cx);
+        check("(goto-char 1)", env, cx);
+        let form = "(list (search-forward \"foo\") (point) (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(4 4 1)");
+        let form = "(list (search-forward \"foo\" nil nil 2) (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(20 17)");
+        // a match must end before the bound, and a regexp special is literal
+        check("(goto-char 1)", env, cx);
+        let form = "(list (search-forward \"bar\" 7 t) (point)
+                          (search-forward \"bar\" 7 'move) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(nil 1 nil 7)");
+        assert_eq!(check("(search-forward \".\" nil t)", env, cx), nil());
+        let form = "(condition-case err (search-forward \"zzz\") (error err))";
+        assert_eq!(check(form, env, cx).to_string(), "(search-failed \"zzz\")");
+        let form = "(condition-case nil (search-forward \"foo\" 1) (error 'wrong-side))";
+        assert_eq!(check(form, env, cx).to_string(), "wrong-side");
+
+        // backward searches stop at the start of the match, which must end
+        // before point
+        check("(goto-char 19)", env, cx);
+        let form = "(list (search-backward \"foo\") (match-end 0) (search-backward \"foo\" 2 t))";
+        assert_eq!(check(form, env, cx).to_string(), "(9 12 nil)");
+        check("(goto-char (point-max))", env, cx);
+        let form = "(list (search-forward \"foo\" nil t -2) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(9 9)");
+
+        // case folding and narrowing
+        check("(setq case-fold-search t)", env, cx);
+        check("(goto-char 1)", env, cx);
+        let form = "(list (search-forward \"bar\" nil nil 2) (match-string 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(16 \"BAR\")");
+        check("(setq case-fold-search nil)", env, cx);
+        check("(narrow-to-region 5 12)", env, cx);
+        check("(goto-char (point-min))", env, cx);
+        let form =
+            "(list (re-search-forward \"\\\\`bar\" nil t) (re-search-forward \"o+\\\\'\" nil t)
+                          (re-search-forward \"BAR\" nil t))";
+        assert_eq!(check(form, env, cx).to_string(), "(8 12 nil)");
+        check("(widen)", env, cx);
+
+        // regexps set the groups and search backward for the last start
+        check("(goto-char (point-max))", env, cx);
+        let form = "(list (re-search-backward \"\\\\(f\\\\)o+\") (match-data t))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(17 (17 20 17 18 #<search>))"
+        );
+        let form = "(list (re-search-backward \"o*\") (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(17 17)");
+        check("(goto-char 1)", env, cx);
+        let form = "(list (re-search-forward \"[a-z]+ \" nil t 3) (match-beginning 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(13 9)");
+        assert_eq!(
+            check("(regexp-quote \"a.b*[c]^$\\\\\")", env, cx),
+            "a\\.b\\*\\[c]\\^\\$\\\\"
+        );
+    }
+
+    #[test]
+    fn test_replace_match() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let form = "(progn (string-match \"\\\\(b+\\\\)c\" \"abbcd\")
+                           (replace-match \"<\\\\1|\\\\&|\\\\\\\\>\" t nil \"abbcd\"))";
+        assert_eq!(check(form, env, cx), "a<bb|bbc|\\>d");
+        let form = "(progn (string-match \"a\\\\(b\\\\)c\" \"abc\") (replace-match \"\\\\1x\" t t \"abc\" 1))";
+        assert_eq!(check(form, env, cx), "a\\1xc");
+        let form = "(condition-case nil (replace-match \"x\" t t \"abc\" 2) (error 'no-group))";
+        assert_eq!(check(form, env, cx).to_string(), "no-group");
+        let form = "(condition-case nil (replace-match \"\\\\q\" t nil \"abc\") (error 'invalid))";
+        assert_eq!(check(form, env, cx).to_string(), "invalid");
+
+        // the case of the replacement follows the matched text
+        let replace = |text: &str, env: &mut Rt<Env>, cx: &mut Context| {
+            let form = format!(
+                "(progn (string-match \"[a-z]+ ?[a-z]*\" \"{text}\")
+                        (replace-match \"new text\" nil nil \"{text}\"))"
+            );
+            check(&form, env, cx).to_string()
+        };
+        check("(setq case-fold-search t)", env, cx);
+        assert_eq!(replace("FOO", env, cx), "\"NEW TEXT\"");
+        assert_eq!(replace("Foo Bar", env, cx), "\"New Text\"");
+        assert_eq!(replace("F", env, cx), "\"NEW TEXT\"");
+        assert_eq!(replace("Foo bar", env, cx), "\"new text\"");
+        assert_eq!(replace("foo", env, cx), "\"new text\"");
+        let form = "(progn (string-match \"foo\" \"FOO\") (replace-match \"bar\" t nil \"FOO\"))";
+        assert_eq!(check(form, env, cx), "bar");
+        check("(setq case-fold-search nil)", env, cx);
+
+        // in a buffer, point moves after the replacement and the match data
+        // follows the change in length
+        check("(set-buffer (get-buffer-create \"replace\"))", env, cx);
+        check("(insert \"hello world!\")", env, cx);
+        check("(goto-char 1)", env, cx);
+        check("(re-search-forward \"\\\\(w\\\\)orld\")", env, cx);
+        let form = "(list (replace-match \"W\\\\1\") (buffer-string) (point) (match-data t))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(nil \"hello Ww!\" 9 (7 9 7 7 #<replace>))"
+        );
+    }
+
+    #[test]
+    fn test_replace_regexp_in_string() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let cases = [
+            ("\"o+\" \"0\" \"foo boo\"", "f0 b0"),
+            ("\"\\\\(a\\\\)\\\\(b\\\\)\" \"\\\\2\\\\1\" \"abab\"", "baba"),
+            (
+                "\"\\\\(a\\\\)\\\\(b\\\\)\" \"\\\\2\\\\1\" \"abab\" nil t",
+                "\\2\\1\\2\\1",
+            ),
+            (
+                "\"\\\\(foo\\\\).*\\\\'\" \"bar\" \" foo foo\" nil nil 1",
+                " bar foo",
+            ),
+            ("\"x*\" \"-\" \"abc\"", "-a-b-c"),
+            ("\"a\" \"b\" \"aaa\" nil nil nil 1", "bb"),
+            ("\"é\" \"e\" \"éaé\"", "eae"),
+            ("\"z\" \"y\" \"\"", ""),
+        ];
+        for (args, expect) in cases {
+            let form = format!("(replace-regexp-in-string {args})");
+            assert_eq!(check(&form, env, cx), expect, "{form}");
+        }
+        // the function sees the match data of the text it is given, and the
+        // match data of the caller is kept
+        check("(string-match \"c\" \"abc\")", env, cx);
+        let form = "(replace-regexp-in-string \"[0-9]+\"
+                      #'(lambda (m) (format \"<%s:%s>\" m (match-end 0))) \"a1 b22\")";
+        assert_eq!(check(form, env, cx), "a<1:1> b<22:2>");
+        assert_eq!(check("(match-data)", env, cx).to_string(), "(2 3)");
+        check("(setq case-fold-search t)", env, cx);
+        let form = "(replace-regexp-in-string \"foo\" \"bar\" \"FOO foo Foo\")";
+        assert_eq!(check(form, env, cx), "BAR bar Bar");
+        let form = "(condition-case nil (replace-regexp-in-string \"a\" \"b\" \"a\" nil nil 1)
+                      (error 'no-group))";
+        assert_eq!(check(form, env, cx).to_string(), "no-group");
+    }
+
+    #[test]
+    fn test_looking_at() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"looking\"))", env, cx);
+        check("(insert \"foo bar baz\")", env, cx);
+        check("(goto-char 5)", env, cx);
+        let form = "(list (looking-at \"ba\\\\(r\\\\)\") (match-data t) (looking-at \"foo\"))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(t (5 8 7 8 #<looking>) nil)"
+        );
+        check("(looking-at \"bar\")", env, cx);
+        let form = "(list (looking-at-p \"b\\\\(a\\\\)\") (looking-at-p \"x\") (match-data t))";
+        assert_eq!(check(form, env, cx).to_string(), "(t nil (5 8 #<looking>))");
+
+        // the shortest match ending at point, unless it is greedy
+        check("(goto-char 7)", env, cx);
+        let form = "(list (looking-back \"o+ ba\") (match-beginning 0)
+                          (looking-back \"o+ ba\" nil t) (match-beginning 0) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(t 3 t 2 7)");
+        let form = "(list (looking-back \"o+ ba\" 4) (looking-back \"o+ ba\" 3)
+                          (looking-back \"fo\"))";
+        assert_eq!(check(form, env, cx).to_string(), "(nil t nil)");
+        let form = "(condition-case nil (looking-back \"a\" 9) (error 'wrong-side))";
+        assert_eq!(check(form, env, cx).to_string(), "wrong-side");
+        // the greedy match does not look past point
+        let form = "(list (looking-back \"ba.*\" nil t) (match-end 0))";
+        assert_eq!(check(form, env, cx).to_string(), "(t 7)");
+    }
+
+    #[test]
+    fn test_regexp_cache() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let first = compile_regexp("a+b", env, cx).unwrap();
+        assert!(Rc::ptr_eq(&first, &compile_regexp("a+b", env, cx).unwrap()));
+        // the case folding is part of the key
+        check("(setq case-fold-search t)", env, cx);
+        let folded = compile_regexp("a+b", env, cx).unwrap();
+        assert!(!Rc::ptr_eq(&first, &folded));
+        check("(setq case-fold-search nil)", env, cx);
+        assert!(Rc::ptr_eq(&first, &compile_regexp("a+b", env, cx).unwrap()));
+        assert!(compile_regexp("\\(", env, cx).is_err());
+        // the least recently used regexp is dropped first
+        for idx in 1..REGEXP_CACHE_SIZE {
+            compile_regexp(&format!("x{idx}"), env, cx).unwrap();
+        }
+        assert!(Rc::ptr_eq(&first, &compile_regexp("a+b", env, cx).unwrap()));
+        compile_regexp("y", env, cx).unwrap();
+        check("(setq case-fold-search t)", env, cx);
+        assert!(!Rc::ptr_eq(
+            &folded,
+            &compile_regexp("a+b", env, cx).unwrap()
+        ));
+        // so is the case table, which is used to ignore case
+        let standard = compile_regexp("o", env, cx).unwrap();
+        assert_eq!(
+            check("(string-match \"o\" \"X\")", env, cx).to_string(),
+            "nil"
+        );
+        let form = "(let ((table (make-char-table 'case-table)))
+                      (aset table ?X ?o)
+                      (set-case-table table))";
+        check(form, env, cx);
+        assert!(!Rc::ptr_eq(
+            &standard,
+            &compile_regexp("o", env, cx).unwrap()
+        ));
+        assert_eq!(
+            check("(string-match \"o\" \"X\")", env, cx).to_string(),
+            "0"
+        );
+        assert_eq!(
+            check("(string-match \"[o]\" \"X\")", env, cx).to_string(),
+            "0"
+        );
     }
 }
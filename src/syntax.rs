@@ -0,0 +1,2501 @@
+#![cfg(feature = "buffers")]
+//! Syntax tables and the motion commands built on them. Every character
+//! belongs to a syntax class, such as word or whitespace, which is looked up
+//! in the syntax table of the current buffer. Syntax tables are char-tables
+//! whose values are raw syntax descriptors, `(CODE . MATCHING-CHAR)`, and
+//! whose parent is usually the standard syntax table.
+//!
+//! The parser of `parse-partial-sexp` and the scans over lists and comments
+//! work on a copy of the accessible text, where the `syntax-table` text
+//! property can override the table for some characters.
+use crate::buffer::get_current_buffer;
+use crate::character::int_to_char;
+use crate::chartab::char_code;
+use crate::core::env::{sym, Env, Symbol};
+use crate::core::error::EvalError;
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{
+    nil, BufferData, CharMap, CharTable, CharTableData, Gc, GcObj, Object, TagType,
+};
+use crate::editfns::validate_region;
+use crate::fns::slice_into_list;
+use crate::textprop::property_runs;
+use anyhow::{bail, Result};
+use fn_macros::defun;
+use memchr::memmem;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The syntax classes, named by the descriptor characters used in syntax
+/// strings. The values are the class codes of raw syntax descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Syntax {
+    Whitespace = 0,
+    Punct,
+    Word,
+    Symbol,
+    Open,
+    Close,
+    Quote,
+    String,
+    Math,
+    Escape,
+    CharQuote,
+    Comment,
+    EndComment,
+    CommentFence = 14,
+    StringFence,
+}
+
+/// The descriptor character of each class code. Code 13 means inherit from
+/// the parent table, and never appears in a raw descriptor.
+const CLASS_CHARS: &[u8; 16] = b" .w_()'\"$\\/<>@!|";
+
+impl Syntax {
+    /// The class named by the syntax descriptor `chr`.
+    pub(crate) fn from_char(chr: char) -> Option<Self> {
+        let syntax = match chr {
+            ' ' | '-' => Self::Whitespace,
+            '.' => Self::Punct,
+            'w' => Self::Word,
+            '_' => Self::Symbol,
+            '(' => Self::Open,
+            ')' => Self::Close,
+            '\'' => Self::Quote,
+            '"' => Self::String,
+            '$' => Self::Math,
+            '\\' => Self::Escape,
+            '/' => Self::CharQuote,
+            '<' => Self::Comment,
+            '>' => Self::EndComment,
+            '!' => Self::CommentFence,
+            '|' => Self::StringFence,
+            _ => return None,
+        };
+        Some(syntax)
+    }
+
+    fn from_code(code: i64) -> Option<Self> {
+        let chr = *CLASS_CHARS.get(usize::try_from(code).ok()?)?;
+        Self::from_char(chr.into())
+    }
+
+    /// The descriptor character of the class.
+    fn to_char(self) -> char {
+        CLASS_CHARS[self as usize].into()
+    }
+}
+
+/// The flags of a syntax descriptor, which mark characters that are part of
+/// two-character comment delimiters, prefix characters, and the comment
+/// style a delimiter belongs to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SyntaxFlags(u8);
+
+impl SyntaxFlags {
+    /// `1`: the first character of a two-character comment starter
+    pub(crate) const COMMENT_START_FIRST: Self = Self(1);
+    /// `2`: the second character of a two-character comment starter
+    pub(crate) const COMMENT_START_SECOND: Self = Self(1 << 1);
+    /// `3`: the first character of a two-character comment ender
+    pub(crate) const COMMENT_END_FIRST: Self = Self(1 << 2);
+    /// `4`: the second character of a two-character comment ender
+    pub(crate) const COMMENT_END_SECOND: Self = Self(1 << 3);
+    /// `p`: a prefix character, skipped by `backward-prefix-chars`
+    pub(crate) const PREFIX: Self = Self(1 << 4);
+    /// `b`: part of a comment delimiter of style b
+    pub(crate) const STYLE_B: Self = Self(1 << 5);
+    /// `n`: part of a comment delimiter of a nestable comment
+    pub(crate) const NESTED: Self = Self(1 << 6);
+    /// `c`: part of a comment delimiter of style c
+    pub(crate) const STYLE_C: Self = Self(1 << 7);
+
+    fn from_char(chr: char) -> Option<Self> {
+        let flag = match chr {
+            '1' => Self::COMMENT_START_FIRST,
+            '2' => Self::COMMENT_START_SECOND,
+            '3' => Self::COMMENT_END_FIRST,
+            '4' => Self::COMMENT_END_SECOND,
+            'p' => Self::PREFIX,
+            'b' => Self::STYLE_B,
+            'n' => Self::NESTED,
+            'c' => Self::STYLE_C,
+            _ => return None,
+        };
+        Some(flag)
+    }
+
+    pub(crate) fn contains(self, flag: Self) -> bool {
+        self.0 & flag.0 == flag.0
+    }
+}
+
+/// The syntax of a character: its class, its flags, and the character it
+/// matches if it is a paren.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SyntaxEntry {
+    pub(crate) class: Syntax,
+    pub(crate) flags: SyntaxFlags,
+    pub(crate) matching: Option<char>,
+}
+
+impl SyntaxEntry {
+    fn new(class: Syntax) -> Self {
+        Self {
+            class,
+            flags: SyntaxFlags::default(),
+            matching: None,
+        }
+    }
+
+    /// Parse a syntax descriptor string such as `"()"` or `". 12b"`: the
+    /// class, the matching character, and the flags. Returns `None` for the
+    /// inherit class `@`.
+    fn parse(descriptor: &str) -> Result<Option<Self>> {
+        let mut chars = descriptor.chars();
+        let class = match chars.next() {
+            Some('@') => return Ok(None),
+            Some(chr) => match Syntax::from_char(chr) {
+                Some(class) => class,
+                None => bail!("Invalid syntax description letter: {chr}"),
+            },
+            None => bail!("Invalid syntax description letter: "),
+        };
+        let matching = chars.next().filter(|x| *x != ' ');
+        let flags = chars
+            .filter_map(SyntaxFlags::from_char)
+            .fold(0, |x, y| x | y.0);
+        Ok(Some(Self {
+            class,
+            flags: SyntaxFlags(flags),
+            matching,
+        }))
+    }
+
+    /// The entry of a raw syntax descriptor, or `None` if `obj` is not one.
+    fn from_obj(obj: GcObj) -> Option<Self> {
+        let Object::Cons(cons) = obj.untag() else {return None};
+        let Object::Int(code) = cons.car().untag() else {return None};
+        let matching = match cons.cdr().untag() {
+            Object::Int(chr) => u32::try_from(chr).ok().and_then(char::from_u32),
+            _ => None,
+        };
+        Some(Self {
+            matching,
+            ..Self::from_code(code)?
+        })
+    }
+
+    /// The entry of the code of a raw syntax descriptor, which has the class
+    /// in the low bits and the flags from bit 16.
+    fn from_code(code: i64) -> Option<Self> {
+        Some(Self {
+            class: Syntax::from_code(code & 0xFFFF)?,
+            flags: SyntaxFlags((code >> 16) as u8),
+            matching: None,
+        })
+    }
+
+    fn code(self) -> i64 {
+        self.class as i64 | i64::from(self.flags.0) << 16
+    }
+
+    /// The raw syntax descriptor of the entry.
+    fn to_obj<'ob>(self, cx: &'ob Context) -> GcObj<'ob> {
+        let matching = self.matching.map_or_else(nil, |x| (x as i64).into());
+        cons!(self.code(), matching; cx)
+    }
+
+    fn has(self, flag: SyntaxFlags) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// The style of the comment that this character starts or ends, where
+    /// `other` is the other character of a two-character delimiter: 1 for
+    /// the `b` flag, plus 2 if either character has the `c` flag.
+    fn comment_style(self, other: Option<Self>) -> u16 {
+        let style_c = |x: Self| if x.has(SyntaxFlags::STYLE_C) { 2 } else { 0 };
+        u16::from(self.has(SyntaxFlags::STYLE_B)) | style_c(self) | other.map_or(0, style_c)
+    }
+}
+
+/// Whether `entry` is the syntax of a character and has `flag`.
+fn has_flag(entry: Option<SyntaxEntry>, flag: SyntaxFlags) -> bool {
+    entry.is_some_and(|x| x.has(flag))
+}
+
+/// The syntax class of `chr` in the standard syntax table. Characters
+/// outside of ASCII that the table does not set get a class from their
+/// Unicode properties.
+fn standard_syntax(chr: char) -> Syntax {
+    match chr {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '$' | '%' => Syntax::Word,
+        ' ' | '\t' | '\n' | '\r' | '\x0c' => Syntax::Whitespace,
+        '(' | '[' | '{' => Syntax::Open,
+        ')' | ']' | '}' => Syntax::Close,
+        '"' => Syntax::String,
+        '\\' => Syntax::Escape,
+        '_' | '-' | '+' | '*' | '/' | '&' | '|' | '<' | '>' | '=' => Syntax::Symbol,
+        c if c.is_ascii() => Syntax::Punct,
+        c if c.is_alphanumeric() => Syntax::Word,
+        c if c.is_whitespace() => Syntax::Whitespace,
+        _ => Syntax::Punct,
+    }
+}
+
+fn standard_entry(chr: char) -> SyntaxEntry {
+    let matching = match chr {
+        '(' => Some(')'),
+        ')' => Some('('),
+        '[' => Some(']'),
+        ']' => Some('['),
+        '{' => Some('}'),
+        '}' => Some('{'),
+        _ => None,
+    };
+    SyntaxEntry {
+        matching,
+        ..SyntaxEntry::new(standard_syntax(chr))
+    }
+}
+
+/// The syntax of every character, taken from a syntax table and its
+/// parents. This is a copy that does not refer to the heap, so it can be
+/// kept in compiled regexps.
+#[derive(Debug, Default)]
+pub(crate) struct SyntaxTable {
+    /// The entries and default value of the table and each of its ancestors
+    levels: Vec<(CharMap<Option<SyntaxEntry>>, Option<SyntaxEntry>)>,
+}
+
+impl SyntaxTable {
+    fn new(mut table: &CharTable) -> Self {
+        let mut levels = Vec::new();
+        loop {
+            let data = table.borrow();
+            let entries = data.entries.map(|x| SyntaxEntry::from_obj(*x));
+            levels.push((entries, SyntaxEntry::from_obj(data.default)));
+            match table.parent() {
+                Some(parent) => table = parent,
+                None => return Self { levels },
+            }
+        }
+    }
+
+    /// The syntax of `chr`. Characters that no table sets get their syntax
+    /// from the standard table.
+    pub(crate) fn entry(&self, chr: char) -> SyntaxEntry {
+        for (entries, default) in &self.levels {
+            if let Some(entry) = entries.get(chr.into()).copied().flatten().or(*default) {
+                return entry;
+            }
+        }
+        standard_entry(chr)
+    }
+
+    pub(crate) fn class(&self, chr: char) -> Syntax {
+        self.entry(chr).class
+    }
+
+    pub(crate) fn is_word(&self, chr: char) -> bool {
+        self.class(chr) == Syntax::Word
+    }
+}
+
+/// The address of a syntax table and the number of char-table changes when
+/// a copy of it was made.
+type SyntaxKey = (usize, usize);
+
+thread_local! {
+    /// The copy of the syntax table that was last used.
+    static SYNTAX_CACHE: RefCell<Option<(SyntaxKey, Rc<SyntaxTable>)>> = const { RefCell::new(None) };
+}
+
+/// The standard syntax table, which is created the first time it is needed.
+#[defun]
+pub(crate) fn standard_syntax_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    if let Some(table) = env.standard_syntax_table.as_ref() {
+        return table.bind(cx).untag();
+    }
+    let mut data = CharTableData::new(sym::SYNTAX_TABLE.into(), nil(), 0);
+    for chr in (0..=0x7F_u8).map(char::from) {
+        data.entries.set(chr.into(), standard_entry(chr).to_obj(cx));
+    }
+    let table: Gc<&CharTable> = cx.add_as(data);
+    env.standard_syntax_table.set(table);
+    table.untag()
+}
+
+/// Return the syntax table of the current buffer.
+#[defun]
+pub(crate) fn syntax_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    let buffer = get_current_buffer(env);
+    match env.syntax_tables.get(buffer.tag()) {
+        Some(table) => table.bind(cx).untag(),
+        None => standard_syntax_table(env, cx),
+    }
+}
+
+/// The syntax of the current buffer, as a copy of its syntax table that is
+/// only made again when a char-table changes.
+pub(crate) fn current_syntax(env: &mut Rt<Env>, cx: &Context) -> Rc<SyntaxTable> {
+    let table = syntax_table(env, cx);
+    let key = (std::ptr::from_ref(table) as usize, CharTable::changes());
+    SYNTAX_CACHE.with_borrow_mut(|cache| match cache {
+        Some((cached, syntax)) if *cached == key => syntax.clone(),
+        _ => {
+            let syntax = Rc::new(SyntaxTable::new(table));
+            *cache = Some((key, syntax.clone()));
+            syntax
+        }
+    })
+}
+
+#[defun]
+fn syntax_table_p(object: GcObj) -> bool {
+    match object.untag() {
+        Object::CharTable(table) => table.borrow().subtype == sym::SYNTAX_TABLE,
+        _ => false,
+    }
+}
+
+fn check_syntax_table(table: GcObj) -> Result<&CharTable> {
+    match table.untag() {
+        Object::CharTable(x) if syntax_table_p(table) => Ok(x),
+        _ => bail!("Wrong type argument: syntax-table-p, {table}"),
+    }
+}
+
+/// Make TABLE the syntax table of the current buffer, and return it.
+#[defun]
+fn set_syntax_table<'ob>(table: GcObj<'ob>, env: &mut Rt<Env>) -> Result<GcObj<'ob>> {
+    let syntax = check_syntax_table(table)?;
+    let buffer = get_current_buffer(env);
+    env.syntax_tables.insert(buffer.tag(), syntax.tag());
+    Ok(table)
+}
+
+/// Return a new syntax table with the entries of TABLE, or of the standard
+/// table if it is nil. A table without a parent gets the standard table as
+/// its parent.
+#[defun]
+fn copy_syntax_table<'ob>(
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let standard = standard_syntax_table(env, cx);
+    let table = match table {
+        Some(table) => check_syntax_table(table)?,
+        None => standard,
+    };
+    let data = table.borrow();
+    let copy = CharTableData {
+        subtype: data.subtype,
+        parent: match data.parent.untag() {
+            Object::NIL => standard.into(),
+            _ => data.parent,
+        },
+        // only the standard table has a default
+        default: nil(),
+        extras: data.extras.clone(),
+        entries: data.entries.clone(),
+    };
+    Ok(cx.add(copy))
+}
+
+/// Convert the syntax descriptor string SYNTAX to a raw syntax descriptor,
+/// `(CODE . MATCHING-CHAR)`. The inherit class `@` gives nil.
+#[defun]
+fn string_to_syntax<'ob>(syntax: &str, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    Ok(SyntaxEntry::parse(syntax)?.map_or_else(nil, |x| x.to_obj(cx)))
+}
+
+/// Set the syntax of CHAR in TABLE, or the current syntax table, to the
+/// descriptor string NEWENTRY. CHAR may be a cons (MIN . MAX) to set the
+/// characters in that range. NEWENTRY is the class character, then the
+/// matching character or a space, then any flags:
+///
+/// - `1`, `2`: the first or second character of a comment starter
+/// - `3`, `4`: the first or second character of a comment ender
+/// - `p`: a prefix character
+/// - `b`, `c`: a comment delimiter of style b or c
+/// - `n`: a delimiter of a nestable comment
+#[defun]
+fn modify_syntax_entry<'ob>(
+    char: GcObj,
+    newentry: &str,
+    table: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<bool> {
+    let table = match table {
+        Some(table) => check_syntax_table(table)?,
+        None => syntax_table(env, cx),
+    };
+    let entry = SyntaxEntry::parse(newentry)?.map_or_else(nil, |x| x.to_obj(cx));
+    let (from, to) = match char.untag() {
+        Object::Cons(range) => (
+            char_code(range.car().try_into()?)?,
+            char_code(range.cdr().try_into()?)?,
+        ),
+        _ => {
+            let chr = char_code(char.try_into()?)?;
+            (chr, chr)
+        }
+    };
+    table.borrow_mut().entries.set_range(from, to, entry);
+    Ok(false)
+}
+
+/// Return the descriptor character of the syntax class of CHARACTER in the
+/// current syntax table, such as `?w` for a word constituent.
+#[defun]
+fn char_syntax(character: i64, env: &mut Rt<Env>, cx: &Context) -> Result<i64> {
+    let class = current_syntax(env, cx).class(int_to_char(character)?);
+    Ok(class.to_char() as i64)
+}
+
+/// Return the descriptor character of the syntax class code CLASS.
+#[defun]
+fn syntax_class_to_char(class: i64) -> Result<i64> {
+    match usize::try_from(class).ok().and_then(|x| CLASS_CHARS.get(x)) {
+        Some(chr) => Ok(i64::from(*chr)),
+        None => bail!("Args out of range: {class}"),
+    }
+}
+
+/// Return the character that CHARACTER matches if it is a paren in the
+/// current syntax table, or nil.
+#[defun]
+fn matching_paren<'ob>(character: i64, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let entry = current_syntax(env, cx).entry(int_to_char(character)?);
+    Ok(match (entry.class, entry.matching) {
+        (Syntax::Open | Syntax::Close, Some(chr)) => (chr as i64).into(),
+        _ => nil(),
+    })
+}
+
+/// The number of characters that satisfy `pred`, scanning from `from`
+/// towards `lim`, which may be before it.
+fn scan(data: &BufferData, from: usize, lim: usize, pred: impl FnMut(&char) -> bool) -> usize {
+    if lim >= from {
+        let (before, after) = data.text.slice(from, lim);
+        before.chars().chain(after.chars()).take_while(pred).count()
+    } else {
+        let (before, after) = data.text.slice(lim, from);
+        before
+            .chars()
+            .chain(after.chars())
+            .rev()
+            .take_while(pred)
+            .count()
+    }
+}
+
+/// Move point over `count` words, backwards if it is negative. Returns false
+/// if the edge of the accessible portion was reached first, in which case
+/// point is left there.
+fn scan_words(data: &mut BufferData, count: i64, syntax: &SyntaxTable) -> bool {
+    let is_word = |x| syntax.is_word(x);
+    let (begv, zv) = (data.begv(), data.zv());
+    let mut pos = data.point();
+    for _ in 0..count.unsigned_abs() {
+        let found = if count > 0 {
+            pos += scan(data, pos, zv, |&x| !is_word(x));
+            let found = pos < zv;
+            pos += scan(data, pos, zv, |&x| is_word(x));
+            found
+        } else {
+            pos -= scan(data, pos, begv, |&x| !is_word(x));
+            let found = pos > begv;
+            pos -= scan(data, pos, begv, |&x| is_word(x));
+            found
+        };
+        if !found {
+            data.goto_char(pos);
+            return false;
+        }
+    }
+    data.goto_char(pos);
+    true
+}
+
+/// Move point forward ARG words, or backward if ARG is negative. Returns t,
+/// or nil if the edge of the buffer was reached first.
+#[defun]
+pub(crate) fn forward_word(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let syntax = current_syntax(env, cx);
+    let mut data = get_current_buffer(env).lock()?;
+    Ok(scan_words(&mut data, arg.unwrap_or(1), &syntax))
+}
+
+/// Move point backward ARG words, or forward if ARG is negative.
+#[defun]
+fn backward_word(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    forward_word(Some(-arg.unwrap_or(1)), env, cx)
+}
+
+/// The character classes that can appear as `[:name:]` in a character set.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CharClass {
+    Alnum,
+    Alpha,
+    Ascii,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Multibyte,
+    Nonascii,
+    Print,
+    Punct,
+    Space,
+    Unibyte,
+    Upper,
+    Word,
+    Xdigit,
+}
+
+impl CharClass {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let class = match name {
+            "alnum" => Self::Alnum,
+            "alpha" => Self::Alpha,
+            "ascii" => Self::Ascii,
+            "blank" => Self::Blank,
+            "cntrl" => Self::Cntrl,
+            "digit" => Self::Digit,
+            "graph" => Self::Graph,
+            "lower" => Self::Lower,
+            "multibyte" => Self::Multibyte,
+            "nonascii" => Self::Nonascii,
+            "print" => Self::Print,
+            "punct" => Self::Punct,
+            "space" => Self::Space,
+            "unibyte" => Self::Unibyte,
+            "upper" => Self::Upper,
+            "word" => Self::Word,
+            "xdigit" => Self::Xdigit,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    /// Whether `chr` is in the class. The classes `punct`, `space` and
+    /// `word` depend on `syntax`.
+    pub(crate) fn contains(self, chr: char, syntax: &SyntaxTable) -> bool {
+        match self {
+            Self::Alnum => chr.is_alphanumeric(),
+            Self::Alpha => chr.is_alphabetic(),
+            Self::Ascii | Self::Unibyte => chr.is_ascii(),
+            Self::Blank => chr == ' ' || chr == '\t' || (!chr.is_ascii() && chr.is_whitespace()),
+            Self::Cntrl => chr.is_ascii_control(),
+            Self::Digit => chr.is_ascii_digit(),
+            Self::Graph => !chr.is_control() && !chr.is_whitespace(),
+            Self::Lower => chr.is_lowercase(),
+            Self::Multibyte | Self::Nonascii => !chr.is_ascii(),
+            Self::Print => !chr.is_control(),
+            Self::Punct => match chr.is_ascii() {
+                true => chr.is_ascii_punctuation(),
+                false => !syntax.is_word(chr),
+            },
+            Self::Space => syntax.class(chr) == Syntax::Whitespace,
+            Self::Upper => chr.is_uppercase(),
+            Self::Word => syntax.is_word(chr),
+            Self::Xdigit => chr.is_ascii_hexdigit(),
+        }
+    }
+}
+
+/// A set of characters as written for `skip-chars-forward`, such as
+/// `"^a-z[:digit:]"`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CharSet {
+    pub(crate) negated: bool,
+    pub(crate) ranges: Vec<(char, char)>,
+    pub(crate) classes: Vec<CharClass>,
+}
+
+impl CharSet {
+    fn parse(spec: &str) -> Result<Self> {
+        let mut set = Self::default();
+        let mut rest = spec;
+        if let Some(stripped) = rest.strip_prefix('^') {
+            set.negated = true;
+            rest = stripped;
+        }
+        let mut chars = rest.char_indices().peekable();
+        while let Some((idx, chr)) = chars.next() {
+            if chr == '[' && rest[idx..].starts_with("[:") {
+                if let Some(len) = rest[idx + 2..].find(":]") {
+                    let name = &rest[idx + 2..idx + 2 + len];
+                    let Some(class) = CharClass::from_name(name) else {bail!("Invalid ISO C character class")};
+                    set.classes.push(class);
+                    let end = idx + 2 + len + 2;
+                    while chars.next_if(|x| x.0 < end).is_some() {}
+                    continue;
+                }
+            }
+            let start = match chr {
+                '\\' => match chars.next() {
+                    Some((_, chr)) => chr,
+                    None => break,
+                },
+                _ => chr,
+            };
+            // a `-` at the end of the set is not a range
+            let is_range = chars.peek().is_some_and(|x| x.1 == '-') && {
+                let mut ahead = chars.clone();
+                ahead.next();
+                ahead.peek().is_some()
+            };
+            if is_range {
+                chars.next();
+                let end = match chars.next() {
+                    Some((_, '\\')) => chars.next().map_or('\\', |x| x.1),
+                    Some((_, chr)) => chr,
+                    None => unreachable!(),
+                };
+                // an empty range matches nothing
+                if start <= end {
+                    set.ranges.push((start, end));
+                }
+            } else {
+                set.ranges.push((start, start));
+            }
+        }
+        Ok(set)
+    }
+
+    /// Whether `chr` is in the ranges or classes of the set, ignoring
+    /// negation.
+    pub(crate) fn includes(&self, chr: char, syntax: &SyntaxTable) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(beg, end)| (beg..=end).contains(&chr))
+            || self.classes.iter().any(|class| class.contains(chr, syntax))
+    }
+
+    /// The character the set is made of, if it is a single one.
+    fn single(&self) -> Option<char> {
+        match (&self.ranges[..], &self.classes[..]) {
+            ([(beg, end)], []) if beg == end => Some(*beg),
+            _ => None,
+        }
+    }
+
+    fn contains(&self, chr: char, syntax: &SyntaxTable) -> bool {
+        self.includes(chr, syntax) != self.negated
+    }
+}
+
+/// Move point over the characters that `pred` accepts, forward to LIM or
+/// backward to it. LIM defaults to the edge of the accessible portion and is
+/// clamped to it. Returns the distance moved.
+fn skip(
+    forward: bool,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    pred: impl Fn(char) -> bool,
+) -> Result<i64> {
+    skip_with(forward, lim, env, |data, from, lim| {
+        scan(data, from, lim, |&x| pred(x))
+    })
+}
+
+/// Like [`skip`], with `scan` counting the characters to move over between
+/// two positions.
+fn skip_with(
+    forward: bool,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    scan: impl Fn(&BufferData, usize, usize) -> usize,
+) -> Result<i64> {
+    let mut data = get_current_buffer(env).lock()?;
+    let (point, begv, zv) = (data.point(), data.begv(), data.zv());
+    let lim = lim.map_or(if forward { zv } else { begv }, |x| {
+        x.saturating_sub(1).clamp(begv, zv)
+    });
+    if forward && lim > point {
+        let moved = scan(&data, point, lim);
+        data.goto_char(point + moved);
+        Ok(moved as i64)
+    } else if !forward && lim < point {
+        let moved = scan(&data, point, lim);
+        data.goto_char(point - moved);
+        Ok(-(moved as i64))
+    } else {
+        Ok(0)
+    }
+}
+
+/// Like [`scan`] for the characters equal to `chr`, or the ones that are not
+/// if `negated`. The bytes are compared directly, which finds the next `chr`
+/// with a SIMD search.
+fn scan_char(data: &BufferData, from: usize, lim: usize, chr: char, negated: bool) -> usize {
+    let mut buf = [0; 4];
+    let needle = chr.encode_utf8(&mut buf).as_bytes();
+    let forward = lim >= from;
+    let (before, after) = data.text.slice(from.min(lim), from.max(lim));
+    let halves = if forward {
+        [before, after]
+    } else {
+        [after, before]
+    };
+    let mut moved = 0;
+    for half in halves {
+        let half = half.as_bytes();
+        let len = match (negated, forward) {
+            (true, true) => memmem::find(half, needle).unwrap_or(half.len()),
+            (true, false) => {
+                memmem::rfind(half, needle).map_or(half.len(), |x| half.len() - x - needle.len())
+            }
+            (false, true) => {
+                let run = half.chunks_exact(needle.len()).take_while(|x| *x == needle);
+                run.count() * needle.len()
+            }
+            (false, false) => {
+                let run = half
+                    .rchunks_exact(needle.len())
+                    .take_while(|x| *x == needle);
+                run.count() * needle.len()
+            }
+        };
+        let skipped = if forward {
+            &half[..len]
+        } else {
+            &half[half.len() - len..]
+        };
+        moved += bytecount::num_chars(skipped);
+        if len < half.len() {
+            break;
+        }
+    }
+    moved
+}
+
+/// Move point over the characters in the set STRING, as the skip-chars
+/// commands do.
+fn skip_chars(
+    forward: bool,
+    string: &str,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    let set = CharSet::parse(string)?;
+    match set.single() {
+        Some(chr) => skip_with(forward, lim, env, |data, from, lim| {
+            scan_char(data, from, lim, chr, set.negated)
+        }),
+        None => {
+            let syntax = current_syntax(env, cx);
+            skip(forward, lim, env, |x| set.contains(x, &syntax))
+        }
+    }
+}
+
+/// Move point forward over the characters in STRING, stopping at LIM.
+/// STRING is a set of characters like the inside of a regexp bracket
+/// expression: `a-z` is a range, `[:alpha:]` is a character class, `\\`
+/// quotes the next character, and a leading `^` matches the characters not
+/// in the set. Returns the distance moved.
+#[defun]
+fn skip_chars_forward(
+    string: &str,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    skip_chars(true, string, lim, env, cx)
+}
+
+/// Move point backward over the characters in STRING, stopping at LIM. See
+/// `skip-chars-forward` for the format of STRING. Returns the distance moved,
+/// which is negative or zero.
+#[defun]
+fn skip_chars_backward(
+    string: &str,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    skip_chars(false, string, lim, env, cx)
+}
+
+/// The syntax classes named in the syntax descriptor string SYNTAX, and
+/// whether the leading `^` negates them.
+fn parse_syntax(syntax: &str) -> Result<(bool, Vec<Syntax>)> {
+    let (negated, syntax) = match syntax.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, syntax),
+    };
+    let classes = syntax.chars().map(|x| match Syntax::from_char(x) {
+        Some(class) => Ok(class),
+        None => bail!("Invalid syntax description letter: {x}"),
+    });
+    Ok((negated, classes.collect::<Result<_>>()?))
+}
+
+/// Move point forward over the characters whose syntax class is in SYNTAX,
+/// a string of syntax descriptors such as `"w_"`, stopping at LIM. A leading
+/// `^` skips the characters whose class is not in SYNTAX. Returns the
+/// distance moved.
+#[defun]
+fn skip_syntax_forward(
+    syntax: &str,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    let (negated, classes) = parse_syntax(syntax)?;
+    let table = current_syntax(env, cx);
+    skip(true, lim, env, |x| {
+        classes.contains(&table.class(x)) != negated
+    })
+}
+
+/// Move point backward over the characters whose syntax class is in SYNTAX,
+/// stopping at LIM. See `skip-syntax-forward`. Returns the distance moved,
+/// which is negative or zero.
+#[defun]
+fn skip_syntax_backward(
+    syntax: &str,
+    lim: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<i64> {
+    let (negated, classes) = parse_syntax(syntax)?;
+    let table = current_syntax(env, cx);
+    skip(false, lim, env, |x| {
+        classes.contains(&table.class(x)) != negated
+    })
+}
+
+/// Move point backward over the characters with the expression prefix
+/// class `'` or the prefix flag `p`.
+#[defun]
+fn backward_prefix_chars(env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let syntax = current_syntax(env, cx);
+    skip(false, None, env, |x| {
+        let entry = syntax.entry(x);
+        entry.class == Syntax::Quote || entry.flags.contains(SyntaxFlags::PREFIX)
+    })?;
+    Ok(false)
+}
+
+/// The comment style of a comment fence, which only another fence ends.
+const FENCE_STYLE: u16 = 256;
+
+/// What a syntax table text property says about the characters it covers.
+enum PropSyntax {
+    Entry(SyntaxEntry),
+    Table(Rc<SyntaxTable>),
+}
+
+/// The accessible text of the current buffer with the syntax of each
+/// character, for scanning over expressions and comments. Positions are
+/// 0-based, like the positions of the buffer text.
+struct SyntaxText {
+    chars: Vec<char>,
+    begv: usize,
+    syntax: Rc<SyntaxTable>,
+    /// The runs of text whose `syntax-table` property overrides the syntax
+    /// table, in order
+    props: Vec<(usize, usize, PropSyntax)>,
+}
+
+impl SyntaxText {
+    /// The text of the current buffer. The `syntax-table` text property is
+    /// only used if `parse-sexp-lookup-properties` is non-nil.
+    fn new(env: &mut Rt<Env>, cx: &Context) -> Result<Self> {
+        let syntax = current_syntax(env, cx);
+        let buffer = get_current_buffer(env);
+        let (chars, begv, zv) = {
+            let data = buffer.lock()?;
+            let (begv, zv) = (data.begv(), data.zv());
+            let (before, after) = data.text.slice(begv, zv);
+            (before.chars().chain(after.chars()).collect(), begv, zv)
+        };
+        let props = if variable_set(sym::PARSE_SEXP_LOOKUP_PROPERTIES, env, cx) {
+            let runs = property_runs(buffer, sym::SYNTAX_TABLE, begv, zv, env, cx);
+            let prop_syntax = |value: GcObj| match value.untag() {
+                Object::CharTable(table) if syntax_table_p(value) => {
+                    Some(PropSyntax::Table(Rc::new(SyntaxTable::new(table))))
+                }
+                _ => SyntaxEntry::from_obj(value).map(PropSyntax::Entry),
+            };
+            let props = runs
+                .into_iter()
+                .map(|(beg, end, value)| Some((beg, end, prop_syntax(value)?)));
+            props.flatten().collect()
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            chars,
+            begv,
+            syntax,
+            props,
+        })
+    }
+
+    fn begv(&self) -> usize {
+        self.begv
+    }
+
+    fn zv(&self) -> usize {
+        self.begv + self.chars.len()
+    }
+
+    fn char(&self, pos: usize) -> char {
+        self.chars[pos - self.begv]
+    }
+
+    fn entry(&self, pos: usize) -> SyntaxEntry {
+        let chr = self.char(pos);
+        let idx = self.props.partition_point(|x| x.1 <= pos);
+        match self.props.get(idx) {
+            Some((beg, _, PropSyntax::Entry(entry))) if *beg <= pos => *entry,
+            Some((beg, _, PropSyntax::Table(table))) if *beg <= pos => table.entry(chr),
+            _ => self.syntax.entry(chr),
+        }
+    }
+
+    fn class(&self, pos: usize) -> Syntax {
+        self.entry(pos).class
+    }
+
+    /// Whether the character at `pos` is quoted by an odd number of escape
+    /// characters before it.
+    fn char_quoted(&self, pos: usize) -> bool {
+        let escapes = (self.begv..pos)
+            .rev()
+            .take_while(|x| matches!(self.class(*x), Syntax::Escape | Syntax::CharQuote))
+            .count();
+        escapes % 2 == 1
+    }
+
+    /// Scan over the rest of a comment of `style` from `from`, to at most
+    /// `stop`. `nesting` is the depth of a nestable comment, or -1 for one
+    /// that does not nest, and `prev` is the syntax of the character before
+    /// `from` if a two-character comment ender may start there.
+    fn comment_end(
+        &self,
+        mut from: usize,
+        stop: usize,
+        mut nesting: i64,
+        style: u16,
+        prev: Option<SyntaxEntry>,
+    ) -> CommentScan {
+        use SyntaxFlags as F;
+        if nesting <= 0 {
+            nesting = -1;
+        }
+        let mut syntax = prev;
+        // start in the middle, so that a two-character ender whose first
+        // character is before `from` is found
+        let mut in_middle = prev.is_some();
+        loop {
+            if !in_middle {
+                if from == stop {
+                    let last = syntax.filter(|x| {
+                        matches!(x.class, Syntax::Escape | Syntax::CharQuote)
+                            || x.has(F::COMMENT_END_FIRST)
+                            || (nesting > 0 && x.has(F::COMMENT_START_FIRST))
+                    });
+                    return CommentScan {
+                        found: false,
+                        pos: from,
+                        nesting,
+                        last,
+                    };
+                }
+                let entry = self.entry(from);
+                syntax = Some(entry);
+                let same_style = entry.comment_style(None) == style;
+                if entry.class == Syntax::EndComment && same_style {
+                    if !entry.has(F::NESTED) && nesting < 0 {
+                        break;
+                    }
+                    if entry.has(F::NESTED) && nesting > 0 {
+                        nesting -= 1;
+                        if nesting == 0 {
+                            break;
+                        }
+                    }
+                }
+                if entry.class == Syntax::CommentFence && style == FENCE_STYLE {
+                    break;
+                }
+                if nesting > 0
+                    && entry.class == Syntax::Comment
+                    && entry.has(F::NESTED)
+                    && same_style
+                {
+                    nesting += 1;
+                }
+                from += 1;
+            }
+            in_middle = false;
+            let nests = |x: SyntaxEntry, y: SyntaxEntry| x.has(F::NESTED) || y.has(F::NESTED);
+            if let Some(first) = syntax.filter(|x| from < stop && x.has(F::COMMENT_END_FIRST)) {
+                let other = self.entry(from);
+                if other.has(F::COMMENT_END_SECOND)
+                    && first.comment_style(Some(other)) == style
+                    && (if nests(first, other) {
+                        nesting > 0
+                    } else {
+                        nesting < 0
+                    })
+                {
+                    // the first character can not also start a delimiter
+                    syntax = None;
+                    nesting -= 1;
+                    if nesting <= 0 {
+                        break;
+                    }
+                    from += 1;
+                }
+            }
+            if let Some(first) = syntax.filter(|x| from < stop && x.has(F::COMMENT_START_FIRST)) {
+                let other = self.entry(from);
+                if nesting > 0
+                    && other.has(F::COMMENT_START_SECOND)
+                    && other.comment_style(Some(first)) == style
+                    && nests(first, other)
+                {
+                    syntax = None;
+                    from += 1;
+                    nesting += 1;
+                }
+            }
+        }
+        CommentScan {
+            found: true,
+            pos: from,
+            nesting,
+            last: None,
+        }
+    }
+
+    /// The start of the comment of `style` that ends with the comment ender
+    /// at `pos`, if it is one. The text is parsed from the start of the
+    /// accessible portion, so comment enders in strings are ignored.
+    fn comment_start(&self, pos: usize, style: u16) -> Option<usize> {
+        let state = self.parse_state(pos);
+        (state.comment != 0 && state.style == style)
+            .then_some(state.comstr_start)
+            .flatten()
+    }
+
+    /// The state of a parse from the start of the accessible portion to
+    /// `pos`.
+    fn parse_state(&self, pos: usize) -> ParseState {
+        let mut parser = Parser::new(self, self.begv, pos, ParseState::default());
+        let flow = parser.run(None, false, CommentStop::Never);
+        parser.finish(flow).0
+    }
+
+    /// Scan forward over `count` comments and the whitespace around them,
+    /// or backward if `count` is negative. Returns the position reached and
+    /// whether all of the comments were found.
+    fn forward_comments(&self, mut from: usize, mut count: i64) -> (usize, bool) {
+        use SyntaxFlags as F;
+        let (begv, zv) = (self.begv(), self.zv());
+        while count > 0 {
+            let (code, style, nested) = loop {
+                if from == zv {
+                    return (from, false);
+                }
+                let entry = self.entry(from);
+                let chr = self.char(from);
+                let mut code = entry.class;
+                let mut style = entry.comment_style(None);
+                let mut nested = entry.has(F::NESTED);
+                from += 1;
+                if from < zv && entry.has(F::COMMENT_START_FIRST) {
+                    let other = self.entry(from);
+                    if other.has(F::COMMENT_START_SECOND) {
+                        code = Syntax::Comment;
+                        style = other.comment_style(Some(entry));
+                        nested |= other.has(F::NESTED);
+                        from += 1;
+                    }
+                }
+                let whitespace =
+                    code == Syntax::Whitespace || (code == Syntax::EndComment && chr == '\n');
+                if !whitespace {
+                    break (code, style, nested);
+                }
+            };
+            let style = match code {
+                Syntax::CommentFence => FENCE_STYLE,
+                Syntax::Comment => style,
+                _ => return (from - 1, false),
+            };
+            let scan = self.comment_end(from, zv, if nested { 1 } else { -1 }, style, None);
+            if !scan.found {
+                return (scan.pos, false);
+            }
+            from = scan.pos + 1;
+            count -= 1;
+        }
+        while count < 0 {
+            loop {
+                if from <= begv {
+                    return (begv, false);
+                }
+                from -= 1;
+                let quoted = self.char_quoted(from);
+                let entry = self.entry(from);
+                let chr = self.char(from);
+                let mut code = entry.class;
+                let mut style = entry.comment_style(None);
+                if from > begv
+                    && entry.has(F::COMMENT_END_SECOND)
+                    && self.entry(from - 1).has(F::COMMENT_END_FIRST)
+                    && !self.char_quoted(from - 1)
+                {
+                    from -= 1;
+                    code = Syntax::EndComment;
+                    style = self.entry(from).comment_style(Some(entry));
+                }
+                match code {
+                    Syntax::CommentFence => {
+                        let fence = (begv..from).rev().find(|x| {
+                            self.class(*x) == Syntax::CommentFence && !self.char_quoted(*x)
+                        });
+                        match fence {
+                            Some(pos) => {
+                                from = pos;
+                                break;
+                            }
+                            None => return (from + 1, false),
+                        }
+                    }
+                    Syntax::EndComment => match self.comment_start(from, style) {
+                        Some(start) => {
+                            from = start;
+                            break;
+                        }
+                        // a newline that does not end a comment is whitespace
+                        None if chr == '\n' => {}
+                        None if entry.class == Syntax::EndComment => return (from + 1, false),
+                        None => return (from + 2, false),
+                    },
+                    Syntax::Whitespace if !quoted => {}
+                    _ => return (from + 1, false),
+                }
+            }
+            count += 1;
+        }
+        (from, true)
+    }
+
+    /// Scan over `count` balanced expressions from `from`, or backward if
+    /// `count` is negative, starting at paren depth `depth`. A scan over
+    /// lists only stops at parens, while a scan over sexps (`sexp`) also
+    /// stops after a symbol or string at depth 0. Returns `None` if the
+    /// edge of the text is reached between expressions.
+    #[allow(clippy::too_many_lines)]
+    fn scan_lists(
+        &self,
+        from: usize,
+        mut count: i64,
+        mut depth: i64,
+        sexp: bool,
+        ignore_comments: bool,
+    ) -> Result<Option<usize>, ScanError> {
+        use SyntaxFlags as F;
+        let (begv, zv) = (self.begv(), self.zv());
+        let min_depth = depth.min(0);
+        let mut from = from.clamp(begv, zv);
+        let mut last_good = from;
+        let mut math_exit = false;
+        let unbalanced =
+            |last_good, from| ScanError::new("Unbalanced parentheses", last_good, from);
+        let premature = |last_good, from| {
+            ScanError::new("Containing expression ends prematurely", last_good, from)
+        };
+        while count > 0 {
+            'done: {
+                while from < zv {
+                    let start = from;
+                    let entry = self.entry(from);
+                    let mut code = entry.class;
+                    let mut style = entry.comment_style(None);
+                    let mut nested = entry.has(F::NESTED);
+                    if depth == min_depth {
+                        last_good = from;
+                    }
+                    from += 1;
+                    if from < zv && entry.has(F::COMMENT_START_FIRST) && ignore_comments {
+                        let other = self.entry(from);
+                        if other.has(F::COMMENT_START_SECOND) {
+                            code = Syntax::Comment;
+                            style = other.comment_style(Some(entry));
+                            nested |= other.has(F::NESTED);
+                            from += 1;
+                        }
+                    }
+                    if entry.has(F::PREFIX) {
+                        continue;
+                    }
+                    let mut close = false;
+                    match code {
+                        Syntax::Escape | Syntax::CharQuote | Syntax::Word | Syntax::Symbol => {
+                            if matches!(code, Syntax::Escape | Syntax::CharQuote) {
+                                if from == zv {
+                                    return Err(unbalanced(last_good, from));
+                                }
+                                from += 1;
+                            }
+                            if depth != 0 || !sexp {
+                                continue;
+                            }
+                            // a symbol counts as a sexp, which ends after it
+                            while from < zv {
+                                match self.class(from) {
+                                    Syntax::Escape | Syntax::CharQuote => {
+                                        from += 1;
+                                        if from == zv {
+                                            return Err(unbalanced(last_good, from));
+                                        }
+                                    }
+                                    Syntax::Word | Syntax::Symbol | Syntax::Quote => {}
+                                    _ => break,
+                                }
+                                from += 1;
+                            }
+                            break 'done;
+                        }
+                        Syntax::Comment | Syntax::CommentFence if ignore_comments => {
+                            let style = if code == Syntax::Comment {
+                                style
+                            } else {
+                                FENCE_STYLE
+                            };
+                            let nesting = if nested { 1 } else { -1 };
+                            let scan = self.comment_end(from, zv, nesting, style, None);
+                            from = scan.pos;
+                            if !scan.found {
+                                if depth == 0 {
+                                    break 'done;
+                                }
+                                return Err(unbalanced(last_good, from));
+                            }
+                            from += 1;
+                            continue;
+                        }
+                        Syntax::Math if sexp => {
+                            if from != zv && self.char(from) == self.char(start) {
+                                from += 1;
+                            }
+                            close = math_exit;
+                            math_exit = !math_exit;
+                        }
+                        Syntax::Open => {}
+                        Syntax::Close => close = true,
+                        Syntax::String | Syntax::StringFence => {
+                            let term = self.char(start);
+                            loop {
+                                if from >= zv {
+                                    return Err(unbalanced(last_good, from));
+                                }
+                                let class = self.class(from);
+                                let ends = match code {
+                                    Syntax::String => self.char(from) == term && class == code,
+                                    _ => class == Syntax::StringFence,
+                                };
+                                if ends {
+                                    break;
+                                }
+                                if matches!(class, Syntax::Escape | Syntax::CharQuote) {
+                                    from += 1;
+                                }
+                                from += 1;
+                            }
+                            from += 1;
+                            if depth == 0 && sexp {
+                                break 'done;
+                            }
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                    if close {
+                        depth -= 1;
+                        if depth == 0 {
+                            break 'done;
+                        }
+                        if depth < min_depth {
+                            return Err(premature(last_good, from));
+                        }
+                    } else {
+                        depth += 1;
+                        if depth == 0 {
+                            break 'done;
+                        }
+                    }
+                }
+                // the end of the text, which is only an error within a list
+                if depth != 0 {
+                    return Err(unbalanced(last_good, from));
+                }
+                return Ok(None);
+            }
+            count -= 1;
+        }
+        while count < 0 {
+            'done: {
+                while from > begv {
+                    from -= 1;
+                    let entry = self.entry(from);
+                    let chr = self.char(from);
+                    let mut code = entry.class;
+                    if depth == min_depth {
+                        last_good = from;
+                    }
+                    let mut style = entry.comment_style(None);
+                    if from > begv
+                        && entry.has(F::COMMENT_END_SECOND)
+                        && self.entry(from - 1).has(F::COMMENT_END_FIRST)
+                        && ignore_comments
+                    {
+                        from -= 1;
+                        code = Syntax::EndComment;
+                        let other = self.entry(from);
+                        style = other.comment_style(Some(entry));
+                    }
+                    // quoting makes anything but a comment ender part of a word
+                    if code != Syntax::EndComment && self.char_quoted(from) {
+                        from -= 1;
+                        code = Syntax::Word;
+                    } else if entry.has(F::PREFIX) {
+                        continue;
+                    }
+                    let mut open = false;
+                    match code {
+                        Syntax::Word | Syntax::Symbol | Syntax::Escape | Syntax::CharQuote => {
+                            if depth != 0 || !sexp {
+                                continue;
+                            }
+                            while from > begv {
+                                let prev = from - 1;
+                                if self.class(prev) == Syntax::EndComment {
+                                    break;
+                                }
+                                if self.char_quoted(prev) {
+                                    from -= 2;
+                                } else if matches!(
+                                    self.class(prev),
+                                    Syntax::Word | Syntax::Symbol | Syntax::Quote
+                                ) {
+                                    from -= 1;
+                                } else {
+                                    break;
+                                }
+                            }
+                            break 'done;
+                        }
+                        Syntax::Math if sexp => {
+                            if from > begv && self.char(from - 1) == chr {
+                                from -= 1;
+                            }
+                            open = math_exit;
+                            math_exit = !math_exit;
+                        }
+                        Syntax::Close => {}
+                        Syntax::Open => open = true,
+                        Syntax::EndComment if ignore_comments => {
+                            if let Some(start) = self.comment_start(from, style) {
+                                from = start;
+                            }
+                            continue;
+                        }
+                        Syntax::CommentFence | Syntax::StringFence => {
+                            loop {
+                                if from == begv {
+                                    return Err(unbalanced(last_good, from));
+                                }
+                                from -= 1;
+                                if !self.char_quoted(from) && self.class(from) == code {
+                                    break;
+                                }
+                            }
+                            if code == Syntax::StringFence && depth == 0 && sexp {
+                                break 'done;
+                            }
+                            continue;
+                        }
+                        Syntax::String => {
+                            loop {
+                                if from == begv {
+                                    return Err(unbalanced(last_good, from));
+                                }
+                                from -= 1;
+                                if !self.char_quoted(from)
+                                    && self.char(from) == chr
+                                    && self.class(from) == Syntax::String
+                                {
+                                    break;
+                                }
+                            }
+                            if depth == 0 && sexp {
+                                break 'done;
+                            }
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                    if open {
+                        depth -= 1;
+                        if depth == 0 {
+                            break 'done;
+                        }
+                        if depth < min_depth {
+                            return Err(premature(last_good, from));
+                        }
+                    } else {
+                        depth += 1;
+                        if depth == 0 {
+                            break 'done;
+                        }
+                    }
+                }
+                if depth != 0 {
+                    return Err(unbalanced(last_good, from));
+                }
+                return Ok(None);
+            }
+            count += 1;
+        }
+        Ok(Some(from))
+    }
+}
+
+/// The result of [`SyntaxText::comment_end`].
+struct CommentScan {
+    /// Whether the end of the comment was found
+    found: bool,
+    /// The last character of the comment ender, or where the scan stopped
+    pos: usize,
+    nesting: i64,
+    /// The syntax of the last character if it may be the first character
+    /// of a two-character delimiter
+    last: Option<SyntaxEntry>,
+}
+
+/// A scan over lists that found unbalanced parens, signaled as `scan-error`.
+struct ScanError {
+    message: &'static str,
+    /// The start of the last complete expression
+    last_good: usize,
+    /// Where the scan stopped
+    from: usize,
+}
+
+impl ScanError {
+    fn new(message: &'static str, last_good: usize, from: usize) -> Self {
+        Self {
+            message,
+            last_good,
+            from,
+        }
+    }
+
+    fn signal(self, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+        let data = list![self.message, self.last_good + 1, self.from + 1; cx];
+        EvalError::signal(sym::SCAN_ERROR.into(), data, env).into()
+    }
+}
+
+/// The character that ends a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringEnd {
+    Char(char),
+    /// Another string fence
+    Fence,
+}
+
+/// The state of a parse by `parse-partial-sexp`.
+#[derive(Debug, Clone, Default)]
+struct ParseState {
+    depth: i64,
+    /// The start of the innermost containing list
+    innermost: Option<usize>,
+    /// The start of the last complete expression
+    last_sexp: Option<usize>,
+    string: Option<StringEnd>,
+    /// 0 outside of a comment, -1 in a comment that does not nest, and the
+    /// nesting depth in one that does
+    comment: i64,
+    /// Whether the parse ended after a quoting character
+    quoted: bool,
+    min_depth: i64,
+    /// The style of the comment, or [`FENCE_STYLE`]
+    style: u16,
+    /// The start of the comment or string
+    comstr_start: Option<usize>,
+    /// The start of each containing list, outermost first
+    open: Vec<usize>,
+    /// The syntax of the last character if it may be the first character
+    /// of a two-character delimiter
+    prev_syntax: Option<SyntaxEntry>,
+}
+
+impl ParseState {
+    /// The state described by a list like the one `parse-partial-sexp`
+    /// returns.
+    fn from_obj(state: GcObj) -> Result<Self> {
+        let elems: Vec<GcObj> = state.as_list()?.collect::<Result<_>>()?;
+        let elem = |n: usize| elems.get(n).map_or(Object::NIL, |x| x.untag());
+        let pos = |obj: Object| match obj {
+            Object::Int(pos) => usize::try_from(pos - 1).ok(),
+            _ => None,
+        };
+        let mut open = Vec::new();
+        if let Some(starts) = elems.get(9) {
+            for start in starts.as_list()? {
+                open.extend(pos(start?.untag()));
+            }
+        }
+        Ok(Self {
+            depth: match elem(0) {
+                Object::Int(depth) => depth,
+                _ => 0,
+            },
+            string: match elem(3) {
+                Object::NIL => None,
+                Object::Int(chr) => int_to_char(chr).ok().map(StringEnd::Char),
+                _ => Some(StringEnd::Fence),
+            },
+            comment: match elem(4) {
+                Object::NIL => 0,
+                Object::Int(nesting) => nesting,
+                _ => -1,
+            },
+            quoted: elem(5) != Object::NIL,
+            style: match elem(7) {
+                Object::NIL => 0,
+                Object::Int(style @ 0..=255) => style as u16,
+                _ => FENCE_STYLE,
+            },
+            comstr_start: pos(elem(8)),
+            open,
+            prev_syntax: match elem(10) {
+                Object::Int(code) => SyntaxEntry::from_code(code),
+                _ => None,
+            },
+            ..Self::default()
+        })
+    }
+
+    fn to_obj<'ob>(&self, cx: &'ob Context) -> GcObj<'ob> {
+        let pos = |pos: Option<usize>| pos.map_or_else(nil, |x| (x + 1).into());
+        let string = match self.string {
+            None => nil(),
+            Some(StringEnd::Char(chr)) => (chr as i64).into(),
+            Some(StringEnd::Fence) => true.into(),
+        };
+        let comment = match self.comment {
+            0 => nil(),
+            ..=-1 => true.into(),
+            nesting => nesting.into(),
+        };
+        let style = match self.style {
+            0 => nil(),
+            FENCE_STYLE => sym::SYNTAX_TABLE.into(),
+            style => i64::from(style).into(),
+        };
+        let in_comstr = self.comment != 0 || self.string.is_some();
+        let comstr_start = if in_comstr {
+            pos(self.comstr_start)
+        } else {
+            nil()
+        };
+        let open: Vec<GcObj> = self.open.iter().map(|x| (x + 1).into()).collect();
+        let prev_syntax = self.prev_syntax.map_or_else(nil, |x| x.code().into());
+        list![
+            self.depth,
+            pos(self.innermost),
+            pos(self.last_sexp),
+            string,
+            comment,
+            self.quoted,
+            self.min_depth,
+            style,
+            comstr_start,
+            slice_into_list(&open, None, cx),
+            prev_syntax;
+            cx
+        ]
+    }
+}
+
+/// Where a parse stops at a comment or string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommentStop {
+    Never,
+    /// After the start of a comment
+    Comment,
+    /// After the start or end of a comment or string
+    Boundary,
+}
+
+/// How a part of a parse ended.
+enum Flow {
+    Continue,
+    Done,
+    /// Stop before the start of the last expression
+    Before,
+    /// The text ended after a quoting character
+    Quoted,
+}
+
+/// The position and level of a list.
+#[derive(Debug, Clone, Copy, Default)]
+struct Level {
+    /// The start of the last expression started at this level
+    last: Option<usize>,
+    /// The start of the last complete expression
+    prev: Option<usize>,
+}
+
+/// A parse of the text by `parse-partial-sexp`, which keeps the syntax of
+/// the last character so that two-character comment delimiters are found.
+struct Parser<'a> {
+    text: &'a SyntaxText,
+    end: usize,
+    from: usize,
+    prev_from: usize,
+    prev_syntax: Option<SyntaxEntry>,
+    prev_prev_syntax: Option<SyntaxEntry>,
+    level: Level,
+    /// The levels of the containing lists
+    outer: Vec<Level>,
+    state: ParseState,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a SyntaxText, from: usize, end: usize, state: ParseState) -> Self {
+        let outer = state.open.iter().map(|x| Level {
+            last: Some(*x),
+            prev: None,
+        });
+        Self {
+            text,
+            end,
+            from,
+            prev_from: from,
+            prev_syntax: state.prev_syntax,
+            prev_prev_syntax: None,
+            level: Level::default(),
+            outer: outer.collect(),
+            state: ParseState {
+                min_depth: state.depth,
+                ..state
+            },
+        }
+    }
+
+    /// Move over the next character and return its syntax.
+    fn advance(&mut self) -> SyntaxEntry {
+        let entry = self.text.entry(self.from);
+        self.prev_from = self.from;
+        self.prev_prev_syntax = self.prev_syntax;
+        self.prev_syntax = Some(entry);
+        self.from += 1;
+        entry
+    }
+
+    fn run(&mut self, target: Option<i64>, stop_before: bool, stop: CommentStop) -> Flow {
+        let start_quoted = std::mem::take(&mut self.state.quoted);
+        let flow = if self.state.comment != 0 {
+            self.comment_rest(stop)
+        } else if self.state.string.is_some() {
+            self.string_rest(start_quoted, stop)
+        } else if start_quoted {
+            self.symbol_rest(true)
+        } else {
+            Flow::Continue
+        };
+        if !matches!(flow, Flow::Continue) {
+            return flow;
+        }
+        while self.from < self.end {
+            let code = self.next_class();
+            if has_flag(self.prev_syntax, SyntaxFlags::PREFIX) {
+                continue;
+            }
+            let flow = match code {
+                Syntax::Escape | Syntax::CharQuote | Syntax::Word | Syntax::Symbol => {
+                    self.start_symbol(code, stop_before)
+                }
+                Syntax::Comment if stop != CommentStop::Never => Flow::Done,
+                Syntax::Comment => self.comment_rest(stop),
+                Syntax::Open => self.open(target, stop_before),
+                Syntax::Close => self.close(target),
+                Syntax::String | Syntax::StringFence => self.start_string(code, stop_before, stop),
+                _ => Flow::Continue,
+            };
+            if !matches!(flow, Flow::Continue) {
+                return flow;
+            }
+        }
+        Flow::Done
+    }
+
+    /// Move over the next character, or the two characters of a comment
+    /// starter, and return its syntax class. The start of a comment is
+    /// recorded in the state.
+    fn next_class(&mut self) -> Syntax {
+        use SyntaxFlags as F;
+        let second = self.text.entry(self.from);
+        if has_flag(self.prev_syntax, F::COMMENT_START_FIRST) && second.has(F::COMMENT_START_SECOND)
+        {
+            let first = self.prev_syntax;
+            self.state.style = second.comment_style(first);
+            let nested = has_flag(first, F::NESTED) || second.has(F::NESTED);
+            self.state.comment = if nested { 1 } else { -1 };
+            self.state.comstr_start = Some(self.prev_from);
+            self.advance();
+            // the syntax was used by the comment starter
+            self.prev_syntax = None;
+            return Syntax::Comment;
+        }
+        let entry = self.advance();
+        match entry.class {
+            Syntax::CommentFence => {
+                self.state.style = FENCE_STYLE;
+                self.state.comment = -1;
+                self.state.comstr_start = Some(self.prev_from);
+                Syntax::Comment
+            }
+            Syntax::Comment => {
+                self.state.style = entry.comment_style(None);
+                self.state.comment = if entry.has(F::NESTED) { 1 } else { -1 };
+                self.state.comstr_start = Some(self.prev_from);
+                Syntax::Comment
+            }
+            class => class,
+        }
+    }
+
+    /// Start a symbol at the last character, which has syntax `code`.
+    fn start_symbol(&mut self, code: Syntax, stop_before: bool) -> Flow {
+        if stop_before {
+            return Flow::Before;
+        }
+        self.level.last = Some(self.prev_from);
+        let quoted = matches!(code, Syntax::Escape | Syntax::CharQuote);
+        self.symbol_rest(quoted)
+    }
+
+    /// Enter the list opened by the last character.
+    fn open(&mut self, target: Option<i64>, stop_before: bool) -> Flow {
+        if stop_before {
+            return Flow::Before;
+        }
+        self.state.depth += 1;
+        self.level.last = Some(self.prev_from);
+        self.outer.push(self.level);
+        self.level = Level::default();
+        if target == Some(self.state.depth) {
+            return Flow::Done;
+        }
+        Flow::Continue
+    }
+
+    /// Leave the list closed by the last character.
+    fn close(&mut self, target: Option<i64>) -> Flow {
+        self.state.depth -= 1;
+        self.state.min_depth = self.state.min_depth.min(self.state.depth);
+        if let Some(level) = self.outer.pop() {
+            self.level = level;
+        }
+        self.level.prev = self.level.last;
+        if target == Some(self.state.depth) {
+            return Flow::Done;
+        }
+        Flow::Continue
+    }
+
+    /// Start a string at the last character, which has syntax `code`.
+    fn start_string(&mut self, code: Syntax, stop_before: bool, stop: CommentStop) -> Flow {
+        self.state.comstr_start = Some(self.prev_from);
+        if stop_before {
+            return Flow::Before;
+        }
+        self.level.last = Some(self.prev_from);
+        self.state.string = Some(match code {
+            Syntax::String => StringEnd::Char(self.text.char(self.prev_from)),
+            _ => StringEnd::Fence,
+        });
+        if stop == CommentStop::Boundary {
+            return Flow::Done;
+        }
+        self.string_rest(false, stop)
+    }
+
+    /// Scan over the rest of a symbol, after a quoting character if
+    /// `quoted`.
+    fn symbol_rest(&mut self, quoted: bool) -> Flow {
+        use SyntaxFlags as F;
+        if quoted {
+            if self.from == self.end {
+                return Flow::Quoted;
+            }
+            self.advance();
+        }
+        while self.from < self.end {
+            let entry = self.text.entry(self.from);
+            if has_flag(self.prev_syntax, F::COMMENT_START_FIRST)
+                && entry.has(F::COMMENT_START_SECOND)
+            {
+                break;
+            }
+            match entry.class {
+                Syntax::Escape | Syntax::CharQuote => {
+                    self.advance();
+                    if self.from == self.end {
+                        return Flow::Quoted;
+                    }
+                }
+                Syntax::Word | Syntax::Symbol | Syntax::Quote => {}
+                _ => break,
+            }
+            self.advance();
+        }
+        self.level.prev = self.level.last;
+        Flow::Continue
+    }
+
+    fn comment_rest(&mut self, stop: CommentStop) -> Flow {
+        let prev = if self.from == self.text.begv() {
+            None
+        } else {
+            self.prev_syntax
+        };
+        let state = &self.state;
+        let scan = self
+            .text
+            .comment_end(self.from, self.end, state.comment, state.style, prev);
+        self.from = scan.pos;
+        self.state.comment = scan.nesting;
+        self.prev_syntax = scan.last;
+        if !scan.found {
+            return Flow::Done;
+        }
+        self.advance();
+        self.state.comment = 0;
+        self.state.style = 0;
+        self.prev_syntax = None;
+        match stop {
+            CommentStop::Boundary => Flow::Done,
+            _ => Flow::Continue,
+        }
+    }
+
+    /// Scan over the rest of a string, after a quoting character if
+    /// `quoted`.
+    fn string_rest(&mut self, mut quoted: bool, stop: CommentStop) -> Flow {
+        let Some(string) = self.state.string else {return Flow::Continue};
+        loop {
+            if !quoted {
+                if self.from >= self.end {
+                    return Flow::Done;
+                }
+                let class = self.text.class(self.from);
+                match (string, class) {
+                    (StringEnd::Char(end), Syntax::String) if self.text.char(self.from) == end => {
+                        break
+                    }
+                    (StringEnd::Fence, Syntax::StringFence) => break,
+                    (_, Syntax::Escape | Syntax::CharQuote) => {
+                        self.advance();
+                        quoted = true;
+                    }
+                    _ => {}
+                }
+            }
+            if quoted {
+                if self.from >= self.end {
+                    return Flow::Quoted;
+                }
+                quoted = false;
+            }
+            self.advance();
+        }
+        self.state.string = None;
+        self.level.prev = self.level.last;
+        self.advance();
+        match stop {
+            CommentStop::Boundary => Flow::Done,
+            _ => Flow::Continue,
+        }
+    }
+
+    /// The state where the parse stopped, and its position.
+    fn finish(mut self, flow: Flow) -> (ParseState, usize) {
+        use SyntaxFlags as F;
+        match flow {
+            Flow::Before => {
+                self.from = self.prev_from;
+                self.prev_syntax = self.prev_prev_syntax;
+            }
+            Flow::Quoted => self.state.quoted = true,
+            Flow::Continue | Flow::Done => {}
+        }
+        let mut state = self.state;
+        state.last_sexp = self.level.prev;
+        state.innermost = self.outer.last().and_then(|x| x.last);
+        state.open = self.outer.iter().filter_map(|x| x.last).collect();
+        let first = has_flag(self.prev_syntax, F::COMMENT_START_FIRST)
+            || has_flag(self.prev_syntax, F::COMMENT_END_FIRST);
+        state.prev_syntax = self.prev_syntax.filter(|_| first || state.quoted);
+        (state, self.from)
+    }
+}
+
+fn variable_set(var: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars.get(var).is_some_and(|x| !x.bind(cx).nil())
+}
+
+/// Parse the text from FROM to TO as Lisp expressions, and return the state
+/// at TO: a list of
+///
+/// 0. the depth in parens
+/// 1. the start of the innermost containing list
+/// 2. the start of the last complete expression
+/// 3. the character that ends the string point is in, t for a string that
+///    a string fence ends, or nil
+/// 4. nil outside of a comment, t in a comment that does not nest, and the
+///    nesting depth otherwise
+/// 5. t if the text ends after a quoting character
+/// 6. the least depth in parens of the parse
+/// 7. the style of the comment, or `syntax-table` for a comment fence
+/// 8. the start of the comment or string
+/// 9. the start of each containing list, outermost first
+/// 10. the syntax of the last character if it may start a two-character
+///     delimiter
+///
+/// The parse stops early at depth TARGETDEPTH, before the start of an
+/// expression if STOPBEFORE, and after the start of a comment if
+/// COMMENTSTOP is non-nil, or the start or end of a comment or string if it
+/// is `syntax-table`. OLDSTATE is the state at FROM, as returned by an
+/// earlier parse. Point is left where the parse stopped.
+#[defun]
+#[allow(clippy::too_many_arguments)]
+fn parse_partial_sexp<'ob>(
+    from: usize,
+    to: usize,
+    targetdepth: Option<i64>,
+    stopbefore: Option<()>,
+    oldstate: Option<GcObj>,
+    commentstop: Option<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    if to < from {
+        bail!("End position is smaller than start position");
+    }
+    let state = match oldstate {
+        Some(state) => ParseState::from_obj(state)?,
+        None => ParseState::default(),
+    };
+    let stop = match commentstop {
+        None => CommentStop::Never,
+        Some(x) if x == sym::SYNTAX_TABLE => CommentStop::Boundary,
+        Some(_) => CommentStop::Comment,
+    };
+    let text = SyntaxText::new(env, cx)?;
+    let (from, to) = validate_region(from, to, (text.begv(), text.zv()), env, cx)?;
+    let mut parser = Parser::new(&text, from, to, state);
+    let flow = parser.run(targetdepth, stopbefore.is_some(), stop);
+    let (state, pos) = parser.finish(flow);
+    get_current_buffer(env).lock()?.goto_char(pos);
+    Ok(state.to_obj(cx))
+}
+
+/// Return the state of the parse from the start of the accessible portion to
+/// POS, which defaults to point, as `parse-partial-sexp` does. Point is left
+/// at POS.
+#[defun]
+fn syntax_ppss<'ob>(pos: Option<usize>, env: &mut Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let (begv, pos) = {
+        let data = get_current_buffer(env).lock()?;
+        (data.begv() + 1, pos.unwrap_or_else(|| data.point() + 1))
+    };
+    parse_partial_sexp(begv, pos, None, None, None, None, env, cx)
+}
+
+/// Scan from FROM over COUNT balanced parenthetical groupings, or backward
+/// if COUNT is negative, and return the position reached. The scan starts
+/// at paren depth DEPTH and stops when the depth returns to 0 after a
+/// group, so a DEPTH of 1 finds the end of the containing list. Returns nil
+/// if the edge of the accessible portion is reached between groups, and
+/// signals `scan-error` if it is reached within one.
+#[defun]
+fn scan_lists<'ob>(
+    from: i64,
+    count: i64,
+    depth: i64,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let from = usize::try_from(from - 1).unwrap_or(0);
+    let pos = scan_expressions(from, count, depth, false, env, cx)?;
+    Ok(pos.map_or_else(nil, |x| (x + 1).into()))
+}
+
+/// Scan from FROM over COUNT balanced expressions, or backward if COUNT is
+/// negative, and return the position reached. An expression is a symbol, a
+/// string or a parenthetical grouping. Returns nil if the edge of the
+/// accessible portion is reached between expressions.
+#[defun]
+fn scan_sexps<'ob>(
+    from: i64,
+    count: i64,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let from = usize::try_from(from - 1).unwrap_or(0);
+    let pos = scan_expressions(from, count, 0, true, env, cx)?;
+    Ok(pos.map_or_else(nil, |x| (x + 1).into()))
+}
+
+/// Scan over lists or sexps as `scan-lists` and `scan-sexps` do, signaling
+/// `scan-error` if the parens are unbalanced.
+fn scan_expressions(
+    from: usize,
+    count: i64,
+    depth: i64,
+    sexp: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Option<usize>> {
+    let text = SyntaxText::new(env, cx)?;
+    let ignore_comments = variable_set(sym::PARSE_SEXP_IGNORE_COMMENTS, env, cx);
+    text.scan_lists(from, count, depth, sexp, ignore_comments)
+        .map_err(|e| e.signal(env, cx))
+}
+
+/// Move point forward over COUNT comments and the whitespace around them,
+/// or backward if COUNT is negative. Returns t if all of the comments were
+/// moved over, and otherwise nil with point at the first character that is
+/// not part of a comment or whitespace.
+#[defun]
+fn forward_comment(count: i64, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let text = SyntaxText::new(env, cx)?;
+    let mut data = get_current_buffer(env).lock()?;
+    let (pos, found) = text.forward_comments(data.point(), count);
+    data.goto_char(pos);
+    Ok(found)
+}
+
+/// Move point over `count` lists or sexps from point, starting at paren
+/// depth `depth`, or to the edge of the accessible portion if the scan
+/// reaches it between expressions.
+fn move_over(count: i64, depth: i64, sexp: bool, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let point = get_current_buffer(env).lock()?.point();
+    let pos = scan_expressions(point, count, depth, sexp, env, cx)?;
+    let mut data = get_current_buffer(env).lock()?;
+    let end = if count > 0 { data.zv() } else { data.begv() };
+    data.goto_char(pos.unwrap_or(end));
+    Ok(())
+}
+
+/// Move forward across ARG balanced expressions, or backward if ARG is
+/// negative. Moving backward also moves over the prefix characters before
+/// the expression.
+#[defun]
+fn forward_sexp(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let arg = arg.unwrap_or(1);
+    move_over(arg, 0, true, env, cx)?;
+    if arg < 0 {
+        backward_prefix_chars(env, cx)?;
+    }
+    Ok(false)
+}
+
+/// Move backward across ARG balanced expressions, or forward if ARG is
+/// negative.
+#[defun]
+fn backward_sexp(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    forward_sexp(Some(-arg.unwrap_or(1)), env, cx)
+}
+
+/// Move forward across ARG balanced groups of parentheses, or backward if
+/// ARG is negative.
+#[defun]
+fn forward_list(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    move_over(arg.unwrap_or(1), 0, false, env, cx)?;
+    Ok(false)
+}
+
+/// Move forward down ARG levels of parentheses, or backward if ARG is
+/// negative.
+#[defun]
+fn down_list(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let arg = arg.unwrap_or(1);
+    for _ in 0..arg.unsigned_abs() {
+        move_over(arg.signum(), -1, false, env, cx)?;
+    }
+    Ok(false)
+}
+
+/// Move forward out of ARG levels of parentheses, or backward if ARG is
+/// negative. If ESCAPE-STRINGS is non-nil, a string that point is in counts
+/// as a level. NO-SYNTAX-CROSSING is accepted for compatibility and ignored.
+#[defun]
+fn up_list(
+    arg: Option<i64>,
+    escape_strings: Option<()>,
+    no_syntax_crossing: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let _ = no_syntax_crossing;
+    let arg = arg.unwrap_or(1);
+    for _ in 0..arg.unsigned_abs() {
+        if escape_strings.is_none() || !escape_string(arg > 0, env, cx)? {
+            move_over(arg.signum(), 1, false, env, cx)?;
+        }
+    }
+    Ok(false)
+}
+
+/// Move point out of the string it is in, to after the string if `forward`
+/// and otherwise to its start. Returns false if point is not in a string.
+fn escape_string(forward: bool, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let text = SyntaxText::new(env, cx)?;
+    let mut data = get_current_buffer(env).lock()?;
+    let state = text.parse_state(data.point());
+    let (Some(_), Some(start)) = (state.string, state.comstr_start) else {return Ok(false)};
+    let ignore_comments = variable_set(sym::PARSE_SEXP_IGNORE_COMMENTS, env, cx);
+    let pos = match forward {
+        true => text
+            .scan_lists(start, 1, 0, true, ignore_comments)
+            .ok()
+            .flatten(),
+        false => Some(start),
+    };
+    data.goto_char(pos.unwrap_or(start));
+    Ok(true)
+}
+
+/// Move backward out of ARG levels of parentheses, or forward if ARG is
+/// negative. See `up-list` for the other arguments.
+#[defun]
+fn backward_up_list(
+    arg: Option<i64>,
+    escape_strings: Option<()>,
+    no_syntax_crossing: Option<()>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let arg = -arg.unwrap_or(1);
+    up_list(Some(arg), escape_strings, no_syntax_crossing, env, cx)
+}
+
+defsym!(SCAN_ERROR);
+defvar!(PARSE_SEXP_IGNORE_COMMENTS);
+defvar!(PARSE_SEXP_LOOKUP_PROPERTIES);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_char_set() {
+        let set = CharSet::parse("a-c\\n^x-").unwrap();
+        let syntax = &SyntaxTable::default();
+        let members: String = "abcdn^x-z\\"
+            .chars()
+            .filter(|&x| set.contains(x, syntax))
+            .collect();
+        assert_eq!(members, "abcn^x-");
+        let set = CharSet::parse("^[:digit:] ").unwrap();
+        let members: String = "a1 b2"
+            .chars()
+            .filter(|&x| set.contains(x, syntax))
+            .collect();
+        assert_eq!(members, "ab");
+        let set = CharSet::parse("z-a").unwrap();
+        assert!(!set.contains('m', syntax));
+        assert!(CharSet::parse("[:foo:]").is_err());
+    }
+
+    #[test]
+    fn test_word_motion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"syntax-words\"))", env, cx);
+        check("(insert \"  foo-bar, baz9 \")", env, cx);
+        check("(goto-char 1)", env, cx);
+        let form = "(list (forward-word) (point) (forward-word 2) (point)
+                          (forward-word) (point) (backward-word 2) (point)
+                          (backward-word 5) (point))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(t 6 t 16 nil 17 t 7 nil 1)"
+        );
+        // a word at the end of the buffer is moved over
+        check(
+            "(progn (erase-buffer) (insert \"ab cd\") (goto-char 3))",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check("(list (forward-word) (point))", env, cx).to_string(),
+            "(t 6)"
+        );
+    }
+
+    #[test]
+    fn test_skip() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"syntax-skip\"))", env, cx);
+        check("(insert \"abc123 \\tdef-ghi\")", env, cx);
+        check("(goto-char 1)", env, cx);
+        let form = "(list (skip-chars-forward \"a-z\") (point)
+                          (skip-chars-forward \"0-9\" 5) (point)
+                          (skip-chars-forward \"^-\") (point)
+                          (skip-chars-backward \"^0-9\") (point)
+                          (skip-chars-forward \"[:digit:][:space:]\" 100) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(3 4 1 5 7 12 -5 7 2 9)");
+        let form = "(list (skip-syntax-forward \"w\") (point)
+                          (skip-syntax-forward \"^w\") (point)
+                          (skip-syntax-forward \"w_\") (point)
+                          (skip-syntax-backward \"w_\") (point)
+                          (skip-syntax-backward \" \") (point))";
+        check("(goto-char 1)", env, cx);
+        assert_eq!(check(form, env, cx).to_string(), "(6 7 2 9 7 16 -7 9 -2 7)");
+
+        // single characters are found across the gap, and counted in
+        // characters
+        check("(erase-buffer)", env, cx);
+        check("(insert \"aaé\\néé\\nxyz\")", env, cx);
+        check("(goto-char 6)", env, cx);
+        check("(insert \"é\")", env, cx);
+        check("(goto-char 1)", env, cx);
+        let form = "(list (skip-chars-forward \"^\\n\") (point)
+                          (progn (goto-char 5) (skip-chars-forward \"é\")) (point)
+                          (skip-chars-forward \"^z\" 10) (point)
+                          (skip-chars-backward \"^\\n\") (point)
+                          (skip-chars-backward \"é\") (point)
+                          (skip-chars-backward \"^a\") (point))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(3 4 3 8 2 10 -1 9 0 9 -6 3)"
+        );
+    }
+
+    #[test]
+    fn test_syntax_entry() {
+        let entry = SyntaxEntry::parse(". 12b").unwrap().unwrap();
+        assert_eq!(entry.class, Syntax::Punct);
+        assert!(entry.flags.contains(SyntaxFlags::COMMENT_START_FIRST));
+        assert!(entry.flags.contains(SyntaxFlags::COMMENT_START_SECOND));
+        assert!(entry.flags.contains(SyntaxFlags::STYLE_B));
+        assert!(!entry.flags.contains(SyntaxFlags::PREFIX));
+        let entry = SyntaxEntry::parse("()").unwrap().unwrap();
+        assert_eq!((entry.class, entry.matching), (Syntax::Open, Some(')')));
+        assert!(SyntaxEntry::parse("@").unwrap().is_none());
+        assert!(SyntaxEntry::parse("z").is_err());
+    }
+
+    #[test]
+    fn test_syntax_table() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"syntax-table\"))", env, cx);
+        check("(insert \"foo-bar 'baz\")", env, cx);
+        let form = "(list (syntax-table-p (syntax-table))
+                          (eq (syntax-table) (standard-syntax-table))
+                          (char-syntax ?a) (char-syntax ?-) (char-syntax 40)
+                          (string-to-syntax \". 12b\") (string-to-syntax \"()\")
+                          (syntax-class-to-char 2) (matching-paren 91)
+                          (matching-paren ?a))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(t t 119 95 40 (2293761) (4 . 41) 119 93 nil)"
+        );
+        // a copy is changed without changing the standard table
+        check("(setq table (copy-syntax-table))", env, cx);
+        check("(modify-syntax-entry ?- \"w\" table)", env, cx);
+        check("(modify-syntax-entry '(?0 . ?9) \".\" table)", env, cx);
+        let form = "(list (eq (char-table-parent table) (standard-syntax-table))
+                          (char-syntax ?-)
+                          (progn (set-syntax-table table) (char-syntax ?-))
+                          (char-syntax ?5) (char-syntax ?a))";
+        assert_eq!(check(form, env, cx).to_string(), "(t 95 119 46 119)");
+        // word motion and regexps follow the table of the buffer
+        if cfg!(feature = "regex") {
+            let form = "(list (string-match \"\\\\w+\" \"a-b\") (match-end 0))";
+            assert_eq!(check(form, env, cx).to_string(), "(0 3)");
+        }
+        check("(goto-char 1)", env, cx);
+        let form = "(list (progn (forward-word) (point))
+                          (progn (set-syntax-table (standard-syntax-table))
+                                 (goto-char 1) (forward-word) (point)))";
+        assert_eq!(check(form, env, cx).to_string(), "(8 4)");
+        // the quote before a word is a prefix
+        let form = "(progn (modify-syntax-entry ?' \"'\" table) (set-syntax-table table)
+                           (goto-char 10) (backward-prefix-chars) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "9");
+    }
+
+    #[test]
+    fn test_parse_partial_sexp() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"syntax-parse\"))", env, cx);
+        check("(set-syntax-table (copy-syntax-table))", env, cx);
+        check("(modify-syntax-entry 59 \"<\")", env, cx);
+        check("(modify-syntax-entry 10 \">\")", env, cx);
+        check("(insert \"(a (b \\\"c(\\\" ; d)\\n e) f)\")", env, cx);
+        let cases = [
+            (
+                "(parse-partial-sexp 1 9)",
+                "(2 4 5 34 nil nil 0 nil 7 (1 4) nil)",
+            ),
+            (
+                "(parse-partial-sexp 1 14)",
+                "(2 4 7 nil t nil 0 nil 12 (1 4) nil)",
+            ),
+            (
+                "(parse-partial-sexp 1 23)",
+                "(0 nil 1 nil nil nil 0 nil nil nil nil)",
+            ),
+            (
+                "(parse-partial-sexp 9 14 nil nil (parse-partial-sexp 1 9))",
+                "(2 4 nil nil t nil 2 nil 12 (1 4) nil)",
+            ),
+            ("(list (car (parse-partial-sexp 1 23 2)) (point))", "(2 5)"),
+            ("(progn (parse-partial-sexp 2 23 nil t) (point))", "2"),
+            (
+                "(progn (parse-partial-sexp 1 23 nil nil nil t) (point))",
+                "13",
+            ),
+            ("(list (nth 8 (syntax-ppss 14)) (point))", "(12 14)"),
+        ];
+        for (form, expect) in cases {
+            assert_eq!(check(form, env, cx).to_string(), expect, "{form}");
+        }
+        // the syntax-table property overrides the table
+        check("(setq parse-sexp-lookup-properties t)", env, cx);
+        check(
+            "(put-text-property 7 8 'syntax-table (string-to-syntax \".\"))",
+            env,
+            cx,
+        );
+        let form = "(nth 3 (parse-partial-sexp 1 9))";
+        assert!(check(form, env, cx).nil());
+    }
+
+    #[test]
+    fn test_scan_lists() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"syntax-scan\"))", env, cx);
+        check("(set-syntax-table (copy-syntax-table))", env, cx);
+        check("(modify-syntax-entry 59 \"<\")", env, cx);
+        check("(modify-syntax-entry 10 \">\")", env, cx);
+        check("(setq parse-sexp-ignore-comments t)", env, cx);
+        check("(insert \"(a (b \\\"c(\\\" ; d)\\n e) f)\")", env, cx);
+        let form = "(list (scan-sexps 1 1) (scan-lists 5 1 1) (scan-sexps 23 -1)
+                          (scan-sexps 6 1) (scan-sexps 11 -1) (scan-sexps 18 -1)
+                          (scan-lists 1 1 -1) (scan-lists 5 -1 1) (scan-sexps 23 1))";
+        assert_eq!(check(form, env, cx).to_string(), "(23 20 1 11 7 7 2 4 nil)");
+        let form = "(condition-case err (scan-lists 5 1 0) (error err))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(scan-error \"Containing expression ends prematurely\" 19 20)"
+        );
+        check("(progn (erase-buffer) (insert \"(a\"))", env, cx);
+        let form = "(condition-case err (scan-sexps 1 1) (error err))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(scan-error \"Unbalanced parentheses\" 1 3)"
+        );
+    }
+
+    #[test]
+    fn test_forward_comment() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"syntax-comment\"))",
+            env,
+            cx,
+        );
+        check("(set-syntax-table (copy-syntax-table))", env, cx);
+        check("(modify-syntax-entry 59 \"<\")", env, cx);
+        check("(modify-syntax-entry 10 \">\")", env, cx);
+        check("(insert \"  ; c1\\n  ; c2\\nx\")", env, cx);
+        let form = "(list (progn (goto-char 1) (forward-comment 2)) (point)
+                          (forward-comment 1) (point)
+                          (forward-comment -2) (point)
+                          (forward-comment -1) (point)
+                          (forward-comment 5) (point))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(t 15 nil 15 t 3 nil 1 nil 15)"
+        );
+        // two-character delimiters and comment styles
+        check("(erase-buffer)", env, cx);
+        check("(modify-syntax-entry ?/ \". 124b\")", env, cx);
+        check("(modify-syntax-entry ?* \". 23\")", env, cx);
+        check("(modify-syntax-entry 10 \"> b\")", env, cx);
+        check("(insert \"a /* x */ b // y\\nc\")", env, cx);
+        let form = "(list (progn (goto-char 3) (forward-comment 1)) (point)
+                          (nth 4 (parse-partial-sexp 1 6)) (nth 8 (parse-partial-sexp 1 6))
+                          (nth 7 (parse-partial-sexp 1 17)) (nth 8 (parse-partial-sexp 1 17))
+                          (progn (goto-char 18) (forward-comment -1)) (point)
+                          (progn (goto-char 10) (forward-comment -1)) (point))";
+        assert_eq!(check(form, env, cx).to_string(), "(t 10 t 3 1 13 t 13 t 3)");
+    }
+
+    #[test]
+    fn test_list_motion() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check(
+            "(set-buffer (get-buffer-create \"syntax-motion\"))",
+            env,
+            cx,
+        );
+        check("(insert \"(a (b c) \\\"d(\\\" e)\")", env, cx);
+        let form = "(list (progn (goto-char 1) (forward-sexp) (point))
+                          (progn (goto-char 2) (forward-sexp 3) (point))
+                          (progn (backward-sexp 2) (point))
+                          (progn (goto-char 5) (up-list) (point))
+                          (progn (backward-up-list) (point))
+                          (progn (down-list) (point))
+                          (progn (forward-list) (point))
+                          (progn (goto-char 12) (up-list 1 t) (point))
+                          (progn (goto-char 12) (backward-up-list 1 t) (point)))";
+        assert_eq!(check(form, env, cx).to_string(), "(17 14 4 9 1 2 9 14 10)");
+        let form = "(condition-case err (progn (goto-char 2) (up-list 2)) (error err))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(scan-error \"Unbalanced parentheses\" 17 17)"
+        );
+    }
+}
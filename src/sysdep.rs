@@ -0,0 +1,525 @@
+//! Information about the system and the user, from the operating system.
+use crate::core::{
+    env::{intern, Symbol},
+    gc::Context,
+    object::{nil, GcObj, Object},
+};
+use anyhow::{bail, Result};
+use fn_macros::defun;
+use std::ffi::{CStr, CString};
+#[cfg(feature = "processes")]
+use std::fs::File;
+#[cfg(feature = "processes")]
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int};
+#[cfg(feature = "processes")]
+use std::os::{fd::AsRawFd, unix::process::ExitStatusExt};
+#[cfg(feature = "processes")]
+use std::process::ExitStatus;
+use std::ptr;
+
+/// `struct passwd` of the C library.
+#[repr(C)]
+#[allow(clippy::struct_field_names)]
+struct Passwd {
+    pw_name: *mut c_char,
+    pw_passwd: *mut c_char,
+    pw_uid: u32,
+    pw_gid: u32,
+    #[cfg(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))]
+    pw_change: i64,
+    #[cfg(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))]
+    pw_class: *mut c_char,
+    pw_gecos: *mut c_char,
+    pw_dir: *mut c_char,
+    pw_shell: *mut c_char,
+    #[cfg(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))]
+    pw_expire: i64,
+    #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+    pw_fields: c_int,
+}
+
+extern "C" {
+    fn getuid() -> u32;
+    fn geteuid() -> u32;
+    fn getgid() -> u32;
+    fn getegid() -> u32;
+    fn gethostname(name: *mut c_char, len: usize) -> c_int;
+    fn getloadavg(loadavg: *mut f64, nelem: c_int) -> c_int;
+    #[cfg(feature = "processes")]
+    fn strsignal(sig: c_int) -> *mut c_char;
+    #[cfg(feature = "processes")]
+    fn kill(pid: c_int, sig: c_int) -> c_int;
+    #[cfg(feature = "processes")]
+    fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    #[cfg(feature = "processes")]
+    fn shutdown(socket: c_int, how: c_int) -> c_int;
+    #[cfg(feature = "processes")]
+    fn poll(fds: *mut PollFd, nfds: NFds, timeout: c_int) -> c_int;
+    fn getpwuid_r(
+        uid: u32,
+        pwd: *mut Passwd,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut Passwd,
+    ) -> c_int;
+    fn getpwnam_r(
+        name: *const c_char,
+        pwd: *mut Passwd,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut Passwd,
+    ) -> c_int;
+}
+
+/// The error of a lookup whose buffer is too small.
+const ERANGE: c_int = 34;
+
+/// An entry of the user database.
+struct User {
+    name: String,
+    gecos: String,
+}
+
+fn c_text(text: *const c_char) -> String {
+    match text.is_null() {
+        true => String::new(),
+        false => unsafe { CStr::from_ptr(text) }
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Look up a user with `lookup`, a `getpw*_r` function with its key applied.
+fn lookup_user(
+    lookup: impl Fn(*mut Passwd, *mut c_char, usize, *mut *mut Passwd) -> c_int,
+) -> Option<User> {
+    let mut buf: Vec<c_char> = vec![0; 1024];
+    let mut entry = MaybeUninit::<Passwd>::uninit();
+    let mut result = ptr::null_mut();
+    while lookup(
+        entry.as_mut_ptr(),
+        buf.as_mut_ptr(),
+        buf.len(),
+        &raw mut result,
+    ) == ERANGE
+        && buf.len() < 1 << 20
+    {
+        buf.resize(buf.len() * 2, 0);
+    }
+    if result.is_null() {
+        return None;
+    }
+    let entry = unsafe { &*result };
+    Some(User {
+        name: c_text(entry.pw_name),
+        gecos: c_text(entry.pw_gecos),
+    })
+}
+
+fn user_by_id(uid: u32) -> Option<User> {
+    lookup_user(|pwd, buf, len, result| unsafe { getpwuid_r(uid, pwd, buf, len, result) })
+}
+
+fn user_by_name(name: &str) -> Option<User> {
+    let name = CString::new(name).ok()?;
+    lookup_user(|pwd, buf, len, result| unsafe { getpwnam_r(name.as_ptr(), pwd, buf, len, result) })
+}
+
+/// The login name of the user running this process: `LOGNAME` or `USER`
+/// from the environment, or else the name of the effective user.
+pub(crate) fn login_name() -> String {
+    let from_env = ["LOGNAME", "USER"]
+        .iter()
+        .find_map(|x| std::env::var(x).ok());
+    from_env.unwrap_or_else(real_login_name)
+}
+
+/// The name of the effective user, ignoring the environment.
+fn real_login_name() -> String {
+    let uid = unsafe { geteuid() };
+    user_by_id(uid).map_or_else(|| "unknown".to_owned(), |x| x.name)
+}
+
+/// The name of the machine this process runs on.
+pub(crate) fn host_name() -> String {
+    let mut buf: [c_char; 256] = [0; 256];
+    let name = match unsafe { gethostname(buf.as_mut_ptr(), buf.len() - 1) } {
+        0 => c_text(buf.as_ptr()),
+        _ => String::new(),
+    };
+    match name.is_empty() {
+        true => "localhost".to_owned(),
+        false => name,
+    }
+}
+
+/// The description of the signal `signal`, such as "Killed", followed by
+/// " (core dumped)" if `core_dumped` is set.
+#[cfg(feature = "processes")]
+pub(crate) fn signal_description(signal: i32, core_dumped: bool) -> String {
+    let name = unsafe { strsignal(signal) };
+    let name = match name.is_null() {
+        true => format!("Signal {signal}"),
+        false => c_text(name),
+    };
+    let core = if core_dumped { " (core dumped)" } else { "" };
+    format!("{name}{core}")
+}
+
+#[cfg(all(feature = "processes", not(target_vendor = "apple")))]
+mod signals {
+    /// The signals, by their names without the `SIG` prefix.
+    pub(super) const SIGNALS: &[(&str, i32)] = &[
+        ("HUP", 1),
+        ("INT", 2),
+        ("QUIT", 3),
+        ("ILL", 4),
+        ("TRAP", 5),
+        ("ABRT", 6),
+        ("IOT", 6),
+        ("BUS", 7),
+        ("FPE", 8),
+        ("KILL", 9),
+        ("USR1", 10),
+        ("SEGV", 11),
+        ("USR2", 12),
+        ("PIPE", 13),
+        ("ALRM", 14),
+        ("TERM", 15),
+        ("STKFLT", 16),
+        ("CHLD", 17),
+        ("CONT", 18),
+        ("STOP", 19),
+        ("TSTP", 20),
+        ("TTIN", 21),
+        ("TTOU", 22),
+        ("URG", 23),
+        ("XCPU", 24),
+        ("XFSZ", 25),
+        ("VTALRM", 26),
+        ("PROF", 27),
+        ("WINCH", 28),
+        ("IO", 29),
+        ("POLL", 29),
+        ("PWR", 30),
+        ("SYS", 31),
+    ];
+    pub(super) const WNOHANG: i32 = 1;
+    pub(super) const WUNTRACED: i32 = 2;
+    pub(super) const WCONTINUED: i32 = 8;
+}
+
+#[cfg(all(feature = "processes", target_vendor = "apple"))]
+mod signals {
+    /// The signals, by their names without the `SIG` prefix.
+    pub(super) const SIGNALS: &[(&str, i32)] = &[
+        ("HUP", 1),
+        ("INT", 2),
+        ("QUIT", 3),
+        ("ILL", 4),
+        ("TRAP", 5),
+        ("ABRT", 6),
+        ("IOT", 6),
+        ("EMT", 7),
+        ("FPE", 8),
+        ("KILL", 9),
+        ("BUS", 10),
+        ("SEGV", 11),
+        ("SYS", 12),
+        ("PIPE", 13),
+        ("ALRM", 14),
+        ("TERM", 15),
+        ("URG", 16),
+        ("STOP", 17),
+        ("TSTP", 18),
+        ("CONT", 19),
+        ("CHLD", 20),
+        ("TTIN", 21),
+        ("TTOU", 22),
+        ("IO", 23),
+        ("XCPU", 24),
+        ("XFSZ", 25),
+        ("VTALRM", 26),
+        ("PROF", 27),
+        ("WINCH", 28),
+        ("INFO", 29),
+        ("USR1", 30),
+        ("USR2", 31),
+    ];
+    pub(super) const WNOHANG: i32 = 1;
+    pub(super) const WUNTRACED: i32 = 2;
+    pub(super) const WCONTINUED: i32 = 0x10;
+}
+
+/// `struct pollfd` of the C library.
+#[cfg(feature = "processes")]
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: i16,
+    revents: i16,
+}
+
+#[cfg(all(feature = "processes", not(target_vendor = "apple")))]
+type NFds = std::os::raw::c_ulong;
+#[cfg(all(feature = "processes", target_vendor = "apple"))]
+type NFds = std::os::raw::c_uint;
+
+#[cfg(feature = "processes")]
+const SHUT_WR: c_int = 1;
+#[cfg(feature = "processes")]
+const SHUT_RDWR: c_int = 2;
+#[cfg(feature = "processes")]
+const POLLOUT: i16 = 4;
+/// The most bytes that can be written to a pipe at once without being
+/// split, once it has room for any.
+#[cfg(feature = "processes")]
+pub(crate) const PIPE_BUF: usize = 512;
+
+/// The number of the signal named `name`, in any case and with or without
+/// the `SIG` prefix.
+#[cfg(feature = "processes")]
+pub(crate) fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    signals::SIGNALS.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// Send `signal` to the process `pid`, or to the process group `-pid` if it
+/// is negative.
+#[cfg(feature = "processes")]
+pub(crate) fn send_signal(pid: i32, signal: i32) -> io::Result<()> {
+    match unsafe { kill(pid, signal) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Reap the child `pid` if it exited, or else report that it was stopped or
+/// continued. Waits until one of those happens if `block` is set, and
+/// otherwise returns `None` if none did.
+#[cfg(feature = "processes")]
+pub(crate) fn wait_child(pid: u32, block: bool) -> Option<ExitStatus> {
+    use signals::{WCONTINUED, WNOHANG, WUNTRACED};
+    let options = WUNTRACED | WCONTINUED | if block { 0 } else { WNOHANG };
+    let mut status = 0;
+    match unsafe { waitpid(pid.try_into().ok()?, ptr::from_mut(&mut status), options) } {
+        x if x > 0 => Some(ExitStatus::from_raw(status)),
+        _ => None,
+    }
+}
+
+/// Shut `socket` down, so that reading it and accepting connections on it
+/// end.
+#[cfg(feature = "processes")]
+pub(crate) fn shutdown_socket(socket: &impl AsRawFd) {
+    unsafe { shutdown(socket.as_raw_fd(), SHUT_RDWR) };
+}
+
+/// Shut down the sending side of `socket`, so that the other end reads the
+/// end of the stream.
+#[cfg(feature = "processes")]
+pub(crate) fn shutdown_output(socket: &impl AsRawFd) {
+    unsafe { shutdown(socket.as_raw_fd(), SHUT_WR) };
+}
+
+/// Write the start of `bytes` to `file` if it is ready to be written to
+/// without waiting, and return how many bytes were written. Fails with
+/// [`io::ErrorKind::WouldBlock`] if it is not ready. Writes of at most
+/// [`PIPE_BUF`] bytes never wait.
+#[cfg(feature = "processes")]
+pub(crate) fn write_ready(mut file: &File, bytes: &[u8]) -> io::Result<usize> {
+    let mut fd = PollFd {
+        fd: file.as_raw_fd(),
+        events: POLLOUT,
+        revents: 0,
+    };
+    match unsafe { poll(ptr::from_mut(&mut fd), 1, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Err(io::ErrorKind::WouldBlock.into()),
+        // an error or a closed reader is reported by the write
+        _ => file.write(bytes),
+    }
+}
+
+/// The full name in the GECOS field `gecos` of the user `login`: the part
+/// before the first comma, with `&` standing for the capitalized login name.
+fn full_name(gecos: &str, login: &str) -> String {
+    let name = gecos.split(',').next().unwrap_or_default();
+    let mut chars = login.chars();
+    let capitalized: String = chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    });
+    name.replace('&', &capitalized)
+}
+
+/// The symbol for this operating system, the value of `system-type`.
+pub(crate) fn system_type<'ob>(cx: &'ob Context) -> Symbol<'ob> {
+    let name = match std::env::consts::OS {
+        "linux" | "android" => "gnu/linux",
+        "macos" | "ios" => "darwin",
+        "freebsd" | "openbsd" | "netbsd" | "dragonfly" => "berkeley-unix",
+        "windows" => "windows-nt",
+        "hurd" => "gnu",
+        "cygwin" => "cygwin",
+        other => other,
+    };
+    intern(name, cx)
+}
+
+/// Return the host name of the machine you are running on, as a string.
+#[defun]
+fn system_name() -> String {
+    host_name()
+}
+
+/// Return the name under which the user logged in, as a string. If UID is
+/// given, return the login name of the user with that ID, or nil if there is
+/// no such user.
+#[defun]
+fn user_login_name<'ob>(uid: Option<i64>, cx: &'ob Context) -> GcObj<'ob> {
+    let name = match uid {
+        Some(uid) => u32::try_from(uid).ok().and_then(user_by_id).map(|x| x.name),
+        None => Some(login_name()),
+    };
+    name.map_or_else(nil, |x| cx.add(x))
+}
+
+/// Return the name of the user's real UID, as a string. This ignores the
+/// environment variables LOGNAME and USER.
+#[defun]
+fn user_real_login_name() -> String {
+    let uid = unsafe { getuid() };
+    user_by_id(uid).map_or_else(|| "unknown".to_owned(), |x| x.name)
+}
+
+/// Return the effective uid of Emacs, as an integer.
+#[defun]
+fn user_uid() -> i64 {
+    i64::from(unsafe { geteuid() })
+}
+
+/// Return the real uid of Emacs, as an integer.
+#[defun]
+fn user_real_uid() -> i64 {
+    i64::from(unsafe { getuid() })
+}
+
+/// Return the effective gid of Emacs, as an integer.
+#[defun]
+fn group_gid() -> i64 {
+    i64::from(unsafe { getegid() })
+}
+
+/// Return the real gid of Emacs, as an integer.
+#[defun]
+fn group_real_gid() -> i64 {
+    i64::from(unsafe { getgid() })
+}
+
+/// Return the full name of the user. UID is an integer user ID or a login
+/// name; if it is nil, return the name of the current user, which is the
+/// environment variable NAME if it is set. Return nil if there is no such
+/// user.
+#[defun]
+fn user_full_name<'ob>(uid: Option<GcObj>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let user = match uid.map(GcObj::untag) {
+        None => {
+            if let Ok(name) = std::env::var("NAME") {
+                return Ok(cx.add(name));
+            }
+            let login = login_name();
+            match user_by_name(&login) {
+                Some(user) => user,
+                None => return Ok(cx.add("unknown")),
+            }
+        }
+        Some(Object::Int(uid)) => match u32::try_from(uid).ok().and_then(user_by_id) {
+            Some(user) => user,
+            None => return Ok(nil()),
+        },
+        Some(Object::String(name)) => match user_by_name(name.try_into()?) {
+            Some(user) => user,
+            None => return Ok(nil()),
+        },
+        Some(other) => bail!("Invalid user ID: {other}"),
+    };
+    Ok(cx.add(full_name(&user.gecos, &user.name)))
+}
+
+/// Return the process ID of Emacs, as an integer.
+#[defun]
+fn emacs_pid() -> i64 {
+    i64::from(std::process::id())
+}
+
+/// Return list of 1 minute, 5 minute and 15 minute load averages. Each is
+/// an integer of 100 times the load average, or a float if USE-FLOATS is
+/// non-nil.
+#[defun(name = "getloadavg")]
+fn load_average<'ob>(use_floats: Option<()>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let mut loads = [0.0; 3];
+    let len = unsafe { getloadavg(loads.as_mut_ptr(), 3) };
+    let Ok(len) = usize::try_from(len) else {bail!("load-average not implemented for this operating system")};
+    let loads: Vec<GcObj> = loads[..len]
+        .iter()
+        .map(|&load| match use_floats {
+            Some(()) => cx.add(load),
+            #[allow(clippy::cast_possible_truncation)]
+            None => ((100.0 * load).round() as i64).into(),
+        })
+        .collect();
+    Ok(crate::fns::slice_into_list(&loads, None, cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{env::Env, gc::RootSet};
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_full_name() {
+        assert_eq!(full_name("Ann &,Room 1,555", "bob"), "Ann Bob");
+        assert_eq!(full_name("", "bob"), "");
+    }
+
+    #[test]
+    fn test_system_info() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let mut eval = |form: &str| {
+            let obj = reader::read(form, cx).unwrap().0;
+            root!(obj, cx);
+            interpreter::eval(obj, None, env, cx).unwrap().to_string()
+        };
+        assert_eq!(eval("(emacs-pid)"), std::process::id().to_string());
+        let uid = unsafe { geteuid() };
+        assert_eq!(eval("(user-uid)"), uid.to_string());
+        if let Some(user) = user_by_id(uid) {
+            let name = format!("\"{}\"", user.name);
+            assert_eq!(eval("(user-login-name (user-uid))"), name);
+            assert_eq!(user_by_name(&user.name).map(|x| x.gecos), Some(user.gecos));
+        }
+        assert_eq!(eval("(user-login-name 4000000000)"), "nil");
+        assert_eq!(eval("(user-full-name \"rune-no-such-user\")"), "nil");
+        assert_ne!(eval("(system-name)"), "\"\"");
+        assert_eq!(eval("(length (getloadavg))"), "3");
+        assert_eq!(eval("(floatp (car (getloadavg t)))"), "t");
+    }
+}
@@ -0,0 +1,870 @@
+#![cfg(feature = "buffers")]
+use crate::buffer::get_current_buffer;
+use crate::core::env::{sym, Env, Symbol, TextProps};
+use crate::core::error::{Type, TypeError};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, Buffer, Gc, GcObj, LispString, Object, TagType};
+use crate::editfns::validate_region;
+use crate::fns::slice_into_list;
+use crate::intervals::{IntervalTree, IntervalValue};
+use anyhow::Result;
+use fn_macros::defun;
+
+impl IntervalValue for TextProps<'_> {
+    /// Properties are the same if they have the same names with `eq` values,
+    /// in any order.
+    fn same(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|x| other.iter().any(|y| x.0 == y.0 && x.1.ptr_eq(y.1)))
+    }
+}
+
+/// The text whose properties are accessed.
+#[derive(Debug, Clone, Copy)]
+enum PropsObject<'ob> {
+    Buffer(&'static Buffer),
+    String(&'ob LispString),
+}
+
+impl<'ob> PropsObject<'ob> {
+    /// Resolve OBJECT to a buffer or string, defaulting to the current buffer.
+    fn new(object: Option<GcObj<'ob>>, env: &mut Rt<Env>) -> Result<Self> {
+        match object.map(Gc::untag) {
+            None | Some(Object::NIL) => Ok(Self::Buffer(get_current_buffer(env))),
+            Some(Object::Buffer(buffer)) => Ok(Self::Buffer(buffer)),
+            Some(Object::String(string)) => Ok(Self::String(string)),
+            Some(x) => Err(TypeError::new(Type::BufferOrString, x).into()),
+        }
+    }
+
+    /// The offset of the first character from the 0-based positions in the
+    /// property tree.
+    fn origin(self) -> usize {
+        match self {
+            Self::Buffer(_) => 1,
+            Self::String(_) => 0,
+        }
+    }
+
+    /// Call F with the property tree of the text, or `None` if it has never
+    /// had properties.
+    fn with_tree<R>(
+        self,
+        env: &Rt<Env>,
+        cx: &'ob Context,
+        f: impl FnOnce(Option<&IntervalTree<TextProps<'ob>>>) -> R,
+    ) -> R {
+        match self {
+            Self::Buffer(buffer) => f(env
+                .text_props
+                .get(buffer.text_owner().tag())
+                .map(|x| x.bind_ref(cx))),
+            Self::String(string) => f(string.props().as_ref()),
+        }
+    }
+}
+
+/// The text properties of the character at `pos` in BUFFER, or `None` if it
+/// has none.
+fn props_at<'a, 'ob>(
+    buffer: &'static Buffer,
+    pos: usize,
+    env: &'a Rt<Env>,
+    cx: &'ob Context,
+) -> Option<&'a TextProps<'ob>> {
+    env.text_props
+        .get(buffer.text_owner().tag())?
+        .bind_ref(cx)
+        .get(pos)
+}
+
+/// Get the value of PROP in PROPS, falling back to the properties of its
+/// `category` symbol.
+fn textget<'ob>(
+    props: Option<&TextProps<'ob>>,
+    prop: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    let Some(props) = props else { return nil() };
+    let lookup = |prop: Symbol| props.iter().find(|x| x.0 == prop).map(|x| x.1);
+    if let Some(value) = lookup(prop) {
+        return value;
+    }
+    match lookup(sym::CATEGORY).map(Gc::untag) {
+        Some(Object::Symbol(category)) => crate::data::get(category, prop, env, cx),
+        _ => nil(),
+    }
+}
+
+/// Convert the region from `start` to `end` of STRING to positions in order.
+/// Signals `args-out-of-range` if either end is outside of the string.
+fn validate_string_region(
+    start: usize,
+    end: usize,
+    string: &LispString,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(usize, usize)> {
+    // string positions are 0-based, so shift them into the form that
+    // `validate_region` expects
+    validate_region(start + 1, end + 1, (0, string.len()), env, cx)
+}
+
+/// The 0-based position of the character at POSITION in OBJECT, or `None` if
+/// POSITION is the end of the accessible portion.
+fn char_position<'ob>(
+    position: usize,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<(PropsObject<'ob>, Option<usize>)> {
+    let object = PropsObject::new(object, env)?;
+    let (pos, end) = match object {
+        PropsObject::Buffer(buffer) => {
+            let bounds = {
+                let data = buffer.lock()?;
+                (data.begv(), data.zv())
+            };
+            (
+                validate_region(position, position, bounds, env, cx)?.0,
+                bounds.1,
+            )
+        }
+        PropsObject::String(string) => {
+            let (pos, _) = validate_string_region(position, position, string, env, cx)?;
+            (pos, string.len())
+        }
+    };
+    Ok((object, (pos < end).then_some(pos)))
+}
+
+/// Replace the properties of each interval from START to END in the buffer of
+/// OBJECT with the result of F, where `None` leaves them unchanged. Returns
+/// whether any properties changed.
+fn modify_props<'ob>(
+    start: usize,
+    end: usize,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+    mut f: impl FnMut(&TextProps<'ob>) -> Option<TextProps<'ob>>,
+) -> Result<bool> {
+    let buffer = match PropsObject::new(object, env)? {
+        PropsObject::Buffer(buffer) => buffer,
+        PropsObject::String(string) => return modify_string_props(start, end, string, env, cx, f),
+    };
+    let (bounds, len) = {
+        let data = buffer.lock()?;
+        ((data.begv(), data.zv()), data.text.len_chars())
+    };
+    let (beg, end) = validate_region(start, end, bounds, env, cx)?;
+    if beg == end {
+        return Ok(false);
+    }
+    if env.text_props.get(buffer.text_owner().tag()).is_none() {
+        let props: IntervalTree<TextProps> = IntervalTree::new(len, Vec::new());
+        env.text_props.insert(buffer.text_owner().tag(), props);
+    }
+    // Compute the new properties first so the old values of the ones that
+    // change can be recorded for undo
+    let tree = env
+        .text_props
+        .get(buffer.text_owner().tag())
+        .unwrap()
+        .bind_ref(cx);
+    let mut new = Vec::new();
+    let mut changes = Vec::new();
+    for (start, stop, props) in tree.iter_from(beg).take_while(|x| x.0 < end) {
+        let new_props = f(props);
+        if let Some(new_props) = &new_props {
+            for (prop, old) in changed_props(props, new_props) {
+                changes.push(((start.max(beg), stop.min(end)), prop, old));
+            }
+        }
+        new.push(new_props);
+    }
+    let mut new = new.into_iter();
+    let props = env.text_props.get_mut(buffer.text_owner().tag()).unwrap();
+    let changed = props
+        .bind_mut(cx)
+        .modify(beg, end, |_| new.next().flatten());
+    for (range, prop, old) in changes {
+        crate::undo::record_property_change(buffer, range, prop, old, env, cx);
+    }
+    // TODO: run the change hooks for property changes
+    if changed {
+        buffer.lock()?.modified();
+    }
+    Ok(changed)
+}
+
+/// Like `modify_props`, for the text of STRING. Changes to strings are not
+/// recorded for undo.
+fn modify_string_props<'ob>(
+    start: usize,
+    end: usize,
+    string: &'ob LispString,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+    f: impl FnMut(&TextProps<'ob>) -> Option<TextProps<'ob>>,
+) -> Result<bool> {
+    let (beg, end) = validate_string_region(start, end, string, env, cx)?;
+    if beg == end {
+        return Ok(false);
+    }
+    let mut tree = match string.props().as_ref() {
+        Some(tree) => tree.map(Clone::clone),
+        None => IntervalTree::new(string.len(), Vec::new()),
+    };
+    let changed = tree.modify(beg, end, f);
+    if changed {
+        string.set_props(Some(tree))?;
+    }
+    Ok(changed)
+}
+
+/// The properties that differ between OLD and NEW, with their values in OLD.
+fn changed_props<'ob>(
+    old: &TextProps<'ob>,
+    new: &TextProps<'ob>,
+) -> Vec<(Symbol<'ob>, GcObj<'ob>)> {
+    let lookup = |props: &TextProps<'ob>, prop| props.iter().find(|x| x.0 == prop).map(|x| x.1);
+    let mut changed: Vec<(Symbol, GcObj)> = Vec::new();
+    for prop in old.iter().chain(new).map(|x| x.0) {
+        let before = lookup(old, prop);
+        let same = match (before, lookup(new, prop)) {
+            (Some(a), Some(b)) => a.ptr_eq(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if !same && !changed.iter().any(|x| x.0 == prop) {
+            changed.push((prop, before.unwrap_or_else(nil)));
+        }
+    }
+    changed
+}
+
+/// Parse the property list PROPERTIES into names and values.
+fn parse_plist(properties: GcObj) -> Result<TextProps> {
+    let mut props = Vec::new();
+    let mut iter = properties.as_list()?;
+    while let Some(name) = iter.next() {
+        let name: Symbol = name?.try_into()?;
+        let value = iter.next().transpose()?.unwrap_or_else(nil);
+        props.push((name, value));
+    }
+    Ok(props)
+}
+
+/// Set the properties in NEW on PROPS. Returns `None` if they were already
+/// set.
+fn add_props<'ob>(props: &TextProps<'ob>, new: &TextProps<'ob>) -> Option<TextProps<'ob>> {
+    let mut props = props.clone();
+    let mut changed = false;
+    for &(name, value) in new {
+        match props.iter_mut().find(|x| x.0 == name) {
+            Some(x) if x.1.ptr_eq(value) => {}
+            Some(x) => {
+                x.1 = value;
+                changed = true;
+            }
+            None => {
+                props.push((name, value));
+                changed = true;
+            }
+        }
+    }
+    changed.then_some(props)
+}
+
+/// Where text inserted into a buffer gets its properties from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum InsertProps {
+    /// The inserted text has no properties
+    None,
+    /// Inherit the sticky properties of the text around the insertion
+    Inherit,
+    /// Copy the properties of the text starting at a position in a buffer
+    Copy(&'static Buffer, usize),
+}
+
+/// Update the text properties of BUFFER for `len` characters inserted at
+/// `pos`, giving the inserted text properties according to `source`.
+pub(crate) fn text_inserted(
+    buffer: &'static Buffer,
+    pos: usize,
+    len: usize,
+    source: InsertProps,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let key = buffer.text_owner().tag();
+    match env.text_props.get_mut(key) {
+        Some(props) => props.bind_mut(cx).insert(pos, len, Vec::new()),
+        None if matches!(source, InsertProps::None) => return Ok(()),
+        None => {
+            let total = buffer.lock()?.text.len_chars();
+            env.text_props
+                .insert(key, IntervalTree::new(total, Vec::new()));
+        }
+    }
+    let new = match source {
+        InsertProps::None => return Ok(()),
+        InsertProps::Inherit => vec![(len, inherited_props(buffer, pos, len, env, cx))],
+        InsertProps::Copy(from, beg) => {
+            let Some(tree) = env.text_props.get(from.text_owner().tag()) else {return Ok(())};
+            let tree = tree.bind_ref(cx);
+            if from.text_owner().tag() == key {
+                // the source text after the insertion point has moved past it
+                let mut new = intervals(tree, beg, pos.clamp(beg, beg + len));
+                new.extend(intervals(tree, pos.max(beg) + len, beg + len + len));
+                new
+            } else {
+                intervals(tree, beg, beg + len)
+            }
+        }
+    };
+    let tree = env.text_props.get_mut(key).unwrap().bind_mut(cx);
+    let mut start = pos;
+    for (len, props) in new {
+        tree.modify(start, start + len, |_| Some(props.clone()));
+        start += len;
+    }
+    Ok(())
+}
+
+/// The lengths and properties of the intervals of TREE between `beg` and
+/// `end`.
+fn intervals<'ob>(
+    tree: &IntervalTree<TextProps<'ob>>,
+    beg: usize,
+    end: usize,
+) -> Vec<(usize, TextProps<'ob>)> {
+    if beg >= end {
+        return Vec::new();
+    }
+    tree.iter_from(beg)
+        .take_while(|x| x.0 < end)
+        .map(|(start, stop, props)| (stop.min(end) - start.max(beg), props.clone()))
+        .collect()
+}
+
+/// The text properties of the text from `beg` to `end` in BUFFER, as the
+/// properties of a string of that text. Returns `None` if the text has no
+/// properties.
+pub(crate) fn buffer_props<'ob>(
+    buffer: &'static Buffer,
+    beg: usize,
+    end: usize,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Option<IntervalTree<TextProps<'ob>>> {
+    let tree = env.text_props.get(buffer.text_owner().tag())?.bind_ref(cx);
+    let parts = intervals(tree, beg, end);
+    if parts.iter().all(|x| x.1.is_empty()) {
+        return None;
+    }
+    let mut props = IntervalTree::default();
+    let mut pos = 0;
+    for (len, value) in parts {
+        props.insert(pos, len, value);
+        pos += len;
+    }
+    Some(props)
+}
+
+/// The runs of text between `beg` and `end` in BUFFER where PROP is non-nil,
+/// as the start and end of each run with the value of PROP.
+pub(crate) fn property_runs<'ob>(
+    buffer: &'static Buffer,
+    prop: Symbol,
+    beg: usize,
+    end: usize,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Vec<(usize, usize, GcObj<'ob>)> {
+    let Some(tree) = env.text_props.get(buffer.text_owner().tag()) else {return Vec::new()};
+    let runs = tree.bind_ref(cx).iter_from(beg).take_while(|x| x.0 < end);
+    runs.filter_map(|(start, stop, props)| {
+        let value = textget(Some(props), prop, env, cx);
+        (!value.nil()).then_some((start.max(beg), stop.min(end), value))
+    })
+    .collect()
+}
+
+/// Update the text properties of BUFFER for the text between `beg1` and
+/// `end1` being swapped with the text between `beg2` and `end2`.
+pub(crate) fn text_transposed(
+    buffer: &'static Buffer,
+    (beg1, end1): (usize, usize),
+    (beg2, end2): (usize, usize),
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    let Some(props) = env.text_props.get_mut(buffer.text_owner().tag()) else {return};
+    let tree = props.bind_mut(cx);
+    let parts: Vec<_> = [(beg2, end2), (end1, beg2), (beg1, end1)]
+        .into_iter()
+        .flat_map(|(beg, end)| intervals(tree, beg, end))
+        .collect();
+    tree.delete(beg1, end2);
+    let mut pos = beg1;
+    for (len, props) in parts {
+        tree.insert(pos, len, props);
+        pos += len;
+    }
+}
+
+/// Whether PROP is listed in the stickiness property VALUE, which is either
+/// t or a list of property names.
+fn sticky_prop(value: GcObj, prop: Symbol) -> bool {
+    match value.untag() {
+        Object::Symbol(sym::TRUE) => true,
+        Object::Cons(list) => list.elements().any(|x| x.is_ok_and(|x| x == prop)),
+        _ => false,
+    }
+}
+
+/// The properties inherited by `len` characters inserted at `pos` in BUFFER.
+/// Properties are rear-sticky unless named in `rear-nonsticky` of the
+/// preceding text, and front-sticky only if named in `front-sticky` of the
+/// following text, which takes precedence.
+fn inherited_props<'ob>(
+    buffer: &'static Buffer,
+    pos: usize,
+    len: usize,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> TextProps<'ob> {
+    let empty = Vec::new();
+    let before = pos
+        .checked_sub(1)
+        .and_then(|x| props_at(buffer, x, env, cx));
+    let after = props_at(buffer, pos + len, env, cx);
+    let rear_nonsticky = textget(before, sym::REAR_NONSTICKY, env, cx);
+    let front_sticky = textget(after, sym::FRONT_STICKY, env, cx);
+    let mut props: TextProps = after
+        .unwrap_or(&empty)
+        .iter()
+        .filter(|x| sticky_prop(front_sticky, x.0))
+        .copied()
+        .collect();
+    for &(prop, value) in before.unwrap_or(&empty) {
+        if !sticky_prop(rear_nonsticky, prop) && !props.iter().any(|x| x.0 == prop) {
+            props.push((prop, value));
+        }
+    }
+    props
+}
+
+/// Update the text properties of BUFFER for the text from `beg` to `end`
+/// being deleted.
+pub(crate) fn text_deleted(
+    buffer: &'static Buffer,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    if let Some(props) = env.text_props.get_mut(buffer.text_owner().tag()) {
+        props.bind_mut(cx).delete(beg, end);
+    }
+}
+
+/// Set one property of the text from START to END.
+#[defun]
+pub(crate) fn put_text_property<'ob>(
+    start: usize,
+    end: usize,
+    property: Symbol<'ob>,
+    value: GcObj<'ob>,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<bool> {
+    let new = vec![(property, value)];
+    modify_props(start, end, object, env, cx, |props| add_props(props, &new))?;
+    Ok(false)
+}
+
+/// Add the properties in the property list PROPERTIES to the text from START
+/// to END. Returns t if any property changed.
+#[defun]
+fn add_text_properties<'ob>(
+    start: usize,
+    end: usize,
+    properties: GcObj<'ob>,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<bool> {
+    let new = parse_plist(properties)?;
+    modify_props(start, end, object, env, cx, |props| add_props(props, &new))
+}
+
+/// Remove the properties named in the property list PROPERTIES from the text
+/// from START to END. The values in PROPERTIES are ignored. Returns t if any
+/// property was removed.
+#[defun]
+fn remove_text_properties<'ob>(
+    start: usize,
+    end: usize,
+    properties: GcObj<'ob>,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<bool> {
+    let names: Vec<_> = parse_plist(properties)?.into_iter().map(|x| x.0).collect();
+    modify_props(start, end, object, env, cx, |props| {
+        let mut props = props.clone();
+        let len = props.len();
+        props.retain(|x| !names.contains(&x.0));
+        (props.len() != len).then_some(props)
+    })
+}
+
+#[defun]
+fn get_text_property<'ob>(
+    position: usize,
+    prop: Symbol,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (object, pos) = char_position(position, object, env, cx)?;
+    Ok(object.with_tree(env, cx, |tree| {
+        let props = pos.and_then(|pos| tree?.get(pos));
+        textget(props, prop, env, cx)
+    }))
+}
+
+#[defun]
+fn text_properties_at<'ob>(
+    position: usize,
+    object: Option<GcObj<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (object, pos) = char_position(position, object, env, cx)?;
+    let plist: Vec<GcObj> = object.with_tree(env, cx, |tree| {
+        let Some(props) = pos.and_then(|pos| tree?.get(pos)) else {return Vec::new()};
+        props.iter().flat_map(|x| [x.0.into(), x.1]).collect()
+    });
+    Ok(slice_into_list(&plist, None, cx))
+}
+
+/// Return the position after POSITION where the value of PROP changes, or nil
+/// if it doesn't change before the end of the text. If LIMIT is non-nil, it is
+/// returned instead of any position past it.
+#[defun]
+fn next_single_property_change<'ob>(
+    position: usize,
+    prop: Symbol,
+    object: Option<GcObj<'ob>>,
+    limit: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let limit_or_nil = || limit.map_or_else(nil, Into::into);
+    let object = PropsObject::new(object, env)?;
+    let origin = object.origin();
+    Ok(object.with_tree(env, cx, |tree| {
+        let Some(tree) = tree else {return limit_or_nil()};
+        let mut intervals = tree.iter_from(position.saturating_sub(origin));
+        let Some(first) = intervals.next() else {return limit_or_nil()};
+        let value = textget(Some(first.2), prop, env, cx);
+        for (start, _, props) in intervals {
+            let pos = start + origin;
+            if limit.is_some_and(|x| pos >= x) {
+                break;
+            }
+            if !textget(Some(props), prop, env, cx).ptr_eq(value) {
+                return pos.into();
+            }
+        }
+        limit_or_nil()
+    }))
+}
+
+defsym!(FRONT_STICKY);
+defsym!(REAR_NONSTICKY);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::root;
+
+    #[test]
+    fn test_text_properties() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"textprop\"))", env, cx);
+        crate::buffer::insert_text("hello world", env, cx).unwrap();
+
+        check("(put-text-property 1 6 'face 'bold)", env, cx);
+        assert_eq!(check("(get-text-property 3 'face)", env, cx), sym!(bold));
+        assert!(check("(get-text-property 6 'face)", env, cx).nil());
+        assert!(check("(get-text-property 12 'face)", env, cx).nil());
+        assert_eq!(check("(next-single-property-change 1 'face)", env, cx), 6);
+        assert!(check("(next-single-property-change 6 'face)", env, cx).nil());
+        assert_eq!(
+            check("(next-single-property-change 1 'face nil 4)", env, cx),
+            4
+        );
+
+        let changed = "(add-text-properties 3 8 '(face bold mouse-face highlight))";
+        assert_eq!(check(changed, env, cx), sym::TRUE);
+        assert!(check("(add-text-properties 4 5 '(face bold))", env, cx).nil());
+        assert_eq!(
+            check("(text-properties-at 4)", env, cx).to_string(),
+            "(face bold mouse-face highlight)"
+        );
+        assert_eq!(check("(next-single-property-change 1 'face)", env, cx), 8);
+        assert_eq!(
+            check("(next-single-property-change 1 'mouse-face)", env, cx),
+            3
+        );
+
+        assert_eq!(
+            check("(remove-text-properties 1 12 '(face nil))", env, cx),
+            sym::TRUE
+        );
+        assert!(check("(remove-text-properties 1 12 '(face nil))", env, cx).nil());
+        assert_eq!(
+            check("(text-properties-at 4)", env, cx).to_string(),
+            "(mouse-face highlight)"
+        );
+
+        // properties move with the text
+        check("(goto-char 1)", env, cx);
+        crate::buffer::insert_text("ab", env, cx).unwrap();
+        assert!(check("(get-text-property 1 'mouse-face)", env, cx).nil());
+        assert_eq!(
+            check("(next-single-property-change 1 'mouse-face)", env, cx),
+            5
+        );
+        crate::buffer::delete_text(1, 6, env, cx).unwrap();
+        assert_eq!(
+            check("(next-single-property-change 1 'mouse-face)", env, cx),
+            2
+        );
+        assert_eq!(
+            check("(get-text-property 2 'mouse-face)", env, cx),
+            sym!(highlight)
+        );
+        assert!(check("(get-text-property 5 'mouse-face)", env, cx).nil());
+
+        // values from the category symbol
+        check("(put 'link 'face 'underline)", env, cx);
+        check("(put-text-property 2 4 'category 'link)", env, cx);
+        assert_eq!(
+            check("(get-text-property 3 'face)", env, cx),
+            sym!(underline)
+        );
+    }
+
+    #[test]
+    fn test_insert_props() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let text = |env: &mut Rt<Env>| {
+            let buffer = get_current_buffer(env);
+            let text = buffer.lock().unwrap().text.to_string();
+            text
+        };
+        check("(set-buffer (get-buffer-create \"insert-src\"))", env, cx);
+        check("(insert \"abc\" ?d (unibyte-string 233))", env, cx);
+        assert_eq!(text(env), "abcdé");
+        check("(put-text-property 2 4 'face 'bold)", env, cx);
+
+        check("(set-buffer (get-buffer-create \"insert-dest\"))", env, cx);
+        check("(insert-char ?x 3)", env, cx);
+        check("(insert-char ?y 0)", env, cx);
+        assert_eq!(text(env), "xxx");
+        check("(goto-char 2)", env, cx);
+        check("(insert-buffer-substring \"insert-src\" 1 4)", env, cx);
+        assert_eq!(text(env), "xabcxx");
+        assert!(check("(get-text-property 2 'face)", env, cx).nil());
+        assert_eq!(check("(get-text-property 3 'face)", env, cx), sym!(bold));
+        assert_eq!(check("(get-text-property 4 'face)", env, cx), sym!(bold));
+        assert!(check("(get-text-property 5 'face)", env, cx).nil());
+        assert_eq!(check("(point)", env, cx), 5);
+
+        // properties are rear-sticky by default
+        check("(goto-char 5)", env, cx);
+        check("(insert \"-\")", env, cx);
+        assert!(check("(get-text-property 5 'face)", env, cx).nil());
+        check("(insert-and-inherit \"+\")", env, cx);
+        assert!(check("(get-text-property 6 'face)", env, cx).nil());
+        check("(goto-char 5)", env, cx);
+        check("(insert-and-inherit \"+\")", env, cx);
+        assert_eq!(text(env), "xabc+-+xx");
+        assert_eq!(check("(get-text-property 5 'face)", env, cx), sym!(bold));
+        check("(put-text-property 4 5 'rear-nonsticky t)", env, cx);
+        check("(goto-char 5)", env, cx);
+        check("(insert-char ?! 1 t)", env, cx);
+        assert!(check("(get-text-property 5 'face)", env, cx).nil());
+
+        // front-sticky properties are inherited from the following text
+        check(
+            "(put-text-property 1 2 'front-sticky '(mouse-face))",
+            env,
+            cx,
+        );
+        check("(put-text-property 1 2 'mouse-face 'highlight)", env, cx);
+        check("(goto-char 1)", env, cx);
+        check("(insert-and-inherit \"<\")", env, cx);
+        assert_eq!(
+            check("(get-text-property 1 'mouse-face)", env, cx),
+            sym!(highlight)
+        );
+
+        // copying from the same buffer, where the insertion moves the source
+        check("(set-buffer (get-buffer-create \"insert-self\"))", env, cx);
+        check("(insert \"abcd\")", env, cx);
+        check("(put-text-property 3 5 'face 'bold)", env, cx);
+        check("(goto-char 4)", env, cx);
+        check("(insert-buffer-substring (current-buffer) 2 5)", env, cx);
+        assert_eq!(text(env), "abcbcdd");
+        assert!(check("(get-text-property 4 'face)", env, cx).nil());
+        assert_eq!(check("(get-text-property 5 'face)", env, cx), sym!(bold));
+        assert_eq!(check("(get-text-property 6 'face)", env, cx), sym!(bold));
+        assert_eq!(check("(get-text-property 7 'face)", env, cx), sym!(bold));
+    }
+
+    #[test]
+    fn test_buffer_substring() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"substring\"))", env, cx);
+        check("(insert \"hello world\")", env, cx);
+        check("(put-text-property 2 4 'face 'bold)", env, cx);
+        assert_eq!(
+            check("(buffer-substring 1 6)", env, cx).to_string(),
+            "#(\"hello\" 1 3 (face bold))"
+        );
+        assert_eq!(
+            check("(buffer-substring-no-properties 1 6)", env, cx).to_string(),
+            "\"hello\""
+        );
+        assert_eq!(
+            check("(buffer-substring 5 8)", env, cx).to_string(),
+            "\"o w\""
+        );
+        check("(narrow-to-region 3 8)", env, cx);
+        assert_eq!(
+            check("(buffer-string)", env, cx).to_string(),
+            "#(\"llo w\" 0 1 (face bold))"
+        );
+        check("(widen)", env, cx);
+
+        // properties of strings
+        check("(setq substring-test (buffer-substring 1 6))", env, cx);
+        assert_eq!(
+            check("(get-text-property 1 'face substring-test)", env, cx),
+            sym!(bold)
+        );
+        assert!(check("(get-text-property 0 'face substring-test)", env, cx).nil());
+        assert_eq!(
+            check(
+                "(next-single-property-change 0 'face substring-test)",
+                env,
+                cx
+            ),
+            1
+        );
+        check(
+            "(put-text-property 3 5 'mouse-face 'highlight substring-test)",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check("(text-properties-at 4 substring-test)", env, cx).to_string(),
+            "(mouse-face highlight)"
+        );
+        assert_eq!(
+            check(
+                "(condition-case nil (get-text-property 6 'face substring-test) (error t))",
+                env,
+                cx
+            ),
+            sym::TRUE
+        );
+        // the buffer is not changed
+        assert!(check("(get-text-property 4 'mouse-face)", env, cx).nil());
+        assert_eq!(
+            check("(equal substring-test \"hello\")", env, cx),
+            sym::TRUE
+        );
+        assert!(check(
+            "(equal-including-properties substring-test \"hello\")",
+            env,
+            cx
+        )
+        .nil());
+        assert_eq!(
+            check(
+                "(equal-including-properties (buffer-substring 1 4) (buffer-substring 1 4))",
+                env,
+                cx
+            ),
+            sym::TRUE
+        );
+
+        // deleting the text keeps its properties
+        check("(goto-char 12)", env, cx);
+        assert_eq!(
+            check("(filter-buffer-substring 1 3 t)", env, cx).to_string(),
+            "#(\"he\" 1 2 (face bold))"
+        );
+        assert_eq!(check("(point)", env, cx), 10);
+        assert_eq!(
+            check("(buffer-string)", env, cx).to_string(),
+            "#(\"llo world\" 0 1 (face bold))"
+        );
+        check(
+            "(setq filter-buffer-substring-function #'(lambda (beg end delete) (goto-char beg) (concat \"<\" (buffer-substring beg end) \">\")))",
+            env,
+            cx,
+        );
+        assert_eq!(check("(filter-buffer-substring 1 4)", env, cx), "<llo>");
+        assert_eq!(check("(point)", env, cx), 10);
+    }
+
+    #[test]
+    fn test_text_properties_gc() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"textprop-gc\"))", env, cx);
+        crate::buffer::insert_text("abc", env, cx).unwrap();
+        check(
+            "(put-text-property 1 3 'help-echo (concat \"a\" \"b\"))",
+            env,
+            cx,
+        );
+        check("(setq textprop-gc (buffer-substring 1 4))", env, cx);
+        check(
+            "(put-text-property 0 1 'help-echo (concat \"c\" \"d\") textprop-gc)",
+            env,
+            cx,
+        );
+        cx.garbage_collect(true);
+        assert_eq!(check("(get-text-property 2 'help-echo)", env, cx), "ab");
+        assert_eq!(
+            check("(get-text-property 1 'help-echo textprop-gc)", env, cx),
+            "ab"
+        );
+        assert_eq!(
+            check("(get-text-property 0 'help-echo textprop-gc)", env, cx),
+            "cd"
+        );
+    }
+}
@@ -7,14 +7,31 @@ use crate::{
     root,
 };
 use fn_macros::defun;
+use std::sync::Mutex;
 use std::thread::{self, JoinHandle};
 
+/// Threads started by `go` that have not been joined yet
+static THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
 #[defun]
 fn go(obj: GcObj) -> bool {
-    go_internal(obj);
+    let handle = go_internal(obj);
+    THREADS.lock().unwrap().push(handle);
     false
 }
 
+/// Wait for every thread started by `go` to finish. Returns how many there
+/// were.
+pub(crate) fn join_threads() -> usize {
+    let handles = std::mem::take(&mut *THREADS.lock().unwrap());
+    let count = handles.len();
+    for handle in handles {
+        // a thread that panicked has nothing left to clean up
+        _ = handle.join();
+    }
+    count
+}
+
 fn go_internal(obj: GcObj) -> JoinHandle<()> {
     let block = Block::new_local_unchecked();
     let sexp = obj.clone_in(&block);
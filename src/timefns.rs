@@ -0,0 +1,1241 @@
+//! Lisp timestamps, and their conversion to and from calendar times.
+//!
+//! A timestamp is one of nil for the current time, a number of seconds, a
+//! pair `(TICKS . HZ)` of ticks at a clock frequency, or a list `(HIGH LOW
+//! USEC PSEC)` of the high and low 16 bits of the seconds and the micro and
+//! picoseconds, where the trailing elements may be left out.
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Rt},
+    object::{nil, GcObj, Object},
+};
+use anyhow::{anyhow, bail, ensure, Result};
+use fn_macros::defun;
+use std::ffi::CStr;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const INVALID_TIME: &str = "Invalid time specification";
+
+/// A timestamp as a number of ticks at a clock frequency of `hz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LispTime {
+    pub(crate) ticks: i128,
+    pub(crate) hz: i128,
+}
+
+impl LispTime {
+    pub(crate) fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    fn seconds(secs: i128) -> Self {
+        Self { ticks: secs, hz: 1 }
+    }
+
+    /// The exact value of the float `secs`.
+    fn from_float(secs: f64) -> Result<Self> {
+        ensure!(secs.is_finite(), INVALID_TIME);
+        let mut hz: i128 = 1;
+        let mut scaled = secs;
+        while scaled.fract() != 0.0 && hz < 1 << 64 {
+            hz <<= 1;
+            scaled *= 2.0;
+        }
+        ensure!(scaled.abs() < 2f64.powi(100), "Time overflow");
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Self {
+            ticks: scaled.round() as i128,
+            hz,
+        })
+    }
+
+    /// The whole seconds, rounded down.
+    pub(crate) fn secs(self) -> i128 {
+        self.ticks.div_euclid(self.hz)
+    }
+
+    /// The time at clock frequency `hz`, rounded down.
+    pub(crate) fn at_hz(self, hz: i128) -> Self {
+        let secs = self.secs();
+        let rest = self.ticks.rem_euclid(self.hz) * hz / self.hz;
+        Self {
+            ticks: secs * hz + rest,
+            hz,
+        }
+    }
+
+    pub(crate) fn to_float(self) -> f64 {
+        let frac = self.ticks.rem_euclid(self.hz);
+        #[allow(clippy::cast_precision_loss)]
+        let result = self.secs() as f64 + frac as f64 / self.hz as f64;
+        result
+    }
+
+    fn whole_secs(self) -> Result<i64> {
+        i64::try_from(self.secs()).map_err(|_| anyhow!("Time overflow"))
+    }
+
+    /// The time as a list `(HIGH LOW USEC PSEC)`.
+    pub(crate) fn to_list<'ob>(self, cx: &'ob Context) -> Result<GcObj<'ob>> {
+        let secs = self.whole_secs()?;
+        let psecs = i64::try_from(
+            self.at_hz(1_000_000_000_000)
+                .ticks
+                .rem_euclid(1_000_000_000_000),
+        )?;
+        Ok(list![secs >> 16, secs & 0xffff, psecs / 1_000_000, psecs % 1_000_000; cx])
+    }
+
+    /// The time as a pair `(TICKS . HZ)`.
+    pub(crate) fn to_pair<'ob>(self, cx: &'ob Context) -> Result<GcObj<'ob>> {
+        let overflow = |_| anyhow!("Time overflow");
+        let ticks = i64::try_from(self.ticks).map_err(overflow)?;
+        let hz = i64::try_from(self.hz).map_err(overflow)?;
+        Ok(cons!(ticks, hz; cx))
+    }
+
+    fn to_system_time(self) -> Option<SystemTime> {
+        let time = self.at_hz(1_000_000_000);
+        let nanos = Duration::from_nanos(u64::try_from(time.ticks.rem_euclid(time.hz)).ok()?);
+        let secs = i64::try_from(time.secs()).ok()?;
+        match u64::try_from(secs) {
+            Ok(secs) => UNIX_EPOCH.checked_add(Duration::from_secs(secs) + nanos),
+            Err(_) => UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))?
+                .checked_add(nanos),
+        }
+    }
+}
+
+impl From<SystemTime> for LispTime {
+    fn from(time: SystemTime) -> Self {
+        let nanos = match time.duration_since(UNIX_EPOCH) {
+            Ok(x) => x.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+        Self {
+            ticks: nanos,
+            hz: 1_000_000_000,
+        }
+    }
+}
+
+fn time_int(obj: GcObj) -> Result<i128> {
+    match obj.untag() {
+        Object::Int(x) => Ok(x.into()),
+        _ => bail!(INVALID_TIME),
+    }
+}
+
+/// The form of a Lisp timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Form {
+    Now,
+    Integer,
+    Float,
+    TicksHz,
+    List,
+}
+
+/// The time that the Lisp timestamp `time` represents.
+pub(crate) fn decode(time: GcObj) -> Result<LispTime> {
+    Ok(decode_form(time)?.0)
+}
+
+/// The time that the Lisp timestamp `time` represents, and its form.
+fn decode_form(time: GcObj) -> Result<(LispTime, Form)> {
+    match time.untag() {
+        Object::NIL => Ok((LispTime::now(), Form::Now)),
+        Object::Int(secs) => Ok((LispTime::seconds(secs.into()), Form::Integer)),
+        Object::Float(secs) => Ok((LispTime::from_float(**secs)?, Form::Float)),
+        Object::Cons(cons) => {
+            // (TICKS . HZ) or (HIGH . LOW)
+            if let Object::Int(_) = cons.cdr().untag() {
+                let (ticks, hz) = (time_int(cons.car())?, time_int(cons.cdr())?);
+                ensure!(hz > 0, INVALID_TIME);
+                return Ok((LispTime { ticks, hz }, Form::TicksHz));
+            }
+            let parts = time
+                .as_list()?
+                .map(|x| time_int(x?))
+                .collect::<Result<Vec<_>>>()?;
+            // the seconds, and the microseconds and picoseconds if given
+            let (secs, hz, rest) = match parts[..] {
+                [high, low] => ((high << 16) + low, 1, 0),
+                [high, low, usec] => ((high << 16) + low, 1_000_000, usec),
+                [high, low, usec, psec] => (
+                    (high << 16) + low,
+                    1_000_000_000_000,
+                    usec * 1_000_000 + psec,
+                ),
+                _ => bail!(INVALID_TIME),
+            };
+            let time = LispTime {
+                ticks: secs * hz + rest,
+                hz,
+            };
+            Ok((time, Form::List))
+        }
+        _ => bail!(INVALID_TIME),
+    }
+}
+
+/// A Lisp timestamp for `time`, as a list `(HIGH LOW USEC PSEC)`.
+pub(crate) fn lisp_time<'ob>(time: SystemTime, cx: &'ob Context) -> GcObj<'ob> {
+    LispTime::from(time).to_list(cx).unwrap_or_else(|_| nil())
+}
+
+/// The time that the Lisp timestamp `time` represents, if it is valid and
+/// can be a `SystemTime`.
+#[cfg_attr(not(feature = "buffers"), allow(dead_code))]
+pub(crate) fn decode_system_time(time: GcObj) -> Option<SystemTime> {
+    decode(time).ok()?.to_system_time()
+}
+
+/// Whether `current-time` and related functions return lists.
+fn list_form(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars
+        .get(sym::CURRENT_TIME_LIST)
+        .is_some_and(|x| !x.bind(cx).nil())
+}
+
+/// Return the current time, as the number of seconds since 1970-01-01
+/// 00:00:00. If `current-time-list` is nil, the time is a pair (TICKS . HZ),
+/// and otherwise a list (HIGH LOW USEC PSEC).
+#[defun]
+fn current_time<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let now = LispTime::now();
+    match list_form(env, cx) {
+        true => now.to_list(cx),
+        false => now.to_pair(cx),
+    }
+}
+
+/// Convert TIME value to a Lisp timestamp of the given FORM. If FORM is a
+/// positive integer, return a pair (TICKS . FORM), truncating if needed. If
+/// FORM is `integer`, return the whole seconds; if it is `list`, return a
+/// list (HIGH LOW USEC PSEC); and if it is t or nil, return a pair (TICKS
+/// . HZ) that represents TIME exactly.
+#[defun]
+fn time_convert<'ob>(time: GcObj, form: Option<GcObj>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let time = decode(time)?;
+    match form.map(GcObj::untag) {
+        None | Some(Object::TRUE) => time.to_pair(cx),
+        Some(Object::Symbol(sym::INTEGER)) => Ok(time.whole_secs()?.into()),
+        Some(Object::Symbol(sym::LIST)) => time.to_list(cx),
+        Some(Object::Int(hz)) if hz > 0 => {
+            let time = time.at_hz(hz.into());
+            let ticks = i64::try_from(time.ticks).map_err(|_| anyhow!("Time overflow"))?;
+            Ok(cons!(ticks, hz; cx))
+        }
+        Some(form) => bail!("Invalid time form: {form}"),
+    }
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+/// `a` and `b` as ticks of a common clock frequency, which is returned
+/// last.
+fn common_ticks(a: LispTime, b: LispTime) -> Result<(i128, i128, i128)> {
+    let overflow = || anyhow!("Time overflow");
+    let hz = (a.hz / gcd(a.hz, b.hz))
+        .checked_mul(b.hz)
+        .ok_or_else(overflow)?;
+    let a_ticks = a.ticks.checked_mul(hz / a.hz).ok_or_else(overflow)?;
+    let b_ticks = b.ticks.checked_mul(hz / b.hz).ok_or_else(overflow)?;
+    Ok((a_ticks, b_ticks, hz))
+}
+
+/// The sum of the timestamps `a` and `b`, or their difference if
+/// `subtract`. The result is a float if either of them is, an integer if it
+/// has whole seconds, and otherwise a list if `current-time-list` is
+/// non-nil and neither of them is a `(TICKS . HZ)` pair and a list can
+/// represent it exactly, or else a pair at the finer clock frequency.
+fn time_arith<'ob>(
+    a: GcObj,
+    b: GcObj,
+    subtract: bool,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let ((a, a_form), (b, b_form)) = (decode_form(a)?, decode_form(b)?);
+    let (a_ticks, b_ticks, hz) = common_ticks(a, b)?;
+    let ticks = match subtract {
+        true => a_ticks.checked_sub(b_ticks),
+        false => a_ticks.checked_add(b_ticks),
+    };
+    let time = LispTime {
+        ticks: ticks.ok_or_else(|| anyhow!("Time overflow"))?,
+        hz,
+    };
+    let forms = [a_form, b_form];
+    if forms.contains(&Form::Float) {
+        Ok(cx.add(time.to_float()))
+    } else if hz == 1 {
+        Ok(time.whole_secs()?.into())
+    } else if list_form(env, cx) && !forms.contains(&Form::TicksHz) && 1_000_000_000_000 % hz == 0 {
+        time.to_list(cx)
+    } else {
+        time.to_pair(cx)
+    }
+}
+
+/// Return the sum of two time values A and B, as a time value.
+#[defun]
+fn time_add<'ob>(a: GcObj, b: GcObj, env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    time_arith(a, b, false, env, cx)
+}
+
+/// Return the difference between time values A and B, as a time value. A
+/// nil argument stands for the current time.
+#[defun]
+fn time_subtract<'ob>(a: GcObj, b: GcObj, env: &Rt<Env>, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    time_arith(a, b, true, env, cx)
+}
+
+/// Compare the time values `a` and `b` exactly.
+fn compare(a: GcObj, b: GcObj) -> Result<std::cmp::Ordering> {
+    let (a_ticks, b_ticks, _) = common_ticks(decode(a)?, decode(b)?)?;
+    Ok(a_ticks.cmp(&b_ticks))
+}
+
+/// Return non-nil if time value A is less than time value B.
+#[defun]
+fn time_less_p(a: GcObj, b: GcObj) -> Result<bool> {
+    Ok(compare(a, b)?.is_lt())
+}
+
+/// Return non-nil if A and B are equal time values. Two nil arguments are
+/// the same current time.
+#[defun]
+fn time_equal_p(a: GcObj, b: GcObj) -> Result<bool> {
+    if a.nil() && b.nil() {
+        return Ok(true);
+    }
+    Ok(compare(a, b)?.is_eq())
+}
+
+/// Return the current time, or the time value SPECIFIED-TIME, as a float
+/// number of seconds since the epoch.
+#[defun]
+fn float_time(specified_time: Option<GcObj>) -> Result<f64> {
+    Ok(decode(specified_time.unwrap_or_else(nil))?.to_float())
+}
+
+/// The calendar fields of `struct tm`, where `long` is 64 bits.
+#[repr(C)]
+#[allow(clippy::struct_field_names)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: i64,
+    tm_zone: *const c_char,
+}
+
+impl Default for Tm {
+    fn default() -> Self {
+        Self {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 0,
+            tm_mday: 0,
+            tm_mon: 0,
+            tm_year: 0,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_gmtoff: 0,
+            tm_zone: std::ptr::null(),
+        }
+    }
+}
+
+extern "C" {
+    fn tzset();
+    fn localtime_r(time: *const i64, tm: *mut Tm) -> *mut Tm;
+    fn mktime(tm: *mut Tm) -> i64;
+}
+
+/// The time zone of a conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Zone {
+    /// The local time zone of the system.
+    Local,
+    Utc,
+    /// A fixed number of seconds east of UTC, and its abbreviation.
+    Fixed(i64, Option<String>),
+    /// A `TZ` rule, like "Europe/Paris" or "EST5EDT,M3.2.0,M11.1.0".
+    Rule(String),
+}
+
+/// The zone that the Lisp zone rule ZONE stands for: nil or `wall` for
+/// local time, t for UTC, a number of seconds east of UTC, a list `(OFFSET
+/// ABBR)` of such a number and its abbreviation, or a `TZ` string.
+pub(crate) fn decode_zone(zone: Option<GcObj>) -> Result<Zone> {
+    let Some(zone) = zone else {return Ok(Zone::Local)};
+    Ok(match zone.untag() {
+        Object::Symbol(sym::WALL) => Zone::Local,
+        Object::TRUE => Zone::Utc,
+        Object::Int(offset) => Zone::Fixed(offset, None),
+        Object::String(rule) => Zone::Rule(<&str>::try_from(rule)?.to_owned()),
+        Object::Cons(_) => match zone.as_list()?.collect::<Result<Vec<_>>>()?[..] {
+            [offset, abbr] => match (offset.untag(), abbr.untag()) {
+                (Object::Int(offset), Object::String(abbr)) => {
+                    Zone::Fixed(offset, Some(<&str>::try_from(abbr)?.to_owned()))
+                }
+                _ => bail!("Invalid time zone specification: {zone}"),
+            },
+            _ => bail!("Invalid time zone specification: {zone}"),
+        },
+        _ => bail!("Invalid time zone specification: {zone}"),
+    })
+}
+
+/// The abbreviation of a zone `offset` seconds east of UTC with no name of
+/// its own, like "+0530" or "-05".
+fn offset_name(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    let (hours, minutes, seconds) = (offset / 3600, offset / 60 % 60, offset % 60);
+    match (minutes, seconds) {
+        (0, 0) => format!("{sign}{hours:02}"),
+        (_, 0) => format!("{sign}{hours:02}{minutes:02}"),
+        _ => format!("{sign}{hours:02}{minutes:02}{seconds:02}"),
+    }
+}
+
+/// Serializes changes to the `TZ` variable of this process, which the C
+/// library reads for local time.
+static TZ_LOCK: Mutex<()> = Mutex::new(());
+
+/// The `TZ` variable of this process before `set-time-zone-rule`.
+static INITIAL_TZ: OnceLock<Option<OsString>> = OnceLock::new();
+
+fn set_tz(tz: Option<&OsStr>) {
+    match tz {
+        Some(tz) => std::env::set_var("TZ", tz),
+        None => std::env::remove_var("TZ"),
+    }
+    unsafe { tzset() };
+}
+
+/// Call `f` with the local time zone of the C library set to `rule`, or
+/// left as it is if that is `None`.
+fn with_rule<T>(rule: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let _guard = TZ_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(rule) = rule else {
+        unsafe { tzset() };
+        return f();
+    };
+    let saved = std::env::var_os("TZ");
+    set_tz(Some(rule.as_ref()));
+    let result = f();
+    set_tz(saved.as_deref());
+    result
+}
+
+/// Make `rule` the local time zone, or the initial one of this process if
+/// it is `None`.
+pub(crate) fn set_local_rule(rule: Option<&str>) {
+    let _guard = TZ_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let initial = INITIAL_TZ.get_or_init(|| std::env::var_os("TZ"));
+    set_tz(rule.map(OsStr::new).or(initial.as_deref()));
+}
+
+/// The POSIX `TZ` rule for a zone `offset` seconds east of UTC named
+/// `name`, like "<+0530>-05:30:00". A name that POSIX does not allow is
+/// replaced by the offset.
+fn fixed_rule(offset: i64, name: Option<&str>) -> String {
+    let valid = |name: &&str| {
+        name.len() >= 3
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-')
+    };
+    let name = name
+        .filter(valid)
+        .map_or_else(|| offset_name(offset), str::to_owned);
+    // POSIX offsets are west of UTC
+    let sign = if offset > 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    let (hours, minutes, seconds) = (offset / 3600, offset / 60 % 60, offset % 60);
+    format!("<{name}>{sign}{hours:02}:{minutes:02}:{seconds:02}")
+}
+
+/// A time broken down into the fields of a calendar in some time zone.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CalendarTime {
+    pub(crate) year: i64,
+    /// From 1 for January.
+    pub(crate) month: i64,
+    pub(crate) day: i64,
+    pub(crate) hour: i64,
+    pub(crate) minute: i64,
+    pub(crate) second: i64,
+    /// From 0 for Sunday.
+    pub(crate) weekday: i64,
+    /// From 0 for January 1.
+    pub(crate) yday: i64,
+    /// Whether daylight saving time is in effect, if that is known.
+    pub(crate) dst: Option<bool>,
+    /// The seconds east of UTC.
+    pub(crate) offset: i64,
+    pub(crate) zone: String,
+}
+
+/// The days from 1970-01-01 to the date in the proleptic Gregorian
+/// calendar, where `month` may be out of range.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year + (month - 1).div_euclid(12);
+    let month = (month - 1).rem_euclid(12) + 1;
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The year, month and day of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+impl CalendarTime {
+    /// The time `secs` after the epoch in a zone `offset` seconds east of
+    /// UTC.
+    fn at_offset(secs: i64, offset: i64, dst: Option<bool>, zone: String) -> Self {
+        let local = secs + offset;
+        let days = local.div_euclid(86400);
+        let rest = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: rest / 3600,
+            minute: rest / 60 % 60,
+            second: rest % 60,
+            weekday: (days + 4).rem_euclid(7),
+            yday: days - days_from_civil(year, 1, 1),
+            dst,
+            offset,
+            zone,
+        }
+    }
+
+    /// The time `secs` after the epoch in `zone`.
+    pub(crate) fn new(secs: i64, zone: &Zone) -> Result<Self> {
+        Ok(match zone {
+            Zone::Utc => Self::at_offset(secs, 0, Some(false), "UTC".to_owned()),
+            Zone::Fixed(offset, name) => {
+                let name = name.clone().unwrap_or_else(|| offset_name(*offset));
+                Self::at_offset(secs, *offset, Some(false), name)
+            }
+            Zone::Local | Zone::Rule(_) => {
+                let rule = match zone {
+                    Zone::Rule(rule) => Some(rule.as_str()),
+                    _ => None,
+                };
+                let mut tm = Tm::default();
+                let result = with_rule(rule, || unsafe {
+                    localtime_r(&raw const secs, &raw mut tm)
+                });
+                ensure!(!result.is_null(), "Specified time is not representable");
+                let zone = match tm.tm_zone.is_null() {
+                    true => offset_name(tm.tm_gmtoff),
+                    false => unsafe { CStr::from_ptr(tm.tm_zone) }
+                        .to_string_lossy()
+                        .into_owned(),
+                };
+                Self {
+                    year: i64::from(tm.tm_year) + 1900,
+                    month: i64::from(tm.tm_mon) + 1,
+                    day: tm.tm_mday.into(),
+                    hour: tm.tm_hour.into(),
+                    minute: tm.tm_min.into(),
+                    second: tm.tm_sec.into(),
+                    weekday: tm.tm_wday.into(),
+                    yday: tm.tm_yday.into(),
+                    dst: (tm.tm_isdst >= 0).then_some(tm.tm_isdst > 0),
+                    offset: tm.tm_gmtoff,
+                    zone,
+                }
+            }
+        })
+    }
+
+    /// The seconds since the epoch of this time in `zone`, where the fields
+    /// may be out of range. In zones with rules, `dst` chooses between ambiguous
+    /// times, and is guessed if it is `None`.
+    pub(crate) fn encode(&self, zone: &Zone) -> Result<i64> {
+        let offset = match zone {
+            Zone::Utc => 0,
+            Zone::Fixed(offset, _) => *offset,
+            Zone::Local => return self.encode_local(None),
+            Zone::Rule(rule) => return self.encode_local(Some(rule)),
+        };
+        let days = days_from_civil(self.year, self.month, 1) + self.day - 1;
+        Ok(days * 86400 + self.hour * 3600 + self.minute * 60 + self.second - offset)
+    }
+
+    fn encode_local(&self, rule: Option<&str>) -> Result<i64> {
+        let field =
+            |x: i64| c_int::try_from(x).map_err(|_| anyhow!("Specified time is not representable"));
+        let mut tm = Tm {
+            tm_sec: field(self.second)?,
+            tm_min: field(self.minute)?,
+            tm_hour: field(self.hour)?,
+            tm_mday: field(self.day)?,
+            tm_mon: field(self.month - 1)?,
+            tm_year: field(self.year - 1900)?,
+            // mktime sets this when it succeeds
+            tm_wday: -1,
+            tm_isdst: self.dst.map_or(-1, c_int::from),
+            ..Tm::default()
+        };
+        let secs = with_rule(rule, || unsafe { mktime(&raw mut tm) });
+        ensure!(tm.tm_wday >= 0, "Specified time is not representable");
+        Ok(secs)
+    }
+}
+
+/// Decode a TIME value as (SEC MINUTE HOUR DAY MONTH YEAR DOW DST UTCOFF)
+/// in the time zone ZONE. DOW is the day of the week from 0 for Sunday, DST
+/// is t if daylight saving time is in effect, nil if it is not and -1 if
+/// that is unknown, and UTCOFF is the seconds east of UTC. SEC is an
+/// integer, unless FORM is t and TIME has fractional seconds, in which case
+/// it is a pair (TICKS . HZ).
+#[defun]
+fn decode_time<'ob>(
+    time: Option<GcObj>,
+    zone: Option<GcObj>,
+    form: Option<GcObj>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let time = decode(time.unwrap_or_else(nil))?;
+    let secs = time.whole_secs()?;
+    let cal = CalendarTime::new(secs, &decode_zone(zone)?)?;
+    let second = match form.map(GcObj::untag) {
+        Some(Object::TRUE) if time.hz != 1 => {
+            let ticks = time.ticks.rem_euclid(time.hz) + i128::from(cal.second) * time.hz;
+            LispTime { ticks, hz: time.hz }.to_pair(cx)?
+        }
+        None | Some(Object::TRUE | Object::Symbol(sym::INTEGER)) => cal.second.into(),
+        Some(form) => bail!("Invalid time form: {form}"),
+    };
+    let dst: GcObj = match cal.dst {
+        Some(dst) => dst.into(),
+        None => (-1).into(),
+    };
+    Ok(
+        list![second, cal.minute, cal.hour, cal.day, cal.month, cal.year, cal.weekday, dst, cal.offset; cx],
+    )
+}
+
+fn calendar_field(obj: GcObj) -> Result<i64> {
+    match obj.untag() {
+        Object::Int(x) => Ok(x),
+        _ => bail!("Invalid calendar field: {obj}"),
+    }
+}
+
+/// Convert TIME, a list (SECOND MINUTE HOUR DAY MONTH YEAR IGNORED DST
+/// ZONE), to a Lisp timestamp. Fields out of their usual range are carried
+/// into the next field. DST is t for daylight saving time, nil for standard
+/// time and -1 to guess, and ZONE is as in `format-time-string`. The
+/// obsolescent form (encode-time SECOND MINUTE HOUR DAY MONTH YEAR &optional
+/// ZONE) is also accepted. SECOND may be a timestamp with fractional
+/// seconds, in which case a pair (TICKS . HZ) is returned.
+#[defun]
+fn encode_time<'ob>(
+    time: GcObj,
+    obsolescent_arguments: &[GcObj],
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let (fields, dst, zone) = if obsolescent_arguments.is_empty() {
+        let fields: Vec<GcObj> = time.as_list()?.collect::<Result<_>>()?;
+        ensure!(fields.len() >= 6, "Invalid time specification: {time}");
+        let dst = match fields.get(7).map(|x| x.untag()) {
+            None | Some(Object::NIL) => Some(false),
+            Some(Object::Int(-1)) => None,
+            Some(_) => Some(true),
+        };
+        (fields[..6].to_vec(), dst, fields.get(8).copied())
+    } else {
+        let mut fields = vec![time];
+        fields.extend_from_slice(obsolescent_arguments);
+        ensure!(
+            fields.len() >= 6,
+            "Wrong number of arguments to encode-time"
+        );
+        let zone = (fields.len() > 6).then(|| *fields.last().unwrap());
+        fields.truncate(6);
+        (fields, None, zone)
+    };
+    let zone = decode_zone(zone.filter(|x| !x.nil()))?;
+    let second = decode(fields[0])?;
+    let whole = i64::try_from(second.secs())?;
+    let cal = CalendarTime {
+        year: calendar_field(fields[5])?,
+        month: calendar_field(fields[4])?,
+        day: calendar_field(fields[3])?,
+        hour: calendar_field(fields[2])?,
+        minute: calendar_field(fields[1])?,
+        second: whole,
+        weekday: 0,
+        yday: 0,
+        dst,
+        offset: 0,
+        zone: String::new(),
+    };
+    let secs = cal.encode(&zone)?;
+    match second.hz {
+        // whole seconds are (HIGH LOW)
+        1 => Ok(list![secs >> 16, secs & 0xffff; cx]),
+        hz => {
+            let ticks = i128::from(secs) * hz + second.ticks.rem_euclid(hz);
+            LispTime { ticks, hz }.to_pair(cx)
+        }
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// The number of ISO 8601 weeks in `year`.
+fn iso_weeks_in(year: i64) -> i64 {
+    let starts_on =
+        |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)).rem_euclid(7);
+    if starts_on(year) == 4 || starts_on(year - 1) == 3 {
+        53
+    } else {
+        52
+    }
+}
+
+/// The ISO 8601 week-based year and week number of `cal`.
+fn iso_week(cal: &CalendarTime) -> (i64, i64) {
+    let weekday = (cal.weekday + 6) % 7;
+    let week = (cal.yday - weekday + 10) / 7;
+    if week < 1 {
+        (cal.year - 1, iso_weeks_in(cal.year - 1))
+    } else if week > iso_weeks_in(cal.year) {
+        (cal.year + 1, 1)
+    } else {
+        (cal.year, week)
+    }
+}
+
+/// The flags, width and modifiers of a conversion in a time format.
+#[derive(Default, Clone, Copy)]
+struct Spec {
+    pad: Option<char>,
+    width: Option<usize>,
+    upcase: bool,
+    swapcase: bool,
+    colons: usize,
+}
+
+impl Spec {
+    fn number(self, out: &mut String, n: i64, digits: usize, pad: char) {
+        let pad = match self.pad {
+            Some('-') => None,
+            Some('_') => Some(' '),
+            Some(_) => Some('0'),
+            None => Some(pad),
+        };
+        let text = n.unsigned_abs().to_string();
+        let width = self
+            .width
+            .unwrap_or(digits)
+            .saturating_sub(usize::from(n < 0));
+        let fill = width.saturating_sub(text.len());
+        match pad {
+            Some('0') => {
+                if n < 0 {
+                    out.push('-');
+                }
+                out.extend(std::iter::repeat_n('0', fill));
+            }
+            Some(pad) => {
+                out.extend(std::iter::repeat_n(pad, fill));
+                if n < 0 {
+                    out.push('-');
+                }
+            }
+            None if n < 0 => out.push('-'),
+            None => {}
+        }
+        out.push_str(&text);
+    }
+
+    /// Add `text`, where the `#` flag makes it lower case if `lower` and
+    /// otherwise upper case.
+    fn text(self, out: &mut String, text: &str, lower: bool) {
+        let text = if self.upcase || (self.swapcase && !lower) {
+            text.to_uppercase()
+        } else if self.swapcase {
+            text.to_lowercase()
+        } else {
+            text.to_owned()
+        };
+        let pad = if self.pad == Some('0') { '0' } else { ' ' };
+        let fill = self.width.unwrap_or(0).saturating_sub(text.chars().count());
+        if self.pad != Some('-') {
+            out.extend(std::iter::repeat_n(pad, fill));
+        }
+        out.push_str(&text);
+    }
+}
+
+/// The UTC offset `offset` with `colons` colons, like `+0530` for none.
+fn format_offset(offset: i64, colons: usize) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    let (hours, minutes, seconds) = (offset / 3600, offset / 60 % 60, offset % 60);
+    match colons {
+        0 => format!("{sign}{hours:02}{minutes:02}"),
+        1 => format!("{sign}{hours:02}:{minutes:02}"),
+        2 => format!("{sign}{hours:02}:{minutes:02}:{seconds:02}"),
+        _ => match (minutes, seconds) {
+            (0, 0) => format!("{sign}{hours:02}"),
+            (_, 0) => format!("{sign}{hours:02}:{minutes:02}"),
+            _ => format!("{sign}{hours:02}:{minutes:02}:{seconds:02}"),
+        },
+    }
+}
+
+/// Format `time`, which is `cal` in its time zone, as `strftime` does with
+/// `format`.
+pub(crate) fn format_time(format: &str, time: LispTime, cal: &CalendarTime) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' | '_' | '0' | '+' => spec.pad = Some(flag),
+                '^' => spec.upcase = true,
+                '#' => spec.swapcase = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        while let Some(digit) = chars.peek().and_then(|x| x.to_digit(10)) {
+            spec.width = Some(spec.width.unwrap_or(0) * 10 + digit as usize);
+            chars.next();
+        }
+        while chars.next_if_eq(&':').is_some() {
+            spec.colons += 1;
+        }
+        while chars.next_if(|&x| x == 'E' || x == 'O').is_some() {}
+        let Some(conv) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        let hour12 = (cal.hour + 11) % 12 + 1;
+        let am_pm = if cal.hour < 12 { "AM" } else { "PM" };
+        let weekday = WEEKDAYS[cal.weekday as usize];
+        let month = MONTHS[(cal.month - 1) as usize];
+        let nested = |format: &str| format_time(format, time, cal);
+        let out = &mut out;
+        match conv {
+            'a' => spec.text(out, &weekday[..3], false),
+            'A' => spec.text(out, weekday, false),
+            'b' | 'h' => spec.text(out, &month[..3], false),
+            'B' => spec.text(out, month, false),
+            'c' => spec.text(out, &nested("%a %b %e %H:%M:%S %Y"), false),
+            'C' => spec.number(out, cal.year.div_euclid(100), 2, '0'),
+            'd' => spec.number(out, cal.day, 2, '0'),
+            'D' | 'x' => spec.text(out, &nested("%m/%d/%y"), false),
+            'e' => spec.number(out, cal.day, 2, ' '),
+            'F' => spec.text(out, &nested("%Y-%m-%d"), false),
+            'g' => spec.number(out, iso_week(cal).0.rem_euclid(100), 2, '0'),
+            'G' => spec.number(out, iso_week(cal).0, 1, '0'),
+            'H' => spec.number(out, cal.hour, 2, '0'),
+            'I' => spec.number(out, hour12, 2, '0'),
+            'j' => spec.number(out, cal.yday + 1, 3, '0'),
+            'k' => spec.number(out, cal.hour, 2, ' '),
+            'l' => spec.number(out, hour12, 2, ' '),
+            'm' => spec.number(out, cal.month, 2, '0'),
+            'M' => spec.number(out, cal.minute, 2, '0'),
+            'n' => out.push('\n'),
+            'N' => {
+                let nanos = time.at_hz(1_000_000_000).ticks.rem_euclid(1_000_000_000);
+                let digits = spec.width.unwrap_or(9);
+                let mut text = format!("{nanos:09}");
+                text.truncate(digits);
+                _ = write!(out, "{text:0<digits$}");
+            }
+            'p' => spec.text(out, am_pm, true),
+            'P' => spec.text(out, &am_pm.to_lowercase(), false),
+            'q' => spec.number(out, (cal.month - 1) / 3 + 1, 1, '0'),
+            'r' => spec.text(out, &nested("%I:%M:%S %p"), false),
+            'R' => spec.text(out, &nested("%H:%M"), false),
+            's' => spec.number(out, i64::try_from(time.secs()).unwrap_or_default(), 1, '0'),
+            'S' => spec.number(out, cal.second, 2, '0'),
+            't' => out.push('\t'),
+            'T' | 'X' => spec.text(out, &nested("%H:%M:%S"), false),
+            'u' => spec.number(out, (cal.weekday + 6) % 7 + 1, 1, '0'),
+            'U' => spec.number(out, (cal.yday + 7 - cal.weekday) / 7, 2, '0'),
+            'V' => spec.number(out, iso_week(cal).1, 2, '0'),
+            'w' => spec.number(out, cal.weekday, 1, '0'),
+            'W' => spec.number(out, (cal.yday + 7 - (cal.weekday + 6) % 7) / 7, 2, '0'),
+            'y' => spec.number(out, cal.year.rem_euclid(100), 2, '0'),
+            'Y' => spec.number(out, cal.year, 1, '0'),
+            'z' => spec.text(out, &format_offset(cal.offset, spec.colons), false),
+            'Z' => spec.text(out, &cal.zone, true),
+            '%' => out.push('%'),
+            other => {
+                _ = write!(out, "%{other}");
+            }
+        }
+    }
+    out
+}
+
+/// Use FORMAT-STRING to format the time value TIME in the time zone ZONE.
+/// The conversions are those of `strftime`, where `%N` is the
+/// nanoseconds, or fewer digits of it with a width, `%q` is the quarter of
+/// the year, and `%:z`, `%::z` and `%:::z` are the UTC offset with colons.
+/// The flags `-`, `_` and `0` choose the padding, `^` converts to upper case
+/// and `#` swaps the case. ZONE is nil or `wall` for local time, t for UTC,
+/// an offset in seconds east of UTC, a list (OFFSET ABBR), or a `TZ` rule
+/// string like "Europe/Paris" or "EST5EDT,M3.2.0,M11.1.0".
+#[defun]
+fn format_time_string(
+    format_string: &str,
+    time: Option<GcObj>,
+    zone: Option<GcObj>,
+) -> Result<String> {
+    let time = decode(time.unwrap_or_else(nil))?;
+    let cal = CalendarTime::new(time.whole_secs()?, &decode_zone(zone)?)?;
+    Ok(format_time(format_string, time, &cal))
+}
+
+/// Return the time TIME in the time zone ZONE as a human-readable string,
+/// like "Sun Sep 16 01:03:52 1973".
+#[defun]
+fn current_time_string(time: Option<GcObj>, zone: Option<GcObj>) -> Result<String> {
+    format_time_string("%a %b %e %H:%M:%S %Y", time, zone)
+}
+
+/// Return the offset and name for the time zone ZONE at TIME, as a list
+/// (OFFSET NAME), where OFFSET is the seconds east of UTC.
+#[defun]
+fn current_time_zone<'ob>(
+    time: Option<GcObj>,
+    zone: Option<GcObj>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let time = decode(time.unwrap_or_else(nil))?;
+    let cal = CalendarTime::new(time.whole_secs()?, &decode_zone(zone)?)?;
+    Ok(list![cal.offset, cx.add(cal.zone); cx])
+}
+
+/// Set the local time zone using TZ, a zone rule as in
+/// `format-time-string`. A nil TZ restores the time zone that Emacs started
+/// with.
+#[defun]
+fn set_time_zone_rule(tz: Option<GcObj>) -> Result<bool> {
+    match decode_zone(tz)? {
+        Zone::Local => set_local_rule(None),
+        Zone::Utc => set_local_rule(Some("UTC0")),
+        Zone::Fixed(offset, name) => set_local_rule(Some(&fixed_rule(offset, name.as_deref()))),
+        Zone::Rule(rule) => set_local_rule(Some(&rule)),
+    }
+    Ok(false)
+}
+
+defvar_bool!(CURRENT_TIME_LIST, true);
+defsym!(WALL);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::{interpreter, reader, root};
+
+    #[test]
+    fn test_time_formats() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let time = |form: &str| decode(reader::read(form, cx).unwrap().0);
+        let ticks = |form: &str| time(form).map(|x| (x.ticks, x.hz)).unwrap();
+        assert_eq!(ticks("5"), (5, 1));
+        assert_eq!(ticks("1.5"), (3, 2));
+        assert_eq!(ticks("-0.25"), (-1, 4));
+        assert_eq!(ticks("(7 . 4)"), (7, 4));
+        assert_eq!(ticks("(1 2)"), (65538, 1));
+        assert_eq!(ticks("(0 1 500000)"), (1_500_000, 1_000_000));
+        assert_eq!(ticks("(0 1 0 1)"), (1_000_000_000_001, 1_000_000_000_000));
+        assert!(time("(1 . 0)").is_err());
+        assert!(time("(1 2 3 4 5)").is_err());
+        assert!(time("\"now\"").is_err());
+    }
+
+    #[test]
+    fn test_calendar() {
+        let cal = CalendarTime::new(951_782_400, &Zone::Utc).unwrap();
+        assert_eq!(
+            (cal.year, cal.month, cal.day, cal.weekday, cal.yday),
+            (2000, 2, 29, 2, 59)
+        );
+        assert_eq!(cal.encode(&Zone::Utc).unwrap(), 951_782_400);
+        let zone = Zone::Fixed(-18000, None);
+        let cal = CalendarTime::new(-1, &zone).unwrap();
+        assert_eq!((cal.year, cal.month, cal.day, cal.hour), (1969, 12, 31, 18));
+        assert_eq!(cal.zone, "-05");
+        assert_eq!(cal.encode(&zone).unwrap(), -1);
+        // out of range fields are carried
+        let cal = CalendarTime {
+            month: 14,
+            day: 0,
+            hour: 25,
+            ..cal
+        };
+        assert_eq!(
+            cal.encode(&Zone::Utc).unwrap(),
+            days_from_civil(1970, 2, 1) * 86400 + 3600 + 59 * 60 + 59
+        );
+    }
+
+    fn secs_of(year: i64, month: i64, day: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = LispTime {
+            ticks: 1_234_567_890_123_456_789,
+            hz: 1_000_000_000,
+        };
+        let cal = CalendarTime::new(1_234_567_890, &Zone::Fixed(19800, None)).unwrap();
+        let cases = [
+            ("%Y-%m-%d %H:%M:%S %z %Z", "2009-02-14 05:01:30 +0530 +0530"),
+            ("%a %A %b %B %h", "Sat Saturday Feb February Feb"),
+            (
+                "%c|%D|%F|%T|%R|%r",
+                "Sat Feb 14 05:01:30 2009|02/14/09|2009-02-14|05:01:30|05:01|05:01:30 AM",
+            ),
+            ("%C %y %e %k %l %I %p %P", "20 09 14  5  5 05 AM am"),
+            ("%j %u %w %U %W %V %G %g %q", "045 6 6 06 06 07 2009 09 1"),
+            ("%s %N %3N %6N", "1234567890 123456789 123 123456"),
+            ("%:z %::z %:::z %%", "+05:30 +05:30:00 +05:30 %"),
+            (
+                "%-d %_m %5Y %^a %#p %#Z %-5H.",
+                "14  2 02009 SAT am +0530 5.",
+            ),
+            ("%10B|%-j", "  February|45"),
+        ];
+        for (format, expect) in cases {
+            assert_eq!(format_time(format, time, &cal), expect, "{format}");
+        }
+        // ISO weeks at the edges of years
+        let cal = CalendarTime::new(secs_of(2021, 1, 1), &Zone::Utc).unwrap();
+        assert_eq!(format_time("%G-W%V-%u", time, &cal), "2020-W53-5");
+        let cal = CalendarTime::new(secs_of(2024, 12, 30), &Zone::Utc).unwrap();
+        assert_eq!(format_time("%G-W%V-%u", time, &cal), "2025-W01-1");
+    }
+
+    fn eval(form: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<String> {
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        interpreter::eval(obj, None, env, cx).map(|x| x.to_string())
+    }
+
+    #[test]
+    fn test_time_arith() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        env.vars.insert(sym::CURRENT_TIME_LIST, GcObj::from(true));
+        let cases = [
+            ("(time-add 1 2)", "3"),
+            ("(time-subtract 1 '(3 . 2))", "(-1 . 2)"),
+            ("(time-add '(1 . 3) '(1 . 7))", "(10 . 21)"),
+            ("(time-add '(0 1 500000) 1)", "(0 2 500000 0)"),
+            (
+                "(time-subtract '(0 1 0 1) '(0 1 0 2))",
+                "(-1 65535 999999 999999)",
+            ),
+            ("(time-add 0.5 '(1 . 4))", "0.75"),
+            ("(time-add 1e9 1e9)", "2000000000.0"),
+            // 0.1 is a little more than a tenth
+            ("(time-less-p 0.1 '(1 . 10))", "nil"),
+            ("(time-less-p '(1 . 10) 0.1)", "t"),
+            ("(time-less-p '(0 1) 1.5)", "t"),
+            ("(time-equal-p '(0 1 500000) 1.5)", "t"),
+            ("(time-equal-p '(3 . 2) '(6 . 4))", "t"),
+            ("(time-equal-p 1 nil)", "nil"),
+            ("(time-equal-p nil nil)", "t"),
+            ("(float-time '(1 . 4))", "0.25"),
+            ("(float-time '(0 1 250000))", "1.25"),
+            ("(time-less-p (time-subtract nil 1) nil)", "t"),
+        ];
+        for (form, expect) in cases {
+            assert_eq!(eval(form, env, cx).unwrap(), expect, "{form}");
+        }
+        env.vars.insert(sym::CURRENT_TIME_LIST, nil());
+        let sum = eval("(time-add '(0 1 500000) 1)", env, cx).unwrap();
+        assert_eq!(sum, "(2500000 . 1000000)");
+        assert!(eval("(time-add 'now 1)", env, cx).is_err());
+    }
+
+    #[test]
+    fn test_zone_rules() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let cases = [
+            (
+                "(format-time-string \"%F %T %Z %z\" 0 \"EST5EDT,M3.2.0,M11.1.0\")",
+                "\"1969-12-31 19:00:00 EST -0500\"",
+            ),
+            (
+                "(format-time-string \"%T %Z %z\" 1625140800 \"EST5EDT,M3.2.0,M11.1.0\")",
+                "\"08:00:00 EDT -0400\"",
+            ),
+            (
+                "(decode-time 1625140800 \"EST5EDT,M3.2.0,M11.1.0\")",
+                "(0 0 8 1 7 2021 4 t -14400)",
+            ),
+            // 01:30 happens twice when daylight saving time ends
+            (
+                "(encode-time '(0 30 1 7 11 2021 nil t \"EST5EDT,M3.2.0,M11.1.0\"))",
+                "(24967 25688)",
+            ),
+            (
+                "(encode-time '(0 30 1 7 11 2021 nil nil \"EST5EDT,M3.2.0,M11.1.0\"))",
+                "(24967 29288)",
+            ),
+            (
+                "(current-time-zone 0 \"EST5EDT,M3.2.0,M11.1.0\")",
+                "(-18000 \"EST\")",
+            ),
+            ("(format-time-string \"%Z %z\" 0 \"UTC0\")", "\"UTC +0000\""),
+        ];
+        for (form, expect) in cases {
+            assert_eq!(eval(form, env, cx).unwrap(), expect, "{form}");
+        }
+        if std::path::Path::new("/usr/share/zoneinfo/America/New_York").exists() {
+            let form = "(format-time-string \"%Z\" 1625140800 \"America/New_York\")";
+            assert_eq!(eval(form, env, cx).unwrap(), "\"EDT\"");
+        }
+        assert_eq!(fixed_rule(19800, None), "<+0530>-05:30:00");
+        assert_eq!(fixed_rule(-3600, Some("XTT")), "<XTT>+01:00:00");
+        assert_eq!(fixed_rule(3600, Some("X T")), "<+01>-01:00:00");
+        // the local zone, which other tests leave alone
+        let local = |env: &mut Rt<Env>, cx: &mut Context| {
+            eval("(format-time-string \"%Z %z\" 0)", env, cx).unwrap()
+        };
+        eval("(set-time-zone-rule \"EST5EDT,M3.2.0,M11.1.0\")", env, cx).unwrap();
+        assert_eq!(local(env, cx), "\"EST -0500\"");
+        let cal = CalendarTime::new(secs_of(2021, 7, 1), &Zone::Local).unwrap();
+        assert_eq!(cal.encode(&Zone::Local).unwrap(), secs_of(2021, 7, 1));
+        eval("(set-time-zone-rule '(3600 \"CET\"))", env, cx).unwrap();
+        assert_eq!(local(env, cx), "\"CET +0100\"");
+        eval("(setenv \"TZ\" \"UTC0\")", env, cx).unwrap();
+        assert_eq!(local(env, cx), "\"UTC +0000\"");
+        eval("(set-time-zone-rule nil)", env, cx).unwrap();
+    }
+
+    #[test]
+    fn test_time_functions() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        let cases = [
+            ("(time-convert 1.5)", "(3 . 2)"),
+            ("(time-convert '(1 2 3 4) 'integer)", "65538"),
+            ("(time-convert 65538.25 'list)", "(1 2 250000 0)"),
+            ("(time-convert '(3 . 2) 1000)", "(1500 . 1000)"),
+            ("(time-convert -0.5 'integer)", "-1"),
+            ("(decode-time 0 t)", "(0 0 0 1 1 1970 4 nil 0)"),
+            (
+                "(decode-time '(61 . 2) 3600 t)",
+                "((61 . 2) 0 1 1 1 1970 4 nil 3600)",
+            ),
+            (
+                "(decode-time 1.25 -3600 'integer)",
+                "(1 0 23 31 12 1969 3 nil -3600)",
+            ),
+            ("(encode-time '(0 0 0 1 1 2000 nil nil t))", "(14445 17280)"),
+            (
+                "(encode-time '((3 . 2) 60 0 1 1 1970 nil nil 3600))",
+                "(3 . 2)",
+            ),
+            ("(encode-time 0 0 0 1 1 2000 t)", "(14445 17280)"),
+            (
+                "(encode-time (decode-time 946684800 '(-3600 \"XT\")))",
+                "(14445 17280)",
+            ),
+            (
+                "(format-time-string \"%F %T %Z\" 0 '(7200 \"EET\"))",
+                "\"1970-01-01 02:00:00 EET\"",
+            ),
+            ("(current-time-string 0 t)", "\"Thu Jan  1 00:00:00 1970\""),
+        ];
+        for (form, expect) in cases {
+            assert_eq!(eval(form, env, cx).unwrap(), expect, "{form}");
+        }
+        assert!(eval("(time-convert 'foo)", env, cx).is_err());
+        assert!(eval("(decode-time 0 \"bad\" 'list)", env, cx).is_err());
+        env.vars.insert(sym::CURRENT_TIME_LIST, GcObj::from(true));
+        assert_eq!(eval("(length (current-time))", env, cx).unwrap(), "4");
+        env.vars.insert(sym::CURRENT_TIME_LIST, nil());
+        assert!(eval("(cdr (current-time))", env, cx)
+            .unwrap()
+            .ends_with("1000000000"));
+    }
+}
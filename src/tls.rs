@@ -0,0 +1,227 @@
+//! Encrypted network streams, with rustls. `make-network-process` makes a
+//! TLS connection when it is given `:tls-parameters`, the way Emacs does:
+//! the handshake is done before the process is made, and then the output of
+//! the process is the decrypted stream, and its input is encrypted before it
+//! is written to the socket.
+#![cfg(feature = "tls")]
+use crate::core::{
+    env::{sym, Env},
+    gc::{Context, Rt},
+    object::{GcObj, Object},
+};
+use crate::process::keyword_arg;
+use anyhow::{bail, Context as _, Result};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The state of a TLS connection, shared by the thread that reads it and
+/// the writers of its input.
+pub(crate) type Session = Arc<Mutex<ClientConnection>>;
+
+/// How a TLS connection is made, from the `:tls-parameters` of
+/// `make-network-process`.
+pub(crate) struct Parameters {
+    /// The name the certificate of the server has to be for
+    hostname: String,
+    /// The files of the certificates of the authorities that are trusted
+    trustfiles: Vec<String>,
+    /// The files of the key and the certificate to identify the client with
+    keylist: Vec<(String, String)>,
+}
+
+/// Parse `value`, which is `(TYPE . PLIST)` like the `:tls-parameters` of
+/// Emacs, where TYPE is `gnutls-x509pki`, and PLIST has the :hostname,
+/// :trustfiles and :keylist of `gnutls-boot`. The hostname defaults to
+/// `host`.
+pub(crate) fn parameters(value: GcObj, host: &str) -> Result<Parameters> {
+    let Object::Cons(cons) = value.untag() else {bail!("Invalid TLS parameters: {value}")};
+    match cons.car().untag() {
+        Object::Symbol(sym::GNUTLS_X509PKI) => {}
+        Object::Symbol(sym::GNUTLS_ANON) => bail!("Anonymous TLS connections are not supported"),
+        kind => bail!("Invalid TLS type: {kind}"),
+    }
+    let plist = cons.cdr().as_list()?.collect::<Result<Vec<_>>>()?;
+    let hostname = match keyword_arg(&plist, sym::KW_HOSTNAME) {
+        x if x.nil() => host.to_owned(),
+        x => <&str>::try_from(x)?.to_owned(),
+    };
+    let mut trustfiles = Vec::new();
+    for file in keyword_arg(&plist, sym::KW_TRUSTFILES).as_list()? {
+        trustfiles.push(<&str>::try_from(file?)?.to_owned());
+    }
+    let mut keylist = Vec::new();
+    for pair in keyword_arg(&plist, sym::KW_KEYLIST).as_list()? {
+        let files = pair?.as_list()?.collect::<Result<Vec<_>>>()?;
+        let [key, cert] = files[..] else {bail!("Invalid key and certificate: {files:?}")};
+        keylist.push((
+            <&str>::try_from(key)?.to_owned(),
+            <&str>::try_from(cert)?.to_owned(),
+        ));
+    }
+    Ok(Parameters {
+        hostname,
+        trustfiles,
+        keylist,
+    })
+}
+
+/// The certificates in the trust files that exist. Like Emacs, the files
+/// that are missing are skipped, so that the default list can name the
+/// files of every system.
+fn root_store(trustfiles: &[String]) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for file in trustfiles.iter().filter(|x| Path::new(x).exists()) {
+        for cert in CertificateDer::pem_file_iter(file)? {
+            roots.add(cert.with_context(|| format!("Invalid certificate in {file}"))?)?;
+        }
+    }
+    Ok(roots)
+}
+
+fn client_config(params: &Parameters) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder().with_root_certificates(root_store(&params.trustfiles)?);
+    let Some((key, cert)) = params.keylist.first() else {return Ok(builder.with_no_client_auth())};
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Reading client certificate {cert}"))?;
+    let key =
+        PrivateKeyDer::from_pem_file(key).with_context(|| format!("Reading client key {key}"))?;
+    Ok(builder.with_client_auth_cert(certs, key)?)
+}
+
+/// Make a TLS connection over `stream`, and check the certificate of the
+/// server. A certificate that is not valid is reported with the reason.
+pub(crate) fn negotiate(stream: &mut TcpStream, params: &Parameters) -> Result<Session> {
+    let config = Arc::new(client_config(params)?);
+    let name = ServerName::try_from(params.hostname.clone())?;
+    let mut session = ClientConnection::new(config, name)?;
+    while session.is_handshaking() {
+        if let Err(e) = session.complete_io(stream) {
+            let host = &params.hostname;
+            match e.get_ref().and_then(|x| x.downcast_ref::<rustls::Error>()) {
+                Some(e @ rustls::Error::InvalidCertificate(_)) => {
+                    bail!("Certificate validation failed {host}: {e}")
+                }
+                _ => bail!("TLS handshake with {host} failed: {e}"),
+            }
+        }
+    }
+    Ok(Arc::new(Mutex::new(session)))
+}
+
+/// Encrypt `bytes` for `session` and queue them in `out`, after anything
+/// else that it has to send.
+pub(crate) fn encrypt(session: &Session, bytes: &[u8], out: &mut VecDeque<u8>) -> io::Result<()> {
+    let mut session = session.lock().unwrap();
+    session.writer().write_all(bytes)?;
+    while session.wants_write() {
+        session.write_tls(out)?;
+    }
+    Ok(())
+}
+
+/// Tell the server that no more input comes. The alert is sent with the next
+/// input that is encrypted.
+pub(crate) fn close_notify(session: &Session) {
+    session.lock().unwrap().send_close_notify();
+}
+
+/// Reads the decrypted output of a TLS connection from its socket.
+pub(crate) struct Reader {
+    socket: TcpStream,
+    session: Session,
+    buf: Vec<u8>,
+}
+
+impl Reader {
+    pub(crate) fn new(socket: TcpStream, session: Session) -> Self {
+        Self {
+            socket,
+            session,
+            buf: vec![0; 4096],
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.session.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                // the server closed the connection without saying so
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                result => return result,
+            }
+            // the session is not locked while waiting, so input can be sent
+            let len = self.socket.read(&mut self.buf)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            let mut session = self.session.lock().unwrap();
+            let mut records = &self.buf[..len];
+            while !records.is_empty() {
+                session.read_tls(&mut records)?;
+                session
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        }
+    }
+}
+
+/// The `:tls-parameters` for a TLS connection to `host`, with the trust
+/// files of `gnutls-trustfiles`, and the key and certificate of
+/// `client_certificate`, which is a list of the two files or nil.
+pub(crate) fn boot_parameters<'ob>(
+    host: GcObj<'ob>,
+    client_certificate: GcObj<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> GcObj<'ob> {
+    // a symbol like `local` is not a name the certificate can be for
+    let hostname = match host.untag() {
+        Object::String(_) => host,
+        _ => crate::core::object::nil(),
+    };
+    let trustfiles = env
+        .vars
+        .get(sym::GNUTLS_TRUSTFILES)
+        .map_or_else(crate::core::object::nil, |x| x.bind(cx));
+    // t asks Emacs to look the certificate up with auth-source
+    let keylist = match client_certificate.untag() {
+        Object::Cons(_) => list![client_certificate; cx],
+        _ => crate::core::object::nil(),
+    };
+    list![
+        sym::GNUTLS_X509PKI,
+        sym::KW_HOSTNAME,
+        hostname,
+        sym::KW_TRUSTFILES,
+        trustfiles,
+        sym::KW_KEYLIST,
+        keylist;
+        cx
+    ]
+}
+
+defsym!(GNUTLS_X509PKI);
+defsym!(GNUTLS_ANON);
+defsym!(KW_HOSTNAME);
+defsym!(KW_TRUSTFILES);
+defsym!(KW_KEYLIST);
+defvar!(
+    GNUTLS_TRUSTFILES,
+    list![
+        "/etc/ssl/certs/ca-certificates.crt",
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        "/etc/ssl/ca-bundle.pem",
+        "/usr/ssl/certs/ca-bundle.crt",
+        "/usr/local/share/certs/ca-root-nss.crt",
+        "/etc/ssl/cert.pem"
+    ]
+);
@@ -0,0 +1,354 @@
+//! Unicode normalization: the canonical and compatibility decompositions
+//! NFD and NFKD, the compositions NFC and NFKC made from them, and the
+//! variants of NFD and NFC that the HFS+ file system of macOS uses for file
+//! names.
+#[cfg(feature = "buffers")]
+use crate::buffer::get_current_buffer;
+#[cfg(feature = "buffers")]
+use crate::core::env::Env;
+#[cfg(feature = "buffers")]
+use crate::core::gc::{Context, Rt};
+#[cfg(feature = "buffers")]
+use crate::editfns::validate_region;
+use crate::unidata::{combining_class, compose_pair, decomposition_mapping, hangul_decomposition};
+#[cfg(feature = "buffers")]
+use anyhow::Result;
+use fn_macros::defun;
+
+/// A normalization form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Form {
+    Nfd,
+    Nfc,
+    Nfkd,
+    Nfkc,
+    HfsNfd,
+    HfsNfc,
+}
+
+impl Form {
+    fn compat(self) -> bool {
+        matches!(self, Form::Nfkd | Form::Nfkc)
+    }
+
+    fn composed(self) -> bool {
+        matches!(self, Form::Nfc | Form::Nfkc | Form::HfsNfc)
+    }
+}
+
+/// Characters that HFS+ keeps as they are: general punctuation, the
+/// symbols, and the CJK compatibility ideographs.
+const HFS_EXCLUDED: &[(u32, u32)] = &[(0x2000, 0x2FFF), (0xF900, 0xFAFF), (0x2F800, 0x2FAFF)];
+
+fn hfs_excluded(chr: u32) -> bool {
+    HFS_EXCLUDED.iter().any(|x| x.0 <= chr && chr <= x.1)
+}
+
+/// Append the full decomposition of `chr` to `out`.
+fn decompose_char(chr: u32, form: Form, out: &mut Vec<u32>) {
+    if matches!(form, Form::HfsNfd | Form::HfsNfc) && hfs_excluded(chr) {
+        return out.push(chr);
+    }
+    if let Some(jamo) = hangul_decomposition(chr) {
+        return out.extend(jamo);
+    }
+    match decomposition_mapping(chr) {
+        Some((compat, parts)) if !compat || form.compat() => {
+            for part in parts {
+                decompose_char(part, form, out);
+            }
+        }
+        _ => out.push(chr),
+    }
+}
+
+/// Put each run of combining characters in order of combining class. The
+/// sort is stable, so characters of the same class keep their order.
+fn canonical_order(chars: &mut [u32]) {
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let len = chars[start..]
+            .iter()
+            .take_while(|x| combining_class(**x) != 0)
+            .count();
+        chars[start..start + len].sort_by_key(|x| combining_class(*x));
+        start += len;
+    }
+}
+
+/// Combine the characters that canonical composition combines, each with
+/// the last starter before it that no character in between blocks.
+fn compose(chars: &[u32]) -> Vec<u32> {
+    let mut result: Vec<u32> = Vec::with_capacity(chars.len());
+    let mut starter = None;
+    let mut last_class = 0;
+    for &chr in chars {
+        let class = combining_class(chr);
+        if let Some(idx) = starter {
+            let adjacent = idx + 1 == result.len();
+            if adjacent || (last_class != 0 && last_class < class) {
+                if let Some(composite) = compose_pair(result[idx], chr) {
+                    result[idx] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(result.len());
+        }
+        last_class = class;
+        result.push(chr);
+    }
+    result
+}
+
+/// `text` in normalization form `form`.
+pub(crate) fn normalize(text: &str, form: Form) -> String {
+    let mut chars = Vec::with_capacity(text.len());
+    for chr in text.chars() {
+        decompose_char(chr.into(), form, &mut chars);
+    }
+    canonical_order(&mut chars);
+    if form.composed() {
+        chars = compose(&chars);
+    }
+    chars.into_iter().filter_map(char::from_u32).collect()
+}
+
+/// Return STR in Normalization Form D, the canonical decomposition.
+#[defun(name = "ucs-normalize-NFD-string")]
+fn ucs_normalize_nfd_string(str: &str) -> String {
+    normalize(str, Form::Nfd)
+}
+
+/// Return STR in Normalization Form C, the canonical decomposition
+/// followed by canonical composition.
+#[defun(name = "ucs-normalize-NFC-string")]
+fn ucs_normalize_nfc_string(str: &str) -> String {
+    normalize(str, Form::Nfc)
+}
+
+/// Return STR in Normalization Form KD, the compatibility decomposition.
+#[defun(name = "ucs-normalize-NFKD-string")]
+fn ucs_normalize_nfkd_string(str: &str) -> String {
+    normalize(str, Form::Nfkd)
+}
+
+/// Return STR in Normalization Form KC, the compatibility decomposition
+/// followed by canonical composition.
+#[defun(name = "ucs-normalize-NFKC-string")]
+fn ucs_normalize_nfkc_string(str: &str) -> String {
+    normalize(str, Form::Nfkc)
+}
+
+/// Return STR decomposed the way the HFS+ file system stores file names:
+/// like NFD, but without decomposing general punctuation, symbols and
+/// the CJK compatibility ideographs.
+#[defun(name = "ucs-normalize-HFS-NFD-string")]
+fn ucs_normalize_hfs_nfd_string(str: &str) -> String {
+    normalize(str, Form::HfsNfd)
+}
+
+/// Return STR decomposed like `ucs-normalize-HFS-NFD-string` and then
+/// composed, which turns a file name from HFS+ into NFC.
+#[defun(name = "ucs-normalize-HFS-NFC-string")]
+fn ucs_normalize_hfs_nfc_string(str: &str) -> String {
+    normalize(str, Form::HfsNfc)
+}
+
+/// Compose the characters of STRING that can be composed, as in NFC.
+#[defun]
+fn string_glyph_compose(string: &str) -> String {
+    normalize(string, Form::Nfc)
+}
+
+/// Decompose the characters of STRING into their base characters and
+/// combining characters, as in NFD.
+#[defun]
+fn string_glyph_decompose(string: &str) -> String {
+    normalize(string, Form::Nfd)
+}
+
+/// Normalize the text between FROM and TO in the current buffer. Only the
+/// part of the text that changes is replaced, so markers before and after
+/// it stay where they are.
+#[cfg(feature = "buffers")]
+fn normalize_region(
+    form: Form,
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let bounds = {
+        let data = buffer.lock()?;
+        (data.begv(), data.zv())
+    };
+    let (beg, end) = validate_region(from, to, bounds, env, cx)?;
+    let old: Vec<char> = buffer.lock()?.text.substring(beg, end).chars().collect();
+    let new: Vec<char> = normalize(&old.iter().collect::<String>(), form)
+        .chars()
+        .collect();
+    let prefix = old.iter().zip(&new).take_while(|(x, y)| x == y).count();
+    let rest = old.len().min(new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(rest)
+        .take_while(|(x, y)| x == y)
+        .count();
+    if prefix == old.len() && old.len() == new.len() {
+        return Ok(false);
+    }
+    let (change_beg, change_end) = (beg + prefix, end - suffix);
+    let point = buffer.lock()?.point();
+    crate::buffer::delete_text(change_beg, change_end, env, cx)?;
+    buffer.lock()?.goto_char(change_beg);
+    let replacement: String = new[prefix..new.len() - suffix].iter().collect();
+    crate::buffer::insert_text(&replacement, env, cx)?;
+    let point = if point >= change_end {
+        point + new.len() - old.len()
+    } else {
+        point.min(change_beg)
+    };
+    buffer.lock()?.goto_char(point);
+    Ok(false)
+}
+
+/// Normalize the region from FROM to TO into Normalization Form D.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-NFD-region")]
+fn ucs_normalize_nfd_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::Nfd, from, to, env, cx)
+}
+
+/// Normalize the region from FROM to TO into Normalization Form C.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-NFC-region")]
+fn ucs_normalize_nfc_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::Nfc, from, to, env, cx)
+}
+
+/// Normalize the region from FROM to TO into Normalization Form KD.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-NFKD-region")]
+fn ucs_normalize_nfkd_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::Nfkd, from, to, env, cx)
+}
+
+/// Normalize the region from FROM to TO into Normalization Form KC.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-NFKC-region")]
+fn ucs_normalize_nfkc_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::Nfkc, from, to, env, cx)
+}
+
+/// Decompose the region from FROM to TO the way HFS+ stores file names.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-HFS-NFD-region")]
+fn ucs_normalize_hfs_nfd_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::HfsNfd, from, to, env, cx)
+}
+
+/// Compose the region from FROM to TO, decomposed the way HFS+ stores
+/// file names.
+#[cfg(feature = "buffers")]
+#[defun(name = "ucs-normalize-HFS-NFC-region")]
+fn ucs_normalize_hfs_nfc_region(
+    from: usize,
+    to: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    normalize_region(Form::HfsNfc, from, to, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let forms = |text: &str| {
+            [Form::Nfd, Form::Nfc, Form::Nfkd, Form::Nfkc].map(|form| normalize(text, form))
+        };
+        assert_eq!(forms("é"), ["e\u{301}", "é", "e\u{301}", "é"]);
+        assert_eq!(forms("ﬁ"), ["ﬁ", "ﬁ", "fi", "fi"]);
+        assert_eq!(
+            forms("\u{1E9B}\u{323}"),
+            [
+                "\u{17F}\u{323}\u{307}",
+                "\u{1E9B}\u{323}",
+                "s\u{323}\u{307}",
+                "\u{1E69}"
+            ]
+        );
+        assert_eq!(
+            forms("각"),
+            [
+                "\u{1100}\u{1161}\u{11A8}",
+                "각",
+                "\u{1100}\u{1161}\u{11A8}",
+                "각"
+            ]
+        );
+        // combining characters are put in order of their class
+        assert_eq!(normalize("q\u{307}\u{323}", Form::Nfc), "q\u{323}\u{307}");
+        assert_eq!(normalize("a\u{308}\u{301}", Form::Nfc), "ä\u{301}");
+        // excluded from composition
+        assert_eq!(normalize("\u{2126}", Form::Nfc), "\u{3A9}");
+        assert_eq!(normalize("\u{915}\u{93C}", Form::Nfc), "\u{915}\u{93C}");
+        assert_eq!(normalize("\u{F900}é", Form::HfsNfd), "\u{F900}e\u{301}");
+    }
+
+    #[cfg(feature = "buffers")]
+    #[test]
+    fn test_normalize_region() {
+        use crate::core::env::Env;
+        use crate::core::gc::{Context, RootSet};
+        use crate::interpreter::check;
+        use crate::root;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        check("(set-buffer (get-buffer-create \"normalize\"))", env, cx);
+        check("(insert \"caf\u{e9} au lait\")", env, cx);
+        let form = "(list (progn (ucs-normalize-NFD-region 1 13) (buffer-string)) (point)
+                          (progn (ucs-normalize-NFC-region 1 14) (buffer-string)) (point))";
+        assert_eq!(
+            check(form, env, cx).to_string(),
+            "(\"cafe\u{301} au lait\" 14 \"caf\u{e9} au lait\" 13)"
+        );
+    }
+}
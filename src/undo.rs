@@ -0,0 +1,668 @@
+//! Recording changes to buffer text in `buffer-undo-list` and undoing them.
+//!
+//! Each buffer has its own undo list. The most recent change is first, and
+//! groups of changes that are undone together are separated by nil
+//! boundaries. The entries are
+//!
+//! - `(BEG . END)`: text was inserted between BEG and END
+//! - `(TEXT . POS)`: TEXT was deleted from POS. If POS is negative, point was
+//!   at the end of the text
+//! - `(nil PROP VAL BEG . END)`: property PROP of the text from BEG to END
+//!   had the value VAL
+//! - `POS`: point was at POS before the first change after a boundary
+//! - `(apply FUN . ARGS)` or `(apply DELTA BEG END FUN . ARGS)`: undone by
+//!   calling FUN with ARGS
+//!
+//! If the list is t, undo information is not recorded.
+#![cfg(feature = "buffers")]
+use crate::buffer::{buffer_var, get_current_buffer, get_live_buffer, set_buffer_var};
+use crate::core::cons::Cons;
+use crate::core::env::{sym, Env, Symbol};
+use crate::core::gc::{Context, Rt};
+use crate::core::object::{nil, Buffer, Function, Gc, GcObj, List, Object};
+use crate::root;
+use anyhow::{bail, ensure, Result};
+use fn_macros::defun;
+
+/// Approximate sizes in bytes of the parts of an undo list, used to decide
+/// where to truncate it.
+const CONS_SIZE: usize = 16;
+const STRING_SIZE: usize = 32;
+
+/// The undo list of BUFFER, or `None` if undo is disabled in it.
+fn undo_list<'ob>(
+    buffer: &'static Buffer,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Option<GcObj<'ob>> {
+    let list = buffer_var(buffer, sym::BUFFER_UNDO_LIST, env, cx);
+    (list != sym::TRUE).then_some(list)
+}
+
+fn push_entry(buffer: &'static Buffer, entry: GcObj, list: GcObj, env: &mut Rt<Env>, cx: &Context) {
+    set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, cons!(entry, list; cx), env);
+}
+
+/// The most recent entry of LIST if it is a cons.
+fn last_entry(list: GcObj<'_>) -> Option<&Cons> {
+    match list.untag() {
+        Object::Cons(head) => match head.car().untag() {
+            Object::Cons(entry) => Some(entry),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Record where point is if this is the first change after a boundary.
+/// Nothing is recorded if point is at `pos`, since undoing the change will
+/// leave it there anyway.
+fn record_point(
+    buffer: &'static Buffer,
+    pos: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let Some(list) = undo_list(buffer, env, cx) else {return Ok(())};
+    let at_boundary = match list.untag() {
+        Object::Cons(head) => head.car().nil(),
+        _ => true,
+    };
+    let point = buffer.lock()?.point();
+    if at_boundary && point != pos {
+        push_entry(buffer, (point + 1).into(), list, env, cx);
+    }
+    Ok(())
+}
+
+/// Record that `len` characters were inserted at `beg` in BUFFER. An
+/// insertion that continues the previous one extends its entry.
+pub(crate) fn record_insert(
+    buffer: &'static Buffer,
+    beg: usize,
+    len: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    record_point(buffer, beg, env, cx)?;
+    let Some(list) = undo_list(buffer, env, cx) else {return Ok(())};
+    let (beg, end) = (beg as i64 + 1, (beg + len) as i64 + 1);
+    if let Some(entry) = last_entry(list) {
+        if let (Object::Int(_), Object::Int(prev_end)) = (entry.car().untag(), entry.cdr().untag())
+        {
+            if prev_end == beg && entry.set_cdr(end.into()).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    push_entry(buffer, cons!(beg, end; cx), list, env, cx);
+    Ok(())
+}
+
+/// Record that TEXT was deleted from `beg` in BUFFER. Deleting forward from
+/// the same position or backward from the start of the previous deletion
+/// extends its entry.
+pub(crate) fn record_delete(
+    buffer: &'static Buffer,
+    beg: usize,
+    text: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    if text.is_empty() {
+        return Ok(());
+    }
+    let end = beg + text.chars().count();
+    let at_end = buffer.lock()?.point() == end;
+    record_point(buffer, if at_end { end } else { beg }, env, cx)?;
+    let Some(list) = undo_list(buffer, env, cx) else {return Ok(())};
+    let pos = if at_end {
+        -(beg as i64 + 1)
+    } else {
+        beg as i64 + 1
+    };
+    if let Some(entry) = last_entry(list) {
+        if let (Object::String(prev), Object::Int(prev_pos)) =
+            (entry.car().untag(), entry.cdr().untag())
+        {
+            let prev: &str = prev.try_into()?;
+            let combined = if pos > 0 && prev_pos == pos {
+                Some(format!("{prev}{text}"))
+            } else if pos < 0 && prev_pos == -(end as i64 + 1) {
+                Some(format!("{text}{prev}"))
+            } else {
+                None
+            };
+            if let Some(combined) = combined {
+                if entry.set_car(cx.add(combined)).is_ok() {
+                    entry.set_cdr(pos.into())?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+    push_entry(buffer, cons!(text, pos; cx), list, env, cx);
+    Ok(())
+}
+
+/// Record that property PROP of the text from `beg` to `end` in BUFFER had
+/// VALUE before it changed.
+pub(crate) fn record_property_change(
+    buffer: &'static Buffer,
+    (beg, end): (usize, usize),
+    prop: Symbol,
+    value: GcObj,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    let Some(list) = undo_list(buffer, env, cx) else {return};
+    let range = cons!(beg as i64 + 1, end as i64 + 1; cx);
+    let entry = cons!(nil(), cons!(prop, cons!(value, range; cx); cx); cx);
+    push_entry(buffer, entry, list, env, cx);
+}
+
+/// The approximate memory used by ENTRY and its list cell.
+fn entry_size(entry: GcObj) -> usize {
+    let mut size = CONS_SIZE;
+    if let Object::Cons(cons) = entry.untag() {
+        size += CONS_SIZE;
+        if let Object::String(text) = cons.car().untag() {
+            size += STRING_SIZE + <&str>::try_from(text).map_or(0, str::len);
+        }
+    }
+    size
+}
+
+/// Truncate LIST at a boundary once it grows past the undo limits. The most
+/// recent group of changes is kept unless it is larger than
+/// `undo-outer-limit`. Older groups are kept until the list is larger than
+/// `undo-limit`, except that a group that would make it larger than
+/// `undo-strong-limit` is dropped. A limit that is not an integer is ignored.
+fn truncate_undo_list<'ob>(
+    list: GcObj<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let limit = |var| match env.vars.get(var).map(|x| x.get(cx)) {
+        Some(Object::Int(x)) => usize::try_from(x).unwrap_or_default(),
+        _ => usize::MAX,
+    };
+    let undo_limit = limit(sym::UNDO_LIMIT);
+    let strong_limit = limit(sym::UNDO_STRONG_LIMIT);
+    let outer_limit = limit(sym::UNDO_OUTER_LIMIT);
+    let list: Gc<List> = list.try_into()?;
+    let mut size = 0;
+    let mut first_group = true;
+    // the cell before the boundary that starts the current group
+    let mut boundary: Option<&Cons> = None;
+    let mut prev: Option<&Cons> = None;
+    for cell in list.conses() {
+        let cell = cell?;
+        let entry = cell.car();
+        // a leading boundary doesn't end a group
+        if let (true, Some(prev)) = (entry.nil(), prev) {
+            if first_group {
+                if size > outer_limit {
+                    return Ok(nil());
+                }
+                first_group = false;
+            } else if size > undo_limit {
+                prev.set_cdr(nil())?;
+                return Ok(list.into());
+            }
+            boundary = Some(prev);
+        }
+        size += entry_size(entry);
+        if !first_group && size > strong_limit {
+            if let Some(boundary) = boundary {
+                boundary.set_cdr(nil())?;
+            }
+            return Ok(list.into());
+        }
+        prev = Some(cell);
+    }
+    if first_group && size > outer_limit {
+        return Ok(nil());
+    }
+    Ok(list.into())
+}
+
+/// Add a boundary to the undo list of the current buffer. The changes between
+/// two boundaries are undone together. The list is truncated to the undo
+/// limits at each boundary.
+#[defun]
+fn undo_boundary(env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let buffer = get_current_buffer(env);
+    let Some(list) = undo_list(buffer, env, cx) else {return Ok(false)};
+    let list = match list.untag() {
+        Object::Cons(head) if !head.car().nil() => cons!(nil(), list; cx),
+        _ => list,
+    };
+    let list = truncate_undo_list(list, env, cx)?;
+    set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, list, env);
+    Ok(false)
+}
+
+/// Stop recording undo information in BUFFER, the current buffer by default,
+/// and discard what was recorded.
+#[defun]
+fn buffer_disable_undo(buffer: Option<GcObj>, env: &mut Rt<Env>) -> Result<bool> {
+    let buffer = match buffer {
+        Some(buffer) if !buffer.nil() => get_live_buffer(buffer)?,
+        _ => get_current_buffer(env),
+    };
+    set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, sym::TRUE.into(), env);
+    Ok(false)
+}
+
+/// Start recording undo information in BUFFER, the current buffer by default,
+/// if it was disabled.
+#[defun]
+fn buffer_enable_undo(buffer: Option<GcObj>, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    let buffer = match buffer {
+        Some(buffer) if !buffer.nil() => get_live_buffer(buffer)?,
+        _ => get_current_buffer(env),
+    };
+    if undo_list(buffer, env, cx).is_none() {
+        set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, nil(), env);
+    }
+    Ok(false)
+}
+
+/// Convert the 1-based region from `beg` to `end` to 0-based positions,
+/// checking that it is in the accessible portion of BUFFER.
+fn undo_region(buffer: &Buffer, beg: i64, end: i64) -> Result<(usize, usize)> {
+    let data = buffer.lock()?;
+    let in_range =
+        |x: i64| usize::try_from(x).is_ok_and(|x| (data.begv() + 1..=data.zv() + 1).contains(&x));
+    ensure!(
+        in_range(beg) && in_range(end),
+        "Changes to be undone are outside visible portion of buffer"
+    );
+    Ok((beg.min(end) as usize - 1, beg.max(end) as usize - 1))
+}
+
+/// Parse the property change `(nil PROP VAL BEG . END)`.
+fn property_change(entry: &Cons) -> Option<(Symbol<'_>, GcObj<'_>, i64, i64)> {
+    let Object::Cons(rest) = entry.cdr().untag() else {return None};
+    let prop: Symbol = rest.car().try_into().ok()?;
+    let Object::Cons(rest) = rest.cdr().untag() else {return None};
+    let Object::Cons(range) = rest.cdr().untag() else {return None};
+    match (range.car().untag(), range.cdr().untag()) {
+        (Object::Int(beg), Object::Int(end)) => Some((prop, rest.car(), beg, end)),
+        _ => None,
+    }
+}
+
+/// Undo the change recorded in ENTRY.
+fn undo_entry(entry: &Rt<GcObj>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let buffer = get_current_buffer(env);
+    let cons = match entry.get(cx) {
+        Object::Cons(cons) => cons,
+        // point was at POS
+        Object::Int(pos) => {
+            let (pos, _) = undo_region(buffer, pos, pos)?;
+            buffer.lock()?.goto_char(pos);
+            return Ok(());
+        }
+        x => bail!("Unrecognized entry in undo list {x}"),
+    };
+    match (cons.car().untag(), cons.cdr().untag()) {
+        (Object::Int(beg), Object::Int(end)) => {
+            let (beg, end) = undo_region(buffer, beg, end)?;
+            // move point first so it is not recorded again when this is undone
+            buffer.lock()?.goto_char(beg);
+            crate::buffer::delete_text(beg, end, env, cx)?;
+        }
+        (Object::String(text), Object::Int(pos)) => {
+            let text = <&str>::try_from(text)?.to_owned();
+            let (at, _) = undo_region(buffer, pos.abs(), pos.abs())?;
+            buffer.lock()?.goto_char(at);
+            crate::buffer::insert_text(&text, env, cx)?;
+            if pos > 0 {
+                buffer.lock()?.goto_char(at);
+            }
+        }
+        // (t . TIME-FLAG): the buffer was unmodified
+        // TODO: restore the modified flag once it is tracked
+        (Object::Symbol(sym::TRUE), _) => {}
+        (Object::NIL, _) => {
+            let Some((prop, value, beg, end)) = property_change(cons) else {bail!("Unrecognized entry in undo list {cons}")};
+            undo_region(buffer, beg, end)?;
+            let (beg, end) = (beg as usize, end as usize);
+            crate::textprop::put_text_property(beg, end, prop, value, None, env, cx)?;
+        }
+        (Object::Symbol(sym::APPLY), _) => undo_apply(entry, env, cx)?,
+        _ => bail!("Unrecognized entry in undo list {cons}"),
+    }
+    Ok(())
+}
+
+/// Undo `(apply FUN . ARGS)` or `(apply DELTA BEG END FUN . ARGS)` by calling
+/// FUN with ARGS. With DELTA, FUN must change the size of the buffer by
+/// DELTA.
+fn undo_apply(entry: &Rt<GcObj>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let Object::Cons(entry) = entry.get(cx) else {unreachable!("undo entry is not a cons")};
+    let form = entry.cdr();
+    let mut args = form.as_list()?.collect::<Result<Vec<_>>>()?;
+    let delta = match args.first().map(|x| x.untag()) {
+        Some(Object::Int(delta)) => {
+            ensure!(args.len() >= 4, "Malformed undo entry (apply {form})");
+            args.drain(..3);
+            Some(delta)
+        }
+        _ => None,
+    };
+    ensure!(!args.is_empty(), "Malformed undo entry (apply {form})");
+    let func: Gc<Function> = args.remove(0).try_into()?;
+    root!(func, cx);
+    root!(args, move(args), cx);
+    let buffer = get_current_buffer(env);
+    let len = buffer.lock()?.text.len_chars() as i64;
+    func.call(args, env, cx, None)?;
+    if let Some(delta) = delta {
+        let new_len = buffer.lock()?.text.len_chars() as i64;
+        ensure!(
+            new_len - len == delta,
+            "Changes to be undone by function different from announced"
+        );
+    }
+    Ok(())
+}
+
+/// Undo N groups of changes from the front of LIST and return the rest of
+/// the list. The changes made while undoing are recorded as usual, so they
+/// can be undone in turn.
+#[defun]
+fn primitive_undo<'ob>(
+    n: usize,
+    list: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<GcObj<'ob>> {
+    root!(rest, move(list.bind(cx)), cx);
+    for _ in 0..n {
+        while let Object::Cons(cons) = rest.get(cx) {
+            let entry = cons.car();
+            rest.set(cons.cdr());
+            if entry.nil() {
+                break;
+            }
+            root!(entry, cx);
+            undo_entry(entry, env, cx)?;
+        }
+    }
+    Ok(rest.bind(cx))
+}
+
+/// Start a change group in the current buffer. Returns the buffer and its
+/// undo list, which the changes in the group are recorded in front of. Undo
+/// is enabled for the duration of the group.
+pub(crate) fn prepare_change_group<'ob>(
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<(&'static Buffer, GcObj<'ob>)> {
+    let buffer = get_current_buffer(env);
+    let list = buffer_var(buffer, sym::BUFFER_UNDO_LIST, env, cx);
+    let mut start = if list == sym::TRUE { nil() } else { list };
+    // an insertion could be merged into the previous one, so add an empty
+    // one to keep the group separate
+    if last_entry(start).is_some_and(|x| matches!(x.car().untag(), Object::Int(_))) {
+        let point = buffer.lock()?.point() as i64 + 1;
+        start = cons!(cons!(point, point; cx), start; cx);
+    }
+    set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, start, env);
+    Ok((buffer, list))
+}
+
+/// End the change group started with LIST in BUFFER, keeping its changes.
+pub(crate) fn accept_change_group(
+    buffer: &'static Buffer,
+    list: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    if list.bind(cx) == sym::TRUE && buffer.is_live() {
+        set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, sym::TRUE.into(), env);
+    }
+}
+
+/// End the change group started with LIST in BUFFER by undoing its changes.
+/// The undo list is restored to LIST.
+pub(crate) fn cancel_change_group(
+    buffer: &'static Buffer,
+    list: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if !buffer.is_live() {
+        return Ok(());
+    }
+    let depth = env.binding_depth();
+    env.bind_current_buffer();
+    crate::buffer::set_current_buffer(buffer, env, cx);
+    // undo in the whole buffer, and restore point and the restriction after
+    let point = {
+        let mut data = buffer.lock()?;
+        data.save_restriction();
+        data.widen();
+        let point = data.point();
+        data.make_marker(point)
+    };
+    let result = undo_change_group(buffer, list, env, cx);
+    if let Ok(mut data) = buffer.lock() {
+        data.restore_restriction();
+        let pos = data.marker_position(point);
+        data.goto_char(pos);
+        data.free_marker(point);
+    }
+    set_buffer_var(buffer, sym::BUFFER_UNDO_LIST, list.bind(cx), env);
+    env.unbind_to(depth, cx);
+    result
+}
+
+/// Undo the entries of the undo list of BUFFER that were added after LIST.
+fn undo_change_group(
+    buffer: &'static Buffer,
+    list: &Rt<GcObj>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let entries = buffer_var(buffer, sym::BUFFER_UNDO_LIST, env, cx);
+    root!(rest, move(entries), cx);
+    while let Object::Cons(cons) = rest.get(cx) {
+        if crate::fns::eq(cons.into(), list.bind(cx)) {
+            break;
+        }
+        let entry = cons.car();
+        rest.set(cons.cdr());
+        if entry.nil() {
+            continue;
+        }
+        root!(entry, cx);
+        undo_entry(entry, env, cx)?;
+    }
+    Ok(())
+}
+
+defvar!(BUFFER_UNDO_LIST);
+defvar!(UNDO_LIMIT, 160_000);
+defvar!(UNDO_STRONG_LIMIT, 240_000);
+defvar!(UNDO_OUTER_LIMIT, 24_000_000);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::check;
+    use crate::{interpreter, reader};
+
+    fn buffer_text(env: &mut Rt<Env>) -> String {
+        get_current_buffer(env).lock().unwrap().text.to_string()
+    }
+
+    #[test]
+    fn test_undo() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        check("(set-buffer (get-buffer-create \"undo-test\"))", env, cx);
+        check("(insert \"hello\")", env, cx);
+        check("(insert \" world\")", env, cx);
+        assert_eq!(check("buffer-undo-list", env, cx).to_string(), "((1 . 12))");
+        check("(undo-boundary)", env, cx);
+        check("(delete-region 1 7)", env, cx);
+        assert_eq!(
+            check("buffer-undo-list", env, cx).to_string(),
+            "((\"hello \" . 1) 12 nil (1 . 12))"
+        );
+
+        // backward deletions are combined
+        check("(undo-boundary)", env, cx);
+        check("(delete-region 5 6)", env, cx);
+        check("(delete-region 4 5)", env, cx);
+        assert_eq!(
+            check("(car buffer-undo-list)", env, cx).to_string(),
+            "(\"ld\" . -4)"
+        );
+        assert_eq!(
+            check("(primitive-undo 1 buffer-undo-list)", env, cx).to_string(),
+            "((\"hello \" . 1) 12 nil (1 . 12))"
+        );
+        assert_eq!(buffer_text(env), "world");
+        assert_eq!(check("(point)", env, cx), 6);
+
+        check("(undo-boundary)", env, cx);
+        check(
+            "(setq pending (cdr (memq nil (cdr buffer-undo-list))))",
+            env,
+            cx,
+        );
+        check("(primitive-undo 1 pending)", env, cx);
+        assert_eq!(buffer_text(env), "hello world");
+        assert_eq!(check("(point)", env, cx), 12);
+
+        // property changes
+        check("(undo-boundary)", env, cx);
+        check("(put-text-property 1 3 'face 'bold)", env, cx);
+        assert_eq!(
+            check("(car buffer-undo-list)", env, cx).to_string(),
+            "(nil face nil 1 . 3)"
+        );
+        check("(primitive-undo 1 buffer-undo-list)", env, cx);
+        assert!(check("(get-text-property 1 'face)", env, cx).nil());
+
+        let form = "(primitive-undo 1 (list (cons 20 30)))";
+        let obj = reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        assert!(interpreter::eval(obj, None, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_atomic_change_group() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        check("(set-buffer (get-buffer-create \"change-group\"))", env, cx);
+        check("(insert \"hello\")", env, cx);
+        let list = check("buffer-undo-list", env, cx).to_string();
+        let form = "(condition-case nil
+                        (atomic-change-group
+                          (insert \" world\")
+                          (put-text-property 1 3 'face 'bold)
+                          (delete-region 1 3)
+                          (error \"abort\"))
+                      (error 'aborted))";
+        assert_eq!(check(form, env, cx), sym!(aborted));
+        assert_eq!(buffer_text(env), "hello");
+        assert!(check("(get-text-property 1 'face)", env, cx).nil());
+        assert_eq!(check("buffer-undo-list", env, cx).to_string(), list);
+
+        // a throw out of the group undoes it too
+        let form = "(catch 'done (atomic-change-group (insert \"!\") (throw 'done 1)))";
+        assert_eq!(check(form, env, cx), 1);
+        assert_eq!(buffer_text(env), "hello");
+
+        // the changes are kept when the body finishes
+        check("(atomic-change-group (insert \"!\"))", env, cx);
+        assert_eq!(buffer_text(env), "hello!");
+
+        // changes are undone even if undo is disabled
+        check("(buffer-disable-undo)", env, cx);
+        let form =
+            "(ignore-errors (atomic-change-group (goto-char 1) (insert \"x\") (error \"abort\")))";
+        check(form, env, cx);
+        assert_eq!(buffer_text(env), "hello!");
+        assert_eq!(check("buffer-undo-list", env, cx), sym::TRUE);
+    }
+
+    #[test]
+    fn test_disable_undo() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        check("(set-buffer (get-buffer-create \"undo-disable\"))", env, cx);
+        check("(insert \"abc\")", env, cx);
+        check("(buffer-disable-undo)", env, cx);
+        assert_eq!(check("buffer-undo-list", env, cx), sym::TRUE);
+        check("(insert \"abc\")", env, cx);
+        assert_eq!(check("buffer-undo-list", env, cx), sym::TRUE);
+        check("(buffer-enable-undo)", env, cx);
+        check("(insert \"d\")", env, cx);
+        assert_eq!(check("buffer-undo-list", env, cx).to_string(), "((7 . 8))");
+
+        // each buffer has its own list
+        check("(set-buffer (get-buffer-create \"undo-other\"))", env, cx);
+        assert!(check("buffer-undo-list", env, cx).nil());
+        check("(buffer-disable-undo \"undo-disable\")", env, cx);
+        assert!(check("buffer-undo-list", env, cx).nil());
+        check("(set-buffer \"undo-disable\")", env, cx);
+        assert_eq!(check("buffer-undo-list", env, cx), sym::TRUE);
+        // internal buffers don't record undo
+        check(
+            "(set-buffer (get-buffer-create \" undo-internal\"))",
+            env,
+            cx,
+        );
+        assert_eq!(check("buffer-undo-list", env, cx), sym::TRUE);
+    }
+
+    #[test]
+    fn test_truncate_undo_list() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, Env::default(), cx);
+        crate::core::env::init_variables(cx, env);
+        check(
+            "(set-buffer (get-buffer-create \"undo-truncate\"))",
+            env,
+            cx,
+        );
+        let list = "(list (cons 1 2) nil (cons 3 4) nil (cons 5 6))";
+        check(&format!("(setq buffer-undo-list {list})"), env, cx);
+        check(
+            "(let ((undo-limit 40)) (undo-boundary) buffer-undo-list)",
+            env,
+            cx,
+        );
+        assert_eq!(
+            check("buffer-undo-list", env, cx).to_string(),
+            "(nil (1 . 2) nil (3 . 4))"
+        );
+        check(&format!("(setq buffer-undo-list {list})"), env, cx);
+        check("(let ((undo-strong-limit 70)) (undo-boundary))", env, cx);
+        assert_eq!(
+            check("buffer-undo-list", env, cx).to_string(),
+            "(nil (1 . 2))"
+        );
+        check(&format!("(setq buffer-undo-list {list})"), env, cx);
+        check("(let ((undo-outer-limit 20)) (undo-boundary))", env, cx);
+        assert!(check("buffer-undo-list", env, cx).nil());
+    }
+}
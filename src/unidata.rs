@@ -0,0 +1,560 @@
+//! Character properties from the Unicode database. The tables in
+//! `src/unidata` are made by `src/unidata/gen.py` and parsed the first time
+//! a property is needed, so they work without the Unicode data files of
+//! Emacs.
+use crate::charset::check_char;
+use crate::core::env::{intern, Symbol};
+use crate::core::gc::Context;
+use crate::core::object::{nil, GcObj, Object};
+use crate::fns::slice_into_list;
+use crate::hashmap::HashMap;
+use anyhow::Result;
+use fn_macros::defun;
+use lazy_static::lazy_static;
+
+/// The properties that are kept as runs of characters.
+#[derive(Debug, Clone, Copy)]
+struct Properties {
+    category: &'static str,
+    bidi_class: &'static str,
+    combining_class: i64,
+    mirrored: bool,
+}
+
+/// The names of characters: the ranges whose names are a prefix followed by
+/// the code in hex, and the other names sorted by character.
+#[derive(Debug, Default)]
+struct Names {
+    ranges: Vec<(u32, u32, &'static str)>,
+    names: Vec<(u32, Box<str>)>,
+}
+
+/// The lines of a table, without its header.
+fn lines(table: &'static str) -> impl Iterator<Item = &'static str> {
+    table.lines().filter(|x| !x.starts_with('#'))
+}
+
+fn hex(field: &str) -> u32 {
+    u32::from_str_radix(field, 16).expect("invalid character in Unicode table")
+}
+
+fn parse_properties(table: &'static str) -> Vec<(u32, Properties)> {
+    let parse = |line: &'static str| {
+        let fields: Vec<_> = line.split(' ').collect();
+        let props = Properties {
+            category: fields[1],
+            bidi_class: fields[2],
+            combining_class: fields[3].parse().unwrap(),
+            mirrored: fields[4] == "Y",
+        };
+        (hex(fields[0]), props)
+    };
+    lines(table).map(parse).collect()
+}
+
+fn parse_mappings(table: &'static str) -> Vec<(u32, &'static str)> {
+    let parse = |line: &'static str| {
+        let (chr, fields) = line.split_once(';').unwrap();
+        (hex(chr), fields)
+    };
+    lines(table).map(parse).collect()
+}
+
+fn parse_names(table: &'static str) -> Names {
+    let mut names = Names::default();
+    // the distance of the first name is from the character before 0
+    let (mut chr, mut prev) = (u32::MAX, String::new());
+    for line in lines(table) {
+        if let Some(range) = line.strip_prefix('=') {
+            let mut fields = range.splitn(3, ' ');
+            let mut next = || fields.next().unwrap();
+            names.ranges.push((hex(next()), hex(next()), next()));
+            continue;
+        }
+        let (delta, name) = line.split_once(' ').unwrap();
+        chr = chr.wrapping_add(if delta.is_empty() { 1 } else { hex(delta) });
+        let mut chars = name.chars();
+        let shared = chars.next().map_or(0, |x| u32::from(x) - u32::from('0'));
+        prev.truncate(shared as usize);
+        prev.push_str(chars.as_str());
+        names.names.push((chr, prev.as_str().into()));
+    }
+    names
+}
+
+fn parse_compositions(table: &'static str) -> HashMap<(u32, u32), u32> {
+    let parse = |line: &'static str| {
+        let fields: Vec<_> = line.split(' ').map(hex).collect();
+        ((fields[0], fields[1]), fields[2])
+    };
+    lines(table).map(parse).collect()
+}
+
+lazy_static! {
+    static ref PROPERTIES: Vec<(u32, Properties)> =
+        parse_properties(include_str!("unidata/categories.txt"));
+    static ref MAPPINGS: Vec<(u32, &'static str)> =
+        parse_mappings(include_str!("unidata/mappings.txt"));
+    static ref NAMES: Names = parse_names(include_str!("unidata/names.txt"));
+    static ref COMPOSITIONS: HashMap<(u32, u32), u32> =
+        parse_compositions(include_str!("unidata/compositions.txt"));
+}
+
+fn properties(chr: u32) -> Properties {
+    let idx = PROPERTIES.partition_point(|x| x.0 <= chr);
+    PROPERTIES[idx.saturating_sub(1)].1
+}
+
+/// The fields of the mappings of `chr`: its decomposition, decimal digit,
+/// digit and numeric values, and its uppercase, lowercase and titlecase
+/// forms.
+fn mappings(chr: u32) -> [&'static str; 7] {
+    let mut fields = [""; 7];
+    if let Ok(idx) = MAPPINGS.binary_search_by_key(&chr, |x| x.0) {
+        for (field, value) in fields.iter_mut().zip(MAPPINGS[idx].1.split(';')) {
+            *field = value;
+        }
+    }
+    fields
+}
+
+/// The canonical combining class of `chr`.
+pub(crate) fn combining_class(chr: u32) -> i64 {
+    properties(chr).combining_class
+}
+
+/// The characters that have a decomposition mapping, in order.
+pub(crate) fn decomposable_chars() -> impl Iterator<Item = u32> {
+    let chars = MAPPINGS.iter().filter(|x| !x.1.starts_with(';'));
+    chars.map(|x| x.0)
+}
+
+/// The general category of `chr`, such as `Lu`.
+pub(crate) fn general_category(chr: u32) -> &'static str {
+    properties(chr).category
+}
+
+/// The value of `chr` if it is a decimal digit.
+pub(crate) fn decimal_digit_value(chr: u32) -> Option<u32> {
+    mappings(chr)[1].parse().ok()
+}
+
+/// The titlecase form of `chr` if it is not its uppercase form, which is
+/// only true of a few digraphs like `ǆ`. Those that are already in
+/// titlecase are their own titlecase form.
+pub(crate) fn titlecase(chr: u32) -> Option<u32> {
+    let [.., upper, _, title] = mappings(chr);
+    match title {
+        "" if general_category(chr) == "Lt" => Some(chr),
+        "" => None,
+        _ if title == upper => None,
+        _ => Some(hex(title)),
+    }
+}
+
+/// The decomposition mapping of `chr` and whether it is a compatibility
+/// mapping, or None if it has none. Hangul syllables have no mapping here.
+pub(crate) fn decomposition_mapping(chr: u32) -> Option<(bool, Vec<u32>)> {
+    let field = mappings(chr)[0];
+    if field.is_empty() {
+        return None;
+    }
+    let parts = field.split_whitespace().filter(|x| !x.starts_with('<'));
+    Some((field.starts_with('<'), parts.map(hex).collect()))
+}
+
+const HANGUL_BASE: u32 = 0xAC00;
+const HANGUL_COUNT: u32 = 11172;
+const JAMO_L_BASE: u32 = 0x1100;
+const JAMO_V_BASE: u32 = 0x1161;
+const JAMO_T_BASE: u32 = 0x11A7;
+const JAMO_L_COUNT: u32 = 19;
+const JAMO_V_COUNT: u32 = 21;
+const JAMO_T_COUNT: u32 = 28;
+const JAMO_L: [&str; 19] = [
+    "G", "GG", "N", "D", "DD", "R", "M", "B", "BB", "S", "SS", "", "J", "JJ", "C", "K", "T", "P",
+    "H",
+];
+const JAMO_V: [&str; 21] = [
+    "A", "AE", "YA", "YAE", "EO", "E", "YEO", "YE", "O", "WA", "WAE", "OE", "YO", "U", "WEO", "WE",
+    "WI", "YU", "EU", "YI", "I",
+];
+const JAMO_T: [&str; 28] = [
+    "", "G", "GG", "GS", "N", "NJ", "NH", "D", "L", "LG", "LM", "LB", "LS", "LT", "LP", "LH", "M",
+    "B", "BS", "S", "SS", "NG", "J", "C", "K", "T", "P", "H",
+];
+
+/// The indexes of the leading consonant, vowel and trailing consonant of a
+/// Hangul syllable.
+fn hangul_jamo(chr: u32) -> Option<(u32, u32, u32)> {
+    let idx = chr.checked_sub(HANGUL_BASE).filter(|x| *x < HANGUL_COUNT)?;
+    let per_l = JAMO_V_COUNT * JAMO_T_COUNT;
+    Some((idx / per_l, idx % per_l / JAMO_T_COUNT, idx % JAMO_T_COUNT))
+}
+
+/// The jamo that the Hangul syllable `chr` is made of, or None if it is not
+/// one.
+pub(crate) fn hangul_decomposition(chr: u32) -> Option<Vec<u32>> {
+    let (l, v, t) = hangul_jamo(chr)?;
+    let mut jamo = vec![JAMO_L_BASE + l, JAMO_V_BASE + v];
+    if t != 0 {
+        jamo.push(JAMO_T_BASE + t);
+    }
+    Some(jamo)
+}
+
+/// The character that canonical composition makes of `first` followed by
+/// `second`, if there is one.
+pub(crate) fn compose_pair(first: u32, second: u32) -> Option<u32> {
+    let jamo_l = first.wrapping_sub(JAMO_L_BASE);
+    let jamo_v = second.wrapping_sub(JAMO_V_BASE);
+    let jamo_t = second.wrapping_sub(JAMO_T_BASE);
+    if jamo_l < JAMO_L_COUNT && jamo_v < JAMO_V_COUNT {
+        return Some(HANGUL_BASE + (jamo_l * JAMO_V_COUNT + jamo_v) * JAMO_T_COUNT);
+    }
+    match hangul_jamo(first) {
+        Some((_, _, 0)) if 0 < jamo_t && jamo_t < JAMO_T_COUNT => Some(first + jamo_t),
+        _ => COMPOSITIONS.get(&(first, second)).copied(),
+    }
+}
+
+/// The name of `chr`, or None if it has none.
+fn char_name(chr: u32) -> Option<String> {
+    if let Some((l, v, t)) = hangul_jamo(chr) {
+        let (l, v, t) = (JAMO_L[l as usize], JAMO_V[v as usize], JAMO_T[t as usize]);
+        return Some(format!("HANGUL SYLLABLE {l}{v}{t}"));
+    }
+    let names = &*NAMES;
+    if let Some((_, _, prefix)) = names.ranges.iter().find(|x| x.0 <= chr && chr <= x.1) {
+        return Some(format!("{prefix}{chr:04X}"));
+    }
+    let idx = names.names.binary_search_by_key(&chr, |x| x.0).ok()?;
+    Some(names.names[idx].1.to_string())
+}
+
+/// The decomposition of `chr` as Emacs gives it: a list of characters,
+/// after a tag symbol for a compatibility decomposition, or a list of
+/// `chr` if it has none.
+fn decomposition<'ob>(chr: u32, cx: &'ob Context) -> GcObj<'ob> {
+    let jamo = hangul_decomposition(chr).unwrap_or_default();
+    let mut parts: Vec<GcObj> = jamo.into_iter().map(|x| i64::from(x).into()).collect();
+    for part in mappings(chr)[0].split_whitespace() {
+        match part.strip_prefix('<').and_then(|x| x.strip_suffix('>')) {
+            Some(tag) => parts.push(intern(tag, cx).into()),
+            None => parts.push(i64::from(hex(part)).into()),
+        }
+    }
+    if parts.is_empty() {
+        parts.push(i64::from(chr).into());
+    }
+    slice_into_list(&parts, None, cx)
+}
+
+fn number<'ob>(field: &str, cx: &'ob Context) -> GcObj<'ob> {
+    if let Ok(int) = field.parse::<i64>() {
+        int.into()
+    } else if let Ok(float) = field.parse::<f64>() {
+        cx.add(float)
+    } else {
+        nil()
+    }
+}
+
+/// Return the value of Unicode property PROPNAME of CHAR. The properties
+/// are `name`, `general-category`, `canonical-combining-class`,
+/// `bidi-class`, `decomposition`, `decimal-digit-value`, `digit-value`,
+/// `numeric-value`, `mirrored`, `uppercase`, `lowercase` and `titlecase`.
+/// The value of an unknown property is nil.
+#[defun]
+fn get_char_code_property<'ob>(
+    char: i64,
+    propname: Symbol,
+    cx: &'ob Context,
+) -> Result<GcObj<'ob>> {
+    let chr = check_char(char)?;
+    let props = properties(chr);
+    let [_, decimal, digit, numeric, upper, lower, title] = mappings(chr);
+    let case = |field: &str| match field {
+        "" => nil(),
+        _ => i64::from(hex(field)).into(),
+    };
+    Ok(match propname.name() {
+        "name" => match char_name(chr) {
+            Some(name) => cx.add(name),
+            None => nil(),
+        },
+        "general-category" => intern(props.category, cx).into(),
+        "canonical-combining-class" => props.combining_class.into(),
+        "bidi-class" => intern(props.bidi_class, cx).into(),
+        "decomposition" => decomposition(chr, cx),
+        "decimal-digit-value" => number(decimal, cx),
+        "digit-value" => number(digit, cx),
+        "numeric-value" => number(numeric, cx),
+        "mirrored" => props.mirrored.into(),
+        "uppercase" => case(upper),
+        "lowercase" => case(lower),
+        "titlecase" => case(title),
+        _ => nil(),
+    })
+}
+
+const CATEGORY_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("Lu", "Letter, Uppercase"),
+    ("Ll", "Letter, Lowercase"),
+    ("Lt", "Letter, Titlecase"),
+    ("Lm", "Letter, Modifier"),
+    ("Lo", "Letter, Other"),
+    ("Mn", "Mark, Nonspacing"),
+    ("Mc", "Mark, Spacing Combining"),
+    ("Me", "Mark, Enclosing"),
+    ("Nd", "Number, Decimal Digit"),
+    ("Nl", "Number, Letter"),
+    ("No", "Number, Other"),
+    ("Pc", "Punctuation, Connector"),
+    ("Pd", "Punctuation, Dash"),
+    ("Ps", "Punctuation, Open"),
+    ("Pe", "Punctuation, Close"),
+    ("Pi", "Punctuation, Initial quote"),
+    ("Pf", "Punctuation, Final quote"),
+    ("Po", "Punctuation, Other"),
+    ("Sm", "Symbol, Math"),
+    ("Sc", "Symbol, Currency"),
+    ("Sk", "Symbol, Modifier"),
+    ("So", "Symbol, Other"),
+    ("Zs", "Separator, Space"),
+    ("Zl", "Separator, Line"),
+    ("Zp", "Separator, Paragraph"),
+    ("Cc", "Other, Control"),
+    ("Cf", "Other, Format"),
+    ("Cs", "Other, Surrogate"),
+    ("Co", "Other, Private Use"),
+    ("Cn", "Other, Not Assigned"),
+];
+
+const BIDI_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("L", "Left-to-Right"),
+    ("LRE", "Left-to-Right Embedding"),
+    ("LRO", "Left-to-Right Override"),
+    ("R", "Right-to-Left"),
+    ("AL", "Right-to-Left Arabic"),
+    ("RLE", "Right-to-Left Embedding"),
+    ("RLO", "Right-to-Left Override"),
+    ("PDF", "Pop Directional Format"),
+    ("LRI", "Left-to-Right Isolate"),
+    ("RLI", "Right-to-Left Isolate"),
+    ("FSI", "First Strong Isolate"),
+    ("PDI", "Pop Directional Isolate"),
+    ("EN", "European Number"),
+    ("ES", "European Number Separator"),
+    ("ET", "European Number Terminator"),
+    ("AN", "Arabic Number"),
+    ("CS", "Common Number Separator"),
+    ("NSM", "Non-Spacing Mark"),
+    ("BN", "Boundary Neutral"),
+    ("B", "Paragraph Separator"),
+    ("S", "Segment Separator"),
+    ("WS", "Whitespace"),
+    ("ON", "Other Neutrals"),
+];
+
+/// Return a description of VALUE of the Unicode property PROP, or nil if
+/// there is none. Only `general-category` and `bidi-class` have
+/// descriptions.
+#[defun]
+fn char_code_property_description<'ob>(prop: Symbol, value: GcObj, cx: &'ob Context) -> GcObj<'ob> {
+    let table = match prop.name() {
+        "general-category" => CATEGORY_DESCRIPTIONS,
+        "bidi-class" => BIDI_DESCRIPTIONS,
+        _ => return nil(),
+    };
+    let Object::Symbol(value) = value.untag() else {return nil()};
+    let desc = table.iter().find(|x| x.0 == value.name());
+    desc.map_or_else(nil, |x| cx.add(x.1))
+}
+
+/// The scripts of ranges of characters, by the names Emacs gives them.
+const SCRIPTS: &[(u32, u32, &str)] = &[
+    (0x0000, 0x024F, "latin"),
+    (0x0250, 0x02AF, "phonetic"),
+    (0x0370, 0x03FF, "greek"),
+    (0x0400, 0x052F, "cyrillic"),
+    (0x0530, 0x058F, "armenian"),
+    (0x0590, 0x05FF, "hebrew"),
+    (0x0600, 0x06FF, "arabic"),
+    (0x0700, 0x074F, "syriac"),
+    (0x0750, 0x077F, "arabic"),
+    (0x0780, 0x07BF, "thaana"),
+    (0x07C0, 0x07FF, "nko"),
+    (0x0800, 0x083F, "samaritan"),
+    (0x0840, 0x085F, "mandaic"),
+    (0x08A0, 0x08FF, "arabic"),
+    (0x0900, 0x097F, "devanagari"),
+    (0x0980, 0x09FF, "bengali"),
+    (0x0A00, 0x0A7F, "gurmukhi"),
+    (0x0A80, 0x0AFF, "gujarati"),
+    (0x0B00, 0x0B7F, "oriya"),
+    (0x0B80, 0x0BFF, "tamil"),
+    (0x0C00, 0x0C7F, "telugu"),
+    (0x0C80, 0x0CFF, "kannada"),
+    (0x0D00, 0x0D7F, "malayalam"),
+    (0x0D80, 0x0DFF, "sinhala"),
+    (0x0E00, 0x0E7F, "thai"),
+    (0x0E80, 0x0EFF, "lao"),
+    (0x0F00, 0x0FFF, "tibetan"),
+    (0x1000, 0x109F, "burmese"),
+    (0x10A0, 0x10FF, "georgian"),
+    (0x1100, 0x11FF, "hangul"),
+    (0x1200, 0x139F, "ethiopic"),
+    (0x13A0, 0x13FF, "cherokee"),
+    (0x1400, 0x167F, "canadian-aboriginal"),
+    (0x1680, 0x169F, "ogham"),
+    (0x16A0, 0x16FF, "runic"),
+    (0x1700, 0x171F, "tagalog"),
+    (0x1780, 0x17FF, "khmer"),
+    (0x1800, 0x18AF, "mongolian"),
+    (0x1C90, 0x1CBF, "georgian"),
+    (0x1D00, 0x1DBF, "phonetic"),
+    (0x1E00, 0x1EFF, "latin"),
+    (0x1F00, 0x1FFF, "greek"),
+    (0x2000, 0x27FF, "symbol"),
+    (0x2800, 0x28FF, "braille"),
+    (0x2900, 0x2BFF, "symbol"),
+    (0x2C00, 0x2C5F, "glagolitic"),
+    (0x2C60, 0x2C7F, "latin"),
+    (0x2C80, 0x2CFF, "coptic"),
+    (0x2D00, 0x2D2F, "georgian"),
+    (0x2D30, 0x2D7F, "tifinagh"),
+    (0x2D80, 0x2DDF, "ethiopic"),
+    (0x2DE0, 0x2DFF, "cyrillic"),
+    (0x2E00, 0x2E7F, "symbol"),
+    (0x2E80, 0x2FFF, "han"),
+    (0x3000, 0x303F, "cjk-misc"),
+    (0x3040, 0x30FF, "kana"),
+    (0x3100, 0x312F, "bopomofo"),
+    (0x3130, 0x318F, "hangul"),
+    (0x3190, 0x319F, "kanbun"),
+    (0x31A0, 0x31BF, "bopomofo"),
+    (0x31C0, 0x31EF, "cjk-misc"),
+    (0x31F0, 0x31FF, "kana"),
+    (0x3200, 0x33FF, "cjk-misc"),
+    (0x3400, 0x4DBF, "han"),
+    (0x4DC0, 0x4DFF, "symbol"),
+    (0x4E00, 0x9FFF, "han"),
+    (0xA000, 0xA4CF, "yi"),
+    (0xA4D0, 0xA4FF, "lisu"),
+    (0xA500, 0xA63F, "vai"),
+    (0xA640, 0xA69F, "cyrillic"),
+    (0xA720, 0xA7FF, "latin"),
+    (0xA980, 0xA9DF, "javanese"),
+    (0xAA00, 0xAA5F, "cham"),
+    (0xAB30, 0xAB6F, "latin"),
+    (0xAC00, 0xD7FF, "hangul"),
+    (0xF900, 0xFAFF, "han"),
+    (0xFB00, 0xFB06, "latin"),
+    (0xFB13, 0xFB17, "armenian"),
+    (0xFB1D, 0xFB4F, "hebrew"),
+    (0xFB50, 0xFDFF, "arabic"),
+    (0xFE30, 0xFE4F, "cjk-misc"),
+    (0xFE70, 0xFEFF, "arabic"),
+    (0xFF00, 0xFF5F, "cjk-misc"),
+    (0xFF61, 0xFF9F, "kana"),
+    (0xFFA0, 0xFFDC, "hangul"),
+    (0xFFE0, 0xFFEF, "cjk-misc"),
+    (0x10000, 0x100FF, "linear-b"),
+    (0x10300, 0x1032F, "old-italic"),
+    (0x10330, 0x1034F, "gothic"),
+    (0x10380, 0x1039F, "ugaritic"),
+    (0x103A0, 0x103DF, "old-persian"),
+    (0x10400, 0x1044F, "deseret"),
+    (0x13000, 0x1343F, "egyptian"),
+    (0x17000, 0x18AFF, "tangut"),
+    (0x18B00, 0x18CFF, "khitan"),
+    (0x1B000, 0x1B16F, "kana"),
+    (0x1B170, 0x1B2FF, "nushu"),
+    (0x1D400, 0x1D7FF, "mathematical"),
+    (0x1F000, 0x1F2FF, "symbol"),
+    (0x1F300, 0x1F64F, "emoji"),
+    (0x1F680, 0x1F6FF, "emoji"),
+    (0x1F900, 0x1F9FF, "emoji"),
+    (0x1FA70, 0x1FAFF, "emoji"),
+    (0x20000, 0x323AF, "han"),
+];
+
+/// Return the script of CHAR, such as `latin` or `han`, or nil if it is
+/// not known.
+#[defun]
+fn char_script<'ob>(char: i64, cx: &'ob Context) -> Result<GcObj<'ob>> {
+    let chr = check_char(char)?;
+    let idx = SCRIPTS.partition_point(|x| x.1 < chr);
+    let script = SCRIPTS.get(idx).filter(|x| x.0 <= chr);
+    Ok(script.map_or_else(nil, |x| intern(x.2, cx).into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    fn property(chr: u32, prop: &str, cx: &Context) -> String {
+        let prop = intern(prop, cx);
+        get_char_code_property(chr.into(), prop, cx)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_char_code_property() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(
+            property('A'.into(), "name", cx),
+            "\"LATIN CAPITAL LETTER A\""
+        );
+        assert_eq!(property('A'.into(), "general-category", cx), "Lu");
+        assert_eq!(property('A'.into(), "lowercase", cx), "97");
+        assert_eq!(property('A'.into(), "uppercase", cx), "nil");
+        assert_eq!(property('a'.into(), "titlecase", cx), "65");
+        assert_eq!(property(10, "name", cx), "\"<control>\"");
+        assert_eq!(property('é'.into(), "decomposition", cx), "(101 769)");
+        assert_eq!(
+            property('ﬁ'.into(), "decomposition", cx),
+            "(compat 102 105)"
+        );
+        assert_eq!(property('a'.into(), "decomposition", cx), "(97)");
+        assert_eq!(
+            property('각'.into(), "decomposition", cx),
+            "(4352 4449 4520)"
+        );
+        assert_eq!(property('각'.into(), "name", cx), "\"HANGUL SYLLABLE GAG\"");
+        assert_eq!(property('中'.into(), "name", cx), "\"CJK IDEOGRAPH-4E2D\"");
+        assert_eq!(property(0x1F600, "name", cx), "\"GRINNING FACE\"");
+        assert_eq!(property(0xE000, "name", cx), "nil");
+        assert_eq!(property('7'.into(), "decimal-digit-value", cx), "7");
+        assert_eq!(property('½'.into(), "numeric-value", cx), "0.5");
+        assert_eq!(property('Ⅻ'.into(), "numeric-value", cx), "12");
+        assert_eq!(property(0x301, "canonical-combining-class", cx), "230");
+        assert_eq!(property('(' as u32, "mirrored", cx), "t");
+        assert_eq!(property(0x5D0, "bidi-class", cx), "R");
+        assert_eq!(property('a'.into(), "no-such-property", cx), "nil");
+        let names = &NAMES.names;
+        assert!(names.windows(2).all(|x| x[0].0 < x[1].0));
+    }
+
+    #[test]
+    fn test_char_script() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let script = |chr: char| char_script(u32::from(chr).into(), cx).unwrap().to_string();
+        assert_eq!(script('a'), "latin");
+        assert_eq!(script('λ'), "greek");
+        assert_eq!(script('中'), "han");
+        assert_eq!(script('か'), "kana");
+        assert_eq!(script('😀'), "emoji");
+        assert_eq!(script('\u{E000}'), "nil");
+        assert!(SCRIPTS.windows(2).all(|x| x[0].1 < x[1].0));
+        let general = intern("general-category", cx);
+        let value = intern("Nd", cx).into();
+        let desc = char_code_property_description(general, value, cx);
+        assert_eq!(desc.to_string(), "\"Number, Decimal Digit\"");
+    }
+}
@@ -0,0 +1,153 @@
+"""Generate the character property tables of `src/unidata.rs`.
+
+The tables are made from the Unicode database of Python's `unicodedata`
+module, so the Unicode version is the one of the Python that runs this:
+
+    python3 src/unidata/gen.py
+
+Each table is a text file with a header line that says which version it was
+made from.
+
+categories.txt -- runs of characters with the same general category, bidi
+    class, canonical combining class and mirrored property, as
+    `START CATEGORY BIDI CLASS MIRRORED` with START in hex and MIRRORED
+    as Y or N.
+
+mappings.txt -- the characters that have a decomposition, a numeric value
+    or a simple case mapping, as
+    `CHAR;DECOMPOSITION;DECIMAL;DIGIT;NUMERIC;UPPER;LOWER;TITLE` where CHAR
+    and the mappings are in hex and empty fields have no value.
+
+compositions.txt -- the pairs of characters that canonical composition
+    combines, as `FIRST SECOND COMPOSITE` in hex. Hangul syllables are
+    composed by the algorithm of the standard and are not included.
+
+names.txt -- the names of characters. A line that starts with `=` is a
+    range `=START END PREFIX` whose names are PREFIX followed by the code
+    in hex. Other lines are `DELTA PREFIX-LENGTH SUFFIX`: DELTA is the hex
+    distance from the character of the previous line, or empty if it is 1,
+    and the name is the first PREFIX-LENGTH characters of the previous name
+    followed by SUFFIX, where PREFIX-LENGTH is a character whose code is 48
+    more than the length.
+"""
+
+import os
+import unicodedata
+
+MAX_PREFIX = 74
+HERE = os.path.dirname(os.path.abspath(__file__))
+HEADER = f"# Unicode {unicodedata.unidata_version}, generated by gen.py\n"
+# Emacs names the unified ideographs this way
+RENAMED_PREFIXES = {"CJK UNIFIED IDEOGRAPH-": "CJK IDEOGRAPH-"}
+
+
+def write(name, lines):
+    with open(os.path.join(HERE, name), "w", encoding="utf-8") as f:
+        f.write(HEADER)
+        f.write("\n".join(lines))
+        f.write("\n")
+
+
+def categories():
+    lines = []
+    prev = None
+    for code in range(0x110000):
+        chr_ = chr(code)
+        props = (
+            unicodedata.category(chr_),
+            unicodedata.bidirectional(chr_) or "L",
+            unicodedata.combining(chr_),
+            "Y" if unicodedata.mirrored(chr_) else "N",
+        )
+        if props != prev:
+            lines.append(f"{code:x} {props[0]} {props[1]} {props[2]} {props[3]}")
+            prev = props
+    return lines
+
+
+def simple_mapping(code, mapped):
+    if len(mapped) == 1 and ord(mapped) != code:
+        return f"{ord(mapped):x}"
+    return ""
+
+
+def number(value):
+    if value is None:
+        return ""
+    if value == int(value):
+        return str(int(value))
+    return repr(value)
+
+
+def mappings():
+    lines = []
+    for code in range(0x110000):
+        chr_ = chr(code)
+        fields = [
+            unicodedata.decomposition(chr_),
+            number(unicodedata.decimal(chr_, None)),
+            number(unicodedata.digit(chr_, None)),
+            number(unicodedata.numeric(chr_, None)),
+            simple_mapping(code, chr_.upper()),
+            simple_mapping(code, chr_.lower()),
+            simple_mapping(code, chr_.title()),
+        ]
+        if any(fields):
+            lines.append(f"{code:x};" + ";".join(fields))
+    return lines
+
+
+def compositions():
+    lines = []
+    for code in range(0x110000):
+        parts = unicodedata.decomposition(chr(code)).split()
+        if len(parts) != 2 or parts[0].startswith("<"):
+            continue
+        first, second = (chr(int(x, 16)) for x in parts)
+        if unicodedata.normalize("NFC", first + second) == chr(code):
+            lines.append(f"{ord(first):x} {ord(second):x} {code:x}")
+    return lines
+
+
+def algorithmic_prefix(code, name):
+    suffix = f"{code:04X}"
+    if name.endswith("-" + suffix):
+        return name[: -len(suffix)]
+    return None
+
+
+def names():
+    ranges = []
+    lines = []
+    prev_code, prev_name = -1, ""
+    for code in range(0x110000):
+        chr_ = chr(code)
+        if unicodedata.category(chr_) == "Cc":
+            name = "<control>"
+        else:
+            name = unicodedata.name(chr_, None)
+        # Hangul syllables are named by the algorithm of the standard
+        if name is None or name.startswith("HANGUL SYLLABLE "):
+            continue
+        prefix = algorithmic_prefix(code, name)
+        if prefix is not None:
+            prefix = RENAMED_PREFIXES.get(prefix, prefix)
+            if ranges and ranges[-1][2] == prefix and ranges[-1][1] == code - 1:
+                ranges[-1][1] = code
+            else:
+                ranges.append([code, code, prefix])
+            continue
+        shared = 0
+        limit = min(len(name), len(prev_name), MAX_PREFIX)
+        while shared < limit and name[shared] == prev_name[shared]:
+            shared += 1
+        delta = code - prev_code
+        lines.append(f"{'' if delta == 1 else format(delta, 'x')} {chr(48 + shared)}{name[shared:]}")
+        prev_code, prev_name = code, name
+    return [f"={beg:x} {end:x} {prefix}" for beg, end, prefix in ranges] + lines
+
+
+write("categories.txt", categories())
+write("mappings.txt", mappings())
+write("compositions.txt", compositions())
+write("names.txt", names())
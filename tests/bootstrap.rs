@@ -0,0 +1,111 @@
+//! Load the vendored standard library in `lisp/` and check that the bootstrap
+//! completes. This takes a while in debug builds, so it is ignored by default.
+//! Run it with
+//!
+//! ```sh
+//! cargo test --release --test bootstrap -- --ignored
+//! ```
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Files loaded by `lisp/bootstrap.el`, in order.
+const BOOTSTRAP_FILES: &[&str] = &[
+    "loadup",
+    "emacs-lisp/byte-run",
+    "emacs-lisp/backquote",
+    "subr",
+    "custom",
+    "env",
+    "emacs-lisp/macroexp",
+    "emacs-lisp/pcase",
+    "gv",
+    "inline",
+    "emacs-lisp/nadvice",
+    "emacs-lisp/cl-preloaded",
+    "cl-lib",
+    "cl-macs",
+    "cl-seq",
+    "cconv",
+    "warnings",
+    "stubs",
+    "bytecomp",
+    "byte-opt",
+    "subr-x",
+];
+
+#[test]
+#[ignore]
+fn bootstrap() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rune"))
+        .arg("--load")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("failed to run rune");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "bootstrap failed:\n{stdout}");
+
+    let mut loaded = stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("Loading "))
+        .filter_map(|line| line.strip_suffix("..."));
+    for file in BOOTSTRAP_FILES {
+        assert!(
+            loaded.any(|x| x == *file),
+            "{file} was not loaded in order:\n{stdout}"
+        );
+    }
+    assert_eq!(stdout.lines().last(), Some("true"));
+}
+
+/// The natives that the standard library would otherwise replace with its
+/// own definitions still work once it is loaded, as do the natives behind the
+/// macros stubbed in `lisp/stubs.el`.
+#[test]
+#[ignore]
+fn natives_after_bootstrap() {
+    let forms = [
+        (
+            r#"(with-temp-buffer (insert "hello") (narrow-to-region 2 4) (buffer-narrowed-p))"#,
+            "t",
+        ),
+        (
+            r#"(with-temp-buffer (insert "hello") (buffer-narrowed-p))"#,
+            "nil",
+        ),
+        (
+            r#"(buffer-name (generate-new-buffer "rune-new" t))"#,
+            r#""rune-new""#,
+        ),
+        ("(list (xor nil 2) (xor 1 nil) (xor 1 2))", "(2 1 nil)"),
+        (
+            r#"(progn (string-match "b\\(c\\)" "abcd") (match-string 1 "abcd"))"#,
+            r#""c""#,
+        ),
+        (
+            r#"(progn (oclosure-define (rune-oc) "doc" x (y :mutable t))
+                      (oclosure-define (rune-oc-2 (:parent rune-oc)) z)
+                      (let ((f (oclosure-lambda (rune-oc-2 (x 1) (z 3)) (n) (list x y z n))))
+                        (list (funcall f 4) (rune-oc--x f) (rune-oc-2--z f) (oclosure-type f))))"#,
+            "((1 nil 3 4) 1 3 rune-oc-2)",
+        ),
+    ];
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rune"))
+        .args(["--load", "--repl"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run rune");
+    let mut stdin = child.stdin.take().unwrap();
+    for (form, _) in forms {
+        writeln!(stdin, "{}", form.replace('\n', " ")).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let results: Vec<_> = stdout.split("> ").skip(1).map(str::trim_end).collect();
+    assert_eq!(results.len(), forms.len() + 1, "{stdout}");
+    for ((form, expect), result) in forms.iter().zip(&results) {
+        assert_eq!(result, expect, "{form}");
+    }
+}
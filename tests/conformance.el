@@ -0,0 +1,200 @@
+;;; conformance.el --- Expected results of core forms  -*- lisp-data -*-
+
+;; Each area is a list of cases, where a case is (FORM "PRINTED VALUE") or
+;; (FORM :error ERROR-SYMBOL), giving what GNU Emacs does. Cases that rune
+;; does not pass yet end with :known-failure, and cases that need a Cargo
+;; feature are marked with :feature FEATURE. Only primitives are used, since
+;; the suite runs without loading the standard library. See
+;; src/conformance.rs for how the suite is run.
+
+(eval
+ (1 "1")
+ ("str" "\"str\"")
+ ((quote (a . b)) "(a . b)")
+ ('nil "nil")
+ (:keyword ":keyword")
+ ((progn) "nil")
+ ((progn 1 2 3) "3")
+ ((prog1 1 2 3) "1")
+ ((let ((x 1) (y 2)) (list x y)) "(1 2)")
+ ((let ((x 1)) (let ((x 2) (y x)) y)) "1")
+ ((let* ((x 1) (y x)) y) "1")
+ ((let (x) x) "nil")
+ ((setq) "nil" :known-failure)
+ ((let (a b) (setq a 1 b (+ a 1)) (list a b)) "(1 2)")
+ ((funcall #'(lambda (a &optional b) (list a b)) 1) "(1 nil)")
+ ((funcall #'(lambda (a &rest b) b) 1 2 3) "(2 3)")
+ ((apply #'+ 1 2 '(3 4)) "10")
+ ((apply #'list '()) "nil")
+ ((funcall #'(lambda (x) x)) :error wrong-number-of-arguments :known-failure)
+ ((funcall 'undefined-function-for-conformance) :error void-function :known-failure)
+ (undefined-variable-for-conformance :error void-variable :known-failure)
+ ((setq nil 1) :error setting-constant :known-failure)
+ ((funcall (let ((x 1)) #'(lambda () x))) "1")
+ ((eval '(+ 1 2)) "3")
+ ((functionp 'car) "t")
+ ((functionp #'(lambda ())) "t")
+ ((functionp 'if) "nil")
+ ((special-form-p 'if) "t" :known-failure))
+
+(control
+ ((if t 1 2) "1")
+ ((if nil 1 2 3) "3")
+ ((if nil 1) "nil")
+ ((cond ((eq 1 2) 'a) ((eq 1 1) 'b)) "b")
+ ((cond (nil 1)) "nil")
+ ((cond (5)) "5")
+ ((and) "t")
+ ((and 1 2) "2")
+ ((and 1 nil 2) "nil")
+ ((or) "nil")
+ ((or nil 2 3) "2")
+ ((let ((i 0) (sum 0)) (while (< i 5) (setq sum (+ sum i) i (1+ i))) sum) "10")
+ ((while nil) "nil")
+ ((catch 'done (throw 'done 42) 1) "42")
+ ((catch 'outer (catch 'inner (throw 'outer 1)) 2) "1")
+ ((throw 'nowhere-to-go 1) :error no-catch :known-failure)
+ ((let ((x 1)) (unwind-protect (setq x 2) (setq x 3)) x) "3")
+ ((let ((x 1)) (catch 'c (unwind-protect (throw 'c 0) (setq x 5))) x) "5")
+ ((condition-case nil (car 1) (error 'caught)) "caught")
+ ((condition-case err (signal 'wrong-type-argument '(1 2)) (error err)) "(wrong-type-argument 1 2)")
+ ((condition-case nil 7 (error 'caught)) "7")
+ ((car (condition-case err (car 1) (error err))) "wrong-type-argument" :known-failure)
+ ((signal 'wrong-type-argument '(x)) :error wrong-type-argument)
+ ((condition-case nil (/ 1 0) (arith-error 'div)) "div" :known-failure))
+
+(numbers
+ ((+) "0")
+ ((*) "1")
+ ((+ 1 2.5) "3.5")
+ ((- 5) "-5")
+ ((- 10 1 2) "7")
+ ((* 2 3 4) "24")
+ ((/ 7 2) "3")
+ ((/ -7 2) "-3")
+ ((/ 7 2.0) "3.5")
+ ((/ 7.0 0) "1.0e+INF" :known-failure)
+ ((/ 5 0) :error arith-error :known-failure)
+ ((% 7 3) "1" :known-failure)
+ ((% -7 3) "-1" :known-failure)
+ ((mod -7 3) "2" :known-failure)
+ ((mod 5.5 2) "1.5")
+ ((1+ 1) "2")
+ ((1- 1.5) "0.5")
+ ((max 1 3 2) "3")
+ ((min 1 3.0 2) "1")
+ ((max 1 3.0) "3.0")
+ ((abs -5) "5" :known-failure)
+ ((= 1 1.0) "t")
+ ((eql 1 1.0) "nil")
+ ((< 1 2 3) "t")
+ ((< 1 3 2) "nil" :known-failure)
+ ((/= 1 2) "t")
+ ((float 3) "3.0")
+ ((truncate 2.7) "2" :known-failure)
+ ((floor -2.5) "-3")
+ ((ceiling 2.1) "3" :known-failure)
+ ((round 2.5) "2" :known-failure)
+ ((round -2.5) "-2" :known-failure)
+ ((floor 7 2) "3")
+ ((ash 1 10) "1024")
+ ((ash -8 -1) "-4")
+ ((logand 12 10) "8")
+ ((logior 12 10) "14")
+ ((logxor 12 10) "6" :known-failure)
+ ((natnump 0) "t" :known-failure)
+ ((integerp 1.0) "nil")
+ ((floatp 1.0) "t")
+ ((numberp 'a) "nil")
+ ((number-to-string 1.5) "\"1.5\"" :known-failure)
+ ((string-to-number "12") "12")
+ ((string-to-number "1.5e2") "150.0")
+ ((string-to-number "ff" 16) "255")
+ ((+ 1 'a) :error wrong-type-argument :known-failure))
+
+(strings
+ ((concat "ab" "cd") "\"abcd\"")
+ ((concat) "\"\"")
+ ((concat "a" '(98) [99]) "\"abc\"" :known-failure)
+ ((substring "hello" 1 3) "\"el\"")
+ ((substring "hello" -3) "\"llo\"" :known-failure)
+ ((substring "hello" 2 1) :error args-out-of-range :known-failure)
+ ((length "héllo") "5")
+ ((string-bytes "héllo") "6" :known-failure)
+ ((aref "abc" 1) "98")
+ ((string= "abc" "abc") "t" :known-failure)
+ ((string= "abc" 'abc) "t" :known-failure)
+ ((string< "abc" "abd") "t" :known-failure)
+ ((string-equal "a" "A") "nil")
+ ((upcase "hello") "\"HELLO\"")
+ ((downcase "HeLLo") "\"hello\"")
+ ((capitalize "hello world") "\"Hello World\"")
+ ((upcase ?a) "65")
+ ((string ?a ?b) "\"ab\"" :known-failure)
+ ((make-string 3 ?x) "\"xxx\"" :known-failure)
+ ((format "%s-%d" 'a 5) "\"a-5\"")
+ ((format "%S" "q") "\"\\\"q\\\"\"" :known-failure)
+ ((format "%05.1f" 3.14159) "\"003.1\"" :known-failure)
+ ((format "%x" 255) "\"ff\"" :known-failure)
+ ((format "%c" ?a) "\"a\"" :known-failure)
+ ((format "%-4s|" "a") "\"a   |\"" :known-failure)
+ ((string-match "b+" "abbc") "1" :feature regex)
+ ((symbol-name 'foo) "\"foo\"")
+ ((intern "conformance-sym") "conformance-sym")
+ ((char-to-string ?z) "\"z\"" :known-failure)
+ ((stringp "a") "t")
+ ((stringp ?a) "nil")
+ ((length 1) :error wrong-type-argument :known-failure))
+
+(sequences
+ ((cons 1 2) "(1 . 2)")
+ ((list 1 2 3) "(1 2 3)")
+ ((car nil) "nil")
+ ((cdr '(1)) "nil")
+ ((car '(1 . 2)) "1")
+ ((car 1) :error wrong-type-argument :known-failure)
+ ((nth 1 '(a b c)) "b")
+ ((nth 5 '(a b c)) "nil")
+ ((nthcdr 2 '(a b c)) "(c)")
+ ((length '(1 2 3)) "3")
+ ((length [1 2]) "2")
+ ((length nil) "0")
+ ((append '(1) '(2) 3) "(1 2 . 3)" :known-failure)
+ ((append [1 2] nil) "(1 2)" :known-failure)
+ ((append) "nil" :known-failure)
+ ((reverse '(1 2 3)) "(3 2 1)")
+ ((reverse [1 2 3]) "[3 2 1]" :known-failure)
+ ((nreverse (list 1 2 3)) "(3 2 1)")
+ ((member 2.0 '(1 2.0 3)) "(2.0 3)")
+ ((memq 'b '(a b c)) "(b c)")
+ ((assq 'b '((a . 1) (b . 2))) "(b . 2)")
+ ((assoc "b" '(("a" . 1) ("b" . 2))) "(\"b\" . 2)")
+ ((rassq 2 '((a . 1) (b . 2))) "(b . 2)")
+ ((delq 'a (list 'a 'b 'a)) "(b)")
+ ((delete 1 (list 1 2 1)) "(2)")
+ ((mapcar #'1+ '(1 2 3)) "(2 3 4)")
+ ((mapcar #'1+ [1 2]) "(2 3)")
+ ((mapcar #'identity "ab") "(97 98)" :known-failure)
+ ((mapconcat #'identity '("a" "b") "-") "\"a-b\"" :known-failure)
+ ((mapc #'ignore '(1 2)) "(1 2)" :known-failure)
+ ((nconc (list 1) (list 2) nil (list 3)) "(1 2 3)")
+ ((make-list 3 'x) "(x x x)" :known-failure)
+ ((vector 1 'a "b") "[1 a \"b\"]" :known-failure)
+ ((make-vector 2 0) "[0 0]" :known-failure)
+ ((aref [1 2 3] 2) "3")
+ ((aref [1 2 3] 3) :error args-out-of-range :known-failure)
+ ((let ((v (vector 1 2))) (aset v 0 'x) v) "[x 2]" :known-failure)
+ ((vconcat '(1 2) [3]) "[1 2 3]" :known-failure)
+ ((copy-sequence [1 2]) "[1 2]" :known-failure)
+ ((elt '(a b c) 1) "b")
+ ((sort (list 3 1 2) #'<) "(1 2 3)" :known-failure)
+ ((sort (vector 3 1 2) #'<) "[1 2 3]" :known-failure)
+ ((equal '(1 (2 "x")) (list 1 (list 2 "x"))) "t")
+ ((eq "a" "a") "nil")
+ ((equal 1 1.0) "nil")
+ ((proper-list-p '(1 2)) "2" :known-failure)
+ ((safe-length '(1 2 3)) "3")
+ ((plist-get '(a 1 b 2) 'b) "2")
+ ((plist-put (list 'a 1) 'b 2) "(a 1 b 2)" :known-failure)
+ ((let ((h (make-hash-table :test 'equal))) (puthash "k" 1 h) (gethash "k" h)) "1" :known-failure)
+ ((hash-table-count (make-hash-table)) "0" :known-failure))
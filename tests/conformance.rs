@@ -0,0 +1,16 @@
+//! Run the conformance suite in `tests/conformance.el` and check that every
+//! case gives the result it expects. The report of each area is printed with
+//! `--nocapture`.
+use std::process::Command;
+
+#[test]
+fn conformance() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rune"))
+        .arg("--conformance")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("failed to run rune");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("{stdout}");
+    assert!(output.status.success(), "conformance failed:\n{stdout}");
+}
@@ -0,0 +1,36 @@
+//! `kill-emacs` runs the same teardown as the end of the REPL before it
+//! exits, so it has to be checked from outside the process.
+
+#[cfg(feature = "processes")]
+#[test]
+fn kill_emacs() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    let forms = [
+        r#"(setq kill-emacs-hook (list #'(lambda () (message "hook ran"))))"#,
+        r#"(make-process :name "sleeper" :command '("/bin/sleep" "30") :connection-type 'pipe
+                         :sentinel #'(lambda (p msg) (message "sentinel: %s" msg)))"#,
+        "(kill-emacs 3)",
+        r#"(message "not reached")"#,
+    ];
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rune"))
+        .arg("--repl")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run rune");
+    let mut stdin = child.stdin.take().unwrap();
+    for form in forms {
+        writeln!(stdin, "{}", form.replace('\n', " ")).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(3), "{stdout}");
+    let hook = stdout.find("MESSAGE: hook ran").expect(&stdout);
+    // the process is killed after the hook, whatever its query-on-exit flag
+    let sentinel = stdout.find("MESSAGE: sentinel: killed").expect(&stdout);
+    assert!(hook < sentinel, "{stdout}");
+    assert!(!stdout.contains("not reached"), "{stdout}");
+}